@@ -0,0 +1,158 @@
+// Battery state of charge and low-battery policy, for portable builds on Li-ion cells in series.
+//
+// Voltages are per cell, so that the policy does not depend on the cell count. The state of charge follows a typical
+// discharge curve at moderate load, which is accurate enough for a level indicator, but not a fuel gauge.
+
+/// Averaging time constant, as a power of two in samples.
+pub const AVERAGE_SHIFT: u32 = 4;
+
+/// Cell voltage, below which the volume is limited.
+pub const LOW_MV: u16 = 3500;
+
+/// Cell voltage, below which the output shuts down.
+pub const CRITICAL_MV: u16 = 3300;
+
+/// Voltage rise, for leaving the low state (e.g. while charging).
+pub const HYSTERESIS_MV: u16 = 100;
+
+// Cell voltage and state of charge in percent, by rising voltage.
+const DISCHARGE_CURVE: [(u16, u8); 10] = [
+    (3300, 0),
+    (3500, 5),
+    (3600, 10),
+    (3700, 30),
+    (3750, 45),
+    (3800, 55),
+    (3900, 70),
+    (4000, 82),
+    (4100, 92),
+    (4200, 100),
+];
+
+/// The state of charge of a cell voltage, in percent.
+pub fn level_percent(cell_mv: u16) -> u8 {
+    let upper = DISCHARGE_CURVE.iter().position(|&(mv, _)| mv > cell_mv);
+
+    match upper {
+        Some(0) => 0,
+        Some(index) => {
+            let (low_mv, low_percent) = DISCHARGE_CURVE[index - 1];
+            let (high_mv, high_percent) = DISCHARGE_CURVE[index];
+            let position = (cell_mv - low_mv) as u32 * (high_percent - low_percent) as u32;
+            low_percent + (position / (high_mv - low_mv) as u32) as u8
+        }
+        None => 100,
+    }
+}
+
+/// Exponential average of voltage readings, which starts at the first reading.
+pub struct Average {
+    // Millivolts, scaled by `1 << AVERAGE_SHIFT`.
+    sum: Option<u32>,
+}
+
+impl Average {
+    pub const fn new() -> Self {
+        Self { sum: None }
+    }
+
+    /// Add a reading, and return the new average.
+    pub fn update(&mut self, mv: u16) -> u16 {
+        let sum = match self.sum {
+            Some(sum) => sum - (sum >> AVERAGE_SHIFT) + mv as u32,
+            None => (mv as u32) << AVERAGE_SHIFT,
+        };
+
+        self.sum = Some(sum);
+        (sum >> AVERAGE_SHIFT) as u16
+    }
+}
+
+impl Default for Average {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatteryState {
+    Normal,
+    /// The volume is limited.
+    Low,
+    /// The output is shut down, until the device restarts.
+    Critical,
+}
+
+/// Tracks the battery state of averaged cell voltages. The critical state latches, since the voltage recovers without
+/// load.
+pub struct LowBatteryPolicy {
+    state: BatteryState,
+}
+
+impl LowBatteryPolicy {
+    pub const fn new() -> Self {
+        Self {
+            state: BatteryState::Normal,
+        }
+    }
+
+    pub fn update(&mut self, cell_mv: u16) -> BatteryState {
+        self.state = match self.state {
+            BatteryState::Critical => BatteryState::Critical,
+            _ if cell_mv < CRITICAL_MV => BatteryState::Critical,
+            _ if cell_mv < LOW_MV => BatteryState::Low,
+            BatteryState::Low if cell_mv < LOW_MV + HYSTERESIS_MV => BatteryState::Low,
+            _ => BatteryState::Normal,
+        };
+
+        self.state
+    }
+}
+
+impl Default for LowBatteryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level() {
+        assert_eq!(level_percent(3000), 0);
+        assert_eq!(level_percent(3300), 0);
+        assert_eq!(level_percent(3650), 20);
+        assert_eq!(level_percent(4200), 100);
+        assert_eq!(level_percent(4350), 100);
+    }
+
+    #[test]
+    fn average() {
+        let mut average = Average::new();
+        assert_eq!(average.update(3800), 3800);
+
+        // A drop is followed by a sixteenth per reading.
+        assert_eq!(average.update(3640), 3790);
+        for _ in 0..100 {
+            average.update(3640);
+        }
+        assert_eq!(average.update(3640), 3640);
+    }
+
+    #[test]
+    fn policy() {
+        let mut policy = LowBatteryPolicy::new();
+        assert_eq!(policy.update(3700), BatteryState::Normal);
+        assert_eq!(policy.update(3450), BatteryState::Low);
+
+        // Recovers with hysteresis only.
+        assert_eq!(policy.update(3550), BatteryState::Low);
+        assert_eq!(policy.update(3600), BatteryState::Normal);
+
+        // Critical latches.
+        assert_eq!(policy.update(3250), BatteryState::Critical);
+        assert_eq!(policy.update(4000), BatteryState::Critical);
+    }
+}
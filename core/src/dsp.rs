@@ -0,0 +1,81 @@
+// Sample processing kernels and filters, in Q31 fixed point or in single precision floating point.
+use crate::gain::db_to_linear;
+
+pub mod auto_eq;
+pub mod biquad;
+pub mod chime;
+pub mod compressor;
+pub mod delay;
+pub mod design;
+pub mod emphasis;
+pub mod fft;
+pub mod fir;
+pub mod interpolate;
+pub mod kernel;
+pub mod loudness;
+pub mod protection;
+pub mod sample;
+
+use kernel::{mul_q31, saturate};
+
+pub use biquad::{BiquadCascade, Coefficients};
+pub use compressor::{Compressor, CompressorParams};
+pub use delay::Delay;
+pub use design::Filter;
+pub use fir::Fir;
+pub use loudness::Loudness;
+pub use protection::{Protection, ProtectionParams};
+pub use sample::Sample;
+
+/// A gain factor, stored as a Q31 mantissa and a left shift, such that gains above unity are supported.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Gain {
+    mantissa: i32,
+    shift: u32,
+}
+
+impl Gain {
+    pub const UNITY: Self = Self {
+        mantissa: i32::MAX,
+        shift: 0,
+    };
+
+    pub const MUTED: Self = Self { mantissa: 0, shift: 0 };
+
+    pub fn from_linear(mut linear: f32) -> Self {
+        let mut shift = 0;
+
+        while linear >= 1.0 && shift < 31 {
+            linear /= 2.0;
+            shift += 1;
+        }
+
+        Self {
+            mantissa: (linear.max(0.0) * i32::MAX as f32) as i32,
+            shift,
+        }
+    }
+
+    pub fn from_db(db: f32) -> Self {
+        Self::from_linear(db_to_linear(db))
+    }
+
+    /// Create a gain from a Q31 factor, which must be in the range [0, 1].
+    pub const fn from_q31(factor: i32) -> Self {
+        Self {
+            mantissa: factor,
+            shift: 0,
+        }
+    }
+
+    #[inline]
+    pub fn apply(&self, sample: i32) -> i32 {
+        let product = mul_q31(sample, self.mantissa);
+
+        if self.shift == 0 {
+            product
+        } else {
+            saturate((product as i64) << self.shift)
+        }
+    }
+}
@@ -0,0 +1,376 @@
+// Room and driver correction from a measured response, coarse enough to be computed on the device.
+//
+// A logarithmic sine sweep passes through third-octave bands at a constant rate. While it does, the level of a
+// microphone's recording is accumulated per band, skipping the first and last quarter of each band's time, which
+// tolerates the latency between playback and recording, and the sweep's fades. Peaking filters are then fitted to the
+// deviation of the band levels from their mean, one at a time, at the band of the largest correctable deviation.
+// Corrections mostly cut: boosts are limited by the coefficient range (see `design`), and dips from room modes do not
+// respond to boosts anyway.
+use super::design::{sin, Filter};
+use crate::gain::{db_to_linear, exp2, linear_to_db};
+
+const PI: f32 = core::f32::consts::PI;
+
+/// Number of analysis bands, in third octaves from 25 Hz to 20 kHz.
+pub const BAND_COUNT: usize = 30;
+
+const FIRST_BAND_HZ: f32 = 25.0;
+const BANDS_PER_OCTAVE: f32 = 3.0;
+
+/// Steps per dB of a correction's gain.
+pub const STEPS_PER_DB: f32 = 2.0;
+
+/// Widest correction in bands (two octaves).
+pub const MAX_WIDTH: u8 = 6;
+
+/// The center frequency of an analysis band.
+pub fn band_hz(band: usize) -> f32 {
+    FIRST_BAND_HZ * exp2(band as f32 / BANDS_PER_OCTAVE)
+}
+
+fn magnitude(value: f32) -> f32 {
+    if value < 0.0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// A logarithmic sine sweep, which spends the same time in each analysis band, from the lower edge of the first band
+/// to the upper edge of the last. It fades in and out within a quarter band.
+pub struct Sweep {
+    frames_per_band: u32,
+    frame: u32,
+    // Phase in turns, in the range [0, 1).
+    phase: f32,
+    amplitude: f32,
+    sample_rate_hz: u32,
+}
+
+impl Sweep {
+    /// A sweep with a peak level in dBFS, at a sample rate of at least 48 kHz.
+    pub fn new(frames_per_band: u32, level_db: f32, sample_rate_hz: u32) -> Self {
+        Self {
+            frames_per_band,
+            frame: 0,
+            phase: 0.0,
+            amplitude: db_to_linear(level_db),
+            sample_rate_hz,
+        }
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frames_per_band * BAND_COUNT as u32
+    }
+
+    fn frequency_hz(&self) -> f32 {
+        let position = self.frame as f32 / self.frames_per_band as f32 - 0.5;
+        FIRST_BAND_HZ * exp2(position / BANDS_PER_OCTAVE)
+    }
+}
+
+impl Iterator for Sweep {
+    type Item = i32;
+
+    fn next(&mut self) -> Option<i32> {
+        let frame_count = self.frame_count();
+        if self.frame >= frame_count {
+            return None;
+        }
+
+        let fade_frames = (self.frames_per_band / 4).max(1);
+        let fade = (self.frame.min(frame_count - 1 - self.frame) as f32 / fade_frames as f32).min(1.0);
+        let sample = self.amplitude * fade * sin(2.0 * PI * self.phase);
+
+        self.phase += self.frequency_hz() / self.sample_rate_hz as f32;
+        self.phase -= self.phase as u32 as f32;
+        self.frame += 1;
+
+        Some((sample * i32::MAX as f32) as i32)
+    }
+}
+
+/// The level of a recording per analysis band, taken in step with a sweep.
+pub struct Response {
+    frames_per_band: u32,
+    frame: u32,
+    energy: [f32; BAND_COUNT],
+    counts: [u32; BAND_COUNT],
+}
+
+impl Response {
+    pub fn new(frames_per_band: u32) -> Self {
+        Self {
+            frames_per_band,
+            frame: 0,
+            energy: [0.0; BAND_COUNT],
+            counts: [0; BAND_COUNT],
+        }
+    }
+
+    /// Add the recording's next frame. Frames after the sweep are ignored.
+    pub fn add(&mut self, sample: i32) {
+        let band = (self.frame / self.frames_per_band) as usize;
+        let offset = self.frame % self.frames_per_band;
+        let quarter = self.frames_per_band / 4;
+
+        if band < BAND_COUNT && (quarter..self.frames_per_band - quarter).contains(&offset) {
+            let sample = sample as f32 / i32::MAX as f32;
+            self.energy[band] += sample * sample;
+            self.counts[band] += 1;
+        }
+        self.frame = self.frame.saturating_add(1);
+    }
+
+    /// The RMS level of each band in dBFS, once the recording covered all bands.
+    pub fn levels_db(&self) -> Option<[f32; BAND_COUNT]> {
+        if self.counts.contains(&0) {
+            return None;
+        }
+
+        Some(core::array::from_fn(|band| {
+            linear_to_db(self.energy[band] / self.counts[band] as f32 + f32::MIN_POSITIVE) / 2.0
+        }))
+    }
+}
+
+/// Limits of a correction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FitParams {
+    /// Frequency range of the correction (e.g. the speakers' range). Bands outside of it are ignored.
+    pub min_hz: f32,
+    pub max_hz: f32,
+    /// Range of the filters' gains in dB.
+    pub min_gain_db: f32,
+    pub max_gain_db: f32,
+    /// Smallest deviation in dB, which is corrected.
+    pub threshold_db: f32,
+}
+
+/// A correction filter at the analysis' resolution: a peaking filter at a band's center frequency, as wide as a number
+/// of bands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Correction {
+    pub band: u8,
+    /// Width in bands, from one to `MAX_WIDTH`.
+    pub width: u8,
+    /// Gain in 0.5 dB steps.
+    pub gain: i8,
+}
+
+impl Correction {
+    /// Size of a correction's encoding.
+    pub const SIZE: usize = 3;
+
+    pub fn is_valid(&self) -> bool {
+        (self.band as usize) < BAND_COUNT && (1..=MAX_WIDTH).contains(&self.width)
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain as f32 / STEPS_PER_DB
+    }
+
+    /// The quality factor of a peaking filter, whose bandwidth spans `width` bands.
+    pub fn q(&self) -> f32 {
+        let octaves = self.width as f32 / BANDS_PER_OCTAVE;
+        exp2(octaves / 2.0) / (exp2(octaves) - 1.0)
+    }
+
+    pub fn filter(&self) -> Filter {
+        Filter::Peaking {
+            frequency_hz: band_hz(self.band as usize),
+            q: self.q(),
+            gain_db: self.gain_db(),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        [self.band, self.width, self.gain as u8]
+    }
+
+    /// Decode a correction. Invalid (e.g. erased) encodings decode to none.
+    pub fn from_bytes([band, width, gain]: [u8; Self::SIZE]) -> Option<Self> {
+        let correction = Self {
+            band,
+            width,
+            gain: gain as i8,
+        };
+
+        correction.is_valid().then_some(correction)
+    }
+}
+
+/// Fit up to `N` correction filters to measured band levels, towards their mean level within the correction's range.
+pub fn fit<const N: usize>(
+    levels_db: &[f32; BAND_COUNT],
+    params: &FitParams,
+    sample_rate_hz: u32,
+) -> [Option<Correction>; N] {
+    let in_range = |band: usize| (params.min_hz..=params.max_hz).contains(&band_hz(band));
+    let mut corrections = [None; N];
+
+    let (sum_db, count) = (0..BAND_COUNT)
+        .filter(|&band| in_range(band))
+        .fold((0.0, 0), |(sum_db, count), band| (sum_db + levels_db[band], count + 1));
+    if count == 0 {
+        return corrections;
+    }
+    let mean_db = sum_db / count as f32;
+
+    // Deviation from the mean, including the filters fitted so far.
+    let mut deviation_db: [f32; BAND_COUNT] = core::array::from_fn(|band| match in_range(band) {
+        true => levels_db[band] - mean_db,
+        false => 0.0,
+    });
+    let gain_db = |deviation_db: f32| (-deviation_db).clamp(params.min_gain_db, params.max_gain_db);
+
+    for correction in corrections.iter_mut() {
+        let Some(band) = (0..BAND_COUNT)
+            .filter(|&band| in_range(band))
+            .max_by(|&a, &b| magnitude(gain_db(deviation_db[a])).total_cmp(&magnitude(gain_db(deviation_db[b]))))
+        else {
+            break;
+        };
+
+        let peak_db = deviation_db[band];
+        let band_gain_db = gain_db(peak_db);
+        if magnitude(band_gain_db) < params.threshold_db {
+            break;
+        }
+
+        // Neighbors, which deviate in the same direction by at least half as much, widen the filter.
+        let similar = |other: usize| {
+            in_range(other)
+                && deviation_db[other] * peak_db > 0.0
+                && magnitude(deviation_db[other]) >= magnitude(peak_db) / 2.0
+        };
+        let below = (0..band).rev().take_while(|&other| similar(other)).count();
+        let above = (band + 1..BAND_COUNT).take_while(|&other| similar(other)).count();
+
+        let steps = band_gain_db * STEPS_PER_DB;
+        let fitted = Correction {
+            band: band as u8,
+            width: (1 + below + above).min(MAX_WIDTH as usize) as u8,
+            gain: if steps < 0.0 { steps - 0.5 } else { steps + 0.5 } as i8,
+        };
+
+        let coefficients = fitted.filter().coefficients(sample_rate_hz);
+        for (other, deviation_db) in deviation_db.iter_mut().enumerate() {
+            if in_range(other) {
+                *deviation_db += coefficients.magnitude_db(band_hz(other), sample_rate_hz);
+            }
+        }
+
+        *correction = Some(fitted);
+    }
+
+    corrections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE_HZ: u32 = 48_000;
+    const FRAMES_PER_BAND: u32 = 12_000;
+
+    const PARAMS: FitParams = FitParams {
+        min_hz: 40.0,
+        max_hz: 16_000.0,
+        min_gain_db: -12.0,
+        max_gain_db: 3.0,
+        threshold_db: 1.0,
+    };
+
+    #[test]
+    fn bands() {
+        assert_eq!(band_hz(0), 25.0);
+        assert!((band_hz(9) - 200.0).abs() < 0.01);
+        assert!((band_hz(BAND_COUNT - 1) - 20_000.0).abs() < 400.0);
+    }
+
+    #[test]
+    fn sweep_level() {
+        let mut sweep = Sweep::new(FRAMES_PER_BAND, -6.0, SAMPLE_RATE_HZ);
+        let mut response = Response::new(FRAMES_PER_BAND);
+        let mut count = 0;
+        for sample in sweep.by_ref() {
+            assert!(sample.unsigned_abs() <= i32::MAX as u32 / 2 + (1 << 24));
+            response.add(sample);
+            count += 1;
+        }
+        assert_eq!(count, sweep.frame_count());
+
+        // A sine at -6 dBFS has an RMS level of -9 dBFS in every band. Windows of the lowest bands span few periods.
+        for (band, level_db) in response.levels_db().unwrap().into_iter().enumerate() {
+            let tolerance_db = if band_hz(band) < 40.0 { 1.0 } else { 0.25 };
+            assert!((level_db + 9.0).abs() < tolerance_db, "{band}: {level_db}");
+        }
+    }
+
+    #[test]
+    fn incomplete_recording() {
+        let mut response = Response::new(FRAMES_PER_BAND);
+        for _ in 0..FRAMES_PER_BAND * (BAND_COUNT as u32 - 1) {
+            response.add(1 << 20);
+        }
+        assert!(response.levels_db().is_none());
+    }
+
+    #[test]
+    fn flat_response() {
+        assert_eq!(fit::<4>(&[-20.0; BAND_COUNT], &PARAMS, SAMPLE_RATE_HZ), [None; 4]);
+    }
+
+    #[test]
+    fn cuts_a_peak() {
+        let mut levels_db = [-20.0; BAND_COUNT];
+        levels_db[10] = -12.0;
+        levels_db[11] = -15.0;
+
+        let corrections = fit::<4>(&levels_db, &PARAMS, SAMPLE_RATE_HZ);
+        let first = corrections[0].unwrap();
+        assert_eq!(first.band, 10);
+        assert_eq!(first.width, 2);
+        assert!(first.gain_db() < -6.0);
+
+        // The remaining deviation is within the threshold, or corrected by further filters.
+        let filters = corrections.map(|correction| correction.map(|c| c.filter().coefficients(SAMPLE_RATE_HZ)));
+        let corrected_db = levels_db[10]
+            + filters
+                .iter()
+                .flatten()
+                .map(|coefficients| coefficients.magnitude_db(band_hz(10), SAMPLE_RATE_HZ))
+                .sum::<f32>();
+        assert!((corrected_db + 20.0).abs() < 2.0, "{corrected_db}");
+    }
+
+    #[test]
+    fn limits_boosts() {
+        let mut levels_db = [-20.0; BAND_COUNT];
+        levels_db[5] = -40.0;
+
+        let first = fit::<4>(&levels_db, &PARAMS, SAMPLE_RATE_HZ)[0].unwrap();
+        assert_eq!(first.band, 5);
+        assert_eq!(first.gain_db(), PARAMS.max_gain_db);
+    }
+
+    #[test]
+    fn ignores_bands_out_of_range() {
+        let mut levels_db = [-20.0; BAND_COUNT];
+        levels_db[0] = 0.0;
+        assert_eq!(fit::<4>(&levels_db, &PARAMS, SAMPLE_RATE_HZ), [None; 4]);
+    }
+
+    #[test]
+    fn encoding() {
+        let correction = Correction {
+            band: 12,
+            width: 3,
+            gain: -9,
+        };
+        assert_eq!(Correction::from_bytes(correction.to_bytes()), Some(correction));
+        assert_eq!(Correction::from_bytes([0xff; Correction::SIZE]), None);
+        assert!((correction.q() - 1.414).abs() < 0.01);
+    }
+}
@@ -0,0 +1,198 @@
+use super::design::sin;
+use super::sample::Sample;
+use crate::gain::linear_to_db;
+
+/// Fractional bits of fixed-point coefficients, which are stored in Q2.30 format, covering the range [-2, 2).
+pub const COEFFICIENT_SHIFT: u32 = 30;
+
+/// Biquad coefficients, normalized such that `a0` equals one.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl Coefficients {
+    // A filter that passes the signal unchanged.
+    pub const IDENTITY: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    /// The filter's gain in dB at a frequency.
+    pub fn magnitude_db(&self, frequency_hz: f32, sample_rate_hz: u32) -> f32 {
+        // Squared magnitudes of the numerator and denominator polynomials, in terms of sin^2(w/2), which avoids the
+        // cancellation of the terms at low frequencies (see the "Audio EQ Cookbook").
+        let half_sin = sin(core::f32::consts::PI * frequency_hz / sample_rate_hz as f32);
+        let phi = half_sin * half_sin;
+        let squared = |c0: f32, c1: f32, c2: f32| {
+            let sum = c0 + c1 + c2;
+            sum * sum - 4.0 * (c0 * c1 + 4.0 * c0 * c2 + c1 * c2) * phi + 16.0 * c0 * c2 * phi * phi
+        };
+        let numerator = squared(self.b0, self.b1, self.b2);
+        let denominator = squared(1.0, self.a1, self.a2);
+
+        linear_to_db(numerator / denominator) / 2.0
+    }
+}
+
+/// A biquad filter in direct form I. Fixed-point filters use a 64 bit accumulator.
+pub struct Biquad<S: Sample> {
+    // Feed-forward and negated feedback coefficients: b0, b1, b2, -a1, -a2.
+    coefficients: [S::Coefficient; 5],
+
+    x: [S; 2],
+    y: [S; 2],
+}
+
+/// Convert a coefficient to Q2.30.
+pub fn to_fixed(value: f32) -> i32 {
+    i32::coefficient(value, COEFFICIENT_SHIFT)
+}
+
+impl<S: Sample> Biquad<S> {
+    pub fn new(coefficients: Coefficients) -> Self {
+        let mut biquad = Self {
+            coefficients: Default::default(),
+            x: [S::ZERO; 2],
+            y: [S::ZERO; 2],
+        };
+        biquad.set_coefficients(coefficients);
+
+        biquad
+    }
+
+    /// Replace the coefficients, keeping the filter state.
+    pub fn set_coefficients(&mut self, coefficients: Coefficients) {
+        self.coefficients = [
+            coefficients.b0,
+            coefficients.b1,
+            coefficients.b2,
+            -coefficients.a1,
+            -coefficients.a2,
+        ]
+        .map(|coefficient| S::coefficient(coefficient, COEFFICIENT_SHIFT));
+    }
+
+    pub fn reset(&mut self) {
+        self.x = [S::ZERO; 2];
+        self.y = [S::ZERO; 2];
+    }
+
+    #[inline]
+    pub fn process(&mut self, sample: S) -> S {
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+
+        let mut accumulator = S::mac(S::accumulator(), b0, sample);
+        accumulator = S::mac(accumulator, b1, self.x[0]);
+        accumulator = S::mac(accumulator, b2, self.x[1]);
+        accumulator = S::mac(accumulator, a1, self.y[0]);
+        accumulator = S::mac(accumulator, a2, self.y[1]);
+
+        let output = S::from_accumulator(accumulator, COEFFICIENT_SHIFT);
+
+        self.x = [sample, self.x[0]];
+        self.y = [output, self.y[0]];
+
+        output
+    }
+}
+
+/// A cascade of `N` biquad filters (e.g. for a parametric equalizer).
+pub struct BiquadCascade<S: Sample, const N: usize> {
+    stages: [Biquad<S>; N],
+}
+
+impl<S: Sample, const N: usize> BiquadCascade<S, N> {
+    pub fn new() -> Self {
+        Self {
+            stages: core::array::from_fn(|_| Biquad::new(Coefficients::IDENTITY)),
+        }
+    }
+
+    pub fn set_coefficients(&mut self, index: usize, coefficients: Coefficients) {
+        self.stages[index].set_coefficients(coefficients);
+    }
+
+    pub fn reset(&mut self) {
+        self.stages.iter_mut().for_each(Biquad::reset);
+    }
+
+    #[inline]
+    pub fn process(&mut self, sample: S) -> S {
+        self.stages
+            .iter_mut()
+            .fold(sample, |sample, stage| stage.process(sample))
+    }
+
+    pub fn process_block(&mut self, samples: &mut [S]) {
+        for sample in samples.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+}
+
+impl<S: Sample, const N: usize> Default for BiquadCascade<S, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::Filter;
+
+    #[test]
+    fn identity_passes_samples() {
+        let mut cascade = BiquadCascade::<i32, 4>::new();
+        let mut samples = [0, 1 << 30, -(1 << 30), 12345, i32::MAX, i32::MIN];
+        let input = samples;
+
+        cascade.process_block(&mut samples);
+        for (output, input) in samples.iter().zip(input) {
+            assert!(output.abs_diff(input) <= 1);
+        }
+    }
+
+    #[test]
+    fn low_pass_settles_to_dc() {
+        let filter = Filter::LowPass {
+            frequency_hz: 1000.0,
+            q: 0.707,
+        };
+        let mut fixed = BiquadCascade::<i32, 1>::new();
+        let mut float = BiquadCascade::<f32, 1>::new();
+        fixed.set_coefficients(0, filter.coefficients(48_000));
+        float.set_coefficients(0, filter.coefficients(48_000));
+
+        let (mut fixed_output, mut float_output) = (0, 0.0);
+        for _ in 0..4800 {
+            fixed_output = fixed.process(1 << 28);
+            float_output = float.process(0.125);
+        }
+
+        assert!(fixed_output.abs_diff(1 << 28) < 1 << 12);
+        assert!((float_output - 0.125).abs() < 1e-4);
+    }
+
+    #[test]
+    fn magnitude() {
+        let peaking = Filter::Peaking {
+            frequency_hz: 100.0,
+            q: 2.0,
+            gain_db: -7.5,
+        }
+        .coefficients(48_000);
+
+        assert!((peaking.magnitude_db(100.0, 48_000) + 7.5).abs() < 0.1);
+        assert!(peaking.magnitude_db(10.0, 48_000).abs() < 0.1);
+        assert!(Coefficients::IDENTITY.magnitude_db(1000.0, 48_000).abs() < 1e-4);
+    }
+}
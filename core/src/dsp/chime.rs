@@ -0,0 +1,245 @@
+// Chimes: short alerts for local events (e.g. the volume reaching its limit), which are mixed into the output.
+//
+// A chime is a sequence of sine notes, each with a short attack and an exponential decay. Chimes are rendered ahead of
+// time as 16 bit PCM at a low rate (the firmware stores them in flash), and played at the output's rate by linear
+// interpolation, which suffices for tones well below the chime rate's Nyquist frequency.
+use super::design::sin;
+use super::Gain;
+use crate::gain::db_to_linear;
+
+const PI: f32 = core::f32::consts::PI;
+
+/// Sample rate of rendered chimes.
+pub const RATE_HZ: u32 = 8000;
+
+// Attack of each note, which avoids a click at its start.
+const ATTACK_SAMPLES: usize = (2 * RATE_HZ / 1000) as usize;
+
+// Level at the end of each note, relative to its peak.
+const DECAY_DB: f32 = -40.0;
+
+// Peak level of a note, slightly below full scale.
+const PEAK: f32 = 0.9 * i16::MAX as f32;
+
+/// A note of a chime.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Note {
+    pub frequency_hz: f32,
+    pub duration_ms: u32,
+}
+
+impl Note {
+    const fn sample_count(&self) -> usize {
+        (self.duration_ms * RATE_HZ / 1000) as usize
+    }
+}
+
+/// Events, which play a chime.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Alert {
+    /// A local volume adjustment reached the lowest or highest volume.
+    VolumeLimit = 0,
+    PresetChanged = 1,
+    /// The amplifiers recovered from a fault.
+    Fault = 2,
+}
+
+impl Alert {
+    /// All alerts, in the order of their values.
+    pub const ALL: [Self; 3] = [Self::VolumeLimit, Self::PresetChanged, Self::Fault];
+
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::VolumeLimit),
+            1 => Some(Self::PresetChanged),
+            2 => Some(Self::Fault),
+            _ => None,
+        }
+    }
+
+    /// The notes of the alert's chime: a short tick, a rising fifth, and three falling notes.
+    pub const fn notes(self) -> &'static [Note] {
+        match self {
+            Self::VolumeLimit => &[Note {
+                frequency_hz: 1760.0,
+                duration_ms: 60,
+            }],
+            Self::PresetChanged => &[
+                Note {
+                    frequency_hz: 880.0,
+                    duration_ms: 90,
+                },
+                Note {
+                    frequency_hz: 1318.5,
+                    duration_ms: 120,
+                },
+            ],
+            Self::Fault => &[
+                Note {
+                    frequency_hz: 987.8,
+                    duration_ms: 110,
+                },
+                Note {
+                    frequency_hz: 784.0,
+                    duration_ms: 110,
+                },
+                Note {
+                    frequency_hz: 587.3,
+                    duration_ms: 160,
+                },
+            ],
+        }
+    }
+}
+
+/// The length of a chime's PCM in samples.
+pub const fn sample_count(notes: &[Note]) -> usize {
+    let mut count = 0;
+    let mut index = 0;
+    while index < notes.len() {
+        count += notes[index].sample_count();
+        index += 1;
+    }
+
+    count
+}
+
+/// Render a chime's notes into PCM of `sample_count(notes)` samples at `RATE_HZ`.
+pub fn render(notes: &[Note], pcm: &mut [i16]) {
+    assert_eq!(pcm.len(), sample_count(notes));
+
+    let mut remaining = pcm;
+    for note in notes {
+        let (samples, rest) = remaining.split_at_mut(note.sample_count());
+        remaining = rest;
+
+        let increment = 2.0 * PI * note.frequency_hz / RATE_HZ as f32;
+        let decay = db_to_linear(DECAY_DB / samples.len() as f32);
+        let mut phase = 0.0;
+        let mut level = PEAK;
+
+        for (index, sample) in samples.iter_mut().enumerate() {
+            let attack = (index + 1).min(ATTACK_SAMPLES) as f32 / ATTACK_SAMPLES as f32;
+            *sample = (sin(phase) * level * attack) as i16;
+
+            phase += increment;
+            if phase > PI {
+                phase -= 2.0 * PI;
+            }
+            level *= decay;
+        }
+    }
+}
+
+/// Plays rendered chimes at the output's rate and a reduced level, as 32 bit samples.
+pub struct Player {
+    pcm: &'static [i16],
+    // Position in the PCM, in samples as unsigned Q16.16.
+    position: u32,
+    // Position increment per output sample.
+    step: u32,
+    gain: Gain,
+}
+
+impl Player {
+    pub fn new(output_rate_hz: u32, level_db: f32) -> Self {
+        Self {
+            pcm: &[],
+            position: 0,
+            step: (((RATE_HZ as u64) << 16) / output_rate_hz as u64) as u32,
+            gain: Gain::from_linear(db_to_linear(level_db)),
+        }
+    }
+
+    /// Play a chime from its start, replacing a playing one.
+    pub fn play(&mut self, pcm: &'static [i16]) {
+        self.pcm = pcm;
+        self.position = 0;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        ((self.position >> 16) as usize) < self.pcm.len()
+    }
+
+    /// The next sample at the output's rate, which is zero after the chime ended.
+    pub fn next_sample(&mut self) -> i32 {
+        let index = (self.position >> 16) as usize;
+        let Some(&current) = self.pcm.get(index) else {
+            return 0;
+        };
+
+        let next = self.pcm.get(index + 1).copied().unwrap_or(0) as i32;
+        let fraction = (self.position & 0xffff) as i32;
+        self.position += self.step;
+
+        let sample = current as i32 + (((next - current as i32) * fraction) >> 16);
+        self.gain.apply(sample << 16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_counts() {
+        assert_eq!(sample_count(Alert::VolumeLimit.notes()), 480);
+        assert_eq!(sample_count(Alert::PresetChanged.notes()), 1680);
+        assert_eq!(sample_count(&[]), 0);
+
+        for alert in Alert::ALL {
+            assert_eq!(Alert::from_u8(alert as u8), Some(alert));
+        }
+        assert_eq!(Alert::from_u8(3), None);
+    }
+
+    #[test]
+    fn render_notes() {
+        let notes = Alert::PresetChanged.notes();
+        let mut pcm = [0i16; sample_count(Alert::PresetChanged.notes())];
+        render(notes, &mut pcm);
+
+        // Each note starts from silence, and decays towards its end.
+        let (first, second) = pcm.split_at(notes[0].sample_count());
+        for samples in [first, second] {
+            let peak = |samples: &[i16]| samples.iter().map(|sample| sample.unsigned_abs()).max().unwrap();
+            assert!(samples[0].unsigned_abs() < 1000);
+            assert!(peak(&samples[..100]) > 20_000);
+            assert!(peak(&samples[samples.len() - 20..]) < 500);
+        }
+
+        // Rising zero crossings at the note's frequency.
+        let crossings = first.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count() as f32;
+        let expected = notes[0].frequency_hz * notes[0].duration_ms as f32 / 1000.0;
+        assert!((crossings - expected).abs() <= 1.0, "{crossings} crossings");
+    }
+
+    #[test]
+    fn player_interpolates() {
+        static PCM: [i16; 3] = [0, 6000, -6000];
+        let mut player = Player::new(RATE_HZ * 4, 0.0);
+        assert!(!player.is_playing());
+        assert_eq!(player.next_sample(), 0);
+
+        player.play(&PCM);
+        let samples: [i32; 12] = core::array::from_fn(|_| player.next_sample() >> 16);
+        let expected = [0, 1500, 3000, 4500, 6000, 3000, 0, -3000, -6000, -4500, -3000, -1500];
+        for (sample, expected) in samples.iter().zip(expected) {
+            assert!((sample - expected).abs() <= 1, "{samples:?}");
+        }
+
+        assert!(!player.is_playing());
+        assert_eq!(player.next_sample(), 0);
+    }
+
+    #[test]
+    fn player_level() {
+        static PCM: [i16; 2] = [16384, 16384];
+        let mut player = Player::new(RATE_HZ, -20.0);
+        player.play(&PCM);
+
+        let sample = player.next_sample() as f32 / (16384 << 16) as f32;
+        assert!((sample - 0.1).abs() < 0.001);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::gain::{db_to_linear, exp2, linear_to_db};
+
+// Full scale of 32 bit PCM samples.
+const FULL_SCALE: f32 = 2_147_483_648.0;
+
+// Lower bound of the detected level, which avoids the logarithm of zero for silent blocks.
+const FLOOR_DB: f32 = -120.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompressorParams {
+    /// Level in dBFS, above which the gain is reduced.
+    pub threshold_db: f32,
+    /// Ratio of input level to output level above the threshold.
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    /// Gain after compression, which raises quiet passages.
+    pub makeup_db: f32,
+}
+
+/// A feed-forward compressor with a hard knee.
+///
+/// The gain is derived from the peak magnitude of blocks of samples, rather than per sample, which keeps the cost of
+/// the logarithm and exponential low. Attack and release smooth the gain reduction in the decibel domain.
+pub struct Compressor {
+    params: CompressorParams,
+    sample_rate_hz: f32,
+    reduction_db: f32,
+}
+
+impl Compressor {
+    pub fn new(params: CompressorParams, sample_rate_hz: u32) -> Self {
+        Self {
+            params,
+            sample_rate_hz: sample_rate_hz as f32,
+            reduction_db: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.reduction_db = 0.0;
+    }
+
+    /// The current gain reduction in dB, which is zero or negative.
+    pub fn reduction_db(&self) -> f32 {
+        self.reduction_db
+    }
+
+    /// Update the gain from the peak magnitude of a block of `frame_count` frames, and return it as a linear factor.
+    pub fn update(&mut self, peak: u32, frame_count: usize) -> f32 {
+        let params = &self.params;
+        let level_db = linear_to_db(peak as f32 / FULL_SCALE).max(FLOOR_DB);
+
+        let target_db = if level_db > params.threshold_db {
+            (params.threshold_db - level_db) * (1.0 - 1.0 / params.ratio)
+        } else {
+            0.0
+        };
+
+        // A first-order smoother, with the attack time for increasing reduction, and the release time otherwise.
+        let time_ms = if target_db < self.reduction_db {
+            params.attack_ms
+        } else {
+            params.release_ms
+        };
+        let time_constant = time_ms * 1e-3 * self.sample_rate_hz;
+        let coefficient = 1.0 - exp2(-(frame_count as f32) * core::f32::consts::LOG2_E / time_constant);
+
+        self.reduction_db += (target_db - self.reduction_db) * coefficient;
+
+        db_to_linear(self.reduction_db + params.makeup_db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: CompressorParams = CompressorParams {
+        threshold_db: -20.0,
+        ratio: 3.0,
+        attack_ms: 10.0,
+        release_ms: 100.0,
+        makeup_db: 0.0,
+    };
+
+    // A peak magnitude at the given level in dBFS.
+    fn peak(level_db: f32) -> u32 {
+        (db_to_linear(level_db) * FULL_SCALE) as u32
+    }
+
+    #[test]
+    fn quiet_signals_pass() {
+        let mut compressor = Compressor::new(PARAMS, 48_000);
+
+        for _ in 0..100 {
+            assert!((compressor.update(peak(-30.0), 48) - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn loud_signals_settle_at_the_ratio() {
+        let mut compressor = Compressor::new(PARAMS, 48_000);
+
+        // 12 dB above the threshold are reduced to 4 dB, after many attack time constants.
+        for _ in 0..200 {
+            compressor.update(peak(-8.0), 48);
+        }
+        assert!((compressor.reduction_db() + 8.0).abs() < 0.01);
+
+        // Release is slower than attack.
+        compressor.update(peak(-60.0), 48);
+        assert!(compressor.reduction_db() < -7.0);
+
+        compressor.reset();
+        assert_eq!(compressor.reduction_db(), 0.0);
+    }
+
+    #[test]
+    fn silence_is_bounded() {
+        let mut compressor = Compressor::new(
+            CompressorParams {
+                makeup_db: 6.0,
+                ..PARAMS
+            },
+            48_000,
+        );
+
+        assert!((compressor.update(0, 48) - 2.0).abs() < 0.01);
+    }
+}
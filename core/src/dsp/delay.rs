@@ -0,0 +1,98 @@
+use super::sample::Sample;
+
+/// Speed of sound in air at 20 degrees Celsius, in m/s.
+pub const SPEED_OF_SOUND: f32 = 343.0;
+
+/// The delay in samples that corresponds to a distance in mm, rounded to the nearest sample.
+pub fn samples_from_distance_mm(distance_mm: f32, sample_rate_hz: u32) -> u32 {
+    (distance_mm * 1e-3 / SPEED_OF_SOUND * sample_rate_hz as f32 + 0.5) as u32
+}
+
+/// A delay line of up to `N` samples, in a ring buffer.
+pub struct Delay<S: Sample, const N: usize> {
+    buffer: [S; N],
+    // Position of the next write.
+    index: usize,
+    delay: usize,
+}
+
+impl<S: Sample, const N: usize> Delay<S, N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [S::ZERO; N],
+            index: 0,
+            delay: 0,
+        }
+    }
+
+    /// Set the delay in samples, which is limited to `N`. The buffered samples are kept.
+    pub fn set_delay(&mut self, delay: usize) {
+        self.delay = delay.min(N);
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer = [S::ZERO; N];
+    }
+
+    #[inline]
+    pub fn process(&mut self, sample: S) -> S {
+        let output = if self.delay == 0 {
+            sample
+        } else {
+            let read = if self.index >= self.delay {
+                self.index - self.delay
+            } else {
+                self.index + N - self.delay
+            };
+
+            self.buffer[read]
+        };
+
+        // Samples are buffered without delay as well, such that a new delay starts from past samples.
+        self.buffer[self.index] = sample;
+        self.index = if self.index + 1 == N { 0 } else { self.index + 1 };
+
+        output
+    }
+}
+
+impl<S: Sample, const N: usize> Default for Delay<S, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_by_samples() {
+        let mut delay = Delay::<i32, 4>::new();
+        delay.set_delay(2);
+
+        let output: [i32; 6] = core::array::from_fn(|index| delay.process(index as i32 + 1));
+        assert_eq!(output, [0, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn maximum_delay() {
+        let mut delay = Delay::<f32, 3>::new();
+        delay.set_delay(10);
+        assert_eq!(delay.delay(), 3);
+
+        let output: [f32; 5] = core::array::from_fn(|index| delay.process(index as f32 + 1.0));
+        assert_eq!(output, [0.0, 0.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn distance() {
+        // 343 mm take 1 ms.
+        assert_eq!(samples_from_distance_mm(343.0, 48_000), 48);
+        assert_eq!(samples_from_distance_mm(0.0, 48_000), 0);
+    }
+}
@@ -0,0 +1,200 @@
+// Biquad filter design, after the "Audio EQ Cookbook" by Robert Bristow-Johnson.
+//
+// `core` provides no trigonometric functions, so polynomial approximations are used. Designs must keep all normalized
+// coefficients within the range of the fixed-point format, which limits boosts to about +6 dB.
+use super::biquad::Coefficients;
+use crate::gain::db_to_linear;
+
+const PI: f32 = core::f32::consts::PI;
+
+// Quality factor of a second-order Butterworth filter (1 / sqrt(2)).
+pub const BUTTERWORTH_Q: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+// Quality factors of the two cascaded sections of a fourth-order Butterworth filter (1 / (2 * cos(pi/8)) and
+// 1 / (2 * cos(3pi/8))).
+pub const BUTTERWORTH_ORDER_4_Q: [f32; 2] = [0.541_196_1, 1.306_563];
+
+/// Approximate `sin(x)`, with an absolute error of less than 1e-5.
+pub(crate) fn sin(x: f32) -> f32 {
+    // Reduce to the range [-pi, pi].
+    let turns = x / (2.0 * PI);
+    let truncated = turns as i32 as f32;
+    let mut x = x - 2.0 * PI * truncated;
+    if x > PI {
+        x -= 2.0 * PI;
+    } else if x < -PI {
+        x += 2.0 * PI;
+    }
+
+    // Reduce to the range [-pi/2, pi/2], by symmetry around +-pi/2.
+    if x > PI / 2.0 {
+        x = PI - x;
+    } else if x < -PI / 2.0 {
+        x = -PI - x;
+    }
+
+    // Taylor series up to the 9th order.
+    let x2 = x * x;
+    x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0))))
+}
+
+pub(crate) fn cos(x: f32) -> f32 {
+    sin(x + PI / 2.0)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter {
+    Peaking { frequency_hz: f32, q: f32, gain_db: f32 },
+    LowShelf { frequency_hz: f32, q: f32, gain_db: f32 },
+    HighShelf { frequency_hz: f32, q: f32, gain_db: f32 },
+    HighPass { frequency_hz: f32, q: f32 },
+    LowPass { frequency_hz: f32, q: f32 },
+}
+
+impl Filter {
+    /// Calculate normalized biquad coefficients for a sample rate.
+    pub fn coefficients(&self, sample_rate_hz: u32) -> Coefficients {
+        let (frequency_hz, q) = match *self {
+            Filter::Peaking { frequency_hz, q, .. }
+            | Filter::LowShelf { frequency_hz, q, .. }
+            | Filter::HighShelf { frequency_hz, q, .. }
+            | Filter::HighPass { frequency_hz, q }
+            | Filter::LowPass { frequency_hz, q } => (frequency_hz, q),
+        };
+
+        let w0 = 2.0 * PI * frequency_hz / sample_rate_hz as f32;
+        let cos_w0 = cos(w0);
+        let alpha = sin(w0) / (2.0 * q);
+
+        let (b0, b1, b2, a0, a1, a2) = match *self {
+            Filter::Peaking { gain_db, .. } => {
+                let a = db_to_linear(gain_db / 2.0);
+
+                (
+                    1.0 + alpha * a,
+                    -2.0 * cos_w0,
+                    1.0 - alpha * a,
+                    1.0 + alpha / a,
+                    -2.0 * cos_w0,
+                    1.0 - alpha / a,
+                )
+            }
+            Filter::LowShelf { gain_db, .. } => {
+                let a = db_to_linear(gain_db / 2.0);
+                let beta = 2.0 * db_to_linear(gain_db / 4.0) * alpha;
+
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 + beta),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 - beta),
+                    (a + 1.0) + (a - 1.0) * cos_w0 + beta,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    (a + 1.0) + (a - 1.0) * cos_w0 - beta,
+                )
+            }
+            Filter::HighShelf { gain_db, .. } => {
+                let a = db_to_linear(gain_db / 2.0);
+                let beta = 2.0 * db_to_linear(gain_db / 4.0) * alpha;
+
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 + beta),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 - beta),
+                    (a + 1.0) - (a - 1.0) * cos_w0 + beta,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    (a + 1.0) - (a - 1.0) * cos_w0 - beta,
+                )
+            }
+            Filter::HighPass { .. } => (
+                (1.0 + cos_w0) / 2.0,
+                -(1.0 + cos_w0),
+                (1.0 + cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+            Filter::LowPass { .. } => (
+                (1.0 - cos_w0) / 2.0,
+                1.0 - cos_w0,
+                (1.0 - cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Magnitude response at DC and at Nyquist.
+    fn dc_gain(c: &Coefficients) -> f32 {
+        (c.b0 + c.b1 + c.b2) / (1.0 + c.a1 + c.a2)
+    }
+
+    fn nyquist_gain(c: &Coefficients) -> f32 {
+        (c.b0 - c.b1 + c.b2) / (1.0 - c.a1 + c.a2)
+    }
+
+    #[test]
+    fn sine_approximation() {
+        for step in -100..=100 {
+            let x = step as f32 * 0.1;
+            let expected = reference_sin(x);
+            assert!((sin(x) - expected).abs() < 1e-5, "sin({x})");
+        }
+    }
+
+    // Reference sine, by a long Taylor series after range reduction.
+    fn reference_sin(x: f32) -> f32 {
+        let x = x as f64 % (2.0 * core::f64::consts::PI);
+        let (mut term, mut sum) = (x, x);
+        for n in 1..30 {
+            term *= -x * x / ((2 * n) as f64 * (2 * n + 1) as f64);
+            sum += term;
+        }
+        sum as f32
+    }
+
+    #[test]
+    fn pass_and_stop_bands() {
+        let low_pass = Filter::LowPass {
+            frequency_hz: 1000.0,
+            q: BUTTERWORTH_Q,
+        }
+        .coefficients(48_000);
+        let high_pass = Filter::HighPass {
+            frequency_hz: 1000.0,
+            q: BUTTERWORTH_Q,
+        }
+        .coefficients(48_000);
+
+        assert!((dc_gain(&low_pass) - 1.0).abs() < 1e-4);
+        assert!(nyquist_gain(&low_pass).abs() < 1e-4);
+        assert!(dc_gain(&high_pass).abs() < 1e-4);
+        assert!((nyquist_gain(&high_pass) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn shelf_gains() {
+        let low_shelf = Filter::LowShelf {
+            frequency_hz: 200.0,
+            q: BUTTERWORTH_Q,
+            gain_db: -6.0,
+        }
+        .coefficients(48_000);
+
+        assert!((dc_gain(&low_shelf) - db_to_linear(-6.0)).abs() < 1e-3);
+        assert!((nyquist_gain(&low_shelf) - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,141 @@
+use super::design::{cos, sin};
+use crate::gain::linear_to_db;
+
+const PI: f32 = core::f32::consts::PI;
+
+// Full scale of 32 bit PCM samples.
+const FULL_SCALE: f32 = 2_147_483_648.0;
+
+/// Lowest reported magnitude in dBFS.
+pub const FLOOR_DB: f32 = -140.0;
+
+/// In-place radix-2 FFT of complex values, given as real and imaginary parts. The length must be a power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    bit_reverse(re, im);
+
+    let mut size = 2;
+    while size <= re.len() {
+        butterflies(re, im, size);
+        size *= 2;
+    }
+}
+
+/// The first step of the FFT: the bit-reversal permutation.
+pub fn bit_reverse(re: &mut [f32], im: &mut [f32]) {
+    let length = re.len();
+    assert!(length.is_power_of_two() && im.len() == length);
+
+    let bits = length.trailing_zeros();
+    for index in 0..length {
+        let reversed = index.reverse_bits() >> (usize::BITS - bits);
+        if bits > 0 && reversed > index {
+            re.swap(index, reversed);
+            im.swap(index, reversed);
+        }
+    }
+}
+
+/// A stage of the FFT, which combines transforms of `size / 2` into transforms of `size`. Stages run for sizes from two
+/// up to the length, which allows for splitting the computation.
+pub fn butterflies(re: &mut [f32], im: &mut [f32], size: usize) {
+    let half = size / 2;
+
+    for k in 0..half {
+        let angle = -2.0 * PI * k as f32 / size as f32;
+        let (w_re, w_im) = (cos(angle), sin(angle));
+
+        for start in (0..re.len()).step_by(size) {
+            let (a, b) = (start + k, start + k + half);
+            let t_re = re[b] * w_re - im[b] * w_im;
+            let t_im = re[b] * w_im + im[b] * w_re;
+
+            re[b] = re[a] - t_re;
+            im[b] = im[a] - t_im;
+            re[a] += t_re;
+            im[a] += t_im;
+        }
+    }
+}
+
+/// The Hann window's value at `index` of `length`.
+pub fn hann(index: usize, length: usize) -> f32 {
+    0.5 - 0.5 * cos(2.0 * PI * index as f32 / length as f32)
+}
+
+/// Prepare 32 bit PCM samples for the FFT, with a Hann window.
+pub fn window(samples: &[i32], re: &mut [f32], im: &mut [f32]) {
+    let length = samples.len();
+
+    for (index, ((re, im), &sample)) in re.iter_mut().zip(im.iter_mut()).zip(samples).enumerate() {
+        *re = sample as f32 / FULL_SCALE * hann(index, length);
+        *im = 0.0;
+    }
+}
+
+/// The magnitudes of a windowed FFT in dBFS, for the bins from DC to half the sample rate (`N / 2 + 1`). A full-scale
+/// sine in the center of a bin reads 0 dBFS.
+pub fn magnitudes_db(re: &[f32], im: &[f32], magnitudes_db: &mut [f32]) {
+    let length = re.len();
+
+    // The window's coherent gain is one half, and a real sine's energy is split between two bins.
+    let scale = 4.0 / length as f32;
+
+    for (bin, magnitude_db) in magnitudes_db.iter_mut().enumerate().take(length / 2 + 1) {
+        let power = (re[bin] * re[bin] + im[bin] * im[bin]) * scale * scale;
+        *magnitude_db = if power > 0.0 {
+            (0.5 * linear_to_db(power)).max(FLOOR_DB)
+        } else {
+            FLOOR_DB
+        };
+    }
+}
+
+/// The magnitude spectrum of 32 bit PCM samples in dBFS, with a Hann window (see `magnitudes_db`). `re` and `im` are
+/// scratch buffers of the samples' length.
+pub fn spectrum_db(samples: &[i32], re: &mut [f32], im: &mut [f32], output_db: &mut [f32]) {
+    window(samples, re, im);
+    fft(re, im);
+    magnitudes_db(re, im, output_db);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LENGTH: usize = 512;
+
+    #[test]
+    fn impulse_is_flat() {
+        let mut re = [0.0; 8];
+        let mut im = [0.0; 8];
+        re[0] = 1.0;
+
+        fft(&mut re, &mut im);
+
+        for (re, im) in re.iter().zip(&im) {
+            assert!((re - 1.0).abs() < 1e-5 && im.abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn sine_peaks_in_its_bin() {
+        let bin = 32;
+        let samples: [i32; LENGTH] = core::array::from_fn(|index| {
+            (0.5 * sin(2.0 * PI * (bin * index) as f32 / LENGTH as f32) * FULL_SCALE) as i32
+        });
+
+        let mut re = [0.0; LENGTH];
+        let mut im = [0.0; LENGTH];
+        let mut magnitudes_db = [0.0; LENGTH / 2 + 1];
+        spectrum_db(&samples, &mut re, &mut im, &mut magnitudes_db);
+
+        assert!((magnitudes_db[bin] + 6.02).abs() < 0.1, "{}", magnitudes_db[bin]);
+
+        // The Hann window's leakage is limited to the neighboring bins.
+        for (index, &magnitude_db) in magnitudes_db.iter().enumerate() {
+            if index.abs_diff(bin) > 1 {
+                assert!(magnitude_db < -80.0, "bin {index}: {magnitude_db}");
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use super::sample::Sample;
+
+// Fixed-point coefficients are stored in Q31 format.
+const COEFFICIENT_SHIFT: u32 = 31;
+
+/// An FIR filter with `N` taps. Fixed-point filters use a 64 bit accumulator.
+pub struct Fir<S: Sample, const N: usize> {
+    coefficients: [S::Coefficient; N],
+
+    // Circular buffer of past input samples, `index` points to the most recent one.
+    history: [S; N],
+    index: usize,
+}
+
+/// Convert floating-point FIR coefficients to the sample representation.
+pub fn coefficients<S: Sample, const N: usize>(coefficients: &[f32; N]) -> [S::Coefficient; N] {
+    coefficients.map(|coefficient| S::coefficient(coefficient, COEFFICIENT_SHIFT))
+}
+
+impl<S: Sample, const N: usize> Fir<S, N> {
+    /// Create a filter from coefficients in the sample representation (Q31 for fixed point).
+    pub const fn new(coefficients: [S::Coefficient; N]) -> Self {
+        Self {
+            coefficients,
+            history: [S::ZERO; N],
+            index: 0,
+        }
+    }
+
+    pub fn set_coefficients(&mut self, coefficients: &[S::Coefficient; N]) {
+        self.coefficients = *coefficients;
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [S::ZERO; N];
+    }
+
+    #[inline]
+    pub fn process(&mut self, sample: S) -> S {
+        self.index = if self.index == 0 { N - 1 } else { self.index - 1 };
+        self.history[self.index] = sample;
+
+        // Split the circular buffer into two contiguous parts, for efficient iteration.
+        let (older, newer) = self.history.split_at(self.index);
+        let mut accumulator = S::accumulator();
+
+        for (coefficient, sample) in self.coefficients.iter().zip(newer.iter().chain(older.iter())) {
+            accumulator = S::mac(accumulator, *coefficient, *sample);
+        }
+
+        S::from_accumulator(accumulator, COEFFICIENT_SHIFT)
+    }
+
+    pub fn process_block(&mut self, samples: &mut [S]) {
+        for sample in samples.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAPS: [f32; 4] = [0.5, 0.25, -0.125, 0.0625];
+
+    #[test]
+    fn impulse_response() {
+        let mut fir = Fir::<i32, 4>::new(coefficients::<i32, 4>(&TAPS));
+        let mut samples = [i32::MAX, 0, 0, 0, 0];
+        fir.process_block(&mut samples);
+
+        for (&output, tap) in samples.iter().zip(TAPS.iter().chain(&[0.0])) {
+            assert!(output.abs_diff((tap * i32::MAX as f32) as i32) <= 2);
+        }
+    }
+
+    #[test]
+    fn reset_clears_history() {
+        let mut fir = Fir::<f32, 4>::new(TAPS);
+        fir.process(1.0);
+        fir.reset();
+
+        assert_eq!(fir.process(0.0), 0.0);
+    }
+}
@@ -0,0 +1,210 @@
+// Rational sample rate conversion by polyphase interpolation, e.g. for upsampling telephony rates to the output rate.
+//
+// The input is conceptually upsampled by `up` (zero stuffing), low-pass filtered at the input's Nyquist frequency, and
+// decimated by `down`. Only the polyphase branch of each output sample is computed. The prototype filter is a
+// Hann-windowed sinc of `TAPS_PER_PHASE` taps per branch, which is designed when the ratio is set, with unity gain per
+// branch, so that constant signals pass unchanged.
+use core::f32::consts::PI;
+
+use super::design::{cos, sin};
+use super::sample::Sample;
+
+/// Highest upsampling factor of a reduced ratio.
+pub const MAX_UP: usize = 6;
+
+/// Taps of each polyphase branch.
+pub const TAPS_PER_PHASE: usize = 8;
+
+// Coefficients are stored in Q2.30 format, since branch peaks may slightly exceed unity.
+const COEFFICIENT_SHIFT: u32 = 30;
+
+/// A reduced ratio of output to input sample rate, for upsampling.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ratio {
+    pub up: usize,
+    pub down: usize,
+}
+
+impl Ratio {
+    /// The ratio between two sample rates, if the output rate is not lower, and the reduced factor is supported.
+    pub const fn new(input_hz: u32, output_hz: u32) -> Option<Self> {
+        if input_hz == 0 || output_hz < input_hz {
+            return None;
+        }
+
+        // Greatest common divisor.
+        let (mut a, mut b) = (output_hz, input_hz);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+
+        let (up, down) = ((output_hz / a) as usize, (input_hz / a) as usize);
+        if up > MAX_UP {
+            return None;
+        }
+
+        Some(Self { up, down })
+    }
+}
+
+/// Interpolates interleaved frames of `C` channels.
+pub struct Interpolator<S: Sample, const C: usize> {
+    ratio: Ratio,
+
+    // Coefficients per branch, for the newest input sample first.
+    coefficients: [[S::Coefficient; TAPS_PER_PHASE]; MAX_UP],
+
+    // Past input samples per channel, the newest first.
+    history: [[S; TAPS_PER_PHASE]; C],
+
+    // Position of the next output in the upsampled sequence, relative to the newest input sample.
+    phase: usize,
+}
+
+impl<S: Sample, const C: usize> Interpolator<S, C> {
+    pub fn new(ratio: Ratio) -> Self {
+        let mut interpolator = Self {
+            ratio,
+            coefficients: [[S::Coefficient::default(); TAPS_PER_PHASE]; MAX_UP],
+            history: [[S::ZERO; TAPS_PER_PHASE]; C],
+            phase: 0,
+        };
+
+        interpolator.set_ratio(ratio);
+        interpolator
+    }
+
+    pub fn ratio(&self) -> Ratio {
+        self.ratio
+    }
+
+    /// Set the ratio, which designs the filter, and resets the history.
+    pub fn set_ratio(&mut self, ratio: Ratio) {
+        let length = ratio.up * TAPS_PER_PHASE;
+        let center = (length - 1) as f32 / 2.0;
+
+        // Windowed sinc with its cutoff at the input's Nyquist frequency.
+        let prototype = |index: usize| {
+            let x = (index as f32 - center) / ratio.up as f32;
+            let sinc = if x == 0.0 { 1.0 } else { sin(PI * x) / (PI * x) };
+            let window = 0.5 - 0.5 * cos(2.0 * PI * (index as f32 + 0.5) / length as f32);
+            sinc * window
+        };
+
+        for (phase, coefficients) in self.coefficients[..ratio.up].iter_mut().enumerate() {
+            let taps: [f32; TAPS_PER_PHASE] = core::array::from_fn(|tap| prototype(phase + ratio.up * tap));
+            let sum: f32 = taps.iter().sum();
+
+            *coefficients = taps.map(|tap| S::coefficient(tap / sum, COEFFICIENT_SHIFT));
+        }
+
+        self.ratio = ratio;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [[S::ZERO; TAPS_PER_PHASE]; C];
+        self.phase = 0;
+    }
+
+    /// Interpolate interleaved input frames into output frames, as many as fit. Returns the number of output samples.
+    pub fn process(&mut self, input: &[S], output: &mut [S]) -> usize {
+        let mut written = 0;
+
+        for frame in input.chunks_exact(C) {
+            for (history, &sample) in self.history.iter_mut().zip(frame) {
+                history.copy_within(..TAPS_PER_PHASE - 1, 1);
+                history[0] = sample;
+            }
+
+            while self.phase < self.ratio.up {
+                let Some(output_frame) = output.get_mut(written..written + C) else {
+                    break;
+                };
+
+                let coefficients = &self.coefficients[self.phase];
+                for (sample, history) in output_frame.iter_mut().zip(&self.history) {
+                    let mut accumulator = S::accumulator();
+                    for (&coefficient, &past) in coefficients.iter().zip(history) {
+                        accumulator = S::mac(accumulator, coefficient, past);
+                    }
+                    *sample = S::from_accumulator(accumulator, COEFFICIENT_SHIFT);
+                }
+
+                written += C;
+                self.phase += self.ratio.down;
+            }
+
+            self.phase = self.phase.saturating_sub(self.ratio.up);
+        }
+
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratios() {
+        assert_eq!(Ratio::new(8_000, 48_000), Some(Ratio { up: 6, down: 1 }));
+        assert_eq!(Ratio::new(32_000, 48_000), Some(Ratio { up: 3, down: 2 }));
+        assert_eq!(Ratio::new(48_000, 48_000), Some(Ratio { up: 1, down: 1 }));
+        assert_eq!(Ratio::new(48_000, 32_000), None);
+        assert_eq!(Ratio::new(44_100, 48_000), None);
+    }
+
+    #[test]
+    fn output_frames() {
+        for (input_hz, output_frames) in [(8_000, 48), (16_000, 48), (32_000, 48)] {
+            let ratio = Ratio::new(input_hz, 48_000).unwrap();
+            let mut interpolator = Interpolator::<i32, 2>::new(ratio);
+
+            let input_frames = input_hz as usize / 1000;
+            let input = [0; 64];
+            let mut output = [0; 2 * 48];
+            assert_eq!(
+                interpolator.process(&input[..2 * input_frames], &mut output),
+                2 * output_frames
+            );
+        }
+    }
+
+    #[test]
+    fn constant() {
+        let mut interpolator = Interpolator::<i32, 2>::new(Ratio::new(16_000, 48_000).unwrap());
+        let input: [i32; 32] = core::array::from_fn(|index| if index % 2 == 0 { 1 << 28 } else { -(1 << 28) });
+        let mut output = [0; 2 * 48];
+
+        // Once the history is filled, a constant signal passes unchanged.
+        interpolator.process(&input, &mut output);
+        for frame in output[2 * 3 * TAPS_PER_PHASE..].chunks_exact(2) {
+            assert!(frame[0].abs_diff(1 << 28) < 1 << 12);
+            assert!(frame[1].abs_diff(-(1 << 28)) < 1 << 12);
+        }
+    }
+
+    #[test]
+    fn sine() {
+        // A 1 kHz tone at 8 kHz stays a 1 kHz tone at 48 kHz, without images.
+        let mut interpolator = Interpolator::<f32, 1>::new(Ratio::new(8_000, 48_000).unwrap());
+        let input: [f32; 64] = core::array::from_fn(|index| sin(2.0 * PI * index as f32 / 8.0));
+        let mut output = [0.0; 6 * 64];
+        interpolator.process(&input, &mut output);
+
+        // The filter delays by half its length.
+        let delay = (6 * TAPS_PER_PHASE - 1) as f32 / 2.0;
+        for (index, &sample) in output.iter().enumerate().skip(6 * TAPS_PER_PHASE) {
+            let expected = sin(2.0 * PI * (index as f32 - delay) / 48.0);
+            assert!((sample - expected).abs() < 0.05, "{index}: {sample} != {expected}");
+        }
+    }
+
+    #[test]
+    fn capacity() {
+        let mut interpolator = Interpolator::<i32, 2>::new(Ratio::new(8_000, 48_000).unwrap());
+        let mut output = [0; 10];
+        assert_eq!(interpolator.process(&[0; 16], &mut output), 10);
+    }
+}
@@ -0,0 +1,130 @@
+// Fixed-point arithmetic kernels for the sample processing.
+//
+// On Cortex-M4, these use DSP instructions (SMMULR, SMLAL, QADD, SSAT) directly. With the `portable-dsp` feature, or
+// on other architectures, equivalent plain Rust implementations are used.
+
+/// Saturate a 64 bit intermediate result to 32 bit.
+#[inline(always)]
+pub fn saturate(x: i64) -> i32 {
+    x.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+#[cfg(all(target_arch = "arm", not(feature = "portable-dsp")))]
+mod imp {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn mul_q31(a: i32, b: i32) -> i32 {
+        let result: i32;
+
+        // SAFETY: Pure arithmetic on registers.
+        unsafe {
+            asm!(
+                "smmulr {r}, {a}, {b}",
+                "qadd {r}, {r}, {r}",
+                r = out(reg) result,
+                a = in(reg) a,
+                b = in(reg) b,
+                options(pure, nomem, nostack),
+            );
+        }
+
+        result
+    }
+
+    #[inline(always)]
+    pub fn mac(accumulator: i64, a: i32, b: i32) -> i64 {
+        let mut low = accumulator as u32;
+        let mut high = (accumulator >> 32) as i32;
+
+        // SAFETY: Pure arithmetic on registers.
+        unsafe {
+            asm!(
+                "smlal {low}, {high}, {a}, {b}",
+                low = inout(reg) low,
+                high = inout(reg) high,
+                a = in(reg) a,
+                b = in(reg) b,
+                options(pure, nomem, nostack, preserves_flags),
+            );
+        }
+
+        ((high as i64) << 32) | low as i64
+    }
+
+    #[inline(always)]
+    pub fn saturate_24(x: i32) -> i32 {
+        let result: i32;
+
+        // SAFETY: Pure arithmetic on registers.
+        unsafe {
+            asm!(
+                "ssat {r}, #24, {x}",
+                r = out(reg) result,
+                x = in(reg) x,
+                options(pure, nomem, nostack),
+            );
+        }
+
+        result
+    }
+}
+
+#[cfg(any(not(target_arch = "arm"), feature = "portable-dsp"))]
+mod imp {
+    #[inline(always)]
+    pub fn mul_q31(a: i32, b: i32) -> i32 {
+        let product = ((a as i64 * b as i64) + (1 << 31)) >> 32;
+        super::saturate(product << 1)
+    }
+
+    #[inline(always)]
+    pub fn mac(accumulator: i64, a: i32, b: i32) -> i64 {
+        accumulator.wrapping_add(a as i64 * b as i64)
+    }
+
+    #[inline(always)]
+    pub fn saturate_24(x: i32) -> i32 {
+        x.clamp(-(1 << 23), (1 << 23) - 1)
+    }
+}
+
+/// Multiply two Q31 values, with rounding and saturation.
+pub use imp::mul_q31;
+
+/// Multiply two 32 bit values, and accumulate into a 64 bit result.
+pub use imp::mac;
+
+/// Saturate to the 24 bit range.
+pub use imp::saturate_24;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn q31_multiplication() {
+        assert_eq!(mul_q31(1 << 30, 1 << 30), 1 << 29);
+        assert_eq!(mul_q31(-(1 << 30), 1 << 30), -(1 << 29));
+        assert!(mul_q31(i32::MAX, 12345).abs_diff(12345) <= 1);
+
+        // Only -1 * -1 overflows.
+        assert_eq!(mul_q31(i32::MIN, i32::MIN), i32::MAX);
+    }
+
+    #[test]
+    fn accumulation() {
+        assert_eq!(mac(1, 1 << 16, 1 << 16), (1 << 32) + 1);
+        assert_eq!(mac(0, i32::MIN, i32::MIN), 1 << 62);
+        assert_eq!(mac(-5, -3, 2), -11);
+    }
+
+    #[test]
+    fn saturation() {
+        assert_eq!(saturate(i64::MAX), i32::MAX);
+        assert_eq!(saturate(-(1 << 40)), i32::MIN);
+        assert_eq!(saturate_24(1 << 24), (1 << 23) - 1);
+        assert_eq!(saturate_24(-(1 << 24)), -(1 << 23));
+        assert_eq!(saturate_24(1000), 1000);
+    }
+}
@@ -0,0 +1,92 @@
+use super::design::{Filter, BUTTERWORTH_Q};
+
+/// Equal-loudness compensation, which boosts bass and treble as the volume is reduced below a reference level.
+///
+/// At low levels, the ear's sensitivity to low and high frequencies falls faster than to the midrange (ISO 226). The
+/// boosts grow linearly with the attenuation, up to a maximum, which approximates the difference between equal-loudness
+/// contours. Maxima above +6 dB exceed the fixed-point coefficient range (see `design`).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Loudness {
+    /// Volume in dB, at and above which no compensation is applied.
+    pub reference_db: f32,
+    pub low_shelf_hz: f32,
+    pub high_shelf_hz: f32,
+    /// Boosts per dB of attenuation below the reference, which must not exceed one.
+    pub low_slope: f32,
+    pub high_slope: f32,
+    pub max_low_db: f32,
+    pub max_high_db: f32,
+}
+
+impl Loudness {
+    /// The low and high shelf boosts in dB, at a volume in dB.
+    pub fn boost_db(&self, volume_db: f32) -> (f32, f32) {
+        let attenuation_db = (self.reference_db - volume_db).max(0.0);
+
+        (
+            (attenuation_db * self.low_slope).min(self.max_low_db),
+            (attenuation_db * self.high_slope).min(self.max_high_db),
+        )
+    }
+
+    /// The headroom for the boosts in dB, by which the signal is attenuated ahead of the filters.
+    ///
+    /// Since the slopes do not exceed one, the headroom never exceeds the attenuation, such that a volume control after
+    /// the filters can make up for it.
+    pub fn headroom_db(&self, volume_db: f32) -> f32 {
+        let (low_db, high_db) = self.boost_db(volume_db);
+        low_db.max(high_db)
+    }
+
+    /// The compensation filters (a low and a high shelf), at a volume in dB.
+    pub fn filters(&self, volume_db: f32) -> [Filter; 2] {
+        let (low_db, high_db) = self.boost_db(volume_db);
+
+        [
+            Filter::LowShelf {
+                frequency_hz: self.low_shelf_hz,
+                q: BUTTERWORTH_Q,
+                gain_db: low_db,
+            },
+            Filter::HighShelf {
+                frequency_hz: self.high_shelf_hz,
+                q: BUTTERWORTH_Q,
+                gain_db: high_db,
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUDNESS: Loudness = Loudness {
+        reference_db: -10.0,
+        low_shelf_hz: 100.0,
+        high_shelf_hz: 10_000.0,
+        low_slope: 0.2,
+        high_slope: 0.1,
+        max_low_db: 6.0,
+        max_high_db: 3.0,
+    };
+
+    #[test]
+    fn boost_follows_the_volume() {
+        assert_eq!(LOUDNESS.boost_db(0.0), (0.0, 0.0));
+        assert_eq!(LOUDNESS.boost_db(-10.0), (0.0, 0.0));
+        assert_eq!(LOUDNESS.boost_db(-30.0), (4.0, 2.0));
+        assert_eq!(LOUDNESS.headroom_db(-30.0), 4.0);
+
+        // Limited to the maxima.
+        assert_eq!(LOUDNESS.boost_db(-100.0), (6.0, 3.0));
+    }
+
+    #[test]
+    fn filters_are_shelves() {
+        let [low, high] = LOUDNESS.filters(-30.0);
+
+        assert!(matches!(low, Filter::LowShelf { gain_db, .. } if gain_db == 4.0));
+        assert!(matches!(high, Filter::HighShelf { gain_db, .. } if gain_db == 2.0));
+    }
+}
@@ -0,0 +1,175 @@
+// Speaker protection, which models a driver's excursion and voice coil temperature from the signal, and reduces the
+// gain of the band that exceeds its modeled limit.
+//
+// Excursion is dominated by low frequencies, so the peak level of the low band serves as its proxy, and is limited
+// immediately. The voice coil's temperature follows the full-band power with a first-order thermal time constant, such
+// that short bursts above the sustained power limit pass, until the coil has heated up. From then on, the full band is
+// limited to the sustained power, at which the modeled temperature settles at its limit.
+//
+// Levels are measured after the reductions, which the model accounts for. Like the compressor, the model is updated
+// once per block.
+use crate::gain::{db_to_linear, exp2, linear_to_db};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ProtectionParams {
+    /// Corner frequency of the low band, below which excursion is modeled, in Hz.
+    pub excursion_hz: f32,
+    /// Peak level of the low band in dBFS, at which the driver reaches its excursion limit.
+    pub excursion_limit_db: f32,
+    /// RMS level in dBFS, which the voice coil sustains indefinitely.
+    pub thermal_limit_db: f32,
+    /// Thermal time constant of the voice coil, in s.
+    pub thermal_time_constant_s: f32,
+    /// Release time of both gain reductions, in ms.
+    pub release_ms: f32,
+}
+
+/// An excursion and thermal model of a driver, which derives the gain reductions of the low band and of the full band.
+pub struct Protection {
+    params: ProtectionParams,
+    sample_rate_hz: f32,
+    excursion_reduction_db: f32,
+    thermal_reduction_db: f32,
+    // Modeled temperature rise, as the smoothed mean square of the full band, relative to full scale.
+    thermal_power: f32,
+}
+
+// A first-order smoothing coefficient for a block of `frame_count` frames, and a time constant in samples.
+fn smoothing(frame_count: usize, time_constant: f32) -> f32 {
+    1.0 - exp2(-(frame_count as f32) * core::f32::consts::LOG2_E / time_constant)
+}
+
+impl Protection {
+    pub fn new(params: ProtectionParams, sample_rate_hz: u32) -> Self {
+        Self {
+            params,
+            sample_rate_hz: sample_rate_hz as f32,
+            excursion_reduction_db: 0.0,
+            thermal_reduction_db: 0.0,
+            thermal_power: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.excursion_reduction_db = 0.0;
+        self.thermal_reduction_db = 0.0;
+        self.thermal_power = 0.0;
+    }
+
+    /// The low band's gain reduction in dB, which is zero or negative.
+    pub fn excursion_reduction_db(&self) -> f32 {
+        self.excursion_reduction_db
+    }
+
+    /// The full band's gain reduction in dB, which is zero or negative.
+    pub fn thermal_reduction_db(&self) -> f32 {
+        self.thermal_reduction_db
+    }
+
+    /// The modeled temperature rise, as an RMS level in dBFS.
+    pub fn thermal_level_db(&self) -> f32 {
+        0.5 * linear_to_db(self.thermal_power)
+    }
+
+    /// Update the model from the low band's peak level and the full band's RMS level in dBFS, over a block of
+    /// `frame_count` frames.
+    pub fn update(&mut self, low_peak_db: f32, full_rms_db: f32, frame_count: usize) {
+        let params = &self.params;
+        let release = smoothing(frame_count, params.release_ms * 1e-3 * self.sample_rate_hz);
+
+        // Excursion follows the signal without delay, so that reductions apply at once.
+        let target_db = (params.excursion_limit_db - (low_peak_db - self.excursion_reduction_db)).min(0.0);
+        if target_db < self.excursion_reduction_db {
+            self.excursion_reduction_db = target_db;
+        } else {
+            self.excursion_reduction_db += (target_db - self.excursion_reduction_db) * release;
+        }
+
+        // The coil heats with the delivered power, and is limited to the sustained power, while at its limit.
+        let full_amplitude = db_to_linear(full_rms_db);
+        let heating = smoothing(frame_count, params.thermal_time_constant_s * self.sample_rate_hz);
+        self.thermal_power += (full_amplitude * full_amplitude - self.thermal_power) * heating;
+
+        let target_db = if self.thermal_level_db() >= params.thermal_limit_db {
+            (params.thermal_limit_db - (full_rms_db - self.thermal_reduction_db)).min(0.0)
+        } else {
+            0.0
+        };
+        if target_db < self.thermal_reduction_db {
+            self.thermal_reduction_db = target_db;
+        } else {
+            self.thermal_reduction_db += (target_db - self.thermal_reduction_db) * release;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: ProtectionParams = ProtectionParams {
+        excursion_hz: 150.0,
+        excursion_limit_db: -6.0,
+        thermal_limit_db: -12.0,
+        thermal_time_constant_s: 1.0,
+        release_ms: 100.0,
+    };
+
+    // One millisecond blocks at 48 kHz.
+    const FRAME_COUNT: usize = 48;
+
+    #[test]
+    fn quiet_signals_pass() {
+        let mut protection = Protection::new(PARAMS, 48_000);
+
+        for _ in 0..10_000 {
+            protection.update(-10.0, -20.0, FRAME_COUNT);
+        }
+        assert_eq!(protection.excursion_reduction_db(), 0.0);
+        assert_eq!(protection.thermal_reduction_db(), 0.0);
+    }
+
+    #[test]
+    fn excursion_is_limited_at_once() {
+        let mut protection = Protection::new(PARAMS, 48_000);
+
+        protection.update(0.0, -20.0, FRAME_COUNT);
+        assert!((protection.excursion_reduction_db() + 6.0).abs() < 1e-4);
+
+        // The measured level includes the reduction, which is kept while the input stays as loud.
+        protection.update(-6.0, -20.0, FRAME_COUNT);
+        assert!((protection.excursion_reduction_db() + 6.0).abs() < 1e-4);
+
+        // Release is gradual.
+        protection.update(-60.0, -60.0, FRAME_COUNT);
+        assert!(protection.excursion_reduction_db() < -5.0);
+        assert_eq!(protection.thermal_reduction_db(), 0.0);
+    }
+
+    #[test]
+    fn bursts_pass_until_the_coil_heats_up() {
+        let mut protection = Protection::new(PARAMS, 48_000);
+
+        // 6 dB above the sustained power heat the coil to its limit after ln(4/3) time constants, about 290 ms.
+        for _ in 0..250 {
+            protection.update(-20.0, -6.0, FRAME_COUNT);
+        }
+        assert_eq!(protection.thermal_reduction_db(), 0.0);
+
+        for _ in 0..100 {
+            let delivered_db = -6.0 + protection.thermal_reduction_db();
+            protection.update(-20.0, delivered_db, FRAME_COUNT);
+        }
+        assert!((protection.thermal_reduction_db() + 6.0).abs() < 0.1);
+
+        // The temperature then settles at the limit.
+        for _ in 0..10_000 {
+            let delivered_db = -6.0 + protection.thermal_reduction_db();
+            protection.update(-20.0, delivered_db, FRAME_COUNT);
+        }
+        assert!((protection.thermal_level_db() - PARAMS.thermal_limit_db).abs() < 0.5);
+
+        protection.reset();
+        assert_eq!(protection.thermal_reduction_db(), 0.0);
+    }
+}
@@ -0,0 +1,155 @@
+// Sample representations for the DSP chain: Q31 fixed point (`i32`), or single precision floating point (`f32`).
+//
+// Filters are generic over the representation. Fixed-point coefficients use a configurable number of fractional bits
+// (e.g. Q2.30 for biquads, Q31 for FIR filters), which floating-point samples ignore.
+use super::kernel::{mac, saturate};
+use super::Gain;
+
+// Full scale of 32 bit PCM samples, as a floating-point factor.
+const FULL_SCALE: f32 = 2_147_483_648.0;
+
+pub trait Sample: Copy + Default + PartialEq {
+    type Coefficient: Copy + Default;
+    type Accumulator: Copy;
+    type Gain: Copy;
+
+    const ZERO: Self;
+
+    /// Convert from a 32 bit PCM sample.
+    fn from_pcm(pcm: i32) -> Self;
+
+    /// Convert to a 32 bit PCM sample, saturating at full scale.
+    fn to_pcm(self) -> i32;
+
+    /// Convert a coefficient to the representation, with `fraction_bits` for fixed-point formats.
+    fn coefficient(value: f32, fraction_bits: u32) -> Self::Coefficient;
+
+    /// Convert a linear gain factor to the representation.
+    fn gain(linear: f32) -> Self::Gain;
+
+    fn apply_gain(self, gain: Self::Gain) -> Self;
+
+    fn accumulator() -> Self::Accumulator;
+
+    fn mac(accumulator: Self::Accumulator, coefficient: Self::Coefficient, sample: Self) -> Self::Accumulator;
+
+    /// Convert an accumulated sum of products back to a sample, where coefficients had `fraction_bits`.
+    fn from_accumulator(accumulator: Self::Accumulator, fraction_bits: u32) -> Self;
+}
+
+impl Sample for i32 {
+    type Coefficient = i32;
+    type Accumulator = i64;
+    type Gain = Gain;
+
+    const ZERO: Self = 0;
+
+    #[inline]
+    fn from_pcm(pcm: i32) -> Self {
+        pcm
+    }
+
+    #[inline]
+    fn to_pcm(self) -> i32 {
+        self
+    }
+
+    fn coefficient(value: f32, fraction_bits: u32) -> Self::Coefficient {
+        (value * (1u64 << fraction_bits) as f32) as i32
+    }
+
+    fn gain(linear: f32) -> Self::Gain {
+        Gain::from_linear(linear)
+    }
+
+    #[inline]
+    fn apply_gain(self, gain: Self::Gain) -> Self {
+        gain.apply(self)
+    }
+
+    #[inline]
+    fn accumulator() -> Self::Accumulator {
+        0
+    }
+
+    #[inline]
+    fn mac(accumulator: Self::Accumulator, coefficient: Self::Coefficient, sample: Self) -> Self::Accumulator {
+        mac(accumulator, coefficient, sample)
+    }
+
+    #[inline]
+    fn from_accumulator(accumulator: Self::Accumulator, fraction_bits: u32) -> Self {
+        saturate(accumulator >> fraction_bits)
+    }
+}
+
+impl Sample for f32 {
+    type Coefficient = f32;
+    type Accumulator = f32;
+    type Gain = f32;
+
+    const ZERO: Self = 0.0;
+
+    #[inline]
+    fn from_pcm(pcm: i32) -> Self {
+        pcm as f32 / FULL_SCALE
+    }
+
+    #[inline]
+    fn to_pcm(self) -> i32 {
+        // Float to integer casts saturate.
+        (self * FULL_SCALE) as i32
+    }
+
+    fn coefficient(value: f32, _fraction_bits: u32) -> Self::Coefficient {
+        value
+    }
+
+    fn gain(linear: f32) -> Self::Gain {
+        linear
+    }
+
+    #[inline]
+    fn apply_gain(self, gain: Self::Gain) -> Self {
+        self * gain
+    }
+
+    #[inline]
+    fn accumulator() -> Self::Accumulator {
+        0.0
+    }
+
+    #[inline]
+    fn mac(accumulator: Self::Accumulator, coefficient: Self::Coefficient, sample: Self) -> Self::Accumulator {
+        accumulator + coefficient * sample
+    }
+
+    #[inline]
+    fn from_accumulator(accumulator: Self::Accumulator, _fraction_bits: u32) -> Self {
+        accumulator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_conversion() {
+        assert_eq!(f32::from_pcm(i32::MIN), -1.0);
+        assert_eq!(f32::from_pcm(1 << 30), 0.5);
+        assert_eq!(0.5f32.to_pcm(), 1 << 30);
+
+        // Saturates at full scale.
+        assert_eq!(1.0f32.to_pcm(), i32::MAX);
+        assert_eq!((-2.0f32).to_pcm(), i32::MIN);
+    }
+
+    #[test]
+    fn fixed_point_coefficients() {
+        assert_eq!(i32::coefficient(0.5, 30), 1 << 29);
+        assert_eq!(i32::coefficient(-1.0, 31), i32::MIN);
+        assert_eq!(i32::from_accumulator(3 << 40, 30), 3 << 10);
+        assert_eq!(i32::from_accumulator(i64::MAX, 30), i32::MAX);
+    }
+}
@@ -0,0 +1,369 @@
+// Virtual FAT12 volume, whose sectors are generated on request, e.g. for exposing files over USB mass storage.
+//
+// The volume consists of the boot sector, two copies of the FAT, a single-sector root directory, and the data region,
+// with one sector per cluster. Files are laid out contiguously in the data region, in order, and their content is
+// provided by the caller. Written sectors are not stored: the caller classifies them by their region, and recognizes
+// written files by their content.
+
+/// Size of a sector, and of a cluster.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Size of a directory entry.
+const ENTRY_SIZE: usize = 32;
+
+const RESERVED_SECTORS: u16 = 1;
+const FAT_COUNT: u8 = 2;
+const ROOT_ENTRY_COUNT: u16 = (SECTOR_SIZE / ENTRY_SIZE) as u16;
+const ROOT_SECTORS: u16 = 1;
+const MEDIA: u8 = 0xf8;
+
+// FAT12 entries of the media descriptor, and the end of a cluster chain.
+const FAT_MEDIA_ENTRY: u16 = 0xf00 | MEDIA as u16;
+const FAT_END_OF_CHAIN: u16 = 0xfff;
+
+// Directory entry attributes.
+const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+const ATTRIBUTE_VOLUME_LABEL: u8 = 0x08;
+const ATTRIBUTE_LONG_NAME: u8 = 0x0f;
+
+// Characters of a long file name entry.
+const LONG_NAME_CHARACTERS: usize = 13;
+
+// Timestamp of all entries (2024-01-01, 00:00).
+const DATE: u16 = (2024 - 1980) << 9 | 1 << 5 | 1;
+
+/// A file on the volume.
+pub struct File<'a> {
+    /// Short (8.3) name, padded with spaces, without the dot.
+    pub name: [u8; 11],
+    /// Long name, of up to 13 ASCII characters.
+    pub long_name: Option<&'a str>,
+    pub size: u32,
+}
+
+impl File<'_> {
+    fn cluster_count(&self) -> u16 {
+        (self.size as usize).div_ceil(SECTOR_SIZE) as u16
+    }
+
+    // Directory entries of the file, including the long name's.
+    fn entry_count(&self) -> usize {
+        1 + self.long_name.is_some() as usize
+    }
+}
+
+/// The region of a sector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Boot,
+    Fat,
+    RootDirectory,
+    /// A data cluster, with the file that occupies it.
+    Data {
+        cluster: u16,
+        file: Option<usize>,
+    },
+    Outside,
+}
+
+pub struct Volume<'a> {
+    pub label: [u8; 11],
+    pub serial_number: u32,
+    pub sector_count: u16,
+    pub files: &'a [File<'a>],
+}
+
+impl Volume<'_> {
+    // Data clusters, which start at cluster 2.
+    fn cluster_count(&self) -> u16 {
+        self.sector_count - self.data_start()
+    }
+
+    /// Sectors of each FAT copy, with 1.5 byte per cluster.
+    pub fn fat_sectors(&self) -> u16 {
+        let clusters = self.sector_count as usize + 2;
+        (clusters * 3 / 2).div_ceil(SECTOR_SIZE) as u16
+    }
+
+    fn root_start(&self) -> u16 {
+        RESERVED_SECTORS + FAT_COUNT as u16 * self.fat_sectors()
+    }
+
+    fn data_start(&self) -> u16 {
+        self.root_start() + ROOT_SECTORS
+    }
+
+    // The index and first cluster of the file that occupies a cluster.
+    fn file_at(&self, cluster: u16) -> Option<(usize, u16)> {
+        let mut first = 2;
+        for (index, file) in self.files.iter().enumerate() {
+            if (first..first + file.cluster_count()).contains(&cluster) {
+                return Some((index, first));
+            }
+            first += file.cluster_count();
+        }
+
+        None
+    }
+
+    // The FAT entry of a cluster.
+    fn fat_entry(&self, cluster: u16) -> u16 {
+        match cluster {
+            0 => FAT_MEDIA_ENTRY,
+            1 => FAT_END_OF_CHAIN,
+            _ => match self.file_at(cluster) {
+                Some((index, first)) if cluster + 1 < first + self.files[index].cluster_count() => cluster + 1,
+                Some(_) => FAT_END_OF_CHAIN,
+                None => 0,
+            },
+        }
+    }
+
+    /// The region of a sector.
+    pub fn region(&self, sector: u32) -> Region {
+        let Ok(sector) = u16::try_from(sector) else {
+            return Region::Outside;
+        };
+
+        match sector {
+            _ if sector >= self.sector_count => Region::Outside,
+            _ if sector < RESERVED_SECTORS => Region::Boot,
+            _ if sector < self.root_start() => Region::Fat,
+            _ if sector < self.data_start() => Region::RootDirectory,
+            _ => {
+                let cluster = sector - self.data_start() + 2;
+                Region::Data {
+                    cluster,
+                    file: self.file_at(cluster).map(|(index, _)| index),
+                }
+            }
+        }
+    }
+
+    /// Generate a sector. File content is read with `read_file(index, offset, buffer)`, which fills the buffer from the
+    /// offset within the file on. The rest of the sector is zero.
+    pub fn read_sector(
+        &self,
+        sector: u32,
+        buffer: &mut [u8; SECTOR_SIZE],
+        mut read_file: impl FnMut(usize, u32, &mut [u8]),
+    ) {
+        buffer.fill(0);
+
+        match self.region(sector) {
+            Region::Boot => self.boot_sector(buffer),
+            Region::Fat => {
+                let fat_sector = (sector as u16 - RESERVED_SECTORS) % self.fat_sectors();
+                self.fat_sector(fat_sector as usize, buffer);
+            }
+            Region::RootDirectory => self.root_directory(buffer),
+            Region::Data {
+                cluster,
+                file: Some(index),
+            } => {
+                let (_, first) = self.file_at(cluster).unwrap();
+                let offset = (cluster - first) as u32 * SECTOR_SIZE as u32;
+                let length = (self.files[index].size - offset).min(SECTOR_SIZE as u32) as usize;
+                read_file(index, offset, &mut buffer[..length]);
+            }
+            Region::Data { file: None, .. } | Region::Outside => (),
+        }
+    }
+
+    fn boot_sector(&self, buffer: &mut [u8; SECTOR_SIZE]) {
+        buffer[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        buffer[3..11].copy_from_slice(b"BLUS    ");
+        buffer[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        buffer[13] = 1;
+        buffer[14..16].copy_from_slice(&RESERVED_SECTORS.to_le_bytes());
+        buffer[16] = FAT_COUNT;
+        buffer[17..19].copy_from_slice(&ROOT_ENTRY_COUNT.to_le_bytes());
+        buffer[19..21].copy_from_slice(&self.sector_count.to_le_bytes());
+        buffer[21] = MEDIA;
+        buffer[22..24].copy_from_slice(&self.fat_sectors().to_le_bytes());
+        // Sectors per track and heads, which are irrelevant without a geometry.
+        buffer[24..26].copy_from_slice(&32u16.to_le_bytes());
+        buffer[26..28].copy_from_slice(&64u16.to_le_bytes());
+        buffer[36] = 0x80;
+        buffer[38] = 0x29;
+        buffer[39..43].copy_from_slice(&self.serial_number.to_le_bytes());
+        buffer[43..54].copy_from_slice(&self.label);
+        buffer[54..62].copy_from_slice(b"FAT12   ");
+        buffer[510..].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    fn fat_sector(&self, fat_sector: usize, buffer: &mut [u8; SECTOR_SIZE]) {
+        let start = fat_sector * SECTOR_SIZE;
+
+        // Clusters whose 12 bit entries overlap the sector.
+        let first_cluster = (start * 2 / 3).saturating_sub(1);
+        let end_cluster = ((start + SECTOR_SIZE) * 2).div_ceil(3) + 1;
+        let cluster_limit = self.cluster_count() as usize + 2;
+
+        for cluster in first_cluster..end_cluster.min(cluster_limit) {
+            let entry = self.fat_entry(cluster as u16);
+            let offset = cluster * 3 / 2;
+
+            // Even clusters start on a byte, odd clusters in the upper nibble.
+            let bytes = if cluster % 2 == 0 {
+                [(offset, entry as u8, 0xff), (offset + 1, (entry >> 8) as u8, 0x0f)]
+            } else {
+                [
+                    (offset, (entry << 4) as u8, 0xf0),
+                    (offset + 1, (entry >> 4) as u8, 0xff),
+                ]
+            };
+            for (position, value, mask) in bytes {
+                if (start..start + SECTOR_SIZE).contains(&position) {
+                    buffer[position - start] |= value & mask;
+                }
+            }
+        }
+    }
+
+    fn root_directory(&self, buffer: &mut [u8; SECTOR_SIZE]) {
+        let mut entries = buffer.chunks_exact_mut(ENTRY_SIZE);
+
+        let label = entries.next().unwrap();
+        label[..11].copy_from_slice(&self.label);
+        label[11] = ATTRIBUTE_VOLUME_LABEL;
+
+        let mut cluster = 2;
+        for file in self.files {
+            // Files beyond the root directory's capacity are left out (see `fits_root_directory`).
+            if entries.len() < file.entry_count() {
+                break;
+            }
+            if let Some(long_name) = file.long_name {
+                long_name_entry(entries.next().unwrap(), long_name, &file.name);
+            }
+
+            let entry = entries.next().unwrap();
+            entry[..11].copy_from_slice(&file.name);
+            entry[11] = ATTRIBUTE_ARCHIVE;
+            for field in [14, 16, 18, 22, 24] {
+                let value = if field == 14 || field == 22 { 0 } else { DATE };
+                entry[field..field + 2].copy_from_slice(&value.to_le_bytes());
+            }
+            let first: u16 = if file.size > 0 { cluster } else { 0 };
+            entry[26..28].copy_from_slice(&first.to_le_bytes());
+            entry[28..32].copy_from_slice(&file.size.to_le_bytes());
+
+            cluster += file.cluster_count();
+        }
+    }
+
+    /// Whether the directory entries of all files fit the root directory, along with the volume label.
+    pub fn fits_root_directory(&self) -> bool {
+        self.files.iter().map(File::entry_count).sum::<usize>() < ROOT_ENTRY_COUNT as usize
+    }
+}
+
+// Checksum of a short name, which links long name entries to it.
+fn short_name_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+// A single long name entry, which precedes the short name's.
+fn long_name_entry(entry: &mut [u8], long_name: &str, short_name: &[u8; 11]) {
+    // Ordinal 1, which is the last entry of the name.
+    entry[0] = 0x41;
+    entry[11] = ATTRIBUTE_LONG_NAME;
+    entry[13] = short_name_checksum(short_name);
+
+    // UCS-2 characters, terminated by a zero and padded with 0xffff.
+    let positions = (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2));
+    let mut characters = long_name
+        .bytes()
+        .map(u16::from)
+        .chain([0])
+        .chain(core::iter::repeat(0xffff));
+    for position in positions.take(LONG_NAME_CHARACTERS) {
+        entry[position..position + 2].copy_from_slice(&characters.next().unwrap().to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILES: [File; 2] = [
+        File {
+            name: *b"README  TXT",
+            long_name: None,
+            size: 100,
+        },
+        File {
+            name: *b"CONFIG  JSO",
+            long_name: Some("config.json"),
+            size: 1000,
+        },
+    ];
+
+    const VOLUME: Volume = Volume {
+        label: *b"BLUS CONFIG",
+        serial_number: 0x1234_5678,
+        sector_count: 2048,
+        files: &FILES,
+    };
+
+    fn read(sector: u32) -> [u8; SECTOR_SIZE] {
+        let mut buffer = [0; SECTOR_SIZE];
+        VOLUME.read_sector(sector, &mut buffer, |index, offset, data| {
+            data.fill(index as u8 + 1);
+            data[0] = offset as u8;
+        });
+        buffer
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(VOLUME.fat_sectors(), 7);
+        assert!(VOLUME.fits_root_directory());
+
+        let boot = read(0);
+        assert_eq!(&boot[510..], &[0x55, 0xaa]);
+        assert_eq!(u16::from_le_bytes([boot[19], boot[20]]), 2048);
+
+        assert_eq!(VOLUME.region(1), Region::Fat);
+        assert_eq!(VOLUME.region(15), Region::RootDirectory);
+        assert_eq!(
+            VOLUME.region(16),
+            Region::Data {
+                cluster: 2,
+                file: Some(0)
+            }
+        );
+        assert_eq!(VOLUME.region(19), Region::Data { cluster: 5, file: None });
+        assert_eq!(VOLUME.region(2048), Region::Outside);
+    }
+
+    #[test]
+    fn fat_chains() {
+        // README on cluster 2, and the configuration on clusters 3 and 4, in both copies.
+        for sector in [1, 8] {
+            let fat = read(sector);
+            assert_eq!(&fat[..8], &[0xf8, 0xff, 0xff, 0xff, 0x4f, 0x00, 0xff, 0x0f]);
+            assert!(fat[8..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn directory_and_data() {
+        let root = read(15);
+        assert_eq!(&root[..11], b"BLUS CONFIG");
+        assert_eq!(&root[32..43], b"README  TXT");
+        assert_eq!(root[64 + 11], ATTRIBUTE_LONG_NAME);
+        assert_eq!(&root[64 + 1..64 + 5], &[b'c', 0, b'o', 0]);
+        assert_eq!(&root[96..107], b"CONFIG  JSO");
+        assert_eq!(u16::from_le_bytes([root[96 + 26], root[96 + 27]]), 3);
+        assert_eq!(u32::from_le_bytes(root[96 + 28..96 + 32].try_into().unwrap()), 1000);
+
+        // The second sector of the configuration holds its last 488 byte.
+        let data = read(18);
+        assert_eq!(data[0], 512u32 as u8);
+        assert_eq!(data[487], 2);
+        assert_eq!(data[488], 0);
+    }
+}
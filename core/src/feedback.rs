@@ -0,0 +1,385 @@
+// Accumulates SOF capture timestamps over the feedback refresh period.
+//
+// Timer counters wrap, so that elapsed ticks are computed with wrapping arithmetic. Counters of 16 bit timers are
+// extended to 32 bit by counting their overflows, since a refresh period spans more ticks than they can count.
+
+#[derive(Clone, Copy)]
+pub struct Accumulator {
+    period_frames: usize,
+    last_ticks: u32,
+    frame_count: usize,
+
+    // Set, if the next capture starts a new measurement.
+    restart: bool,
+}
+
+impl Accumulator {
+    /// Create an accumulator for a refresh period of `period_frames` USB (micro)frames.
+    pub const fn new(period_frames: usize) -> Self {
+        Self {
+            period_frames,
+            last_ticks: 0,
+            frame_count: 0,
+            restart: true,
+        }
+    }
+
+    /// Restart the measurement with the next capture, discarding the partial refresh period.
+    pub fn reset(&mut self) {
+        self.restart = true;
+    }
+
+    /// Add the timer counter value, captured at a SOF.
+    ///
+    /// Returns the number of timer ticks over the refresh period, when it is complete. The timer counter may wrap.
+    pub fn update(&mut self, ticks: u32) -> Option<u32> {
+        if self.restart {
+            self.restart = false;
+            self.frame_count = 0;
+            self.last_ticks = ticks;
+            return None;
+        }
+
+        self.frame_count += 1;
+        if self.frame_count < self.period_frames {
+            return None;
+        }
+
+        let elapsed = ticks.wrapping_sub(self.last_ticks);
+        self.frame_count = 0;
+        self.last_ticks = ticks;
+        Some(elapsed)
+    }
+}
+
+/// Extends a timer counter of less than 32 bit, by counting its overflows in the upper bits.
+#[derive(Clone, Copy)]
+pub struct CounterExtension {
+    bits: u32,
+    overflows: u32,
+}
+
+impl CounterExtension {
+    /// Create an extension for a counter of `bits` width (at most 32).
+    pub const fn new(bits: u32) -> Self {
+        assert!(bits > 0 && bits <= 32);
+        Self { bits, overflows: 0 }
+    }
+
+    /// Handle a timer interrupt, with its overflow (update) flag and captured counter value, if any.
+    ///
+    /// Returns the extended capture. An overflow that is flagged along with a capture preceded it, if the captured
+    /// value is in the lower half of the counter's range.
+    pub fn update(&mut self, overflow: bool, captured: Option<u32>) -> Option<u32> {
+        if self.bits == 32 {
+            return captured;
+        }
+
+        let overflows = self.overflows;
+        if overflow {
+            self.overflows = overflows.wrapping_add(1);
+        }
+
+        captured.map(|captured| {
+            let overflows = if overflow && captured < 1 << (self.bits - 1) {
+                self.overflows
+            } else {
+                overflows
+            };
+
+            overflows << self.bits | captured
+        })
+    }
+}
+
+/// Refresh periods that were measured, but not taken by the feedback task yet.
+#[derive(Clone, Copy)]
+pub struct PendingPeriods {
+    ticks: u64,
+    periods: u32,
+}
+
+impl PendingPeriods {
+    pub const fn new() -> Self {
+        Self { ticks: 0, periods: 0 }
+    }
+
+    /// Add the ticks of a refresh period. Returns `false`, if earlier periods were still pending.
+    pub fn push(&mut self, ticks: u32) -> bool {
+        let on_time = self.periods == 0;
+
+        self.ticks += ticks as u64;
+        self.periods += 1;
+        on_time
+    }
+
+    /// Take the average ticks per pending period, if any. The remainder of the division is kept for the next periods,
+    /// such that no ticks are lost.
+    pub fn take(&mut self) -> Option<u32> {
+        if self.periods == 0 {
+            return None;
+        }
+
+        let average = self.ticks / self.periods as u64;
+        self.ticks %= self.periods as u64;
+        self.periods = 0;
+        Some(average as u32)
+    }
+}
+
+impl Default for PendingPeriods {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Number of bins of the tick histogram.
+pub const HISTOGRAM_BIN_COUNT: usize = 8;
+
+// Averaging time constant of the histogram's reference period, as a power of two in periods.
+const HISTOGRAM_AVERAGE_SHIFT: u32 = 4;
+
+/// Counts refresh periods by the deviation of their ticks from the average period, so that jitter and outliers (e.g.
+/// missed SOFs) are visible, which the average hides. Bin `n` counts deviations of up to `1 << n` ticks (bin 0 up to
+/// one tick), and the last bin all larger ones.
+#[derive(Clone, Copy)]
+pub struct TickHistogram {
+    bins: [u32; HISTOGRAM_BIN_COUNT],
+    // Ticks per period, scaled by `1 << HISTOGRAM_AVERAGE_SHIFT`, or `None` until the first period after a restart.
+    average: Option<u64>,
+}
+
+impl TickHistogram {
+    pub const fn new() -> Self {
+        Self {
+            bins: [0; HISTOGRAM_BIN_COUNT],
+            average: None,
+        }
+    }
+
+    /// Restart the average with the next period, e.g. after a lost connection or a sample rate change. Counts are kept.
+    pub fn restart(&mut self) {
+        self.average = None;
+    }
+
+    /// Add the ticks of a refresh period. The first period after a restart only sets the average.
+    pub fn add(&mut self, ticks: u32) {
+        let Some(average) = self.average else {
+            self.average = Some((ticks as u64) << HISTOGRAM_AVERAGE_SHIFT);
+            return;
+        };
+
+        let deviation = (ticks as u64).abs_diff(average >> HISTOGRAM_AVERAGE_SHIFT);
+        let bin = match deviation {
+            0 | 1 => 0,
+            _ => (u64::BITS - (deviation - 1).leading_zeros()) as usize,
+        };
+        self.bins[bin.min(HISTOGRAM_BIN_COUNT - 1)] += 1;
+
+        self.average = Some(average - (average >> HISTOGRAM_AVERAGE_SHIFT) + ticks as u64);
+    }
+
+    /// The counts per bin.
+    pub fn bins(&self) -> [u32; HISTOGRAM_BIN_COUNT] {
+        self.bins
+    }
+}
+
+impl Default for TickHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The feedback value from the timer ticks over a refresh period, in samples per (micro)frame with `shift` fractional
+/// bits. `ticks_per_sample` and the period must be powers of two, such that the conversion is exact.
+pub const fn feedback_value(ticks: u32, ticks_per_sample: u32, period_frames: usize, shift: usize) -> u32 {
+    ticks * (((1 << shift) / ticks_per_sample) / period_frames as u32)
+}
+
+/// Encoding and refresh period limits of feedback values, by bus speed (USB 2.0, 5.12.4.2).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FeedbackFormat {
+    /// Fractional bits of the value in samples per (micro)frame.
+    pub shift: usize,
+    /// Size of the value on the endpoint, in byte.
+    pub size: usize,
+    /// Shortest and longest refresh period, as the log2 of its (micro)frame count.
+    pub min_refresh: usize,
+    pub max_refresh: usize,
+}
+
+impl FeedbackFormat {
+    /// 10.14 format in three byte, refreshed every 2 to 512 frames (bRefresh 1 to 9).
+    pub const FULL_SPEED: Self = Self {
+        shift: 14,
+        size: 3,
+        min_refresh: 1,
+        max_refresh: 9,
+    };
+
+    /// 16.16 format in four byte, refreshed every 1 to 32768 microframes (bInterval 1 to 16).
+    pub const HIGH_SPEED: Self = Self {
+        shift: 16,
+        size: 4,
+        min_refresh: 0,
+        max_refresh: 15,
+    };
+
+    /// Whether a refresh period of `1 << refresh` (micro)frames is allowed.
+    pub const fn is_valid_refresh(&self, refresh: usize) -> bool {
+        refresh >= self.min_refresh && refresh <= self.max_refresh
+    }
+
+    /// The nominal value at a sample rate, with `frames_per_ms` (micro)frames per millisecond.
+    pub const fn nominal(&self, sample_rate_hz: u32, frames_per_ms: usize) -> u32 {
+        (((sample_rate_hz as u64) << self.shift) / (1000 * frames_per_ms as u64)) as u32
+    }
+
+    /// Whether a value fits the endpoint's size.
+    pub const fn fits(&self, value: u32) -> bool {
+        self.size >= 4 || value < 1 << (8 * self.size)
+    }
+
+    /// Whether a value is within an eighth of the nominal value. Clocks deviate by far less, so that other values point
+    /// to a broken measurement, which hosts would reject or follow into buffer under- or overruns.
+    pub const fn is_plausible(&self, value: u32, nominal: u32) -> bool {
+        self.fits(value) && value.abs_diff(nominal) <= nominal / 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps() {
+        let mut accumulator = Accumulator::new(8);
+        let mut ticks = u32::MAX - 100;
+
+        assert_eq!(accumulator.update(ticks), None);
+        for frame in 1..=8 {
+            ticks = ticks.wrapping_add(1536);
+            assert_eq!(accumulator.update(ticks), (frame == 8).then_some(8 * 1536));
+        }
+    }
+
+    #[test]
+    fn reset_discards_period() {
+        let mut accumulator = Accumulator::new(4);
+
+        assert_eq!(accumulator.update(0), None);
+        assert_eq!(accumulator.update(10), None);
+        accumulator.reset();
+
+        assert_eq!(accumulator.update(1000), None);
+        for frame in 1..4 {
+            assert_eq!(accumulator.update(1000 + frame * 10), None);
+        }
+        assert_eq!(accumulator.update(1040), Some(40));
+    }
+
+    #[test]
+    fn extends_16_bit_counter() {
+        let mut extension = CounterExtension::new(16);
+        let mut accumulator = Accumulator::new(8);
+        let mut counter = 0xffffu32 - 100;
+
+        assert_eq!(
+            accumulator.update(extension.update(false, Some(counter)).unwrap()),
+            None
+        );
+        for frame in 1..=8 {
+            // A period spans more ticks than the counter's range.
+            let wrapped = counter + 12_288 > 0xffff;
+            counter = (counter + 12_288) & 0xffff;
+
+            let ticks = extension.update(wrapped, Some(counter)).unwrap();
+            assert_eq!(accumulator.update(ticks), (frame == 8).then_some(8 * 12_288));
+        }
+    }
+
+    #[test]
+    fn overflow_along_with_capture() {
+        let mut extension = CounterExtension::new(16);
+        assert_eq!(extension.update(true, None), None);
+
+        // The capture preceded the overflow.
+        assert_eq!(extension.update(true, Some(0xfff0)), Some(0x1_fff0));
+        // The overflow preceded the capture.
+        assert_eq!(extension.update(true, Some(0x0010)), Some(0x3_0010));
+        assert_eq!(extension.update(false, Some(0x0020)), Some(0x3_0020));
+    }
+
+    #[test]
+    fn full_width_counter_is_unchanged() {
+        let mut extension = CounterExtension::new(32);
+        assert_eq!(extension.update(true, Some(u32::MAX)), Some(u32::MAX));
+    }
+
+    #[test]
+    fn pending_periods_keep_ticks() {
+        let mut pending = PendingPeriods::new();
+        assert_eq!(pending.take(), None);
+
+        assert!(pending.push(100));
+        assert!(!pending.push(103));
+        assert_eq!(pending.take(), Some(101));
+
+        // The remainder goes into the next average.
+        assert!(pending.push(100));
+        assert_eq!(pending.take(), Some(101));
+        assert_eq!(pending.take(), None);
+    }
+
+    #[test]
+    fn nominal_feedback_value() {
+        // 48 kHz with a 12.288 MHz timer, over eight 1 ms frames, in 10.14 format.
+        assert_eq!(feedback_value(8 * 12_288, 256, 8, 14), 48 << 14);
+    }
+
+    #[test]
+    fn feedback_formats() {
+        let full_speed = FeedbackFormat::FULL_SPEED;
+        assert_eq!(full_speed.nominal(48_000, 1), 48 << 14);
+        assert_eq!(full_speed.nominal(44_100, 1), (441 << 14) / 10);
+        assert!(!full_speed.is_valid_refresh(0));
+        assert!(full_speed.is_valid_refresh(3));
+        assert!(!full_speed.is_valid_refresh(10));
+
+        // Six samples per microframe.
+        let high_speed = FeedbackFormat::HIGH_SPEED;
+        assert_eq!(high_speed.nominal(48_000, 8), 6 << 16);
+        assert!(high_speed.is_valid_refresh(0));
+
+        let nominal = full_speed.nominal(48_000, 1);
+        assert!(full_speed.is_plausible(nominal + 100, nominal));
+        assert!(!full_speed.is_plausible(nominal / 2, nominal));
+        assert!(!full_speed.is_plausible(1 << 24, 1 << 24));
+        assert!(high_speed.is_plausible(1 << 24, 1 << 24));
+    }
+
+    #[test]
+    fn histogram() {
+        let mut histogram = TickHistogram::new();
+
+        // The first period sets the average.
+        histogram.add(1000);
+        assert_eq!(histogram.bins(), [0; HISTOGRAM_BIN_COUNT]);
+
+        for ticks in [1000, 1001, 999, 1002, 1004, 1020] {
+            histogram.add(ticks);
+        }
+        assert_eq!(histogram.bins(), [3, 1, 1, 0, 0, 1, 0, 0]);
+
+        // A missed SOF is an outlier.
+        histogram.add(2000);
+        assert_eq!(histogram.bins()[HISTOGRAM_BIN_COUNT - 1], 1);
+
+        // Counts are kept across a restart.
+        histogram.restart();
+        histogram.add(500);
+        histogram.add(500);
+        assert_eq!(histogram.bins()[0], 4);
+    }
+}
@@ -0,0 +1,162 @@
+// Conversion between the pipeline's 32 bit samples and the packed sample formats of the streaming path.
+//
+// The pipeline works on left-aligned 32 bit samples (Q31). Narrower formats keep the most significant bits, either
+// truncated (towards negative infinity, as by a plain shift) or rounded to nearest, which saturates at full scale. DAC
+// interface formats place a narrow sample in a 32 bit slot: left-aligned for Philips I2S and left-justified formats, or
+// right-aligned and sign-extended for LSB-justified formats. DMA transfers 32 bit samples as two 16 bit words.
+
+/// Rounding of the bits that are dropped, when a sample is narrowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    /// Drop the bits, which rounds towards negative infinity.
+    Truncate,
+    /// Round to the nearest value, with halves rounded up, and saturate at positive full scale.
+    Nearest,
+}
+
+/// Placement of a narrow sample in a 32 bit slot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Justification {
+    /// Most significant bit first, followed by zeros (Philips I2S, left-justified).
+    Left,
+    /// Least significant bit last, preceded by the sign (LSB-justified).
+    Right,
+}
+
+/// Order of a 32 bit sample's halves in 16 bit DMA words.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WordOrder {
+    /// Less significant half first, as 32 bit little-endian USB samples are stored.
+    LowFirst,
+    /// More significant half first, as the I2S peripheral receives a sample.
+    HighFirst,
+}
+
+/// Reduce a sample to its `bits` most significant bits (1 to 32), as a right-aligned value.
+pub fn narrow(sample: i32, bits: u32, rounding: Rounding) -> i32 {
+    let shift = 32 - bits;
+    if shift == 0 {
+        return sample;
+    }
+
+    match rounding {
+        Rounding::Truncate => sample >> shift,
+        Rounding::Nearest => {
+            let max = (1i64 << (bits - 1)) - 1;
+            ((sample as i64 + (1 << (shift - 1))) >> shift).min(max) as i32
+        }
+    }
+}
+
+/// Extend a right-aligned value of `bits` bits (1 to 32) to a sample. Bits above the value are ignored.
+pub fn widen(value: i32, bits: u32) -> i32 {
+    value << (32 - bits)
+}
+
+/// Place a sample in a 32 bit slot with a resolution of `bits` bits.
+pub fn to_slot(sample: i32, bits: u32, justification: Justification, rounding: Rounding) -> i32 {
+    let value = narrow(sample, bits, rounding);
+
+    match justification {
+        Justification::Left => widen(value, bits),
+        Justification::Right => value,
+    }
+}
+
+/// Take a sample from a 32 bit slot with a resolution of `bits` bits. Bits outside the sample are ignored.
+pub fn from_slot(slot: i32, bits: u32, justification: Justification) -> i32 {
+    match justification {
+        Justification::Left => widen(slot >> (32 - bits), bits),
+        Justification::Right => widen(slot, bits),
+    }
+}
+
+/// Split a sample into 16 bit words.
+#[inline]
+pub fn to_words(sample: i32, order: WordOrder) -> [u16; 2] {
+    let (low, high) = (sample as u16, (sample as u32 >> 16) as u16);
+
+    match order {
+        WordOrder::LowFirst => [low, high],
+        WordOrder::HighFirst => [high, low],
+    }
+}
+
+/// Join 16 bit words into a sample.
+#[inline]
+pub fn from_words(words: [u16; 2], order: WordOrder) -> i32 {
+    let (low, high) = match order {
+        WordOrder::LowFirst => (words[0], words[1]),
+        WordOrder::HighFirst => (words[1], words[0]),
+    };
+
+    (low as u32 | (high as u32) << 16) as i32
+}
+
+/// Pack the 24 most significant bits of a sample into little-endian bytes.
+pub fn to_le_bytes_24(sample: i32, rounding: Rounding) -> [u8; 3] {
+    let [low, middle, high, _] = narrow(sample, 24, rounding).to_le_bytes();
+    [low, middle, high]
+}
+
+/// Unpack a sample from 24 bit little-endian bytes.
+pub fn from_le_bytes_24(bytes: [u8; 3]) -> i32 {
+    i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrowing() {
+        assert_eq!(narrow(0x1234_5678, 24, Rounding::Truncate), 0x12_3456);
+        assert_eq!(narrow(0x1234_5680, 24, Rounding::Nearest), 0x12_3457);
+        assert_eq!(narrow(0x1234_567f, 24, Rounding::Nearest), 0x12_3456);
+
+        // Truncation rounds negative samples down, rounding to the nearest value.
+        assert_eq!(narrow(-0x180, 24, Rounding::Truncate), -2);
+        assert_eq!(narrow(-0x181, 24, Rounding::Nearest), -2);
+        assert_eq!(narrow(-0x17f, 24, Rounding::Nearest), -1);
+
+        // Rounding up at full scale saturates.
+        assert_eq!(narrow(i32::MAX, 16, Rounding::Nearest), i16::MAX as i32);
+        assert_eq!(narrow(i32::MIN, 16, Rounding::Nearest), i16::MIN as i32);
+        assert_eq!(narrow(i32::MAX, 32, Rounding::Nearest), i32::MAX);
+    }
+
+    #[test]
+    fn slots() {
+        let sample = -0x1234_5678;
+
+        let left = to_slot(sample, 24, Justification::Left, Rounding::Truncate);
+        assert_eq!(left & 0xff, 0);
+        assert_eq!(from_slot(left, 24, Justification::Left), left);
+
+        let right = to_slot(sample, 16, Justification::Right, Rounding::Truncate);
+        assert_eq!(right, (sample >> 16) as i16 as i32);
+        assert_eq!(from_slot(right, 16, Justification::Right), sample & !0xffff);
+
+        // Garbage around the sample is ignored.
+        assert_eq!(from_slot(0x1234_56ff, 24, Justification::Left), 0x1234_5600);
+        assert_eq!(from_slot(0x7f00_1234, 16, Justification::Right), 0x1234_0000);
+    }
+
+    #[test]
+    fn words() {
+        let sample = -0x1234_5678;
+        assert_eq!(to_words(0x1234_5678, WordOrder::LowFirst), [0x5678, 0x1234]);
+        assert_eq!(to_words(0x1234_5678, WordOrder::HighFirst), [0x1234, 0x5678]);
+
+        for order in [WordOrder::LowFirst, WordOrder::HighFirst] {
+            assert_eq!(from_words(to_words(sample, order), order), sample);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(to_le_bytes_24(0x1234_5678, Rounding::Truncate), [0x56, 0x34, 0x12]);
+        assert_eq!(from_le_bytes_24([0x56, 0x34, 0x12]), 0x1234_5600);
+        assert_eq!(from_le_bytes_24(to_le_bytes_24(-0x100, Rounding::Nearest)), -0x100);
+    }
+}
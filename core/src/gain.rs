@@ -0,0 +1,97 @@
+// Gain conversion helpers. `core` provides no transcendental functions, so an approximation is used.
+
+// Base-2 logarithm of ten, divided by 20 (conversion from decibel to powers of two).
+const LOG2_10_OVER_20: f32 = 0.166_096_4;
+
+/// Approximate `2^x`, with a relative error of less than 1e-5.
+pub fn exp2(x: f32) -> f32 {
+    let x = x.clamp(-126.0, 126.0);
+
+    // Split into integer and fractional part, such that the fraction is in the range [0, 1).
+    let truncated = x as i32;
+    let integer = if (truncated as f32) > x {
+        truncated - 1
+    } else {
+        truncated
+    };
+    let fraction = x - integer as f32;
+
+    // Polynomial approximation of 2^fraction.
+    let mantissa =
+        1.0 + fraction * (0.693_018_6 + fraction * (0.241_404_8 + fraction * (0.052_073_9 + fraction * 0.013_493_5)));
+
+    // Compose 2^integer via the float exponent.
+    let scale = f32::from_bits(((integer + 127) as u32) << 23);
+
+    mantissa * scale
+}
+
+/// Convert a gain in decibel to a linear factor.
+pub fn db_to_linear(db: f32) -> f32 {
+    exp2(db * LOG2_10_OVER_20)
+}
+
+/// Approximate `log2(x)` for positive `x`, with an absolute error of less than 1e-5.
+pub fn log2(x: f32) -> f32 {
+    // Split into exponent and mantissa, such that the mantissa is in the range [1, 2).
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mantissa = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+
+    // log2(m) = 2 / ln(2) * atanh(s), with s = (m - 1) / (m + 1) in the range [0, 1/3).
+    let s = (mantissa - 1.0) / (mantissa + 1.0);
+    let s2 = s * s;
+    let atanh = s * (1.0 + s2 * (1.0 / 3.0 + s2 * (1.0 / 5.0 + s2 * (1.0 / 7.0 + s2 * (1.0 / 9.0)))));
+
+    exponent as f32 + 2.0 * core::f32::consts::LOG2_E * atanh
+}
+
+/// Convert a positive linear factor to a gain in decibel.
+pub fn linear_to_db(linear: f32) -> f32 {
+    log2(linear) / LOG2_10_OVER_20
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual / expected - 1.0).abs() < 1e-5, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn powers_of_two() {
+        assert_close(exp2(0.0), 1.0);
+        assert_close(exp2(3.0), 8.0);
+        assert_close(exp2(-1.0), 0.5);
+        assert_close(exp2(0.5), core::f32::consts::SQRT_2);
+        assert_close(exp2(-2.5), 0.176_776_7);
+    }
+
+    #[test]
+    fn decibel() {
+        assert_close(db_to_linear(20.0), 10.0);
+        assert_close(db_to_linear(-40.0), 0.01);
+        assert_close(db_to_linear(-6.020_6), 0.5);
+    }
+
+    #[test]
+    fn logarithm() {
+        for (x, expected) in [
+            (1.0, 0.0),
+            (8.0, 3.0),
+            (0.5, -1.0),
+            (3.0, 1.584_962_5),
+            (1e-6, -19.931_568),
+        ] {
+            assert!(
+                (log2(x) - expected).abs() < 1e-5,
+                "log2({x}) = {} != {expected}",
+                log2(x)
+            );
+        }
+
+        assert!((linear_to_db(10.0) - 20.0).abs() < 1e-4);
+        assert!((linear_to_db(0.5) + 6.020_6).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,313 @@
+// Minimal JSON for configuration files: a flat object of numbers, booleans, strings, and arrays of numbers.
+//
+// Strings have no escapes. Nested objects are not supported, and neither are arrays of other values than numbers.
+use core::fmt::{self, Write};
+
+/// A field's value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Number(f32),
+    Bool(bool),
+    String(&'a str),
+    /// An array of numbers, which are read with `numbers`.
+    Array(&'a str),
+}
+
+impl<'a> Value<'a> {
+    /// The numbers of an array, which are `None` where they are invalid.
+    pub fn numbers(self) -> impl Iterator<Item = Option<f32>> + 'a {
+        let text = match self {
+            Value::Array(text) => text,
+            _ => "",
+        };
+
+        text.split(',')
+            .map(str::trim)
+            .filter(|number| !number.is_empty())
+            .map(|number| number.parse().ok())
+    }
+}
+
+/// The byte position of a syntax error.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SyntaxError(pub usize);
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), SyntaxError> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(SyntaxError(self.position));
+        }
+
+        self.position += 1;
+        Ok(())
+    }
+
+    // Take the text up to a terminating byte, which is skipped.
+    fn take_until(&mut self, end: u8) -> Result<&'a str, SyntaxError> {
+        let rest = &self.text[self.position..];
+        let length = rest
+            .bytes()
+            .position(|byte| byte == end)
+            .ok_or(SyntaxError(self.text.len()))?;
+
+        self.position += length + 1;
+        Ok(&rest[..length])
+    }
+
+    fn string(&mut self) -> Result<&'a str, SyntaxError> {
+        self.expect(b'"')?;
+        let start = self.position;
+        let string = self.take_until(b'"')?;
+
+        if string.contains('\\') {
+            return Err(SyntaxError(start));
+        }
+        Ok(string)
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, SyntaxError> {
+        self.skip_whitespace();
+        let start = self.position;
+        let rest = &self.text[start..];
+
+        match self.peek() {
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'[') => {
+                self.position += 1;
+                let array = self.take_until(b']')?;
+
+                let value = Value::Array(array);
+                if value.numbers().any(|number| number.is_none()) {
+                    return Err(SyntaxError(start));
+                }
+                Ok(value)
+            }
+            _ if rest.starts_with("true") => {
+                self.position += 4;
+                Ok(Value::Bool(true))
+            }
+            _ if rest.starts_with("false") => {
+                self.position += 5;
+                Ok(Value::Bool(false))
+            }
+            _ => {
+                let length = rest
+                    .bytes()
+                    .position(|byte| !matches!(byte, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'))
+                    .unwrap_or(rest.len());
+                let number = rest[..length].parse().map_err(|_| SyntaxError(start))?;
+
+                self.position += length;
+                Ok(Value::Number(number))
+            }
+        }
+    }
+}
+
+/// Parse an object, calling `field` with each key and value. Whitespace and zeros (e.g. the padding of a sector) may
+/// follow the object.
+pub fn parse<'a>(text: &'a str, mut field: impl FnMut(&'a str, Value<'a>)) -> Result<(), SyntaxError> {
+    let mut parser = Parser { text, position: 0 };
+    parser.expect(b'{')?;
+
+    parser.skip_whitespace();
+    if parser.peek() == Some(b'}') {
+        parser.position += 1;
+    } else {
+        loop {
+            let key = parser.string()?;
+            parser.expect(b':')?;
+            field(key, parser.value()?);
+
+            parser.skip_whitespace();
+            match parser.peek() {
+                Some(b',') => parser.position += 1,
+                Some(b'}') => {
+                    parser.position += 1;
+                    break;
+                }
+                _ => return Err(SyntaxError(parser.position)),
+            }
+        }
+    }
+
+    let padding = |c: char| c.is_whitespace() || c == '\0';
+    if text[parser.position..].trim_end_matches(padding).is_empty() {
+        Ok(())
+    } else {
+        Err(SyntaxError(parser.position))
+    }
+}
+
+/// The length of the object at the start of the bytes (after whitespace), once it is complete.
+pub fn object_length(bytes: &[u8]) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+
+    for (index, &byte) in bytes.iter().enumerate() {
+        match byte {
+            b'"' => in_string = !in_string,
+            _ if in_string => (),
+            b'{' => depth += 1,
+            b'}' if depth == 1 => return Some(index + 1),
+            b'}' => depth = depth.checked_sub(1)?,
+            b' ' | b'\t' | b'\r' | b'\n' => (),
+            _ if depth == 0 => return None,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+// A buffer that fails, once it is full.
+struct Cursor<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        let end = self.length + string.len();
+        self.buffer
+            .get_mut(self.length..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(string.as_bytes());
+
+        self.length = end;
+        Ok(())
+    }
+}
+
+/// Writes an object, one field per line.
+pub struct Writer<'a> {
+    cursor: Cursor<'a>,
+    fields: usize,
+    result: fmt::Result,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        let mut cursor = Cursor { buffer, length: 0 };
+        let result = cursor.write_str("{");
+
+        Self {
+            cursor,
+            fields: 0,
+            result,
+        }
+    }
+
+    fn field(&mut self, key: &str, value: fmt::Arguments) {
+        let separator = if self.fields == 0 { "" } else { "," };
+        self.fields += 1;
+
+        if self.result.is_ok() {
+            self.result = write!(self.cursor, "{separator}\n  \"{key}\": {value}");
+        }
+    }
+
+    pub fn number(&mut self, key: &str, value: f32) {
+        self.field(key, format_args!("{value}"));
+    }
+
+    pub fn boolean(&mut self, key: &str, value: bool) {
+        self.field(key, format_args!("{value}"));
+    }
+
+    pub fn string(&mut self, key: &str, value: &str) {
+        self.field(key, format_args!("\"{value}\""));
+    }
+
+    pub fn numbers(&mut self, key: &str, values: &[f32]) {
+        struct Numbers<'a>(&'a [f32]);
+
+        impl fmt::Display for Numbers<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("[")?;
+                for (index, value) in self.0.iter().enumerate() {
+                    let separator = if index == 0 { "" } else { ", " };
+                    write!(f, "{separator}{value}")?;
+                }
+                f.write_str("]")
+            }
+        }
+
+        self.field(key, format_args!("{}", Numbers(values)));
+    }
+
+    /// Close the object, and return its length, or `None`, if it does not fit the buffer.
+    pub fn finish(mut self) -> Option<usize> {
+        self.result.ok()?;
+        self.cursor.write_str("\n}\n").ok()?;
+
+        Some(self.cursor.length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = [0; 128];
+        let mut writer = Writer::new(&mut buffer);
+        writer.number("preset", 2.0);
+        writer.numbers("trim_db", &[-1.5, 0.0]);
+        writer.boolean("loudness", true);
+        writer.string("layout", "stereo");
+        let length = writer.finish().unwrap();
+
+        let text = core::str::from_utf8(&buffer[..length]).unwrap();
+        assert_eq!(object_length(&buffer), Some(length - 1));
+
+        let mut fields = 0;
+        parse(text, |key, value| {
+            match (fields, key) {
+                (0, "preset") => assert_eq!(value, Value::Number(2.0)),
+                (1, "trim_db") => assert!(value.numbers().eq([Some(-1.5), Some(0.0)])),
+                (2, "loudness") => assert_eq!(value, Value::Bool(true)),
+                (3, "layout") => assert_eq!(value, Value::String("stereo")),
+                _ => panic!("unexpected field {key}"),
+            }
+            fields += 1;
+        })
+        .unwrap();
+        assert_eq!(fields, 4);
+
+        assert_eq!(Writer::new(&mut [0; 8]).finish(), Some(4));
+        let mut small = [0; 8];
+        let mut writer = Writer::new(&mut small);
+        writer.number("preset", 2.0);
+        assert_eq!(writer.finish(), None);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let ignore = |_, _| ();
+
+        assert_eq!(parse("{}\0\0", ignore), Ok(()));
+        assert_eq!(parse("{\"a\": 1,}", ignore), Err(SyntaxError(8)));
+        assert_eq!(parse("{\"a\": [1, x]}", ignore), Err(SyntaxError(6)));
+        assert_eq!(parse("{\"a\": 1} 2", ignore), Err(SyntaxError(8)));
+        assert_eq!(object_length(b"  {\"a\": \"}\"} trailing"), Some(12));
+        assert_eq!(object_length(b"{\"a\": 1"), None);
+        assert_eq!(object_length(b"x{}"), None);
+    }
+}
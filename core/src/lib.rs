@@ -0,0 +1,25 @@
+// Hardware-independent logic of the firmware: DSP kernels and filters, sample format conversion, level metering,
+// feedback arithmetic, USB packet sizes, the settings' record format, the vendor protocol's framing, the framing of
+// serial links, MIDI control, the configuration drive's FAT volume and JSON files, the battery policy, the volume
+// curve, the routing of output zones, the auto-EQ's sweep analysis, the chimes' synthesis, and the rules of the USB
+// composite's layout.
+//
+// It builds for the host as well, such that it is covered by `cargo test` there.
+#![no_std]
+
+pub mod battery;
+pub mod dsp;
+pub mod fat;
+pub mod feedback;
+pub mod format;
+pub mod gain;
+pub mod json;
+pub mod meter;
+pub mod midi;
+pub mod packet;
+pub mod protocol;
+pub mod record;
+pub mod serial;
+pub mod usb_layout;
+pub mod volume;
+pub mod zone;
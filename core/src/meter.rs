@@ -0,0 +1,144 @@
+// Level metering with fast attack and slow decay, for a meter display on the host.
+//
+// Samples are accumulated over a measurement period (peak magnitude and sum of squares), which keeps the cost per
+// sample low. The meter's ballistics are applied once per period, in the decibel domain.
+use crate::gain::linear_to_db;
+
+/// Lowest reported level in dBFS, e.g. for silence.
+pub const FLOOR_DB: f32 = -120.0;
+
+// Full scale of 32 bit PCM samples, and of their squares after truncation to 16 bit.
+const FULL_SCALE: f32 = 2_147_483_648.0;
+const SQUARE_FULL_SCALE: f32 = (1u64 << 30) as f32;
+
+/// Peak magnitude and sum of squares of the samples of a measurement period.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Accumulator {
+    peak: u32,
+    square_sum: u64,
+    count: u32,
+}
+
+impl Accumulator {
+    pub const EMPTY: Self = Self {
+        peak: 0,
+        square_sum: 0,
+        count: 0,
+    };
+
+    /// Add a 32 bit PCM sample. Squares are summed with 16 bit resolution, which suffices for metering.
+    #[inline]
+    pub fn add(&mut self, sample: i32) {
+        let high = sample >> 16;
+
+        self.peak = self.peak.max(sample.unsigned_abs());
+        self.square_sum += (high * high) as u64;
+        self.count += 1;
+    }
+
+    /// Combine with the samples of another accumulator. Saturates, if the samples are not taken for a long time.
+    pub fn merge(&mut self, other: &Self) {
+        self.peak = self.peak.max(other.peak);
+        self.square_sum = self.square_sum.saturating_add(other.square_sum);
+        self.count = self.count.saturating_add(other.count);
+    }
+
+    pub fn peak_db(&self) -> f32 {
+        linear_to_db(self.peak as f32 / FULL_SCALE).max(FLOOR_DB)
+    }
+
+    pub fn rms_db(&self) -> f32 {
+        if self.count == 0 {
+            return FLOOR_DB;
+        }
+
+        let mean_square = self.square_sum as f32 / self.count as f32 / SQUARE_FULL_SCALE;
+        (0.5 * linear_to_db(mean_square)).max(FLOOR_DB)
+    }
+}
+
+/// A peak and RMS meter, which follows rising levels immediately, and decays at a constant rate in dB/s.
+pub struct LevelMeter {
+    peak_decay_db_per_s: f32,
+    rms_decay_db_per_s: f32,
+    peak_db: f32,
+    rms_db: f32,
+}
+
+impl LevelMeter {
+    pub const fn new(peak_decay_db_per_s: f32, rms_decay_db_per_s: f32) -> Self {
+        Self {
+            peak_decay_db_per_s,
+            rms_decay_db_per_s,
+            peak_db: FLOOR_DB,
+            rms_db: FLOOR_DB,
+        }
+    }
+
+    /// Update the meter with the samples of a measurement period of `period_ms`, and return the peak and RMS levels in
+    /// dBFS.
+    pub fn update(&mut self, accumulator: &Accumulator, period_ms: u32) -> (f32, f32) {
+        let period_s = period_ms as f32 * 1e-3;
+
+        self.peak_db = accumulator
+            .peak_db()
+            .max(self.peak_db - self.peak_decay_db_per_s * period_s)
+            .max(FLOOR_DB);
+        self.rms_db = accumulator
+            .rms_db()
+            .max(self.rms_db - self.rms_decay_db_per_s * period_s)
+            .max(FLOOR_DB);
+
+        (self.peak_db, self.rms_db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.05, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn square_wave_levels() {
+        let mut accumulator = Accumulator::EMPTY;
+        for index in 0..96 {
+            accumulator.add(if index % 2 == 0 { i32::MAX / 2 } else { -i32::MAX / 2 });
+        }
+
+        // A square wave's RMS level equals its peak level.
+        assert_close(accumulator.peak_db(), -6.02);
+        assert_close(accumulator.rms_db(), -6.02);
+    }
+
+    #[test]
+    fn silence_is_at_the_floor() {
+        let mut accumulator = Accumulator::EMPTY;
+        assert_eq!(accumulator.rms_db(), FLOOR_DB);
+
+        accumulator.add(0);
+        assert_eq!(accumulator.peak_db(), FLOOR_DB);
+        assert_eq!(accumulator.rms_db(), FLOOR_DB);
+    }
+
+    #[test]
+    fn fast_attack_slow_decay() {
+        let mut loud = Accumulator::EMPTY;
+        loud.add(i32::MAX);
+
+        let mut meter = LevelMeter::new(20.0, 10.0);
+        let (peak_db, rms_db) = meter.update(&loud, 50);
+        assert_close(peak_db, 0.0);
+        assert_close(rms_db, 0.0);
+
+        // After one second of silence, the levels decayed at their rates.
+        let mut levels = (0.0, 0.0);
+        for _ in 0..20 {
+            levels = meter.update(&Accumulator::EMPTY, 50);
+        }
+        assert_close(levels.0, -20.0);
+        assert_close(levels.1, -10.0);
+    }
+}
@@ -0,0 +1,112 @@
+// USB-MIDI event packets, and the mapping of control change messages to DSP parameters.
+//
+// Every event is a four byte packet: the cable number and code index, followed by the MIDI message. Only control
+// changes are used, on any channel.
+use crate::gain::exp2;
+
+/// Size of a USB-MIDI event packet.
+pub const PACKET_SIZE: usize = 4;
+
+/// Controllers, which are mapped to DSP parameters.
+pub mod controller {
+    /// Master volume (channel volume).
+    pub const VOLUME: u8 = 7;
+    /// Gain offset of the first equalizer band. The following bands use the following controllers.
+    pub const EQ_GAIN: u8 = 20;
+    /// Crossover frequency towards a subwoofer.
+    pub const CROSSOVER: u8 = 28;
+    /// Resets all adjustments (reset all controllers).
+    pub const RESET_ALL: u8 = 121;
+}
+
+/// Master volume step per controller value.
+pub const VOLUME_STEP_DB: f32 = 0.5;
+
+/// Equalizer gain offset step per controller value, around the center value.
+pub const EQ_GAIN_STEP_DB: f32 = 0.25;
+
+// Crossover frequency range, which spans two octaves.
+const CROSSOVER_MIN_HZ: f32 = 40.0;
+const CROSSOVER_OCTAVES: f32 = 2.0;
+
+// Code index number and status of control changes.
+const CONTROL_CHANGE_CIN: u8 = 0x0b;
+const CONTROL_CHANGE_STATUS: u8 = 0xb0;
+
+const CENTER_VALUE: u8 = 64;
+const MAX_VALUE: u8 = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControlChange {
+    pub channel: u8,
+    pub controller: u8,
+    pub value: u8,
+}
+
+/// Parse a control change from an event packet. Other events are ignored.
+pub fn parse_control_change(packet: &[u8; PACKET_SIZE]) -> Option<ControlChange> {
+    let &[header, status, controller, value] = packet;
+    if header & 0x0f != CONTROL_CHANGE_CIN || status & 0xf0 != CONTROL_CHANGE_STATUS {
+        return None;
+    }
+
+    (controller <= MAX_VALUE && value <= MAX_VALUE).then_some(ControlChange {
+        channel: status & 0x0f,
+        controller,
+        value,
+    })
+}
+
+/// The master volume of a controller value in dB, from -63 dB to 0 dB, or `None` (muted) for zero.
+pub fn volume_db(value: u8) -> Option<f32> {
+    (value > 0).then(|| (value.min(MAX_VALUE) as f32 - MAX_VALUE as f32) * VOLUME_STEP_DB)
+}
+
+/// The equalizer gain offset of a controller value in dB, zero at the center value.
+pub fn eq_gain_offset_db(value: u8) -> f32 {
+    (value.min(MAX_VALUE) as f32 - CENTER_VALUE as f32) * EQ_GAIN_STEP_DB
+}
+
+/// The crossover frequency of a controller value, logarithmically from 40 Hz to 160 Hz, or `None` (the preset's own)
+/// for zero.
+pub fn crossover_hz(value: u8) -> Option<f32> {
+    (value > 0).then(|| {
+        let position = (value.min(MAX_VALUE) - 1) as f32 / (MAX_VALUE - 1) as f32;
+        CROSSOVER_MIN_HZ * exp2(position * CROSSOVER_OCTAVES)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_change() {
+        assert_eq!(
+            parse_control_change(&[0x0b, 0xb3, 7, 100]),
+            Some(ControlChange {
+                channel: 3,
+                controller: 7,
+                value: 100,
+            })
+        );
+
+        // Note on, and a data byte out of range.
+        assert_eq!(parse_control_change(&[0x09, 0x90, 60, 100]), None);
+        assert_eq!(parse_control_change(&[0x0b, 0xb0, 7, 0x80]), None);
+    }
+
+    #[test]
+    fn mapping() {
+        assert_eq!(volume_db(0), None);
+        assert_eq!(volume_db(127), Some(0.0));
+        assert_eq!(volume_db(1), Some(-63.0));
+
+        assert_eq!(eq_gain_offset_db(64), 0.0);
+        assert_eq!(eq_gain_offset_db(0), -16.0);
+
+        assert_eq!(crossover_hz(0), None);
+        assert!((crossover_hz(1).unwrap() - 40.0).abs() < 0.01);
+        assert!((crossover_hz(127).unwrap() - 160.0).abs() < 0.01);
+    }
+}
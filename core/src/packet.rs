@@ -0,0 +1,121 @@
+// Sizes of USB audio packets and buffers, derived from the stream format.
+//
+// An isochronous packet carries the samples of one (micro)frame. Its maximum size leaves room for the host's rate
+// adaption according to feedback, within the bus speed's isochronous packet limit.
+
+/// Largest isochronous packet at full speed.
+pub const FULL_SPEED_MAX_ISO_PACKET_SIZE: usize = 1023;
+
+/// Largest isochronous packet at high speed, with a single transaction per microframe.
+pub const HIGH_SPEED_MAX_ISO_PACKET_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StreamFormat {
+    pub sample_rate_hz: u32,
+    /// Size of a sample in byte.
+    pub sample_size: usize,
+    pub channel_count: usize,
+    /// USB (micro)frames per millisecond: 1 at full speed, 8 at high speed.
+    pub frames_per_ms: usize,
+}
+
+impl StreamFormat {
+    /// Size of an audio frame (one sample per channel) in byte.
+    pub const fn frame_size(&self) -> usize {
+        self.sample_size * self.channel_count
+    }
+
+    /// Size of the samples per USB (micro)frame at the nominal rate, rounded up to whole audio frames.
+    pub const fn nominal_packet_size(&self) -> usize {
+        (self.sample_rate_hz as usize).div_ceil(1000 * self.frames_per_ms) * self.frame_size()
+    }
+
+    /// Whether the stream fits isochronous packets of `limit` byte, with one audio frame of margin for feedback.
+    pub const fn fits(&self, limit: usize) -> bool {
+        self.nominal_packet_size() + self.frame_size() <= limit
+    }
+
+    /// The maximum packet size: `factor` times the nominal size, but at most the whole audio frames that fit `limit`.
+    pub const fn max_packet_size(&self, factor: usize, limit: usize) -> usize {
+        let size = factor * self.nominal_packet_size();
+        let limit = limit - limit % self.frame_size();
+
+        if size < limit {
+            size
+        } else {
+            limit
+        }
+    }
+
+    /// The maximum number of audio frames per packet.
+    pub const fn max_frames_per_packet(&self, factor: usize, limit: usize) -> usize {
+        self.max_packet_size(factor, limit) / self.frame_size()
+    }
+}
+
+/// Size of the USB driver's OUT endpoint buffer, which holds a packet of each of the given endpoints.
+pub const fn ep_out_buffer_size(max_packet_sizes: &[usize]) -> usize {
+    let mut size = 0;
+    let mut index = 0;
+
+    while index < max_packet_sizes.len() {
+        size += max_packet_sizes[index];
+        index += 1;
+    }
+
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEREO_48K: StreamFormat = StreamFormat {
+        sample_rate_hz: 48_000,
+        sample_size: 4,
+        channel_count: 2,
+        frames_per_ms: 1,
+    };
+
+    #[test]
+    fn nominal_packet_size() {
+        assert_eq!(STEREO_48K.nominal_packet_size(), 384);
+
+        // Fractional frames per packet are rounded up.
+        let format = StreamFormat {
+            sample_rate_hz: 44_100,
+            ..STEREO_48K
+        };
+        assert_eq!(format.nominal_packet_size(), 45 * 8);
+    }
+
+    #[test]
+    fn max_packet_size_is_limited_to_whole_frames() {
+        assert_eq!(STEREO_48K.max_packet_size(2, FULL_SPEED_MAX_ISO_PACKET_SIZE), 768);
+
+        let quad = StreamFormat {
+            channel_count: 4,
+            ..STEREO_48K
+        };
+        assert_eq!(quad.max_packet_size(2, FULL_SPEED_MAX_ISO_PACKET_SIZE), 1008);
+        assert_eq!(quad.max_frames_per_packet(2, FULL_SPEED_MAX_ISO_PACKET_SIZE), 63);
+    }
+
+    #[test]
+    fn bandwidth() {
+        assert!(STEREO_48K.fits(FULL_SPEED_MAX_ISO_PACKET_SIZE));
+
+        // Eight channels at 48 kHz exceed full speed, but fit high speed.
+        let format = StreamFormat {
+            channel_count: 8,
+            ..STEREO_48K
+        };
+        assert!(!format.fits(FULL_SPEED_MAX_ISO_PACKET_SIZE));
+
+        let format = StreamFormat {
+            frames_per_ms: 8,
+            ..format
+        };
+        assert!(format.fits(HIGH_SPEED_MAX_ISO_PACKET_SIZE));
+    }
+}
@@ -0,0 +1,219 @@
+// Framing of the vendor interface's requests, shared by the firmware and host tools.
+//
+// Data is little-endian. Control transfers carry at most the control buffer's size, so that uploads are framed into
+// chunks, each preceded by its 32 bit offset.
+//
+// The same requests may be sent over a serial link (see `serial`), e.g. by a Bluetooth or home-automation front-end.
+// The message kind is the request code, with `SERIAL_READ` set for requests that read data, and the payload is the
+// request's value, followed by its data. The response echoes the kind and sequence number, and its payload is a status
+// byte, followed by the read data.
+use crate::dsp::Filter;
+
+/// Size of an equalizer band: type (`u8`), frequency, Q, and gain in dB (`f32`).
+pub const EQ_BAND_SIZE: usize = 13;
+
+/// Size of the offset that precedes every upload chunk.
+pub const OFFSET_SIZE: usize = 4;
+
+/// Flag of a serial request's message kind, for requests that read data.
+pub const SERIAL_READ: u8 = 0x80;
+
+/// Size of the value that precedes a serial request's data.
+pub const SERIAL_VALUE_SIZE: usize = 2;
+
+/// Status byte of serial responses, for accepted and rejected requests.
+pub const SERIAL_ACCEPTED: u8 = 0;
+pub const SERIAL_REJECTED: u8 = 1;
+
+// Limit of equalizer bands' Q.
+const MAX_Q: f32 = 20.0;
+
+/// Limits of equalizer bands' gains. Gains must not exceed the limit of the filter design.
+pub const MIN_GAIN_DB: f32 = -24.0;
+pub const MAX_GAIN_DB: f32 = 6.0;
+
+/// Encode an equalizer band.
+pub fn encode_eq_band(filter: &Filter) -> [u8; EQ_BAND_SIZE] {
+    let (filter_type, frequency_hz, q, gain_db) = match *filter {
+        Filter::Peaking {
+            frequency_hz,
+            q,
+            gain_db,
+        } => (0, frequency_hz, q, gain_db),
+        Filter::LowShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        } => (1, frequency_hz, q, gain_db),
+        Filter::HighShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        } => (2, frequency_hz, q, gain_db),
+        Filter::HighPass { frequency_hz, q } => (3, frequency_hz, q, 0.0),
+        Filter::LowPass { frequency_hz, q } => (4, frequency_hz, q, 0.0),
+    };
+
+    let mut data = [0; EQ_BAND_SIZE];
+    data[0] = filter_type;
+    data[1..5].copy_from_slice(&frequency_hz.to_le_bytes());
+    data[5..9].copy_from_slice(&q.to_le_bytes());
+    data[9..].copy_from_slice(&gain_db.to_le_bytes());
+    data
+}
+
+/// Parse an equalizer band, whose frequency must be below Nyquist, and whose Q and gain must be within limits.
+pub fn parse_eq_band(data: &[u8; EQ_BAND_SIZE], sample_rate_hz: u32) -> Option<Filter> {
+    let value = |index: usize| f32::from_le_bytes(data[1 + 4 * index..5 + 4 * index].try_into().unwrap());
+    let (frequency_hz, q, gain_db) = (value(0), value(1), value(2));
+
+    let valid = frequency_hz > 0.0 && frequency_hz < sample_rate_hz as f32 / 2.0 && q > 0.0 && q <= MAX_Q;
+    if !valid || !(MIN_GAIN_DB..=MAX_GAIN_DB).contains(&gain_db) {
+        return None;
+    }
+
+    match data[0] {
+        0 => Some(Filter::Peaking {
+            frequency_hz,
+            q,
+            gain_db,
+        }),
+        1 => Some(Filter::LowShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        }),
+        2 => Some(Filter::HighShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        }),
+        3 => Some(Filter::HighPass { frequency_hz, q }),
+        4 => Some(Filter::LowPass { frequency_hz, q }),
+        _ => None,
+    }
+}
+
+/// Frame an upload chunk as its offset, followed by the data. Returns the frame's length, or `None`, if the frame
+/// does not fit the buffer.
+pub fn frame_chunk(offset: u32, data: &[u8], buffer: &mut [u8]) -> Option<usize> {
+    let length = OFFSET_SIZE + data.len();
+
+    let frame = buffer.get_mut(..length)?;
+    frame[..OFFSET_SIZE].copy_from_slice(&offset.to_le_bytes());
+    frame[OFFSET_SIZE..].copy_from_slice(data);
+    Some(length)
+}
+
+/// Split an upload chunk into its offset and data, which must not be empty.
+pub fn parse_chunk(frame: &[u8]) -> Option<(u32, &[u8])> {
+    if frame.len() <= OFFSET_SIZE {
+        return None;
+    }
+
+    let (offset, data) = frame.split_at(OFFSET_SIZE);
+    Some((u32::from_le_bytes(offset.try_into().unwrap()), data))
+}
+
+/// A vendor request, received over a serial link.
+#[derive(Debug, PartialEq)]
+pub struct SerialRequest<'a> {
+    /// The request code.
+    pub request: u8,
+    /// Whether the request reads data.
+    pub read: bool,
+    pub value: u16,
+    /// The data of requests that do not read data.
+    pub data: &'a [u8],
+}
+
+/// Frame a serial request's payload as its value, followed by the data. Returns the payload's length, or `None`, if it
+/// does not fit the buffer.
+pub fn frame_serial_request(value: u16, data: &[u8], buffer: &mut [u8]) -> Option<usize> {
+    let length = SERIAL_VALUE_SIZE + data.len();
+
+    let payload = buffer.get_mut(..length)?;
+    payload[..SERIAL_VALUE_SIZE].copy_from_slice(&value.to_le_bytes());
+    payload[SERIAL_VALUE_SIZE..].copy_from_slice(data);
+    Some(length)
+}
+
+/// Parse a serial request from a message's kind and payload. Requests that read data must not carry any.
+pub fn parse_serial_request(kind: u8, payload: &[u8]) -> Option<SerialRequest<'_>> {
+    let (value, data) = payload.split_first_chunk::<SERIAL_VALUE_SIZE>()?;
+    let read = kind & SERIAL_READ != 0;
+    if read && !data.is_empty() {
+        return None;
+    }
+
+    Some(SerialRequest {
+        request: kind & !SERIAL_READ,
+        read,
+        value: u16::from_le_bytes(*value),
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eq_band_round_trip() {
+        let filter = Filter::Peaking {
+            frequency_hz: 1000.0,
+            q: 0.7,
+            gain_db: -3.0,
+        };
+
+        assert!(parse_eq_band(&encode_eq_band(&filter), 48_000) == Some(filter));
+    }
+
+    #[test]
+    fn eq_band_limits() {
+        let above_nyquist = Filter::LowPass {
+            frequency_hz: 30_000.0,
+            q: 0.7,
+        };
+        let boost = Filter::HighShelf {
+            frequency_hz: 8000.0,
+            q: 0.7,
+            gain_db: 12.0,
+        };
+
+        assert!(parse_eq_band(&encode_eq_band(&above_nyquist), 48_000).is_none());
+        assert!(parse_eq_band(&encode_eq_band(&boost), 48_000).is_none());
+
+        let mut unknown_type = encode_eq_band(&boost);
+        unknown_type[0] = 5;
+        assert!(parse_eq_band(&unknown_type, 48_000).is_none());
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let mut buffer = [0; 64];
+        let length = frame_chunk(0x1234, &[1, 2, 3], &mut buffer).unwrap();
+
+        assert_eq!(parse_chunk(&buffer[..length]), Some((0x1234, &[1u8, 2, 3][..])));
+        assert_eq!(frame_chunk(0, &[0; 61], &mut buffer), None);
+        assert_eq!(parse_chunk(&buffer[..OFFSET_SIZE]), None);
+    }
+
+    #[test]
+    fn serial_request_round_trip() {
+        let mut buffer = [0; 16];
+        let length = frame_serial_request(3, &[0xfe], &mut buffer).unwrap();
+
+        assert_eq!(
+            parse_serial_request(0x02, &buffer[..length]),
+            Some(SerialRequest {
+                request: 0x02,
+                read: false,
+                value: 3,
+                data: &[0xfe],
+            })
+        );
+        assert_eq!(parse_serial_request(0x01 | SERIAL_READ, &buffer[..length]), None);
+        assert_eq!(parse_serial_request(0x01, &buffer[..1]), None);
+    }
+}
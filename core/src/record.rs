@@ -0,0 +1,124 @@
+// Record format of the key-value store, which persists the device settings.
+//
+// A record holds a sequence number, key, value length, value, and checksum. Erased flash (all ones) marks free records,
+// and records with an invalid checksum (e.g. from an interrupted write) are skipped.
+
+/// Size of a record in flash.
+pub const RECORD_SIZE: usize = 16;
+
+/// Maximum size of a value.
+pub const VALUE_SIZE: usize = 6;
+
+/// Number of keys.
+pub const KEY_COUNT: usize = 24;
+
+// Sequence number, key, length, value, and checksum must fit the record.
+const _: () = assert!(4 + 1 + 1 + VALUE_SIZE + 4 <= RECORD_SIZE);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Record {
+    pub sequence: u32,
+    pub key: u8,
+    length: usize,
+    value: [u8; VALUE_SIZE],
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0x4b56_5354, |sum, &byte| sum.rotate_left(5) ^ byte as u32)
+}
+
+/// Whether a record is erased, and thus free.
+pub fn is_erased(bytes: &[u8; RECORD_SIZE]) -> bool {
+    bytes.iter().all(|&byte| byte == 0xff)
+}
+
+impl Record {
+    /// A record of a key and value, or `None`, if the value is too long.
+    pub fn new(sequence: u32, key: u8, value: &[u8]) -> Option<Self> {
+        let mut record = Self {
+            sequence,
+            key,
+            length: value.len(),
+            value: [0; VALUE_SIZE],
+        };
+
+        record.value.get_mut(..value.len())?.copy_from_slice(value);
+        Some(record)
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value[..self.length]
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+
+        bytes[..4].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[4] = self.key;
+        bytes[5] = self.length as u8;
+        bytes[6..6 + self.length].copy_from_slice(self.value());
+
+        let checksum = checksum(&bytes[..6 + VALUE_SIZE]);
+        bytes[6 + VALUE_SIZE..10 + VALUE_SIZE].copy_from_slice(&checksum.to_le_bytes());
+
+        bytes
+    }
+
+    /// Decode a record, if it is valid.
+    pub fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let checksum_bytes = bytes[6 + VALUE_SIZE..10 + VALUE_SIZE].try_into().unwrap();
+        let (key, length) = (bytes[4], bytes[5] as usize);
+
+        if checksum(&bytes[..6 + VALUE_SIZE]) != u32::from_le_bytes(checksum_bytes)
+            || key as usize >= KEY_COUNT
+            || length > VALUE_SIZE
+        {
+            return None;
+        }
+
+        Self::new(
+            u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            key,
+            &bytes[6..6 + length],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let record = Record::new(42, 3, &[1, 2, 3]).unwrap();
+        let decoded = Record::decode(&record.encode()).unwrap();
+
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.value(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let mut bytes = Record::new(1, 0, &[0xaa]).unwrap().encode();
+        bytes[6] ^= 1;
+
+        assert_eq!(Record::decode(&bytes), None);
+        assert_eq!(Record::decode(&[0xff; RECORD_SIZE]), None);
+        assert!(is_erased(&[0xff; RECORD_SIZE]));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let bytes = Record::new(1, KEY_COUNT as u8 - 1, &[]).unwrap().encode();
+        assert!(Record::decode(&bytes).is_some());
+
+        let bytes = Record::new(1, KEY_COUNT as u8, &[]).unwrap().encode();
+        assert_eq!(Record::decode(&bytes), None);
+    }
+
+    #[test]
+    fn rejects_long_values() {
+        assert_eq!(Record::new(0, 0, &[0; VALUE_SIZE + 1]), None);
+    }
+}
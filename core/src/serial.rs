@@ -0,0 +1,231 @@
+// Framing of messages on serial links, e.g. the stereo link between two units.
+//
+// A frame starts with a sync word, followed by the message kind, a sequence number, the payload's length (`u16`), the
+// payload, and a CRC-16 (CCITT-FALSE) over everything after the sync word. Data is little-endian. The decoder takes one
+// byte at a time, and resynchronizes on the next sync word after corrupt or truncated frames.
+
+/// Start of every frame.
+pub const SYNC: [u8; 2] = [0xb1, 0x05];
+
+/// Size of the sync word, kind, sequence number, and length.
+pub const HEADER_SIZE: usize = 6;
+
+pub const CRC_SIZE: usize = 2;
+
+/// Size of a frame with a payload of `payload_size` byte.
+pub const fn frame_size(payload_size: usize) -> usize {
+    HEADER_SIZE + payload_size + CRC_SIZE
+}
+
+/// CRC-16 with the CCITT polynomial, an initial value of 0xffff, and no reflection.
+pub fn crc16(data: &[u8]) -> u16 {
+    update_crc16(0xffff, data)
+}
+
+// Continue a CRC-16 over more data.
+fn update_crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Encode a frame into a buffer. Returns the frame's length, or `None`, if the frame does not fit the buffer.
+pub fn encode(kind: u8, sequence: u8, payload: &[u8], buffer: &mut [u8]) -> Option<usize> {
+    let length = u16::try_from(payload.len()).ok()?;
+    let frame = buffer.get_mut(..frame_size(payload.len()))?;
+
+    frame[..2].copy_from_slice(&SYNC);
+    frame[2] = kind;
+    frame[3] = sequence;
+    frame[4..HEADER_SIZE].copy_from_slice(&length.to_le_bytes());
+    frame[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+
+    let crc = crc16(&frame[SYNC.len()..HEADER_SIZE + payload.len()]);
+    frame[HEADER_SIZE + payload.len()..].copy_from_slice(&crc.to_le_bytes());
+    Some(frame.len())
+}
+
+/// A received frame.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frame<'a> {
+    pub kind: u8,
+    pub sequence: u8,
+    pub payload: &'a [u8],
+}
+
+/// Reasons for discarding a frame.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameError {
+    /// The payload does not fit the decoder's buffer.
+    TooLong,
+    Crc,
+}
+
+/// A decoder for frames with payloads of up to `N` byte.
+pub struct Decoder<const N: usize> {
+    buffer: [u8; N],
+    header: [u8; HEADER_SIZE],
+    crc: [u8; CRC_SIZE],
+    // Bytes received of the current frame, including the sync word.
+    position: usize,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            header: [0; HEADER_SIZE],
+            crc: [0; CRC_SIZE],
+            position: 0,
+        }
+    }
+
+    /// Discard a partially received frame.
+    pub fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    fn payload_size(&self) -> usize {
+        u16::from_le_bytes([self.header[4], self.header[5]]) as usize
+    }
+
+    /// Add a received byte. Returns a frame, once it is complete, or the reason for discarding it.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame<'_>, FrameError>> {
+        if self.position < SYNC.len() {
+            // A mismatch may still be the start of the next sync word.
+            self.position = if byte == SYNC[self.position] {
+                self.position + 1
+            } else {
+                (byte == SYNC[0]) as usize
+            };
+            return None;
+        }
+
+        if self.position < HEADER_SIZE {
+            self.header[self.position] = byte;
+            self.position += 1;
+
+            if self.position == HEADER_SIZE && self.payload_size() > N {
+                self.position = 0;
+                return Some(Err(FrameError::TooLong));
+            }
+            return None;
+        }
+
+        let payload_size = self.payload_size();
+        let offset = self.position - HEADER_SIZE;
+        if offset < payload_size {
+            self.buffer[offset] = byte;
+        } else {
+            self.crc[offset - payload_size] = byte;
+        }
+        self.position += 1;
+
+        if self.position < frame_size(payload_size) {
+            return None;
+        }
+        self.position = 0;
+
+        let crc = update_crc16(crc16(&self.header[SYNC.len()..]), &self.buffer[..payload_size]);
+        if crc != u16::from_le_bytes(self.crc) {
+            return Some(Err(FrameError::Crc));
+        }
+
+        Some(Ok(Frame {
+            kind: self.header[2],
+            sequence: self.header[3],
+            payload: &self.buffer[..payload_size],
+        }))
+    }
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decode a byte stream, and collect the kinds of complete frames and the number of discarded frames.
+    fn decode<const N: usize>(decoder: &mut Decoder<N>, bytes: &[u8]) -> ([u8; 4], usize, usize) {
+        let (mut kinds, mut count, mut errors) = ([0; 4], 0, 0);
+
+        for &byte in bytes {
+            match decoder.push(byte) {
+                Some(Ok(frame)) => {
+                    kinds[count] = frame.kind;
+                    count += 1;
+                }
+                Some(Err(_)) => errors += 1,
+                None => (),
+            }
+        }
+
+        (kinds, count, errors)
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = [0; 32];
+        let length = encode(3, 7, &[1, 2, 3], &mut buffer).unwrap();
+        assert_eq!(length, frame_size(3));
+
+        let mut decoder = Decoder::<8>::new();
+        let mut frame = None;
+        for &byte in &buffer[..length] {
+            if let Some(result) = decoder.push(byte) {
+                frame = Some(result.map(|frame| (frame.kind, frame.sequence, frame.payload.len())));
+            }
+        }
+        assert_eq!(frame, Some(Ok((3, 7, 3))));
+
+        assert!(encode(3, 7, &[0; 32], &mut buffer).is_none());
+    }
+
+    #[test]
+    fn resynchronizes_after_corruption() {
+        let mut stream = [0; 64];
+        let mut length = 0;
+
+        // Noise, which ends with the first byte of the sync word.
+        stream[..3].copy_from_slice(&[0x00, 0x42, SYNC[0]]);
+        length += 3;
+        length += encode(1, 0, &[5, 6], &mut stream[length..]).unwrap();
+
+        // A corrupt frame, followed by a good one.
+        let corrupt = length;
+        length += encode(2, 1, &[7, 8], &mut stream[length..]).unwrap();
+        stream[corrupt + HEADER_SIZE] ^= 0xff;
+        length += encode(4, 2, &[], &mut stream[length..]).unwrap();
+
+        let (kinds, count, errors) = decode(&mut Decoder::<8>::new(), &stream[..length]);
+        assert_eq!((&kinds[..count], errors), (&[1, 4][..], 1));
+    }
+
+    #[test]
+    fn rejects_long_payloads() {
+        let mut stream = [0; 64];
+        let mut length = encode(1, 0, &[0; 16], &mut stream).unwrap();
+        length += encode(2, 1, &[0; 4], &mut stream[length..]).unwrap();
+
+        let (kinds, count, errors) = decode(&mut Decoder::<8>::new(), &stream[..length]);
+        assert_eq!((&kinds[..count], errors), (&[2][..], 1));
+    }
+}
@@ -0,0 +1,419 @@
+// Layout of the composite USB device: its functions in the order of registration, with their interfaces and endpoints.
+//
+// The USB stack numbers interfaces and allocates endpoints in the order of registration, and groups each function's
+// interfaces with an interface association descriptor (IAD). Hosts are strict about the result, e.g. Windows' composite
+// driver fails to enumerate a device whose IAD announces a class that differs from its interfaces'. The firmware
+// validates its layout at build time, so that adding a function cannot break enumeration unnoticed.
+use crate::packet::{FULL_SPEED_MAX_ISO_PACKET_SIZE, HIGH_SPEED_MAX_ISO_PACKET_SIZE};
+
+/// Class codes of the device's interfaces.
+pub mod class {
+    pub const AUDIO: u8 = 0x01;
+    pub const HID: u8 = 0x03;
+    pub const VENDOR: u8 = 0xff;
+}
+
+/// Subclass codes of audio interfaces.
+pub mod audio {
+    pub const CONTROL: u8 = 0x01;
+    pub const STREAMING: u8 = 0x02;
+    pub const MIDI_STREAMING: u8 = 0x03;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferType {
+    Bulk,
+    Interrupt,
+    Isochronous,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Endpoint {
+    pub direction: Direction,
+    pub transfer_type: TransferType,
+    pub max_packet_size: usize,
+}
+
+impl Endpoint {
+    pub const fn bulk_out(max_packet_size: usize) -> Self {
+        Self::new(Direction::Out, TransferType::Bulk, max_packet_size)
+    }
+
+    pub const fn interrupt_in(max_packet_size: usize) -> Self {
+        Self::new(Direction::In, TransferType::Interrupt, max_packet_size)
+    }
+
+    pub const fn isochronous_in(max_packet_size: usize) -> Self {
+        Self::new(Direction::In, TransferType::Isochronous, max_packet_size)
+    }
+
+    pub const fn isochronous_out(max_packet_size: usize) -> Self {
+        Self::new(Direction::Out, TransferType::Isochronous, max_packet_size)
+    }
+
+    const fn new(direction: Direction, transfer_type: TransferType, max_packet_size: usize) -> Self {
+        Self {
+            direction,
+            transfer_type,
+            max_packet_size,
+        }
+    }
+
+    // Whether the maximum packet size is within the transfer type's limits at the bus speed.
+    const fn is_valid(&self, high_speed: bool) -> bool {
+        let size = self.max_packet_size;
+
+        size > 0
+            && match (self.transfer_type, high_speed) {
+                (TransferType::Bulk, false) => matches!(size, 8 | 16 | 32 | 64),
+                (TransferType::Bulk, true) => size == 512,
+                (TransferType::Interrupt, false) => size <= 64,
+                (TransferType::Interrupt, true) => size <= 1024,
+                (TransferType::Isochronous, false) => size <= FULL_SPEED_MAX_ISO_PACKET_SIZE,
+                (TransferType::Isochronous, true) => size <= HIGH_SPEED_MAX_ISO_PACKET_SIZE,
+            }
+    }
+}
+
+/// An interface with the endpoints of all its alternate settings, which are allocated separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interface {
+    pub class: u8,
+    pub sub_class: u8,
+    pub endpoints: &'static [Endpoint],
+}
+
+/// A function, whose interfaces are grouped by an IAD with the function's class and subclass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Function {
+    pub class: u8,
+    pub sub_class: u8,
+    pub interfaces: &'static [Interface],
+}
+
+/// Limits of the USB peripheral.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bus {
+    pub high_speed: bool,
+    /// Endpoints per direction, including the control endpoint.
+    pub endpoint_count: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayoutError {
+    NoFunctions,
+    /// A function (by its index) has no interfaces.
+    EmptyFunction(usize),
+    /// A function's class or subclass differs from its first interface's, or one of its interfaces has another class.
+    ClassMismatch(usize),
+    /// An audio function does not start with its audio control interface, or has several.
+    AudioControl(usize),
+    /// More endpoints of a direction than the peripheral has.
+    TooManyEndpoints(Direction),
+    /// An endpoint of a function exceeds its transfer type's packet size limits at the bus speed.
+    PacketSize(usize),
+}
+
+/// The functions of a configuration, in the order of registration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub functions: &'static [Function],
+}
+
+impl Layout {
+    pub const fn interface_count(&self) -> usize {
+        self.first_interface(self.functions.len()) as usize
+    }
+
+    /// The number of a function's first interface, as assigned by the USB stack.
+    pub const fn first_interface(&self, function: usize) -> u8 {
+        let mut number = 0;
+        let mut index = 0;
+
+        while index < function {
+            number += self.functions[index].interfaces.len();
+            index += 1;
+        }
+
+        number as u8
+    }
+
+    /// The number of endpoints of a direction, excluding the control endpoint.
+    pub const fn endpoint_count(&self, direction: Direction) -> usize {
+        let mut count = 0;
+        let mut function = 0;
+
+        while function < self.functions.len() {
+            let interfaces = self.functions[function].interfaces;
+            let mut interface = 0;
+
+            while interface < interfaces.len() {
+                let endpoints = interfaces[interface].endpoints;
+                let mut endpoint = 0;
+
+                while endpoint < endpoints.len() {
+                    if matches!(
+                        (endpoints[endpoint].direction, direction),
+                        (Direction::In, Direction::In) | (Direction::Out, Direction::Out)
+                    ) {
+                        count += 1;
+                    }
+                    endpoint += 1;
+                }
+                interface += 1;
+            }
+            function += 1;
+        }
+
+        count
+    }
+
+    pub const fn validate(&self, bus: &Bus) -> Result<(), LayoutError> {
+        if self.functions.is_empty() {
+            return Err(LayoutError::NoFunctions);
+        }
+
+        let mut index = 0;
+        while index < self.functions.len() {
+            if let Err(e) = validate_function(index, &self.functions[index], bus) {
+                return Err(e);
+            }
+            index += 1;
+        }
+
+        if self.endpoint_count(Direction::In) >= bus.endpoint_count {
+            return Err(LayoutError::TooManyEndpoints(Direction::In));
+        }
+        if self.endpoint_count(Direction::Out) >= bus.endpoint_count {
+            return Err(LayoutError::TooManyEndpoints(Direction::Out));
+        }
+
+        Ok(())
+    }
+
+    /// Validate the layout, and panic with the violated rule otherwise, for checks at build time.
+    pub const fn assert_valid(&self, bus: &Bus) {
+        match self.validate(bus) {
+            Ok(()) => {}
+            Err(LayoutError::NoFunctions) => panic!("USB layout: no functions"),
+            Err(LayoutError::EmptyFunction(_)) => panic!("USB layout: a function has no interfaces"),
+            Err(LayoutError::ClassMismatch(_)) => panic!("USB layout: a function's IAD does not match its interfaces"),
+            Err(LayoutError::AudioControl(_)) => {
+                panic!("USB layout: an audio function must start with its only audio control interface")
+            }
+            Err(LayoutError::TooManyEndpoints(Direction::In)) => panic!("USB layout: too many IN endpoints"),
+            Err(LayoutError::TooManyEndpoints(Direction::Out)) => panic!("USB layout: too many OUT endpoints"),
+            Err(LayoutError::PacketSize(_)) => panic!("USB layout: an endpoint's packet size exceeds its limits"),
+        }
+    }
+}
+
+const fn validate_function(index: usize, function: &Function, bus: &Bus) -> Result<(), LayoutError> {
+    let Some(first) = function.interfaces.first() else {
+        return Err(LayoutError::EmptyFunction(index));
+    };
+
+    if first.class != function.class || first.sub_class != function.sub_class {
+        return Err(LayoutError::ClassMismatch(index));
+    }
+
+    let is_audio = function.class == class::AUDIO;
+    if is_audio && first.sub_class != audio::CONTROL {
+        return Err(LayoutError::AudioControl(index));
+    }
+
+    let mut interface = 0;
+    while interface < function.interfaces.len() {
+        let Interface {
+            class,
+            sub_class,
+            endpoints,
+        } = function.interfaces[interface];
+
+        if class != function.class {
+            return Err(LayoutError::ClassMismatch(index));
+        }
+        if is_audio && interface > 0 && sub_class == audio::CONTROL {
+            return Err(LayoutError::AudioControl(index));
+        }
+
+        let mut endpoint = 0;
+        while endpoint < endpoints.len() {
+            if !endpoints[endpoint].is_valid(bus.high_speed) {
+                return Err(LayoutError::PacketSize(index));
+            }
+            endpoint += 1;
+        }
+        interface += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL_SPEED: Bus = Bus {
+        high_speed: false,
+        endpoint_count: 4,
+    };
+
+    const HIGH_SPEED: Bus = Bus {
+        high_speed: true,
+        endpoint_count: 6,
+    };
+
+    const AUDIO_CONTROL: Interface = Interface {
+        class: class::AUDIO,
+        sub_class: audio::CONTROL,
+        endpoints: &[],
+    };
+
+    const AUDIO_STREAMING: Interface = Interface {
+        class: class::AUDIO,
+        sub_class: audio::STREAMING,
+        endpoints: &[Endpoint::isochronous_out(768), Endpoint::isochronous_in(3)],
+    };
+
+    const SPEAKER: Function = Function {
+        class: class::AUDIO,
+        sub_class: audio::CONTROL,
+        interfaces: &[AUDIO_CONTROL, AUDIO_STREAMING],
+    };
+
+    const VENDOR_INTERFACE: Interface = Interface {
+        class: class::VENDOR,
+        sub_class: 0,
+        endpoints: &[Endpoint::interrupt_in(8)],
+    };
+
+    const VENDOR: Function = Function {
+        class: class::VENDOR,
+        sub_class: 0,
+        interfaces: &[VENDOR_INTERFACE],
+    };
+
+    const HID_INTERFACE: Interface = Interface {
+        class: class::HID,
+        sub_class: 0,
+        endpoints: &[Endpoint::interrupt_in(1)],
+    };
+
+    const HID: Function = Function {
+        class: class::HID,
+        sub_class: 0,
+        interfaces: &[HID_INTERFACE],
+    };
+
+    fn validate(functions: &'static [Function], bus: &Bus) -> Result<(), LayoutError> {
+        Layout { functions }.validate(bus)
+    }
+
+    #[test]
+    fn numbers_interfaces() {
+        const LAYOUT: Layout = Layout {
+            functions: &[SPEAKER, VENDOR, HID],
+        };
+        const _: () = LAYOUT.assert_valid(&FULL_SPEED);
+
+        assert_eq!(LAYOUT.first_interface(0), 0);
+        assert_eq!(LAYOUT.first_interface(1), 2);
+        assert_eq!(LAYOUT.first_interface(2), 3);
+        assert_eq!(LAYOUT.interface_count(), 4);
+        assert_eq!(LAYOUT.endpoint_count(Direction::In), 3);
+        assert_eq!(LAYOUT.endpoint_count(Direction::Out), 1);
+        assert_eq!(LAYOUT.validate(&FULL_SPEED), Ok(()));
+    }
+
+    #[test]
+    fn rejects_endpoint_exhaustion() {
+        const FUNCTIONS: &[Function] = &[SPEAKER, VENDOR, HID, HID];
+
+        assert_eq!(
+            validate(FUNCTIONS, &FULL_SPEED),
+            Err(LayoutError::TooManyEndpoints(Direction::In))
+        );
+        assert_eq!(validate(FUNCTIONS, &HIGH_SPEED), Ok(()));
+        assert_eq!(validate(&[], &FULL_SPEED), Err(LayoutError::NoFunctions));
+    }
+
+    #[test]
+    fn rejects_mismatched_classes() {
+        // The IAD announces a different subclass than the first interface's.
+        const IAD: Function = Function {
+            sub_class: audio::STREAMING,
+            ..SPEAKER
+        };
+        assert_eq!(validate(&[IAD], &FULL_SPEED), Err(LayoutError::ClassMismatch(0)));
+
+        // An HID interface within the vendor function.
+        const MIXED: Function = Function {
+            interfaces: &[VENDOR_INTERFACE, HID_INTERFACE],
+            ..VENDOR
+        };
+        assert_eq!(
+            validate(&[VENDOR, MIXED], &FULL_SPEED),
+            Err(LayoutError::ClassMismatch(1))
+        );
+
+        const EMPTY: Function = Function { interfaces: &[], ..HID };
+        assert_eq!(validate(&[EMPTY], &FULL_SPEED), Err(LayoutError::EmptyFunction(0)));
+    }
+
+    #[test]
+    fn requires_leading_audio_control() {
+        const STREAMING_FIRST: Function = Function {
+            sub_class: audio::STREAMING,
+            interfaces: &[AUDIO_STREAMING, AUDIO_CONTROL],
+            ..SPEAKER
+        };
+        assert_eq!(
+            validate(&[STREAMING_FIRST], &FULL_SPEED),
+            Err(LayoutError::AudioControl(0))
+        );
+
+        const TWO_CONTROLS: Function = Function {
+            interfaces: &[AUDIO_CONTROL, AUDIO_CONTROL],
+            ..SPEAKER
+        };
+        assert_eq!(
+            validate(&[TWO_CONTROLS], &FULL_SPEED),
+            Err(LayoutError::AudioControl(0))
+        );
+    }
+
+    #[test]
+    fn limits_packet_sizes() {
+        const BULK: Function = Function {
+            interfaces: &[Interface {
+                endpoints: &[Endpoint::bulk_out(64)],
+                ..VENDOR_INTERFACE
+            }],
+            ..VENDOR
+        };
+        assert_eq!(validate(&[BULK], &FULL_SPEED), Ok(()));
+
+        // High-speed bulk endpoints have 512 byte packets.
+        assert_eq!(validate(&[BULK], &HIGH_SPEED), Err(LayoutError::PacketSize(0)));
+
+        const LARGE_PACKETS: Function = Function {
+            interfaces: &[
+                AUDIO_CONTROL,
+                Interface {
+                    endpoints: &[Endpoint::isochronous_out(1024)],
+                    ..AUDIO_STREAMING
+                },
+            ],
+            ..SPEAKER
+        };
+        assert_eq!(validate(&[LARGE_PACKETS], &FULL_SPEED), Err(LayoutError::PacketSize(0)));
+        assert_eq!(validate(&[LARGE_PACKETS], &HIGH_SPEED), Ok(()));
+    }
+}
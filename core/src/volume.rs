@@ -0,0 +1,133 @@
+// Mapping of the host's volume slider to the applied gain.
+//
+// Hosts map their volume slider linearly onto the advertised dB range, so that a wide range (e.g. -100 dB to 0 dB)
+// leaves the bottom half of the slider inaudible. The curve instead follows a cubic taper of the linear gain, which is
+// perceived as roughly even across the slider, between a configurable minimum and maximum gain.
+use crate::gain::{db_to_linear, linear_to_db};
+
+/// Lowest minimum gain, in dB.
+pub const MIN_DB: i8 = -100;
+
+/// Highest maximum gain, in dB.
+pub const MAX_DB: i8 = 0;
+
+/// Steps per dB of the curve's resolution.
+pub const STEPS_PER_DB: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VolumeCurve {
+    /// Gain at the bottom of the slider, in dB.
+    pub min_db: i8,
+    /// Gain at the top of the slider, in dB.
+    pub max_db: i8,
+    /// Resolution of the gain in 0.5 dB steps, or zero for a continuous gain.
+    pub step: u8,
+}
+
+impl VolumeCurve {
+    pub const DEFAULT: Self = Self {
+        min_db: -60,
+        max_db: 0,
+        step: 1,
+    };
+
+    /// Whether the gain range is within limits, and not empty.
+    pub fn is_valid(&self) -> bool {
+        MIN_DB <= self.min_db && self.min_db < self.max_db && self.max_db <= MAX_DB
+    }
+
+    /// The gain in dB at a slider position from 0 (bottom) to 1 (top).
+    pub fn gain_db(&self, position: f32) -> f32 {
+        let (min_db, max_db) = (self.min_db as f32, self.max_db as f32);
+        let position = position.clamp(0.0, 1.0);
+
+        let min_linear = db_to_linear(min_db - max_db);
+        let db = max_db + linear_to_db(min_linear + (1.0 - min_linear) * position * position * position);
+
+        if self.step == 0 {
+            return db.clamp(min_db, max_db);
+        }
+
+        // Round the attenuation from the top to whole steps.
+        let step_db = self.step as f32 / STEPS_PER_DB;
+        let steps = ((max_db - db) / step_db + 0.5) as u32;
+        (max_db - steps as f32 * step_db).clamp(min_db, max_db)
+    }
+}
+
+impl Default for VolumeCurve {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The position of a volume on a slider from `min_db` (0) to `max_db` (1).
+pub fn position(db: f32, min_db: f32, max_db: f32) -> f32 {
+    ((db - min_db) / (max_db - min_db)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve() {
+        let curve = VolumeCurve {
+            min_db: -60,
+            max_db: -6,
+            step: 0,
+        };
+
+        assert!((curve.gain_db(0.0) + 60.0).abs() < 0.01);
+        assert!((curve.gain_db(1.0) + 6.0).abs() < 0.01);
+
+        // The middle of the slider is well audible, unlike at -53 dB of a linear mapping.
+        let middle = curve.gain_db(0.5);
+        assert!(middle > -25.0 && middle < -23.0);
+
+        // Monotonic.
+        let mut previous = f32::MIN;
+        for step in 0..=100 {
+            let db = curve.gain_db(step as f32 / 100.0);
+            assert!(db >= previous);
+            previous = db;
+        }
+    }
+
+    #[test]
+    fn steps() {
+        let curve = VolumeCurve {
+            step: 4,
+            ..VolumeCurve::DEFAULT
+        };
+
+        for step in 0..=100 {
+            let db = curve.gain_db(step as f32 / 100.0);
+            assert_eq!(db % 2.0, 0.0);
+        }
+        assert_eq!(curve.gain_db(0.0), -60.0);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(VolumeCurve::DEFAULT.is_valid());
+        assert!(!VolumeCurve {
+            min_db: -20,
+            max_db: -20,
+            step: 0
+        }
+        .is_valid());
+        assert!(!VolumeCurve {
+            min_db: -110,
+            ..VolumeCurve::DEFAULT
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn slider() {
+        assert_eq!(position(-100.0, -100.0, 0.0), 0.0);
+        assert_eq!(position(-25.0, -100.0, 0.0), 0.75);
+        assert_eq!(position(6.0, -100.0, 0.0), 1.0);
+    }
+}
@@ -0,0 +1,171 @@
+// Routing of the stereo stream to output zones, each a stereo DAC (e.g. speakers in a second room, or the rear pair of
+// a four channel output).
+//
+// Each channel of a zone plays the stream's left or right channel, their mix, or silence, followed by the zone's gain.
+use crate::dsp::Gain;
+
+/// Number of output zones.
+pub const ZONE_COUNT: usize = 2;
+
+/// Lowest gain of a zone, in 0.5 dB steps.
+pub const MIN_GAIN: i8 = -120;
+
+/// Steps per dB of a zone's gain.
+pub const STEPS_PER_DB: f32 = 2.0;
+
+/// The source of a zone's channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Route {
+    Left = 0,
+    Right = 1,
+    /// The average of both channels, e.g. for a mono speaker or subwoofer.
+    Mix = 2,
+    Silent = 3,
+}
+
+impl Route {
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Left),
+            1 => Some(Self::Right),
+            2 => Some(Self::Mix),
+            3 => Some(Self::Silent),
+            _ => None,
+        }
+    }
+
+    /// The sample of a stereo frame for this route.
+    #[inline]
+    pub fn select(self, [left, right]: [i32; 2]) -> i32 {
+        match self {
+            Self::Left => left,
+            Self::Right => right,
+            Self::Mix => (left >> 1) + (right >> 1),
+            Self::Silent => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoneConfig {
+    /// Sources of the zone's left and right channel.
+    pub routes: [Route; 2],
+    /// Gain in 0.5 dB steps, from `MIN_GAIN` to zero.
+    pub gain: i8,
+}
+
+impl ZoneConfig {
+    pub const DEFAULT: Self = Self {
+        routes: [Route::Left, Route::Right],
+        gain: 0,
+    };
+
+    pub fn is_valid(&self) -> bool {
+        (MIN_GAIN..=0).contains(&self.gain)
+    }
+}
+
+impl Default for ZoneConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A zone's configuration, prepared for processing.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ZoneRouting {
+    routes: [Route; 2],
+    gain: Gain,
+}
+
+impl ZoneRouting {
+    /// Passes frames unchanged.
+    pub const IDENTITY: Self = Self {
+        routes: ZoneConfig::DEFAULT.routes,
+        gain: Gain::UNITY,
+    };
+
+    pub fn new(config: ZoneConfig) -> Self {
+        Self {
+            routes: config.routes,
+            gain: match config.gain {
+                0 => Gain::UNITY,
+                gain => Gain::from_db(gain as f32 / STEPS_PER_DB),
+            },
+        }
+    }
+
+    /// Whether frames pass unchanged, such that processing can be skipped.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Route a stereo frame to the zone's channels.
+    #[inline]
+    pub fn apply(&self, frame: [i32; 2]) -> [i32; 2] {
+        self.routes.map(|route| self.gain.apply(route.select(frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: [i32; 2] = [1 << 28, -(1 << 26)];
+
+    #[test]
+    fn routes() {
+        let routing = ZoneRouting::new(ZoneConfig {
+            routes: [Route::Right, Route::Left],
+            gain: 0,
+        });
+        assert_eq!(routing.apply(FRAME), [FRAME[1], FRAME[0]]);
+
+        let routing = ZoneRouting::new(ZoneConfig {
+            routes: [Route::Mix, Route::Silent],
+            gain: 0,
+        });
+        assert_eq!(routing.apply(FRAME), [(1 << 27) - (1 << 25), 0]);
+
+        // Mixing full-scale channels does not overflow.
+        assert_eq!(Route::Mix.select([i32::MAX, i32::MAX]), i32::MAX - 1);
+    }
+
+    #[test]
+    fn gain() {
+        let routing = ZoneRouting::new(ZoneConfig {
+            gain: -12,
+            ..ZoneConfig::DEFAULT
+        });
+
+        let [left, right] = routing.apply(FRAME);
+        assert!(left.abs_diff(FRAME[0] / 2) < 1 << 20);
+        assert!(right.abs_diff(FRAME[1] / 2) < 1 << 18);
+    }
+
+    #[test]
+    fn identity() {
+        assert!(ZoneRouting::new(ZoneConfig::DEFAULT).is_identity());
+        assert!(!ZoneRouting::new(ZoneConfig {
+            gain: -1,
+            ..ZoneConfig::DEFAULT
+        })
+        .is_identity());
+    }
+
+    #[test]
+    fn validity() {
+        assert!(ZoneConfig::DEFAULT.is_valid());
+        assert!(!ZoneConfig {
+            gain: 1,
+            ..ZoneConfig::DEFAULT
+        }
+        .is_valid());
+        assert!(!ZoneConfig {
+            gain: MIN_GAIN - 1,
+            ..ZoneConfig::DEFAULT
+        }
+        .is_valid());
+    }
+}
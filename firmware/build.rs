@@ -1,5 +1,69 @@
+use blus_core::dsp::chime::{self, Alert};
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs};
+
+// Run git in the package directory, returning its trimmed output on success.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+// Render the chimes as PCM into a source file, which `src/chime.rs` includes.
+fn write_chimes() {
+    let mut source = format!("static CHIMES: [&[i16]; {}] = [\n", Alert::ALL.len());
+    for alert in Alert::ALL {
+        let mut pcm = vec![0i16; chime::sample_count(alert.notes())];
+        chime::render(alert.notes(), &mut pcm);
+        source.push_str(&format!("    // {:?}\n    &{:?},\n", alert, pcm));
+    }
+    source.push_str("];\n");
+
+    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("chimes.rs");
+    fs::write(path, source).unwrap();
+}
+
 fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+    // On-target tests, see `tests/hil.rs`.
+    println!("cargo:rustc-link-arg-tests=--nmagic");
+    println!("cargo:rustc-link-arg-tests=-Tlink.x");
+    println!("cargo:rustc-link-arg-tests=-Tdefmt.x");
+
+    // Linker script fragments in the package directory.
+    println!("cargo:rustc-link-search={}", env!("CARGO_MANIFEST_DIR"));
+    // Places the firmware image CRC, see `src/image_crc.rs`.
+    println!("cargo:rustc-link-arg-bins=-Timage_crc.x");
+    println!("cargo:rustc-link-arg-tests=-Timage_crc.x");
+
+    // Reserves flash for the key-value store, see `src/kv_store.rs`.
+    println!("cargo:rustc-link-arg-bins=-Tkv_store.x");
+    println!("cargo:rustc-link-arg-tests=-Tkv_store.x");
+
+    // Build information, see `src/version.rs`.
+    let mut git_hash = git(&["rev-parse", "--short=8", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    if git(&["status", "--porcelain"]).is_some_and(|status| !status.is_empty()) {
+        git_hash.push_str("-dirty");
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=BUILD_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+
+    if env::var_os("CARGO_FEATURE_CHIMES").is_some() {
+        write_chimes();
+    }
 }
@@ -0,0 +1,11 @@
+/* Firmware image CRC, placed after the read-only data, see `src/image_crc.rs`. */
+__image_start = ORIGIN(FLASH);
+
+SECTIONS
+{
+  .image_crc : ALIGN(4)
+  {
+    __image_end = .;
+    KEEP(*(.image_crc));
+  } > FLASH
+} INSERT AFTER .rodata;
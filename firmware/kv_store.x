@@ -0,0 +1,6 @@
+/* Flash sectors 1 and 2 (16 kiB each), reserved for the key-value store, see `src/kv_store.rs`. The vector table stays
+   in sector 0, and the code starts after the reserved sectors. */
+__kv_store_start = ORIGIN(FLASH) + 16K;
+__kv_store_end = ORIGIN(FLASH) + 48K;
+
+_stext = __kv_store_end;
@@ -0,0 +1,47 @@
+// Per-channel delays for time alignment, e.g. of the ways of active speakers, or for an off-center listening position,
+// and per-channel polarity inversion, e.g. for miswired drivers, or crossovers that require it.
+//
+// Delays are set in samples at the DSP chain's sample rate (the host tool converts distances), up to 10 ms. Delays and
+// polarities are part of the persistent settings.
+use defmt::info;
+
+use crate::trim::OutOfRange;
+use crate::*;
+
+pub const MAX_DELAY_SAMPLES: usize = SAMPLE_RATE_HZ as usize / 100;
+
+/// Set the delay of a channel in samples, and apply it to the DSP chain.
+pub fn set_delay(channel: usize, samples: u16) -> Result<(), OutOfRange> {
+    if channel >= INPUT_CHANNEL_COUNT || samples as usize > MAX_DELAY_SAMPLES {
+        return Err(OutOfRange);
+    }
+
+    info!("Delay of channel {}: {} samples", channel, samples);
+    settings::modify(|settings| settings.delay[channel] = samples);
+    PRESET_SIGNAL.signal(preset::active());
+
+    Ok(())
+}
+
+/// The delays of all channels in samples.
+pub fn delays() -> [u16; INPUT_CHANNEL_COUNT] {
+    settings::get().delay
+}
+
+/// Set the channels whose polarity is inverted, as a mask with one bit per channel, and apply it to the DSP chain.
+pub fn set_inverted(mask: u8) -> Result<(), OutOfRange> {
+    if mask >> INPUT_CHANNEL_COUNT != 0 {
+        return Err(OutOfRange);
+    }
+
+    info!("Inverted polarity mask: {:#04x}", mask);
+    settings::modify(|settings| settings.inverted = mask);
+    PRESET_SIGNAL.signal(preset::active());
+
+    Ok(())
+}
+
+/// Whether the polarity of a channel is inverted.
+pub fn is_inverted(channel: usize) -> bool {
+    settings::get().inverted & 1 << channel != 0
+}
@@ -0,0 +1,42 @@
+// Amplifier fault recovery policy: latched faults are recovered by re-initializing the amplifiers, unless they recur
+// too often. Then, the outputs stay muted until the next reset, since a persistent fault (e.g. a shorted speaker) would
+// otherwise cycle through shutdown and recovery.
+use embassy_time::{Duration, Instant};
+use heapless::HistoryBuffer;
+
+// At most this many recoveries within the window.
+const MAX_RECOVERY_COUNT: usize = 3;
+const RECOVERY_WINDOW: Duration = Duration::from_secs(60);
+
+// Time for the fault condition to clear, before recovery.
+pub const RECOVERY_DELAY: Duration = Duration::from_millis(100);
+
+pub struct RecoveryLimiter {
+    recoveries: HistoryBuffer<Instant, MAX_RECOVERY_COUNT>,
+}
+
+impl RecoveryLimiter {
+    pub const fn new() -> Self {
+        Self {
+            recoveries: HistoryBuffer::new(),
+        }
+    }
+
+    /// Register a recovery attempt, returning `false` if the rate limit is exceeded.
+    pub fn try_recover(&mut self, now: Instant) -> bool {
+        let oldest = self.recoveries.oldest_ordered().next().copied();
+
+        if self.recoveries.is_full() && oldest.is_some_and(|oldest| now - oldest < RECOVERY_WINDOW) {
+            return false;
+        }
+
+        self.recoveries.write(now);
+        true
+    }
+}
+
+impl Default for RecoveryLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
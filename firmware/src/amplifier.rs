@@ -0,0 +1,270 @@
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{error, info, warn};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_futures::select::{select4, Either4};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Output;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+
+use crate::amp_fault::{self, RecoveryLimiter};
+use crate::i2c_recovery::RecoveringI2c;
+use crate::i2c_scan::{self, ExpectedDevice};
+use crate::self_test::{self, Check};
+use crate::tas2780::{Slot, Tas2780};
+use crate::thermal::{Foldback, ThermalFoldback};
+use crate::*;
+
+// Two two-way speakers, driven by one mono amplifier per way.
+const AMP_COUNT: usize = 4;
+const AMP_ADDRESSES: [u8; AMP_COUNT] = [0x38, 0x39, 0x3a, 0x3b];
+const AMP_SLOTS: [Slot; AMP_COUNT] = [Slot::Left, Slot::Left, Slot::Right, Slot::Right];
+
+// Devices on the board's I2C bus.
+const EXPECTED_DEVICES: [ExpectedDevice; AMP_COUNT] = [
+    ExpectedDevice {
+        name: "left woofer amplifier",
+        address: AMP_ADDRESSES[0],
+        critical: true,
+    },
+    ExpectedDevice {
+        name: "left tweeter amplifier",
+        address: AMP_ADDRESSES[1],
+        critical: true,
+    },
+    ExpectedDevice {
+        name: "right woofer amplifier",
+        address: AMP_ADDRESSES[2],
+        critical: true,
+    },
+    ExpectedDevice {
+        name: "right tweeter amplifier",
+        address: AMP_ADDRESSES[3],
+        critical: true,
+    },
+];
+
+// Interval for reading the amplifiers' die temperature.
+const TEMPERATURE_POLL_PERIOD: Duration = Duration::from_secs(1);
+
+type Amplifier = Tas2780<I2cDevice<'static, NoopRawMutex, RecoveringI2c>>;
+
+// Apply thermal foldback to a requested volume.
+fn fold_back(volume: Volume, foldback: Foldback) -> Volume {
+    match (volume, foldback) {
+        (Volume::Muted, _) | (_, Foldback::Mute) => Volume::Muted,
+        (volume, Foldback::None) => volume,
+        (Volume::DeciBel(db), Foldback::Attenuate(attenuation_db)) => Volume::DeciBel(db - attenuation_db),
+    }
+}
+
+async fn set_volume(amplifiers: &mut [Amplifier; AMP_COUNT], volume: (Volume, Volume), foldback: Foldback) {
+    for (amplifier, slot) in amplifiers.iter_mut().zip(AMP_SLOTS) {
+        let volume = match slot {
+            Slot::Left => volume.0,
+            Slot::Right => volume.1,
+        };
+
+        let result = match fold_back(volume, foldback) {
+            Volume::Muted => amplifier.set_mute(true).await,
+            Volume::DeciBel(db) => match amplifier.set_volume_db(db).await {
+                Ok(()) => amplifier.set_mute(false).await,
+                Err(e) => Err(e),
+            },
+        };
+
+        if result.is_err() {
+            warn!("Failed to set volume of amplifier at {:#x}", amplifier.address());
+        }
+    }
+}
+
+// Leave standby muted, and unmute with the volume, once the outputs settled.
+async fn wake(amplifiers: &mut [Amplifier; AMP_COUNT], volume: (Volume, Volume), foldback: Foldback) {
+    for amplifier in amplifiers.iter_mut() {
+        if amplifier.set_mute(true).await.is_err() {
+            warn!("Failed to wake amplifier at {:#x}", amplifier.address());
+        }
+    }
+
+    board::POWER_SEQUENCE.wait_settled().await;
+    set_volume(amplifiers, volume, foldback).await;
+}
+
+// Mute, and enter standby once the mute ramp completed.
+async fn sleep(amplifiers: &mut [Amplifier; AMP_COUNT]) {
+    for amplifier in amplifiers.iter_mut() {
+        if amplifier.set_mute(true).await.is_err() {
+            warn!("Failed to mute amplifier at {:#x}", amplifier.address());
+        }
+    }
+
+    board::POWER_SEQUENCE.wait_muted().await;
+    for amplifier in amplifiers.iter_mut() {
+        if amplifier.set_standby(true).await.is_err() {
+            warn!("Failed to set standby of amplifier at {:#x}", amplifier.address());
+        }
+    }
+    board::POWER_SEQUENCE.wait_discharged().await;
+}
+
+// Read the highest die temperature of all amplifiers.
+async fn max_temperature(amplifiers: &mut [Amplifier; AMP_COUNT]) -> Option<i16> {
+    let mut max_temperature = None;
+
+    for amplifier in amplifiers.iter_mut() {
+        match amplifier.read_temperature().await {
+            Ok(temperature) => max_temperature = max_temperature.max(Some(temperature)),
+            Err(_) => warn!("Failed to read temperature of amplifier at {:#x}", amplifier.address()),
+        }
+    }
+
+    max_temperature
+}
+
+// Initialize all amplifiers, and load their coefficients. Leaves them in software shutdown.
+async fn configure(amplifiers: &mut [Amplifier; AMP_COUNT]) {
+    for (amplifier, slot) in amplifiers.iter_mut().zip(AMP_SLOTS) {
+        if amplifier.init(slot).await.is_err() {
+            warn!("Failed to initialize amplifier at {:#x}", amplifier.address());
+        }
+    }
+
+    // Optional DSP coefficients, e.g. speaker equalization.
+    #[cfg(feature = "spi-flash")]
+    match coefficients::load_from_flash(amplifiers).await {
+        Ok(count) => info!("Loaded {} coefficient blocks", count),
+        Err(coefficients::LoadError::NoFlash | coefficients::LoadError::NoBlob) => info!("No amplifier coefficients"),
+        Err(e) => warn!("Failed to load amplifier coefficients: {}", e),
+    }
+}
+
+// Read (and thereby clear) the latched faults of all amplifiers. Returns whether any amplifier shut down.
+async fn read_faults(amplifiers: &mut [Amplifier; AMP_COUNT]) -> bool {
+    let mut shutdown = false;
+
+    for amplifier in amplifiers.iter_mut() {
+        match amplifier.read_faults().await {
+            Ok(faults) if faults.is_empty() => (),
+            Ok(faults) => {
+                warn!("Fault of amplifier at {:#x}: {}", amplifier.address(), faults);
+                shutdown |= faults.is_shutdown();
+            }
+            Err(_) => warn!("Failed to read faults of amplifier at {:#x}", amplifier.address()),
+        }
+    }
+
+    shutdown
+}
+
+// Configures the amplifiers at boot, and tracks volume and standby requests afterwards.
+// While active, the amplifiers' temperature is monitored, and gain is reduced when they run hot. Faults that are
+// signaled on the shared IRQ line (active low) are logged, and recovered from by re-initialization.
+//
+// Standby uses the amplifiers' software shutdown mode, which retains the register configuration. Entering and leaving
+// it follows the board's power sequence (see `power_sequence`), which avoids pops. The soft-start ramp
+// that follows a wake-up is applied in the streaming task, and covers the amplifier settling time.
+#[embassy_executor::task]
+pub async fn control_task(mut shutdown: Output<'static>, mut fault_irq: ExtiInput<'static>, i2c_bus: &'static I2cBus) {
+    shutdown.set_high();
+    board::POWER_SEQUENCE.wait_enabled().await;
+
+    let devices_present = i2c_scan::check_devices(&mut I2cDevice::new(i2c_bus), &EXPECTED_DEVICES).await;
+    if !self_test::check(Check::I2cDevices, devices_present) {
+        error!("Outputs stay muted");
+        OUTPUT_INHIBITED.store(true, Relaxed);
+    }
+
+    let mut amplifiers = AMP_ADDRESSES.map(|address| Tas2780::new(I2cDevice::new(i2c_bus), address));
+    configure(&mut amplifiers).await;
+
+    // Faults that were latched during initialization are cleared by reading them, unless their cause persists.
+    read_faults(&mut amplifiers).await;
+    self_test::check(Check::AmpFaults, !read_faults(&mut amplifiers).await);
+    let mut recovery_limiter = RecoveryLimiter::new();
+
+    let mut volume = (Volume::Muted, Volume::Muted);
+    let mut standby = true;
+
+    let mut thermal_foldback = ThermalFoldback::new();
+    let mut foldback = Foldback::None;
+    let mut temperature_ticker = Ticker::every(TEMPERATURE_POLL_PERIOD);
+
+    loop {
+        match select4(
+            VOLUME_SIGNAL.wait(),
+            AMP_STANDBY_SIGNAL.wait(),
+            temperature_ticker.next(),
+            fault_irq.wait_for_falling_edge(),
+        )
+        .await
+        {
+            Either4::First(new_volume) => {
+                volume = new_volume;
+
+                if !standby {
+                    set_volume(&mut amplifiers, volume, foldback).await;
+                }
+            }
+            Either4::Second(new_standby) => {
+                standby = new_standby || OUTPUT_INHIBITED.load(Relaxed);
+                info!("Amplifier standby: {}", standby);
+
+                if standby {
+                    sleep(&mut amplifiers).await;
+
+                    // Amplifiers in standby do not heat.
+                    #[cfg(feature = "fan-control")]
+                    fan::report_temperature(None);
+                } else {
+                    wake(&mut amplifiers, volume, foldback).await;
+                }
+            }
+            Either4::Third(()) => {
+                if standby {
+                    continue;
+                }
+
+                let Some(temperature) = max_temperature(&mut amplifiers).await else {
+                    continue;
+                };
+
+                #[cfg(feature = "fan-control")]
+                fan::report_temperature(Some(temperature));
+
+                let new_foldback = thermal_foldback.update(temperature);
+
+                if new_foldback != foldback {
+                    warn!("Thermal foldback at {} C: {}", temperature, new_foldback);
+                    foldback = new_foldback;
+                    set_volume(&mut amplifiers, volume, foldback).await;
+                }
+            }
+            Either4::Fourth(()) => {
+                if !read_faults(&mut amplifiers).await {
+                    continue;
+                }
+
+                if !recovery_limiter.try_recover(Instant::now()) {
+                    error!("Amplifier faults recur, outputs stay muted");
+                    OUTPUT_INHIBITED.store(true, Relaxed);
+                    self_test::fail(Check::AmpFaults);
+
+                    standby = true;
+                    sleep(&mut amplifiers).await;
+                    continue;
+                }
+
+                warn!("Recovering amplifiers");
+                Timer::after(amp_fault::RECOVERY_DELAY).await;
+                configure(&mut amplifiers).await;
+
+                if !standby {
+                    wake(&mut amplifiers, volume, foldback).await;
+
+                    #[cfg(feature = "chimes")]
+                    chime::play(chime::Alert::Fault);
+                }
+            }
+        }
+    }
+}
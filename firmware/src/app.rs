@@ -0,0 +1,383 @@
+// Bring-up of the application: the clock tree, board peripherals, USB device, and all tasks.
+//
+// `main` builds an `App` with the board's defaults. Alternative mains (e.g. a measurement rig with another clock tree
+// or USB identity, or a bootloader hand-off) override parts of the configuration with `App::builder()`, and reuse the
+// bring-up.
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{debug, info, unwrap};
+use embassy_executor::Spawner;
+use embassy_stm32::flash::Flash;
+use embassy_stm32::{wdg, Peripherals};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::zerocopy_channel;
+use embassy_usb::class::uac1::speaker::{self, Speaker};
+use static_cell::StaticCell;
+
+use crate::board::Board;
+use crate::channel_layout::ChannelLayout;
+use crate::*;
+
+/// The device's USB identity, for the audio and the configuration (mass storage) device.
+#[derive(Clone, Copy)]
+pub struct UsbIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: &'static str,
+    pub product: &'static str,
+    pub serial_number: Option<&'static str>,
+}
+
+impl UsbIdentity {
+    /// The identity from the compile-time configuration (see `config`).
+    pub const DEFAULT: Self = Self {
+        vid: USB_VID,
+        pid: USB_PID,
+        manufacturer: USB_MANUFACTURER,
+        product: USB_PRODUCT,
+        serial_number: USB_SERIAL_NUMBER,
+    };
+
+    /// A USB device configuration with this identity.
+    pub fn config(&self) -> embassy_usb::Config<'static> {
+        let mut config = embassy_usb::Config::new(self.vid, self.pid);
+        config.manufacturer = Some(self.manufacturer);
+        config.product = Some(self.product);
+        config.serial_number = self.serial_number;
+
+        config
+    }
+}
+
+impl Default for UsbIdentity {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Creates the board's peripheral drivers.
+pub type BoardProfile = fn(Peripherals) -> Board;
+
+/// Configures an `App`, starting from the board's defaults.
+pub struct AppBuilder {
+    clock_profile: embassy_stm32::Config,
+    usb_identity: UsbIdentity,
+    channel_layout: Option<ChannelLayout>,
+    board_profile: BoardProfile,
+}
+
+impl AppBuilder {
+    /// The peripheral configuration with the clock tree, instead of `board::config()`.
+    pub fn clock_profile(mut self, config: embassy_stm32::Config) -> Self {
+        self.clock_profile = config;
+        self
+    }
+
+    pub fn usb_identity(mut self, identity: UsbIdentity) -> Self {
+        self.usb_identity = identity;
+        self
+    }
+
+    /// A fixed channel layout of the USB stream, instead of the one from the settings.
+    pub fn channel_layout(mut self, layout: ChannelLayout) -> Self {
+        self.channel_layout = Some(layout);
+        self
+    }
+
+    /// The creation of peripheral drivers, instead of `board::init()`.
+    pub fn board_profile(mut self, board_profile: BoardProfile) -> Self {
+        self.board_profile = board_profile;
+        self
+    }
+
+    pub fn build(self) -> App {
+        App {
+            clock_profile: self.clock_profile,
+            usb_identity: self.usb_identity,
+            channel_layout: self.channel_layout,
+            board_profile: self.board_profile,
+        }
+    }
+}
+
+/// The application's configuration, which brings up the device.
+pub struct App {
+    clock_profile: embassy_stm32::Config,
+    usb_identity: UsbIdentity,
+    channel_layout: Option<ChannelLayout>,
+    board_profile: BoardProfile,
+}
+
+impl App {
+    pub fn builder() -> AppBuilder {
+        AppBuilder {
+            clock_profile: board::config(),
+            usb_identity: UsbIdentity::DEFAULT,
+            channel_layout: None,
+            board_profile: board::init,
+        }
+    }
+
+    /// Initialize the peripherals, and spawn all tasks. Returns once the tasks are running, or never, if the device
+    /// starts as a configuration device instead.
+    pub async fn run(self, spawner: Spawner) {
+        info!("Hi.");
+        info!("{}", version::VERSION_STRING);
+
+        reset_reason::init();
+
+        let p = embassy_stm32::init(self.clock_profile);
+        self_test::check_clocks();
+        let board = (self.board_profile)(p);
+
+        let mut core_peri = cortex_m::Peripherals::take().unwrap();
+
+        // Enable instruction cache.
+        core_peri.SCB.enable_icache();
+
+        // The cycle counter measures CPU load (and feedback, with the `feedback-frame-number` feature).
+        core_peri.DCB.enable_trace();
+        core_peri.DWT.enable_cycle_counter();
+        unwrap!(spawner.spawn(cpu_load::measurement_task()));
+
+        // A corrupt image must not power the outputs.
+        let image_intact = image_crc::check(board.crc) != image_crc::ImageCheck::Corrupt;
+        if !self_test::check(self_test::Check::ImageCrc, image_intact) {
+            OUTPUT_INHIBITED.store(true, Relaxed);
+        }
+
+        // Save power while the executor sleeps.
+        power::gate_idle_clocks();
+
+        // Load persistent settings. This may erase flash, so it happens before the watchdog is started.
+        let settings_store = settings::load(Flash::new_blocking(board.flash));
+
+        // Configuration as a mass storage device, instead of audio, while the wake-up button is held.
+        #[cfg(feature = "msc-config")]
+        if msc::is_requested(&board.wakeup_button, board::WAKEUP_BUTTON_ACTIVE_LOW) {
+            #[cfg(feature = "spi-flash")]
+            {
+                static SPI_BUS: StaticCell<partition::SpiBus> = StaticCell::new();
+                let (spi, cs) = board.spi_flash;
+                let spi_bus = SPI_BUS.init(embassy_sync::mutex::Mutex::new(spi));
+                unwrap!(spawner.spawn(partition::init_task(spi_bus, cs)));
+            }
+
+            msc::run(spawner, board.usb_driver, settings_store, self.usb_identity).await;
+        }
+
+        // The channel layout shapes the audio descriptors.
+        let layout = channel_layout::init(self.channel_layout.unwrap_or(settings::get().channel_layout));
+        debug!(
+            "USB stream is {} with a packet size of {} byte",
+            layout,
+            layout.max_packet_size()
+        );
+
+        // The power source shapes the configuration descriptor, and limits the amplifiers' volume.
+        #[cfg(feature = "power-detect")]
+        let power_source = power_source::detect(board.supply_sense.is_high());
+        #[cfg(not(feature = "power-detect"))]
+        let power_source = power_source::detect(true);
+
+        #[cfg(feature = "usb-midi")]
+        const CONFIG_DESCRIPTOR_SIZE: usize = 256 + midi::DESCRIPTOR_SIZE;
+        #[cfg(not(feature = "usb-midi"))]
+        const CONFIG_DESCRIPTOR_SIZE: usize = 256;
+
+        static CONFIG_DESCRIPTOR: StaticCell<[u8; CONFIG_DESCRIPTOR_SIZE]> = StaticCell::new();
+        let config_descriptor = CONFIG_DESCRIPTOR.init([0; CONFIG_DESCRIPTOR_SIZE]);
+
+        static BOS_DESCRIPTOR: StaticCell<[u8; 32]> = StaticCell::new();
+        let bos_descriptor = BOS_DESCRIPTOR.init([0; 32]);
+
+        static CONTROL_BUF: StaticCell<[u8; USB_CONTROL_BUF_SIZE]> = StaticCell::new();
+        let control_buf = CONTROL_BUF.init([0; USB_CONTROL_BUF_SIZE]);
+
+        static STATE: StaticCell<speaker::State> = StaticCell::new();
+        let state = STATE.init(speaker::State::new());
+
+        // Basic USB device configuration
+        let mut config = self.usb_identity.config();
+        config.self_powered = power_source.self_powered();
+        config.max_power = power_source.max_power_ma();
+        config.supports_remote_wakeup = true;
+
+        // Required for windows compatibility.
+        // https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
+        config.device_class = 0xEF;
+        config.device_sub_class = 0x02;
+        config.device_protocol = 0x01;
+        config.composite_with_iads = true;
+
+        let mut builder = embassy_usb::Builder::new(
+            board.usb_driver,
+            config,
+            config_descriptor,
+            bos_descriptor,
+            &mut [], // no msos descriptors
+            control_buf,
+        );
+
+        // Tracks connection state changes, for resetting the audio pipeline.
+        static DEVICE_HANDLER: StaticCell<usb_audio::DeviceHandler> = StaticCell::new();
+        builder.handler(DEVICE_HANDLER.init(usb_audio::DeviceHandler::new()));
+
+        // Functions are registered in the order of `usb_layout::LAYOUT`.
+
+        // Create the UAC1 Speaker class components. Its feature unit has mute and volume controls per channel of the
+        // layout, which the control task maps onto the outputs (see `usb_audio::control_task`).
+        let (stream, feedback, control_changed) = Speaker::new(
+            &mut builder,
+            state,
+            layout.max_packet_size() as u16,
+            SAMPLE_WIDTH,
+            &SAMPLE_RATES_HZ,
+            layout.channels(),
+            FEEDBACK_REFRESH_PERIOD,
+        );
+
+        // Vendor interface for device configuration, after the audio interfaces.
+        let meter_endpoint = vendor::register(&mut builder);
+
+        // Media keys, e.g. for synchronizing the host's volume with the encoder.
+        let consumer_control = hid::register(&mut builder);
+
+        // MIDI control changes, for adjusting DSP parameters live.
+        #[cfg(feature = "usb-midi")]
+        let midi_endpoint = midi::register(&mut builder);
+
+        // Build and run the USB device
+        let usb_device = builder.build();
+
+        // Establish a zero-copy channel for transferring received audio samples from the USB audio task.
+        static USB_SAMPLE_BLOCKS: StaticCell<[UsbSampleBlock; USB_SAMPLE_BLOCK_COUNT]> = StaticCell::new();
+        let usb_sample_blocks = USB_SAMPLE_BLOCKS.init([const { UsbSampleBlock::new() }; USB_SAMPLE_BLOCK_COUNT]);
+
+        static USB_CHANNEL: StaticCell<zerocopy_channel::Channel<'_, NoopRawMutex, UsbSampleBlock>> = StaticCell::new();
+        let usb_channel = USB_CHANNEL.init(zerocopy_channel::Channel::new(usb_sample_blocks));
+        let (usb_sender, usb_receiver) = usb_channel.split();
+
+        // Capture the feedback timer at USB SOF, or measure feedback from the USB frame number.
+        #[cfg(not(feature = "feedback-frame-number"))]
+        sof_capture::start(sof_capture::SofCapture::new(board.sof_timer, board::SOF_SOURCE));
+
+        #[cfg(feature = "feedback-frame-number")]
+        unwrap!(spawner.spawn(frame_feedback::frame_number_task()));
+
+        // The second zone plays along with the I2S output.
+        #[cfg(feature = "dual-zone")]
+        zone::init(board.zone_2_i2s);
+
+        unwrap!(spawner.spawn(output::i2s_task(board.i2s, usb_receiver)));
+
+        // Mirrors the I2S output.
+        #[cfg(feature = "spdif-output")]
+        spdif::init(board.spdif_output);
+
+        // Shared I2C bus for amplifier or codec control, which recovers from stuck devices.
+        static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+        let i2c_bus = I2C_BUS.init(embassy_sync::mutex::Mutex::new(i2c_recovery::RecoveringI2c::new(
+            board.i2c,
+            board::I2C_PINS,
+        )));
+
+        // External flash for coefficient sets, presets, and staged firmware images.
+        #[cfg(feature = "spi-flash")]
+        {
+            static SPI_BUS: StaticCell<partition::SpiBus> = StaticCell::new();
+            let (spi, cs) = board.spi_flash;
+            let spi_bus = SPI_BUS.init(embassy_sync::mutex::Mutex::new(spi));
+            unwrap!(spawner.spawn(partition::init_task(spi_bus, cs)));
+            unwrap!(spawner.spawn(upload::upload_task()));
+        }
+
+        // Front-panel LED and button on a GPIO expander.
+        #[cfg(feature = "front-panel-expander")]
+        unwrap!(spawner.spawn(expander_io::expander_task(
+            i2c_bus,
+            board::EXPANDER_ADDRESS,
+            board::EXPANDER_VARIANT,
+            Some(board.expander_interrupt)
+        )));
+
+        unwrap!(spawner.spawn(status_led::status_task(board.status_led, board::STATUS_LED_ACTIVE_LOW)));
+        unwrap!(spawner.spawn(self_test::enumeration_task()));
+
+        // Device state on an addressable LED.
+        #[cfg(feature = "status-ws2812")]
+        unwrap!(spawner.spawn(status_indicator::indicator_task(board.status_indicator)));
+
+        // Stream format, volume, and buffer health on an OLED.
+        #[cfg(feature = "status-display")]
+        unwrap!(spawner.spawn(display::display_task(i2c_bus, display::ADDRESS)));
+
+        // Amplifiers or codec.
+        board::spawn_output_control(spawner, board.output_control, i2c_bus);
+
+        // Resets the device, if one of the audio tasks hangs.
+        let watchdog = wdg::IndependentWatchdog::new(board.watchdog, watchdog::WATCHDOG_TIMEOUT_US);
+        unwrap!(spawner.spawn(watchdog::supervisor_task(watchdog)));
+
+        // Button for waking up a suspended host, and for the board's button actions otherwise.
+        unwrap!(spawner.spawn(buttons::button_task(
+            board.wakeup_button,
+            board::WAKEUP_BUTTON_ACTIVE_LOW,
+            board::WAKEUP_BUTTON_ACTIONS
+        )));
+
+        unwrap!(spawner.spawn(stats::report_task()));
+        unwrap!(spawner.spawn(memory::report_task()));
+        unwrap!(spawner.spawn(bootloader::request_task()));
+        unwrap!(spawner.spawn(settings::store_task(settings_store)));
+        unwrap!(spawner.spawn(sleep_timer::sleep_task()));
+        #[cfg(feature = "fan-control")]
+        unwrap!(spawner.spawn(fan::fan_task(board.fan.0, board.fan.1)));
+        #[cfg(feature = "battery-monitor")]
+        unwrap!(spawner.spawn(battery::monitor_task(board.battery_sense.0, board.battery_sense.1)));
+
+        // Launch USB audio tasks.
+        unwrap!(spawner.spawn(usb_audio::control_task(control_changed)));
+        #[cfg(feature = "aux-input")]
+        let aux_input = Some(board.aux_input);
+        #[cfg(not(feature = "aux-input"))]
+        let aux_input = None;
+        #[cfg(feature = "i2s-input")]
+        let i2s_input = Some(board.i2s_input);
+        #[cfg(not(feature = "i2s-input"))]
+        let i2s_input = None;
+        #[cfg(not(feature = "stereo-link-secondary"))]
+        unwrap!(spawner.spawn(usb_audio::streaming_task(stream, usb_sender, aux_input, i2s_input)));
+
+        // A stereo link's secondary plays the samples from its primary, instead of the USB stream.
+        #[cfg(feature = "stereo-link-secondary")]
+        {
+            let _ = (stream, aux_input, i2s_input);
+            unwrap!(spawner.spawn(link::receive_task(board.link_rx, usb_sender)));
+        }
+        #[cfg(feature = "stereo-link-primary")]
+        unwrap!(spawner.spawn(link::transmit_task(board.link_tx)));
+        #[cfg(feature = "uart-control")]
+        unwrap!(spawner.spawn(uart_control::control_task(board.control_uart.0, board.control_uart.1)));
+        unwrap!(spawner.spawn(usb_audio::feedback_task(feedback)));
+        unwrap!(spawner.spawn(usb_audio::usb_task(usb_device)));
+        unwrap!(spawner.spawn(hid::consumer_control_task(consumer_control)));
+        unwrap!(spawner.spawn(meter::meter_task(meter_endpoint)));
+        #[cfg(feature = "usb-midi")]
+        unwrap!(spawner.spawn(midi::midi_task(midi_endpoint)));
+
+        #[cfg(feature = "spectrum")]
+        unwrap!(spawner.spawn(spectrum::spectrum_task()));
+
+        #[cfg(feature = "ir-remote")]
+        {
+            ir_capture::start(ir_capture::IrCapture::new(board.ir_timer, board::IR_CHANNEL));
+            unwrap!(spawner.spawn(ir_remote::remote_task()));
+        }
+
+        #[cfg(feature = "rotary-encoder")]
+        {
+            let (a, b) = board.encoder;
+            unwrap!(spawner.spawn(encoder::encoder_task(a, b)));
+        }
+    }
+}
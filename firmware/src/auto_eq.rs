@@ -0,0 +1,160 @@
+// Auto-EQ: a room and driver correction without host software. A measurement microphone (with a preamplifier) on the
+// left aux input records the built-in sweep, as both speakers play it at the listening position. The recording's level
+// per third-octave band is analyzed, and correction filters are fitted to it (see `blus_core::dsp::auto_eq`).
+//
+// A vendor request starts the measurement, which interrupts the playing source. The sweep bypasses the DSP chain, and
+// plays at the amplifiers' current volume. The correction is stored in the settings, and played by the "Room
+// correction" preset, which is selected once a measurement succeeded.
+use blus_core::dsp::auto_eq::{self, FitParams, Response, Sweep};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering::Relaxed};
+use defmt::{info, warn, Format};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::zerocopy_channel;
+
+use crate::aux_input::{self, AuxStream};
+use crate::preset::{EQ_BAND_COUNT, PRESETS};
+use crate::watchdog::{self, Task};
+use crate::*;
+
+/// The preset, which plays the correction.
+pub const PRESET: usize = PRESETS.len() - 1;
+
+// A quarter second per band, which covers a few periods of the lowest band, and a sweep of 7.5 s.
+const FRAMES_PER_BAND: u32 = SAMPLE_RATE_HZ / 4;
+
+// Peak level of the sweep.
+const SWEEP_LEVEL_DB: f32 = -12.0;
+
+// The recording continues after the sweep, for the latency of the output buffer and the aux input.
+const TAIL_FRAMES: u32 = FRAMES_PER_BAND / 4;
+
+// Lowest mean level of the recording, below which no microphone is assumed (about 6 dB above the ADC's noise floor).
+const MIN_LEVEL_DB: f32 = -60.0;
+
+// The microphone's aux input channel.
+const MICROPHONE_CHANNEL: usize = 0;
+
+const FIT: FitParams = FitParams {
+    min_hz: 40.0,
+    max_hz: 16_000.0,
+    min_gain_db: -12.0,
+    max_gain_db: 3.0,
+    threshold_db: 1.5,
+};
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum State {
+    Idle = 0,
+    Measuring = 1,
+    Done = 2,
+    /// The recording was too quiet, e.g. without a microphone.
+    NoSignal = 3,
+    /// The aux input fell behind, which misaligns the recording.
+    Overrun = 4,
+}
+
+impl State {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Measuring,
+            2 => Self::Done,
+            3 => Self::NoSignal,
+            4 => Self::Overrun,
+            _ => Self::Idle,
+        }
+    }
+}
+
+static STATE: AtomicU8 = AtomicU8::new(State::Idle as u8);
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn state() -> State {
+    State::from_u8(STATE.load(Relaxed))
+}
+
+fn set_state(state: State) {
+    info!("Auto-EQ: {}", state);
+    STATE.store(state as u8, Relaxed);
+}
+
+/// Request a measurement, which the streaming task starts after the playing source faded out.
+pub fn start() {
+    if state() == State::Measuring {
+        return;
+    }
+
+    REQUESTED.store(true, Relaxed);
+    source::interrupt();
+}
+
+/// Take a pending measurement request.
+pub fn take_request() -> bool {
+    REQUESTED.swap(false, Relaxed)
+}
+
+/// Play the sweep, record it from the aux input, and store the fitted correction. The aux input paces the sweep, since
+/// both run from the same clock.
+pub async fn measure(
+    aux: &mut AuxStream<'_>,
+    sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+) {
+    set_state(State::Measuring);
+
+    let mut sweep = Sweep::new(FRAMES_PER_BAND, SWEEP_LEVEL_DB, SAMPLE_RATE_HZ);
+    let mut response = Response::new(FRAMES_PER_BAND);
+    let frame_count = sweep.frame_count() + TAIL_FRAMES;
+
+    let mut recording = [0i32; aux_input::SAMPLES_PER_BLOCK];
+    let mut playback = [0i32; aux_input::SAMPLES_PER_BLOCK];
+    let mut frame = 0;
+
+    while frame < frame_count {
+        watchdog::check_in(Task::Streaming);
+
+        if aux.read(&mut recording).await.is_err() {
+            set_state(State::Overrun);
+            return;
+        }
+
+        for (output, input) in playback
+            .chunks_exact_mut(INPUT_CHANNEL_COUNT)
+            .zip(recording.chunks_exact(INPUT_CHANNEL_COUNT))
+        {
+            output.fill(sweep.next().unwrap_or(0));
+            response.add(input[MICROPHONE_CHANNEL]);
+        }
+        frame += (recording.len() / INPUT_CHANNEL_COUNT) as u32;
+
+        let samples = sender.send().await;
+        samples.set_samples(&playback);
+        sender.send_done();
+        stats::block_queued();
+    }
+
+    let Some(levels_db) = response.levels_db() else {
+        set_state(State::NoSignal);
+        return;
+    };
+
+    let mean_db = levels_db.iter().sum::<f32>() / levels_db.len() as f32;
+    if mean_db < MIN_LEVEL_DB {
+        warn!("Auto-EQ recording at {} dBFS is too quiet", mean_db);
+        set_state(State::NoSignal);
+        return;
+    }
+
+    let corrections = auto_eq::fit::<EQ_BAND_COUNT>(&levels_db, &FIT, SAMPLE_RATE_HZ);
+    for correction in corrections.iter().flatten() {
+        info!(
+            "Correction at {} Hz: {} dB, Q {}",
+            auto_eq::band_hz(correction.band as usize),
+            correction.gain_db(),
+            correction.q()
+        );
+    }
+
+    settings::modify(|settings| settings.set_correction(corrections));
+    preset::select(PRESET).unwrap();
+    set_state(State::Done);
+}
@@ -0,0 +1,205 @@
+// Analog stereo aux input, sampled by the on-chip ADC at the audio sample rate.
+//
+// A timer triggers a scan of both channels per sample period, and the DMA writes the conversions to a ring buffer in
+// channel order. The trigger timer and the I2S clock derive from the same oscillator, so that the input neither
+// drifts against the output nor needs rate adaptation. Samples are offset-free Q31 after a DC blocker, which removes
+// the input's bias voltage.
+use defmt::Format;
+use embassy_stm32::dma::{ReadableRingBuffer, TransferOptions};
+use embassy_stm32::gpio::Flex;
+use embassy_stm32::pac::adc::vals::{Dds, Exten, Res, SampleTime};
+use embassy_stm32::pac::adccommon::vals::Adcpre;
+use embassy_stm32::pac::timer::vals::Mms;
+use embassy_stm32::peripherals::{ADC1, DMA2_CH4, TIM3};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::Timer;
+use embassy_stm32::{adc, pac, rcc};
+use embassy_time::{block_for, Duration};
+use static_cell::StaticCell;
+
+use crate::*;
+
+// Input channels, scanned in this order.
+const CHANNEL_COUNT: usize = 2;
+static_assertions::const_assert_eq!(CHANNEL_COUNT, INPUT_CHANNEL_COUNT);
+
+/// Samples per block, 1 ms of audio.
+pub const SAMPLES_PER_BLOCK: usize = (SAMPLE_RATE_HZ / 1000) as usize * CHANNEL_COUNT;
+
+// Reads are limited to the size of a USB packet, for mixing.
+const MAX_READ_SIZE: usize = USB_SAMPLE_BLOCK_SAMPLE_COUNT;
+static_assertions::const_assert!(SAMPLES_PER_BLOCK <= MAX_READ_SIZE);
+
+// DMA ring buffer of 4 ms.
+const RING_BUFFER_SIZE: usize = 4 * SAMPLES_PER_BLOCK;
+
+// External trigger selection of TIM3_TRGO.
+const EXTSEL_TIM3_TRGO: u8 = 0b1000;
+
+// Time for completing a scan in progress, after disabling the trigger.
+const SCAN_TIME: Duration = Duration::from_micros(20);
+
+// Conversions are 12 bit, and scaled to the upper bits (less the sign bit) for the DC blocker.
+const SAMPLE_SHIFT: u32 = 16;
+
+// Bias of the input, the DC blocker's initial offset.
+const MID_SCALE: i32 = 2048 << SAMPLE_SHIFT;
+
+// DC blocker time constant, as a power of two in samples (about 20 ms).
+const DC_SHIFT: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct Overrun;
+
+pub struct AuxInput {
+    timer: Timer<'static, TIM3>,
+    dma: DMA2_CH4,
+    // Kept in analog mode.
+    _pins: [Flex<'static>; CHANNEL_COUNT],
+    buffer: &'static mut [u16; RING_BUFFER_SIZE],
+}
+
+impl AuxInput {
+    /// Sample the ADC channels of both pins, left channel first.
+    pub fn new(
+        _adc: ADC1,
+        tim: TIM3,
+        dma: DMA2_CH4,
+        mut pins: [Flex<'static>; CHANNEL_COUNT],
+        channels: [u8; CHANNEL_COUNT],
+    ) -> Self {
+        for pin in pins.iter_mut() {
+            pin.set_as_analog();
+        }
+
+        rcc::enable_and_reset::<ADC1>();
+        let regs = pac::ADC1;
+
+        // The ADC clock must not exceed 36 MHz.
+        pac::ADC_COMMON.ccr().modify(|w| w.set_adcpre(Adcpre::DIV4));
+
+        regs.cr1().modify(|w| {
+            w.set_scan(true);
+            w.set_res(Res::BITS12);
+        });
+        regs.cr2().modify(|w| {
+            w.set_extsel(EXTSEL_TIM3_TRGO);
+            w.set_dds(Dds::CONTINUOUS);
+            w.set_adon(true);
+        });
+
+        regs.sqr1().modify(|w| w.set_l(CHANNEL_COUNT as u8 - 1));
+        for (index, &channel) in channels.iter().enumerate() {
+            regs.sqr3().modify(|w| w.set_sq(index, channel));
+            regs.smpr2()
+                .modify(|w| w.set_smp(channel as usize, SampleTime::CYCLES84));
+        }
+
+        // Triggers a scan per sample period.
+        let timer = Timer::new(tim);
+        timer.set_frequency(Hertz(SAMPLE_RATE_HZ));
+        timer.regs_gp16().cr2().modify(|w| w.set_mms(Mms::UPDATE));
+
+        static BUFFER: StaticCell<[u16; RING_BUFFER_SIZE]> = StaticCell::new();
+
+        Self {
+            timer,
+            dma,
+            _pins: pins,
+            buffer: BUFFER.init([0; RING_BUFFER_SIZE]),
+        }
+    }
+
+    /// Start sampling. Sampling stops, when the stream is dropped.
+    pub fn start(&mut self) -> AuxStream<'_> {
+        let regs = pac::ADC1;
+        let request = adc::RxDma::<ADC1>::request(&self.dma);
+
+        // SAFETY: The ring buffer is dropped along with the stream, before the DMA channel and buffer are reused.
+        let mut ring = unsafe {
+            ReadableRingBuffer::new(
+                &mut self.dma,
+                request,
+                regs.dr().as_ptr() as *mut u16,
+                &mut self.buffer[..],
+                TransferOptions::default(),
+            )
+        };
+        ring.start();
+
+        let mut stream = AuxStream {
+            timer: &self.timer,
+            ring,
+            dc_offset: [MID_SCALE; CHANNEL_COUNT],
+        };
+
+        stream.restart();
+        stream.timer.start();
+
+        stream
+    }
+}
+
+pub struct AuxStream<'a> {
+    timer: &'a Timer<'static, TIM3>,
+    ring: ReadableRingBuffer<'a, u16>,
+    dc_offset: [i32; CHANNEL_COUNT],
+}
+
+impl AuxStream<'_> {
+    fn stop_conversions(&mut self) {
+        pac::ADC1.cr2().modify(|w| w.set_exten(Exten::DISABLED));
+        block_for(SCAN_TIME);
+    }
+
+    // Restart after an overrun. Conversions are stopped at the end of a scan, which keeps the ring buffer's read
+    // position at the first channel.
+    fn restart(&mut self) {
+        let regs = pac::ADC1;
+        self.stop_conversions();
+
+        // An ADC overrun stops DMA requests, until they are re-enabled.
+        regs.sr().write(|w| w.0 = 0);
+        regs.cr2().modify(|w| w.set_dma(false));
+        regs.cr2().modify(|w| w.set_dma(true));
+
+        self.ring.clear();
+        regs.cr2().modify(|w| w.set_exten(Exten::RISINGEDGE));
+    }
+
+    /// Read whole frames as Q31 samples, in channel order (at most a USB packet's worth). Waits for the ADC, and
+    /// restarts it after an overrun, which means that the reader fell behind.
+    pub async fn read(&mut self, samples: &mut [i32]) -> Result<(), Overrun> {
+        assert!(samples.len() % CHANNEL_COUNT == 0);
+
+        let mut conversions = [0u16; MAX_READ_SIZE];
+        let conversions = &mut conversions[..samples.len()];
+
+        if self.ring.read_exact(conversions).await.is_err() {
+            self.restart();
+            return Err(Overrun);
+        }
+
+        for (frame, conversions) in samples
+            .chunks_exact_mut(CHANNEL_COUNT)
+            .zip(conversions.chunks_exact(CHANNEL_COUNT))
+        {
+            for ((sample, &conversion), offset) in frame.iter_mut().zip(conversions).zip(self.dc_offset.iter_mut()) {
+                let value = (conversion as i32) << SAMPLE_SHIFT;
+                *offset += (value - *offset) >> DC_SHIFT;
+
+                // Fits, since both are within the 28 bit range of scaled conversions.
+                *sample = (value - *offset) << (31 - 12 - SAMPLE_SHIFT);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AuxStream<'_> {
+    fn drop(&mut self) {
+        self.stop_conversions();
+        self.timer.stop();
+    }
+}
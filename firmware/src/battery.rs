@@ -0,0 +1,113 @@
+// Battery monitoring for portable builds, with volume limiting and shutdown on low charge.
+//
+// The battery voltage is sensed through a divider on an ADC input, once per second, and referenced to the internal
+// voltage reference, so that it does not depend on the supply's accuracy. Averaged cell voltages drive the policy (see
+// `blus_core::battery`): a low battery limits the volume, which avoids brown-outs at peaks, and a critical battery
+// shuts the output down gracefully (muted, amplifiers in standby) until the device restarts. The level is reported with
+// a vendor request, and to the host as HID battery strength.
+use blus_core::battery::{self, Average, BatteryState, LowBatteryPolicy};
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering::Relaxed};
+use defmt::{error, info, warn};
+use embassy_stm32::adc::{Adc, SampleTime};
+use embassy_stm32::peripherals::ADC1;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Ticker};
+use embassy_usb::class::uac1::speaker::Volume;
+
+use crate::status_led::LedStatus;
+use crate::*;
+
+const MEASUREMENT_PERIOD: Duration = Duration::from_secs(1);
+
+// Highest amplifier volume on a low battery.
+const LOW_BATTERY_MAX_VOLUME_DB: f32 = -10.0;
+
+// Factory calibration of the internal reference: its reading at 3.3 V supply, and full scale.
+const VREFINT_CAL: *const u16 = 0x1fff_7a2a as *const u16;
+const VREFINT_CAL_MV: u32 = 3300;
+const FULL_SCALE: u32 = 4095;
+
+/// Signals the state of charge in percent, when it changed.
+pub static LEVEL_SIGNAL: Signal<ThreadModeRawMutex, u8> = Signal::new();
+
+static CELL_MV: AtomicU16 = AtomicU16::new(0);
+static LEVEL_PERCENT: AtomicU8 = AtomicU8::new(100);
+static STATE: AtomicU8 = AtomicU8::new(BatteryState::Normal as u8);
+
+fn state() -> BatteryState {
+    match STATE.load(Relaxed) {
+        0 => BatteryState::Normal,
+        1 => BatteryState::Low,
+        _ => BatteryState::Critical,
+    }
+}
+
+/// The averaged cell voltage in millivolts, the state of charge in percent, and the state.
+pub fn status() -> (u16, u8, BatteryState) {
+    (CELL_MV.load(Relaxed), LEVEL_PERCENT.load(Relaxed), state())
+}
+
+/// Cap a volume on a low battery.
+pub fn limit(volume: Volume) -> Volume {
+    match (state(), volume) {
+        (BatteryState::Low, Volume::DeciBel(db)) => Volume::DeciBel(db.min(LOW_BATTERY_MAX_VOLUME_DB)),
+        (BatteryState::Critical, _) => Volume::Muted,
+        _ => volume,
+    }
+}
+
+// Read the battery voltage at the divider's input, in millivolts.
+fn read_battery_mv(adc: &mut Adc<'static, ADC1>, sense: &mut board::BatterySensePin) -> u32 {
+    let mut vrefint = adc.enable_vrefint();
+    let reference = adc.blocking_read(&mut vrefint).max(1) as u32;
+    let sample = adc.blocking_read(sense) as u32;
+
+    // SAFETY: The calibration value is in read-only system memory.
+    let calibration = unsafe { VREFINT_CAL.read_volatile() } as u32;
+    let supply_mv = VREFINT_CAL_MV * calibration / reference;
+
+    sample * supply_mv / FULL_SCALE * board::BATTERY_DIVIDER_RATIO
+}
+
+/// Measures the battery, and applies the low-battery policy.
+#[embassy_executor::task]
+pub async fn monitor_task(adc: ADC1, mut sense: board::BatterySensePin) {
+    let mut adc = Adc::new(adc);
+    adc.set_sample_time(SampleTime::CYCLES480);
+
+    let mut average = Average::new();
+    let mut policy = LowBatteryPolicy::new();
+    let mut ticker = Ticker::every(MEASUREMENT_PERIOD);
+
+    loop {
+        let cell_mv = read_battery_mv(&mut adc, &mut sense) / board::BATTERY_CELL_COUNT;
+        let cell_mv = average.update(cell_mv as u16);
+        let level_percent = battery::level_percent(cell_mv);
+
+        CELL_MV.store(cell_mv, Relaxed);
+        if LEVEL_PERCENT.swap(level_percent, Relaxed) != level_percent {
+            LEVEL_SIGNAL.signal(level_percent);
+        }
+
+        let new_state = policy.update(cell_mv);
+        if new_state != state() {
+            STATE.store(new_state as u8, Relaxed);
+
+            match new_state {
+                BatteryState::Normal => info!("Battery recovered at {} mV per cell", cell_mv),
+                BatteryState::Low => warn!("Low battery at {} mV per cell, limiting volume", cell_mv),
+                BatteryState::Critical => {
+                    error!("Critical battery at {} mV per cell, shutting down output", cell_mv);
+                    OUTPUT_INHIBITED.store(true, Relaxed);
+                    AMP_STANDBY_SIGNAL.signal(true);
+                    STATUS_LED_SIGNAL.signal(LedStatus::Error);
+                }
+            }
+
+            trim::update();
+        }
+
+        ticker.next().await;
+    }
+}
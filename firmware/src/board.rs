@@ -0,0 +1,265 @@
+// Board support: clock tree, pin assignments, and output stage of the supported boards, selected by cargo feature.
+//
+// Every board profile provides the same items:
+// - `config()`, the peripheral configuration with the board's clock tree,
+// - `init()`, which creates the peripheral drivers (including USB) from the board's pin assignment,
+// - `OutputControl` and `spawn_output_control()`, for driving the output stage (amplifiers, codec),
+// - `POWER_SEQUENCE`, the settling times of the output stage's power sequence (see `power_sequence`),
+// - `I2S_SPI` and `MCLK_ENABLED`, for reconfiguring I2S clocks at runtime,
+// - `I2C_PINS`, for recovering a stuck I2C bus,
+// - the polarity of the status LED and wake-up button, and the wake-up button's actions.
+use embassy_stm32::exti::ExtiInput;
+#[cfg(feature = "power-detect")]
+use embassy_stm32::gpio::Input;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::i2s::I2S;
+use embassy_stm32::{bind_interrupts, i2c, peripherals, usb};
+use static_cell::StaticCell;
+
+use crate::sof_capture::{SofSource, UsbSof};
+use crate::*;
+
+// Takes the SOF capture timer from the peripherals. A timer that captures on an input channel also gets its pin, which
+// keeps its alternate function when the capture pin is dropped.
+#[cfg(not(any(feature = "sof-timer-tim5", feature = "sof-timer-tim9", feature = "sof-timer-tim11")))]
+macro_rules! take_sof_timer {
+    ($p:ident) => {
+        $p.TIM2
+    };
+}
+#[cfg(feature = "sof-timer-tim5")]
+macro_rules! take_sof_timer {
+    ($p:ident) => {{
+        let _: embassy_stm32::timer::input_capture::CapturePin<'_, $crate::board::SofTimer, embassy_stm32::timer::Ch1> =
+            embassy_stm32::timer::input_capture::CapturePin::new_ch1($p.PA0, embassy_stm32::gpio::Pull::None);
+        $p.TIM5
+    }};
+}
+#[cfg(feature = "sof-timer-tim9")]
+macro_rules! take_sof_timer {
+    ($p:ident) => {{
+        let _: embassy_stm32::timer::input_capture::CapturePin<'_, $crate::board::SofTimer, embassy_stm32::timer::Ch1> =
+            embassy_stm32::timer::input_capture::CapturePin::new_ch1($p.PA2, embassy_stm32::gpio::Pull::None);
+        $p.TIM9
+    }};
+}
+#[cfg(feature = "sof-timer-tim11")]
+macro_rules! take_sof_timer {
+    ($p:ident) => {{
+        let _: embassy_stm32::timer::input_capture::CapturePin<'_, $crate::board::SofTimer, embassy_stm32::timer::Ch1> =
+            embassy_stm32::timer::input_capture::CapturePin::new_ch1($p.PB9, embassy_stm32::gpio::Pull::None);
+        $p.TIM11
+    }};
+}
+
+#[cfg(feature = "board-custom")]
+mod custom;
+#[cfg(feature = "board-custom")]
+pub use custom::*;
+
+#[cfg(feature = "board-f4-discovery")]
+mod f4_discovery;
+#[cfg(feature = "board-f4-discovery")]
+pub use f4_discovery::*;
+
+#[cfg(feature = "board-nucleo")]
+mod nucleo;
+#[cfg(feature = "board-nucleo")]
+pub use nucleo::*;
+
+#[cfg(feature = "board-hs")]
+mod hs;
+#[cfg(feature = "board-hs")]
+pub use hs::*;
+
+// Full-speed USB with the internal PHY, or high-speed USB with an external ULPI PHY.
+#[cfg(not(feature = "usb-high-speed"))]
+pub type UsbPeripheral = peripherals::USB_OTG_FS;
+#[cfg(feature = "usb-high-speed")]
+pub type UsbPeripheral = peripherals::USB_OTG_HS;
+
+// Front-panel pins, either on the MCU or on a GPIO expander.
+#[cfg(not(feature = "front-panel-expander"))]
+pub type StatusLed = Output<'static>;
+#[cfg(not(feature = "front-panel-expander"))]
+pub type WakeupButton = ExtiInput<'static>;
+#[cfg(feature = "front-panel-expander")]
+pub type StatusLed = crate::expander_io::ExpanderOutput;
+#[cfg(feature = "front-panel-expander")]
+pub type WakeupButton = crate::expander_io::ExpanderInput;
+
+// Addressable status LED, driven by SPI.
+#[cfg(feature = "status-ws2812")]
+pub type StatusIndicatorSpi = embassy_stm32::spi::Spi<'static, embassy_stm32::mode::Async>;
+
+// Boards capture the USB SOF with TIM2, triggered internally, by default. Boards without a free TIM2 select TIM5, TIM9,
+// or TIM11 instead, which capture on channel 1, wired to the SOF output pin (see `sof_capture`). `main` binds the
+// interrupt vector of the selected timer.
+#[cfg(not(any(feature = "sof-timer-tim5", feature = "sof-timer-tim9", feature = "sof-timer-tim11")))]
+pub type SofTimer = peripherals::TIM2;
+#[cfg(feature = "sof-timer-tim5")]
+pub type SofTimer = peripherals::TIM5;
+#[cfg(feature = "sof-timer-tim9")]
+pub type SofTimer = peripherals::TIM9;
+#[cfg(feature = "sof-timer-tim11")]
+pub type SofTimer = peripherals::TIM11;
+
+#[cfg(all(
+    not(any(feature = "sof-timer-tim5", feature = "sof-timer-tim9", feature = "sof-timer-tim11")),
+    not(feature = "usb-high-speed")
+))]
+pub const SOF_SOURCE: SofSource = SofSource::Itr1(UsbSof::OtgFs);
+#[cfg(all(
+    not(any(feature = "sof-timer-tim5", feature = "sof-timer-tim9", feature = "sof-timer-tim11")),
+    feature = "usb-high-speed"
+))]
+pub const SOF_SOURCE: SofSource = SofSource::Itr1(UsbSof::OtgHs);
+#[cfg(any(feature = "sof-timer-tim5", feature = "sof-timer-tim9", feature = "sof-timer-tim11"))]
+pub const SOF_SOURCE: SofSource = SofSource::Input(embassy_stm32::timer::Channel::Ch1);
+
+#[cfg(not(feature = "usb-high-speed"))]
+bind_interrupts!(pub struct Irqs {
+    OTG_FS => usb::InterruptHandler<peripherals::USB_OTG_FS>;
+    I2C1_EV => i2c::EventInterruptHandler<peripherals::I2C1>;
+    I2C1_ER => i2c::ErrorInterruptHandler<peripherals::I2C1>;
+});
+
+#[cfg(feature = "usb-high-speed")]
+bind_interrupts!(pub struct Irqs {
+    OTG_HS => usb::InterruptHandler<peripherals::USB_OTG_HS>;
+    I2C1_EV => i2c::EventInterruptHandler<peripherals::I2C1>;
+    I2C1_ER => i2c::ErrorInterruptHandler<peripherals::I2C1>;
+});
+
+/// Board peripherals, as used by the application.
+pub struct Board {
+    pub usb_driver: UsbDriver,
+
+    // Timer for capturing USB SOF, for feedback calculation.
+    pub sof_timer: SofTimer,
+    pub watchdog: peripherals::IWDG,
+
+    // Internal flash, for persistent settings.
+    pub flash: peripherals::FLASH,
+
+    // CRC unit, for checking the firmware image.
+    pub crc: peripherals::CRC,
+
+    // External flash bus and chip select.
+    #[cfg(feature = "spi-flash")]
+    pub spi_flash: (
+        embassy_stm32::spi::Spi<'static, embassy_stm32::mode::Async>,
+        Output<'static>,
+    ),
+
+    // Senses the external supply, high while it is present.
+    #[cfg(feature = "power-detect")]
+    pub supply_sense: Input<'static>,
+
+    pub i2s: I2S<'static, u16>,
+
+    // I2S output of the second zone's DAC.
+    #[cfg(feature = "dual-zone")]
+    pub zone_2_i2s: I2S<'static, u16>,
+
+    pub i2c: I2cPeripheral,
+    pub status_led: StatusLed,
+    pub wakeup_button: WakeupButton,
+
+    // Data line of the addressable status LED.
+    #[cfg(feature = "status-ws2812")]
+    pub status_indicator: StatusIndicatorSpi,
+
+    // Timer that captures the IR receiver's edges.
+    #[cfg(feature = "ir-remote")]
+    pub ir_timer: IrTimer,
+
+    // Analog stereo input, sampled by the ADC.
+    #[cfg(feature = "aux-input")]
+    pub aux_input: crate::aux_input::AuxInput,
+
+    // External S/PDIF receiver, as I2S master.
+    #[cfg(feature = "i2s-input")]
+    pub i2s_input: crate::i2s_input::I2sInput,
+
+    // S/PDIF transmitter, which mirrors the I2S output.
+    #[cfg(feature = "spdif-output")]
+    pub spdif_output: crate::spdif::SpdifOutput,
+
+    // Transmitter of the stereo link towards the secondary unit.
+    #[cfg(feature = "stereo-link-primary")]
+    pub link_tx: embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>,
+
+    // Receiver of the stereo link from the primary unit.
+    #[cfg(feature = "stereo-link-secondary")]
+    pub link_rx: embassy_stm32::usart::RingBufferedUartRx<'static>,
+
+    // Transmitter and receiver of the external front-end's control link.
+    #[cfg(feature = "uart-control")]
+    pub control_uart: (
+        embassy_stm32::usart::UartTx<'static, embassy_stm32::mode::Async>,
+        embassy_stm32::usart::RingBufferedUartRx<'static>,
+    ),
+
+    // Quadrature inputs of the volume encoder.
+    #[cfg(feature = "rotary-encoder")]
+    pub encoder: (ExtiInput<'static>, ExtiInput<'static>),
+
+    // Interrupt line of the front-panel GPIO expander.
+    #[cfg(feature = "front-panel-expander")]
+    pub expander_interrupt: ExtiInput<'static>,
+
+    // ADC and input of the battery voltage divider.
+    #[cfg(feature = "battery-monitor")]
+    pub battery_sense: (peripherals::ADC1, BatterySensePin),
+
+    // PWM output and tachometer input of the cooling fan.
+    #[cfg(feature = "fan-control")]
+    pub fan: (
+        embassy_stm32::timer::simple_pwm::SimplePwm<'static, FanTimer>,
+        ExtiInput<'static>,
+    ),
+    pub output_control: OutputControl,
+}
+
+// Buffer for all USB OUT endpoints: control, feedback, and audio stream.
+fn usb_ep_out_buffer() -> &'static mut [u8] {
+    static EP_OUT_BUFFER: StaticCell<[u8; USB_EP_OUT_BUFFER_SIZE]> = StaticCell::new();
+    EP_OUT_BUFFER.init([0; USB_EP_OUT_BUFFER_SIZE])
+}
+
+fn usb_config() -> usb::Config {
+    let mut usb_config = usb::Config::default();
+    usb_config.vbus_detection = true;
+
+    usb_config
+}
+
+// I2S DMA ring buffer, shared by all boards.
+fn i2s_buffer() -> &'static mut [u16; I2S_BUFFER_SIZE] {
+    static I2S_BUFFER: StaticCell<[u16; I2S_BUFFER_SIZE]> = StaticCell::new();
+    I2S_BUFFER.init([0; I2S_BUFFER_SIZE])
+}
+
+// DMA ring buffer of the second zone's I2S output.
+#[cfg(feature = "dual-zone")]
+fn zone_2_i2s_buffer() -> &'static mut [u16; I2S_BUFFER_SIZE] {
+    static I2S_BUFFER: StaticCell<[u16; I2S_BUFFER_SIZE]> = StaticCell::new();
+    I2S_BUFFER.init([0; I2S_BUFFER_SIZE])
+}
+
+// I2S output configuration, 32 bit frames.
+fn i2s_config(master_clock: bool) -> embassy_stm32::i2s::Config {
+    let mut i2s_config = embassy_stm32::i2s::Config::default();
+    // A stereo link's secondary takes the clocks from its primary.
+    i2s_config.mode = if cfg!(feature = "stereo-link-secondary") {
+        embassy_stm32::i2s::Mode::Slave
+    } else {
+        embassy_stm32::i2s::Mode::Master
+    };
+    i2s_config.standard = embassy_stm32::i2s::Standard::Philips;
+    i2s_config.format = embassy_stm32::i2s::Format::Data32Channel32;
+    i2s_config.master_clock = master_clock;
+
+    i2s_config
+}
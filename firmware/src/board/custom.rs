@@ -0,0 +1,333 @@
+// Custom board (STM32F401CC or STM32F411CE) with four TAS2780 amplifiers.
+use defmt::unwrap;
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+#[cfg(feature = "aux-input")]
+use embassy_stm32::gpio::Flex;
+#[cfg(feature = "power-detect")]
+use embassy_stm32::gpio::Input;
+#[cfg(feature = "fan-control")]
+use embassy_stm32::gpio::OutputType;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::time::Hertz;
+#[cfg(feature = "ir-remote")]
+use embassy_stm32::timer::{input_capture::CapturePin, Ch3};
+#[cfg(feature = "fan-control")]
+use embassy_stm32::timer::{
+    low_level::CountingMode,
+    simple_pwm::{PwmPin, SimplePwm},
+};
+
+#[cfg(any(feature = "stereo-link", feature = "uart-control"))]
+use embassy_stm32::usart;
+use embassy_stm32::{i2c, i2s, pac, spi, usb, Peripherals};
+
+#[cfg(feature = "dual-zone")]
+use super::zone_2_i2s_buffer;
+use super::{i2s_buffer, i2s_config, usb_config, usb_ep_out_buffer, Board, Irqs};
+use crate::buttons::{Action, Press, SINGLE_BUTTON_ACTIONS};
+#[cfg(feature = "front-panel-expander")]
+use crate::expander_io::{ExpanderInput, ExpanderOutput};
+#[cfg(feature = "front-panel-expander")]
+use crate::gpio_expander::Variant;
+use crate::i2c_recovery::I2cPins;
+#[cfg(feature = "i2s-input")]
+use crate::i2s_input::{I2sInputPins, I2sPin};
+use crate::power_sequence::PowerSequence;
+use crate::*;
+
+pub const I2S_SPI: pac::spi::Spi = pac::SPI2;
+pub const MCLK_ENABLED: bool = cfg!(feature = "mclk-output");
+
+// The second zone's DAC on I2S3, which has no MCLK output.
+#[cfg(feature = "dual-zone")]
+pub const ZONE_2_I2S_SPI: pac::spi::Spi = pac::SPI3;
+
+// I2C1 pins, for bus recovery.
+pub const I2C_PINS: I2cPins = I2cPins {
+    port: pac::GPIOB,
+    scl: 6,
+    sda: 7,
+};
+
+// Front-panel GPIO expander, with the status LED on pin 0 and the wake-up button on pin 8.
+#[cfg(feature = "front-panel-expander")]
+pub const EXPANDER_ADDRESS: u8 = 0x20;
+#[cfg(feature = "front-panel-expander")]
+pub const EXPANDER_VARIANT: Variant = Variant::Pca9555;
+
+// IR receiver output on PB8 (TIM4 channel 3).
+#[cfg(feature = "ir-remote")]
+pub type IrTimer = embassy_stm32::peripherals::TIM4;
+#[cfg(feature = "ir-remote")]
+pub const IR_CHANNEL: embassy_stm32::timer::Channel = embassy_stm32::timer::Channel::Ch3;
+
+// Fan PWM on PB9 (TIM11 channel 1).
+#[cfg(feature = "fan-control")]
+pub type FanTimer = embassy_stm32::peripherals::TIM11;
+
+// Battery of two Li-ion cells in series, sensed on PA3 (ADC1_IN3) through a divider by three.
+#[cfg(feature = "battery-monitor")]
+pub type BatterySensePin = embassy_stm32::peripherals::PA3;
+#[cfg(feature = "battery-monitor")]
+pub const BATTERY_CELL_COUNT: u32 = 2;
+#[cfg(feature = "battery-monitor")]
+pub const BATTERY_DIVIDER_RATIO: u32 = 3;
+
+// I2S3 lines of the external S/PDIF receiver (alternate function 6): WS on PA15, CK on PB3, and SD on PB5.
+#[cfg(feature = "i2s-input")]
+pub const I2S_INPUT_PINS: I2sInputPins = I2sInputPins {
+    ws: I2sPin {
+        port: pac::GPIOA,
+        pin: 15,
+    },
+    ck: I2sPin {
+        port: pac::GPIOB,
+        pin: 3,
+    },
+    sd: I2sPin {
+        port: pac::GPIOB,
+        pin: 5,
+    },
+    af: 6,
+};
+
+// The receiver's unlock output (e.g. DIR9001 ERROR) on PA8 is high, while it is not locked.
+#[cfg(feature = "i2s-input")]
+pub const I2S_INPUT_UNLOCK_ACTIVE_LOW: bool = false;
+
+pub const STATUS_LED_ACTIVE_LOW: bool = true;
+#[cfg(not(any(feature = "aux-input", feature = "i2s-input")))]
+pub const WAKEUP_BUTTON_ACTIONS: &[(Press, Action)] = SINGLE_BUTTON_ACTIONS;
+
+// A double press cycles through the sources.
+#[cfg(any(feature = "aux-input", feature = "i2s-input"))]
+pub const WAKEUP_BUTTON_ACTIONS: &[(Press, Action)] = &[
+    (Press::Short, Action::NextPreset),
+    (Press::Double, Action::NextSource),
+    (Press::Long, Action::ToggleMute),
+    (Press::Triple, Action::EnterBootloader),
+];
+pub const WAKEUP_BUTTON_ACTIVE_LOW: bool = true;
+
+pub fn config() -> embassy_stm32::Config {
+    use embassy_stm32::rcc::*;
+
+    // Uses a 24.576 MHz external oscillator.
+    chip::clock_config(
+        Hse {
+            freq: Hertz(25_000_000),
+            mode: HseMode::Bypass,
+        },
+        PllPreDiv::DIV25,
+    )
+}
+
+// TAS2780 amplifiers, which ramp their volume when muting and unmuting.
+pub const POWER_SEQUENCE: PowerSequence = PowerSequence {
+    enable_ms: 2,
+    settle_ms: 5,
+    mute_ms: 10,
+    discharge_ms: 5,
+};
+
+/// Shutdown and fault lines of the amplifiers.
+pub struct OutputControl {
+    amp_shutdown: Output<'static>,
+    amp_fault: ExtiInput<'static>,
+}
+
+// SPI mode 0 for the external flash.
+#[cfg(feature = "spi-flash")]
+fn spi_flash_config() -> spi::Config {
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(21_000_000);
+
+    config
+}
+
+// SPI clock for the WS2812 status LED, three SPI bits per data bit. APB1 at 42 or 48 MHz, divided by 16.
+#[cfg(feature = "status-ws2812")]
+fn status_indicator_config() -> spi::Config {
+    let mut config = spi::Config::default();
+    config.frequency = Hertz(3_000_000);
+
+    config
+}
+
+// DMA ring buffer of the stereo link's receiver, about 3 ms at the link's baud rate.
+#[cfg(feature = "stereo-link-secondary")]
+fn link_rx_buffer() -> &'static mut [u8] {
+    static BUFFER: static_cell::StaticCell<[u8; 1024]> = static_cell::StaticCell::new();
+    BUFFER.init([0; 1024])
+}
+
+// DMA ring buffer of the control link's receiver.
+#[cfg(feature = "uart-control")]
+fn control_rx_buffer() -> &'static mut [u8] {
+    static BUFFER: static_cell::StaticCell<[u8; 256]> = static_cell::StaticCell::new();
+    BUFFER.init([0; 256])
+}
+
+pub fn init(p: Peripherals) -> Board {
+    #[cfg(not(feature = "mclk-output"))]
+    let i2s = i2s::I2S::new_txonly_nomck(
+        p.SPI2,
+        p.PB15,
+        p.PB12,
+        p.PB10,
+        p.DMA1_CH4,
+        i2s_buffer(),
+        Hertz(SAMPLE_RATE_HZ),
+        i2s_config(false),
+    );
+
+    // For an external DAC, which requires MCLK.
+    #[cfg(feature = "mclk-output")]
+    let i2s = i2s::I2S::new_txonly(
+        p.SPI2,
+        p.PB15,
+        p.PB12,
+        p.PB10,
+        p.PC6,
+        p.DMA1_CH4,
+        i2s_buffer(),
+        Hertz(SAMPLE_RATE_HZ),
+        i2s_config(true),
+    );
+
+    // I2C1 transmits on DMA1 stream 7, which leaves stream 6 to USART2 (e.g. for the stereo link).
+    let i2c = i2c::I2c::new(
+        p.I2C1,
+        p.PB6,
+        p.PB7,
+        Irqs,
+        p.DMA1_CH7,
+        p.DMA1_CH0,
+        Hertz(400_000),
+        Default::default(),
+    );
+
+    // The pin keeps its alternate function, when the capture pin is dropped.
+    #[cfg(feature = "ir-remote")]
+    let _: CapturePin<'_, IrTimer, Ch3> = CapturePin::new_ch3(p.PB8, Pull::Up);
+
+    Board {
+        usb_driver: usb::Driver::new_fs(p.USB_OTG_FS, Irqs, p.PA12, p.PA11, usb_ep_out_buffer(), usb_config()),
+        sof_timer: take_sof_timer!(p),
+        watchdog: p.IWDG,
+        flash: p.FLASH,
+        crc: p.CRC,
+        #[cfg(feature = "spi-flash")]
+        spi_flash: (
+            spi::Spi::new(p.SPI1, p.PA5, p.PA7, p.PA6, p.DMA2_CH3, p.DMA2_CH0, spi_flash_config()),
+            Output::new(p.PA4, Level::High, Speed::VeryHigh),
+        ),
+        // A divider from the external supply, which is pulled down without it.
+        #[cfg(feature = "power-detect")]
+        supply_sense: Input::new(p.PB9, Pull::Down),
+        i2s,
+        // SD on PB5, WS on PA15, and CK on PB3.
+        #[cfg(feature = "dual-zone")]
+        zone_2_i2s: i2s::I2S::new_txonly_nomck(
+            p.SPI3,
+            p.PB5,
+            p.PA15,
+            p.PB3,
+            p.DMA1_CH5,
+            zone_2_i2s_buffer(),
+            Hertz(SAMPLE_RATE_HZ),
+            i2s_config(false),
+        ),
+        i2c,
+        #[cfg(not(feature = "front-panel-expander"))]
+        status_led: Output::new(p.PC13, Level::High, Speed::Low),
+        #[cfg(not(feature = "front-panel-expander"))]
+        wakeup_button: ExtiInput::new(p.PA0, p.EXTI0, Pull::Up),
+        #[cfg(feature = "front-panel-expander")]
+        status_led: ExpanderOutput::new(0, Level::High),
+        #[cfg(feature = "front-panel-expander")]
+        wakeup_button: ExpanderInput::new(8),
+        #[cfg(feature = "status-ws2812")]
+        status_indicator: spi::Spi::new_txonly_nosck(p.SPI3, p.PB5, p.DMA1_CH5, status_indicator_config()),
+        #[cfg(feature = "ir-remote")]
+        ir_timer: p.TIM4,
+        // Left and right channel on PA1 (ADC1_IN1) and PA2 (ADC1_IN2).
+        #[cfg(feature = "aux-input")]
+        aux_input: aux_input::AuxInput::new(p.ADC1, p.TIM3, p.DMA2_CH4, [Flex::new(p.PA1), Flex::new(p.PA2)], [1, 2]),
+        // The I2S lines are configured by the driver, whose pins are taken here.
+        #[cfg(feature = "i2s-input")]
+        i2s_input: {
+            let _ = (p.PA15, p.PB3, p.PB5);
+            i2s_input::I2sInput::new(
+                p.SPI3,
+                p.DMA1_CH2,
+                I2S_INPUT_PINS,
+                ExtiInput::new(p.PA8, p.EXTI8, Pull::None),
+                I2S_INPUT_UNLOCK_ACTIVE_LOW,
+            )
+        },
+        #[cfg(feature = "stereo-link-primary")]
+        link_tx: unwrap!(usart::UartTx::new(p.USART2, p.PA2, p.DMA1_CH6, link::uart_config())),
+        #[cfg(feature = "stereo-link-secondary")]
+        link_rx: unwrap!(usart::UartRx::new(
+            p.USART2,
+            link::LinkIrqs,
+            p.PA3,
+            p.DMA1_CH5,
+            link::uart_config()
+        ))
+        .into_ring_buffered(link_rx_buffer()),
+        #[cfg(feature = "uart-control")]
+        control_uart: {
+            let uart = unwrap!(usart::Uart::new(
+                p.USART2,
+                p.PA3,
+                p.PA2,
+                uart_control::ControlIrqs,
+                p.DMA1_CH6,
+                p.DMA1_CH5,
+                uart_control::uart_config()
+            ));
+            let (tx, rx) = uart.split();
+            (tx, rx.into_ring_buffered(control_rx_buffer()))
+        },
+        #[cfg(feature = "rotary-encoder")]
+        encoder: (
+            ExtiInput::new(p.PB3, p.EXTI3, Pull::Up),
+            ExtiInput::new(p.PB4, p.EXTI4, Pull::Up),
+        ),
+        #[cfg(feature = "front-panel-expander")]
+        expander_interrupt: ExtiInput::new(p.PB2, p.EXTI2, Pull::Up),
+        #[cfg(feature = "battery-monitor")]
+        battery_sense: (p.ADC1, p.PA3),
+        // The fan's tachometer output is open drain.
+        #[cfg(feature = "fan-control")]
+        fan: (
+            SimplePwm::new(
+                p.TIM11,
+                Some(PwmPin::new_ch1(p.PB9, OutputType::PushPull)),
+                None,
+                None,
+                None,
+                Hertz(fan::PWM_FREQUENCY_HZ),
+                CountingMode::EdgeAlignedUp,
+            ),
+            ExtiInput::new(p.PB14, p.EXTI14, Pull::Up),
+        ),
+        output_control: OutputControl {
+            // Amplifiers are held in shutdown, until configured by the amplifier task.
+            amp_shutdown: Output::new(p.PB0, Level::Low, Speed::Low),
+            // Shared open-drain IRQ line of the amplifiers.
+            amp_fault: ExtiInput::new(p.PB1, p.EXTI1, Pull::Up),
+        },
+    }
+}
+
+pub fn spawn_output_control(spawner: Spawner, output_control: OutputControl, i2c_bus: &'static I2cBus) {
+    unwrap!(spawner.spawn(amplifier::control_task(
+        output_control.amp_shutdown,
+        output_control.amp_fault,
+        i2c_bus
+    )));
+}
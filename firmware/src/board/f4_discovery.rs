@@ -0,0 +1,99 @@
+// STM32F401C-DISCO (STM32F401VC) or STM32F411E-DISCO (STM32F411VE) board with its on-board CS43L22 DAC.
+use defmt::unwrap;
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{i2c, i2s, pac, usb, Peripherals};
+
+use super::{i2s_buffer, i2s_config, usb_config, usb_ep_out_buffer, Board, Irqs};
+use crate::buttons::{Action, Press, EVALUATION_BOARD_ACTIONS};
+use crate::i2c_recovery::I2cPins;
+use crate::power_sequence::PowerSequence;
+use crate::*;
+
+pub const I2S_SPI: pac::spi::Spi = pac::SPI3;
+pub const MCLK_ENABLED: bool = true;
+
+// I2C1 pins, for bus recovery.
+pub const I2C_PINS: I2cPins = I2cPins {
+    port: pac::GPIOB,
+    scl: 6,
+    sda: 9,
+};
+
+pub const STATUS_LED_ACTIVE_LOW: bool = false;
+pub const WAKEUP_BUTTON_ACTIONS: &[(Press, Action)] = EVALUATION_BOARD_ACTIONS;
+pub const WAKEUP_BUTTON_ACTIVE_LOW: bool = false;
+
+pub fn config() -> embassy_stm32::Config {
+    use embassy_stm32::rcc::*;
+
+    // Uses the on-board 8 MHz crystal.
+    chip::clock_config(
+        Hse {
+            freq: Hertz(8_000_000),
+            mode: HseMode::Oscillator,
+        },
+        PllPreDiv::DIV8,
+    )
+}
+
+// CS43L22 codec, whose headphone charge pump settles after power-up.
+pub const POWER_SEQUENCE: PowerSequence = PowerSequence {
+    enable_ms: 1,
+    settle_ms: 20,
+    mute_ms: 10,
+    discharge_ms: 10,
+};
+
+/// Reset line of the codec.
+pub struct OutputControl {
+    codec_reset: Output<'static>,
+}
+
+pub fn init(p: Peripherals) -> Board {
+    // The CS43L22 requires MCLK.
+    let i2s = i2s::I2S::new_txonly(
+        p.SPI3,
+        p.PC12,
+        p.PA4,
+        p.PC10,
+        p.PC7,
+        p.DMA1_CH5,
+        i2s_buffer(),
+        Hertz(SAMPLE_RATE_HZ),
+        i2s_config(true),
+    );
+
+    let i2c = i2c::I2c::new(
+        p.I2C1,
+        p.PB6,
+        p.PB9,
+        Irqs,
+        p.DMA1_CH6,
+        p.DMA1_CH0,
+        Hertz(100_000),
+        Default::default(),
+    );
+
+    Board {
+        usb_driver: usb::Driver::new_fs(p.USB_OTG_FS, Irqs, p.PA12, p.PA11, usb_ep_out_buffer(), usb_config()),
+        sof_timer: take_sof_timer!(p),
+        watchdog: p.IWDG,
+        flash: p.FLASH,
+        crc: p.CRC,
+        i2s,
+        i2c,
+        status_led: Output::new(p.PD14, Level::Low, Speed::Low),
+        wakeup_button: ExtiInput::new(p.PA0, p.EXTI0, Pull::None),
+        output_control: OutputControl {
+            // The codec is held in reset, until configured by the codec task.
+            codec_reset: Output::new(p.PD4, Level::Low, Speed::Low),
+        },
+    }
+}
+
+pub fn spawn_output_control(spawner: Spawner, output_control: OutputControl, i2c_bus: &'static I2cBus) {
+    unwrap!(spawner.spawn(codec::cs43l22::control_task(output_control.codec_reset, i2c_bus)));
+}
@@ -0,0 +1,130 @@
+// Custom high-speed board (STM32F446RE) with a USB3300 ULPI PHY and a TLV320AIC3204 codec.
+//
+// High-speed isochronous endpoints provide the bandwidth for multi-channel streams at high sample rates (e.g. eight
+// channels at 96 kHz), which do not fit into full-speed packets.
+use defmt::unwrap;
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{i2c, i2s, pac, usb, Peripherals};
+
+use super::{i2s_buffer, i2s_config, usb_config, usb_ep_out_buffer, Board, Irqs};
+use crate::buttons::{Action, Press, SINGLE_BUTTON_ACTIONS};
+use crate::i2c_recovery::I2cPins;
+use crate::power_sequence::PowerSequence;
+use crate::test_mode::TestModeDriver;
+use crate::*;
+
+pub const I2S_SPI: pac::spi::Spi = pac::SPI3;
+pub const MCLK_ENABLED: bool = true;
+
+// I2C1 pins, for bus recovery.
+pub const I2C_PINS: I2cPins = I2cPins {
+    port: pac::GPIOB,
+    scl: 8,
+    sda: 9,
+};
+
+// S/PDIF output on PC1 (SAI1_SD_A), e.g. to a coax transformer or TOSLINK transmitter.
+#[cfg(feature = "spdif-output")]
+const SPDIF_PIN: crate::spdif::SpdifPin = crate::spdif::SpdifPin {
+    port: pac::GPIOC,
+    pin: 1,
+    af: 6,
+};
+
+pub const STATUS_LED_ACTIVE_LOW: bool = true;
+pub const WAKEUP_BUTTON_ACTIONS: &[(Press, Action)] = SINGLE_BUTTON_ACTIONS;
+pub const WAKEUP_BUTTON_ACTIVE_LOW: bool = true;
+
+pub fn config() -> embassy_stm32::Config {
+    use embassy_stm32::rcc::*;
+
+    // Uses an 8 MHz crystal. The PHY has its own 24 MHz crystal, and provides the ULPI clock.
+    chip::clock_config(
+        Hse {
+            freq: Hertz(8_000_000),
+            mode: HseMode::Oscillator,
+        },
+        PllPreDiv::DIV8,
+    )
+}
+
+// TLV320AIC3204 codec, whose headphone drivers soft-step their power-up.
+pub const POWER_SEQUENCE: PowerSequence = PowerSequence {
+    enable_ms: 0,
+    settle_ms: 20,
+    mute_ms: 10,
+    discharge_ms: 10,
+};
+
+/// The codec has no control lines besides I2C.
+pub struct OutputControl;
+
+pub fn init(p: Peripherals) -> Board {
+    // ULPI uses most of port B, so that I2S is on SPI3. High-speed devices must support the electrical test modes.
+    let usb_driver = TestModeDriver::new(usb::Driver::new_hs_ulpi(
+        p.USB_OTG_HS,
+        Irqs,
+        p.PA5,
+        p.PC2,
+        p.PC3,
+        p.PC0,
+        p.PA3,
+        p.PB0,
+        p.PB1,
+        p.PB10,
+        p.PB11,
+        p.PB12,
+        p.PB13,
+        p.PB5,
+        usb_ep_out_buffer(),
+        usb_config(),
+    ));
+
+    let i2s = i2s::I2S::new_txonly(
+        p.SPI3,
+        p.PC12,
+        p.PA4,
+        p.PC10,
+        p.PC7,
+        p.DMA1_CH5,
+        i2s_buffer(),
+        Hertz(SAMPLE_RATE_HZ),
+        i2s_config(true),
+    );
+
+    let i2c = i2c::I2c::new(
+        p.I2C1,
+        p.PB8,
+        p.PB9,
+        Irqs,
+        p.DMA1_CH6,
+        p.DMA1_CH0,
+        Hertz(400_000),
+        Default::default(),
+    );
+
+    #[cfg(feature = "spdif-output")]
+    let spdif_output = crate::spdif::SpdifOutput::new(p.SAI1, p.DMA2_CH1, SPDIF_PIN);
+
+    Board {
+        usb_driver,
+        sof_timer: take_sof_timer!(p),
+        watchdog: p.IWDG,
+        flash: p.FLASH,
+        crc: p.CRC,
+        i2s,
+        i2c,
+        status_led: Output::new(p.PC13, Level::High, Speed::Low),
+        wakeup_button: ExtiInput::new(p.PA0, p.EXTI0, Pull::Up),
+        #[cfg(feature = "spdif-output")]
+        spdif_output,
+        output_control: OutputControl,
+    }
+}
+
+pub fn spawn_output_control(spawner: Spawner, _output_control: OutputControl, i2c_bus: &'static I2cBus) {
+    unwrap!(spawner.spawn(codec::tlv320aic3204::control_task(i2c_bus)));
+}
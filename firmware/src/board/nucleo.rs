@@ -0,0 +1,97 @@
+// NUCLEO-F401RE, NUCLEO-F411RE or NUCLEO-F446RE board with a TLV320AIC3204 DAC hat on the Arduino headers.
+use defmt::unwrap;
+use embassy_executor::Spawner;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{i2c, i2s, pac, usb, Peripherals};
+
+use super::{i2s_buffer, i2s_config, usb_config, usb_ep_out_buffer, Board, Irqs};
+use crate::buttons::{Action, Press, EVALUATION_BOARD_ACTIONS};
+use crate::i2c_recovery::I2cPins;
+use crate::power_sequence::PowerSequence;
+use crate::*;
+
+pub const I2S_SPI: pac::spi::Spi = pac::SPI2;
+pub const MCLK_ENABLED: bool = true;
+
+// I2C1 pins, for bus recovery.
+pub const I2C_PINS: I2cPins = I2cPins {
+    port: pac::GPIOB,
+    scl: 8,
+    sda: 9,
+};
+
+pub const STATUS_LED_ACTIVE_LOW: bool = false;
+pub const WAKEUP_BUTTON_ACTIONS: &[(Press, Action)] = EVALUATION_BOARD_ACTIONS;
+pub const WAKEUP_BUTTON_ACTIVE_LOW: bool = true;
+
+pub fn config() -> embassy_stm32::Config {
+    use embassy_stm32::rcc::*;
+
+    // Uses the 8 MHz clock output of the on-board ST-LINK.
+    chip::clock_config(
+        Hse {
+            freq: Hertz(8_000_000),
+            mode: HseMode::Bypass,
+        },
+        PllPreDiv::DIV8,
+    )
+}
+
+// TLV320AIC3204 codec, whose headphone drivers soft-step their power-up.
+pub const POWER_SEQUENCE: PowerSequence = PowerSequence {
+    enable_ms: 0,
+    settle_ms: 20,
+    mute_ms: 10,
+    discharge_ms: 10,
+};
+
+/// The codec hat has no control lines besides I2C.
+pub struct OutputControl;
+
+pub fn init(p: Peripherals) -> Board {
+    // The codec derives its clocks from MCLK.
+    let i2s = i2s::I2S::new_txonly(
+        p.SPI2,
+        p.PB15,
+        p.PB12,
+        p.PB13,
+        p.PC6,
+        p.DMA1_CH4,
+        i2s_buffer(),
+        Hertz(SAMPLE_RATE_HZ),
+        i2s_config(true),
+    );
+
+    // Arduino D15 (SCL) and D14 (SDA).
+    let i2c = i2c::I2c::new(
+        p.I2C1,
+        p.PB8,
+        p.PB9,
+        Irqs,
+        p.DMA1_CH6,
+        p.DMA1_CH0,
+        Hertz(400_000),
+        Default::default(),
+    );
+
+    Board {
+        usb_driver: usb::Driver::new_fs(p.USB_OTG_FS, Irqs, p.PA12, p.PA11, usb_ep_out_buffer(), usb_config()),
+        sof_timer: take_sof_timer!(p),
+        watchdog: p.IWDG,
+        flash: p.FLASH,
+        crc: p.CRC,
+        i2s,
+        i2c,
+        // Green user LED (LD2).
+        status_led: Output::new(p.PA5, Level::Low, Speed::Low),
+        // Blue user button (B1), with an external pull-up.
+        wakeup_button: ExtiInput::new(p.PC13, p.EXTI13, Pull::None),
+        output_control: OutputControl,
+    }
+}
+
+pub fn spawn_output_control(spawner: Spawner, _output_control: OutputControl, i2c_bus: &'static I2cBus) {
+    unwrap!(spawner.spawn(codec::tlv320aic3204::control_task(i2c_bus)));
+}
@@ -0,0 +1,85 @@
+// Entry into the ROM DFU bootloader (system memory), for reflashing over USB without a custom bootloader.
+//
+// A request is stored in RAM that is not initialized at startup, followed by a system reset. At the next boot, the
+// request is detected before any peripheral is configured, so that clocks and peripherals are in their reset state.
+// Interrupts are masked, system memory is remapped to address zero, and the bootloader is started with its own stack
+// pointer and reset vector.
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{compiler_fence, AtomicBool, Ordering::Relaxed, Ordering::SeqCst};
+use cortex_m::peripheral::{NVIC, SCB};
+use defmt::info;
+use embassy_stm32::pac;
+use embassy_time::Timer;
+
+use crate::*;
+
+// Vector table of the system memory bootloader.
+const SYSTEM_MEMORY: u32 = 0x1fff_0000;
+
+// Marks a pending bootloader request.
+const MAGIC: u32 = 0x4446_5521;
+
+// Time for completing the USB control transfer that requested the bootloader.
+const REQUEST_DELAY_MS: u64 = 50;
+
+// Set while a request waits for the control transfer, for status indication.
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+#[link_section = ".uninit.BOOTLOADER_REQUEST"]
+static mut BOOTLOADER_REQUEST: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Reset into the bootloader.
+pub fn request() -> ! {
+    info!("Entering bootloader");
+
+    // SAFETY: Only accessed here and at boot, before any task runs.
+    unsafe { (*addr_of_mut!(BOOTLOADER_REQUEST)).write(MAGIC) };
+    compiler_fence(SeqCst);
+
+    SCB::sys_reset();
+}
+
+/// Start the bootloader, if it was requested before the last reset. Must be called first thing at boot.
+pub fn check() {
+    // SAFETY: Called at boot, before any task runs. All bit patterns are valid.
+    let request = unsafe { (*addr_of_mut!(BOOTLOADER_REQUEST)).assume_init_mut() };
+
+    if *request != MAGIC {
+        return;
+    }
+    *request = 0;
+
+    cortex_m::interrupt::disable();
+
+    // SAFETY: Interrupts are disabled, and the application does not resume.
+    unsafe {
+        for index in 0..8 {
+            (*NVIC::PTR).icer[index].write(u32::MAX);
+            (*NVIC::PTR).icpr[index].write(u32::MAX);
+        }
+
+        // Map system memory to address zero, as if booting with BOOT0 set.
+        pac::RCC.apb2enr().modify(|w| w.set_syscfgen(true));
+        pac::SYSCFG.memrmp().modify(|w| w.set_mem_mode(1));
+
+        // The bootloader relies on interrupts, which are unmasked with all sources disabled.
+        cortex_m::interrupt::enable();
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32);
+    }
+}
+
+/// Whether the device is about to enter the bootloader.
+pub fn is_pending() -> bool {
+    PENDING.load(Relaxed)
+}
+
+/// Enters the bootloader after a request (e.g. via vendor request), once the request was acknowledged.
+#[embassy_executor::task]
+pub async fn request_task() {
+    BOOTLOADER_SIGNAL.wait().await;
+    PENDING.store(true, Relaxed);
+    Timer::after_millis(REQUEST_DELAY_MS).await;
+
+    request();
+}
@@ -0,0 +1,151 @@
+// Button input with debouncing and press classification, mapped to device actions by a per-board table.
+//
+// Presses are classified as short, double, or triple (with at most `MULTI_PRESS_WINDOW` between them), or long (held
+// for `LONG_PRESS_TIME`). Single presses are therefore reported after the multi-press window elapsed. While the host is
+// suspended, any press requests a remote wakeup instead of its action.
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{info, Format};
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::*;
+
+// Time for contacts to settle after an edge.
+const DEBOUNCE_TIME: Duration = Duration::from_millis(20);
+
+const LONG_PRESS_TIME: Duration = Duration::from_millis(800);
+const MULTI_PRESS_WINDOW: Duration = Duration::from_millis(300);
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Press {
+    Short,
+    Double,
+    Triple,
+    Long,
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Action {
+    NextPreset,
+    ToggleMute,
+    EnterBootloader,
+    NextSource,
+    ToggleNightMode,
+    ToggleBypass,
+}
+
+/// Actions of the wake-up button on boards with a single button.
+pub const SINGLE_BUTTON_ACTIONS: &[(Press, Action)] = &[
+    (Press::Short, Action::NextPreset),
+    (Press::Double, Action::ToggleNightMode),
+    (Press::Long, Action::ToggleMute),
+    (Press::Triple, Action::EnterBootloader),
+];
+
+/// Actions of the evaluation boards' user button, where a double press toggles the DSP bypass for A/B comparison.
+pub const EVALUATION_BOARD_ACTIONS: &[(Press, Action)] = &[
+    (Press::Short, Action::NextPreset),
+    (Press::Double, Action::ToggleBypass),
+    (Press::Long, Action::ToggleMute),
+    (Press::Triple, Action::EnterBootloader),
+];
+
+pub struct Button {
+    input: board::WakeupButton,
+    active_low: bool,
+}
+
+impl Button {
+    pub fn new(input: board::WakeupButton, active_low: bool) -> Self {
+        Self { input, active_low }
+    }
+
+    fn is_pressed(&self) -> bool {
+        self.input.is_high() != self.active_low
+    }
+
+    // Wait for a debounced change to the given state.
+    async fn wait_for(&mut self, pressed: bool) {
+        loop {
+            if pressed != self.active_low {
+                self.input.wait_for_rising_edge().await;
+            } else {
+                self.input.wait_for_falling_edge().await;
+            }
+
+            Timer::after(DEBOUNCE_TIME).await;
+
+            if self.is_pressed() == pressed {
+                return;
+            }
+        }
+    }
+
+    /// Wait for the next classified press.
+    pub async fn next_press(&mut self) -> Press {
+        self.wait_for(true).await;
+        let mut count = 1;
+
+        loop {
+            if with_timeout(LONG_PRESS_TIME, self.wait_for(false)).await.is_err() {
+                // Only a single press can be long, the last of a series is ignored.
+                if count == 1 {
+                    return Press::Long;
+                }
+
+                self.wait_for(false).await;
+                break;
+            }
+
+            if count == 3 || with_timeout(MULTI_PRESS_WINDOW, self.wait_for(true)).await.is_err() {
+                break;
+            }
+
+            count += 1;
+        }
+
+        match count {
+            1 => Press::Short,
+            2 => Press::Double,
+            _ => Press::Triple,
+        }
+    }
+}
+
+fn perform(action: Action) {
+    match action {
+        Action::NextPreset => preset::select_next(),
+        Action::ToggleMute => trim::toggle_mute(),
+        Action::EnterBootloader => BOOTLOADER_SIGNAL.signal(()),
+        Action::NextSource => source::select_next(),
+        Action::ToggleNightMode => night_mode::toggle(),
+        Action::ToggleBypass => bypass::toggle(),
+    }
+}
+
+/// Performs the actions of a button, as given by its table.
+#[embassy_executor::task]
+pub async fn button_task(input: board::WakeupButton, active_low: bool, actions: &'static [(Press, Action)]) {
+    let mut button = Button::new(input, active_low);
+
+    loop {
+        let press = button.next_press().await;
+
+        if USB_IS_SUSPENDED.load(Relaxed) {
+            REMOTE_WAKEUP_SIGNAL.signal(());
+            continue;
+        }
+
+        // A press only wakes the device from sleep.
+        if sleep_timer::activity() {
+            continue;
+        }
+
+        match actions.iter().find(|(mapped, _)| *mapped == press) {
+            Some(&(_, action)) => {
+                info!("Button press {}: {}", press, action);
+                perform(action);
+            }
+            None => log_debug!("Button press {} without action", press),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// DSP bypass: plays the received samples without the DSP chain, for comparing presets and equalizers against the
+// unprocessed signal, and for verifying that the chain is transparent.
+//
+// Toggling crossfades between the chain's output and the unprocessed samples, so that it does not click. The chain
+// keeps running while bypassed, such that its filters are settled, when it is played again. Only the loudness
+// compensation's headroom is applied to the unprocessed samples, since the amplifiers' volume makes up for it. Like
+// night mode, bypass is toggled by a button or a vendor request, and not persisted.
+use blus_core::gain::db_to_linear;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use defmt::info;
+
+use crate::dsp::Gain;
+use crate::*;
+
+// Duration of the crossfade.
+const CROSSFADE_MS: u32 = 20;
+const CROSSFADE_FRAMES: u32 = CROSSFADE_MS * SAMPLE_RATE_HZ / 1000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Relaxed)
+}
+
+/// Enable or disable bypass, which the pipeline follows with a crossfade.
+pub fn set(enabled: bool) {
+    info!("DSP bypass: {}", enabled);
+    ENABLED.store(enabled, Relaxed);
+}
+
+pub fn toggle() {
+    set(!is_enabled());
+}
+
+/// A linear crossfade between the DSP chain's output and the unprocessed samples, which follows the bypass flag.
+pub struct Crossfade {
+    // Frames into the crossfade, from zero (processed) to `CROSSFADE_FRAMES` (bypassed).
+    position: u32,
+    bypassed: bool,
+    // Gain of the unprocessed samples.
+    gain: Gain,
+}
+
+impl Crossfade {
+    pub fn new() -> Self {
+        let mut crossfade = Self {
+            position: 0,
+            bypassed: false,
+            gain: Gain::UNITY,
+        };
+        crossfade.reset();
+
+        crossfade
+    }
+
+    /// Jump to the bypass flag's state, e.g. at the start of a stream.
+    pub fn reset(&mut self) {
+        self.configure();
+        self.update();
+        self.position = if self.bypassed { CROSSFADE_FRAMES } else { 0 };
+    }
+
+    /// Follow the bypass flag, once per block.
+    pub fn update(&mut self) {
+        self.bypassed = is_enabled();
+    }
+
+    /// Follow a change of the loudness compensation's headroom, along with the DSP chain.
+    pub fn configure(&mut self) {
+        let headroom_db = loudness::headroom_db();
+        self.gain = if headroom_db > 0.0 {
+            Gain::from_linear(db_to_linear(-headroom_db))
+        } else {
+            Gain::UNITY
+        };
+    }
+
+    /// Advance the crossfade by a frame.
+    pub fn step(&mut self) {
+        if self.bypassed {
+            self.position = (self.position + 1).min(CROSSFADE_FRAMES);
+        } else {
+            self.position = self.position.saturating_sub(1);
+        }
+    }
+
+    /// Mix a processed and an unprocessed sample at the crossfade's position.
+    #[inline]
+    pub fn apply(&self, processed: i32, unprocessed: i32) -> i32 {
+        match self.position {
+            0 => processed,
+            CROSSFADE_FRAMES => self.gain.apply(unprocessed),
+            position => {
+                let mix = (position as u64 * i32::MAX as u64 / CROSSFADE_FRAMES as u64) as i32;
+                let unprocessed = Gain::from_q31(mix).apply(self.gain.apply(unprocessed));
+                Gain::from_q31(i32::MAX - mix)
+                    .apply(processed)
+                    .saturating_add(unprocessed)
+            }
+        }
+    }
+}
+
+impl Default for Crossfade {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,123 @@
+// The channel layout of the USB audio stream, selected at boot from the settings.
+//
+// The processing pipeline is stereo. Streams of other layouts are remixed into stereo frames as they are received, so
+// that one firmware binary can appear as a mono, stereo, 2.1, or 4.0 device. The layout determines the channel cluster
+// of the audio descriptors, so that a changed layout only takes effect at the next boot.
+//
+// The host mutes each channel of the stream separately. A side of the output is muted by the amplifiers' (or codec's)
+// mute, once all channels that feed it are muted. Channels that are muted while their side still plays are silenced
+// digitally, as they are remixed.
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::Format;
+use embassy_usb::class::uac1;
+
+use crate::*;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum ChannelLayout {
+    Mono = 0,
+    Stereo = 1,
+    /// Stereo with a low-frequency effects channel, which is mixed into both sides.
+    TwoPointOne = 2,
+    /// Front and surround pairs, which are mixed into one pair.
+    Quad = 3,
+}
+
+impl ChannelLayout {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Mono),
+            1 => Some(Self::Stereo),
+            2 => Some(Self::TwoPointOne),
+            3 => Some(Self::Quad),
+            _ => None,
+        }
+    }
+
+    /// The channels of the stream, in their order within a frame.
+    pub const fn channels(self) -> &'static [uac1::Channel] {
+        use uac1::Channel::*;
+
+        match self {
+            Self::Mono => &[CenterFront],
+            Self::Stereo => &[LeftFront, RightFront],
+            Self::TwoPointOne => &[LeftFront, RightFront, LowFrequencyEffects],
+            Self::Quad => &[LeftFront, RightFront, LeftSurround, RightSurround],
+        }
+    }
+
+    pub const fn channel_count(self) -> usize {
+        self.channels().len()
+    }
+
+    /// The isochronous endpoint's maximum packet size for this layout.
+    pub const fn max_packet_size(self) -> usize {
+        usb_max_packet_size(self.channel_count())
+    }
+
+    /// Remix a frame of the stream into a stereo frame, without the channels in the `muted` mask (by their index within
+    /// a frame). Mixed channels are attenuated by 6 dB, which avoids clipping.
+    pub fn to_stereo(self, frame: &[i32], muted: u8) -> [i32; INPUT_CHANNEL_COUNT] {
+        let mut unmuted = [0i32; MAX_USB_CHANNEL_COUNT];
+        for (index, (sample, &frame_sample)) in unmuted.iter_mut().zip(frame).enumerate() {
+            if muted & (1 << index) == 0 {
+                *sample = frame_sample;
+            }
+        }
+
+        match unmuted[..frame.len()] {
+            [mono] => [mono, mono],
+            [left, right] => [left, right],
+            [left, right, lfe] => [(left >> 1) + (lfe >> 1), (right >> 1) + (lfe >> 1)],
+            [left, right, left_surround, right_surround] => {
+                [(left >> 1) + (left_surround >> 1), (right >> 1) + (right_surround >> 1)]
+            }
+            _ => [0; INPUT_CHANNEL_COUNT],
+        }
+    }
+}
+
+static_assertions::const_assert!(ChannelLayout::Quad.channel_count() <= MAX_USB_CHANNEL_COUNT);
+
+/// The sides of the output (left and right) that a channel of the stream feeds.
+pub const fn sides(channel: uac1::Channel) -> &'static [usize] {
+    use uac1::Channel::*;
+
+    match channel {
+        LeftFront | LeftSurround => &[0],
+        RightFront | RightSurround => &[1],
+        _ => &[0, 1],
+    }
+}
+
+static ACTIVE: AtomicU8 = AtomicU8::new(DEFAULT_CHANNEL_LAYOUT as u8);
+
+// Channels of the stream that are silenced while remixing, by their index within a frame.
+static MUTED: AtomicU8 = AtomicU8::new(0);
+
+/// Activate the layout from the settings. Must be called before the audio descriptors are built.
+pub fn init(layout: ChannelLayout) -> ChannelLayout {
+    ACTIVE.store(layout as u8, Relaxed);
+    layout
+}
+
+/// The layout of the stream, as described to the host.
+pub fn active() -> ChannelLayout {
+    ChannelLayout::from_u8(ACTIVE.load(Relaxed)).unwrap_or(DEFAULT_CHANNEL_LAYOUT)
+}
+
+/// Select the layout for the next boot.
+pub fn select(layout: ChannelLayout) {
+    settings::modify(|settings| settings.channel_layout = layout);
+}
+
+/// Silence the channels in a mask (by their index within a frame) while remixing.
+pub fn set_muted(mask: u8) {
+    MUTED.store(mask, Relaxed);
+}
+
+/// The channels that are silenced while remixing.
+pub fn muted() -> u8 {
+    MUTED.load(Relaxed)
+}
@@ -0,0 +1,76 @@
+// Chimes for local events: the volume reaching its limit on a local adjustment, a preset change, and the amplifiers'
+// recovery from a fault (see `blus_core::dsp::chime`).
+//
+// The chimes are rendered by the build script, and stored as PCM in flash. The pipeline mixes them into the playing
+// stream after the DSP chain, at a reduced level, so that playback continues. While no source plays, the amplifiers
+// are in standby, and chimes are skipped. A new chime replaces a playing one.
+use blus_core::dsp::chime::Player;
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::info;
+
+pub use blus_core::dsp::chime::Alert;
+
+use crate::*;
+
+include!(concat!(env!("OUT_DIR"), "/chimes.rs"));
+
+// Level of chimes, relative to full scale.
+const LEVEL_DB: f32 = -18.0;
+
+// The requested alert's value, or none.
+const NONE: u8 = u8::MAX;
+static REQUESTED: AtomicU8 = AtomicU8::new(NONE);
+
+/// Request an alert's chime, which the pipeline plays from its next block.
+pub fn play(alert: Alert) {
+    info!("Chime: {}", alert as u8);
+    REQUESTED.store(alert as u8, Relaxed);
+}
+
+/// Mixes chimes into the output's frames.
+pub struct Mixer {
+    player: Player,
+    // The chime's sample for the current frame.
+    sample: i32,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            player: Player::new(SAMPLE_RATE_HZ, LEVEL_DB),
+            sample: 0,
+        }
+    }
+
+    /// Start a requested chime, once per block.
+    pub fn update(&mut self) {
+        if let Some(alert) = Alert::from_u8(REQUESTED.swap(NONE, Relaxed)) {
+            self.player.play(CHIMES[alert as usize]);
+            self.sample = self.player.next_sample();
+        }
+    }
+
+    /// Drop a requested or playing chime, e.g. when a stream starts.
+    pub fn reset(&mut self) {
+        REQUESTED.store(NONE, Relaxed);
+        self.player.play(&[]);
+        self.sample = 0;
+    }
+
+    /// Advance the chime by a frame.
+    pub fn step(&mut self) {
+        self.sample = self.player.next_sample();
+    }
+
+    /// Mix the chime into a sample of the current frame.
+    #[inline]
+    pub fn apply(&self, sample: i32) -> i32 {
+        sample.saturating_add(self.sample)
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
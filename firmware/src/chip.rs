@@ -0,0 +1,44 @@
+// Chip support: clock tree tables for the supported STM32F4 families, selected by cargo feature.
+//
+// All tables expect a 1 MHz PLL input clock, which boards derive from their HSE by means of the PLL pre-divider. They
+// provide the highest system clock that does not require over-drive, a 48 MHz USB clock, and a 172 MHz I2S clock for
+// the 48 kHz family of sample rates (-0.02 %), which is reconfigured at runtime - see `i2s_clock`.
+//
+// Every chip module provides the same items:
+// - `clock_config()`, the peripheral configuration for a given HSE and PLL pre-divider,
+// - `SYSCLK_HZ`, the resulting system clock frequency,
+// - `APB1_PRESCALER` and `APB1_PRESCALER_REDUCED`, the APB1 prescaler at full and half core clock (see `power`).
+use embassy_stm32::rcc::{Hse, Pll, PllMul, PllPreDiv, PllRDiv, PllSource};
+
+#[cfg(feature = "chip-f401")]
+mod f401;
+#[cfg(feature = "chip-f401")]
+pub use f401::*;
+
+#[cfg(feature = "chip-f411")]
+mod f411;
+#[cfg(feature = "chip-f411")]
+pub use f411::*;
+
+#[cfg(feature = "chip-f446")]
+mod f446;
+#[cfg(feature = "chip-f446")]
+pub use f446::*;
+
+// Settings that are common to all chips.
+fn base_config(hse: Hse, prediv: PllPreDiv) -> embassy_stm32::Config {
+    let mut peripheral_config = embassy_stm32::Config::default();
+    peripheral_config.rcc.hse = Some(hse);
+    peripheral_config.rcc.pll_src = PllSource::HSE;
+
+    // 172 MHz I2S clock, the setting of `i2s_clock::Family::Rate48k`.
+    peripheral_config.rcc.plli2s = Some(Pll {
+        prediv,
+        mul: PllMul::MUL344,
+        divp: None,
+        divq: None,
+        divr: Some(PllRDiv::DIV2),
+    });
+
+    peripheral_config
+}
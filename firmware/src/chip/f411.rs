@@ -0,0 +1,31 @@
+// STM32F411: 100 MHz maximum system clock. The I2S PLL has its own pre-divider.
+use embassy_stm32::pac::rcc::vals::Ppre;
+use embassy_stm32::rcc::*;
+
+use super::base_config;
+
+pub const SYSCLK_HZ: u32 = 96_000_000;
+
+pub const APB1_PRESCALER: Ppre = Ppre::DIV2;
+pub const APB1_PRESCALER_REDUCED: Ppre = Ppre::DIV1;
+
+pub fn clock_config(hse: Hse, prediv: PllPreDiv) -> embassy_stm32::Config {
+    let mut peripheral_config = base_config(hse, prediv);
+    peripheral_config.rcc.sys = Sysclk::PLL1_P;
+
+    peripheral_config.rcc.ahb_pre = AHBPrescaler::DIV1;
+    peripheral_config.rcc.apb1_pre = APBPrescaler::DIV2;
+    peripheral_config.rcc.apb2_pre = APBPrescaler::DIV1;
+
+    // 96 MHz system clock, 48 MHz USB clock. 100 MHz is not possible with an exact USB clock.
+    peripheral_config.rcc.mux.clk48sel = mux::Clk48sel::PLL1_Q;
+    peripheral_config.rcc.pll = Some(Pll {
+        prediv,
+        mul: PllMul::MUL384,
+        divp: Some(PllPDiv::DIV4),
+        divq: Some(PllQDiv::DIV8),
+        divr: None,
+    });
+
+    peripheral_config
+}
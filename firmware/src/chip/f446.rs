@@ -0,0 +1,47 @@
+// STM32F446: 180 MHz maximum system clock (168 MHz without over-drive). The USB clock is generated by the SAI PLL,
+// which leaves the main PLL free for the system clock. Also features a SPDIF transmitter (via SAI).
+use embassy_stm32::pac::rcc::vals::Ppre;
+use embassy_stm32::rcc::*;
+
+use super::base_config;
+
+pub const SYSCLK_HZ: u32 = 168_000_000;
+
+pub const APB1_PRESCALER: Ppre = Ppre::DIV4;
+pub const APB1_PRESCALER_REDUCED: Ppre = Ppre::DIV2;
+
+pub fn clock_config(hse: Hse, prediv: PllPreDiv) -> embassy_stm32::Config {
+    let mut peripheral_config = base_config(hse, prediv);
+    peripheral_config.rcc.sys = Sysclk::PLL1_P;
+
+    // APB1 is limited to 45 MHz, APB2 to 90 MHz.
+    peripheral_config.rcc.ahb_pre = AHBPrescaler::DIV1;
+    peripheral_config.rcc.apb1_pre = APBPrescaler::DIV4;
+    peripheral_config.rcc.apb2_pre = APBPrescaler::DIV2;
+
+    // 168 MHz system clock.
+    peripheral_config.rcc.pll = Some(Pll {
+        prediv,
+        mul: PllMul::MUL336,
+        divp: Some(PllPDiv::DIV2),
+        divq: Some(PllQDiv::DIV7),
+        divr: Some(PllRDiv::DIV2),
+    });
+
+    // 48 MHz USB clock.
+    peripheral_config.rcc.mux.clk48sel = mux::Clk48sel::PLLSAI1_P;
+    peripheral_config.rcc.pllsai = Some(Pll {
+        prediv,
+        mul: PllMul::MUL192,
+        divp: Some(PllPDiv::DIV4),
+        divq: None,
+        divr: None,
+    });
+
+    // SAI clock at half the I2S PLL's VCO clock, which is divided further for the S/PDIF output (see `spdif`).
+    if let Some(plli2s) = peripheral_config.rcc.plli2s.as_mut() {
+        plli2s.divq = Some(PllQDiv::DIV2);
+    }
+
+    peripheral_config
+}
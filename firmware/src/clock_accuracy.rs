@@ -0,0 +1,84 @@
+// Clock accuracy self-measurement: the local clock (HSE and PLLs) against the host's USB start-of-frame (SOF).
+//
+// The host sends a SOF every 1 ms (or every 125 us microframe at high speed), timed by its own clock. The DWT cycle
+// counter is sampled along with the SOF capture (or the frame number change), and the cycles over a window of one
+// minute are compared against the nominal system clock. Latency adds jitter to every sample, but does not accumulate,
+// so that the resolution is far below 1 ppm. The result goes to `stats`, and is positive if the local clock runs fast.
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::chip::SYSCLK_HZ;
+use crate::*;
+
+// Measurement window of one minute.
+const WINDOW_FRAMES: u32 = 60_000 * USB_FRAMES_PER_MS as u32;
+
+const CYCLES_PER_FRAME: u32 = SYSCLK_HZ / 1000 / USB_FRAMES_PER_MS as u32;
+
+#[derive(Clone, Copy)]
+struct State {
+    // Cycle count at the last recorded SOF, if a measurement runs.
+    last_cycles: Option<u32>,
+    elapsed_cycles: u64,
+    frames: u32,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            last_cycles: None,
+            elapsed_cycles: 0,
+            frames: 0,
+        }
+    }
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> = Mutex::new(Cell::new(State::new()));
+
+// Offset of the local clock, in parts per billion.
+fn offset_ppb(elapsed_cycles: u64, frames: u32) -> i32 {
+    let nominal_cycles = frames as u64 * CYCLES_PER_FRAME as u64;
+    ((elapsed_cycles as i64 - nominal_cycles as i64) * 1_000_000_000 / nominal_cycles as i64) as i32
+}
+
+/// Record the cycle count at a SOF, `frames` (micro)frames after the last recorded one.
+pub fn record(frames: u32, cycles: u32) {
+    let result = STATE.lock(|cell| {
+        let mut state = cell.get();
+        let mut result = None;
+
+        if let Some(last_cycles) = state.last_cycles {
+            let delta = cycles.wrapping_sub(last_cycles);
+            let nominal = frames * CYCLES_PER_FRAME;
+
+            // Missing SOFs (e.g. during suspend, or after a bus reset) restart the measurement, as well as a reduced
+            // core clock (see `power`).
+            if frames == 0 || delta.abs_diff(nominal) > nominal / 4 {
+                state = State::new();
+            } else {
+                state.elapsed_cycles += delta as u64;
+                state.frames += frames;
+            }
+        }
+
+        if state.frames >= WINDOW_FRAMES {
+            result = Some(offset_ppb(state.elapsed_cycles, state.frames));
+            state.elapsed_cycles = 0;
+            state.frames = 0;
+        }
+
+        state.last_cycles = Some(cycles);
+        cell.set(state);
+        result
+    });
+
+    if let Some(offset_ppb) = result {
+        stats::record_clock_offset(offset_ppb);
+    }
+}
+
+/// Discard the running measurement, e.g. when the host connection is lost.
+pub fn reset() {
+    STATE.lock(|cell| cell.set(State::new()));
+}
@@ -0,0 +1,104 @@
+// Common interface for audio codecs and DACs, which are controlled via I2C.
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{info, warn, Format};
+use embassy_futures::select::{select, Either};
+use embassy_usb::class::uac1::speaker::Volume;
+
+use crate::*;
+
+pub mod cs43l22;
+pub mod tlv320aic3204;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum PowerState {
+    // Lowest power consumption, the configuration is lost.
+    Off,
+    // Outputs are powered down, the configuration is retained.
+    Standby,
+    On,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait AudioCodec {
+    type Error;
+
+    /// Reset the codec and load its base configuration. Leaves the codec in standby.
+    async fn init(&mut self) -> Result<(), Self::Error>;
+
+    /// Reconfigure internal clock dividers for a new sample rate.
+    async fn set_sample_rate(&mut self, sample_rate_hz: u32) -> Result<(), Self::Error>;
+
+    /// Set the output volume of the left and right channel.
+    async fn set_volume(&mut self, left: Volume, right: Volume) -> Result<(), Self::Error>;
+
+    async fn set_mute(&mut self, mute: bool) -> Result<(), Self::Error>;
+
+    async fn set_power_state(&mut self, state: PowerState) -> Result<(), Self::Error>;
+}
+
+// Convert a volume to steps of the given size (in dB), clamped to the register's range. Registers hold the steps'
+// lower byte.
+pub(crate) fn volume_to_steps(db: f32, steps_per_db: f32, min: i16, max: i16) -> i16 {
+    let steps = db * steps_per_db;
+    let steps = if steps < 0.0 { steps - 0.5 } else { steps + 0.5 };
+
+    (steps as i32).clamp(min as i32, max as i32) as i16
+}
+
+// Power up the outputs muted, and unmute with the volume, once they settled.
+async fn wake<C: AudioCodec>(codec: &mut C, volume: (Volume, Volume)) -> Result<(), C::Error> {
+    codec.set_mute(true).await?;
+    codec.set_power_state(PowerState::On).await?;
+
+    board::POWER_SEQUENCE.wait_settled().await;
+    codec.set_volume(volume.0, volume.1).await
+}
+
+// Mute, and power down the outputs once the mute ramp completed.
+async fn sleep<C: AudioCodec>(codec: &mut C) -> Result<(), C::Error> {
+    codec.set_mute(true).await?;
+    board::POWER_SEQUENCE.wait_muted().await;
+
+    codec.set_power_state(PowerState::Standby).await?;
+    board::POWER_SEQUENCE.wait_discharged().await;
+    Ok(())
+}
+
+/// Configure a codec, and track volume and standby requests afterwards. Standby follows the board's power sequence
+/// (see `power_sequence`).
+pub async fn control<C: AudioCodec>(mut codec: C) -> ! {
+    if codec.init().await.is_err() || codec.set_sample_rate(SAMPLE_RATE_HZ).await.is_err() {
+        warn!("Failed to initialize codec");
+    }
+
+    let mut volume = (Volume::Muted, Volume::Muted);
+    let mut standby = true;
+
+    loop {
+        let result = match select(VOLUME_SIGNAL.wait(), AMP_STANDBY_SIGNAL.wait()).await {
+            Either::First(new_volume) => {
+                volume = new_volume;
+
+                if standby {
+                    Ok(())
+                } else {
+                    codec.set_volume(volume.0, volume.1).await
+                }
+            }
+            Either::Second(new_standby) => {
+                standby = new_standby || OUTPUT_INHIBITED.load(Relaxed);
+                info!("Codec standby: {}", standby);
+
+                if standby {
+                    sleep(&mut codec).await
+                } else {
+                    wake(&mut codec, volume).await
+                }
+            }
+        };
+
+        if result.is_err() {
+            warn!("Codec communication failed");
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Driver for the Cirrus Logic CS43L22 DAC with headphone amplifier, as found on the STM32F4-Discovery boards.
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{debug, error, Format};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_stm32::gpio::Output;
+use embassy_time::Timer;
+use embassy_usb::class::uac1::speaker::Volume;
+use embedded_hal_async::i2c::I2c;
+
+use super::{volume_to_steps, AudioCodec, PowerState};
+use crate::i2c_scan::{self, ExpectedDevice};
+use crate::self_test::{self, Check};
+use crate::{I2cBus, OUTPUT_INHIBITED};
+
+pub const DEFAULT_ADDRESS: u8 = 0x4a;
+
+mod reg {
+    pub const ID: u8 = 0x01;
+    pub const POWER_CTL_1: u8 = 0x02;
+    pub const POWER_CTL_2: u8 = 0x04;
+    pub const CLOCKING_CTL: u8 = 0x05;
+    pub const INTERFACE_CTL_1: u8 = 0x06;
+    pub const PLAYBACK_CTL_2: u8 = 0x0f;
+    pub const MASTER_VOLUME_A: u8 = 0x20;
+    pub const MASTER_VOLUME_B: u8 = 0x21;
+}
+
+// Chip ID in the upper five bits of the ID register.
+const CHIP_ID: u8 = 0b11100;
+
+// Values of the `POWER_CTL_1` register.
+const POWERED_DOWN: u8 = 0x01;
+const POWERED_UP: u8 = 0x9e;
+
+// Headphone outputs always on, speaker outputs always off.
+const HEADPHONE_ON: u8 = 0xaf;
+const OUTPUTS_OFF: u8 = 0xff;
+
+// Headphone mute bits for channel A and B.
+const HEADPHONE_MUTE: u8 = 0b1100_0000;
+
+// The master volume control has 0.5 dB steps, from -102 dB to +12 dB. Its register wraps around below -64 dB (0x80):
+// -64.5 dB is 0x7f, down to -102 dB at 0x34.
+const VOLUME_STEPS_PER_DB: f32 = 2.0;
+const VOLUME_MIN: i16 = -204;
+const VOLUME_MAX: i16 = 24;
+
+// Supported range of sample rates, with automatic clock ratio detection.
+const SAMPLE_RATE_MIN_HZ: u32 = 8_000;
+const SAMPLE_RATE_MAX_HZ: u32 = 96_000;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Error<E> {
+    I2c(E),
+    InvalidChipId(u8),
+    UnsupportedSampleRate(u32),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::I2c(e)
+    }
+}
+
+pub struct Cs43l22<I2C> {
+    i2c: I2C,
+    address: u8,
+    reset: Output<'static>,
+}
+
+impl<I2C: I2c> Cs43l22<I2C> {
+    pub fn new(i2c: I2C, address: u8, reset: Output<'static>) -> Self {
+        Self { i2c, address, reset }
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), I2C::Error> {
+        self.i2c.write(self.address, &[register, value]).await
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, I2C::Error> {
+        let mut value = [0u8];
+        self.i2c.write_read(self.address, &[register], &mut value).await?;
+
+        Ok(value[0])
+    }
+}
+
+impl<I2C: I2c> AudioCodec for Cs43l22<I2C> {
+    type Error = Error<I2C::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        self.reset.set_low();
+        Timer::after_millis(1).await;
+        self.reset.set_high();
+        Timer::after_millis(1).await;
+
+        let id = self.read_register(reg::ID).await?;
+        if id >> 3 != CHIP_ID {
+            return Err(Error::InvalidChipId(id));
+        }
+
+        self.write_register(reg::POWER_CTL_1, POWERED_DOWN).await?;
+
+        // Required initialization settings, see the datasheet, section 4.11.
+        self.write_register(0x00, 0x99).await?;
+        self.write_register(0x47, 0x80).await?;
+        let value = self.read_register(0x32).await?;
+        self.write_register(0x32, value | 0x80).await?;
+        self.write_register(0x32, value & !0x80).await?;
+        self.write_register(0x00, 0x00).await?;
+
+        // Automatic clock ratio detection, from MCLK.
+        self.write_register(reg::CLOCKING_CTL, 0x80).await?;
+
+        // Slave mode, I2S format, up to 24 bit data.
+        self.write_register(reg::INTERFACE_CTL_1, 0x04).await?;
+
+        self.set_mute(true).await?;
+        self.set_power_state(PowerState::Standby).await?;
+
+        debug!("CS43L22 at {:#x} initialized", self.address);
+
+        Ok(())
+    }
+
+    async fn set_sample_rate(&mut self, sample_rate_hz: u32) -> Result<(), Self::Error> {
+        // The clock ratio is detected automatically, only the range is checked.
+        if !(SAMPLE_RATE_MIN_HZ..=SAMPLE_RATE_MAX_HZ).contains(&sample_rate_hz) {
+            return Err(Error::UnsupportedSampleRate(sample_rate_hz));
+        }
+
+        Ok(())
+    }
+
+    async fn set_volume(&mut self, left: Volume, right: Volume) -> Result<(), Self::Error> {
+        let mut mute = 0u8;
+
+        for (volume, register, mute_bit) in [
+            (left, reg::MASTER_VOLUME_A, 1 << 6),
+            (right, reg::MASTER_VOLUME_B, 1 << 7),
+        ] {
+            match volume {
+                Volume::Muted => mute |= mute_bit,
+                Volume::DeciBel(db) => {
+                    let steps = volume_to_steps(db, VOLUME_STEPS_PER_DB, VOLUME_MIN, VOLUME_MAX);
+                    self.write_register(register, steps as u8).await?;
+                }
+            }
+        }
+
+        let value = self.read_register(reg::PLAYBACK_CTL_2).await?;
+        self.write_register(reg::PLAYBACK_CTL_2, (value & !HEADPHONE_MUTE) | mute)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_mute(&mut self, mute: bool) -> Result<(), Self::Error> {
+        let value = self.read_register(reg::PLAYBACK_CTL_2).await?;
+        let value = if mute {
+            value | HEADPHONE_MUTE
+        } else {
+            value & !HEADPHONE_MUTE
+        };
+        self.write_register(reg::PLAYBACK_CTL_2, value).await?;
+
+        Ok(())
+    }
+
+    async fn set_power_state(&mut self, state: PowerState) -> Result<(), Self::Error> {
+        match state {
+            PowerState::On => {
+                self.write_register(reg::POWER_CTL_2, HEADPHONE_ON).await?;
+                self.write_register(reg::POWER_CTL_1, POWERED_UP).await?;
+            }
+            PowerState::Standby => {
+                self.write_register(reg::POWER_CTL_1, POWERED_DOWN).await?;
+                self.write_register(reg::POWER_CTL_2, OUTPUTS_OFF).await?;
+            }
+            PowerState::Off => {
+                self.write_register(reg::POWER_CTL_1, POWERED_DOWN).await?;
+                self.reset.set_low();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[embassy_executor::task]
+pub async fn control_task(mut reset: Output<'static>, i2c_bus: &'static I2cBus) {
+    const EXPECTED_DEVICES: [ExpectedDevice; 1] = [ExpectedDevice {
+        name: "CS43L22",
+        address: DEFAULT_ADDRESS,
+        critical: true,
+    }];
+
+    // The codec only responds, when out of reset.
+    reset.set_high();
+    board::POWER_SEQUENCE.wait_enabled().await;
+
+    let devices_present = i2c_scan::check_devices(&mut I2cDevice::new(i2c_bus), &EXPECTED_DEVICES).await;
+    if !self_test::check(Check::I2cDevices, devices_present) {
+        error!("Outputs stay muted");
+        OUTPUT_INHIBITED.store(true, Relaxed);
+    }
+
+    let codec = Cs43l22::new(I2cDevice::new(i2c_bus), DEFAULT_ADDRESS, reset);
+    super::control(codec).await;
+}
@@ -0,0 +1,234 @@
+// Driver for the TI TLV320AIC3204 stereo codec, used as a DAC with headphone/line outputs.
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{debug, error, Format};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_time::Timer;
+use embassy_usb::class::uac1::speaker::Volume;
+use embedded_hal_async::i2c::I2c;
+
+use super::{volume_to_steps, AudioCodec, PowerState};
+use crate::i2c_scan::{self, ExpectedDevice};
+use crate::self_test::{self, Check};
+use crate::{mclk, I2cBus, OUTPUT_INHIBITED, SAMPLE_RATE_HZ};
+
+pub const DEFAULT_ADDRESS: u8 = 0x18;
+
+// Register addresses, as (page, register).
+mod reg {
+    pub const PAGE_SELECT: u8 = 0x00;
+
+    pub const SW_RESET: (u8, u8) = (0, 0x01);
+    pub const CLOCK_MUX: (u8, u8) = (0, 0x04);
+    pub const NDAC: (u8, u8) = (0, 0x0b);
+    pub const MDAC: (u8, u8) = (0, 0x0c);
+    pub const DOSR_MSB: (u8, u8) = (0, 0x0d);
+    pub const DOSR_LSB: (u8, u8) = (0, 0x0e);
+    pub const INTERFACE_1: (u8, u8) = (0, 0x1b);
+    pub const DAC_PROCESSING_BLOCK: (u8, u8) = (0, 0x3c);
+    pub const DAC_SETUP_1: (u8, u8) = (0, 0x3f);
+    pub const DAC_SETUP_2: (u8, u8) = (0, 0x40);
+    pub const DAC_LEFT_VOLUME: (u8, u8) = (0, 0x41);
+    pub const DAC_RIGHT_VOLUME: (u8, u8) = (0, 0x42);
+
+    pub const POWER_CONFIG: (u8, u8) = (1, 0x01);
+    pub const LDO_CONTROL: (u8, u8) = (1, 0x02);
+    pub const OUTPUT_POWER: (u8, u8) = (1, 0x09);
+    pub const COMMON_MODE: (u8, u8) = (1, 0x0a);
+    pub const HPL_ROUTING: (u8, u8) = (1, 0x0c);
+    pub const HPR_ROUTING: (u8, u8) = (1, 0x0d);
+    pub const HPL_GAIN: (u8, u8) = (1, 0x10);
+    pub const HPR_GAIN: (u8, u8) = (1, 0x11);
+    pub const HP_STARTUP: (u8, u8) = (1, 0x14);
+}
+
+// The digital volume control has 0.5 dB steps, from -63.5 dB to +24 dB.
+const VOLUME_STEPS_PER_DB: f32 = 2.0;
+const VOLUME_MIN: i16 = -127;
+const VOLUME_MAX: i16 = 48;
+
+// Fixed divider values, the DAC oversampling ratio is derived from the sample rate.
+const NDAC: u32 = 1;
+const MDAC: u32 = 2;
+
+// Valid range of the DAC oversampling ratio (DOSR) for the selected processing block.
+const DOSR_MIN: u32 = 32;
+const DOSR_MAX: u32 = 1024;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Error<E> {
+    I2c(E),
+    UnsupportedSampleRate(u32),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::I2c(e)
+    }
+}
+
+pub struct Tlv320aic3204<I2C> {
+    i2c: I2C,
+    address: u8,
+    mclk_hz: u32,
+    page: Option<u8>,
+}
+
+impl<I2C: I2c> Tlv320aic3204<I2C> {
+    /// Create a new driver instance. The codec's clocks are derived from the master clock `mclk_hz`.
+    pub fn new(i2c: I2C, address: u8, mclk_hz: u32) -> Self {
+        Self {
+            i2c,
+            address,
+            mclk_hz,
+            page: None,
+        }
+    }
+
+    async fn write_register(&mut self, register: (u8, u8), value: u8) -> Result<(), I2C::Error> {
+        let (page, address) = register;
+
+        if self.page != Some(page) {
+            self.i2c.write(self.address, &[reg::PAGE_SELECT, page]).await?;
+            self.page = Some(page);
+        }
+
+        self.i2c.write(self.address, &[address, value]).await
+    }
+}
+
+impl<I2C: I2c> AudioCodec for Tlv320aic3204<I2C> {
+    type Error = Error<I2C::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        self.write_register(reg::SW_RESET, 0x01).await?;
+        Timer::after_millis(1).await;
+
+        // CODEC_CLKIN is MCLK.
+        self.write_register(reg::CLOCK_MUX, 0x00).await?;
+
+        // I2S, 32 bit words, BCLK and WCLK are inputs.
+        self.write_register(reg::INTERFACE_1, 0b0011_0000).await?;
+
+        // Processing block PRB_P1 (default filter, 8 biquads).
+        self.write_register(reg::DAC_PROCESSING_BLOCK, 0x01).await?;
+
+        // Disable weak AVDD, enable the internal LDO for AVDD.
+        self.write_register(reg::POWER_CONFIG, 0x08).await?;
+        self.write_register(reg::LDO_CONTROL, 0x01).await?;
+
+        // Common mode of 0.9 V for headphone drivers, supplied from LDOIN.
+        self.write_register(reg::COMMON_MODE, 0x3b).await?;
+
+        // Route left and right DAC to the headphone drivers, at 0 dB gain.
+        self.write_register(reg::HPL_ROUTING, 0x08).await?;
+        self.write_register(reg::HPR_ROUTING, 0x08).await?;
+        self.write_register(reg::HPL_GAIN, 0x00).await?;
+        self.write_register(reg::HPR_GAIN, 0x00).await?;
+
+        // Soft-stepping of the headphone driver power-up, to avoid pops.
+        self.write_register(reg::HP_STARTUP, 0x25).await?;
+
+        self.set_mute(true).await?;
+        self.set_power_state(PowerState::Standby).await?;
+
+        debug!("TLV320AIC3204 at {:#x} initialized", self.address);
+
+        Ok(())
+    }
+
+    async fn set_sample_rate(&mut self, sample_rate_hz: u32) -> Result<(), Self::Error> {
+        let divider = NDAC * MDAC * sample_rate_hz;
+
+        if sample_rate_hz == 0 || self.mclk_hz % divider != 0 {
+            return Err(Error::UnsupportedSampleRate(sample_rate_hz));
+        }
+
+        let dosr = self.mclk_hz / divider;
+
+        if !(DOSR_MIN..=DOSR_MAX).contains(&dosr) {
+            return Err(Error::UnsupportedSampleRate(sample_rate_hz));
+        }
+
+        // Divider values of the maximum value are encoded as zero, bit 7 powers the divider.
+        self.write_register(reg::NDAC, 0x80 | NDAC as u8).await?;
+        self.write_register(reg::MDAC, 0x80 | MDAC as u8).await?;
+        self.write_register(reg::DOSR_MSB, ((dosr >> 8) & 0x03) as u8).await?;
+        self.write_register(reg::DOSR_LSB, dosr as u8).await?;
+
+        Ok(())
+    }
+
+    async fn set_volume(&mut self, left: Volume, right: Volume) -> Result<(), Self::Error> {
+        let mut mute = 0u8;
+
+        for (volume, register, mute_bit) in [
+            (left, reg::DAC_LEFT_VOLUME, 1 << 3),
+            (right, reg::DAC_RIGHT_VOLUME, 1 << 2),
+        ] {
+            match volume {
+                Volume::Muted => mute |= mute_bit,
+                Volume::DeciBel(db) => {
+                    let steps = volume_to_steps(db, VOLUME_STEPS_PER_DB, VOLUME_MIN, VOLUME_MAX);
+                    self.write_register(register, steps as u8).await?;
+                }
+            }
+        }
+
+        // Left and right volume are controlled independently.
+        self.write_register(reg::DAC_SETUP_2, mute).await?;
+
+        Ok(())
+    }
+
+    async fn set_mute(&mut self, mute: bool) -> Result<(), Self::Error> {
+        // Mute bits of the left and right channel.
+        let value = if mute { 0x0c } else { 0x00 };
+        self.write_register(reg::DAC_SETUP_2, value).await?;
+
+        Ok(())
+    }
+
+    async fn set_power_state(&mut self, state: PowerState) -> Result<(), Self::Error> {
+        match state {
+            PowerState::On => {
+                // Power up the DAC channels, routed left-to-left and right-to-right, then the output drivers.
+                self.write_register(reg::DAC_SETUP_1, 0xd4).await?;
+                self.write_register(reg::OUTPUT_POWER, 0x30).await?;
+            }
+            PowerState::Standby => {
+                self.write_register(reg::OUTPUT_POWER, 0x00).await?;
+                self.write_register(reg::DAC_SETUP_1, 0x14).await?;
+            }
+            PowerState::Off => {
+                self.write_register(reg::OUTPUT_POWER, 0x00).await?;
+                self.write_register(reg::DAC_SETUP_1, 0x14).await?;
+
+                // Disable the internal LDO, re-enabling weak AVDD. Requires `init` afterwards.
+                self.write_register(reg::LDO_CONTROL, 0x00).await?;
+                self.write_register(reg::POWER_CONFIG, 0x00).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[embassy_executor::task]
+pub async fn control_task(i2c_bus: &'static I2cBus) {
+    const EXPECTED_DEVICES: [ExpectedDevice; 1] = [ExpectedDevice {
+        name: "TLV320AIC3204",
+        address: DEFAULT_ADDRESS,
+        critical: true,
+    }];
+
+    board::POWER_SEQUENCE.wait_enabled().await;
+
+    let devices_present = i2c_scan::check_devices(&mut I2cDevice::new(i2c_bus), &EXPECTED_DEVICES).await;
+    if !self_test::check(Check::I2cDevices, devices_present) {
+        error!("Outputs stay muted");
+        OUTPUT_INHIBITED.store(true, Relaxed);
+    }
+
+    let codec = Tlv320aic3204::new(I2cDevice::new(i2c_bus), DEFAULT_ADDRESS, mclk::mclk_hz(SAMPLE_RATE_HZ));
+    super::control(codec).await;
+}
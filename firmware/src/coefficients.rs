@@ -0,0 +1,142 @@
+// Coefficient blobs for the smart amplifiers, e.g. DSP filter sets exported from TI PPC3.
+//
+// A blob is a sequence of blocks, each of which is written to consecutive registers of one amplifier in a single burst
+// transfer. A block has a five byte header (I2C address, book, page, first register, data length), followed by the
+// data. A block of length zero ends the blob, so that it can be padded to a multiple of four byte with zeros.
+//
+// With the `spi-flash` feature, a blob is loaded from the coefficient partition of the external flash, where it is
+// preceded by a blob header (see `partition`). It is streamed block by block, and all transfers are asynchronous.
+use defmt::Format;
+use embedded_hal_async::i2c::I2c;
+
+use crate::tas2780::Tas2780;
+
+pub const BLOCK_HEADER_SIZE: usize = 5;
+pub const MAX_BLOCK_SIZE: usize = u8::MAX as usize;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct BlockHeader {
+    pub address: u8,
+    pub book: u8,
+    pub page: u8,
+    pub register: u8,
+    pub length: u8,
+}
+
+impl BlockHeader {
+    pub fn parse(bytes: [u8; BLOCK_HEADER_SIZE]) -> Self {
+        let [address, book, page, register, length] = bytes;
+
+        Self {
+            address,
+            book,
+            page,
+            register,
+            length,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum LoadError {
+    /// The external flash was not detected.
+    NoFlash,
+    /// No valid blob is stored.
+    NoBlob,
+    /// A block extends beyond the end of the blob.
+    Truncated,
+    /// A block is addressed to an unknown amplifier.
+    UnknownAddress(u8),
+    /// Reading the blob failed.
+    Flash,
+    /// Writing a block to the amplifier at the address failed.
+    I2c(u8),
+}
+
+/// Write a block to the amplifier with the block's address.
+pub async fn write_block<I2C: I2c>(
+    amplifiers: &mut [Tas2780<I2C>],
+    header: BlockHeader,
+    data: &[u8],
+) -> Result<(), LoadError> {
+    let amplifier = amplifiers
+        .iter_mut()
+        .find(|amplifier| amplifier.address() == header.address)
+        .ok_or(LoadError::UnknownAddress(header.address))?;
+
+    amplifier
+        .write_block(header.book, header.page, header.register, data)
+        .await
+        .map_err(|_| LoadError::I2c(header.address))
+}
+
+/// Load a blob from memory, returning the number of blocks written.
+pub async fn load<I2C: I2c>(amplifiers: &mut [Tas2780<I2C>], mut blob: &[u8]) -> Result<usize, LoadError> {
+    let mut count = 0;
+
+    while blob.len() >= BLOCK_HEADER_SIZE {
+        let header = BlockHeader::parse(blob[..BLOCK_HEADER_SIZE].try_into().unwrap());
+        if header.length == 0 {
+            break;
+        }
+
+        let data = blob
+            .get(BLOCK_HEADER_SIZE..BLOCK_HEADER_SIZE + header.length as usize)
+            .ok_or(LoadError::Truncated)?;
+
+        write_block(amplifiers, header, data).await?;
+        blob = &blob[BLOCK_HEADER_SIZE + data.len()..];
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Load the blob from the coefficient partition of the external flash, returning the number of blocks written.
+#[cfg(feature = "spi-flash")]
+pub async fn load_from_flash<I2C: I2c>(amplifiers: &mut [Tas2780<I2C>]) -> Result<usize, LoadError> {
+    use crate::partition::{self, BLOB_HEADER_SIZE, COEFFICIENTS, COEFFICIENTS_MAGIC};
+
+    let mut external_flash = partition::EXTERNAL_FLASH.lock().await;
+    let flash = external_flash.as_mut().ok_or(LoadError::NoFlash)?;
+
+    let blob = partition::validate_blob(flash, &COEFFICIENTS, COEFFICIENTS_MAGIC)
+        .await
+        .map_err(|_| LoadError::Flash)?
+        .ok_or(LoadError::NoBlob)?;
+
+    let end = BLOB_HEADER_SIZE + blob.length;
+    let mut offset = BLOB_HEADER_SIZE;
+    let mut data = [0u8; MAX_BLOCK_SIZE];
+    let mut count = 0;
+
+    while offset + BLOCK_HEADER_SIZE as u32 <= end {
+        let mut header = [0u8; BLOCK_HEADER_SIZE];
+        COEFFICIENTS
+            .read(flash, offset, &mut header)
+            .await
+            .map_err(|_| LoadError::Flash)?;
+
+        let header = BlockHeader::parse(header);
+        if header.length == 0 {
+            break;
+        }
+
+        offset += BLOCK_HEADER_SIZE as u32;
+        if offset + header.length as u32 > end {
+            return Err(LoadError::Truncated);
+        }
+
+        let data = &mut data[..header.length as usize];
+        COEFFICIENTS
+            .read(flash, offset, data)
+            .await
+            .map_err(|_| LoadError::Flash)?;
+
+        write_block(amplifiers, header, data).await?;
+        offset += header.length as u32;
+        count += 1;
+    }
+
+    Ok(count)
+}
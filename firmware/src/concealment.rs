@@ -0,0 +1,133 @@
+// Concealment of gaps in the isochronous stream, e.g. when the host process stalls or is killed mid-stream.
+//
+// Missing packets are replaced by repetitions of the last received packet, which fade out to silence over
+// `CONCEALMENT_FRAME_COUNT` (micro)frames. If the gap lasts longer, no more blocks are produced, so that the output
+// task runs out of samples, stops, and discards its buffer state.
+//
+// Packets that the device missed (e.g. during a long critical section) are found from the frame numbers of received
+// packets, and concealed before the next one is played.
+use embassy_time::Duration;
+
+use crate::dsp::Gain;
+use crate::usb_frame;
+use crate::*;
+
+// Maximum number of consecutive (micro)frames that are concealed.
+pub const CONCEALMENT_FRAME_COUNT: usize = 4 * USB_FRAMES_PER_MS;
+
+const FRAME_DURATION: Duration = Duration::from_micros(1000 / USB_FRAMES_PER_MS as u64);
+
+// A packet is considered missing, if it is late by half a (micro)frame.
+const STALL_TIMEOUT: Duration = Duration::from_micros(3 * 1000 / (2 * USB_FRAMES_PER_MS as u64));
+
+pub struct Concealment {
+    last: UsbSampleBlock,
+    valid: bool,
+    concealed_frame_count: usize,
+
+    // Frame number of the last received packet.
+    last_frame: Option<u16>,
+
+    // A received packet, which is held back while missed packets before it are concealed.
+    held: UsbSampleBlock,
+}
+
+impl Concealment {
+    pub const fn new() -> Self {
+        Self {
+            last: UsbSampleBlock::new(),
+            valid: false,
+            concealed_frame_count: 0,
+            last_frame: None,
+            held: UsbSampleBlock::new(),
+        }
+    }
+
+    /// Forget the last packet, e.g. when the stream is closed.
+    pub fn reset(&mut self) {
+        self.valid = false;
+        self.concealed_frame_count = 0;
+        self.last_frame = None;
+    }
+
+    /// The number of packets that were missed before a packet, which was received in (micro)frame `frame`.
+    ///
+    /// Packets that were concealed while waiting are not counted. Longer gaps (e.g. after a stall) count as none.
+    pub fn missed_packets(&mut self, frame: u16) -> usize {
+        let Some(last_frame) = self.last_frame.replace(frame) else {
+            return 0;
+        };
+
+        let missed = (usb_frame::elapsed(last_frame, frame) as usize).saturating_sub(1);
+        if missed > CONCEALMENT_FRAME_COUNT {
+            return 0;
+        }
+
+        missed.saturating_sub(self.concealed_frame_count)
+    }
+
+    /// Hold back a received packet, while missed packets are concealed in its place.
+    pub fn hold(&mut self, block: &UsbSampleBlock) {
+        self.held.copy_from(block);
+    }
+
+    /// Restore the held back packet.
+    pub fn release(&self, block: &mut UsbSampleBlock) {
+        block.copy_from(&self.held);
+    }
+
+    /// Time to wait for the next packet, before concealing it. None, if concealment is not possible.
+    pub fn timeout(&self) -> Option<Duration> {
+        if !self.valid || self.concealed_frame_count >= CONCEALMENT_FRAME_COUNT {
+            None
+        } else if self.concealed_frame_count > 0 {
+            Some(FRAME_DURATION)
+        } else {
+            Some(STALL_TIMEOUT)
+        }
+    }
+
+    /// Keep a copy of a received packet.
+    ///
+    /// Returns true, if the packet ends a gap, such that the output must fade in again.
+    pub fn packet_received(&mut self, block: &UsbSampleBlock) -> bool {
+        let gap_ended = self.concealed_frame_count > 0;
+
+        self.last.copy_from(block);
+        self.valid = true;
+        self.concealed_frame_count = 0;
+
+        gap_ended
+    }
+
+    /// Fill a block for a missing packet, with the last packet and a fade-out ramp.
+    ///
+    /// Returns false, if the gap is too long for concealment.
+    pub fn conceal(&mut self, block: &mut UsbSampleBlock) -> bool {
+        if self.timeout().is_none() {
+            return false;
+        }
+
+        let block_sample_count = self.last.sample_count() as u64;
+        let ramp_sample_count = block_sample_count * CONCEALMENT_FRAME_COUNT as u64;
+        let mut sample_index = block_sample_count * self.concealed_frame_count as u64;
+
+        block.copy_from(&self.last);
+        block.process(|sample| {
+            let remaining = ramp_sample_count - sample_index;
+            sample_index += 1;
+
+            Gain::from_q31((remaining * i32::MAX as u64 / ramp_sample_count) as i32).apply(sample)
+        });
+
+        self.concealed_frame_count += 1;
+
+        true
+    }
+}
+
+impl Default for Concealment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,58 @@
+// Compile-time device configuration: USB identity and audio topology.
+//
+// Rebranding or resizing the device only requires changes to this file. Packet and buffer sizes are derived from these
+// values in the crate root.
+use embassy_usb::class::uac1;
+
+use crate::channel_layout::ChannelLayout;
+
+// USB identity (pid.codes test VID/PID).
+pub const USB_VID: u16 = 0x1209;
+pub const USB_PID: u16 = 0xaf02;
+pub const USB_MANUFACTURER: &str = "elagil";
+pub const USB_PRODUCT: &str = "testing";
+pub const USB_SERIAL_NUMBER: Option<&str> = None;
+
+// Current drawn from the bus, when the device runs from its external supply or from bus power (see `power_source`).
+pub const USB_SELF_POWERED_MAX_POWER_MA: u16 = 0;
+pub const USB_BUS_POWERED_MAX_POWER_MA: u16 = 500;
+
+// Stereo processing -> two two-way speakers
+pub const INPUT_CHANNEL_COUNT: usize = 2;
+
+// Channel layout of the USB stream, unless another one is selected in the settings (see `channel_layout`). Packet sizes
+// provide for up to `MAX_USB_CHANNEL_COUNT` channels.
+pub const DEFAULT_CHANNEL_LAYOUT: ChannelLayout = ChannelLayout::Stereo;
+pub const MAX_USB_CHANNEL_COUNT: usize = 4;
+
+pub const SAMPLE_RATE_HZ: u32 = 48_000;
+
+// Sample rates that are advertised to the host. Telephony rates are upsampled to `SAMPLE_RATE_HZ` (see `upsampling`).
+#[cfg(not(feature = "telephony-rates"))]
+pub const SAMPLE_RATES_HZ: [u32; 1] = [SAMPLE_RATE_HZ];
+#[cfg(feature = "telephony-rates")]
+pub const SAMPLE_RATES_HZ: [u32; 4] = [8_000, 16_000, 32_000, SAMPLE_RATE_HZ];
+
+pub const SAMPLE_WIDTH: uac1::SampleWidth = uac1::SampleWidth::Width4Byte;
+
+// Period of feedback values, in (micro)frames (the feedback endpoint's bRefresh or bInterval). Longer periods measure
+// with finer resolution, but follow clock drift more slowly. Limits of the bus speed are checked at compile time.
+pub const FEEDBACK_REFRESH_PERIOD: uac1::FeedbackRefresh = uac1::FeedbackRefresh::Period8Frames;
+
+// Maximum packet size, as a multiple of the nominal packet size. Provides margin for feedback (excessive), as far as
+// isochronous packets allow.
+pub const USB_PACKET_SIZE_FACTOR: usize = 2;
+
+// Audio that is buffered before output starts, in ms. Adds latency, but bridges irregular packet arrival at the start
+// of a stream.
+pub const OUTPUT_PREFILL_MS: usize = 2;
+
+// Largest pre-fill, which adaptive buffering grows to after repeated underruns (see `prefill`), in ms.
+pub const MAX_OUTPUT_PREFILL_MS: usize = 4;
+
+// Output channel that a stereo link's primary forwards to its secondary (see `link`).
+pub const LINK_CHANNEL: usize = 1;
+static_assertions::const_assert!(LINK_CHANNEL < INPUT_CHANNEL_COUNT);
+
+// Fade-in at the start of a stream or source, and after a gap, in ms.
+pub const STREAM_FADE_IN_MS: usize = 5;
@@ -0,0 +1,156 @@
+// The settings as a JSON configuration file, which the mass-storage configuration mode exposes (see `msc`).
+//
+// Gains are in dB, delays in samples. Unknown fields are ignored, so that files of other firmware versions apply. A
+// file with an invalid value is rejected as a whole.
+use blus_core::json::{self, SyntaxError, Value, Writer};
+use blus_core::volume;
+use defmt::Format;
+
+use crate::channel_layout::ChannelLayout;
+use crate::preset::PRESETS;
+use crate::settings::Settings;
+use crate::trim::{BALANCE_MAX, STEPS_PER_DB, TRIM_MAX};
+use crate::*;
+
+/// Maximum size of a configuration file.
+pub const MAX_SIZE: usize = 1024;
+
+// Names of the channel layouts, by value.
+const CHANNEL_LAYOUTS: [&str; 4] = ["mono", "stereo", "2.1", "4.0"];
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum ConfigError {
+    /// The file is no valid JSON object, at the byte position.
+    Syntax(usize),
+    /// A field's value is of the wrong type, or out of range.
+    InvalidValue,
+}
+
+impl From<SyntaxError> for ConfigError {
+    fn from(error: SyntaxError) -> Self {
+        Self::Syntax(error.0)
+    }
+}
+
+/// Write the settings as a configuration file. Returns its length.
+pub fn write(settings: &Settings, buffer: &mut [u8; MAX_SIZE]) -> usize {
+    let mut writer = Writer::new(buffer);
+
+    writer.number("preset", settings.preset as f32);
+    writer.numbers("trim_db", &settings.trim.map(|trim| trim as f32 / STEPS_PER_DB));
+    writer.number("balance_db", settings.balance as f32 / STEPS_PER_DB);
+    writer.string("channel_layout", CHANNEL_LAYOUTS[settings.channel_layout as usize]);
+    writer.boolean("loudness", settings.loudness);
+    writer.boolean("de_emphasis", settings.de_emphasis);
+    writer.numbers("delay_samples", &settings.delay.map(|samples| samples as f32));
+    let inverted: [f32; INPUT_CHANNEL_COUNT] =
+        core::array::from_fn(|channel| (settings.inverted >> channel & 1) as f32);
+    writer.numbers("inverted", &inverted);
+    writer.number("sleep_timeout_min", settings.sleep_timeout as f32);
+    writer.number("volume_min_db", settings.volume_min_db as f32);
+    writer.number("volume_max_db", settings.volume_max_db as f32);
+    writer.number("volume_step_db", settings.volume_step as f32 / volume::STEPS_PER_DB);
+
+    // All fields fit by far.
+    writer.finish().unwrap_or(0)
+}
+
+// A gain in 0.5 dB steps, within a limit.
+fn steps(value: Option<f32>, limit: i8) -> Option<i8> {
+    let steps = (value? * STEPS_PER_DB).round();
+    (steps.abs() <= limit as f32).then_some(steps as i8)
+}
+
+// An array with one value per channel.
+fn per_channel<T: Copy + Default>(value: Value, f: impl Fn(f32) -> Option<T>) -> Option<[T; INPUT_CHANNEL_COUNT]> {
+    let mut values = [T::default(); INPUT_CHANNEL_COUNT];
+    let mut count = 0;
+
+    for number in value.numbers() {
+        *values.get_mut(count)? = f(number?)?;
+        count += 1;
+    }
+    (count == INPUT_CHANNEL_COUNT).then_some(values)
+}
+
+// Apply a field to the settings.
+fn apply_field(settings: &mut Settings, key: &str, value: Value) -> Option<()> {
+    let number = match value {
+        Value::Number(number) => Some(number),
+        _ => None,
+    };
+
+    match key {
+        "preset" => {
+            let preset = number.filter(|&preset| preset >= 0.0 && (preset as usize) < PRESETS.len())?;
+            settings.preset = preset as u8;
+        }
+        "trim_db" => settings.trim = per_channel(value, |trim| steps(Some(trim), TRIM_MAX))?,
+        "balance_db" => settings.balance = steps(number, BALANCE_MAX)?,
+        "channel_layout" => {
+            let Value::String(name) = value else { return None };
+            let layout = CHANNEL_LAYOUTS.iter().position(|&layout| layout == name)?;
+            settings.channel_layout = ChannelLayout::from_u8(layout as u8)?;
+        }
+        "loudness" | "de_emphasis" => {
+            let Value::Bool(enabled) = value else { return None };
+            match key {
+                "loudness" => settings.loudness = enabled,
+                _ => settings.de_emphasis = enabled,
+            }
+        }
+        "delay_samples" => {
+            settings.delay = per_channel(value, |samples| {
+                (samples >= 0.0 && samples as usize <= alignment::MAX_DELAY_SAMPLES).then_some(samples as u16)
+            })?;
+        }
+        "inverted" => {
+            let inverted = per_channel(value, |inverted| {
+                (inverted == 0.0 || inverted == 1.0).then_some(inverted)
+            })?;
+            settings.inverted = inverted
+                .iter()
+                .enumerate()
+                .fold(0, |mask, (channel, &inverted)| mask | (inverted as u8) << channel);
+        }
+        "sleep_timeout_min" => {
+            let minutes = number.filter(|&minutes| (0.0..=sleep_timer::MAX_TIMEOUT_MIN as f32).contains(&minutes))?;
+            settings.sleep_timeout = minutes as u8;
+        }
+        // The curve's range is validated as a whole, after all fields applied.
+        "volume_min_db" | "volume_max_db" => {
+            let db = number.filter(|&db| (volume::MIN_DB as f32..=volume::MAX_DB as f32).contains(&db))?;
+            match key {
+                "volume_min_db" => settings.volume_min_db = db as i8,
+                _ => settings.volume_max_db = db as i8,
+            }
+        }
+        "volume_step_db" => {
+            let step = (number? * volume::STEPS_PER_DB).round();
+            settings.volume_step = (0.0..=u8::MAX as f32).contains(&step).then_some(step as u8)?;
+        }
+        _ => (),
+    }
+
+    Some(())
+}
+
+/// Apply a configuration file to the settings, which are left unchanged on errors.
+pub fn apply(text: &str, settings: &mut Settings) -> Result<(), ConfigError> {
+    let mut applied = *settings;
+    let mut valid = true;
+
+    json::parse(text, |key, value| {
+        if apply_field(&mut applied, key, value).is_none() {
+            log_debug!("Invalid configuration field {}", key);
+            valid = false;
+        }
+    })?;
+
+    if !valid || !applied.volume_curve().is_valid() {
+        return Err(ConfigError::InvalidValue);
+    }
+
+    *settings = applied;
+    Ok(())
+}
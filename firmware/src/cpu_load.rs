@@ -0,0 +1,71 @@
+// CPU load estimation, from the time that the thread-mode executor sleeps.
+//
+// The executor is driven by a custom loop, which measures each sleep (WFE) with the DWT cycle counter. Interrupt
+// handlers that run while the core is woken up count as idle time, so that the load only covers task execution.
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use cortex_m::peripheral::DWT;
+use embassy_executor::{raw, Spawner};
+use embassy_time::{Duration, Ticker};
+use static_cell::StaticCell;
+
+use crate::log_debug;
+
+// Context of the thread-mode pender, which wakes the executor with SEV (see `embassy_executor::Executor`).
+const THREAD_PENDER: usize = usize::MAX;
+
+const MEASUREMENT_PERIOD: Duration = Duration::from_secs(1);
+
+// Cycles spent sleeping, since the last measurement.
+static IDLE_CYCLES: AtomicU32 = AtomicU32::new(0);
+
+// Load of the last measurement period, and the highest load since boot, in permille.
+static LOAD_PERMILLE: AtomicU32 = AtomicU32::new(0);
+static PEAK_LOAD_PERMILLE: AtomicU32 = AtomicU32::new(0);
+
+/// Run the thread-mode executor, measuring its idle time. The DWT cycle counter must be enabled by `init`.
+pub fn run_executor(init: impl FnOnce(Spawner)) -> ! {
+    static EXECUTOR: StaticCell<raw::Executor> = StaticCell::new();
+    let executor = EXECUTOR.init(raw::Executor::new(THREAD_PENDER as *mut ()));
+
+    init(executor.spawner());
+
+    loop {
+        // SAFETY: Only polled from this loop, in thread mode.
+        unsafe { executor.poll() };
+
+        let start = DWT::cycle_count();
+        cortex_m::asm::wfe();
+        IDLE_CYCLES.fetch_add(DWT::cycle_count().wrapping_sub(start), Relaxed);
+    }
+}
+
+/// The load of the last measurement period, and the highest load since boot, in permille.
+pub fn load_permille() -> (u32, u32) {
+    (LOAD_PERMILLE.load(Relaxed), PEAK_LOAD_PERMILLE.load(Relaxed))
+}
+
+#[embassy_executor::task]
+pub async fn measurement_task() {
+    let mut ticker = Ticker::every(MEASUREMENT_PERIOD);
+    let mut start = DWT::cycle_count();
+    IDLE_CYCLES.store(0, Relaxed);
+
+    loop {
+        ticker.next().await;
+
+        let end = DWT::cycle_count();
+        let elapsed = end.wrapping_sub(start);
+        let idle = IDLE_CYCLES.swap(0, Relaxed).min(elapsed);
+        start = end;
+
+        if elapsed == 0 {
+            continue;
+        }
+
+        let load = ((elapsed - idle) as u64 * 1000 / elapsed as u64) as u32;
+        LOAD_PERMILLE.store(load, Relaxed);
+        PEAK_LOAD_PERMILLE.fetch_max(load, Relaxed);
+
+        log_debug!("CPU load: {} permille", load);
+    }
+}
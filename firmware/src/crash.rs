@@ -0,0 +1,106 @@
+// Panic handling, which persists the panic message across a reset.
+//
+// The F401 has no backup SRAM, so the message is stored in a RAM section that is not initialized at startup.
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{compiler_fence, Ordering::SeqCst};
+use heapless::String;
+
+pub const PANIC_MESSAGE_SIZE: usize = 192;
+
+// Marks a valid panic record.
+const MAGIC: u32 = 0x5041_4e43;
+
+#[repr(C)]
+struct PanicRecord {
+    magic: u32,
+    length: u32,
+    checksum: u32,
+    message: [u8; PANIC_MESSAGE_SIZE],
+}
+
+#[link_section = ".uninit.PANIC_RECORD"]
+static mut PANIC_RECORD: MaybeUninit<PanicRecord> = MaybeUninit::uninit();
+
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(MAGIC, |sum, &byte| sum.rotate_left(5) ^ byte as u32)
+}
+
+// Writes into a fixed-size buffer, truncating the text that does not fit.
+struct TruncatingWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(self.buffer.len() - self.length);
+        self.buffer[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+
+        Ok(())
+    }
+}
+
+/// Take the message of a panic that happened before the last reset, if any.
+///
+/// The record is invalidated, so that the message is only reported once.
+pub fn take_panic_message() -> Option<String<PANIC_MESSAGE_SIZE>> {
+    // SAFETY: Only accessed from here and the panic handler, which never returns. All bit patterns are valid for the
+    // record's fields.
+    let record = unsafe { (*addr_of_mut!(PANIC_RECORD)).assume_init_mut() };
+
+    let valid = record.magic == MAGIC && (record.length as usize) <= PANIC_MESSAGE_SIZE;
+    record.magic = 0;
+
+    if !valid {
+        return None;
+    }
+
+    let message = &record.message[..record.length as usize];
+    if checksum(message) != record.checksum {
+        return None;
+    }
+
+    // The message may have been truncated within a multi-byte character.
+    let text = match core::str::from_utf8(message) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&message[..e.valid_up_to()]).unwrap(),
+    };
+
+    let mut result = String::new();
+    result.push_str(text).unwrap();
+
+    Some(result)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    // SAFETY: Interrupts are disabled, and this function never returns.
+    let record = unsafe { (*addr_of_mut!(PANIC_RECORD)).assume_init_mut() };
+
+    let mut writer = TruncatingWriter {
+        buffer: &mut record.message,
+        length: 0,
+    };
+    _ = write!(writer, "{}", info);
+
+    let length = writer.length;
+    record.length = length as u32;
+    record.checksum = checksum(&record.message[..length]);
+    record.magic = MAGIC;
+    compiler_fence(SeqCst);
+
+    defmt::error!("{}", defmt::Display2Format(info));
+
+    if cortex_m::peripheral::DCB::is_debugger_attached() {
+        // Let the debugger report the panic.
+        cortex_m::asm::udf();
+    } else {
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+}
@@ -0,0 +1,19 @@
+// De-emphasis of pre-emphasized recordings, which are mostly early CDs at 44.1 kHz.
+//
+// Neither UAC1 nor the speaker class carry the CD's emphasis flag (the class only exposes mute and volume), so the
+// host cannot request de-emphasis per track. Instead, it is a user setting, which is part of the persistent settings.
+// The filter is designed for the DSP chain's sample rate, like all other stages.
+use defmt::info;
+
+use crate::*;
+
+pub fn is_enabled() -> bool {
+    settings::get().de_emphasis
+}
+
+/// Enable or disable de-emphasis, which is stored in the settings.
+pub fn set(enabled: bool) {
+    info!("De-emphasis: {}", enabled);
+    settings::modify(|settings| settings.de_emphasis = enabled);
+    PRESET_SIGNAL.signal(preset::active());
+}
@@ -0,0 +1,127 @@
+// Status display on an SSD1306 OLED, on the shared I2C bus.
+//
+// Shows the device state, stream format, host volume, active preset and source, and buffer health, one line of text
+// per display page. All values are read from shared state, so that the display never waits for the audio tasks. Only
+// changed pages are written, and a missing display is retried periodically.
+use core::fmt::Write;
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{info, warn};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_time::{Duration, Ticker, Timer};
+use embassy_usb::class::uac1::speaker::Volume;
+use heapless::String;
+
+use crate::font::{self, GLYPH_WIDTH};
+use crate::preset::PRESETS;
+use crate::source::{Selection, Source};
+use crate::ssd1306::{Ssd1306, PAGE_COUNT, WIDTH};
+use crate::status_indicator::DeviceState;
+use crate::*;
+
+// Default address of SSD1306 modules (SA0 low).
+pub const ADDRESS: u8 = 0x3c;
+
+const REFRESH_PERIOD: Duration = Duration::from_millis(250);
+
+// Delay before probing for the display again.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// One column of spacing between characters.
+const CHARACTER_WIDTH: usize = GLYPH_WIDTH + 1;
+const CHARACTERS_PER_LINE: usize = WIDTH / CHARACTER_WIDTH;
+
+type Line = String<CHARACTERS_PER_LINE>;
+type Page = [u8; WIDTH];
+
+fn render(text: &str) -> Page {
+    let mut page = [0u8; WIDTH];
+
+    for (cell, character) in page.chunks_exact_mut(CHARACTER_WIDTH).zip(text.chars()) {
+        cell[..GLYPH_WIDTH].copy_from_slice(font::glyph(character));
+    }
+
+    page
+}
+
+fn format_volume(line: &mut Line) {
+    let volume = trim::master_volume();
+
+    // Lines are truncated on overflow.
+    _ = match volume {
+        _ if trim::is_muted() => write!(line, "Volume: muted"),
+        (Volume::DeciBel(left), Volume::DeciBel(right)) if left != right => {
+            write!(line, "Volume: {:.1}/{:.1}", left, right)
+        }
+        (Volume::DeciBel(db), _) | (_, Volume::DeciBel(db)) => write!(line, "Volume: {:.1} dB", db),
+        _ => write!(line, "Volume: muted"),
+    };
+}
+
+// The text of all lines.
+fn lines() -> [Line; PAGE_COUNT] {
+    let mut lines: [Line; PAGE_COUNT] = Default::default();
+
+    _ = write!(lines[0], "{}", DeviceState::current().name());
+    _ = match (source::active(), i2s_input::status()) {
+        (Source::I2s, (true, Some(sample_rate_hz))) => write!(lines[1], "{} Hz, digital", sample_rate_hz),
+        (Source::I2s, _) => write!(lines[1], "No digital signal"),
+        _ => write!(
+            lines[1],
+            "{} Hz, {} bit",
+            USB_SAMPLE_RATE_HZ.load(Relaxed),
+            SAMPLE_WIDTH_BIT
+        ),
+    };
+    format_volume(&mut lines[2]);
+    _ = write!(lines[3], "Preset: {}", PRESETS[preset::active()].name);
+    _ = write!(lines[4], "Source: {}", source::active().name());
+    if source::selection() == Selection::Automatic {
+        _ = write!(lines[4], " (auto)");
+    }
+    _ = write!(
+        lines[5],
+        "Buffer: {}/{} blocks",
+        stats::buffer_fill(),
+        USB_SAMPLE_BLOCK_COUNT
+    );
+    _ = write!(lines[6], "Underruns: {}", stats::underruns());
+    _ = write!(lines[7], "Overruns: {}", stats::overruns());
+
+    lines
+}
+
+#[embassy_executor::task]
+pub async fn display_task(i2c_bus: &'static I2cBus, address: u8) {
+    let mut display = Ssd1306::new(I2cDevice::new(i2c_bus), address);
+
+    loop {
+        if display.init().await.is_err() {
+            Timer::after(RETRY_DELAY).await;
+            continue;
+        }
+        info!("Display at {:#x} initialized", address);
+
+        // The display memory is undefined after initialization, so all pages are written first.
+        let mut shown: [Option<Page>; PAGE_COUNT] = [None; PAGE_COUNT];
+        let mut ticker = Ticker::every(REFRESH_PERIOD);
+
+        'refresh: loop {
+            for (index, (line, shown)) in lines().iter().zip(shown.iter_mut()).enumerate() {
+                let page = render(line);
+                if *shown == Some(page) {
+                    continue;
+                }
+
+                if display.write_page(index, &page).await.is_err() {
+                    warn!("Failed to update display at {:#x}", address);
+                    break 'refresh;
+                }
+                *shown = Some(page);
+            }
+
+            ticker.next().await;
+        }
+
+        Timer::after(RETRY_DELAY).await;
+    }
+}
@@ -0,0 +1,25 @@
+// Sample processing, in Q31 fixed point, or in single precision floating point with the `float-dsp` feature.
+//
+// Kernels and filters are hardware-independent (see `blus-core`), except for the CMSIS-DSP backend.
+#[cfg(feature = "cmsis-dsp")]
+mod cmsis;
+
+#[cfg(all(feature = "cmsis-dsp", feature = "float-dsp"))]
+compile_error!("The CMSIS-DSP backend only supports fixed-point samples.");
+
+pub use blus_core::dsp::{
+    biquad, compressor, delay, design, emphasis, fir, kernel, loudness, protection, sample, Coefficients, Compressor,
+    CompressorParams, Delay, Filter, Gain, Loudness, Protection, ProtectionParams, Sample,
+};
+
+// Filter implementations, either plain Rust (default), or CMSIS-DSP.
+#[cfg(not(feature = "cmsis-dsp"))]
+pub use blus_core::dsp::{BiquadCascade, Fir};
+#[cfg(feature = "cmsis-dsp")]
+pub use cmsis::{BiquadCascade, Fir};
+
+/// The sample representation of the DSP chain.
+#[cfg(not(feature = "float-dsp"))]
+pub type DspSample = i32;
+#[cfg(feature = "float-dsp")]
+pub type DspSample = f32;
@@ -0,0 +1,154 @@
+// Biquad and FIR filters, backed by the CMSIS-DSP Q31 kernels.
+//
+// These have the same interface as the plain Rust filters, but are most efficient when used with `process_block`. Only
+// Q31 samples are supported.
+// The CMSIS instance structures hold pointers into the filter state, so they are created for every call, instead of
+// being stored alongside.
+use core::marker::PhantomData;
+
+use cmsis_dsp_sys::{arm_biquad_cascade_df1_q31, arm_biquad_casd_df1_inst_q31, arm_fir_instance_q31, arm_fir_q31};
+
+use super::biquad::{to_fixed, Coefficients, COEFFICIENT_SHIFT};
+use super::sample::Sample;
+
+// Shift that converts the Q2.30 coefficients' products back to Q31.
+const POST_SHIFT: i8 = (31 - COEFFICIENT_SHIFT) as i8;
+
+pub struct BiquadCascade<S: Sample, const N: usize> {
+    // Per stage: b0, b1, b2, -a1, -a2, as expected by CMSIS.
+    coefficients: [[i32; 5]; N],
+
+    // Per stage: x[n-1], x[n-2], y[n-1], y[n-2].
+    state: [[i32; 4]; N],
+
+    _sample: PhantomData<S>,
+}
+
+impl<const N: usize> BiquadCascade<i32, N> {
+    pub fn new() -> Self {
+        let mut cascade = Self {
+            coefficients: [[0; 5]; N],
+            state: [[0; 4]; N],
+            _sample: PhantomData,
+        };
+
+        for index in 0..N {
+            cascade.set_coefficients(index, Coefficients::IDENTITY);
+        }
+
+        cascade
+    }
+
+    pub fn set_coefficients(&mut self, index: usize, coefficients: Coefficients) {
+        self.coefficients[index] = [
+            to_fixed(coefficients.b0),
+            to_fixed(coefficients.b1),
+            to_fixed(coefficients.b2),
+            to_fixed(-coefficients.a1),
+            to_fixed(-coefficients.a2),
+        ];
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [[0; 4]; N];
+    }
+
+    pub fn process(&mut self, sample: i32) -> i32 {
+        let mut samples = [sample];
+        self.process_block(&mut samples);
+
+        samples[0]
+    }
+
+    pub fn process_block(&mut self, samples: &mut [i32]) {
+        let instance = arm_biquad_casd_df1_inst_q31 {
+            numStages: N as u32,
+            pState: self.state.as_mut_ptr().cast(),
+            pCoeffs: self.coefficients.as_ptr().cast(),
+            postShift: POST_SHIFT,
+        };
+
+        // SAFETY: State and coefficient arrays have the sizes required for `N` stages. In-place processing is
+        // supported by this kernel.
+        unsafe {
+            arm_biquad_cascade_df1_q31(&instance, samples.as_ptr(), samples.as_mut_ptr(), samples.len() as u32);
+        }
+    }
+}
+
+impl<const N: usize> Default for BiquadCascade<i32, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// CMSIS requires `N + block size - 1` contiguous state words. Blocks are limited to `N` samples, and the state is
+// split into two arrays, which are contiguous due to the C representation.
+#[repr(C)]
+struct FirState<const N: usize> {
+    history: [i32; N],
+    scratch: [i32; N],
+}
+
+pub struct Fir<S: Sample, const N: usize> {
+    // Coefficients in time-reversed order, as expected by CMSIS.
+    coefficients: [i32; N],
+    state: FirState<N>,
+
+    _sample: PhantomData<S>,
+}
+
+impl<const N: usize> Fir<i32, N> {
+    /// Create a filter from Q31 coefficients.
+    pub const fn new(coefficients: [i32; N]) -> Self {
+        let mut reversed = [0; N];
+        let mut index = 0;
+
+        while index < N {
+            reversed[index] = coefficients[N - 1 - index];
+            index += 1;
+        }
+
+        Self {
+            coefficients: reversed,
+            state: FirState {
+                history: [0; N],
+                scratch: [0; N],
+            },
+            _sample: PhantomData,
+        }
+    }
+
+    pub fn set_coefficients(&mut self, coefficients: &[i32; N]) {
+        for (reversed, coefficient) in self.coefficients.iter_mut().zip(coefficients.iter().rev()) {
+            *reversed = *coefficient;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state.history = [0; N];
+    }
+
+    pub fn process(&mut self, sample: i32) -> i32 {
+        let mut samples = [sample];
+        self.process_block(&mut samples);
+
+        samples[0]
+    }
+
+    pub fn process_block(&mut self, samples: &mut [i32]) {
+        let mut instance = arm_fir_instance_q31 {
+            numTaps: N as u16,
+            pState: (&mut self.state as *mut FirState<N>).cast(),
+            pCoeffs: self.coefficients.as_ptr(),
+        };
+
+        for block in samples.chunks_mut(N) {
+            // SAFETY: The state holds `2 * N >= N + block.len() - 1` words. In-place processing is supported, as
+            // input samples are copied to the state before computing the outputs.
+            unsafe {
+                arm_fir_q31(&mut instance, block.as_ptr(), block.as_mut_ptr(), block.len() as u32);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Rotary encoder for volume control, decoded from its quadrature signals by means of EXTI.
+//
+// Each detent adjusts the master volume locally, and sends a consumer control key to the host, which then sets its
+// volume (and thereby ours) accordingly. This keeps the knob and the host's volume slider in sync.
+use defmt::Format;
+use embassy_futures::select::select;
+use embassy_stm32::exti::ExtiInput;
+
+use crate::hid::ConsumerKey;
+use crate::*;
+
+// Volume change per detent, until the host responds.
+const VOLUME_STEP_DB: f32 = 1.0;
+
+// Quadrature transitions per detent.
+const TRANSITIONS_PER_DETENT: i8 = 4;
+
+// Count change by previous and current state (two bits each, A in the upper bit). Invalid transitions, which are
+// caused by contact bounce, do not count.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+pub struct Quadrature {
+    state: u8,
+    count: i8,
+}
+
+impl Quadrature {
+    pub const fn new() -> Self {
+        Self { state: 0, count: 0 }
+    }
+
+    /// Update with the current levels, returning the direction of a completed detent.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<Direction> {
+        let state = ((a as u8) << 1) | b as u8;
+        self.count += TRANSITIONS[((self.state << 2) | state) as usize];
+        self.state = state;
+
+        if self.count >= TRANSITIONS_PER_DETENT {
+            self.count = 0;
+            Some(Direction::Clockwise)
+        } else if self.count <= -TRANSITIONS_PER_DETENT {
+            self.count = 0;
+            Some(Direction::CounterClockwise)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Quadrature {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[embassy_executor::task]
+pub async fn encoder_task(mut a: ExtiInput<'static>, mut b: ExtiInput<'static>) {
+    let mut quadrature = Quadrature::new();
+    quadrature.update(a.is_high(), b.is_high());
+
+    loop {
+        select(a.wait_for_any_edge(), b.wait_for_any_edge()).await;
+
+        let Some(direction) = quadrature.update(a.is_high(), b.is_high()) else {
+            continue;
+        };
+
+        log_debug!("Encoder: {}", direction);
+
+        // A step only wakes the device from sleep.
+        if sleep_timer::activity() {
+            continue;
+        }
+
+        let (step_db, key) = match direction {
+            Direction::Clockwise => (VOLUME_STEP_DB, ConsumerKey::VolumeUp),
+            Direction::CounterClockwise => (-VOLUME_STEP_DB, ConsumerKey::VolumeDown),
+        };
+
+        trim::adjust_master_volume(step_db);
+
+        // Keys are dropped, if the host does not read them.
+        let _ = CONSUMER_KEY_CHANNEL.try_send(key);
+    }
+}
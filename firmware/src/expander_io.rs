@@ -0,0 +1,152 @@
+// Front-panel IO on a GPIO expander, with pin handles that mirror the API of the MCU's `Output` and `ExtiInput`.
+//
+// The status LED and wake-up button tasks therefore work with either, and boards select the pin types. The expander
+// task writes output levels to the expander when they change, and reads the inputs whenever the expander's interrupt
+// line is asserted (or periodically, without an interrupt line).
+use core::sync::atomic::{AtomicU16, Ordering::Relaxed};
+use defmt::{info, warn};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Level;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+use crate::gpio_expander::{GpioExpander, Variant};
+use crate::*;
+
+pub const PIN_COUNT: usize = 16;
+
+// Input polling period, without an interrupt line.
+const POLL_PERIOD: Duration = Duration::from_millis(20);
+
+// Delay before retrying after a failed transfer.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+static OUTPUT_MASK: AtomicU16 = AtomicU16::new(0);
+static OUTPUT_LEVELS: AtomicU16 = AtomicU16::new(0);
+
+// Inputs are assumed to be pulled up, until they are read.
+static INPUT_LEVELS: AtomicU16 = AtomicU16::new(u16::MAX);
+
+static OUTPUTS_CHANGED_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+static INPUT_CHANGED_SIGNALS: [Signal<ThreadModeRawMutex, ()>; PIN_COUNT] = [const { Signal::new() }; PIN_COUNT];
+
+/// An output pin of the expander.
+pub struct ExpanderOutput {
+    mask: u16,
+}
+
+impl ExpanderOutput {
+    pub fn new(pin: usize, initial_level: Level) -> Self {
+        let mut output = Self { mask: 1 << pin };
+        OUTPUT_MASK.fetch_or(output.mask, Relaxed);
+        output.set_level(initial_level);
+
+        output
+    }
+
+    pub fn set_level(&mut self, level: Level) {
+        match level {
+            Level::High => OUTPUT_LEVELS.fetch_or(self.mask, Relaxed),
+            Level::Low => OUTPUT_LEVELS.fetch_and(!self.mask, Relaxed),
+        };
+
+        OUTPUTS_CHANGED_SIGNAL.signal(());
+    }
+}
+
+/// An input pin of the expander. Only one task may wait for it.
+pub struct ExpanderInput {
+    pin: usize,
+}
+
+impl ExpanderInput {
+    pub fn new(pin: usize) -> Self {
+        Self { pin }
+    }
+
+    pub fn is_high(&self) -> bool {
+        INPUT_LEVELS.load(Relaxed) & (1 << self.pin) != 0
+    }
+
+    pub async fn wait_for_rising_edge(&mut self) {
+        loop {
+            INPUT_CHANGED_SIGNALS[self.pin].wait().await;
+
+            if self.is_high() {
+                return;
+            }
+        }
+    }
+
+    pub async fn wait_for_falling_edge(&mut self) {
+        loop {
+            INPUT_CHANGED_SIGNALS[self.pin].wait().await;
+
+            if !self.is_high() {
+                return;
+            }
+        }
+    }
+}
+
+// Read the inputs, and notify waiters of changed pins.
+async fn update_inputs<I2C: embedded_hal_async::i2c::I2c>(expander: &mut GpioExpander<I2C>) -> bool {
+    let Ok(levels) = expander.read_inputs().await else {
+        return false;
+    };
+
+    let changed = (INPUT_LEVELS.swap(levels, Relaxed) ^ levels) & !OUTPUT_MASK.load(Relaxed);
+
+    for (pin, signal) in INPUT_CHANGED_SIGNALS.iter().enumerate() {
+        if changed & (1 << pin) != 0 {
+            signal.signal(());
+        }
+    }
+
+    true
+}
+
+/// Mirrors expander pins, which must be created before the task is spawned.
+#[embassy_executor::task]
+pub async fn expander_task(
+    i2c_bus: &'static I2cBus,
+    address: u8,
+    variant: Variant,
+    mut interrupt: Option<ExtiInput<'static>>,
+) {
+    let mut expander = GpioExpander::new(I2cDevice::new(i2c_bus), address, variant);
+    let output_mask = OUTPUT_MASK.load(Relaxed);
+
+    // Output levels are set before the direction, which avoids glitches.
+    if expander.write_outputs(OUTPUT_LEVELS.load(Relaxed)).await.is_err()
+        || expander.set_outputs(output_mask).await.is_err()
+        || expander.enable_interrupts(!output_mask).await.is_err()
+    {
+        warn!("Failed to configure GPIO expander at {:#x}", address);
+    } else {
+        info!("GPIO expander at {:#x} configured ({})", address, variant);
+    }
+
+    update_inputs(&mut expander).await;
+
+    loop {
+        let inputs_changed = async {
+            match interrupt.as_mut() {
+                Some(interrupt) => interrupt.wait_for_low().await,
+                None => Timer::after(POLL_PERIOD).await,
+            }
+        };
+
+        let success = match select(inputs_changed, OUTPUTS_CHANGED_SIGNAL.wait()).await {
+            Either::First(()) => update_inputs(&mut expander).await,
+            Either::Second(()) => expander.write_outputs(OUTPUT_LEVELS.load(Relaxed)).await.is_ok(),
+        };
+
+        if !success {
+            warn!("Failed to access GPIO expander at {:#x}", address);
+            Timer::after(RETRY_DELAY).await;
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// PWM fan control for high-power amplifier builds, driven by the amplifiers' die temperature.
+//
+// The fan follows a temperature-to-duty curve: it starts at `START_C` with the minimum duty, that reliably keeps it
+// spinning, rises linearly to full speed at `FULL_SPEED_C`, and stops with hysteresis. Starting from rest, it is kicked
+// at full duty, since many fans do not start at low duty. The tachometer (two pulses per revolution, open drain) is
+// counted per measurement window, and a fan that does not turn while driven is reported as stalled, and kicked again.
+// Thermal foldback (see `thermal`) still protects the amplifiers, if the fan fails.
+use core::sync::atomic::{AtomicBool, AtomicI16, AtomicU16, AtomicU8, Ordering::Relaxed};
+use defmt::{info, warn};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+
+use crate::*;
+
+// Temperature, at which the fan starts with the minimum duty.
+const START_C: i16 = 60;
+
+// Temperature, at which the fan runs at full speed.
+const FULL_SPEED_C: i16 = 95;
+
+// Temperature drop below `START_C` for stopping the fan.
+const HYSTERESIS_C: i16 = 8;
+
+// Lowest duty, at which typical fans keep spinning.
+const MIN_DUTY_PERCENT: u8 = 30;
+
+// Full duty from rest, until the fan turns.
+const KICK_TIME: Duration = Duration::from_millis(500);
+
+// Tachometer pulses are counted over this window, which also paces duty updates.
+const TACH_WINDOW: Duration = Duration::from_secs(1);
+const PULSES_PER_REVOLUTION: u32 = 2;
+
+// A driven fan below this speed, for this many windows, is considered stalled.
+const STALL_RPM: u16 = 200;
+const STALL_WINDOWS: u8 = 3;
+
+/// PWM frequency, above the audible range, as common for 4-pin fans.
+pub const PWM_FREQUENCY_HZ: u32 = 25_000;
+
+// The latest die temperature while the amplifiers are active, or `i16::MIN` in standby, when they do not heat.
+static TEMPERATURE_C: AtomicI16 = AtomicI16::new(i16::MIN);
+
+static DUTY_PERCENT: AtomicU8 = AtomicU8::new(0);
+static SPEED_RPM: AtomicU16 = AtomicU16::new(0);
+static STALLED: AtomicBool = AtomicBool::new(false);
+
+/// Report the amplifiers' temperature, or `None`, while they are in standby.
+pub fn report_temperature(temperature_c: Option<i16>) {
+    TEMPERATURE_C.store(temperature_c.unwrap_or(i16::MIN), Relaxed);
+}
+
+/// The fan's duty in percent, its measured speed in revolutions per minute, and whether it stalled.
+pub fn status() -> (u8, u16, bool) {
+    (
+        DUTY_PERCENT.load(Relaxed),
+        SPEED_RPM.load(Relaxed),
+        STALLED.load(Relaxed),
+    )
+}
+
+/// Maps temperatures to duty cycles, with hysteresis for stopping.
+pub struct FanCurve {
+    running: bool,
+}
+
+impl FanCurve {
+    pub const fn new() -> Self {
+        Self { running: false }
+    }
+
+    /// The duty in percent for a temperature, or zero without a reading.
+    pub fn duty_percent(&mut self, temperature_c: Option<i16>) -> u8 {
+        let Some(temperature_c) = temperature_c else {
+            self.running = false;
+            return 0;
+        };
+
+        if temperature_c >= START_C {
+            self.running = true;
+        } else if temperature_c < START_C - HYSTERESIS_C {
+            self.running = false;
+        }
+
+        if !self.running {
+            return 0;
+        }
+
+        let excess = (temperature_c - START_C).clamp(0, FULL_SPEED_C - START_C) as u32;
+        let span = (100 - MIN_DUTY_PERCENT) as u32;
+        MIN_DUTY_PERCENT + (excess * span / (FULL_SPEED_C - START_C) as u32) as u8
+    }
+}
+
+impl Default for FanCurve {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Count tachometer pulses until the deadline.
+async fn count_pulses(tach: &mut ExtiInput<'static>, deadline: Instant) -> u32 {
+    let mut pulses = 0;
+    while with_deadline(deadline, tach.wait_for_falling_edge()).await.is_ok() {
+        pulses += 1;
+    }
+
+    pulses
+}
+
+/// Drives the fan along the curve, and monitors its tachometer.
+#[embassy_executor::task]
+pub async fn fan_task(mut pwm: SimplePwm<'static, board::FanTimer>, mut tach: ExtiInput<'static>) {
+    let mut curve = FanCurve::new();
+    let mut duty_percent = 0;
+    let mut slow_windows = 0;
+
+    pwm.ch1().set_duty_cycle_fully_off();
+    pwm.ch1().enable();
+
+    loop {
+        let temperature_c = Some(TEMPERATURE_C.load(Relaxed)).filter(|&temperature_c| temperature_c != i16::MIN);
+        let new_duty_percent = curve.duty_percent(temperature_c);
+
+        if new_duty_percent != duty_percent {
+            info!("Fan duty: {} % at {} C", new_duty_percent, temperature_c);
+
+            if duty_percent == 0 {
+                pwm.ch1().set_duty_cycle_fully_on();
+                Timer::after(KICK_TIME).await;
+            }
+
+            duty_percent = new_duty_percent;
+            pwm.ch1().set_duty_cycle_percent(duty_percent);
+            DUTY_PERCENT.store(duty_percent, Relaxed);
+        }
+
+        let pulses = count_pulses(&mut tach, Instant::now() + TACH_WINDOW).await;
+        let rpm = (pulses * 60 / PULSES_PER_REVOLUTION) as u64 * 1000 / TACH_WINDOW.as_millis();
+        SPEED_RPM.store(rpm as u16, Relaxed);
+
+        if duty_percent == 0 || rpm >= STALL_RPM as u64 {
+            slow_windows = 0;
+            STALLED.store(false, Relaxed);
+            continue;
+        }
+
+        slow_windows += 1;
+        if slow_windows >= STALL_WINDOWS {
+            warn!("Fan stalled at {} % duty", duty_percent);
+            STALLED.store(true, Relaxed);
+            slow_windows = 0;
+
+            // Kick the fan again.
+            pwm.ch1().set_duty_cycle_fully_on();
+            Timer::after(KICK_TIME).await;
+            pwm.ch1().set_duty_cycle_percent(duty_percent);
+        }
+    }
+}
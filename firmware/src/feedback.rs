@@ -0,0 +1,106 @@
+// Accumulates SOF capture timestamps over the feedback refresh period.
+//
+// The accumulator is driven by the SOF capture interrupt, and reset from the USB tasks when the host connection is
+// lost. Its arithmetic is hardware-independent (see `blus-core`), and shared between both contexts here. Complete
+// periods are handed to the feedback task without overwriting ones that it did not take yet.
+use core::cell::Cell;
+
+use blus_core::feedback::{Accumulator, PendingPeriods};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+
+use crate::stats;
+
+pub use blus_core::feedback::feedback_value;
+
+pub struct FeedbackAccumulator {
+    state: Mutex<CriticalSectionRawMutex, Cell<Accumulator>>,
+}
+
+impl FeedbackAccumulator {
+    /// Create an accumulator for a refresh period of `period_frames` USB (micro)frames.
+    pub const fn new(period_frames: usize) -> Self {
+        Self {
+            state: Mutex::new(Cell::new(Accumulator::new(period_frames))),
+        }
+    }
+
+    /// Restart the measurement with the next capture, discarding the partial refresh period.
+    pub fn reset(&self) {
+        self.state.lock(|cell| {
+            let mut accumulator = cell.get();
+            accumulator.reset();
+            cell.set(accumulator);
+        });
+    }
+
+    /// Add the timer counter value, captured at a SOF.
+    ///
+    /// Returns the number of timer ticks over the refresh period, when it is complete. The timer counter may wrap.
+    pub fn update(&self, ticks: u32) -> Option<u32> {
+        self.state.lock(|cell| {
+            let mut accumulator = cell.get();
+            let result = accumulator.update(ticks);
+            cell.set(accumulator);
+            result
+        })
+    }
+}
+
+/// Hands measured refresh periods from the SOF interrupt to the feedback task. Periods that the task did not take in
+/// time are accumulated and delivered as their average, so that no measured ticks are lost.
+pub struct FeedbackTicks {
+    pending: Mutex<CriticalSectionRawMutex, Cell<PendingPeriods>>,
+    signal: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl FeedbackTicks {
+    pub const fn new() -> Self {
+        Self {
+            pending: Mutex::new(Cell::new(PendingPeriods::new())),
+            signal: Signal::new(),
+        }
+    }
+
+    /// Add the ticks of a complete refresh period. A period that is still pending counts as a missed deadline.
+    pub fn push(&self, ticks: u32) {
+        let on_time = self.pending.lock(|cell| {
+            let mut pending = cell.get();
+            let on_time = pending.push(ticks);
+            cell.set(pending);
+            on_time
+        });
+
+        stats::record_feedback_ticks(ticks);
+        if !on_time {
+            stats::record_missed_feedback();
+        }
+        self.signal.signal(());
+    }
+
+    /// Wait for refresh periods, and take their average ticks.
+    pub async fn receive(&self) -> u32 {
+        loop {
+            self.signal.wait().await;
+
+            let ticks = self.pending.lock(|cell| {
+                let mut pending = cell.get();
+                let ticks = pending.take();
+                cell.set(pending);
+                ticks
+            });
+
+            if let Some(ticks) = ticks {
+                return ticks;
+            }
+        }
+    }
+
+    /// Discard pending periods, e.g. when the host connection is lost.
+    pub fn reset(&self) {
+        self.pending.lock(|cell| cell.set(PendingPeriods::new()));
+        self.signal.reset();
+        stats::restart_tick_histogram();
+    }
+}
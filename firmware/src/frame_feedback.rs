@@ -0,0 +1,78 @@
+// Feedback measurement from the USB frame number, for boards or chips that cannot route SOF to a timer.
+//
+// At the end of every refresh period, the frame number is polled until it changes, and the core's cycle counter
+// (DWT) is sampled. The number of cycles between two frame number changes is then converted to feedback counter
+// ticks, such that the result is equivalent to the SOF capture measurement.
+//
+// The task sleeps until shortly before the expected frame change, so that polling takes little time. The cycle
+// counter runs at the system clock, which must not be reduced while streaming (see `power`).
+use cortex_m::peripheral::DWT;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::chip::SYSCLK_HZ;
+use crate::usb_frame;
+use crate::*;
+
+const PERIOD_FRAMES: u16 = FEEDBACK_REFRESH_PERIOD.frame_count() as u16;
+const PERIOD: Duration = Duration::from_micros(PERIOD_FRAMES as u64 * 1000 / USB_FRAMES_PER_MS as u64);
+
+// Wake up this long before the expected frame change.
+const WAKE_MARGIN: Duration = Duration::from_micros(200);
+
+// Give up polling after this long, e.g. during suspend.
+const POLL_TIMEOUT_CYCLES: u32 = (SYSCLK_HZ / 1000) * 2;
+
+// Poll until the frame number changes, and return the new frame number and the cycle count at the change.
+fn wait_for_frame_change() -> Option<(u16, u32)> {
+    let start_frame = usb_frame::number();
+    let start_cycles = DWT::cycle_count();
+
+    loop {
+        let (frame, cycles) = critical_section::with(|_| (usb_frame::number(), DWT::cycle_count()));
+
+        if frame != start_frame {
+            return Some((frame, cycles));
+        }
+
+        if cycles.wrapping_sub(start_cycles) > POLL_TIMEOUT_CYCLES {
+            return None;
+        }
+    }
+}
+
+/// Measures feedback, and signals it like the SOF capture interrupt. The DWT cycle counter must be enabled.
+#[embassy_executor::task]
+pub async fn frame_number_task() {
+    let mut last_change: Option<(u16, u32)> = None;
+    let mut wake_at = Instant::now();
+
+    loop {
+        Timer::at(wake_at).await;
+
+        let Some((frame, cycles)) = wait_for_frame_change() else {
+            // No SOF, restart the measurement.
+            last_change = None;
+            wake_at = Instant::now() + PERIOD;
+            continue;
+        };
+
+        wake_at = Instant::now() + PERIOD - WAKE_MARGIN;
+
+        if let Some((last_frame, last_cycles)) = last_change {
+            let frames = usb_frame::elapsed(last_frame, frame);
+
+            // Skip the result, if the task was delayed by more than one period.
+            if frames > 0 && frames <= 2 * PERIOD_FRAMES {
+                let elapsed_cycles = cycles.wrapping_sub(last_cycles) as u64;
+                let ticks = elapsed_cycles * FEEDBACK_COUNTER_TICK_RATE as u64 * PERIOD_FRAMES as u64
+                    / (SYSCLK_HZ as u64 * frames as u64);
+
+                FEEDBACK_TICKS.push(ticks as u32);
+            }
+
+            clock_accuracy::record(frames as u32, cycles);
+        }
+
+        last_change = Some((frame, cycles));
+    }
+}
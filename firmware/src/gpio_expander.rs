@@ -0,0 +1,86 @@
+// Driver for 16 bit I2C GPIO expanders (NXP PCA9555, Microchip MCP23017).
+//
+// Both provide two 8 bit ports with registers in pairs (port 0/A first), and auto-increment within a pair. Pins are
+// numbered 0 to 15, where 0 to 7 are port 0/A.
+use defmt::Format;
+use embedded_hal_async::i2c::I2c;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Variant {
+    Pca9555,
+    /// MCP23017 with the default register layout (`IOCON.BANK` cleared).
+    Mcp23017,
+}
+
+// Register addresses of the first register of each pair.
+struct Registers {
+    input: u8,
+    output: u8,
+    // Set bits configure inputs on both variants.
+    direction: u8,
+}
+
+impl Variant {
+    fn registers(self) -> Registers {
+        match self {
+            Variant::Pca9555 => Registers {
+                input: 0x00,
+                output: 0x02,
+                direction: 0x06,
+            },
+            Variant::Mcp23017 => Registers {
+                input: 0x12,
+                output: 0x14,
+                direction: 0x00,
+            },
+        }
+    }
+}
+
+// Interrupt-on-change enable register pair of the MCP23017.
+const MCP23017_GPINTEN: u8 = 0x04;
+
+pub struct GpioExpander<I2C> {
+    i2c: I2C,
+    address: u8,
+    variant: Variant,
+}
+
+impl<I2C: I2c> GpioExpander<I2C> {
+    pub fn new(i2c: I2C, address: u8, variant: Variant) -> Self {
+        Self { i2c, address, variant }
+    }
+
+    async fn write_pair(&mut self, register: u8, value: u16) -> Result<(), I2C::Error> {
+        let [low, high] = value.to_le_bytes();
+        self.i2c.write(self.address, &[register, low, high]).await
+    }
+
+    /// Configure the pins in the mask as outputs, all others as inputs.
+    pub async fn set_outputs(&mut self, output_mask: u16) -> Result<(), I2C::Error> {
+        self.write_pair(self.variant.registers().direction, !output_mask).await
+    }
+
+    /// Enable change interrupts for the pins in the mask. The PCA9555 always signals changes of all inputs.
+    pub async fn enable_interrupts(&mut self, mask: u16) -> Result<(), I2C::Error> {
+        match self.variant {
+            Variant::Pca9555 => Ok(()),
+            Variant::Mcp23017 => self.write_pair(MCP23017_GPINTEN, mask).await,
+        }
+    }
+
+    /// Set the level of all output pins.
+    pub async fn write_outputs(&mut self, levels: u16) -> Result<(), I2C::Error> {
+        self.write_pair(self.variant.registers().output, levels).await
+    }
+
+    /// Read the level of all pins. On the PCA9555, this also clears the interrupt.
+    pub async fn read_inputs(&mut self) -> Result<u16, I2C::Error> {
+        let mut levels = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[self.variant.registers().input], &mut levels)
+            .await?;
+
+        Ok(u16::from_le_bytes(levels))
+    }
+}
@@ -0,0 +1,162 @@
+// HID consumer control interface, for sending media keys (e.g. volume up/down) to the host.
+//
+// With the `battery-monitor` feature, the interface also reports the battery strength, in a second report. Reports
+// are then preceded by their ID.
+use blus_core::usb_layout::{class, Endpoint, Function, Interface};
+use defmt::{warn, Format};
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::Builder;
+use static_cell::StaticCell;
+
+use crate::*;
+
+// One byte report, with one bit per key.
+#[cfg(not(feature = "battery-monitor"))]
+const REPORT_SIZE: usize = 1;
+
+// The report ID, followed by one byte of keys or the battery strength.
+#[cfg(feature = "battery-monitor")]
+const REPORT_SIZE: usize = 2;
+
+// IDs of the consumer control and battery reports, as in the report descriptor.
+#[cfg(feature = "battery-monitor")]
+const CONSUMER_REPORT_ID: u8 = 1;
+#[cfg(feature = "battery-monitor")]
+const BATTERY_REPORT_ID: u8 = 2;
+
+#[cfg(not(feature = "battery-monitor"))]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0c, // Usage Page (Consumer)
+    0x09, 0x01, // Usage (Consumer Control)
+    0xa1, 0x01, // Collection (Application)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x06, //   Report Count (6)
+    0x09, 0xe9, //   Usage (Volume Increment)
+    0x09, 0xea, //   Usage (Volume Decrement)
+    0x09, 0xe2, //   Usage (Mute)
+    0x09, 0xcd, //   Usage (Play/Pause)
+    0x09, 0xb5, //   Usage (Scan Next Track)
+    0x09, 0xb6, //   Usage (Scan Previous Track)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x95, 0x02, //   Report Count (2)
+    0x81, 0x01, //   Input (Constant)
+    0xc0, // End Collection
+];
+
+#[cfg(feature = "battery-monitor")]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0c, // Usage Page (Consumer)
+    0x09, 0x01, // Usage (Consumer Control)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x06, //   Report Count (6)
+    0x09, 0xe9, //   Usage (Volume Increment)
+    0x09, 0xea, //   Usage (Volume Decrement)
+    0x09, 0xe2, //   Usage (Mute)
+    0x09, 0xcd, //   Usage (Play/Pause)
+    0x09, 0xb5, //   Usage (Scan Next Track)
+    0x09, 0xb6, //   Usage (Scan Previous Track)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x95, 0x02, //   Report Count (2)
+    0x81, 0x01, //   Input (Constant)
+    0xc0, // End Collection
+    0x05, 0x06, // Usage Page (Generic Device Controls)
+    0x09, 0x20, // Usage (Battery Strength)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x02, //   Report ID (2)
+    0x09, 0x20, //   Usage (Battery Strength)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x64, //   Logical Maximum (100)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x01, //   Report Count (1)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
+
+const POLL_INTERVAL_MS: u8 = 10;
+
+/// The consumer control interface, with its interrupt IN endpoint (see `usb_layout`).
+pub const FUNCTION: Function = Function {
+    class: class::HID,
+    sub_class: 0,
+    interfaces: &[Interface {
+        class: class::HID,
+        sub_class: 0,
+        endpoints: &[Endpoint::interrupt_in(REPORT_SIZE)],
+    }],
+};
+
+/// Keys in the order of the report descriptor's usages.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum ConsumerKey {
+    VolumeUp = 0,
+    VolumeDown = 1,
+    Mute = 2,
+    PlayPause = 3,
+    NextTrack = 4,
+    PreviousTrack = 5,
+}
+
+pub type ConsumerControl = HidWriter<'static, UsbDriver, REPORT_SIZE>;
+
+/// Add the consumer control interface to the USB device.
+pub fn register(builder: &mut Builder<'static, UsbDriver>) -> ConsumerControl {
+    static STATE: StaticCell<hid::State> = StaticCell::new();
+
+    let config = hid::Config {
+        report_descriptor: REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: POLL_INTERVAL_MS,
+        max_packet_size: REPORT_SIZE as u16,
+    };
+
+    HidWriter::new(builder, STATE.init(hid::State::new()), config)
+}
+
+// Send a key as a press followed by a release.
+async fn send_key(writer: &mut ConsumerControl, key: ConsumerKey) {
+    for report in [1 << key as u8, 0] {
+        #[cfg(feature = "battery-monitor")]
+        let report = [CONSUMER_REPORT_ID, report];
+        #[cfg(not(feature = "battery-monitor"))]
+        let report = [report];
+
+        if let Err(e) = writer.write(&report).await {
+            warn!("Failed to send consumer key {}: {}", key, e);
+            break;
+        }
+    }
+}
+
+/// Sends queued keys to the host, as a press followed by a release.
+#[cfg(not(feature = "battery-monitor"))]
+#[embassy_executor::task]
+pub async fn consumer_control_task(mut writer: ConsumerControl) {
+    loop {
+        let key = CONSUMER_KEY_CHANNEL.receive().await;
+        send_key(&mut writer, key).await;
+    }
+}
+
+/// Sends queued keys to the host, as a press followed by a release, and the battery strength, when it changed.
+#[cfg(feature = "battery-monitor")]
+#[embassy_executor::task]
+pub async fn consumer_control_task(mut writer: ConsumerControl) {
+    use embassy_futures::select::{select, Either};
+
+    loop {
+        match select(CONSUMER_KEY_CHANNEL.receive(), battery::LEVEL_SIGNAL.wait()).await {
+            Either::First(key) => send_key(&mut writer, key).await,
+            Either::Second(level_percent) => {
+                if let Err(e) = writer.write(&[BATTERY_REPORT_ID, level_percent]).await {
+                    warn!("Failed to send battery strength: {}", e);
+                }
+            }
+        }
+    }
+}
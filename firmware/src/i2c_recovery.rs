@@ -0,0 +1,175 @@
+// I2C bus with stuck-bus recovery and transaction retries, for amplifier and codec control.
+//
+// A device that was interrupted mid-transfer (e.g. by a reset of the MCU) can hold SDA low indefinitely. Recovery
+// clocks SCL as a GPIO until the device releases SDA, generates a stop condition, and then resets the I2C peripheral,
+// restoring its configuration. Failed transactions are retried with exponential backoff. Missing acknowledgements are
+// not retried, since they are expected for absent devices (e.g. during a bus scan).
+use defmt::{info, warn};
+use embassy_stm32::i2c::Error;
+use embassy_stm32::pac;
+use embassy_stm32::pac::gpio::vals::{Idr, Moder, Ot};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation};
+
+use crate::chip::SYSCLK_HZ;
+use crate::*;
+
+// All boards use I2C1, see `board::Irqs`.
+const I2C_REGS: pac::i2c::I2c = pac::I2C1;
+
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(1);
+
+// Clock pulses that release any device from an interrupted byte transfer (eight data bits and acknowledge).
+const RECOVERY_CLOCK_COUNT: usize = 9;
+
+// Half period of the recovery clock (100 kHz).
+const HALF_PERIOD_CYCLES: u32 = SYSCLK_HZ / 200_000;
+
+/// GPIO port and pin numbers of the I2C bus, for driving it manually during recovery.
+pub struct I2cPins {
+    pub port: pac::gpio::Gpio,
+    pub scl: usize,
+    pub sda: usize,
+}
+
+fn is_retryable(error: Error) -> bool {
+    !matches!(error, Error::Nack | Error::ZeroLengthTransfer)
+}
+
+fn is_sda_low(pins: &I2cPins) -> bool {
+    pins.port.idr().read().idr(pins.sda) == Idr::LOW
+}
+
+// Whether the peripheral is stuck in a busy state, or a device holds SDA low.
+fn is_bus_stuck(pins: &I2cPins) -> bool {
+    I2C_REGS.sr2().read().busy() || is_sda_low(pins)
+}
+
+fn set_line(pins: &I2cPins, pin: usize, high: bool) {
+    pins.port
+        .bsrr()
+        .write(|w| if high { w.set_bs(pin, true) } else { w.set_br(pin, true) });
+    cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+}
+
+// Clock out stuck devices, generate a stop condition, and reset the peripheral.
+fn recover(pins: &I2cPins) {
+    I2C_REGS.cr1().modify(|w| w.set_pe(false));
+
+    // Drive both lines as open-drain GPIOs, starting released.
+    for pin in [pins.scl, pins.sda] {
+        pins.port.bsrr().write(|w| w.set_bs(pin, true));
+        pins.port.otyper().modify(|w| w.set_ot(pin, Ot::OPENDRAIN));
+        pins.port.moder().modify(|w| w.set_moder(pin, Moder::OUTPUT));
+    }
+
+    for _ in 0..RECOVERY_CLOCK_COUNT {
+        if !is_sda_low(pins) {
+            break;
+        }
+
+        set_line(pins, pins.scl, false);
+        set_line(pins, pins.scl, true);
+    }
+
+    // Stop condition: SDA rises while SCL is high.
+    set_line(pins, pins.scl, false);
+    set_line(pins, pins.sda, false);
+    set_line(pins, pins.scl, true);
+    set_line(pins, pins.sda, true);
+
+    for pin in [pins.scl, pins.sda] {
+        pins.port.moder().modify(|w| w.set_moder(pin, Moder::ALTERNATE));
+    }
+
+    // A software reset clears the timing configuration, so it is restored afterwards.
+    let cr2 = I2C_REGS.cr2().read();
+    let ccr = I2C_REGS.ccr().read();
+    let trise = I2C_REGS.trise().read();
+    let oar1 = I2C_REGS.oar1().read();
+
+    I2C_REGS.cr1().modify(|w| w.set_swrst(true));
+    I2C_REGS.cr1().modify(|w| w.set_swrst(false));
+
+    I2C_REGS.cr2().write_value(cr2);
+    I2C_REGS.ccr().write_value(ccr);
+    I2C_REGS.trise().write_value(trise);
+    I2C_REGS.oar1().write_value(oar1);
+    I2C_REGS.cr1().modify(|w| w.set_pe(true));
+}
+
+/// The I2C peripheral, wrapped with bus recovery and retries.
+pub struct RecoveringI2c {
+    i2c: I2cPeripheral,
+    pins: I2cPins,
+    recovery_count: u32,
+}
+
+impl RecoveringI2c {
+    pub fn new(i2c: I2cPeripheral, pins: I2cPins) -> Self {
+        let mut bus = Self {
+            i2c,
+            pins,
+            recovery_count: 0,
+        };
+
+        // Devices may still hold the bus from before a reset.
+        if is_bus_stuck(&bus.pins) {
+            warn!("I2C bus is stuck at boot");
+            bus.recover();
+        }
+
+        bus
+    }
+
+    fn recover(&mut self) {
+        recover(&self.pins);
+        self.recovery_count += 1;
+
+        if is_bus_stuck(&self.pins) {
+            warn!("I2C bus recovery failed");
+        } else {
+            info!("I2C bus recovered ({} recoveries)", self.recovery_count);
+        }
+    }
+}
+
+impl ErrorType for RecoveringI2c {
+    type Error = Error;
+}
+
+impl I2c for RecoveringI2c {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+
+        loop {
+            let error = match self.i2c.transaction(address, operations).await {
+                Ok(()) => return Ok(()),
+                Err(e) if !is_retryable(e) => return Err(e),
+                Err(e) if attempt >= MAX_ATTEMPTS => {
+                    warn!("I2C transaction with {:#x} failed: {}", address, e);
+                    return Err(e);
+                }
+                Err(e) => e,
+            };
+
+            log_debug!(
+                "I2C transaction with {:#x} failed ({}), attempt {} of {}",
+                address,
+                error,
+                attempt,
+                MAX_ATTEMPTS
+            );
+
+            if matches!(error, Error::Bus | Error::Arbitration | Error::Timeout) || is_bus_stuck(&self.pins) {
+                self.recover();
+            }
+
+            Timer::after(backoff).await;
+            backoff = backoff * 2;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// Boot-time I2C diagnostics: bus scan and presence check of expected devices.
+use defmt::{error, info, warn};
+use embedded_hal_async::i2c::I2c;
+use heapless::Vec;
+
+// Non-reserved 7 bit addresses.
+const FIRST_ADDRESS: u8 = 0x08;
+const LAST_ADDRESS: u8 = 0x77;
+const ADDRESS_COUNT: usize = (LAST_ADDRESS - FIRST_ADDRESS + 1) as usize;
+
+pub struct ExpectedDevice {
+    pub name: &'static str,
+    pub address: u8,
+
+    // Output is not unmuted, if a critical device is missing.
+    pub critical: bool,
+}
+
+// Probe a single address by reading one byte, which only succeeds if the address is acknowledged.
+pub async fn probe<I2C: I2c>(i2c: &mut I2C, address: u8) -> bool {
+    let mut buffer = [0u8];
+    i2c.read(address, &mut buffer).await.is_ok()
+}
+
+// Find all responding devices on the bus.
+pub async fn scan<I2C: I2c>(i2c: &mut I2C) -> Vec<u8, ADDRESS_COUNT> {
+    let mut addresses = Vec::new();
+
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        if probe(i2c, address).await {
+            addresses.push(address).unwrap();
+        }
+    }
+
+    addresses
+}
+
+/// Scan the bus and check that all expected devices respond.
+///
+/// Returns `false`, if a critical device is missing.
+pub async fn check_devices<I2C: I2c>(i2c: &mut I2C, devices: &[ExpectedDevice]) -> bool {
+    let addresses = scan(i2c).await;
+    info!("I2C devices found at {:#x}", addresses.as_slice());
+
+    let mut success = true;
+
+    for device in devices {
+        if addresses.contains(&device.address) {
+            info!("Found {} at {:#x}", device.name, device.address);
+        } else if device.critical {
+            error!("Missing critical device {} at {:#x}", device.name, device.address);
+            success = false;
+        } else {
+            warn!("Missing device {} at {:#x}", device.name, device.address);
+        }
+    }
+
+    success
+}
@@ -0,0 +1,203 @@
+// I2S clock management: switches the I2S PLL between the 44.1 kHz and 48 kHz families of sample rates.
+//
+// Every family has an I2S PLL setting, whose clock is an (almost) integer multiple of all of the family's rates. A
+// rate is set by the I2S prescaler, `I2SCLK / (RATIO * (2 * I2SDIV + ODD))`, with a ratio of 256 with MCLK output (see
+// `mclk`), or 64 without (two 32 bit channels). The PLL is only reprogrammed, when the family changes. Rates whose
+// clock error exceeds `MAX_CLOCK_ERROR_PPM` are rejected, and the error of every configured rate is logged.
+//
+// All boards' clock trees provide a 1 MHz PLL input clock, so that `I2SCLK = 1 MHz * PLLI2SN / PLLI2SR`. At boot,
+// the clock tree is set up for the 48 kHz family (see `chip`).
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_stm32::pac;
+use embassy_stm32::pac::rcc::vals::{Plli2sn, Plli2sr};
+use embassy_stm32::pac::spi::vals::Odd;
+
+#[cfg(feature = "dual-zone")]
+use crate::board::ZONE_2_I2S_SPI;
+use crate::board::{I2S_SPI, MCLK_ENABLED};
+use crate::mclk::MCLK_FS_RATIO;
+
+/// Largest accepted deviation of a sample rate from its nominal value.
+pub const MAX_CLOCK_ERROR_PPM: u32 = 500;
+
+// Bit clocks per frame without MCLK output, for two 32 bit channels.
+const BIT_CLOCK_FS_RATIO: u32 = 64;
+
+// Range of the prescaler's division `2 * I2SDIV + ODD`, for an I2SDIV of 2 to 255.
+const PRESCALER_RANGE: RangeInclusive<u32> = 4..=511;
+
+const PLL_INPUT_HZ: u32 = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum Family {
+    /// 22.05, 44.1, 88.2 kHz
+    Rate44k1 = 0,
+    /// 16, 32, 48, 96 kHz
+    Rate48k = 1,
+}
+
+impl Family {
+    /// The family of a sample rate, if any.
+    pub fn of(sample_rate_hz: u32) -> Option<Self> {
+        if sample_rate_hz % 11_025 == 0 {
+            Some(Self::Rate44k1)
+        } else if sample_rate_hz % 8_000 == 0 {
+            Some(Self::Rate48k)
+        } else {
+            None
+        }
+    }
+
+    fn pll_setting(self) -> PllSetting {
+        match self {
+            // 135.5 MHz, 12 * 256 * 44.108 kHz (+0.02 %)
+            Family::Rate44k1 => PllSetting {
+                plli2s_n: 271,
+                plli2s_r: 2,
+            },
+            // 172 MHz, 14 * 256 * 47.991 kHz (-0.02 %)
+            Family::Rate48k => PllSetting {
+                plli2s_n: 344,
+                plli2s_r: 2,
+            },
+        }
+    }
+
+    /// The I2S PLL's VCO clock.
+    pub fn vco_hz(self) -> u32 {
+        PLL_INPUT_HZ * self.pll_setting().plli2s_n as u32
+    }
+}
+
+struct PllSetting {
+    plli2s_n: u16,
+    plli2s_r: u8,
+}
+
+impl PllSetting {
+    fn i2s_clock_hz(&self) -> u32 {
+        PLL_INPUT_HZ * self.plli2s_n as u32 / self.plli2s_r as u32
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum ClockError {
+    /// The sample rate belongs to neither family.
+    UnsupportedSampleRate(u32),
+    /// The closest achievable sample rate deviates too much, or no divider is in range.
+    ExcessiveError { sample_rate_hz: u32, error_ppm: i32 },
+}
+
+// The configured family, as set up at boot.
+static FAMILY: AtomicU8 = AtomicU8::new(Family::Rate48k as u8);
+
+/// The family that the I2S PLL is configured for.
+pub fn family() -> Family {
+    match FAMILY.load(Relaxed) {
+        0 => Family::Rate44k1,
+        _ => Family::Rate48k,
+    }
+}
+
+/// The integer divider within a range, which brings a clock closest to a multiple of a sample rate, along with the
+/// resulting error of the sample rate.
+pub fn divider(
+    clock_hz: u32,
+    sample_rate_hz: u32,
+    fs_ratio: u32,
+    range: RangeInclusive<u32>,
+) -> Result<(u32, i32), ClockError> {
+    let target_hz = fs_ratio as u64 * sample_rate_hz as u64;
+    let divider = ((clock_hz as u64 + target_hz / 2) / target_hz) as u32;
+
+    let error_ppm = (clock_hz as i64 * 1_000_000 / (divider.max(1) as i64 * target_hz as i64) - 1_000_000) as i32;
+    if !range.contains(&divider) || error_ppm.unsigned_abs() > MAX_CLOCK_ERROR_PPM {
+        return Err(ClockError::ExcessiveError {
+            sample_rate_hz,
+            error_ppm,
+        });
+    }
+
+    Ok((divider, error_ppm))
+}
+
+// The family, I2S prescaler division, and clock error of a sample rate.
+fn setting(sample_rate_hz: u32) -> Result<(Family, u32, i32), ClockError> {
+    let family = Family::of(sample_rate_hz).ok_or(ClockError::UnsupportedSampleRate(sample_rate_hz))?;
+    let fs_ratio = if MCLK_ENABLED {
+        MCLK_FS_RATIO
+    } else {
+        BIT_CLOCK_FS_RATIO
+    };
+
+    let (division, error_ppm) = divider(
+        family.pll_setting().i2s_clock_hz(),
+        sample_rate_hz,
+        fs_ratio,
+        PRESCALER_RANGE,
+    )?;
+
+    Ok((family, division, error_ppm))
+}
+
+/// Whether I2S output at a sample rate is possible within the clock error limit.
+pub fn is_supported(sample_rate_hz: u32) -> bool {
+    setting(sample_rate_hz).is_ok()
+}
+
+// Write an I2S peripheral's prescaler.
+fn set_prescaler(spi: pac::spi::Spi, division: u32, master_clock: bool) {
+    spi.i2spr().write(|w| {
+        w.set_i2sdiv((division / 2) as u8);
+        w.set_odd(if division % 2 == 1 { Odd::ODD } else { Odd::EVEN });
+        w.set_mckoe(master_clock);
+    });
+}
+
+/// Reconfigure the I2S clock tree for a new sample rate. The I2S peripheral must be stopped.
+pub fn set_sample_rate(sample_rate_hz: u32) -> Result<(), ClockError> {
+    let (family, division, error_ppm) = setting(sample_rate_hz)?;
+
+    critical_section::with(|_| {
+        if family != self::family() {
+            let pll_setting = family.pll_setting();
+
+            pac::RCC.cr().modify(|w| w.set_plli2son(false));
+            while pac::RCC.cr().read().plli2srdy() {}
+
+            pac::RCC.plli2scfgr().modify(|w| {
+                w.set_plli2sn(Plli2sn::from_bits(pll_setting.plli2s_n));
+                w.set_plli2sr(Plli2sr::from_bits(pll_setting.plli2s_r));
+            });
+
+            pac::RCC.cr().modify(|w| w.set_plli2son(true));
+            while !pac::RCC.cr().read().plli2srdy() {}
+
+            FAMILY.store(family as u8, Relaxed);
+        }
+
+        set_prescaler(I2S_SPI, division, MCLK_ENABLED);
+
+        // The second zone has no MCLK output, so that its prescaler also divides by the ratio of MCLK to bit clock.
+        #[cfg(feature = "dual-zone")]
+        set_prescaler(
+            ZONE_2_I2S_SPI,
+            if MCLK_ENABLED {
+                division * MCLK_FS_RATIO / BIT_CLOCK_FS_RATIO
+            } else {
+                division
+            },
+            false,
+        );
+    });
+
+    info!(
+        "I2S clock: {} Hz ({} family), error {} ppm",
+        sample_rate_hz, family, error_ppm
+    );
+
+    Ok(())
+}
@@ -0,0 +1,292 @@
+// External digital input: an S/PDIF or TOSLINK receiver (e.g. WM8804, DIR9001) as I2S master, received by SPI3 in I2S
+// slave mode.
+//
+// The receiver recovers the source's clock, which drifts against the output's. Its lock state is read from an unlock
+// (or error) output, and the sample rate is inferred by counting received frames against the system time. Samples are
+// expected as 24 bit Philips I2S in 32 bit slots (64 fs), which the DMA reads as two 16 bit words per sample.
+use blus_core::format::{from_words, WordOrder};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_stm32::dma::{ReadableRingBuffer, TransferOptions};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::pac::gpio::vals::{Idr, Moder};
+use embassy_stm32::pac::spi::vals::{Chlen, Datlen, I2scfg, I2sstd};
+use embassy_stm32::peripherals::{DMA1_CH2, SPI3};
+use embassy_stm32::{pac, rcc, spi};
+use embassy_time::{with_timeout, Duration, Instant};
+use static_cell::StaticCell;
+
+use crate::*;
+
+const REGS: pac::spi::Spi = pac::SPI3;
+
+const CHANNEL_COUNT: usize = 2;
+static_assertions::const_assert_eq!(CHANNEL_COUNT, INPUT_CHANNEL_COUNT);
+
+// Every sample is received as two 16 bit words, most significant first.
+const WORDS_PER_SAMPLE: usize = 2;
+
+/// Frames per block, 1 ms at the nominal sample rate.
+pub const FRAMES_PER_BLOCK: usize = (SAMPLE_RATE_HZ / 1000) as usize;
+
+// Reads are limited to the size of a USB packet.
+const MAX_READ_SIZE: usize = USB_SAMPLE_BLOCK_SAMPLE_COUNT;
+static_assertions::const_assert!((FRAMES_PER_BLOCK + 1) * CHANNEL_COUNT <= MAX_READ_SIZE);
+
+// DMA ring buffer of 8 blocks.
+const RING_BUFFER_SIZE: usize = 8 * FRAMES_PER_BLOCK * CHANNEL_COUNT * WORDS_PER_SAMPLE;
+
+/// Sample rates of S/PDIF sources, which measured rates are matched to.
+pub const NOMINAL_RATES_HZ: [u32; 5] = [32_000, 44_100, 48_000, 88_200, 96_000];
+
+// Measured rates match a nominal rate within 2 %, which covers the read granularity of a block.
+const RATE_TOLERANCE_DIVISOR: u32 = 50;
+
+// Frames are counted for this long, for inferring the sample rate.
+const MEASUREMENT_PERIOD: Duration = Duration::from_millis(200);
+
+// Reads fail, if the receiver provides no clock for this long.
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Polls of the word select line for a level, before giving up (a few frames at the lowest rate, with several cycles
+// per poll).
+const SYNC_POLL_COUNT: u32 = 4 * chip::SYSCLK_HZ / 32_000 / 8;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum InputError {
+    /// The receiver is not locked to a source.
+    Unlocked,
+    /// The receiver provides no clock.
+    NoClock,
+    /// The reader fell behind, or reception lost its frame alignment.
+    Overrun,
+}
+
+/// GPIO port and pin number of an I2S line.
+#[derive(Clone, Copy)]
+pub struct I2sPin {
+    pub port: pac::gpio::Gpio,
+    pub pin: usize,
+}
+
+/// I2S lines of the receiver, which are configured for their alternate function.
+pub struct I2sInputPins {
+    pub ws: I2sPin,
+    pub ck: I2sPin,
+    pub sd: I2sPin,
+    pub af: u8,
+}
+
+// Receiver state, for status requests.
+static LOCKED: AtomicBool = AtomicBool::new(false);
+static INFERRED_RATE_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the receiver is locked, and its inferred sample rate, while the input is received.
+pub fn status() -> (bool, Option<u32>) {
+    let rate_hz = INFERRED_RATE_HZ.load(Relaxed);
+    (LOCKED.load(Relaxed), (rate_hz != 0).then_some(rate_hz))
+}
+
+pub struct I2sInput {
+    dma: DMA1_CH2,
+    ws: I2sPin,
+    unlock: ExtiInput<'static>,
+    unlock_active_low: bool,
+    buffer: &'static mut [u16; RING_BUFFER_SIZE],
+}
+
+impl I2sInput {
+    /// Receive from SPI3 in I2S slave mode. The receiver's unlock output is high (or low) while it is not locked.
+    pub fn new(
+        _spi: SPI3,
+        dma: DMA1_CH2,
+        pins: I2sInputPins,
+        unlock: ExtiInput<'static>,
+        unlock_active_low: bool,
+    ) -> Self {
+        for line in [pins.ws, pins.ck, pins.sd] {
+            line.port.afr(line.pin / 8).modify(|w| w.set_afr(line.pin % 8, pins.af));
+            line.port.moder().modify(|w| w.set_moder(line.pin, Moder::ALTERNATE));
+        }
+
+        rcc::enable_and_reset::<SPI3>();
+        REGS.i2scfgr().write(|w| {
+            w.set_i2smod(true);
+            w.set_i2scfg(I2scfg::SLAVERX);
+            w.set_i2sstd(I2sstd::PHILIPS);
+            w.set_datlen(Datlen::TWENTYFOURBIT);
+            w.set_chlen(Chlen::THIRTYTWOBIT);
+        });
+        REGS.cr2().modify(|w| w.set_rxdmaen(true));
+
+        static BUFFER: StaticCell<[u16; RING_BUFFER_SIZE]> = StaticCell::new();
+
+        Self {
+            dma,
+            ws: pins.ws,
+            unlock,
+            unlock_active_low,
+            buffer: BUFFER.init([0; RING_BUFFER_SIZE]),
+        }
+    }
+
+    /// Start receiving. Reception stops, when the stream is dropped.
+    pub fn start(&mut self) -> I2sStream<'_> {
+        let request = spi::RxDma::<SPI3>::request(&self.dma);
+
+        // SAFETY: The ring buffer is dropped along with the stream, before the DMA channel and buffer are reused.
+        let mut ring = unsafe {
+            ReadableRingBuffer::new(
+                &mut self.dma,
+                request,
+                REGS.dr().as_ptr() as *mut u16,
+                &mut self.buffer[..],
+                TransferOptions::default(),
+            )
+        };
+        ring.start();
+
+        let mut stream = I2sStream {
+            ring,
+            ws: self.ws,
+            unlock: &self.unlock,
+            unlock_active_low: self.unlock_active_low,
+            measurement_start: Instant::now(),
+            measured_frames: 0,
+            sample_rate_hz: None,
+        };
+
+        // Fails without a clock, which the next read reports.
+        _ = stream.restart();
+
+        stream
+    }
+}
+
+pub struct I2sStream<'a> {
+    ring: ReadableRingBuffer<'a, u16>,
+    ws: I2sPin,
+    unlock: &'a ExtiInput<'static>,
+    unlock_active_low: bool,
+    measurement_start: Instant,
+    measured_frames: u32,
+    sample_rate_hz: Option<u32>,
+}
+
+impl I2sStream<'_> {
+    fn is_locked(&self) -> bool {
+        self.unlock.is_high() == self.unlock_active_low
+    }
+
+    // Wait for a level of the word select line, with a bounded number of polls.
+    fn wait_for_ws(&self, level: Idr) -> Result<(), InputError> {
+        for _ in 0..SYNC_POLL_COUNT {
+            if self.ws.port.idr().read().idr(self.ws.pin) == level {
+                return Ok(());
+            }
+        }
+
+        Err(InputError::NoClock)
+    }
+
+    // Restart reception at a frame boundary. In Philips mode, word select is high during the right channel, and the
+    // slave must be enabled during that time, so that the first received sample is a left one.
+    fn restart(&mut self) -> Result<(), InputError> {
+        REGS.i2scfgr().modify(|w| w.set_i2se(false));
+
+        // An overrun is cleared by reading the data register, followed by the status register.
+        _ = REGS.dr().read();
+        _ = REGS.sr().read();
+        self.ring.clear();
+        self.reset_measurement();
+
+        critical_section::with(|_| {
+            self.wait_for_ws(Idr::LOW)?;
+            self.wait_for_ws(Idr::HIGH)?;
+            REGS.i2scfgr().modify(|w| w.set_i2se(true));
+
+            Ok(())
+        })
+    }
+
+    // Infer the sample rate anew, e.g. after the receiver lost its lock.
+    fn reset_measurement(&mut self) {
+        self.measurement_start = Instant::now();
+        self.measured_frames = 0;
+        self.sample_rate_hz = None;
+        INFERRED_RATE_HZ.store(0, Relaxed);
+    }
+
+    // Count received frames, and match their rate to a nominal rate after every measurement period.
+    fn measure(&mut self, frame_count: usize) {
+        self.measured_frames += frame_count as u32;
+
+        let elapsed = self.measurement_start.elapsed();
+        if elapsed < MEASUREMENT_PERIOD {
+            return;
+        }
+
+        let measured_hz = (self.measured_frames as u64 * 1_000_000 / elapsed.as_micros()) as u32;
+        let sample_rate_hz = NOMINAL_RATES_HZ
+            .into_iter()
+            .find(|&nominal_hz| nominal_hz.abs_diff(measured_hz) <= nominal_hz / RATE_TOLERANCE_DIVISOR);
+
+        if sample_rate_hz != self.sample_rate_hz {
+            info!("I2S input rate: {} Hz (measured {} Hz)", sample_rate_hz, measured_hz);
+            self.sample_rate_hz = sample_rate_hz;
+            INFERRED_RATE_HZ.store(sample_rate_hz.unwrap_or(0), Relaxed);
+        }
+
+        self.measurement_start = Instant::now();
+        self.measured_frames = 0;
+    }
+
+    /// The sample rate of the source, once inferred.
+    pub fn sample_rate_hz(&self) -> Option<u32> {
+        self.sample_rate_hz
+    }
+
+    /// Read whole frames as Q31 samples, in channel order (at most a USB packet's worth). Waits for the receiver, and
+    /// restarts reception after an error.
+    pub async fn read(&mut self, samples: &mut [i32]) -> Result<(), InputError> {
+        assert!(samples.len() % CHANNEL_COUNT == 0);
+
+        let mut words = [0u16; MAX_READ_SIZE * WORDS_PER_SAMPLE];
+        let words = &mut words[..samples.len() * WORDS_PER_SAMPLE];
+
+        let result = match with_timeout(CLOCK_TIMEOUT, self.ring.read_exact(words)).await {
+            Err(_) => Err(InputError::NoClock),
+            // A frame error means that word select changed unexpectedly.
+            Ok(Ok(_)) if !REGS.sr().read().fre() => Ok(()),
+            Ok(_) => Err(InputError::Overrun),
+        };
+
+        let locked = self.is_locked();
+        LOCKED.store(locked, Relaxed);
+
+        if let Err(e) = result {
+            _ = self.restart();
+            return Err(e);
+        }
+        if !locked {
+            self.reset_measurement();
+            return Err(InputError::Unlocked);
+        }
+
+        for (sample, words) in samples.iter_mut().zip(words.chunks_exact(WORDS_PER_SAMPLE)) {
+            *sample = from_words([words[0], words[1]], WordOrder::HighFirst);
+        }
+
+        self.measure(samples.len() / CHANNEL_COUNT);
+
+        Ok(())
+    }
+}
+
+impl Drop for I2sStream<'_> {
+    fn drop(&mut self) {
+        REGS.i2scfgr().modify(|w| w.set_i2se(false));
+        LOCKED.store(false, Relaxed);
+        INFERRED_RATE_HZ.store(0, Relaxed);
+    }
+}
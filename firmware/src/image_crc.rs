@@ -0,0 +1,90 @@
+// Boot-time integrity check of the firmware image.
+//
+// A post-build step (`tools/patch_image_crc.py`) calculates a CRC over the vector table, code, and read-only data, and
+// stores it in the `.image_crc` section, which the linker script `image_crc.x` places right after them. At boot, the
+// CRC is recalculated with the CRC unit. The key-value store pages between vector table and code are excluded. Images
+// without a stored CRC (e.g. flashed by `cargo run`) are not checked.
+use core::ptr::addr_of;
+use defmt::{error, info, warn, Format};
+use embassy_stm32::crc::Crc;
+use embassy_stm32::peripherals;
+
+// Value of the CRC, before the post-build step.
+const UNPATCHED: u32 = 0xffff_ffff;
+
+/// Initial value of the CRC-32/MPEG-2, as calculated by the CRC unit.
+pub const CRC_INITIAL: u32 = 0xffff_ffff;
+const CRC_POLYNOMIAL: u32 = 0x04c1_1db7;
+
+/// Feed a word into a CRC-32/MPEG-2 in software, for data that is not memory-mapped (e.g. in external flash).
+pub fn crc_update(mut crc: u32, word: u32) -> u32 {
+    crc ^= word;
+    for _ in 0..32 {
+        crc = if crc & (1 << 31) != 0 {
+            (crc << 1) ^ CRC_POLYNOMIAL
+        } else {
+            crc << 1
+        };
+    }
+
+    crc
+}
+
+#[used]
+#[link_section = ".image_crc"]
+static IMAGE_CRC: u32 = UNPATCHED;
+
+extern "C" {
+    // Provided by the linker script `image_crc.x`.
+    static __image_start: u32;
+    static __image_end: u32;
+
+    // Provided by the linker script `kv_store.x`.
+    static __kv_store_start: u32;
+    static __kv_store_end: u32;
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum ImageCheck {
+    Valid,
+    Unchecked,
+    Corrupt,
+}
+
+/// Check the firmware image against its stored CRC.
+pub fn check(crc: peripherals::CRC) -> ImageCheck {
+    // SAFETY: The value is patched after linking, so it must not be constant-folded.
+    let expected = unsafe { addr_of!(IMAGE_CRC).read_volatile() };
+
+    if expected == UNPATCHED {
+        warn!("Firmware image has no CRC, not checked");
+        return ImageCheck::Unchecked;
+    }
+
+    // SAFETY: The linker scripts define word-aligned ranges in flash.
+    let (vector_table, code) = unsafe {
+        let words = |start: *const u32, end: *const u32| {
+            core::slice::from_raw_parts(start, (end as usize - start as usize) / 4)
+        };
+
+        (
+            words(addr_of!(__image_start), addr_of!(__kv_store_start)),
+            words(addr_of!(__kv_store_end), addr_of!(__image_end)),
+        )
+    };
+
+    let mut crc = Crc::new(crc);
+    crc.feed_words(vector_table);
+    let actual = crc.feed_words(code);
+
+    if actual == expected {
+        info!("Firmware image CRC is valid ({:#010x})", actual);
+        ImageCheck::Valid
+    } else {
+        error!(
+            "Firmware image CRC mismatch: {:#010x} instead of {:#010x}",
+            actual, expected
+        );
+        ImageCheck::Corrupt
+    }
+}
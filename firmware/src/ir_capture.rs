@@ -0,0 +1,118 @@
+// Captures the edges of an infrared receiver with a timer, and decodes NEC frames in the timer's interrupt.
+//
+// The timer counts microseconds, and captures its counter at both edges of the receiver output. Counter overflows are
+// tracked, so that idle times beyond the 16 bit counter range are never mistaken for short intervals.
+use core::cell::RefCell;
+
+use embassy_stm32::interrupt::typelevel::Interrupt;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::{FilterValue, InputCaptureMode, InputTISelection, Timer};
+use embassy_stm32::timer::{Channel, GeneralInstance4Channel};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::board::IrTimer;
+use crate::nec::{Decoder, Event};
+
+const TICK_RATE: Hertz = Hertz(1_000_000);
+
+pub struct IrCapture<T: GeneralInstance4Channel> {
+    timer: Timer<'static, T>,
+    channel: Channel,
+    decoder: Decoder,
+    last_capture: u16,
+    // Counter overflows since the last capture.
+    overflows: u8,
+}
+
+impl<T: GeneralInstance4Channel> IrCapture<T> {
+    /// Capture on a timer channel, whose pin must be configured for the timer's alternate function (e.g. by means of
+    /// an `input_capture::CapturePin`).
+    pub fn new(tim: T, channel: Channel) -> Self {
+        let timer = Timer::new(tim);
+        timer.set_tick_freq(TICK_RATE);
+
+        timer.set_input_ti_selection(channel, InputTISelection::Normal);
+        timer.set_input_capture_mode(channel, InputCaptureMode::BothEdges);
+        timer.set_input_capture_prescaler(channel, 0);
+
+        // Suppresses glitches of the receiver output.
+        timer.set_input_capture_filter(channel, FilterValue::FCK_INT_N8);
+
+        Self {
+            timer,
+            channel,
+            decoder: Decoder::new(),
+            last_capture: 0,
+            // Starts out idle.
+            overflows: u8::MAX,
+        }
+    }
+
+    /// Start capturing, with an interrupt per edge and per counter overflow.
+    pub fn start(&mut self) {
+        // Reset all interrupt flags.
+        self.timer.regs_gp16().sr().write(|r| r.0 = 0);
+
+        self.timer.enable_channel(self.channel, true);
+        self.timer.enable_input_interrupt(self.channel, true);
+        self.timer.enable_update_interrupt(true);
+        self.timer.start();
+
+        T::CaptureCompareInterrupt::unpend();
+        unsafe { T::CaptureCompareInterrupt::enable() };
+    }
+
+    /// Handle the timer interrupt, and return a decoded event, if any.
+    pub fn on_interrupt(&mut self) -> Option<Event> {
+        let regs = self.timer.regs_gp16();
+        let channel_index = self.channel.index();
+        let status = regs.sr().read();
+
+        // The capture flag is cleared by reading the capture register.
+        let capture = status.ccif(channel_index).then(|| regs.ccr(channel_index).read().ccr());
+        let overflow = status.uif();
+        if overflow {
+            regs.sr().modify(|r| r.set_uif(false));
+        }
+
+        // With both pending, a capture in the lower half of the counter range happened after the overflow.
+        let overflow_first = overflow && capture.map_or(true, |capture| capture < u16::MAX / 2);
+        if overflow_first {
+            self.overflows = self.overflows.saturating_add(1);
+        }
+
+        let event = capture.and_then(|capture| {
+            // A single overflow is covered by the wrapping difference, if the counter did not pass the last capture.
+            let duration_us = if self.overflows > 1 || (self.overflows == 1 && capture >= self.last_capture) {
+                u32::MAX
+            } else {
+                capture.wrapping_sub(self.last_capture) as u32
+            };
+
+            self.last_capture = capture;
+            self.overflows = 0;
+
+            self.decoder.update(duration_us)
+        });
+
+        if overflow && !overflow_first {
+            self.overflows = 1;
+        }
+
+        event
+    }
+}
+
+static IR_CAPTURE: Mutex<CriticalSectionRawMutex, RefCell<Option<IrCapture<IrTimer>>>> = Mutex::new(RefCell::new(None));
+
+/// Start capturing, and hand over the capture to the interrupt handler.
+pub fn start(mut ir_capture: IrCapture<IrTimer>) {
+    ir_capture.start();
+    IR_CAPTURE.lock(|cell| cell.borrow_mut().replace(ir_capture));
+}
+
+/// Called from the timer's interrupt handler. Returns a decoded event, if any.
+pub fn on_interrupt() -> Option<Event> {
+    IR_CAPTURE.lock(|cell| cell.borrow_mut().as_mut().and_then(IrCapture::on_interrupt))
+}
@@ -0,0 +1,129 @@
+// Infrared remote control, mapping NEC key codes to device actions.
+//
+// Codes are learned per action (via vendor request), and stored with the settings, so that any NEC remote can be
+// used. Held volume keys repeat, other actions are performed once per key press.
+use core::cell::Cell;
+use defmt::{info, Format};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::hid::ConsumerKey;
+use crate::nec::{Code, Event};
+use crate::*;
+
+// Volume change per key press or repeat frame, until the host responds.
+const VOLUME_STEP_DB: f32 = 1.0;
+
+// Repeat frames follow every 108 ms. Later ones belong to a different key press, whose code was missed.
+const REPEAT_TIMEOUT: Duration = Duration::from_millis(150);
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum IrAction {
+    VolumeUp,
+    VolumeDown,
+    ToggleMute,
+    NextInput,
+}
+
+/// Actions in the order of their indices, as used by vendor requests and the settings.
+pub const ACTIONS: [IrAction; 4] = [
+    IrAction::VolumeUp,
+    IrAction::VolumeDown,
+    IrAction::ToggleMute,
+    IrAction::NextInput,
+];
+pub const ACTION_COUNT: usize = ACTIONS.len();
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct UnknownAction(pub usize);
+
+// The action, whose code is learned from the next key press.
+static LEARNING: Mutex<CriticalSectionRawMutex, Cell<Option<IrAction>>> = Mutex::new(Cell::new(None));
+
+/// Learn the code of the action with the given index from the next key press.
+pub fn learn(index: usize) -> Result<(), UnknownAction> {
+    let action = *ACTIONS.get(index).ok_or(UnknownAction(index))?;
+
+    info!("Learning IR code for {}", action);
+    LEARNING.lock(|learning| learning.set(Some(action)));
+
+    Ok(())
+}
+
+/// The learned code of the action with the given index.
+pub fn code(index: usize) -> Result<Option<Code>, UnknownAction> {
+    settings::get().ir_codes.get(index).copied().ok_or(UnknownAction(index))
+}
+
+fn perform(action: IrAction) {
+    // A key only wakes the device from sleep.
+    if sleep_timer::activity() {
+        return;
+    }
+
+    let volume_step = |step_db, key| {
+        trim::adjust_master_volume(step_db);
+
+        // Keys are dropped, if the host does not read them.
+        let _ = CONSUMER_KEY_CHANNEL.try_send(key);
+    };
+
+    match action {
+        IrAction::VolumeUp => volume_step(VOLUME_STEP_DB, ConsumerKey::VolumeUp),
+        IrAction::VolumeDown => volume_step(-VOLUME_STEP_DB, ConsumerKey::VolumeDown),
+        IrAction::ToggleMute => trim::toggle_mute(),
+        IrAction::NextInput => source::select_next(),
+    }
+}
+
+/// Performs the actions of received key codes, or learns codes.
+#[embassy_executor::task]
+pub async fn remote_task() {
+    // The last action and the time of its last frame, for repeats.
+    let mut last: Option<(IrAction, Instant)> = None;
+
+    loop {
+        let event = IR_EVENT_CHANNEL.receive().await;
+        log_debug!("IR: {}", event);
+
+        let code = match event {
+            Event::Code(code) => code,
+            Event::Repeat => {
+                last = last
+                    .filter(|(_, time)| time.elapsed() < REPEAT_TIMEOUT)
+                    .map(|(action, _)| {
+                        if matches!(action, IrAction::VolumeUp | IrAction::VolumeDown) {
+                            perform(action);
+                        }
+                        (action, Instant::now())
+                    });
+                continue;
+            }
+        };
+
+        if let Some(action) = LEARNING.lock(|learning| learning.take()) {
+            info!("Learned IR code {} for {}", code, action);
+            settings::modify(|settings| {
+                // A code triggers only a single action.
+                for learned in settings.ir_codes.iter_mut().filter(|learned| **learned == Some(code)) {
+                    *learned = None;
+                }
+                settings.ir_codes[action as usize] = Some(code);
+            });
+            last = None;
+            continue;
+        }
+
+        let learned = settings::get().ir_codes;
+        last = ACTIONS
+            .iter()
+            .zip(learned)
+            .find(|(_, learned)| *learned == Some(code))
+            .map(|(&action, _)| (action, Instant::now()));
+
+        if let Some((action, _)) = last {
+            perform(action);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// Key-value store on two pages of the internal flash (sectors 1 and 2, 16 kiB each), emulating an EEPROM.
+//
+// Values are appended as sequence-numbered records to the active page, so changing a value rarely erases flash, and
+// wear is spread over all records of both pages. When the active page is full, the latest record of every key is
+// copied to the other (erased) page, which becomes active. Erasing stalls the CPU on flash access for a few hundred
+// milliseconds, so the stale page is erased at boot, before the watchdog starts, if possible. Otherwise, it is erased
+// on demand, when the active page runs full at runtime, which stays well within the watchdog's timeout. The linker
+// script `kv_store.x` keeps the application out of both pages. The record format is hardware-independent (see
+// `blus-core`).
+use blus_core::record::{is_erased, Record, RECORD_SIZE};
+use defmt::{info, warn, Format};
+use embassy_stm32::flash::{self, Blocking, Flash};
+use heapless::Vec;
+
+pub use blus_core::record::{KEY_COUNT, VALUE_SIZE};
+
+// Must match `kv_store.x`.
+const PAGE_SIZE: u32 = 16 * 1024;
+const PAGE_OFFSETS: [u32; 2] = [16 * 1024, 32 * 1024];
+
+const RECORDS_PER_PAGE: u32 = PAGE_SIZE / RECORD_SIZE as u32;
+
+// The active page is compacted at boot, if fewer free records are left.
+const COMPACTION_THRESHOLD: u32 = RECORDS_PER_PAGE / 8;
+
+// Corrupt records that a healthy store may hold: an interrupted write leaves one, while more point to failing flash.
+const MAX_CORRUPT_RECORDS: u32 = 1;
+
+pub type Value = Vec<u8, VALUE_SIZE>;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum KvError {
+    UnknownKey,
+    ValueTooLong,
+    /// Both pages are in use, since the stale one could not be erased.
+    Full,
+    Flash(flash::Error),
+}
+
+pub struct KvStore {
+    flash: Flash<'static, Blocking>,
+    values: [Option<Value>; KEY_COUNT],
+    active_page: usize,
+    next_record: u32,
+    next_sequence: u32,
+    spare_page_erased: bool,
+    // Corrupt records and failed flash operations, while opening the store.
+    corrupt_records: u32,
+    flash_errors: u32,
+}
+
+impl KvStore {
+    /// Open the store, completing an interrupted compaction and erasing the stale page. Must be called before the
+    /// watchdog is started.
+    pub fn open(flash: Flash<'static, Blocking>) -> Self {
+        let mut store = Self {
+            flash,
+            values: Default::default(),
+            active_page: 0,
+            next_record: 0,
+            next_sequence: 0,
+            spare_page_erased: false,
+            corrupt_records: 0,
+            flash_errors: 0,
+        };
+
+        // Sequence number and page of the latest record per key, and of the latest record and used records per page.
+        let mut latest: [Option<(u32, usize)>; KEY_COUNT] = [None; KEY_COUNT];
+        let mut page_sequence = [None; 2];
+        let mut page_used = [RECORDS_PER_PAGE; 2];
+
+        for page in 0..2 {
+            for index in 0..RECORDS_PER_PAGE {
+                let bytes = store.read_record(page, index);
+
+                if is_erased(&bytes) {
+                    page_used[page] = index;
+                    break;
+                }
+
+                // Invalid records (e.g. from an interrupted write) are skipped.
+                let Some(record) = Record::decode(&bytes) else {
+                    store.corrupt_records += 1;
+                    continue;
+                };
+
+                page_sequence[page] = page_sequence[page].max(Some(record.sequence));
+
+                let key = record.key as usize;
+                if latest[key].map_or(true, |(sequence, _)| record.sequence > sequence) {
+                    latest[key] = Some((record.sequence, page));
+                    store.values[key] = Some(Vec::from_slice(record.value()).unwrap());
+                }
+            }
+        }
+
+        store.active_page = if page_sequence[1] > page_sequence[0] { 1 } else { 0 };
+        store.next_record = page_used[store.active_page];
+        store.next_sequence = page_sequence[0]
+            .max(page_sequence[1])
+            .map_or(0, |sequence| sequence + 1);
+
+        let spare_page = 1 - store.active_page;
+        if page_used[spare_page] > 0 {
+            // Complete an interrupted compaction, by moving values that were not copied yet.
+            for key in 0..KEY_COUNT {
+                if latest[key].is_some_and(|(_, page)| page == spare_page) {
+                    let value = store.values[key].clone().unwrap();
+                    if let Err(e) = store.append(key as u8, &value) {
+                        warn!("Failed to move value {}: {}", key, e);
+                    }
+                }
+            }
+
+            store.erase_spare_page();
+        } else {
+            store.spare_page_erased = true;
+        }
+
+        if RECORDS_PER_PAGE - store.next_record < COMPACTION_THRESHOLD && store.compact().is_ok() {
+            store.erase_spare_page();
+        }
+
+        info!(
+            "Key-value store: page {}, {} of {} records used, {} corrupt",
+            store.active_page, store.next_record, RECORDS_PER_PAGE, store.corrupt_records
+        );
+
+        store
+    }
+
+    fn record_offset(&self, page: usize, index: u32) -> u32 {
+        PAGE_OFFSETS[page] + index * RECORD_SIZE as u32
+    }
+
+    fn read_record(&mut self, page: usize, index: u32) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+
+        if let Err(e) = self.flash.blocking_read(self.record_offset(page, index), &mut bytes) {
+            warn!("Failed to read key-value record: {}", e);
+            self.flash_errors += 1;
+        }
+
+        bytes
+    }
+
+    // Append a record to the active page, without compaction.
+    fn append(&mut self, key: u8, value: &[u8]) -> Result<(), KvError> {
+        if self.next_record >= RECORDS_PER_PAGE {
+            return Err(KvError::Full);
+        }
+
+        let record = Record::new(self.next_sequence, key, value).ok_or(KvError::ValueTooLong)?;
+
+        let offset = self.record_offset(self.active_page, self.next_record);
+        self.next_record += 1;
+        self.next_sequence += 1;
+
+        self.flash
+            .blocking_write(offset, &record.encode())
+            .map_err(KvError::Flash)
+    }
+
+    // Copy the latest values to the spare page, which becomes the active page. The spare page is erased first, if it
+    // still holds the stale records of the last compaction.
+    fn compact(&mut self) -> Result<(), KvError> {
+        if !self.spare_page_erased {
+            self.erase_spare_page();
+        }
+        if !self.spare_page_erased {
+            return Err(KvError::Full);
+        }
+
+        info!("Compacting key-value store");
+
+        self.active_page = 1 - self.active_page;
+        self.next_record = 0;
+        self.spare_page_erased = false;
+
+        for key in 0..KEY_COUNT {
+            if let Some(value) = self.values[key].clone() {
+                self.append(key as u8, &value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn erase_spare_page(&mut self) {
+        let offset = PAGE_OFFSETS[1 - self.active_page];
+
+        match self.flash.blocking_erase(offset, offset + PAGE_SIZE) {
+            Ok(()) => self.spare_page_erased = true,
+            Err(e) => {
+                warn!("Failed to erase key-value page: {}", e);
+                self.flash_errors += 1;
+            }
+        }
+    }
+
+    /// Whether opening the store met no flash errors, and at most one corrupt record.
+    pub fn is_healthy(&self) -> bool {
+        self.flash_errors == 0 && self.corrupt_records <= MAX_CORRUPT_RECORDS
+    }
+
+    /// The latest value of a key, if it was ever written.
+    pub fn read(&self, key: u8) -> Option<&[u8]> {
+        self.values.get(key as usize)?.as_deref()
+    }
+
+    /// Write the value of a key. Unchanged values are not written again.
+    pub fn write(&mut self, key: u8, value: &[u8]) -> Result<(), KvError> {
+        if key as usize >= KEY_COUNT {
+            return Err(KvError::UnknownKey);
+        }
+        if value.len() > VALUE_SIZE {
+            return Err(KvError::ValueTooLong);
+        }
+        if self.read(key) == Some(value) {
+            return Ok(());
+        }
+
+        if self.next_record >= RECORDS_PER_PAGE {
+            self.compact()?;
+        }
+
+        self.append(key, value)?;
+        self.values[key as usize] = Some(Vec::from_slice(value).unwrap());
+
+        Ok(())
+    }
+}
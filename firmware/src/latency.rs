@@ -0,0 +1,56 @@
+// Latency measurement between USB packet arrival and hand-over of its samples to the I2S DMA buffer.
+//
+// Every `MEASUREMENT_INTERVAL` packets, one sample block is marked on arrival. Streaming and output task count the
+// blocks that pass through the channel, so the output task can identify the marked block without tagging its data.
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use embassy_time::Instant;
+
+use crate::{stats, I2S_BUFFER_SIZE, SAMPLE_SIZE_PER_MS};
+
+// Number of packets between two measurements.
+const MEASUREMENT_INTERVAL: u32 = 1000;
+
+// Duration of the I2S DMA ring buffer, which adds up to this much latency after hand-over.
+pub const DMA_BUFFER_LATENCY_US: u32 = (I2S_BUFFER_SIZE * 1000 / (SAMPLE_SIZE_PER_MS / 2)) as u32;
+
+const NO_MARK: u32 = u32::MAX;
+
+// Sequence numbers of blocks that were sent by the streaming task, and received by the output task.
+static SENT_SEQUENCE: AtomicU32 = AtomicU32::new(0);
+static RECEIVED_SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+// The marked block, and its arrival time in (truncated) ticks.
+static MARKED_SEQUENCE: AtomicU32 = AtomicU32::new(NO_MARK);
+static MARK_TICKS: AtomicU32 = AtomicU32::new(0);
+
+fn now_ticks() -> u32 {
+    Instant::now().as_ticks() as u32
+}
+
+/// Called by the streaming task for every block that is sent to the output task.
+pub fn block_sent(arrival: Instant) {
+    let sequence = SENT_SEQUENCE.fetch_add(1, Relaxed);
+
+    if sequence % MEASUREMENT_INTERVAL == 0 && MARKED_SEQUENCE.load(Relaxed) == NO_MARK {
+        MARK_TICKS.store(arrival.as_ticks() as u32, Relaxed);
+        MARKED_SEQUENCE.store(sequence, Relaxed);
+    }
+}
+
+/// Called by the output task for every block that is taken from the channel.
+///
+/// `played` is false for discarded blocks, which cancels a pending measurement.
+pub fn block_received(played: bool) {
+    let sequence = RECEIVED_SEQUENCE.fetch_add(1, Relaxed);
+
+    if sequence != MARKED_SEQUENCE.load(Relaxed) {
+        return;
+    }
+
+    if played {
+        let latency_ticks = now_ticks().wrapping_sub(MARK_TICKS.load(Relaxed));
+        stats::record_latency(latency_ticks);
+    }
+
+    MARKED_SEQUENCE.store(NO_MARK, Relaxed);
+}
@@ -1,44 +1,453 @@
 #![no_std]
 
+#[cfg(any(
+    all(feature = "board-custom", feature = "board-f4-discovery"),
+    all(feature = "board-custom", feature = "board-nucleo"),
+    all(feature = "board-custom", feature = "board-hs"),
+    all(feature = "board-f4-discovery", feature = "board-nucleo"),
+    all(feature = "board-f4-discovery", feature = "board-hs"),
+    all(feature = "board-nucleo", feature = "board-hs"),
+))]
+compile_error!("Only one board feature can be selected.");
+
+#[cfg(not(any(
+    feature = "board-custom",
+    feature = "board-f4-discovery",
+    feature = "board-nucleo",
+    feature = "board-hs"
+)))]
+compile_error!("A board feature must be selected.");
+
+#[cfg(any(
+    all(feature = "chip-f401", feature = "chip-f411"),
+    all(feature = "chip-f401", feature = "chip-f446"),
+    all(feature = "chip-f411", feature = "chip-f446"),
+))]
+compile_error!("Only one chip feature can be selected.");
+
+#[cfg(not(any(feature = "chip-f401", feature = "chip-f411", feature = "chip-f446")))]
+compile_error!("A chip feature must be selected, e.g. `stm32f401cc`.");
+
+#[cfg(any(
+    all(feature = "sof-timer-tim5", feature = "sof-timer-tim9"),
+    all(feature = "sof-timer-tim5", feature = "sof-timer-tim11"),
+    all(feature = "sof-timer-tim9", feature = "sof-timer-tim11"),
+))]
+compile_error!("Only one SOF timer feature can be selected.");
+
+#[cfg(all(feature = "sof-timer-tim11", feature = "fan-control"))]
+compile_error!("The fan uses TIM11 on PB9, and cannot be combined with `sof-timer-tim11`.");
+
+#[cfg(all(feature = "mclk-output", not(feature = "board-custom")))]
+compile_error!("The `mclk-output` feature is only available for the custom board.");
+
+#[cfg(all(feature = "front-panel-expander", not(feature = "board-custom")))]
+compile_error!("The `front-panel-expander` feature is only available for the custom board.");
+
+#[cfg(all(feature = "rotary-encoder", not(feature = "board-custom")))]
+compile_error!("The `rotary-encoder` feature is only available for the custom board.");
+
+#[cfg(all(feature = "status-ws2812", not(feature = "board-custom")))]
+compile_error!("The `status-ws2812` feature is only available for the custom board.");
+
+#[cfg(all(feature = "ir-remote", not(feature = "board-custom")))]
+compile_error!("The `ir-remote` feature is only available for the custom board.");
+
+#[cfg(all(feature = "aux-input", not(feature = "board-custom")))]
+compile_error!("The `aux-input` feature is only available for the custom board.");
+
+#[cfg(all(feature = "i2s-input", not(feature = "board-custom")))]
+compile_error!("The `i2s-input` feature is only available for the custom board.");
+
+#[cfg(all(feature = "i2s-input", any(feature = "status-ws2812", feature = "rotary-encoder")))]
+compile_error!(
+    "The `i2s-input` feature uses SPI3 and PB3, and cannot be combined with `status-ws2812` or `rotary-encoder`."
+);
+
+#[cfg(all(feature = "power-detect", not(feature = "board-custom")))]
+compile_error!("The `power-detect` feature is only available for the custom board.");
+
+#[cfg(all(feature = "spi-flash", not(feature = "board-custom")))]
+compile_error!("The `spi-flash` feature is only available for the custom board.");
+
+#[cfg(all(feature = "stereo-link", not(feature = "board-custom")))]
+compile_error!("The `stereo-link` features are only available for the custom board.");
+
+#[cfg(all(feature = "stereo-link-primary", feature = "stereo-link-secondary"))]
+compile_error!("A unit is either the primary or the secondary of a stereo link.");
+
+#[cfg(all(feature = "stereo-link", feature = "aux-input"))]
+compile_error!("The stereo link uses USART2 on PA2 and PA3, and cannot be combined with `aux-input`.");
+
+#[cfg(all(feature = "uart-control", not(feature = "board-custom")))]
+compile_error!("The `uart-control` feature is only available for the custom board.");
+
+#[cfg(all(
+    feature = "uart-control",
+    any(feature = "stereo-link", feature = "aux-input", feature = "status-ws2812")
+))]
+compile_error!(
+    "UART control uses USART2 on PA2 and PA3, and DMA1 stream 5. It cannot be combined with the stereo link, \
+     `aux-input`, or `status-ws2812`."
+);
+
+#[cfg(all(
+    feature = "stereo-link-secondary",
+    any(feature = "i2s-input", feature = "mclk-output", feature = "status-ws2812")
+))]
+compile_error!(
+    "The stereo link's secondary takes its clocks and samples from the primary, and uses DMA1 stream 5. It cannot be \
+     combined with `i2s-input`, `mclk-output`, or `status-ws2812`."
+);
+
+#[cfg(all(feature = "battery-monitor", not(feature = "board-custom")))]
+compile_error!("The `battery-monitor` feature is only available for the custom board.");
+
+#[cfg(all(
+    feature = "battery-monitor",
+    any(feature = "aux-input", feature = "stereo-link", feature = "uart-control")
+))]
+compile_error!(
+    "The battery is sensed with ADC1 on PA3. It cannot be combined with `aux-input`, the stereo link, or \
+     `uart-control`."
+);
+
+#[cfg(all(feature = "fan-control", not(feature = "board-custom")))]
+compile_error!("The `fan-control` feature is only available for the custom board.");
+
+#[cfg(all(feature = "fan-control", feature = "power-detect"))]
+compile_error!("The fan's PWM output and the supply sense share PB9.");
+
+#[cfg(all(feature = "msc-config", feature = "front-panel-expander"))]
+compile_error!(
+    "The configuration mode is entered with the wake-up button, which is read at boot, before the expander."
+);
+
+#[cfg(all(feature = "dual-zone", not(feature = "board-custom")))]
+compile_error!("The `dual-zone` feature is only available for the custom board.");
+
+#[cfg(all(
+    feature = "dual-zone",
+    any(
+        feature = "i2s-input",
+        feature = "status-ws2812",
+        feature = "rotary-encoder",
+        feature = "stereo-link-secondary",
+        feature = "uart-control"
+    )
+))]
+compile_error!(
+    "The second zone uses SPI3 on PA15, PB3, and PB5, and DMA1 stream 5. It cannot be combined with `i2s-input`, \
+     `status-ws2812`, `rotary-encoder`, the stereo link's secondary, or `uart-control`."
+);
+
+#[cfg(all(feature = "spdif-output", not(feature = "board-hs")))]
+compile_error!("The `spdif-output` feature is only available for the high-speed board.");
+
+#[cfg(all(feature = "usb-high-speed", not(feature = "chip-f446")))]
+compile_error!("High-speed USB requires an STM32F446.");
+
+pub mod alignment;
+pub mod amp_fault;
+pub mod amplifier;
+pub mod app;
+#[cfg(feature = "auto-eq")]
+pub mod auto_eq;
+pub mod aux_input;
+#[cfg(feature = "battery-monitor")]
+pub mod battery;
+pub mod board;
+pub mod bootloader;
+pub mod buttons;
+pub mod bypass;
+pub mod channel_layout;
+#[cfg(feature = "chimes")]
+pub mod chime;
+pub mod chip;
+pub mod clock_accuracy;
+pub mod codec;
+pub mod coefficients;
+pub mod concealment;
+pub mod config;
+#[cfg(feature = "msc-config")]
+pub mod config_file;
+pub mod cpu_load;
+pub mod crash;
+pub mod de_emphasis;
+pub mod display;
+pub mod dsp;
+pub mod encoder;
+pub mod expander_io;
+#[cfg(feature = "fan-control")]
+pub mod fan;
+pub mod feedback;
+pub mod font;
+pub mod frame_feedback;
+pub mod gpio_expander;
+pub mod hid;
+pub mod i2c_recovery;
+pub mod i2c_scan;
+pub mod i2s_clock;
+pub mod i2s_input;
+pub mod image_crc;
+#[cfg(feature = "ir-remote")]
+pub mod ir_capture;
+pub mod ir_remote;
+pub mod kv_store;
+pub mod latency;
+#[cfg(feature = "stereo-link")]
+pub mod link;
+pub mod log_level;
+pub mod loudness;
+pub mod mclk;
+pub mod memory;
+pub mod meter;
+#[cfg(feature = "usb-midi")]
+pub mod midi;
+#[cfg(feature = "msc-config")]
+pub mod msc;
+pub mod nec;
+pub mod night_mode;
+pub mod output;
+pub mod partition;
+pub mod power;
+pub mod power_sequence;
+pub mod power_source;
+pub mod prefill;
+pub mod preset;
+pub mod profile;
+#[cfg(feature = "speaker-protection")]
+pub mod protection;
+pub mod reset_reason;
+pub mod sample_block;
+pub mod self_test;
+pub mod settings;
+pub mod silence;
+pub mod sleep_timer;
+pub mod sof_capture;
+pub mod source;
+#[cfg(feature = "spdif-output")]
+pub mod spdif;
+#[cfg(feature = "spectrum")]
+pub mod spectrum;
+pub mod spi_flash;
+pub mod ssd1306;
+pub mod stats;
+pub mod status_indicator;
+pub mod status_led;
+pub mod tas2780;
+#[cfg(feature = "usb-high-speed")]
+pub mod test_mode;
+pub mod thermal;
+pub mod trim;
+#[cfg(feature = "uart-control")]
+pub mod uart_control;
+pub mod upload;
+pub mod upsampling;
 pub mod usb_audio;
+pub mod usb_frame;
+pub mod usb_layout;
+pub mod vendor;
+pub mod version;
+pub mod watchdog;
+pub mod ws2812;
+#[cfg(feature = "dual-zone")]
+pub mod zone;
 
-use core::sync::atomic::AtomicBool;
-use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+use blus_core::feedback::FeedbackFormat;
+use blus_core::packet::{self, StreamFormat};
+use core::sync::atomic::{AtomicBool, AtomicU32};
+use embassy_stm32::{i2c, mode, usb};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
 use embassy_sync::signal::Signal;
-use embassy_usb::class::uac1;
 use embassy_usb::class::uac1::speaker::Volume;
 use heapless::Vec;
 
-// Stereo input -> two two-way speakers
-pub const INPUT_CHANNEL_COUNT: usize = 2;
+pub use config::*;
 
-pub const SAMPLE_RATE_HZ: u32 = 48_000;
 pub const FEEDBACK_COUNTER_TICK_RATE: u32 = 24_576_000 / 2;
 
-pub const SAMPLE_WIDTH: uac1::SampleWidth = uac1::SampleWidth::Width4Byte;
 pub const SAMPLE_WIDTH_BIT: usize = SAMPLE_WIDTH.in_bit();
 pub const SAMPLE_SIZE: usize = SAMPLE_WIDTH as usize;
 pub const SAMPLE_SIZE_PER_S: usize = (SAMPLE_RATE_HZ as usize) * INPUT_CHANNEL_COUNT * SAMPLE_SIZE;
 pub const SAMPLE_SIZE_PER_MS: usize = SAMPLE_SIZE_PER_S.div_ceil(1000);
 
-pub const AUDIO_CHANNELS: [uac1::Channel; INPUT_CHANNEL_COUNT] = [uac1::Channel::LeftFront, uac1::Channel::RightFront];
+// USB (micro)frames per millisecond: 1 ms frames for full-speed USB, 125 us microframes for high-speed USB.
+#[cfg(not(feature = "usb-high-speed"))]
+pub const USB_FRAMES_PER_MS: usize = 1;
+#[cfg(feature = "usb-high-speed")]
+pub const USB_FRAMES_PER_MS: usize = 8;
+
+// Isochronous packet limit of the bus speed.
+#[cfg(not(feature = "usb-high-speed"))]
+pub const USB_MAX_ISO_PACKET_SIZE: usize = packet::FULL_SPEED_MAX_ISO_PACKET_SIZE;
+#[cfg(feature = "usb-high-speed")]
+pub const USB_MAX_ISO_PACKET_SIZE: usize = packet::HIGH_SPEED_MAX_ISO_PACKET_SIZE;
+
+// Feedback encoding of the bus speed: 10.14 format (three bytes) for full-speed endpoints, and 16.16 format (four
+// bytes) for high-speed endpoints.
+#[cfg(not(feature = "usb-high-speed"))]
+pub const FEEDBACK_FORMAT: FeedbackFormat = FeedbackFormat::FULL_SPEED;
+#[cfg(feature = "usb-high-speed")]
+pub const FEEDBACK_FORMAT: FeedbackFormat = FeedbackFormat::HIGH_SPEED;
+
+// The refresh period must be within the bus speed's limits.
+static_assertions::const_assert!(FEEDBACK_FORMAT.is_valid_refresh(FEEDBACK_REFRESH_PERIOD as usize));
+
+// Highest advertised sample rate, which sizes the packets.
+pub const MAX_SAMPLE_RATE_HZ: u32 = {
+    let mut max = 0;
+    let mut index = 0;
+
+    while index < SAMPLE_RATES_HZ.len() {
+        if SAMPLE_RATES_HZ[index] > max {
+            max = SAMPLE_RATES_HZ[index];
+        }
+        index += 1;
+    }
+
+    max
+};
+
+/// Format of a USB stream of `channel_count` channels, at the highest sample rate.
+pub const fn usb_stream_format(channel_count: usize) -> StreamFormat {
+    StreamFormat {
+        sample_rate_hz: MAX_SAMPLE_RATE_HZ,
+        sample_size: SAMPLE_SIZE,
+        channel_count,
+        frames_per_ms: USB_FRAMES_PER_MS,
+    }
+}
+
+/// Maximum packet size for a stream of `channel_count` channels.
+pub const fn usb_max_packet_size(channel_count: usize) -> usize {
+    usb_stream_format(channel_count).max_packet_size(USB_PACKET_SIZE_FACTOR, USB_MAX_ISO_PACKET_SIZE)
+}
+
+// Feedback values at the highest sample rate must fit the endpoint, including plausible deviations.
+static_assertions::const_assert!(
+    FEEDBACK_FORMAT.fits(FEEDBACK_FORMAT.nominal(MAX_SAMPLE_RATE_HZ, USB_FRAMES_PER_MS) * 9 / 8)
+);
+
+// Largest packets of all channel layouts, which size the buffers.
+pub const USB_MAX_PACKET_SIZE: usize = usb_max_packet_size(MAX_USB_CHANNEL_COUNT);
+
+// Streams of all channel counts must fit the bus's isochronous packets, with margin for feedback. Otherwise, reduce the
+// channel count, sample rate, or sample width.
+static_assertions::const_assert!(usb_stream_format(MAX_USB_CHANNEL_COUNT).fits(USB_MAX_ISO_PACKET_SIZE));
 
-// Size of audio samples per 1 ms - suitable for full-speed USB
-pub const USB_FRAME_SIZE: usize = SAMPLE_SIZE_PER_MS;
+pub const USB_CONTROL_BUF_SIZE: usize = 64;
+pub const USB_FEEDBACK_BUF_SIZE: usize = 4;
 
-// 8 ms period
-pub const FEEDBACK_REFRESH_PERIOD: uac1::FeedbackRefresh = uac1::FeedbackRefresh::Period8Frames;
+// Bulk packets are 64 byte at full speed, and 512 byte at high speed.
+pub const USB_MIDI_BUF_SIZE: usize = match (cfg!(feature = "usb-midi"), cfg!(feature = "usb-high-speed")) {
+    (false, _) => 0,
+    (true, false) => 64,
+    (true, true) => 512,
+};
 
-// Factor of two as a margin for feedback (excessive)
-pub const USB_MAX_PACKET_SIZE: usize = 2 * USB_FRAME_SIZE;
-pub const USB_MAX_SAMPLE_COUNT: usize = USB_MAX_PACKET_SIZE / SAMPLE_SIZE;
+// Endpoints of the USB peripheral per direction, including the control endpoint: four of OTG_FS, and six of OTG_HS.
+#[cfg(not(feature = "usb-high-speed"))]
+pub const USB_ENDPOINT_COUNT: usize = 4;
+#[cfg(feature = "usb-high-speed")]
+pub const USB_ENDPOINT_COUNT: usize = 6;
+
+// The USB driver's OUT endpoint buffer.
+pub const USB_EP_OUT_BUFFER_SIZE: usize = packet::ep_out_buffer_size(&[
+    USB_CONTROL_BUF_SIZE,
+    USB_FEEDBACK_BUF_SIZE,
+    USB_MAX_PACKET_SIZE,
+    USB_MIDI_BUF_SIZE,
+]);
+
+// Capacity of a sample block in samples, which holds the largest packet of any channel count, before and after
+// remixing into the stereo pipeline (see `channel_layout`).
+pub const USB_SAMPLE_BLOCK_SAMPLE_COUNT: usize = {
+    let mut max = 0;
+    let mut channel_count = 1;
+
+    while channel_count <= MAX_USB_CHANNEL_COUNT {
+        let frame_count =
+            usb_stream_format(channel_count).max_frames_per_packet(USB_PACKET_SIZE_FACTOR, USB_MAX_ISO_PACKET_SIZE);
+
+        // Remixed frames have at least the pipeline's channel count.
+        let frame_size = if channel_count > INPUT_CHANNEL_COUNT {
+            channel_count
+        } else {
+            INPUT_CHANNEL_COUNT
+        };
+
+        let sample_count = frame_count * frame_size;
+        if sample_count > max {
+            max = sample_count;
+        }
+        channel_count += 1;
+    }
+
+    max
+};
+
+// Number of sample blocks in the channel between streaming and output task. More blocks add robustness against
+// irregular packet arrival, at the cost of memory.
+pub const USB_SAMPLE_BLOCK_COUNT: usize = (MAX_OUTPUT_PREFILL_MS + 2) * USB_FRAMES_PER_MS;
+
+// Number of sample blocks that are buffered before output starts. Each block adds one (micro)frame of latency.
+pub const OUTPUT_PREFILL_BLOCK_COUNT: usize = OUTPUT_PREFILL_MS * USB_FRAMES_PER_MS;
+pub const MAX_OUTPUT_PREFILL_BLOCK_COUNT: usize = MAX_OUTPUT_PREFILL_MS * USB_FRAMES_PER_MS;
+static_assertions::const_assert!(OUTPUT_PREFILL_BLOCK_COUNT >= 1);
+static_assertions::const_assert!(OUTPUT_PREFILL_BLOCK_COUNT <= MAX_OUTPUT_PREFILL_BLOCK_COUNT);
+
+// The streaming task drops samples, when the channel is nearly full, so the largest pre-fill leaves room.
+static_assertions::const_assert!(MAX_OUTPUT_PREFILL_BLOCK_COUNT + 1 < USB_SAMPLE_BLOCK_COUNT);
+
+// I2S DMA ring buffer, in 16 bit words, holding 2 ms more audio than the largest pre-fill.
+pub const I2S_BUFFER_SIZE: usize = (MAX_OUTPUT_PREFILL_MS + 2) * SAMPLE_SIZE_PER_MS / 2;
+
+// Pre-filled blocks are queued into the ring buffer before output starts, with room for blocks above the nominal size.
+static_assertions::const_assert!((MAX_OUTPUT_PREFILL_MS + 1) * SAMPLE_SIZE_PER_MS / 2 <= I2S_BUFFER_SIZE);
 
 // Task communication
 pub static USB_IS_STREAMING: AtomicBool = AtomicBool::new(false);
+pub static I2S_IS_ACTIVE: AtomicBool = AtomicBool::new(false);
+pub static USB_IS_SUSPENDED: AtomicBool = AtomicBool::new(false);
+pub static USB_IS_CONFIGURED: AtomicBool = AtomicBool::new(false);
+pub static USB_SAMPLE_RATE_HZ: AtomicU32 = AtomicU32::new(SAMPLE_RATE_HZ);
+
+// Feedback measurement, driven by the SOF capture interrupt.
+pub static FEEDBACK_ACCUMULATOR: feedback::FeedbackAccumulator =
+    feedback::FeedbackAccumulator::new(FEEDBACK_REFRESH_PERIOD.frame_count());
+pub static FEEDBACK_TICKS: feedback::FeedbackTicks = feedback::FeedbackTicks::new();
+
+// Set, if the boot-time self-test failed. Outputs are never unmuted in that case.
+pub static OUTPUT_INHIBITED: AtomicBool = AtomicBool::new(false);
 
-pub static FEEDBACK_SIGNAL: Signal<CriticalSectionRawMutex, u32> = Signal::new();
+pub static STREAM_OPEN_SIGNAL: Signal<ThreadModeRawMutex, bool> = Signal::new();
 pub static I2S_ACTIVE_SIGNAL: Signal<ThreadModeRawMutex, bool> = Signal::new();
+pub static SAMPLE_RATE_SIGNAL: Signal<ThreadModeRawMutex, u32> = Signal::new();
 pub static VOLUME_SIGNAL: Signal<ThreadModeRawMutex, (Volume, Volume)> = Signal::new();
+pub static AMP_STANDBY_SIGNAL: Signal<ThreadModeRawMutex, bool> = Signal::new();
+pub static REMOTE_WAKEUP_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+pub static SOURCE_SIGNAL: Signal<ThreadModeRawMutex, source::Source> = Signal::new();
+pub static PRESET_SIGNAL: Signal<ThreadModeRawMutex, usize> = Signal::new();
+pub static BOOTLOADER_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+pub static SETTINGS_CHANGED_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+pub static STATUS_LED_SIGNAL: Signal<ThreadModeRawMutex, status_led::LedStatus> = Signal::new();
+pub static CLIP_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+#[cfg(feature = "stereo-link-secondary")]
+pub static LINK_START_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+#[cfg(feature = "spectrum")]
+pub static SPECTRUM_CAPTURED_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+pub static IR_EVENT_CHANNEL: Channel<CriticalSectionRawMutex, nec::Event, 4> = Channel::new();
+pub static CONSUMER_KEY_CHANNEL: Channel<ThreadModeRawMutex, hid::ConsumerKey, 8> = Channel::new();
+pub static UPLOAD_CHANNEL: Channel<ThreadModeRawMutex, upload::Command, 1> = Channel::new();
 
 // Type definitions
-pub type UsbSampleBlock = Vec<u16, { 2 * USB_MAX_SAMPLE_COUNT }>;
+pub type UsbSampleBlock = sample_block::SampleBlock<{ 2 * USB_SAMPLE_BLOCK_SAMPLE_COUNT }>;
+pub type I2cPeripheral = i2c::I2c<'static, mode::Async>;
+pub type I2cBus = Mutex<NoopRawMutex, i2c_recovery::RecoveringI2c>;
+#[cfg(not(feature = "usb-high-speed"))]
+pub type UsbDriver = usb::Driver<'static, board::UsbPeripheral>;
+#[cfg(feature = "usb-high-speed")]
+pub type UsbDriver = test_mode::TestModeDriver<usb::Driver<'static, board::UsbPeripheral>>;
@@ -0,0 +1,259 @@
+// Stereo link between two units, e.g. for separate left and right active speakers: the primary unit receives USB
+// audio, and forwards one channel of its output to the secondary unit, which has no USB connection.
+//
+// Both units share the primary's I2S bit and word clocks (PB10 and PB12), so that the secondary's I2S runs in slave
+// mode at exactly the same rate. Samples are forwarded over USART2 (PA2 on the primary to PA3 on the secondary), one
+// frame per output block (see `blus_core::serial`): the master volume of the forwarded channel, followed by its
+// samples, rounded to 24 bit. The secondary plays them on both of its channels, after the primary's DSP chain.
+//
+// Alignment is sample-accurate, since both outputs start with the same clock edge. At the start of a stream, the
+// primary forwards its pre-filled blocks, followed by a start message. The secondary queues the same blocks, and
+// enables its I2S, which then waits for the clocks. The primary starts its own I2S, and thereby the clocks, once the
+// start message was sent, plus a margin for the secondary's reaction. From then on, both consume one block per block
+// period. A stop message ends the stream. Corrupt or lost blocks are replaced with silence of the nominal block length,
+// which keeps the alignment within a sample.
+use blus_core::format::{from_le_bytes_24, from_words, to_le_bytes_24, Rounding};
+use blus_core::serial::{self, Decoder};
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::{info, warn};
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{self, RingBufferedUartRx, UartTx};
+use embassy_stm32::{bind_interrupts, peripherals};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_sync::zerocopy_channel;
+use embassy_time::{Duration, Timer};
+use embassy_usb::class::uac1::speaker::Volume;
+use heapless::Vec;
+
+use crate::sample_block::WORD_ORDER;
+use crate::watchdog::{self, Task};
+use crate::*;
+
+bind_interrupts!(pub struct LinkIrqs {
+    USART2 => usart::InterruptHandler<peripherals::USART2>;
+});
+
+// 3 Mbaud divide APB1 (42 or 48 MHz) exactly.
+pub const BAUD_RATE: u32 = 3_000_000;
+
+// Size of a forwarded sample.
+const SAMPLE_SIZE: usize = 3;
+
+// Size of the master volume (`i16` in 1/256 dB), which is `MUTED` while muted.
+const VOLUME_SIZE: usize = 2;
+const MUTED: i16 = i16::MIN;
+
+// Frames of the largest output block, and of a nominal one.
+const MAX_FRAMES_PER_BLOCK: usize = USB_SAMPLE_BLOCK_SAMPLE_COUNT / INPUT_CHANNEL_COUNT;
+const NOMINAL_FRAMES_PER_BLOCK: usize = (SAMPLE_RATE_HZ / 1000) as usize;
+
+const MAX_PAYLOAD_SIZE: usize = VOLUME_SIZE + MAX_FRAMES_PER_BLOCK * SAMPLE_SIZE;
+const MAX_FRAME_SIZE: usize = serial::frame_size(MAX_PAYLOAD_SIZE);
+
+// Every block must be sent within a block period, with 10 bit per byte (start and stop bits).
+static_assertions::const_assert!(
+    serial::frame_size(VOLUME_SIZE + (NOMINAL_FRAMES_PER_BLOCK + 1) * SAMPLE_SIZE) * 10 * 1000 < BAUD_RATE as usize
+);
+
+// Time for the secondary to enable its I2S, after receiving the start message.
+const START_MARGIN: Duration = Duration::from_millis(1);
+
+// Message kinds.
+mod kind {
+    pub const SAMPLES: u8 = 0;
+    pub const START: u8 = 1;
+    pub const STOP: u8 = 2;
+}
+
+pub fn uart_config() -> usart::Config {
+    let mut config = usart::Config::default();
+    config.baudrate = BAUD_RATE;
+
+    config
+}
+
+type Message = Vec<u8, MAX_FRAME_SIZE>;
+
+static TX_CHANNEL: Channel<CriticalSectionRawMutex, Message, 4> = Channel::new();
+static START_SENT_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Queue a message for the transmit task. Messages are dropped, while the queue is full.
+fn send(kind: u8, payload: &[u8]) {
+    static SEQUENCE: AtomicU8 = AtomicU8::new(0);
+
+    let mut message = Message::new();
+    _ = message.resize_default(MAX_FRAME_SIZE);
+    let sequence = SEQUENCE.fetch_add(1, Relaxed);
+    let Some(length) = serial::encode(kind, sequence, payload, &mut message) else {
+        return;
+    };
+    message.truncate(length);
+
+    if TX_CHANNEL.try_send(message).is_err() {
+        warn!("Link queue full");
+    }
+}
+
+// The forwarded channel's master volume, or `MUTED`.
+fn volume() -> i16 {
+    let (left, right) = trim::master_volume();
+    match [left, right][LINK_CHANNEL] {
+        Volume::DeciBel(db) if !trim::is_muted() => (db * 256.0) as i16,
+        _ => MUTED,
+    }
+}
+
+/// Forward the forwarded channel of samples that were written to the I2S output, as 16 bit words.
+pub fn write(words: &[u16]) {
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    payload[..VOLUME_SIZE].copy_from_slice(&volume().to_le_bytes());
+
+    let frames = words.chunks_exact(2 * INPUT_CHANNEL_COUNT).take(MAX_FRAMES_PER_BLOCK);
+    let mut length = VOLUME_SIZE;
+    for frame in frames {
+        let sample = from_words([frame[2 * LINK_CHANNEL], frame[2 * LINK_CHANNEL + 1]], WORD_ORDER);
+        payload[length..length + SAMPLE_SIZE].copy_from_slice(&to_le_bytes_24(sample, Rounding::Nearest));
+        length += SAMPLE_SIZE;
+    }
+
+    send(kind::SAMPLES, &payload[..length]);
+}
+
+/// Start the secondary's output, after its pre-filled blocks. Returns, once the primary's I2S may start.
+pub async fn start() {
+    START_SENT_SIGNAL.reset();
+    send(kind::START, &[]);
+
+    START_SENT_SIGNAL.wait().await;
+    Timer::after(START_MARGIN).await;
+}
+
+/// Stop the secondary's output, along with the primary's.
+pub fn stop() {
+    send(kind::STOP, &[]);
+}
+
+// Sends queued messages to the secondary unit.
+#[embassy_executor::task]
+pub async fn transmit_task(mut tx: UartTx<'static, Async>) {
+    info!("Stereo link primary at {} baud", BAUD_RATE);
+
+    loop {
+        let message = TX_CHANNEL.receive().await;
+
+        if tx.write(&message).await.is_err() {
+            warn!("Link transmit error");
+        }
+        if message[serial::SYNC.len()] == kind::START {
+            START_SENT_SIGNAL.signal(());
+        }
+    }
+}
+
+// Decode a samples message into stereo samples, returning the master volume and the number of samples.
+fn decode_samples(payload: &[u8], samples: &mut [i32; USB_SAMPLE_BLOCK_SAMPLE_COUNT]) -> Option<(i16, usize)> {
+    let (volume, payload) = payload.split_first_chunk::<VOLUME_SIZE>()?;
+    if payload.len() % SAMPLE_SIZE != 0 {
+        return None;
+    }
+
+    let mut count = 0;
+    for (frame, bytes) in samples
+        .chunks_exact_mut(INPUT_CHANNEL_COUNT)
+        .zip(payload.chunks_exact(SAMPLE_SIZE))
+    {
+        frame.fill(from_le_bytes_24([bytes[0], bytes[1], bytes[2]]));
+        count += INPUT_CHANNEL_COUNT;
+    }
+
+    Some((i16::from_le_bytes(*volume), count))
+}
+
+// Queue a block of samples for output.
+async fn queue(sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>, samples: &[i32]) {
+    let block = sender.send().await;
+    block.set_samples(samples);
+    sender.send_done();
+    stats::block_queued();
+}
+
+// Receives messages from the primary unit, and queues their samples for output, in place of the USB stream.
+#[embassy_executor::task]
+pub async fn receive_task(
+    mut rx: RingBufferedUartRx<'static>,
+    mut sender: zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+) {
+    info!("Stereo link secondary at {} baud", BAUD_RATE);
+
+    let mut decoder = Decoder::<MAX_PAYLOAD_SIZE>::new();
+    let mut buffer = [0u8; 64];
+    let mut samples = [0i32; USB_SAMPLE_BLOCK_SAMPLE_COUNT];
+    let mut streaming = false;
+    let mut next_sequence: Option<u8> = None;
+    let mut volume = None;
+
+    loop {
+        let length = match watchdog::idle(Task::Streaming, rx.read(&mut buffer)).await {
+            Ok(length) => length,
+            Err(e) => {
+                warn!("Link receive error: {}", e);
+                decoder.reset();
+                continue;
+            }
+        };
+
+        for &byte in &buffer[..length] {
+            let frame = match decoder.push(byte) {
+                None => continue,
+                Some(Ok(frame)) => frame,
+                Some(Err(_)) => {
+                    log_debug!("Corrupt link frame");
+                    continue;
+                }
+            };
+
+            // Lost blocks are replaced by silence, so that the following ones keep their position.
+            let lost = next_sequence.map_or(0, |sequence| frame.sequence.wrapping_sub(sequence));
+            next_sequence = Some(frame.sequence.wrapping_add(1));
+            if streaming && lost > 0 {
+                warn!("Lost {} link frames", lost);
+                for _ in 0..lost {
+                    queue(&mut sender, &[0; NOMINAL_FRAMES_PER_BLOCK * INPUT_CHANNEL_COUNT]).await;
+                }
+            }
+
+            match frame.kind {
+                kind::SAMPLES => {
+                    let Some((block_volume, count)) = decode_samples(frame.payload, &mut samples) else {
+                        continue;
+                    };
+
+                    if volume != Some(block_volume) {
+                        volume = Some(block_volume);
+                        let volume = match block_volume {
+                            MUTED => Volume::Muted,
+                            _ => Volume::DeciBel(block_volume as f32 / 256.0),
+                        };
+                        trim::set_master_volume((volume, volume));
+                    }
+
+                    streaming = true;
+                    queue(&mut sender, &samples[..count]).await;
+                }
+                kind::START => {
+                    info!("Link stream started");
+                    LINK_START_SIGNAL.signal(());
+                }
+                kind::STOP => {
+                    info!("Link stream stopped");
+                    streaming = false;
+                    LINK_START_SIGNAL.reset();
+                    STREAM_OPEN_SIGNAL.signal(false);
+                }
+                _ => (),
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// Runtime log filtering, on top of defmt's compile-time filter (`DEFMT_LOG`).
+//
+// Verbose output of the streaming path goes through the `log_*!` macros, which only log if the runtime level allows it.
+// Debug builds start verbose, release builds start quiet. The level can be changed with a vendor request.
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::{info, Format};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Format)]
+#[repr(u8)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Off),
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            5 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+const DEFAULT_LEVEL: Level = if cfg!(debug_assertions) {
+    Level::Debug
+} else {
+    Level::Warn
+};
+
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+
+pub fn level() -> Level {
+    Level::from_u8(LEVEL.load(Relaxed)).unwrap_or(DEFAULT_LEVEL)
+}
+
+pub fn set_level(level: Level) {
+    info!("Log level: {}", level);
+    LEVEL.store(level as u8, Relaxed);
+}
+
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level <= self::level()
+}
+
+/// Log at info level, if enabled at runtime.
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Info) {
+            defmt::info!($($arg)*);
+        }
+    };
+}
+
+/// Log at debug level, if enabled at runtime.
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Debug) {
+            defmt::debug!($($arg)*);
+        }
+    };
+}
+
+/// Log at trace level, if enabled at runtime.
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => {
+        if $crate::log_level::enabled($crate::log_level::Level::Trace) {
+            defmt::trace!($($arg)*);
+        }
+    };
+}
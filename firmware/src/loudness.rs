@@ -0,0 +1,64 @@
+// Loudness compensation, which boosts bass and treble as the host's master volume is reduced.
+//
+// The compensation filters are recomputed in the DSP chain, whenever the master volume changes. The signal is
+// attenuated by the boost's headroom ahead of the filters, and the amplifiers' volume is raised by the same amount (see
+// `trim`), such that the midrange level still follows the master volume, and the boost cannot clip. Loudness
+// compensation is part of the persistent settings.
+use defmt::info;
+use embassy_usb::class::uac1::speaker::Volume;
+
+use crate::dsp::{Filter, Loudness};
+use crate::*;
+
+// Compensation below -10 dB, reaching its maxima at -40 dB.
+pub const LOUDNESS: Loudness = Loudness {
+    reference_db: -10.0,
+    low_shelf_hz: 100.0,
+    high_shelf_hz: 10_000.0,
+    low_slope: 0.2,
+    high_slope: 0.1,
+    max_low_db: 6.0,
+    max_high_db: 3.0,
+};
+
+// The louder channel's master volume, or `None` while muted.
+fn volume_db() -> Option<f32> {
+    match trim::master_volume() {
+        (Volume::DeciBel(left), Volume::DeciBel(right)) => Some(left.max(right)),
+        (Volume::DeciBel(db), Volume::Muted) | (Volume::Muted, Volume::DeciBel(db)) => Some(db),
+        (Volume::Muted, Volume::Muted) => None,
+    }
+}
+
+pub fn is_enabled() -> bool {
+    settings::get().loudness
+}
+
+/// Enable or disable loudness compensation, which is stored in the settings.
+pub fn set(enabled: bool) {
+    info!("Loudness compensation: {}", enabled);
+    settings::modify(|settings| settings.loudness = enabled);
+    trim::update();
+    PRESET_SIGNAL.signal(preset::active());
+}
+
+/// Recompute the compensation for a changed master volume.
+pub fn volume_changed() {
+    if is_enabled() {
+        PRESET_SIGNAL.signal(preset::active());
+    }
+}
+
+/// The compensation filters at the current master volume, if enabled.
+pub fn filters() -> Option<[Filter; 2]> {
+    volume_db()
+        .filter(|_| is_enabled())
+        .map(|volume_db| LOUDNESS.filters(volume_db))
+}
+
+/// The headroom of the compensation filters at the current master volume in dB, or zero if disabled.
+pub fn headroom_db() -> f32 {
+    volume_db()
+        .filter(|_| is_enabled())
+        .map_or(0.0, |volume_db| LOUDNESS.headroom_db(volume_db))
+}
@@ -0,0 +1,9 @@
+// Master clock (MCLK) generation for external DACs, at 256 fs.
+//
+// MCLK is output by the I2S peripheral, and derived from the I2S PLL, whose setting follows the sample rate's family
+// (see `i2s_clock`).
+pub const MCLK_FS_RATIO: u32 = 256;
+
+pub const fn mclk_hz(sample_rate_hz: u32) -> u32 {
+    MCLK_FS_RATIO * sample_rate_hz
+}
@@ -0,0 +1,81 @@
+// Stack usage and static buffer headroom, reported periodically for right-sizing buffers.
+//
+// All tasks of the thread-mode executor and all interrupt handlers share the main stack, so that its high-water mark
+// covers every task (task futures themselves live in the executor's static arena). The free stack is painted with a
+// pattern at boot, and the high-water mark is the lowest overwritten word.
+use core::ptr::addr_of_mut;
+use defmt::{info, warn};
+use embassy_time::{Duration, Ticker};
+
+use crate::*;
+
+const PAINT_PATTERN: u32 = 0xcafe_f00d;
+
+// Words below the current stack pointer that are left untouched when painting, e.g. for an exception frame.
+const PAINT_MARGIN_WORDS: usize = 64;
+
+// A warning is logged, if less stack than this is left.
+const STACK_WARNING_THRESHOLD: usize = 1024;
+
+const REPORT_PERIOD: Duration = Duration::from_secs(60);
+
+extern "C" {
+    // Initial stack pointer, provided by the cortex-m-rt linker script.
+    static mut _stack_start: u32;
+}
+
+// The stack grows down from its start to the end of static data (the heap start, without a heap).
+fn stack_bounds() -> (*mut u32, *mut u32) {
+    // SAFETY: Only the symbol's address is taken.
+    (cortex_m_rt::heap_start(), unsafe { addr_of_mut!(_stack_start) })
+}
+
+/// Fill the unused stack with a pattern. Must be called first thing at boot.
+pub fn paint_stack() {
+    let (bottom, _) = stack_bounds();
+    let end = (cortex_m::register::msp::read() as *mut u32).wrapping_sub(PAINT_MARGIN_WORDS);
+
+    let mut address = bottom;
+    while address < end {
+        // SAFETY: The range between the end of static data and the current stack pointer is unused.
+        unsafe {
+            address.write_volatile(PAINT_PATTERN);
+            address = address.add(1);
+        }
+    }
+}
+
+/// The stack's high-water mark and total size in byte.
+pub fn stack_usage() -> (usize, usize) {
+    let (bottom, top) = stack_bounds();
+
+    let mut address = bottom;
+    // SAFETY: The address stays within the stack.
+    while address < top && unsafe { address.read_volatile() } == PAINT_PATTERN {
+        address = address.wrapping_add(1);
+    }
+
+    (top as usize - address as usize, top as usize - bottom as usize)
+}
+
+#[embassy_executor::task]
+pub async fn report_task() {
+    let mut ticker = Ticker::every(REPORT_PERIOD);
+
+    loop {
+        ticker.next().await;
+
+        let (used, size) = stack_usage();
+        info!("Stack: {} of {} byte used", used, size);
+
+        if size - used < STACK_WARNING_THRESHOLD {
+            warn!("Stack headroom is low: {} byte", size - used);
+        }
+
+        info!(
+            "Sample blocks: at most {} of {} queued",
+            stats::buffer_fill_peak(),
+            USB_SAMPLE_BLOCK_COUNT
+        );
+    }
+}
@@ -0,0 +1,76 @@
+// Peak and RMS level meters of the DSP chain's output, reported to the host on an interrupt endpoint of the vendor
+// interface, e.g. for a VU meter in the host tool.
+//
+// The streaming task accumulates the samples of every block, and the meter task applies the meters' ballistics (see
+// `blus_core::meter`) and sends a report every 50 ms. A report holds the peak and RMS level of each channel, as `i16`
+// in 0.1 dBFS. Reports are only sent while the host polls the endpoint.
+use blus_core::meter::{Accumulator, LevelMeter};
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Ticker};
+use embassy_usb::driver::{Driver, EndpointIn};
+
+use crate::*;
+
+const REPORT_PERIOD_MS: u32 = 50;
+
+// Interval of the host's endpoint polls.
+pub const POLL_INTERVAL_MS: u8 = REPORT_PERIOD_MS as u8;
+
+const PEAK_DECAY_DB_PER_S: f32 = 20.0;
+const RMS_DECAY_DB_PER_S: f32 = 10.0;
+
+/// Peak and RMS level (`i16` each) per channel.
+pub const REPORT_SIZE: usize = 4 * INPUT_CHANNEL_COUNT;
+
+pub type MeterEndpoint = <UsbDriver as Driver<'static>>::EndpointIn;
+
+static ACCUMULATORS: Mutex<CriticalSectionRawMutex, Cell<[Accumulator; INPUT_CHANNEL_COUNT]>> =
+    Mutex::new(Cell::new([Accumulator::EMPTY; INPUT_CHANNEL_COUNT]));
+
+/// Add the accumulated samples of a block, per channel.
+pub fn record(block: &[Accumulator; INPUT_CHANNEL_COUNT]) {
+    ACCUMULATORS.lock(|accumulators| {
+        let mut value = accumulators.get();
+        for (accumulator, block) in value.iter_mut().zip(block) {
+            accumulator.merge(block);
+        }
+        accumulators.set(value);
+    });
+}
+
+// A level in 0.1 dBFS.
+fn tenths_db(level_db: f32) -> i16 {
+    (level_db * 10.0) as i16
+}
+
+#[embassy_executor::task]
+pub async fn meter_task(mut endpoint: MeterEndpoint) {
+    let mut meters: [LevelMeter; INPUT_CHANNEL_COUNT] =
+        core::array::from_fn(|_| LevelMeter::new(PEAK_DECAY_DB_PER_S, RMS_DECAY_DB_PER_S));
+    let mut report = [0u8; REPORT_SIZE];
+
+    loop {
+        endpoint.wait_enabled().await;
+        let mut ticker = Ticker::every(Duration::from_millis(REPORT_PERIOD_MS as u64));
+
+        loop {
+            ticker.next().await;
+
+            let accumulators =
+                ACCUMULATORS.lock(|accumulators| accumulators.replace([Accumulator::EMPTY; INPUT_CHANNEL_COUNT]));
+            for ((bytes, meter), accumulator) in report.chunks_exact_mut(4).zip(meters.iter_mut()).zip(&accumulators) {
+                let (peak_db, rms_db) = meter.update(accumulator, REPORT_PERIOD_MS);
+
+                bytes[..2].copy_from_slice(&tenths_db(peak_db).to_le_bytes());
+                bytes[2..].copy_from_slice(&tenths_db(rms_db).to_le_bytes());
+            }
+
+            // Waits for the host to poll. The endpoint is disabled, when the device is reset or deconfigured.
+            if endpoint.write(&report).await.is_err() {
+                break;
+            }
+        }
+    }
+}
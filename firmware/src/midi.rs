@@ -0,0 +1,144 @@
+// USB-MIDI interface, for adjusting DSP parameters live from a MIDI controller or a DAW.
+//
+// Control changes on any channel set the master volume (until the host sets it again), offset the equalizer bands'
+// gains, and set the crossover frequency (see `blus_core::midi`). Adjustments are not persisted. The interface only
+// receives, so that it needs no IN endpoint, of which full-speed devices have few.
+use blus_core::midi::{self, controller, ControlChange};
+use blus_core::usb_layout::{audio, class, Endpoint, Function, Interface};
+use defmt::{info, warn};
+use embassy_usb::class::uac1::speaker::Volume;
+use embassy_usb::driver::{Driver, Endpoint as _, EndpointOut};
+use embassy_usb::Builder;
+
+use crate::preset::{self, EQ_BAND_COUNT};
+use crate::*;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+// Class-specific descriptor subtypes.
+const HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const MS_GENERAL: u8 = 0x01;
+
+const JACK_EMBEDDED: u8 = 0x01;
+const JACK_EXTERNAL: u8 = 0x02;
+
+// The host writes to the embedded IN jack, which is wired to an external OUT jack (the DSP).
+const EMBEDDED_IN_JACK_ID: u8 = 1;
+const EXTERNAL_OUT_JACK_ID: u8 = 2;
+
+// Sizes of the MIDI streaming descriptors, for the header's total length.
+const MS_HEADER_SIZE: u16 = 7;
+const IN_JACK_SIZE: u16 = 6;
+const OUT_JACK_SIZE: u16 = 9;
+const ENDPOINT_SIZE: u16 = 7;
+const MS_ENDPOINT_SIZE: u16 = 5;
+
+/// Size of the interfaces' descriptors, including the interface association, which extends the configuration
+/// descriptor.
+pub const DESCRIPTOR_SIZE: usize = 8 + 2 * 9 + 9 + (MS_HEADER_SIZE + IN_JACK_SIZE + OUT_JACK_SIZE) as usize + 7 + 5;
+
+/// The MIDI interfaces: an audio control interface, and the MIDI streaming interface with its bulk OUT endpoint (see
+/// `usb_layout`).
+pub const FUNCTION: Function = Function {
+    class: class::AUDIO,
+    sub_class: audio::CONTROL,
+    interfaces: &[
+        Interface {
+            class: class::AUDIO,
+            sub_class: audio::CONTROL,
+            endpoints: &[],
+        },
+        Interface {
+            class: class::AUDIO,
+            sub_class: audio::MIDI_STREAMING,
+            endpoints: &[Endpoint::bulk_out(USB_MIDI_BUF_SIZE)],
+        },
+    ],
+};
+
+pub type MidiEndpoint = <UsbDriver as Driver<'static>>::EndpointOut;
+
+/// Add the MIDI interfaces to the USB device, and return their OUT endpoint.
+pub fn register(builder: &mut Builder<'static, UsbDriver>) -> MidiEndpoint {
+    let mut function = builder.function(class::AUDIO, audio::CONTROL, 0);
+
+    // An audio control interface without units, which only refers to the MIDI streaming interface.
+    let mut control_interface = function.interface();
+    usb_layout::check_interface(usb_layout::MIDI, control_interface.interface_number());
+    let streaming_number = u8::from(control_interface.interface_number()) + 1;
+    let mut alt_setting = control_interface.alt_setting(class::AUDIO, audio::CONTROL, 0, None);
+    alt_setting.descriptor(CS_INTERFACE, &[HEADER, 0x00, 0x01, 0x09, 0x00, 1, streaming_number]);
+
+    let mut streaming_interface = function.interface();
+    let mut alt_setting = streaming_interface.alt_setting(class::AUDIO, audio::MIDI_STREAMING, 0, None);
+    let total_length = MS_HEADER_SIZE + IN_JACK_SIZE + OUT_JACK_SIZE + ENDPOINT_SIZE + MS_ENDPOINT_SIZE;
+    let [length_low, length_high] = total_length.to_le_bytes();
+    alt_setting.descriptor(CS_INTERFACE, &[HEADER, 0x00, 0x01, length_low, length_high]);
+    alt_setting.descriptor(CS_INTERFACE, &[MIDI_IN_JACK, JACK_EMBEDDED, EMBEDDED_IN_JACK_ID, 0]);
+    alt_setting.descriptor(
+        CS_INTERFACE,
+        &[
+            MIDI_OUT_JACK,
+            JACK_EXTERNAL,
+            EXTERNAL_OUT_JACK_ID,
+            1,
+            EMBEDDED_IN_JACK_ID,
+            1,
+            0,
+        ],
+    );
+
+    let endpoint = alt_setting.endpoint_bulk_out(USB_MIDI_BUF_SIZE as u16);
+    alt_setting.descriptor(CS_ENDPOINT, &[MS_GENERAL, 1, EMBEDDED_IN_JACK_ID]);
+
+    endpoint
+}
+
+// Apply a control change to the DSP parameters.
+fn apply(change: ControlChange) {
+    log_debug!("MIDI controller {} = {}", change.controller, change.value);
+
+    match change.controller {
+        controller::VOLUME => {
+            let volume = midi::volume_db(change.value).map_or(Volume::Muted, Volume::DeciBel);
+            trim::set_local_master_volume(volume);
+        }
+        band if (controller::EQ_GAIN..controller::EQ_GAIN + EQ_BAND_COUNT as u8).contains(&band) => {
+            let index = (band - controller::EQ_GAIN) as usize;
+            _ = preset::adjust_eq_gain(index, midi::eq_gain_offset_db(change.value));
+        }
+        controller::CROSSOVER => preset::adjust_crossover(midi::crossover_hz(change.value)),
+        controller::RESET_ALL => preset::reset_adjustments(),
+        _ => (),
+    }
+}
+
+// Receives MIDI events from the host, and applies their control changes.
+#[embassy_executor::task]
+pub async fn midi_task(mut endpoint: MidiEndpoint) {
+    let mut buffer = [0u8; USB_MIDI_BUF_SIZE];
+
+    loop {
+        endpoint.wait_enabled().await;
+        info!("MIDI interface enabled");
+
+        loop {
+            let length = match endpoint.read(&mut buffer).await {
+                Ok(length) => length,
+                Err(e) => {
+                    warn!("MIDI receive error: {}", e);
+                    break;
+                }
+            };
+
+            for packet in buffer[..length].chunks_exact(midi::PACKET_SIZE) {
+                if let Some(change) = midi::parse_control_change(packet.try_into().unwrap()) {
+                    apply(change);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,561 @@
+// Mass-storage configuration mode, for configuring the device without installing any software.
+//
+// While the wake-up button is held at plug-in, the device enumerates as a USB mass storage device (bulk-only transport,
+// SCSI commands) instead of an audio device. It exposes a small virtual FAT volume (see `blus_core::fat`) with a
+// README and the current settings as `config.json` (see `config_file`). Written sectors are not stored, but recognized
+// by their content: a JSON object is applied to the settings, and a coefficient image (a blob with its header, with
+// the `spi-flash` feature) is written to the coefficient partition. The device then resets into normal operation.
+use blus_core::fat::{self, File, Region, Volume, SECTOR_SIZE};
+use blus_core::json;
+use cortex_m::peripheral::SCB;
+use defmt::{info, unwrap, warn};
+use embassy_time::Timer;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, Endpoint, EndpointIn, EndpointOut};
+use embassy_usb::{Builder, Handler, InterfaceNumber};
+use heapless::Vec;
+use static_cell::StaticCell;
+
+use crate::app::UsbIdentity;
+use crate::config_file;
+use crate::settings::{self, SettingsStore};
+use crate::*;
+
+const MSC_CLASS: u8 = 0x08;
+const SCSI_SUBCLASS: u8 = 0x06;
+const BULK_ONLY_PROTOCOL: u8 = 0x50;
+
+// Class requests of the bulk-only transport.
+const GET_MAX_LUN: u8 = 0xfe;
+const BULK_ONLY_RESET: u8 = 0xff;
+
+const PACKET_SIZE: usize = 64;
+
+// Command block and command status wrappers.
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_SIZE: usize = 31;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_SIZE: usize = 13;
+
+mod opcode {
+    pub const TEST_UNIT_READY: u8 = 0x00;
+    pub const REQUEST_SENSE: u8 = 0x03;
+    pub const INQUIRY: u8 = 0x12;
+    pub const MODE_SENSE_6: u8 = 0x1a;
+    pub const START_STOP_UNIT: u8 = 0x1b;
+    pub const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+    pub const READ_FORMAT_CAPACITIES: u8 = 0x23;
+    pub const READ_CAPACITY_10: u8 = 0x25;
+    pub const READ_10: u8 = 0x28;
+    pub const WRITE_10: u8 = 0x2a;
+    pub const VERIFY_10: u8 = 0x2f;
+    pub const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+}
+
+// Sense key and additional sense code of unsupported commands (illegal request, invalid command operation code).
+const SENSE_ILLEGAL_REQUEST: (u8, u8) = (0x05, 0x20);
+
+// 1 MiB, which holds a coefficient image of the whole partition.
+const SECTOR_COUNT: u16 = 2048;
+
+// Time for the host to receive the status of the last write, before the device resets.
+const RESET_DELAY_MS: u64 = 100;
+
+const README: &[u8] = b"Drop a config.json onto this drive, to apply it to the device's settings. The device restarts \
+afterwards.\r\n\r\nGains are in dB (trim in 0.5 dB steps, up to 12 dB), delays in samples (up to 10 ms), and inverted \
+polarities are 0 or 1 per channel. Channel layouts are mono, stereo, 2.1, or 4.0, and take effect at the next boot.\r\n\
+\r\nWith external flash, a coefficient blob with its header (a padded file) is written to the coefficient partition \
+likewise.\r\n";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Passed = 0,
+    Failed = 1,
+}
+
+/// Whether the configuration mode was requested, by holding the wake-up button at plug-in.
+pub fn is_requested(button: &board::WakeupButton, active_low: bool) -> bool {
+    button.is_high() != active_low
+}
+
+struct MscHandler {
+    interface: InterfaceNumber,
+}
+
+impl MscHandler {
+    fn is_own(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == self.interface.0 as u16
+    }
+}
+
+impl Handler for MscHandler {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !self.is_own(&req) {
+            return None;
+        }
+
+        Some(match req.request {
+            BULK_ONLY_RESET => OutResponse::Accepted,
+            _ => OutResponse::Rejected,
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.is_own(&req) {
+            return None;
+        }
+
+        Some(match req.request {
+            // A single logical unit.
+            GET_MAX_LUN => {
+                buf[0] = 0;
+                InResponse::Accepted(&buf[..1])
+            }
+            _ => InResponse::Rejected,
+        })
+    }
+}
+
+type MscEndpoints = (
+    <UsbDriver as Driver<'static>>::EndpointOut,
+    <UsbDriver as Driver<'static>>::EndpointIn,
+);
+
+// A command block wrapper's tag, expected data length and direction, and command block.
+struct Command {
+    tag: u32,
+    data_length: u32,
+    data_in: bool,
+    block: [u8; 16],
+}
+
+impl Command {
+    fn parse(cbw: &[u8]) -> Option<Self> {
+        let word = |offset: usize| u32::from_le_bytes(cbw[offset..offset + 4].try_into().unwrap());
+
+        if cbw.len() != CBW_SIZE || word(0) != CBW_SIGNATURE {
+            return None;
+        }
+        Some(Self {
+            tag: word(4),
+            data_length: word(8),
+            data_in: cbw[12] & 0x80 != 0,
+            block: cbw[15..].try_into().unwrap(),
+        })
+    }
+
+    fn opcode(&self) -> u8 {
+        self.block[0]
+    }
+
+    // The logical block address and block count of READ(10) and WRITE(10).
+    fn blocks(&self) -> (u32, u32) {
+        let address = u32::from_be_bytes(self.block[2..6].try_into().unwrap());
+        let count = u16::from_be_bytes(self.block[7..9].try_into().unwrap());
+        (address, count as u32)
+    }
+}
+
+// A file that is being written, recognized by its first sector.
+enum Upload {
+    None,
+    Config {
+        next_sector: u32,
+        text: Vec<u8, { config_file::MAX_SIZE }>,
+    },
+    #[cfg(feature = "spi-flash")]
+    Coefficients {
+        first_sector: u32,
+        next_sector: u32,
+        header: [u8; partition::BLOB_HEADER_SIZE as usize],
+        end: u32,
+    },
+}
+
+struct Storage {
+    volume: Volume<'static>,
+    config: &'static [u8],
+    upload: Upload,
+    store: SettingsStore,
+    sense: (u8, u8),
+    reset_pending: bool,
+}
+
+impl Storage {
+    fn read_sector(&self, sector: u32, buffer: &mut [u8; SECTOR_SIZE]) {
+        let config = self.config;
+        self.volume.read_sector(sector, buffer, |index, offset, data| {
+            let content = if index == 0 { README } else { config };
+            data.copy_from_slice(&content[offset as usize..offset as usize + data.len()]);
+        });
+    }
+
+    // Apply a complete configuration file.
+    fn apply_config(&mut self, text: &[u8]) {
+        let Ok(text) = core::str::from_utf8(text) else {
+            warn!("Configuration file is not UTF-8");
+            return;
+        };
+
+        let mut settings = settings::get();
+        match config_file::apply(text, &mut settings) {
+            Ok(()) => {
+                settings::modify(|current| *current = settings);
+                match self.store.store() {
+                    Ok(()) => {
+                        info!("Applied configuration file");
+                        self.reset_pending = true;
+                    }
+                    Err(e) => warn!("Failed to store settings: {}", e),
+                }
+            }
+            Err(e) => warn!("Invalid configuration file: {}", e),
+        }
+    }
+
+    async fn write_sector(&mut self, sector: u32, data: &[u8; SECTOR_SIZE]) {
+        // Hosts also write the FAT and directories, which are not stored.
+        if !matches!(self.volume.region(sector), Region::Data { .. }) {
+            return;
+        }
+
+        if data.trim_ascii_start().first() == Some(&b'{') {
+            self.upload = Upload::Config {
+                next_sector: sector,
+                text: Vec::new(),
+            };
+        }
+        #[cfg(feature = "spi-flash")]
+        if data[..4] == partition::COEFFICIENTS_MAGIC.to_le_bytes() {
+            self.begin_coefficients(sector, data).await;
+            return;
+        }
+
+        match &mut self.upload {
+            Upload::Config { next_sector, text } if sector == *next_sector => {
+                *next_sector += 1;
+                if text.extend_from_slice(data).is_err() {
+                    warn!("Configuration file is too large");
+                    self.upload = Upload::None;
+                }
+            }
+            #[cfg(feature = "spi-flash")]
+            Upload::Coefficients { next_sector, .. } if sector == *next_sector => {
+                self.write_coefficients(sector, data).await;
+            }
+            _ => (),
+        }
+
+        if let Upload::Config { text, .. } = &self.upload {
+            if let Some(length) = json::object_length(text) {
+                let Upload::Config { text, .. } = core::mem::replace(&mut self.upload, Upload::None) else {
+                    return;
+                };
+                self.apply_config(&text[..length]);
+            }
+        }
+    }
+
+    #[cfg(feature = "spi-flash")]
+    async fn begin_coefficients(&mut self, sector: u32, data: &[u8; SECTOR_SIZE]) {
+        use partition::{BLOB_HEADER_SIZE, COEFFICIENTS};
+
+        self.upload = Upload::None;
+        let length = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if length == 0 || length % 4 != 0 || length > COEFFICIENTS.size - BLOB_HEADER_SIZE {
+            warn!("Invalid coefficient image length {}", length);
+            return;
+        }
+
+        info!("Coefficient image of {} byte", length);
+        let mut external_flash = partition::EXTERNAL_FLASH.lock().await;
+        let Some(flash) = external_flash.as_mut() else {
+            warn!("No external flash");
+            return;
+        };
+        if let Err(e) = COEFFICIENTS.erase(flash, 0, BLOB_HEADER_SIZE + length).await {
+            warn!("Failed to erase coefficients: {}", e);
+            return;
+        }
+        drop(external_flash);
+
+        self.upload = Upload::Coefficients {
+            first_sector: sector,
+            next_sector: sector,
+            header: unwrap!(data[..BLOB_HEADER_SIZE as usize].try_into()),
+            end: BLOB_HEADER_SIZE + length,
+        };
+        self.write_coefficients(sector, data).await;
+    }
+
+    // Write a sector of the coefficient image. The header is written last, once the image is complete.
+    #[cfg(feature = "spi-flash")]
+    async fn write_coefficients(&mut self, sector: u32, data: &[u8; SECTOR_SIZE]) {
+        use partition::{BLOB_HEADER_SIZE, COEFFICIENTS, COEFFICIENTS_MAGIC};
+
+        let Upload::Coefficients {
+            first_sector,
+            next_sector,
+            header,
+            end,
+        } = &mut self.upload
+        else {
+            return;
+        };
+        *next_sector += 1;
+
+        let offset = (sector - *first_sector) * SECTOR_SIZE as u32;
+        let skip = if offset == 0 { BLOB_HEADER_SIZE } else { 0 };
+        let length = (*end - offset).min(SECTOR_SIZE as u32);
+        let complete = offset + length == *end;
+
+        let mut external_flash = partition::EXTERNAL_FLASH.lock().await;
+        let Some(flash) = external_flash.as_mut() else {
+            return;
+        };
+        let mut result = COEFFICIENTS
+            .write(flash, offset + skip, &data[skip as usize..length as usize])
+            .await;
+
+        if complete && result.is_ok() {
+            result = COEFFICIENTS.write(flash, 0, &header[..]).await;
+            self.upload = Upload::None;
+
+            match partition::validate_blob(flash, &COEFFICIENTS, COEFFICIENTS_MAGIC).await {
+                Ok(Some(blob)) => {
+                    info!("Coefficient image is valid: {}", blob);
+                    self.reset_pending = true;
+                }
+                _ => warn!("Coefficient image is invalid"),
+            }
+        }
+
+        if let Err(e) = result {
+            warn!("Failed to write coefficients: {}", e);
+            self.upload = Upload::None;
+        }
+    }
+}
+
+// Send data of up to the expected length, padded with zeros, so that the host never waits for missing data.
+async fn send(endpoint: &mut impl EndpointIn, data: &[u8], expected: u32) -> Result<u32, ()> {
+    let expected = expected as usize;
+    let mut packet = [0u8; PACKET_SIZE];
+
+    for start in (0..expected).step_by(PACKET_SIZE) {
+        let end = (start + PACKET_SIZE).min(expected);
+        packet.fill(0);
+        let available = data.get(start..end.min(data.len())).unwrap_or(&[]);
+        packet[..available.len()].copy_from_slice(available);
+
+        endpoint.write(&packet[..end - start]).await.map_err(|_| ())?;
+    }
+
+    // The residue of data that was padded.
+    Ok(expected.saturating_sub(data.len()) as u32)
+}
+
+// Execute a command, and return its status and residue.
+async fn execute(
+    storage: &mut Storage,
+    command: &Command,
+    (read_endpoint, write_endpoint): &mut MscEndpoints,
+) -> Result<(Status, u32), ()> {
+    let mut response = [0u8; 36];
+
+    let response: &[u8] = match command.opcode() {
+        opcode::TEST_UNIT_READY
+        | opcode::START_STOP_UNIT
+        | opcode::PREVENT_ALLOW_MEDIUM_REMOVAL
+        | opcode::VERIFY_10
+        | opcode::SYNCHRONIZE_CACHE_10 => &[],
+        opcode::INQUIRY => {
+            // Direct-access device, removable, SPC-2, with vendor, product, and revision.
+            response[..5].copy_from_slice(&[0x00, 0x80, 0x04, 0x02, 31]);
+            response[8..16].copy_from_slice(b"BLUS    ");
+            response[16..32].copy_from_slice(b"Configuration   ");
+            response[32..36].copy_from_slice(b"1.0 ");
+            &response
+        }
+        opcode::REQUEST_SENSE => {
+            let (key, code) = core::mem::take(&mut storage.sense);
+            response[..18].copy_from_slice(&[0x70, 0, key, 0, 0, 0, 0, 10, 0, 0, 0, 0, code, 0, 0, 0, 0, 0]);
+            &response[..18]
+        }
+        opcode::MODE_SENSE_6 => &[3, 0, 0, 0],
+        opcode::READ_CAPACITY_10 => {
+            response[..4].copy_from_slice(&(SECTOR_COUNT as u32 - 1).to_be_bytes());
+            response[4..8].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+            &response[..8]
+        }
+        opcode::READ_FORMAT_CAPACITIES => {
+            // A single descriptor of formatted media.
+            response[3] = 8;
+            response[4..8].copy_from_slice(&(SECTOR_COUNT as u32).to_be_bytes());
+            response[8] = 0x02;
+            response[9..12].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes()[1..]);
+            &response[..12]
+        }
+        opcode::READ_10 => {
+            let (address, count) = command.blocks();
+            let mut sector = [0u8; SECTOR_SIZE];
+
+            for address in address..address + count {
+                storage.read_sector(address, &mut sector);
+                for packet in sector.chunks(PACKET_SIZE) {
+                    write_endpoint.write(packet).await.map_err(|_| ())?;
+                }
+            }
+            return Ok((
+                Status::Passed,
+                command.data_length.saturating_sub(count * SECTOR_SIZE as u32),
+            ));
+        }
+        opcode::WRITE_10 => {
+            let (address, count) = command.blocks();
+            let mut sector = [0u8; SECTOR_SIZE];
+
+            for address in address..address + count {
+                for packet in sector.chunks_mut(PACKET_SIZE) {
+                    read_endpoint.read(packet).await.map_err(|_| ())?;
+                }
+                storage.write_sector(address, &sector).await;
+            }
+            return Ok((
+                Status::Passed,
+                command.data_length.saturating_sub(count * SECTOR_SIZE as u32),
+            ));
+        }
+        _ => {
+            log_debug!("Unsupported SCSI command {:#x}", command.opcode());
+            storage.sense = SENSE_ILLEGAL_REQUEST;
+
+            if command.data_in {
+                let residue = send(write_endpoint, &[], command.data_length).await?;
+                return Ok((Status::Failed, residue));
+            }
+
+            // Data from the host is discarded.
+            let mut packet = [0u8; PACKET_SIZE];
+            let mut remaining = command.data_length as usize;
+            while remaining > 0 {
+                remaining = remaining.saturating_sub(read_endpoint.read(&mut packet).await.map_err(|_| ())?);
+            }
+            return Ok((Status::Failed, 0));
+        }
+    };
+
+    let residue = send(write_endpoint, response, command.data_length).await?;
+    Ok((Status::Passed, residue))
+}
+
+// Serves the virtual volume, until the device resets.
+#[embassy_executor::task]
+async fn msc_task(mut storage: Storage, mut endpoints: MscEndpoints) {
+    let mut cbw = [0u8; PACKET_SIZE];
+
+    loop {
+        endpoints.0.wait_enabled().await;
+        info!("Mass storage configuration mode");
+
+        loop {
+            let Ok(length) = endpoints.0.read(&mut cbw).await else {
+                break;
+            };
+            let Some(command) = Command::parse(&cbw[..length]) else {
+                warn!("Invalid command block wrapper");
+                continue;
+            };
+
+            let Ok((status, residue)) = execute(&mut storage, &command, &mut endpoints).await else {
+                break;
+            };
+
+            let mut csw = [0u8; CSW_SIZE];
+            csw[..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+            csw[4..8].copy_from_slice(&command.tag.to_le_bytes());
+            csw[8..12].copy_from_slice(&residue.to_le_bytes());
+            csw[12] = status as u8;
+            if endpoints.1.write(&csw).await.is_err() {
+                break;
+            }
+
+            if storage.reset_pending {
+                Timer::after_millis(RESET_DELAY_MS).await;
+                SCB::sys_reset();
+            }
+        }
+    }
+}
+
+/// Enumerate as a mass storage device, until a configuration was applied.
+pub async fn run(
+    spawner: embassy_executor::Spawner,
+    driver: UsbDriver,
+    store: SettingsStore,
+    identity: UsbIdentity,
+) -> ! {
+    static CONFIG_FILE: StaticCell<[u8; config_file::MAX_SIZE]> = StaticCell::new();
+    let text = CONFIG_FILE.init([0; config_file::MAX_SIZE]);
+    let config_length = config_file::write(&settings::get(), text);
+
+    static FILES: StaticCell<[File<'static>; 2]> = StaticCell::new();
+    let files = FILES.init([
+        File {
+            name: *b"README  TXT",
+            long_name: None,
+            size: README.len() as u32,
+        },
+        File {
+            name: *b"CONFIG  JSO",
+            long_name: Some("config.json"),
+            size: config_length as u32,
+        },
+    ]);
+
+    let storage = Storage {
+        volume: Volume {
+            label: *b"BLUS CONFIG",
+            serial_number: 0x424c_5553,
+            sector_count: SECTOR_COUNT,
+            files,
+        },
+        config: &text[..config_length],
+        upload: Upload::None,
+        store,
+        sense: (0, 0),
+        reset_pending: false,
+    };
+
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 64]> = StaticCell::new();
+    static BOS_DESCRIPTOR: StaticCell<[u8; 32]> = StaticCell::new();
+    static CONTROL_BUF: StaticCell<[u8; USB_CONTROL_BUF_SIZE]> = StaticCell::new();
+
+    let mut builder = Builder::new(
+        driver,
+        identity.config(),
+        CONFIG_DESCRIPTOR.init([0; 64]),
+        BOS_DESCRIPTOR.init([0; 32]),
+        &mut [],
+        CONTROL_BUF.init([0; USB_CONTROL_BUF_SIZE]),
+    );
+
+    let (interface, endpoints) = {
+        let mut function = builder.function(MSC_CLASS, SCSI_SUBCLASS, BULK_ONLY_PROTOCOL);
+        let mut interface = function.interface();
+        let number = interface.interface_number();
+        let mut alt_setting = interface.alt_setting(MSC_CLASS, SCSI_SUBCLASS, BULK_ONLY_PROTOCOL, None);
+        let read_endpoint = alt_setting.endpoint_bulk_out(PACKET_SIZE as u16);
+        let write_endpoint = alt_setting.endpoint_bulk_in(PACKET_SIZE as u16);
+
+        (number, (read_endpoint, write_endpoint))
+    };
+
+    static MSC_HANDLER: StaticCell<MscHandler> = StaticCell::new();
+    builder.handler(MSC_HANDLER.init(MscHandler { interface }));
+
+    unwrap!(spawner.spawn(msc_task(storage, endpoints)));
+    builder.build().run().await
+}
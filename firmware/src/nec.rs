@@ -0,0 +1,137 @@
+// Decoder for the NEC infrared remote control protocol, fed with the durations between edges of the receiver output.
+//
+// A frame starts with a 9 ms mark and a 4.5 ms space, followed by 32 bits (LSB first): address, inverted address,
+// command, and inverted command. Every bit is a 562 us mark, followed by a 562 us (zero) or 1687 us (one) space. While
+// a key is held, repeat frames (a 9 ms mark and a 2.25 ms space) follow every 108 ms.
+//
+// Only durations are evaluated, not levels, so the receiver's polarity does not matter. Long idle times never match a
+// mark, which synchronizes the decoder with the next leader.
+use defmt::Format;
+
+// Nominal durations in microseconds.
+const LEADER_MARK_US: u32 = 9000;
+const LEADER_SPACE_US: u32 = 4500;
+const REPEAT_SPACE_US: u32 = 2250;
+const BIT_MARK_US: u32 = 562;
+const ZERO_SPACE_US: u32 = 562;
+const ONE_SPACE_US: u32 = 1687;
+
+const BIT_COUNT: u8 = 32;
+
+/// A key code. Addresses of the extended protocol use 16 bit, others only the lower 8 bit.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct Code {
+    pub address: u16,
+    pub command: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Event {
+    Code(Code),
+    /// The previous key is still held.
+    Repeat,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    LeaderMark,
+    // Marks have even, spaces odd intervals.
+    Data { interval: u8, bits: u32 },
+}
+
+// Accepts a tolerance of 25 %.
+fn matches(duration_us: u32, nominal_us: u32) -> bool {
+    (nominal_us * 3 / 4..=nominal_us * 5 / 4).contains(&duration_us)
+}
+
+fn decode(bits: u32) -> Option<Code> {
+    let [address, address_inverted, command, command_inverted] = bits.to_le_bytes();
+
+    if command != !command_inverted {
+        return None;
+    }
+
+    let address = if address == !address_inverted {
+        address as u16
+    } else {
+        u16::from_le_bytes([address, address_inverted])
+    };
+
+    Some(Code { address, command })
+}
+
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { state: State::Idle }
+    }
+
+    // Start over, where the interval may be the mark of the next leader.
+    fn restart(&mut self, duration_us: u32) {
+        self.state = if matches(duration_us, LEADER_MARK_US) {
+            State::LeaderMark
+        } else {
+            State::Idle
+        };
+    }
+
+    /// Process the duration between two edges, returning an event after a complete frame.
+    pub fn update(&mut self, duration_us: u32) -> Option<Event> {
+        match self.state {
+            State::Idle => self.restart(duration_us),
+            State::LeaderMark => {
+                if matches(duration_us, LEADER_SPACE_US) {
+                    self.state = State::Data { interval: 0, bits: 0 };
+                } else if matches(duration_us, REPEAT_SPACE_US) {
+                    self.state = State::Idle;
+                    return Some(Event::Repeat);
+                } else {
+                    self.restart(duration_us);
+                }
+            }
+            State::Data { interval, bits } if interval % 2 == 0 => {
+                if matches(duration_us, BIT_MARK_US) {
+                    self.state = State::Data {
+                        interval: interval + 1,
+                        bits,
+                    };
+                } else {
+                    self.restart(duration_us);
+                }
+            }
+            State::Data { interval, bits } => {
+                let bit = if matches(duration_us, ZERO_SPACE_US) {
+                    0
+                } else if matches(duration_us, ONE_SPACE_US) {
+                    1
+                } else {
+                    self.restart(duration_us);
+                    return None;
+                };
+
+                let bits = bits | bit << (interval / 2);
+                let interval = interval + 1;
+
+                if interval < 2 * BIT_COUNT {
+                    self.state = State::Data { interval, bits };
+                } else {
+                    // The trailing mark is not awaited.
+                    self.state = State::Idle;
+                    return decode(bits).map(Event::Code);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,43 @@
+// Night mode: reduced dynamic range and bass, for listening at low levels.
+//
+// A gentle compressor limits loud passages and raises quiet ones, and a low shelf reduces the bass, which carries
+// furthest. Night mode applies on top of the active preset. It is toggled by a button or a vendor request, and not
+// persisted, such that the device always starts at full range.
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use defmt::info;
+
+use crate::dsp::design::BUTTERWORTH_Q;
+use crate::dsp::{CompressorParams, Filter};
+use crate::*;
+
+// Full-scale peaks are reduced by 20 dB, and raised by the makeup gain to -14 dBFS.
+pub const COMPRESSOR: CompressorParams = CompressorParams {
+    threshold_db: -30.0,
+    ratio: 3.0,
+    attack_ms: 10.0,
+    release_ms: 300.0,
+    makeup_db: 6.0,
+};
+
+pub const BASS_SHELF: Filter = Filter::LowShelf {
+    frequency_hz: 150.0,
+    q: BUTTERWORTH_Q,
+    gain_db: -6.0,
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Relaxed)
+}
+
+/// Enable or disable night mode, and apply it to the DSP chain.
+pub fn set(enabled: bool) {
+    info!("Night mode: {}", enabled);
+    ENABLED.store(enabled, Relaxed);
+    PRESET_SIGNAL.signal(preset::active());
+}
+
+pub fn toggle() {
+    set(!is_enabled());
+}
@@ -0,0 +1,190 @@
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{info, warn};
+#[cfg(feature = "dual-zone")]
+use embassy_futures::join::join;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::i2s::I2S;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::zerocopy_channel;
+use embassy_time::{with_timeout, with_timeout_at, Duration, Instant, Timer};
+
+use crate::profile::{self, Stage};
+use crate::watchdog::{self, Task};
+use crate::*;
+use crate::{i2s_clock, latency, prefill, stats};
+
+// Output stops, if no samples were received for this long.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Written to the DMA ring buffer when the stream closes, so that the last samples are not repeated.
+static SILENCE: [u16; I2S_BUFFER_SIZE] = [0; I2S_BUFFER_SIZE];
+
+// Plays back received USB sample blocks via I2S (DMA ring buffer).
+#[embassy_executor::task]
+pub async fn i2s_task(
+    mut i2s: I2S<'static, u16>,
+    mut receiver: zerocopy_channel::Receiver<'static, NoopRawMutex, UsbSampleBlock>,
+) {
+    // The second zone's output, which runs along with the first.
+    #[cfg(feature = "dual-zone")]
+    let mut zone_2_i2s = defmt::unwrap!(zone::take_output());
+    #[cfg(feature = "dual-zone")]
+    let mut zone_2_words = [0u16; zone::ZONE_2_BLOCK_SIZE];
+
+    let mut adaptation = prefill::Adaptation::new();
+
+    loop {
+        // Wait for the first block of a stream.
+        _ = watchdog::idle(Task::Output, receiver.receive()).await;
+        STREAM_OPEN_SIGNAL.reset();
+
+        // Pre-fill the channel, before starting output. A stereo link's secondary queues as many blocks as its primary
+        // pre-filled, up to the largest pre-fill.
+        #[cfg(not(feature = "stereo-link-secondary"))]
+        let prefill_block_count = prefill::block_count();
+        #[cfg(feature = "stereo-link-secondary")]
+        let prefill_block_count = MAX_OUTPUT_PREFILL_BLOCK_COUNT;
+
+        let prefill_deadline = Instant::now() + RECEIVE_TIMEOUT * prefill_block_count as u32;
+        #[cfg(not(feature = "stereo-link-secondary"))]
+        while receiver.len() < prefill_block_count && Instant::now() < prefill_deadline {
+            Timer::after_millis(1).await;
+        }
+
+        // A stereo link's secondary pre-fills the blocks that its primary did, which precede the start message.
+        #[cfg(feature = "stereo-link-secondary")]
+        _ = with_timeout_at(prefill_deadline, LINK_START_SIGNAL.wait()).await;
+
+        if let Some(sample_rate_hz) = SAMPLE_RATE_SIGNAL.try_take() {
+            match i2s_clock::set_sample_rate(sample_rate_hz) {
+                Ok(()) => {
+                    // The feedback timer runs on the new clock.
+                    stats::restart_tick_histogram();
+                    #[cfg(feature = "spdif-output")]
+                    spdif::set_sample_rate(sample_rate_hz);
+                }
+                Err(e) => warn!("Cannot reconfigure I2S clock: {}", e),
+            }
+        }
+
+        // Queue the pre-filled blocks before starting the DMA, so that output starts at a block boundary, instead of
+        // playing stale or partial blocks.
+        for _ in 0..prefill_block_count {
+            let Some(samples) = receiver.try_receive() else {
+                break;
+            };
+
+            #[cfg(feature = "dual-zone")]
+            {
+                let length = zone::route(samples, &mut zone_2_words);
+                _ = zone_2_i2s.write_immediate(&zone_2_words[..length]).await;
+            }
+            let result = i2s.write_immediate(samples.words()).await;
+            #[cfg(feature = "spdif-output")]
+            spdif::write(samples.words());
+            #[cfg(feature = "stereo-link-primary")]
+            link::write(samples.words());
+            let queued = matches!(result, Ok((written, _)) if written == samples.words().len());
+            receiver.receive_done();
+            stats::block_dequeued();
+            latency::block_received(queued);
+        }
+
+        #[cfg(feature = "stereo-link-primary")]
+        link::start().await;
+
+        info!("Start I2S output");
+        i2s.start();
+        #[cfg(feature = "dual-zone")]
+        zone_2_i2s.start();
+        #[cfg(feature = "spdif-output")]
+        spdif::start();
+        I2S_IS_ACTIVE.store(true, Relaxed);
+        I2S_ACTIVE_SIGNAL.signal(true);
+
+        loop {
+            watchdog::check_in(Task::Output);
+
+            let samples = match select(
+                with_timeout(RECEIVE_TIMEOUT, receiver.receive()),
+                STREAM_OPEN_SIGNAL.wait(),
+            )
+            .await
+            {
+                Either::First(Ok(samples)) => samples,
+                Either::First(Err(_)) => {
+                    log_debug!("No samples received");
+
+                    if USB_IS_STREAMING.load(Relaxed) {
+                        stats::record_underrun();
+                        adaptation.record_underrun(Instant::now());
+                    }
+                    break;
+                }
+                Either::Second(true) => continue,
+                Either::Second(false) => {
+                    // The host closed the stream (alt setting 0), mute by replacing buffered samples with silence.
+                    log_debug!("Stream closed");
+                    // Bounded, since a stereo link's secondary loses its clocks, when the primary stops.
+                    _ = with_timeout(RECEIVE_TIMEOUT, i2s.write(&SILENCE)).await;
+                    #[cfg(feature = "dual-zone")]
+                    _ = with_timeout(RECEIVE_TIMEOUT, zone_2_i2s.write(&SILENCE)).await;
+                    break;
+                }
+            };
+
+            #[cfg(not(feature = "dual-zone"))]
+            let result = profile::measure(Stage::DmaRefill, i2s.write(samples.words())).await;
+
+            // Both zones' ring buffers drain at the same rate.
+            #[cfg(feature = "dual-zone")]
+            let result = {
+                let length = {
+                    profile_scope!(Stage::Conversion);
+                    zone::route(samples, &mut zone_2_words)
+                };
+                let writes = join(i2s.write(samples.words()), zone_2_i2s.write(&zone_2_words[..length]));
+                let (result, zone_2_result) = profile::measure(Stage::DmaRefill, writes).await;
+                result.and(zone_2_result)
+            };
+            #[cfg(feature = "spdif-output")]
+            spdif::write(samples.words());
+            #[cfg(feature = "stereo-link-primary")]
+            link::write(samples.words());
+            let sample_count = samples.sample_count();
+            receiver.receive_done();
+            stats::block_dequeued();
+            latency::block_received(result.is_ok());
+
+            if result.is_err() {
+                warn!("I2S buffer overrun");
+                stats::record_overrun();
+                // The DMA overtook the written samples, since blocks arrived late.
+                adaptation.record_underrun(Instant::now());
+                stats::record_dropped(sample_count);
+                break;
+            }
+        }
+
+        // The secondary stops first, while the clocks still run.
+        #[cfg(feature = "stereo-link-primary")]
+        link::stop();
+
+        info!("Stop I2S output");
+        i2s.stop().await;
+        #[cfg(feature = "dual-zone")]
+        zone_2_i2s.stop().await;
+        #[cfg(feature = "spdif-output")]
+        spdif::stop();
+        I2S_IS_ACTIVE.store(false, Relaxed);
+        I2S_ACTIVE_SIGNAL.signal(false);
+
+        // Discard stale samples, so that they are not played at the start of the next stream.
+        while let Some(samples) = receiver.try_receive() {
+            stats::record_dropped(samples.sample_count());
+            receiver.receive_done();
+            stats::block_dequeued();
+            latency::block_received(false);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// Partition layout of the external SPI flash (at least 2 MiB, e.g. W25Q16), and the format of blobs in partitions.
+//
+// | Partition    | Offset  | Size    | Contents                                  |
+// | ------------ | ------- | ------- | ----------------------------------------- |
+// | coefficients | 0       | 512 kiB | coefficient sets (e.g. for amplifiers)    |
+// | presets      | 512 kiB | 64 kiB  | DSP presets                               |
+// | staged image | 1 MiB   | 1 MiB   | firmware image for the next update        |
+//
+// Staged images and coefficient blobs start with a header (magic, length, CRC), followed by the data. The CRC matches
+// the image CRC of the firmware (CRC-32/MPEG-2 over 32 bit words). Installing a staged image overwrites the running
+// application, so it is the task of a bootloader: it validates the staged image like `validate_staged_image`, copies it
+// to the internal flash, and invalidates the header.
+use defmt::{info, warn, Format};
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::{mode, spi};
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::mutex::Mutex;
+
+use crate::image_crc;
+use crate::spi_flash::{SpiFlash, SECTOR_SIZE};
+
+pub type SpiBus = Mutex<NoopRawMutex, spi::Spi<'static, mode::Async>>;
+pub type ExternalFlash = SpiFlash<SpiDevice<'static, NoopRawMutex, spi::Spi<'static, mode::Async>, Output<'static>>>;
+
+/// The external flash, once it was detected.
+pub static EXTERNAL_FLASH: Mutex<ThreadModeRawMutex, Option<ExternalFlash>> = Mutex::new(None);
+
+// Minimum capacity for the partition layout.
+const REQUIRED_CAPACITY: u32 = 2 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct Partition {
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+}
+
+pub const COEFFICIENTS: Partition = Partition {
+    name: "coefficients",
+    offset: 0,
+    size: 512 * 1024,
+};
+
+pub const PRESETS: Partition = Partition {
+    name: "presets",
+    offset: 512 * 1024,
+    size: 64 * 1024,
+};
+
+pub const STAGED_IMAGE: Partition = Partition {
+    name: "staged image",
+    offset: 1024 * 1024,
+    size: 1024 * 1024,
+};
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum PartitionError {
+    Flash,
+    OutOfBounds,
+}
+
+impl Partition {
+    fn check_bounds(&self, offset: u32, length: usize) -> Result<u32, PartitionError> {
+        match offset.checked_add(length as u32) {
+            Some(end) if end <= self.size => Ok(self.offset + offset),
+            _ => Err(PartitionError::OutOfBounds),
+        }
+    }
+
+    pub async fn read<SPI: embedded_hal_async::spi::SpiDevice>(
+        &self,
+        flash: &mut SpiFlash<SPI>,
+        offset: u32,
+        buffer: &mut [u8],
+    ) -> Result<(), PartitionError> {
+        let address = self.check_bounds(offset, buffer.len())?;
+        flash.read(address, buffer).await.map_err(|_| PartitionError::Flash)
+    }
+
+    /// Program erased memory within the partition.
+    pub async fn write<SPI: embedded_hal_async::spi::SpiDevice>(
+        &self,
+        flash: &mut SpiFlash<SPI>,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), PartitionError> {
+        let address = self.check_bounds(offset, data.len())?;
+        flash.write(address, data).await.map_err(|_| PartitionError::Flash)
+    }
+
+    /// Erase all sectors that overlap the range.
+    pub async fn erase<SPI: embedded_hal_async::spi::SpiDevice>(
+        &self,
+        flash: &mut SpiFlash<SPI>,
+        offset: u32,
+        length: u32,
+    ) -> Result<(), PartitionError> {
+        let start = self.check_bounds(offset, length as usize)?;
+
+        let mut address = start & !(SECTOR_SIZE - 1);
+        while address < start + length {
+            flash.erase_sector(address).await.map_err(|_| PartitionError::Flash)?;
+            address += SECTOR_SIZE;
+        }
+
+        Ok(())
+    }
+}
+
+// Marks a staged image.
+const STAGED_IMAGE_MAGIC: u32 = 0x5354_4147;
+
+/// Marks a coefficient blob.
+pub const COEFFICIENTS_MAGIC: u32 = 0x434f_4546;
+
+/// Size of the header (magic, length, CRC) that precedes a blob in a partition.
+pub const BLOB_HEADER_SIZE: u32 = 12;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct BlobHeader {
+    pub length: u32,
+    pub crc: u32,
+}
+
+/// Check the blob at the start of a partition, returning its header if it is complete and valid.
+pub async fn validate_blob<SPI: embedded_hal_async::spi::SpiDevice>(
+    flash: &mut SpiFlash<SPI>,
+    partition: &Partition,
+    expected_magic: u32,
+) -> Result<Option<BlobHeader>, PartitionError> {
+    let mut header = [0u8; BLOB_HEADER_SIZE as usize];
+    partition.read(flash, 0, &mut header).await?;
+
+    let word = |index: usize| u32::from_le_bytes(header[4 * index..4 * index + 4].try_into().unwrap());
+    let (magic, length, crc) = (word(0), word(1), word(2));
+
+    if magic != expected_magic || length % 4 != 0 || length > partition.size - BLOB_HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let mut actual = image_crc::CRC_INITIAL;
+    let mut chunk = [0u8; 256];
+    let mut offset = 0;
+
+    while offset < length {
+        let chunk_length = chunk.len().min((length - offset) as usize);
+        partition
+            .read(flash, BLOB_HEADER_SIZE + offset, &mut chunk[..chunk_length])
+            .await?;
+
+        for word in chunk[..chunk_length].chunks_exact(4) {
+            actual = image_crc::crc_update(actual, u32::from_le_bytes(word.try_into().unwrap()));
+        }
+
+        offset += chunk_length as u32;
+    }
+
+    if actual == crc {
+        Ok(Some(BlobHeader { length, crc }))
+    } else {
+        warn!("CRC mismatch in {} partition", partition.name);
+        Ok(None)
+    }
+}
+
+/// Check the staged image, returning its header if it is complete and valid.
+pub async fn validate_staged_image<SPI: embedded_hal_async::spi::SpiDevice>(
+    flash: &mut SpiFlash<SPI>,
+) -> Result<Option<BlobHeader>, PartitionError> {
+    validate_blob(flash, &STAGED_IMAGE, STAGED_IMAGE_MAGIC).await
+}
+
+/// Detect the external flash, report the staged image, and provide the flash to other tasks.
+#[embassy_executor::task]
+pub async fn init_task(spi_bus: &'static SpiBus, cs: Output<'static>) {
+    // Held during detection, so that users which start at the same time wait for the result.
+    let mut external_flash = EXTERNAL_FLASH.lock().await;
+    let mut flash = SpiFlash::new(SpiDevice::new(spi_bus, cs));
+
+    let id = match flash.jedec_id().await {
+        Ok(id) if id.capacity_bytes() >= REQUIRED_CAPACITY => id,
+        Ok(id) => {
+            warn!("External flash is too small: {}", id);
+            return;
+        }
+        Err(_) => {
+            warn!("No external flash found");
+            return;
+        }
+    };
+    info!("External flash: {} ({} byte)", id, id.capacity_bytes());
+
+    match validate_staged_image(&mut flash).await {
+        Ok(Some(header)) => info!("Valid staged image: {}", header),
+        Ok(None) => info!("No staged image"),
+        Err(e) => warn!("Failed to read staged image: {}", e),
+    }
+
+    *external_flash = Some(flash);
+}
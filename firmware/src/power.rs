@@ -0,0 +1,65 @@
+// Runtime clock adjustments for saving power, e.g. during USB suspend.
+//
+// The executor sleeps (WFE) whenever no task is ready, which stops the core clock. Peripherals keep their clocks in
+// sleep, unless gated by the RCC's low-power enable registers. Streaming needs DMA, SRAM, USB, I2S/SAI, I2C, and the
+// timers (SOF capture, time driver, IR capture, and the aux input's trigger) to run on, and flash stays clocked, since
+// DMA may read constant data from it. The GPIO ports that carry the board's pins (I2S, USB, I2C, and the wake-up
+// sources), SYSCFG (EXTI line selection), and PWR also stay clocked. Only peripherals that are unused while streaming
+// are gated in sleep: the GPIO ports without pins of the board, and the CRC unit, which is only used for the
+// image check at boot, and disabled afterwards.
+//
+// The core clock is halved by the AHB prescaler, while the APB1 prescaler is reduced accordingly (see `chip`). This
+// keeps APB1 peripherals (I2C) at their configured clock, but APB2 peripherals and all timers run at half speed. In
+// particular, `embassy_time` runs slow while clocks are reduced, so only coarse timing must be relied on.
+use embassy_stm32::pac;
+use embassy_stm32::pac::rcc::vals::Hpre;
+
+use crate::chip::{APB1_PRESCALER, APB1_PRESCALER_REDUCED};
+
+pub fn reduce_clocks() {
+    critical_section::with(|_| {
+        // Lower HCLK first, so that APB1 never exceeds its maximum frequency.
+        pac::RCC.cfgr().modify(|w| w.set_hpre(Hpre::DIV2));
+        pac::RCC.cfgr().modify(|w| w.set_ppre1(APB1_PRESCALER_REDUCED));
+    });
+}
+
+pub fn restore_clocks() {
+    critical_section::with(|_| {
+        pac::RCC.cfgr().modify(|w| w.set_ppre1(APB1_PRESCALER));
+        pac::RCC.cfgr().modify(|w| w.set_hpre(Hpre::DIV1));
+    });
+}
+
+/// Gate the clocks of peripherals that are idle while the core sleeps, and of unused peripherals. Must be called after
+/// the image check.
+pub fn gate_idle_clocks() {
+    critical_section::with(|_| {
+        pac::RCC.ahb1enr().modify(|w| w.set_crcen(false));
+
+        // Boards use ports A to C, and the F4-Discovery board also port D. Port H only carries the HSE oscillator's
+        // pins, which do not depend on the port's clock.
+        pac::RCC.ahb1lpenr().modify(|w| {
+            #[cfg(not(feature = "board-f4-discovery"))]
+            w.set_gpiodlpen(false);
+            w.set_gpioelpen(false);
+            #[cfg(feature = "chip-f446")]
+            {
+                w.set_gpioflpen(false);
+                w.set_gpioglpen(false);
+            }
+            w.set_gpiohlpen(false);
+            w.set_crclpen(false);
+        });
+    });
+}
+
+// Disable the I2S PLL. The I2S peripheral must be stopped beforehand.
+pub fn stop_i2s_clock() {
+    pac::RCC.cr().modify(|w| w.set_plli2son(false));
+}
+
+pub fn start_i2s_clock() {
+    pac::RCC.cr().modify(|w| w.set_plli2son(true));
+    while !pac::RCC.cr().read().plli2srdy() {}
+}
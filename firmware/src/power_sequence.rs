@@ -0,0 +1,45 @@
+// Power sequencing of the output stage (amplifiers or codec), which avoids pops at power-up, wake-up, and shutdown.
+//
+// Pops arise when outputs are unmuted before they settled (charge pumps, DC bias), or powered down while they still
+// carry a signal. Therefore, the output stage
+// - is configured once its enable line (shutdown or reset) was released, and the stage is ready,
+// - leaves standby muted, and is only unmuted by setting the volume, once its outputs settled,
+// - is muted before entering standby, and has its outputs discharged before power is reduced further (USB suspend).
+//
+// Timings depend on the output stage, and are provided per board (`board::POWER_SEQUENCE`).
+use embassy_time::Timer;
+
+/// Settling times of an output stage's power sequence, in ms.
+pub struct PowerSequence {
+    /// From releasing the enable line, until the stage accepts configuration.
+    pub enable_ms: u64,
+    /// From leaving standby, until the outputs settled for unmuting.
+    pub settle_ms: u64,
+    /// From muting, until the mute ramp completed.
+    pub mute_ms: u64,
+    /// From entering standby, until the outputs discharged.
+    pub discharge_ms: u64,
+}
+
+impl PowerSequence {
+    pub async fn wait_enabled(&self) {
+        Timer::after_millis(self.enable_ms).await;
+    }
+
+    pub async fn wait_settled(&self) {
+        Timer::after_millis(self.settle_ms).await;
+    }
+
+    pub async fn wait_muted(&self) {
+        Timer::after_millis(self.mute_ms).await;
+    }
+
+    pub async fn wait_discharged(&self) {
+        Timer::after_millis(self.discharge_ms).await;
+    }
+
+    /// Duration of a shutdown into standby, from muting.
+    pub const fn shutdown_ms(&self) -> u64 {
+        self.mute_ms + self.discharge_ms
+    }
+}
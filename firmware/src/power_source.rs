@@ -0,0 +1,67 @@
+// The device's power source, detected once at boot, before enumeration.
+//
+// With the `power-detect` feature, the custom board senses the external supply on a GPIO. The device then enumerates
+// as self-powered, or as bus-powered with the full configuration current. Bus power cannot supply the amplifiers' peak
+// current, so that their volume is capped. Without the feature, an external supply is assumed.
+//
+// The configuration descriptor only changes with a new enumeration, so that a supply that is connected or removed later
+// takes effect at the next boot.
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_usb::class::uac1::speaker::Volume;
+
+use crate::*;
+
+// Highest amplifier volume on bus power (a quarter of full power).
+const BUS_POWERED_MAX_VOLUME_DB: f32 = -6.0;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum PowerSource {
+    Bus,
+    External,
+}
+
+impl PowerSource {
+    pub const fn self_powered(self) -> bool {
+        matches!(self, Self::External)
+    }
+
+    /// Current that is drawn from the bus, as described to the host.
+    pub const fn max_power_ma(self) -> u16 {
+        match self {
+            Self::Bus => USB_BUS_POWERED_MAX_POWER_MA,
+            Self::External => USB_SELF_POWERED_MAX_POWER_MA,
+        }
+    }
+}
+
+static BUS_POWERED: AtomicBool = AtomicBool::new(false);
+
+/// Record the power source, from the presence of the external supply. Must be called before the USB device is built.
+pub fn detect(external_supply: bool) -> PowerSource {
+    let source = if external_supply {
+        PowerSource::External
+    } else {
+        PowerSource::Bus
+    };
+
+    info!("Power source: {}", source);
+    BUS_POWERED.store(source == PowerSource::Bus, Relaxed);
+    source
+}
+
+pub fn active() -> PowerSource {
+    if BUS_POWERED.load(Relaxed) {
+        PowerSource::Bus
+    } else {
+        PowerSource::External
+    }
+}
+
+/// Cap a volume to what the power source can supply.
+pub fn limit(volume: Volume) -> Volume {
+    match (active(), volume) {
+        (PowerSource::Bus, Volume::DeciBel(db)) => Volume::DeciBel(db.min(BUS_POWERED_MAX_VOLUME_DB)),
+        _ => volume,
+    }
+}
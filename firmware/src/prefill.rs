@@ -0,0 +1,91 @@
+// Adaptive buffering: when underruns recur within a window, the output's pre-fill grows by a millisecond, up to
+// `MAX_OUTPUT_PREFILL_MS`. This trades latency for stability on hosts with irregular isochronous scheduling. The
+// learned depth is stored with the settings, and applies from the next stream. Returning to a fixed pre-fill also
+// resets the learned depth to `OUTPUT_PREFILL_MS`.
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant};
+use heapless::HistoryBuffer;
+
+use crate::*;
+
+// The pre-fill grows after this many underruns within the window.
+const UNDERRUN_COUNT: usize = 3;
+const UNDERRUN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Whether the pre-fill adapts to underruns.
+pub fn is_adaptive() -> bool {
+    settings::get().adaptive_prefill
+}
+
+/// The output's pre-fill in ms: the learned depth, if adaptive, otherwise the default.
+pub fn depth_ms() -> usize {
+    let settings = settings::get();
+
+    if settings.adaptive_prefill {
+        settings.prefill_ms as usize
+    } else {
+        OUTPUT_PREFILL_MS
+    }
+}
+
+/// Number of sample blocks that are buffered before output starts.
+pub fn block_count() -> usize {
+    depth_ms() * USB_FRAMES_PER_MS
+}
+
+/// Enable or disable adaptive buffering. Disabling it forgets the learned depth.
+pub fn set_adaptive(enabled: bool) {
+    info!("Adaptive pre-fill: {}", enabled);
+
+    settings::modify(|settings| {
+        settings.adaptive_prefill = enabled;
+        if !enabled {
+            settings.prefill_ms = OUTPUT_PREFILL_MS as u8;
+        }
+    });
+}
+
+/// Tracks the output's underruns, and grows the pre-fill when they recur.
+pub struct Adaptation {
+    underruns: HistoryBuffer<Instant, UNDERRUN_COUNT>,
+}
+
+impl Adaptation {
+    pub const fn new() -> Self {
+        Self {
+            underruns: HistoryBuffer::new(),
+        }
+    }
+
+    /// Register an underrun, and grow the pre-fill, if too many occurred within the window.
+    pub fn record_underrun(&mut self, now: Instant) {
+        if !is_adaptive() {
+            return;
+        }
+
+        self.underruns.write(now);
+        let oldest = self.underruns.oldest_ordered().next().copied();
+
+        if !self.underruns.is_full() || oldest.is_some_and(|oldest| now - oldest >= UNDERRUN_WINDOW) {
+            return;
+        }
+
+        // Underruns at the new depth start a new window.
+        self.underruns.clear();
+
+        let depth_ms = depth_ms();
+        if depth_ms >= MAX_OUTPUT_PREFILL_MS {
+            warn!("Underruns recur at the largest pre-fill ({} ms)", depth_ms);
+            return;
+        }
+
+        info!("Growing pre-fill to {} ms", depth_ms + 1);
+        settings::modify(|settings| settings.prefill_ms = (depth_ms + 1) as u8);
+    }
+}
+
+impl Default for Adaptation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
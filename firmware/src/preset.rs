@@ -0,0 +1,456 @@
+// DSP presets, each consisting of an equalizer, a crossover high-pass, and per-channel gains.
+//
+// Presets are built into the firmware image. The active preset is selected by the wake-up button (while the host is
+// awake) or a vendor request, and its index is part of the persistent settings. With the `auto-eq` feature, the last
+// preset plays the measured correction, which is stored in the settings.
+//
+// The host tool can set user equalizer bands, which replace the active preset's equalizer while any band is set. They
+// are not persisted, and neither are live adjustments (e.g. from a MIDI controller) of the bands' gains and the
+// crossover frequency.
+use blus_core::gain::db_to_linear;
+use blus_core::protocol::{MAX_GAIN_DB, MIN_GAIN_DB};
+use core::cell::Cell;
+use defmt::{info, Format};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::alignment::MAX_DELAY_SAMPLES;
+use crate::dsp::design::{BUTTERWORTH_ORDER_4_Q, BUTTERWORTH_Q};
+use crate::dsp::emphasis;
+use crate::dsp::{BiquadCascade, Coefficients, Compressor, Delay, DspSample, Filter, Sample};
+#[cfg(feature = "speaker-protection")]
+use crate::protection::SpeakerProtection;
+use crate::*;
+
+// Maximum number of equalizer bands per preset.
+pub const EQ_BAND_COUNT: usize = 4;
+
+// Equalizer bands, followed by two Butterworth high-pass stages (a fourth-order Linkwitz-Riley crossover), the night
+// mode's bass shelf, the loudness compensation's two shelves, the de-emphasis filter, and the speaker protection's
+// excursion shelf (with the `speaker-protection` feature).
+const STAGE_COUNT: usize = EQ_BAND_COUNT + 6 + cfg!(feature = "speaker-protection") as usize;
+const NIGHT_MODE_STAGE: usize = EQ_BAND_COUNT + 2;
+const LOUDNESS_STAGES: usize = EQ_BAND_COUNT + 3;
+const DE_EMPHASIS_STAGE: usize = EQ_BAND_COUNT + 5;
+#[cfg(feature = "speaker-protection")]
+const PROTECTION_STAGE: usize = EQ_BAND_COUNT + 6;
+
+// Sections of the subsonic high-pass, which runs ahead of the gains (and thereby the night mode's compressor).
+const SUBSONIC_STAGE_COUNT: usize = BUTTERWORTH_ORDER_4_Q.len();
+
+pub struct Preset {
+    pub name: &'static str,
+    pub eq: &'static [Filter],
+    /// Crossover frequency towards a subwoofer, below which the main speakers are high-passed.
+    pub crossover_hz: Option<f32>,
+    /// Corner frequency of a fourth-order Butterworth high-pass, which protects the drivers of ported enclosures from
+    /// excessive excursion below the port's tuning.
+    pub subsonic_hz: Option<f32>,
+    pub gain_db: [f32; INPUT_CHANNEL_COUNT],
+    /// Plays the auto-EQ's stored correction as its equalizer, in place of `eq`.
+    pub measured: bool,
+}
+
+pub const PRESETS: [Preset; 5 + cfg!(feature = "auto-eq") as usize] = [
+    Preset {
+        name: "Flat",
+        eq: &[],
+        crossover_hz: None,
+        subsonic_hz: None,
+        gain_db: [0.0; INPUT_CHANNEL_COUNT],
+        measured: false,
+    },
+    // Reduced boominess and brightness at short listening distances.
+    Preset {
+        name: "Near-field",
+        eq: &[
+            Filter::Peaking {
+                frequency_hz: 180.0,
+                q: 1.0,
+                gain_db: -3.0,
+            },
+            Filter::HighShelf {
+                frequency_hz: 8_000.0,
+                q: BUTTERWORTH_Q,
+                gain_db: -2.0,
+            },
+        ],
+        crossover_hz: None,
+        subsonic_hz: None,
+        gain_db: [0.0; INPUT_CHANNEL_COUNT],
+        measured: false,
+    },
+    // Bass and treble boost for low listening levels, with headroom for the boost.
+    Preset {
+        name: "Loudness",
+        eq: &[
+            Filter::LowShelf {
+                frequency_hz: 100.0,
+                q: BUTTERWORTH_Q,
+                gain_db: 6.0,
+            },
+            Filter::HighShelf {
+                frequency_hz: 10_000.0,
+                q: BUTTERWORTH_Q,
+                gain_db: 3.0,
+            },
+        ],
+        crossover_hz: None,
+        subsonic_hz: None,
+        gain_db: [-6.0; INPUT_CHANNEL_COUNT],
+        measured: false,
+    },
+    // Main speakers above 80 Hz, for use with a subwoofer.
+    Preset {
+        name: "Subwoofer",
+        eq: &[],
+        crossover_hz: Some(80.0),
+        subsonic_hz: None,
+        gain_db: [0.0; INPUT_CHANNEL_COUNT],
+        measured: false,
+    },
+    // Full range, with subsonic protection for ported speakers tuned to about 40 Hz.
+    Preset {
+        name: "Ported",
+        eq: &[],
+        crossover_hz: None,
+        subsonic_hz: Some(30.0),
+        gain_db: [0.0; INPUT_CHANNEL_COUNT],
+        measured: false,
+    },
+    // The auto-EQ's room and driver correction (see `auto_eq`), with headroom for its boosts.
+    #[cfg(feature = "auto-eq")]
+    Preset {
+        name: "Room correction",
+        eq: &[],
+        crossover_hz: None,
+        subsonic_hz: None,
+        gain_db: [-3.0; INPUT_CHANNEL_COUNT],
+        measured: true,
+    },
+];
+
+#[derive(Clone, Copy, Format)]
+pub struct UnknownPreset(pub usize);
+
+#[derive(Clone, Copy, Format)]
+pub struct UnknownBand(pub usize);
+
+static USER_EQ: Mutex<CriticalSectionRawMutex, Cell<[Option<Filter>; EQ_BAND_COUNT]>> =
+    Mutex::new(Cell::new([None; EQ_BAND_COUNT]));
+
+/// Set (or clear) a user equalizer band, and apply it to the active preset.
+pub fn set_eq_band(index: usize, filter: Option<Filter>) -> Result<(), UnknownBand> {
+    if index >= EQ_BAND_COUNT {
+        return Err(UnknownBand(index));
+    }
+
+    info!("Set user EQ band {}", index);
+    USER_EQ.lock(|user_eq| {
+        let mut bands = user_eq.get();
+        bands[index] = filter;
+        user_eq.set(bands);
+    });
+    PRESET_SIGNAL.signal(active());
+
+    Ok(())
+}
+
+// Live adjustments: gain offsets of the equalizer bands, and a crossover frequency in place of the preset's.
+#[derive(Clone, Copy)]
+struct Adjustments {
+    eq_gain_db: [f32; EQ_BAND_COUNT],
+    crossover_hz: Option<f32>,
+}
+
+impl Adjustments {
+    const NONE: Self = Self {
+        eq_gain_db: [0.0; EQ_BAND_COUNT],
+        crossover_hz: None,
+    };
+}
+
+static ADJUSTMENTS: Mutex<CriticalSectionRawMutex, Cell<Adjustments>> = Mutex::new(Cell::new(Adjustments::NONE));
+
+fn modify_adjustments(f: impl FnOnce(&mut Adjustments)) {
+    ADJUSTMENTS.lock(|adjustments| {
+        let mut modified = adjustments.get();
+        f(&mut modified);
+        adjustments.set(modified);
+    });
+    PRESET_SIGNAL.signal(active());
+}
+
+/// Offset the gain of an equalizer band (of the preset or the user's), within the bands' gain limits.
+pub fn adjust_eq_gain(index: usize, offset_db: f32) -> Result<(), UnknownBand> {
+    if index >= EQ_BAND_COUNT {
+        return Err(UnknownBand(index));
+    }
+
+    modify_adjustments(|adjustments| adjustments.eq_gain_db[index] = offset_db);
+    Ok(())
+}
+
+/// Set the crossover frequency, or restore the preset's.
+pub fn adjust_crossover(frequency_hz: Option<f32>) {
+    modify_adjustments(|adjustments| adjustments.crossover_hz = frequency_hz);
+}
+
+/// Remove all live adjustments.
+pub fn reset_adjustments() {
+    info!("Reset live adjustments");
+    modify_adjustments(|adjustments| *adjustments = Adjustments::NONE);
+}
+
+// Offset a band's gain, which leaves pass filters unchanged.
+fn offset_gain(filter: Filter, offset_db: f32) -> Filter {
+    let offset = |gain_db: f32| (gain_db + offset_db).clamp(MIN_GAIN_DB, MAX_GAIN_DB);
+
+    match filter {
+        Filter::Peaking {
+            frequency_hz,
+            q,
+            gain_db,
+        } => Filter::Peaking {
+            frequency_hz,
+            q,
+            gain_db: offset(gain_db),
+        },
+        Filter::LowShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        } => Filter::LowShelf {
+            frequency_hz,
+            q,
+            gain_db: offset(gain_db),
+        },
+        Filter::HighShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        } => Filter::HighShelf {
+            frequency_hz,
+            q,
+            gain_db: offset(gain_db),
+        },
+        pass => pass,
+    }
+}
+
+/// Select a preset by index, which is stored in the settings.
+pub fn select(index: usize) -> Result<(), UnknownPreset> {
+    let preset = PRESETS.get(index).ok_or(UnknownPreset(index))?;
+
+    info!("Select preset {} ({})", index, preset.name);
+    settings::modify(|settings| settings.preset = index as u8);
+    PRESET_SIGNAL.signal(index);
+
+    #[cfg(feature = "chimes")]
+    chime::play(chime::Alert::PresetChanged);
+
+    Ok(())
+}
+
+/// Select the next preset, wrapping around after the last one.
+pub fn select_next() {
+    let index = (active() + 1) % PRESETS.len();
+    select(index).unwrap();
+}
+
+/// The index of the active preset, falling back to the first one for invalid settings.
+pub fn active() -> usize {
+    let index = settings::get().preset as usize;
+
+    if index < PRESETS.len() {
+        index
+    } else {
+        0
+    }
+}
+
+/// The processing chain for incoming samples, configured by a preset, night mode, loudness compensation, de-emphasis,
+/// and the channels' delays and polarities. The preset's subsonic high-pass comes first, ahead of all gains.
+pub struct DspChain {
+    subsonic: [BiquadCascade<DspSample, SUBSONIC_STAGE_COUNT>; INPUT_CHANNEL_COUNT],
+    filters: [BiquadCascade<DspSample, STAGE_COUNT>; INPUT_CHANNEL_COUNT],
+    delays: [Delay<DspSample, MAX_DELAY_SAMPLES>; INPUT_CHANNEL_COUNT],
+    inverted: [bool; INPUT_CHANNEL_COUNT],
+    gains: [<DspSample as Sample>::Gain; INPUT_CHANNEL_COUNT],
+    // The preset's gains, to which the compressor's gain is applied in night mode, and the speaker protection's.
+    preset_gains: [f32; INPUT_CHANNEL_COUNT],
+    compressor: Option<Compressor>,
+    #[cfg(feature = "speaker-protection")]
+    protection: SpeakerProtection,
+}
+
+impl DspChain {
+    pub fn new(preset: &Preset) -> Self {
+        let mut chain = Self {
+            subsonic: core::array::from_fn(|_| BiquadCascade::new()),
+            filters: core::array::from_fn(|_| BiquadCascade::new()),
+            delays: core::array::from_fn(|_| Delay::new()),
+            inverted: [false; INPUT_CHANNEL_COUNT],
+            gains: [DspSample::gain(1.0); INPUT_CHANNEL_COUNT],
+            preset_gains: [1.0; INPUT_CHANNEL_COUNT],
+            compressor: None,
+            #[cfg(feature = "speaker-protection")]
+            protection: SpeakerProtection::new(),
+        };
+        chain.configure(preset);
+
+        chain
+    }
+
+    /// Apply a preset, night mode, loudness compensation at the current master volume, de-emphasis, delays, and
+    /// polarities, keeping the filter state.
+    pub fn configure(&mut self, preset: &Preset) {
+        let mut stages = [Coefficients::IDENTITY; STAGE_COUNT];
+
+        let user_eq = USER_EQ.lock(Cell::get);
+        let eq = if user_eq.iter().any(Option::is_some) {
+            user_eq
+        } else if preset.measured {
+            settings::get()
+                .correction()
+                .map(|correction| correction.map(|correction| correction.filter()))
+        } else {
+            core::array::from_fn(|index| preset.eq.get(index).copied())
+        };
+
+        let adjustments = ADJUSTMENTS.lock(Cell::get);
+        for (stage, (filter, offset_db)) in stages.iter_mut().zip(eq.iter().zip(adjustments.eq_gain_db)) {
+            if let Some(filter) = filter {
+                *stage = offset_gain(*filter, offset_db).coefficients(SAMPLE_RATE_HZ);
+            }
+        }
+
+        if let Some(frequency_hz) = adjustments.crossover_hz.or(preset.crossover_hz) {
+            let high_pass = Filter::HighPass {
+                frequency_hz,
+                q: BUTTERWORTH_Q,
+            }
+            .coefficients(SAMPLE_RATE_HZ);
+
+            stages[EQ_BAND_COUNT] = high_pass;
+            stages[EQ_BAND_COUNT + 1] = high_pass;
+        }
+
+        if night_mode::is_enabled() {
+            stages[NIGHT_MODE_STAGE] = night_mode::BASS_SHELF.coefficients(SAMPLE_RATE_HZ);
+
+            // Keep the compressor's state, when only the preset changed.
+            if self.compressor.is_none() {
+                self.compressor = Some(Compressor::new(night_mode::COMPRESSOR, SAMPLE_RATE_HZ));
+            }
+        } else {
+            self.compressor = None;
+        }
+
+        if let Some(filters) = loudness::filters() {
+            for (stage, filter) in stages[LOUDNESS_STAGES..].iter_mut().zip(filters) {
+                *stage = filter.coefficients(SAMPLE_RATE_HZ);
+            }
+        }
+
+        if de_emphasis::is_enabled() {
+            stages[DE_EMPHASIS_STAGE] = emphasis::de_emphasis(SAMPLE_RATE_HZ);
+        }
+
+        #[cfg(feature = "speaker-protection")]
+        {
+            stages[PROTECTION_STAGE] = self.protection.shelf();
+        }
+
+        // The low-Q section comes first, so that the high-Q section's resonance acts on an already attenuated signal.
+        let subsonic = BUTTERWORTH_ORDER_4_Q.map(|q| match preset.subsonic_hz {
+            Some(frequency_hz) => Filter::HighPass { frequency_hz, q }.coefficients(SAMPLE_RATE_HZ),
+            None => Coefficients::IDENTITY,
+        });
+        for filter in self.subsonic.iter_mut() {
+            for (index, coefficients) in subsonic.iter().enumerate() {
+                filter.set_coefficients(index, *coefficients);
+            }
+        }
+
+        for filter in self.filters.iter_mut() {
+            for (index, coefficients) in stages.iter().enumerate() {
+                filter.set_coefficients(index, *coefficients);
+            }
+        }
+
+        for (delay, samples) in self.delays.iter_mut().zip(alignment::delays()) {
+            delay.set_delay(samples as usize);
+        }
+        self.inverted = core::array::from_fn(alignment::is_inverted);
+
+        // The loudness compensation's headroom is made up for by the amplifiers' volume.
+        let headroom_db = loudness::headroom_db();
+        self.preset_gains = preset.gain_db.map(|gain_db| db_to_linear(gain_db - headroom_db));
+        self.gains = self.preset_gains.map(DspSample::gain);
+    }
+
+    pub fn reset(&mut self) {
+        self.subsonic.iter_mut().for_each(|filter| filter.reset());
+        self.filters.iter_mut().for_each(|filter| filter.reset());
+        self.delays.iter_mut().for_each(|delay| delay.reset());
+
+        if let Some(compressor) = self.compressor.as_mut() {
+            compressor.reset();
+        }
+
+        #[cfg(feature = "speaker-protection")]
+        {
+            self.protection.reset();
+            let shelf = self.protection.shelf();
+            self.filters
+                .iter_mut()
+                .for_each(|filter| filter.set_coefficients(PROTECTION_STAGE, shelf));
+        }
+    }
+
+    /// Update the compressor from the peak magnitude of a block of `frame_count` frames, in night mode, and the speaker
+    /// protection from the block's output. The gains apply from the next block on, which lets the first block of a
+    /// transient pass uncompressed.
+    pub fn update_dynamics(&mut self, peak: u32, frame_count: usize) {
+        let gain = self
+            .compressor
+            .as_mut()
+            .map(|compressor| compressor.update(peak, frame_count));
+
+        #[cfg(feature = "speaker-protection")]
+        let gain = {
+            let (thermal_gain, shelf) = self.protection.update(frame_count);
+            if let Some(shelf) = shelf {
+                self.filters
+                    .iter_mut()
+                    .for_each(|filter| filter.set_coefficients(PROTECTION_STAGE, shelf));
+            }
+
+            Some(gain.unwrap_or(1.0) * thermal_gain)
+        };
+
+        if let Some(gain) = gain {
+            self.gains = self.preset_gains.map(|preset_gain| DspSample::gain(preset_gain * gain));
+        }
+    }
+
+    /// Process a 32 bit PCM sample of a channel.
+    #[inline]
+    pub fn process(&mut self, channel: usize, sample: i32) -> i32 {
+        let sample = self.subsonic[channel].process(DspSample::from_pcm(sample));
+        let sample = sample.apply_gain(self.gains[channel]);
+        let sample = self.filters[channel].process(sample);
+
+        #[cfg(feature = "speaker-protection")]
+        self.protection.measure(channel, sample);
+
+        let pcm = self.delays[channel].process(sample).to_pcm();
+
+        if self.inverted[channel] {
+            pcm.saturating_neg()
+        } else {
+            pcm
+        }
+    }
+}
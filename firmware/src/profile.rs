@@ -0,0 +1,161 @@
+// Cycle profiling of the streaming stages, to know how much headroom remains for DSP.
+//
+// Each stage's execution time is measured with the DWT cycle counter, and aggregated to minimum, average, and maximum
+// per report period of the statistics (see `stats::report_task`). Synchronous stages are measured with
+// `profile_scope!`, which records the cycles until the end of the enclosing scope. Asynchronous stages (packet
+// reception, DMA refill) are measured with `measure`, which only counts the cycles spent polling, not waiting.
+use core::cell::Cell;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use cortex_m::peripheral::DWT;
+use defmt::Format;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::chip::SYSCLK_HZ;
+
+/// Number of profiled stages.
+pub const STAGE_COUNT: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Stage {
+    /// Copying a received USB packet into a sample block.
+    Reception = 0,
+    /// Remixing, mixing, or storing samples into the block format.
+    Conversion = 1,
+    /// The DSP chain, with fades, metering, and clip detection.
+    Dsp = 2,
+    /// Copying a sample block into the I2S DMA buffer.
+    DmaRefill = 3,
+}
+
+pub const STAGES: [Stage; STAGE_COUNT] = [Stage::Reception, Stage::Conversion, Stage::Dsp, Stage::DmaRefill];
+
+/// Aggregated execution times of a stage, in cycles. All zero, if the stage did not run.
+#[derive(Clone, Copy, Format)]
+pub struct StageProfile {
+    pub min_cycles: u32,
+    pub avg_cycles: u32,
+    pub max_cycles: u32,
+}
+
+impl StageProfile {
+    pub const EMPTY: Self = Self {
+        min_cycles: 0,
+        avg_cycles: 0,
+        max_cycles: 0,
+    };
+}
+
+// Measurements of the running period, per stage.
+struct Aggregate {
+    min: AtomicU32,
+    max: AtomicU32,
+    total: AtomicU32,
+    count: AtomicU32,
+}
+
+impl Aggregate {
+    const fn new() -> Self {
+        Self {
+            min: AtomicU32::new(u32::MAX),
+            max: AtomicU32::new(0),
+            total: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+        }
+    }
+}
+
+static AGGREGATES: [Aggregate; STAGE_COUNT] = [const { Aggregate::new() }; STAGE_COUNT];
+
+// Profiles of the last completed period.
+static LAST_PROFILES: Mutex<CriticalSectionRawMutex, Cell<[StageProfile; STAGE_COUNT]>> =
+    Mutex::new(Cell::new([StageProfile::EMPTY; STAGE_COUNT]));
+
+/// Record an execution time of a stage.
+pub fn record(stage: Stage, cycles: u32) {
+    let aggregate = &AGGREGATES[stage as usize];
+    aggregate.min.fetch_min(cycles, Relaxed);
+    aggregate.max.fetch_max(cycles, Relaxed);
+    aggregate.total.fetch_add(cycles, Relaxed);
+    aggregate.count.fetch_add(1, Relaxed);
+}
+
+/// Records the cycles from its creation until it is dropped. Created by `profile_scope!`.
+pub struct Scope {
+    stage: Stage,
+    start: u32,
+}
+
+impl Scope {
+    pub fn new(stage: Stage) -> Self {
+        Self {
+            stage,
+            start: DWT::cycle_count(),
+        }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        record(self.stage, DWT::cycle_count().wrapping_sub(self.start));
+    }
+}
+
+/// Profile the rest of the enclosing scope as a stage.
+#[macro_export]
+macro_rules! profile_scope {
+    ($stage:expr) => {
+        let _profile_scope = $crate::profile::Scope::new($stage);
+    };
+}
+
+/// Await a future, and record the cycles spent polling it as a stage, if it completes.
+pub async fn measure<F: Future>(stage: Stage, future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cycles: u32 = 0;
+
+    let output = poll_fn(|cx| {
+        let start = DWT::cycle_count();
+        let poll = future.as_mut().poll(cx);
+        cycles = cycles.wrapping_add(DWT::cycle_count().wrapping_sub(start));
+        poll
+    })
+    .await;
+
+    record(stage, cycles);
+    output
+}
+
+/// Complete the running period, and return its profiles, which are also kept for `profiles`.
+pub fn complete_period() -> [StageProfile; STAGE_COUNT] {
+    let profiles = AGGREGATES.each_ref().map(|aggregate| {
+        let count = aggregate.count.swap(0, Relaxed);
+        let total = aggregate.total.swap(0, Relaxed);
+        let min = aggregate.min.swap(u32::MAX, Relaxed);
+        let max = aggregate.max.swap(0, Relaxed);
+
+        match count {
+            0 => StageProfile::EMPTY,
+            _ => StageProfile {
+                min_cycles: min,
+                avg_cycles: total / count,
+                max_cycles: max,
+            },
+        }
+    });
+
+    LAST_PROFILES.lock(|last| last.set(profiles));
+    profiles
+}
+
+/// The profiles of the last completed period.
+pub fn profiles() -> [StageProfile; STAGE_COUNT] {
+    LAST_PROFILES.lock(|last| last.get())
+}
+
+/// Convert cycles of the system clock to nanoseconds.
+pub fn cycles_to_ns(cycles: u32) -> u32 {
+    (cycles as u64 * 1_000_000_000 / SYSCLK_HZ as u64) as u32
+}
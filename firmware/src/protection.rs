@@ -0,0 +1,112 @@
+// Excursion and thermal protection of small drivers, which are easily overdriven by equalizer boosts (see
+// `blus_core::dsp::protection`).
+//
+// The DSP chain's output is measured per channel: the full band for the thermal model, and a low-passed copy as the
+// excursion proxy. The louder channel drives the model. Once per block, excursion reductions update a low shelf in the
+// DSP chain, and thermal reductions its gains. The limits describe the drivers at full amplifier volume, so that they
+// are conservative at lower volumes.
+use blus_core::gain::db_to_linear;
+use blus_core::meter::Accumulator;
+
+use crate::dsp::design::BUTTERWORTH_Q;
+use crate::dsp::{BiquadCascade, Coefficients, DspSample, Filter, Protection, ProtectionParams, Sample};
+use crate::*;
+
+// A small full-range driver in a closed box: excursion is limited below 150 Hz, and the voice coil sustains a quarter
+// of full-scale power, with a thermal time constant of 5 s.
+pub const PROTECTION: ProtectionParams = ProtectionParams {
+    excursion_hz: 150.0,
+    excursion_limit_db: -6.0,
+    thermal_limit_db: -6.0,
+    thermal_time_constant_s: 5.0,
+    release_ms: 200.0,
+};
+
+// Changes of the excursion reduction, below which the low shelf is not redesigned.
+const SHELF_STEP_DB: f32 = 0.25;
+
+pub struct SpeakerProtection {
+    model: Protection,
+    // Low-pass filters for the excursion proxy.
+    low_pass: [BiquadCascade<DspSample, 1>; INPUT_CHANNEL_COUNT],
+    // Low band and full band levels of the current block.
+    levels: [[Accumulator; 2]; INPUT_CHANNEL_COUNT],
+    shelf_db: f32,
+}
+
+impl SpeakerProtection {
+    pub fn new() -> Self {
+        let low_pass = Filter::LowPass {
+            frequency_hz: PROTECTION.excursion_hz,
+            q: BUTTERWORTH_Q,
+        }
+        .coefficients(SAMPLE_RATE_HZ);
+
+        Self {
+            model: Protection::new(PROTECTION, SAMPLE_RATE_HZ),
+            low_pass: core::array::from_fn(|_| {
+                let mut filter = BiquadCascade::new();
+                filter.set_coefficients(0, low_pass);
+                filter
+            }),
+            levels: [[Accumulator::EMPTY; 2]; INPUT_CHANNEL_COUNT],
+            shelf_db: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.model.reset();
+        self.low_pass.iter_mut().for_each(|filter| filter.reset());
+        self.levels = [[Accumulator::EMPTY; 2]; INPUT_CHANNEL_COUNT];
+        self.shelf_db = 0.0;
+    }
+
+    /// Measure an output sample of a channel.
+    #[inline]
+    pub fn measure(&mut self, channel: usize, sample: DspSample) {
+        let [low, full] = &mut self.levels[channel];
+
+        low.add(self.low_pass[channel].process(sample).to_pcm());
+        full.add(sample.to_pcm());
+    }
+
+    /// The low shelf, which applies the excursion reduction.
+    pub fn shelf(&self) -> Coefficients {
+        Filter::LowShelf {
+            frequency_hz: PROTECTION.excursion_hz,
+            q: BUTTERWORTH_Q,
+            gain_db: self.shelf_db,
+        }
+        .coefficients(SAMPLE_RATE_HZ)
+    }
+
+    /// Update the model from the measured block of `frame_count` frames. Returns the thermal gain reduction as a
+    /// linear factor, and a redesigned low shelf, if the excursion reduction changed.
+    pub fn update(&mut self, frame_count: usize) -> (f32, Option<Coefficients>) {
+        let levels = core::mem::replace(&mut self.levels, [[Accumulator::EMPTY; 2]; INPUT_CHANNEL_COUNT]);
+        let low_peak_db = levels.iter().map(|[low, _]| low.peak_db()).fold(f32::MIN, f32::max);
+        let full_rms_db = levels.iter().map(|[_, full]| full.rms_db()).fold(f32::MIN, f32::max);
+
+        self.model.update(low_peak_db, full_rms_db, frame_count);
+
+        // Small reductions are rounded to none, so that the shelf is removed after the release.
+        let reduction_db = match self.model.excursion_reduction_db() {
+            reduction_db if reduction_db > -SHELF_STEP_DB => 0.0,
+            reduction_db => reduction_db,
+        };
+        let shelf = if (reduction_db - self.shelf_db).abs() >= SHELF_STEP_DB {
+            self.shelf_db = reduction_db;
+            Some(self.shelf())
+        } else {
+            None
+        };
+
+        (db_to_linear(self.model.thermal_reduction_db()), shelf)
+    }
+}
+
+impl Default for SpeakerProtection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
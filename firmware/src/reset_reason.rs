@@ -0,0 +1,71 @@
+// Classification of the last reset, from the RCC reset flags and the persisted panic record.
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::{info, warn, Format};
+use embassy_stm32::pac;
+
+use crate::crash;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum ResetReason {
+    Unknown = 0,
+    PowerOn = 1,
+    Pin = 2,
+    BrownOut = 3,
+    Software = 4,
+    IndependentWatchdog = 5,
+    WindowWatchdog = 6,
+    LowPower = 7,
+    /// A software reset by the panic handler.
+    Panic = 8,
+}
+
+static RESET_REASON: AtomicU8 = AtomicU8::new(ResetReason::Unknown as u8);
+
+// Read and clear the reset flags. Several flags can be set at once, so that the most specific one is reported.
+fn read_flags() -> ResetReason {
+    let csr = pac::RCC.csr().read();
+    pac::RCC.csr().modify(|w| w.set_rmvf(true));
+
+    if csr.iwdgrstf() {
+        ResetReason::IndependentWatchdog
+    } else if csr.wwdgrstf() {
+        ResetReason::WindowWatchdog
+    } else if csr.lpwrrstf() {
+        ResetReason::LowPower
+    } else if csr.sftrstf() {
+        ResetReason::Software
+    } else if csr.porrstf() {
+        // A power-on reset also sets the brown-out flag.
+        ResetReason::PowerOn
+    } else if csr.borrstf() {
+        ResetReason::BrownOut
+    } else if csr.pinrstf() {
+        ResetReason::Pin
+    } else {
+        ResetReason::Unknown
+    }
+}
+
+/// Classify and log the last reset. Must be called once at boot.
+pub fn init() -> ResetReason {
+    let mut reason = read_flags();
+
+    if let Some(message) = crash::take_panic_message() {
+        warn!("Recovered from panic: {}", message.as_str());
+
+        if reason == ResetReason::Software {
+            reason = ResetReason::Panic;
+        }
+    }
+
+    info!("Reset reason: {}", reason);
+    RESET_REASON.store(reason as u8, Relaxed);
+
+    reason
+}
+
+/// The reason of the last reset, as classified at boot.
+pub fn get() -> u8 {
+    RESET_REASON.load(Relaxed)
+}
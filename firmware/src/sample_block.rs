@@ -0,0 +1,119 @@
+// Sample storage that is shared between USB reception and I2S output.
+//
+// The USB driver reads packets directly into the block's storage, and samples are processed in place. 32 bit
+// little-endian USB samples already have the word order of the output, so that the block's 16 bit words are written
+// to the I2S DMA's ring buffer without conversion. This write is the only copy on the way from USB to I2S, as the DMA
+// runs from its own ring buffer rather than from the blocks.
+use blus_core::format::{from_words, to_words, WordOrder};
+
+/// Order of a sample's words in a block, as received from USB, and as the I2S DMA takes them.
+pub const WORD_ORDER: WordOrder = WordOrder::LowFirst;
+
+// Largest frame that can be remixed.
+const MAX_FRAME_SIZE: usize = 8;
+
+/// A block of samples with a capacity of `N` 16 bit words.
+pub struct SampleBlock<const N: usize> {
+    words: [u16; N],
+    length: usize,
+}
+
+impl<const N: usize> SampleBlock<N> {
+    pub const fn new() -> Self {
+        Self {
+            words: [0; N],
+            length: 0,
+        }
+    }
+
+    /// The full storage as bytes, for receiving a USB packet.
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        // SAFETY: `u8` has no alignment requirement, and every bit pattern is valid for `u16`.
+        unsafe { core::slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, 2 * N) }
+    }
+
+    /// Set the number of valid bytes in the storage, after receiving a USB packet.
+    pub fn set_byte_length(&mut self, byte_length: usize) {
+        self.length = byte_length.min(2 * N) / 2;
+    }
+
+    /// Copy the valid samples of another block.
+    pub fn copy_from(&mut self, other: &Self) {
+        self.words[..other.length].copy_from_slice(&other.words[..other.length]);
+        self.length = other.length;
+    }
+
+    /// Store 32 bit samples from a local source, as many as fit.
+    pub fn set_samples(&mut self, samples: &[i32]) {
+        let samples = &samples[..samples.len().min(N / 2)];
+
+        for (word_pair, &sample) in self.words.chunks_exact_mut(2).zip(samples) {
+            word_pair.copy_from_slice(&to_words(sample, WORD_ORDER));
+        }
+
+        self.length = 2 * samples.len();
+    }
+
+    /// The valid samples as 16 bit words, for output.
+    pub fn words(&self) -> &[u16] {
+        &self.words[..self.length]
+    }
+
+    /// The valid samples as 32 bit samples.
+    pub fn samples(&self) -> impl Iterator<Item = i32> + '_ {
+        (0..self.sample_count()).map(|index| self.sample(index))
+    }
+
+    /// The number of 32 bit samples.
+    pub fn sample_count(&self) -> usize {
+        self.length / 2
+    }
+
+    fn sample(&self, index: usize) -> i32 {
+        from_words([self.words[2 * index], self.words[2 * index + 1]], WORD_ORDER)
+    }
+
+    fn set_sample(&mut self, index: usize, sample: i32) {
+        self.words[2 * index..2 * index + 2].copy_from_slice(&to_words(sample, WORD_ORDER));
+    }
+
+    /// Remix frames of `channel_count` samples into frames of `M` samples in place, as many as fit.
+    pub fn remix<const M: usize>(&mut self, channel_count: usize, mut f: impl FnMut(&[i32]) -> [i32; M]) {
+        assert!(channel_count > 0 && channel_count <= MAX_FRAME_SIZE);
+        let frame_count = (self.sample_count() / channel_count).min(N / 2 / M);
+
+        let mut remix_frame = |frame: usize| {
+            let mut input = [0; MAX_FRAME_SIZE];
+            for (channel, sample) in input[..channel_count].iter_mut().enumerate() {
+                *sample = self.sample(frame * channel_count + channel);
+            }
+
+            for (channel, sample) in f(&input[..channel_count]).into_iter().enumerate() {
+                self.set_sample(frame * M + channel, sample);
+            }
+        };
+
+        // Growing frames move towards the end of the storage, so that they are remixed from last to first.
+        if M > channel_count {
+            (0..frame_count).rev().for_each(&mut remix_frame);
+        } else {
+            (0..frame_count).for_each(&mut remix_frame);
+        }
+
+        self.length = 2 * M * frame_count;
+    }
+
+    /// Process all 32 bit samples in place.
+    pub fn process(&mut self, mut f: impl FnMut(i32) -> i32) {
+        for word_pair in self.words[..self.length].chunks_exact_mut(2) {
+            let sample = from_words([word_pair[0], word_pair[1]], WORD_ORDER);
+            word_pair.copy_from_slice(&to_words(f(sample), WORD_ORDER));
+        }
+    }
+}
+
+impl<const N: usize> Default for SampleBlock<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
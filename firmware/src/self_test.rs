@@ -0,0 +1,127 @@
+// Self-test: checks at startup (and of the USB enumeration, while a host powers the bus), whose failures are shown as
+// blink codes on the status LED, so that bare units are diagnosed without a debugger.
+//
+// Each check has a number, which is its blink code: the LED pulses that many times, then pauses. Failed checks are
+// shown in turn, and logged. Only the USB enumeration recovers, when the host configures the device later, or the bus
+// loses power (e.g. a charge-only cable). All other failures stay until reset.
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering::Relaxed};
+use defmt::{error, info, Format};
+use embassy_stm32::pac;
+use embassy_time::{Duration, Ticker};
+
+use crate::status_led::LedStatus;
+use crate::*;
+
+const POLL_PERIOD: Duration = Duration::from_secs(1);
+
+// Time for a host to configure the device, after it powered the bus, in polls.
+const ENUMERATION_TIMEOUT_POLLS: u32 = 5;
+
+/// Self-test checks, by their blink code, in the order of the startup.
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum Check {
+    /// The enabled oscillators and PLLs are locked.
+    ClockLock = 1,
+    /// The firmware image's CRC matches (see `image_crc`).
+    ImageCrc = 2,
+    /// The settings' flash pages read and erase without errors, and hold no corrupt records (see `kv_store`).
+    Settings = 3,
+    /// All critical I2C devices respond (see `i2c_scan`).
+    I2cDevices = 4,
+    /// The amplifiers' latched faults clear, and do not recur.
+    AmpFaults = 5,
+    /// The host configures the device within five seconds after powering the bus.
+    UsbEnumeration = 6,
+}
+
+impl Check {
+    const ALL: [Self; 6] = [
+        Self::ClockLock,
+        Self::ImageCrc,
+        Self::Settings,
+        Self::I2cDevices,
+        Self::AmpFaults,
+        Self::UsbEnumeration,
+    ];
+
+    /// The number of LED pulses of the check's blink code.
+    pub fn blink_count(self) -> u8 {
+        self as u8
+    }
+
+    fn mask(self) -> u8 {
+        1 << (self as u8 - 1)
+    }
+}
+
+// Failed checks, by their mask.
+static FAILED: AtomicU8 = AtomicU8::new(0);
+
+// Whether a host powers the bus, from the USB device handler.
+static USB_IS_POWERED: AtomicBool = AtomicBool::new(false);
+
+/// Record a check's result, and show a failure on the status LED. Returns whether the check passed.
+pub fn check(check: Check, passed: bool) -> bool {
+    if passed {
+        info!("Self-test passed: {}", check);
+    } else {
+        fail(check);
+    }
+
+    passed
+}
+
+/// Record a failed check.
+pub fn fail(check: Check) {
+    error!("Self-test failed: {} (blink code {})", check, check.blink_count());
+    FAILED.fetch_or(check.mask(), Relaxed);
+    STATUS_LED_SIGNAL.signal(LedStatus::SelfTestFailed);
+}
+
+// Clear a failed check, which recovered.
+fn recover(check: Check) {
+    if FAILED.fetch_and(!check.mask(), Relaxed) & check.mask() != 0 {
+        info!("Self-test recovered: {}", check);
+    }
+}
+
+/// The failed checks.
+pub fn failures() -> impl Iterator<Item = Check> {
+    let failed = FAILED.load(Relaxed);
+    Check::ALL.into_iter().filter(move |check| failed & check.mask() != 0)
+}
+
+/// Check that the enabled oscillators and PLLs are locked.
+pub fn check_clocks() -> bool {
+    let cr = pac::RCC.cr().read();
+    let locked = (!cr.hseon() || cr.hserdy()) && (!cr.pllon() || cr.pllrdy()) && (!cr.plli2son() || cr.plli2srdy());
+
+    check(Check::ClockLock, locked)
+}
+
+/// Follow the USB power, as detected by the USB device handler.
+pub fn usb_power_changed(powered: bool) {
+    USB_IS_POWERED.store(powered, Relaxed);
+}
+
+/// Checks that the host configures the device, while it powers the bus.
+#[embassy_executor::task]
+pub async fn enumeration_task() {
+    let mut ticker = Ticker::every(POLL_PERIOD);
+    let mut unconfigured_polls = 0;
+
+    loop {
+        ticker.next().await;
+
+        if USB_IS_POWERED.load(Relaxed) && !USB_IS_CONFIGURED.load(Relaxed) {
+            unconfigured_polls += 1;
+            if unconfigured_polls == ENUMERATION_TIMEOUT_POLLS {
+                fail(Check::UsbEnumeration);
+            }
+        } else {
+            unconfigured_polls = 0;
+            recover(Check::UsbEnumeration);
+        }
+    }
+}
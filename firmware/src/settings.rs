@@ -0,0 +1,454 @@
+// Persistent device settings, stored in the key-value store of the internal flash.
+//
+// Every setting is stored under its own key, so a change only writes the settings that changed.
+use blus_core::dsp::auto_eq::Correction;
+use blus_core::volume::VolumeCurve;
+use blus_core::zone::{Route, ZoneConfig, ZONE_COUNT};
+use core::cell::Cell;
+use defmt::{info, warn, Format};
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration};
+
+use crate::channel_layout::ChannelLayout;
+use crate::kv_store::{KvError, KvStore, VALUE_SIZE};
+use crate::nec::Code;
+use crate::preset::EQ_BAND_COUNT;
+use crate::self_test::{self, Check};
+use crate::source::{Selection, Source};
+use crate::trim::{BALANCE_MAX, TRIM_MAX};
+use crate::*;
+
+// Keys in the key-value store.
+mod key {
+    pub const TRIM: u8 = 0;
+    pub const BALANCE: u8 = 1;
+    pub const PRESET: u8 = 2;
+    pub const IR_CODES: [u8; 2] = [3, 4];
+    pub const SOURCE: u8 = 5;
+    pub const CHANNEL_LAYOUT: u8 = 6;
+    pub const LOUDNESS: u8 = 7;
+    pub const DELAY: u8 = 8;
+    pub const POLARITY: u8 = 9;
+    pub const DE_EMPHASIS: u8 = 10;
+    pub const SLEEP_TIMEOUT: u8 = 11;
+    pub const VOLUME_CURVE: u8 = 12;
+    pub const ZONES: u8 = 13;
+    pub const CORRECTION: [u8; 2] = [14, 15];
+    pub const PREFILL: u8 = 16;
+}
+
+static_assertions::const_assert!(INPUT_CHANNEL_COUNT <= VALUE_SIZE);
+
+// Delays are stored as `u16` per channel, and inverted polarities as a mask of one bit per channel.
+static_assertions::const_assert!(2 * INPUT_CHANNEL_COUNT <= VALUE_SIZE);
+static_assertions::const_assert!(INPUT_CHANNEL_COUNT <= 8);
+
+// The source selection is stored along with the priority order.
+static_assertions::const_assert!(1 + source::INPUT_COUNT <= VALUE_SIZE);
+
+// Zones are stored as their two routes and gain, one after another.
+const ZONE_SIZE: usize = 3;
+static_assertions::const_assert!(ZONE_COUNT * ZONE_SIZE <= VALUE_SIZE);
+
+// The auto-EQ's correction filters are stored in their encoding, several per key.
+const CORRECTIONS_PER_KEY: usize = VALUE_SIZE / Correction::SIZE;
+static_assertions::const_assert!(EQ_BAND_COUNT <= key::CORRECTION.len() * CORRECTIONS_PER_KEY);
+
+// Learned IR codes are stored as address (`u16`) and command, several per key. Erased values mark unlearned codes.
+const IR_CODE_SIZE: usize = 3;
+const IR_CODES_PER_KEY: usize = VALUE_SIZE / IR_CODE_SIZE;
+static_assertions::const_assert!(ir_remote::ACTION_COUNT <= key::IR_CODES.len() * IR_CODES_PER_KEY);
+
+fn encode_ir_code(code: Option<Code>) -> [u8; IR_CODE_SIZE] {
+    match code {
+        Some(code) => {
+            let [low, high] = code.address.to_le_bytes();
+            [low, high, code.command]
+        }
+        None => [0xff; IR_CODE_SIZE],
+    }
+}
+
+fn decode_ir_code(bytes: &[u8]) -> Option<Code> {
+    match *bytes {
+        [0xff, 0xff, 0xff] => None,
+        [low, high, command] => Some(Code {
+            address: u16::from_le_bytes([low, high]),
+            command,
+        }),
+        _ => None,
+    }
+}
+
+// Settings are stored after they did not change for this long, which avoids flash wear during quick adjustments.
+const STORE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct Settings {
+    /// Per-channel trim in 0.5 dB steps.
+    pub trim: [i8; INPUT_CHANNEL_COUNT],
+    /// Balance in 0.5 dB steps. Positive values attenuate the left channel, negative values the right channel.
+    pub balance: i8,
+    /// Index of the active DSP preset.
+    pub preset: u8,
+    /// Learned IR remote codes, by action index.
+    pub ir_codes: [Option<Code>; ir_remote::ACTION_COUNT],
+    /// Manual or automatic source selection.
+    pub source: Selection,
+    /// Priority order of automatic source selection.
+    pub source_priority: [Source; source::INPUT_COUNT],
+    /// Channel layout of the USB stream, which takes effect at the next boot.
+    pub channel_layout: ChannelLayout,
+    /// Whether loudness compensation is enabled.
+    pub loudness: bool,
+    /// Per-channel delay in samples, for time alignment.
+    pub delay: [u16; INPUT_CHANNEL_COUNT],
+    /// Channels with inverted polarity, one bit per channel.
+    pub inverted: u8,
+    /// Whether de-emphasis is enabled.
+    pub de_emphasis: bool,
+    /// Minutes without audio or control activity, after which the device sleeps. Zero disables the sleep timer.
+    pub sleep_timeout: u8,
+    /// Gain range of the volume curve in dB, and its resolution in 0.5 dB steps (see `VolumeCurve`).
+    pub volume_min_db: i8,
+    pub volume_max_db: i8,
+    pub volume_step: u8,
+    /// Routes of each output zone's channels (see `Route`), and its gain in 0.5 dB steps (see `ZoneConfig`).
+    pub zone_routes: [[u8; 2]; ZONE_COUNT],
+    pub zone_gain: [i8; ZONE_COUNT],
+    /// The auto-EQ's correction filters, encoded (see `Correction::to_bytes`).
+    pub correction: [[u8; Correction::SIZE]; EQ_BAND_COUNT],
+    /// Whether the output's pre-fill grows after repeated underruns, and its learned depth in ms (see `prefill`).
+    pub adaptive_prefill: bool,
+    pub prefill_ms: u8,
+}
+
+impl Settings {
+    pub const DEFAULT: Self = Self {
+        trim: [0; INPUT_CHANNEL_COUNT],
+        balance: 0,
+        preset: 0,
+        ir_codes: [None; ir_remote::ACTION_COUNT],
+        source: Selection::Automatic,
+        source_priority: source::DEFAULT_PRIORITY,
+        channel_layout: DEFAULT_CHANNEL_LAYOUT,
+        loudness: false,
+        delay: [0; INPUT_CHANNEL_COUNT],
+        inverted: 0,
+        de_emphasis: false,
+        sleep_timeout: 0,
+        volume_min_db: VolumeCurve::DEFAULT.min_db,
+        volume_max_db: VolumeCurve::DEFAULT.max_db,
+        volume_step: VolumeCurve::DEFAULT.step,
+        zone_routes: [[Route::Left as u8, Route::Right as u8]; ZONE_COUNT],
+        zone_gain: [ZoneConfig::DEFAULT.gain; ZONE_COUNT],
+        correction: [[0xff; Correction::SIZE]; EQ_BAND_COUNT],
+        adaptive_prefill: false,
+        prefill_ms: OUTPUT_PREFILL_MS as u8,
+    };
+
+    pub fn volume_curve(&self) -> VolumeCurve {
+        VolumeCurve {
+            min_db: self.volume_min_db,
+            max_db: self.volume_max_db,
+            step: self.volume_step,
+        }
+    }
+
+    /// Set the volume curve, if it is valid. Returns whether it was.
+    pub fn set_volume_curve(&mut self, curve: VolumeCurve) -> bool {
+        if !curve.is_valid() {
+            return false;
+        }
+
+        self.volume_min_db = curve.min_db;
+        self.volume_max_db = curve.max_db;
+        self.volume_step = curve.step;
+        true
+    }
+
+    /// The configuration of an output zone, which must exist. Invalid routes are silent.
+    pub fn zone(&self, zone: usize) -> ZoneConfig {
+        ZoneConfig {
+            routes: self.zone_routes[zone].map(|route| Route::from_u8(route).unwrap_or(Route::Silent)),
+            gain: self.zone_gain[zone],
+        }
+    }
+
+    /// Set the configuration of an output zone, if the zone exists, and the configuration is valid. Returns whether
+    /// it was set.
+    pub fn set_zone(&mut self, zone: usize, config: ZoneConfig) -> bool {
+        if zone >= ZONE_COUNT || !config.is_valid() {
+            return false;
+        }
+
+        self.zone_routes[zone] = config.routes.map(|route| route as u8);
+        self.zone_gain[zone] = config.gain;
+        true
+    }
+
+    /// The auto-EQ's correction filters. Invalid filters are left out.
+    pub fn correction(&self) -> [Option<Correction>; EQ_BAND_COUNT] {
+        self.correction.map(Correction::from_bytes)
+    }
+
+    pub fn set_correction(&mut self, correction: [Option<Correction>; EQ_BAND_COUNT]) {
+        self.correction = correction.map(|correction| match correction {
+            Some(correction) => correction.to_bytes(),
+            None => [0xff; Correction::SIZE],
+        });
+    }
+
+    /// Read the settings from a store, keeping defaults for missing or invalid values.
+    pub fn load(store: &KvStore) -> Self {
+        let mut settings = Self::DEFAULT;
+
+        // Missing values or values of the wrong size (e.g. from a different channel count) keep their defaults, as do
+        // values beyond the trim's and balance's range.
+        if let Some(trim) = store
+            .read(key::TRIM)
+            .filter(|trim| trim.len() == INPUT_CHANNEL_COUNT)
+            .filter(|trim| trim.iter().all(|&trim| (-TRIM_MAX..=TRIM_MAX).contains(&(trim as i8))))
+        {
+            settings.trim = core::array::from_fn(|channel| trim[channel] as i8);
+        }
+        if let Some(&[balance]) = store.read(key::BALANCE) {
+            if (-BALANCE_MAX..=BALANCE_MAX).contains(&(balance as i8)) {
+                settings.balance = balance as i8;
+            }
+        }
+        if let Some(&[preset]) = store.read(key::PRESET) {
+            settings.preset = preset;
+        }
+
+        for (&key, codes) in key::IR_CODES.iter().zip(settings.ir_codes.chunks_mut(IR_CODES_PER_KEY)) {
+            if let Some(value) = store.read(key) {
+                for (code, bytes) in codes.iter_mut().zip(value.chunks_exact(IR_CODE_SIZE)) {
+                    *code = decode_ir_code(bytes);
+                }
+            }
+        }
+
+        // Invalid priority orders keep the default.
+        if let Some(value) = store
+            .read(key::SOURCE)
+            .filter(|value| value.len() == 1 + source::INPUT_COUNT)
+        {
+            settings.source = Selection::from_u8(value[0]).unwrap_or(Selection::Automatic);
+            if let Ok(priority) = source::validate_priority(&value[1..]) {
+                settings.source_priority = priority;
+            }
+        }
+
+        if let Some(layout) = store
+            .read(key::CHANNEL_LAYOUT)
+            .and_then(|value| ChannelLayout::from_u8(*value.first()?))
+        {
+            settings.channel_layout = layout;
+        }
+        if let Some(&[loudness]) = store.read(key::LOUDNESS) {
+            settings.loudness = loudness != 0;
+        }
+
+        // Delays beyond the delay lines' length keep the default.
+        if let Some(delay) = store
+            .read(key::DELAY)
+            .filter(|delay| delay.len() == 2 * INPUT_CHANNEL_COUNT)
+        {
+            let delay: [u16; INPUT_CHANNEL_COUNT] =
+                core::array::from_fn(|channel| u16::from_le_bytes([delay[2 * channel], delay[2 * channel + 1]]));
+
+            if delay
+                .iter()
+                .all(|&samples| samples as usize <= alignment::MAX_DELAY_SAMPLES)
+            {
+                settings.delay = delay;
+            }
+        }
+        if let Some(&[inverted]) = store.read(key::POLARITY) {
+            settings.inverted = inverted;
+        }
+        if let Some(&[de_emphasis]) = store.read(key::DE_EMPHASIS) {
+            settings.de_emphasis = de_emphasis != 0;
+        }
+        if let Some(&[sleep_timeout]) = store.read(key::SLEEP_TIMEOUT) {
+            settings.sleep_timeout = sleep_timeout;
+        }
+        if let Some(&[min_db, max_db, step]) = store.read(key::VOLUME_CURVE) {
+            settings.set_volume_curve(VolumeCurve {
+                min_db: min_db as i8,
+                max_db: max_db as i8,
+                step,
+            });
+        }
+        if let Some(value) = store.read(key::ZONES) {
+            for (zone, bytes) in value.chunks_exact(ZONE_SIZE).take(ZONE_COUNT).enumerate() {
+                if let (Some(left), Some(right)) = (Route::from_u8(bytes[0]), Route::from_u8(bytes[1])) {
+                    settings.set_zone(
+                        zone,
+                        ZoneConfig {
+                            routes: [left, right],
+                            gain: bytes[2] as i8,
+                        },
+                    );
+                }
+            }
+        }
+
+        for (&key, corrections) in key::CORRECTION
+            .iter()
+            .zip(settings.correction.chunks_mut(CORRECTIONS_PER_KEY))
+        {
+            if let Some(value) = store.read(key) {
+                for (correction, bytes) in corrections.iter_mut().zip(value.chunks_exact(Correction::SIZE)) {
+                    correction.copy_from_slice(bytes);
+                }
+            }
+        }
+
+        // Depths beyond the buffers keep the default.
+        if let Some(&[adaptive, prefill_ms]) = store.read(key::PREFILL) {
+            settings.adaptive_prefill = adaptive != 0;
+            if (OUTPUT_PREFILL_MS..=MAX_OUTPUT_PREFILL_MS).contains(&(prefill_ms as usize)) {
+                settings.prefill_ms = prefill_ms;
+            }
+        }
+
+        settings
+    }
+
+    /// Write the settings to a store. Unchanged values are not written again.
+    pub fn store(&self, store: &mut KvStore) -> Result<(), KvError> {
+        store.write(key::TRIM, &self.trim.map(|trim| trim as u8))?;
+        store.write(key::BALANCE, &[self.balance as u8])?;
+        store.write(key::PRESET, &[self.preset])?;
+
+        for (&key, codes) in key::IR_CODES.iter().zip(self.ir_codes.chunks(IR_CODES_PER_KEY)) {
+            let mut value = [0u8; VALUE_SIZE];
+            for (bytes, &code) in value.chunks_exact_mut(IR_CODE_SIZE).zip(codes) {
+                bytes.copy_from_slice(&encode_ir_code(code));
+            }
+
+            store.write(key, &value[..codes.len() * IR_CODE_SIZE])?;
+        }
+
+        let mut value = [self.source.to_u8(); 1 + source::INPUT_COUNT];
+        for (byte, &source) in value[1..].iter_mut().zip(&self.source_priority) {
+            *byte = source as u8;
+        }
+        store.write(key::SOURCE, &value)?;
+
+        store.write(key::CHANNEL_LAYOUT, &[self.channel_layout as u8])?;
+        store.write(key::LOUDNESS, &[self.loudness as u8])?;
+
+        let mut value = [0u8; 2 * INPUT_CHANNEL_COUNT];
+        for (bytes, samples) in value.chunks_exact_mut(2).zip(self.delay) {
+            bytes.copy_from_slice(&samples.to_le_bytes());
+        }
+        store.write(key::DELAY, &value)?;
+        store.write(key::POLARITY, &[self.inverted])?;
+        store.write(key::DE_EMPHASIS, &[self.de_emphasis as u8])?;
+        store.write(key::SLEEP_TIMEOUT, &[self.sleep_timeout])?;
+        store.write(
+            key::VOLUME_CURVE,
+            &[self.volume_min_db as u8, self.volume_max_db as u8, self.volume_step],
+        )?;
+
+        let mut value = [0u8; ZONE_COUNT * ZONE_SIZE];
+        for ((bytes, [left, right]), gain) in value
+            .chunks_exact_mut(ZONE_SIZE)
+            .zip(self.zone_routes)
+            .zip(self.zone_gain)
+        {
+            bytes.copy_from_slice(&[left, right, gain as u8]);
+        }
+        store.write(key::ZONES, &value)?;
+
+        for (&key, corrections) in key::CORRECTION.iter().zip(self.correction.chunks(CORRECTIONS_PER_KEY)) {
+            let mut value = [0u8; VALUE_SIZE];
+            for (bytes, correction) in value.chunks_exact_mut(Correction::SIZE).zip(corrections) {
+                bytes.copy_from_slice(correction);
+            }
+
+            store.write(key, &value[..corrections.len() * Correction::SIZE])?;
+        }
+
+        store.write(key::PREFILL, &[self.adaptive_prefill as u8, self.prefill_ms])?;
+
+        Ok(())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static SETTINGS: Mutex<CriticalSectionRawMutex, Cell<Settings>> = Mutex::new(Cell::new(Settings::DEFAULT));
+
+/// The current settings.
+pub fn get() -> Settings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Change the current settings. They are stored to flash after a delay.
+pub fn modify(f: impl FnOnce(&mut Settings)) {
+    SETTINGS.lock(|settings| {
+        let mut value = settings.get();
+        f(&mut value);
+        settings.set(value);
+    });
+
+    SETTINGS_CHANGED_SIGNAL.signal(());
+}
+
+/// Settings storage in the internal flash.
+pub struct SettingsStore {
+    store: KvStore,
+    stored: Settings,
+}
+
+/// Load the settings from flash. Must be called before the watchdog is started, since it may erase flash.
+pub fn load(flash: Flash<'static, Blocking>) -> SettingsStore {
+    let store = KvStore::open(flash);
+    self_test::check(Check::Settings, store.is_healthy());
+    let stored = Settings::load(&store);
+
+    info!("Loaded settings: {}", stored);
+    SETTINGS.lock(|settings| settings.set(stored));
+
+    SettingsStore { store, stored }
+}
+
+impl SettingsStore {
+    /// Store the current settings, if they changed.
+    pub fn store(&mut self) -> Result<(), KvError> {
+        let settings = get();
+        if settings == self.stored {
+            return Ok(());
+        }
+
+        settings.store(&mut self.store)?;
+        self.stored = settings;
+        info!("Stored settings: {}", settings);
+        Ok(())
+    }
+}
+
+/// Stores changed settings to flash.
+#[embassy_executor::task]
+pub async fn store_task(mut store: SettingsStore) {
+    loop {
+        SETTINGS_CHANGED_SIGNAL.wait().await;
+
+        // Wait for the settings to settle.
+        while with_timeout(STORE_DELAY, SETTINGS_CHANGED_SIGNAL.wait()).await.is_ok() {}
+
+        if let Err(e) = store.store() {
+            warn!("Failed to store settings: {}", e);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// Silence detection on the incoming sample stream, a soft-start ramp for leaving amplifier standby, and a ramp down for
+// switching sources.
+
+use crate::dsp::Gain;
+use crate::*;
+
+// Samples with a magnitude below this threshold are considered silent (about -96 dBFS for 32 bit samples).
+pub const SILENCE_THRESHOLD: u32 = 1 << 15;
+
+// Duration of continuous silence, after which the amplifiers enter standby.
+pub const SILENCE_TIMEOUT_MS: usize = 10_000;
+
+// Duration of the soft-start ramp, after leaving standby.
+pub const FADE_IN_MS: usize = 50;
+
+// Duration of the ramp down, before switching sources.
+pub const FADE_OUT_MS: usize = 10;
+const FADE_OUT_SAMPLE_COUNT: u32 = (FADE_OUT_MS as u32 * SAMPLE_RATE_HZ / 1000) * INPUT_CHANNEL_COUNT as u32;
+
+pub struct SilenceDetector {
+    silent_frames: usize,
+    standby: bool,
+}
+
+impl SilenceDetector {
+    pub const fn new() -> Self {
+        Self {
+            silent_frames: 0,
+            standby: true,
+        }
+    }
+
+    /// Force the detector into standby state, e.g. when the host closes the stream (alt setting 0).
+    pub fn reset(&mut self) {
+        self.silent_frames = 0;
+        self.standby = true;
+    }
+
+    /// Update the detector with the peak magnitude of one USB (micro)frame.
+    ///
+    /// Returns the new standby state, if it changed.
+    pub fn update(&mut self, peak: u32) -> Option<bool> {
+        if peak < SILENCE_THRESHOLD {
+            self.silent_frames = self.silent_frames.saturating_add(1);
+        } else {
+            self.silent_frames = 0;
+        }
+
+        let standby = self.silent_frames >= SILENCE_TIMEOUT_MS * USB_FRAMES_PER_MS;
+
+        if standby != self.standby {
+            self.standby = standby;
+            Some(standby)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SilenceDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A linear gain ramp from silence to unity gain.
+pub struct FadeIn {
+    sample_index: u32,
+    sample_count: u32,
+}
+
+impl FadeIn {
+    pub const fn new() -> Self {
+        Self {
+            sample_index: 0,
+            sample_count: 0,
+        }
+    }
+
+    /// Restart the ramp with a duration of `duration_ms`.
+    pub fn restart(&mut self, duration_ms: usize) {
+        self.sample_index = 0;
+        self.sample_count = (duration_ms as u32 * SAMPLE_RATE_HZ / 1000) * INPUT_CHANNEL_COUNT as u32;
+    }
+
+    pub fn apply(&mut self, sample: i32) -> i32 {
+        if self.sample_index >= self.sample_count {
+            return sample;
+        }
+
+        let gain = Gain::from_q31((self.sample_index as u64 * i32::MAX as u64 / self.sample_count as u64) as i32);
+        self.sample_index += 1;
+
+        gain.apply(sample)
+    }
+}
+
+impl Default for FadeIn {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A linear gain ramp from unity gain to silence, which holds silence when done.
+pub struct FadeOut {
+    // Remaining samples of the ramp, if started.
+    remaining: Option<u32>,
+}
+
+impl FadeOut {
+    pub const fn new() -> Self {
+        Self { remaining: None }
+    }
+
+    /// Start the ramp, unless it is running already.
+    pub fn start(&mut self) {
+        self.remaining.get_or_insert(FADE_OUT_SAMPLE_COUNT);
+    }
+
+    pub fn reset(&mut self) {
+        self.remaining = None;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    pub fn apply(&mut self, sample: i32) -> i32 {
+        let Some(remaining) = self.remaining.as_mut() else {
+            return sample;
+        };
+
+        let gain = Gain::from_q31((*remaining as u64 * i32::MAX as u64 / FADE_OUT_SAMPLE_COUNT as u64) as i32);
+        *remaining = remaining.saturating_sub(1);
+
+        gain.apply(sample)
+    }
+}
+
+impl Default for FadeOut {
+    fn default() -> Self {
+        Self::new()
+    }
+}
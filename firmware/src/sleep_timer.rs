@@ -0,0 +1,108 @@
+// Sleep timer, which powers the device down after a configurable time without audio or control activity.
+//
+// The timer runs while the amplifiers are in standby after silence (or without a stream), and restarts with every
+// control activity: vendor requests, host volume changes, and buttons. On expiry, the amplifiers are powered down along
+// the board's power sequence, and clocks are reduced as during USB suspend. The I2S clock only stops, when no output is
+// running, since a host may keep a stream of silence open. The device stays enumerated, and wakes on USB activity (a
+// stream, audio, or a request) or a button press. The timeout is part of the persistent settings.
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::*;
+
+/// Longest timeout, in minutes.
+pub const MAX_TIMEOUT_MIN: u8 = 240;
+
+static ACTIVITY_SIGNAL: Signal<ThreadModeRawMutex, ()> = Signal::new();
+static PLAYING: AtomicBool = AtomicBool::new(false);
+static ASLEEP: AtomicBool = AtomicBool::new(false);
+static I2S_CLOCK_STOPPED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct InvalidTimeout;
+
+pub fn is_asleep() -> bool {
+    ASLEEP.load(Relaxed)
+}
+
+/// Set the timeout in minutes, where zero disables the sleep timer. It is stored in the settings.
+pub fn set_timeout(minutes: u8) -> Result<(), InvalidTimeout> {
+    if minutes > MAX_TIMEOUT_MIN {
+        return Err(InvalidTimeout);
+    }
+
+    info!("Sleep timeout: {} min", minutes);
+    settings::modify(|settings| settings.sleep_timeout = minutes);
+    activity();
+    Ok(())
+}
+
+/// Restart the timer on activity, and wake up, if asleep. Returns whether the device was asleep.
+///
+/// Waking is immediate, so that clocks run again, before a caller powers anything up.
+pub fn activity() -> bool {
+    ACTIVITY_SIGNAL.signal(());
+
+    if !ASLEEP.swap(false, Relaxed) {
+        return false;
+    }
+
+    // During USB suspend, clocks are restored on resume.
+    if !USB_IS_SUSPENDED.load(Relaxed) {
+        power::restore_clocks();
+        if I2S_CLOCK_STOPPED.swap(false, Relaxed) {
+            power::start_i2s_clock();
+        }
+    }
+
+    info!("Woke up");
+    true
+}
+
+/// Track whether audio is playing (the amplifiers left standby), which holds the timer.
+pub fn set_playing(playing: bool) {
+    PLAYING.store(playing, Relaxed);
+    activity();
+}
+
+// Power the amplifiers down, and reduce clocks.
+async fn sleep() {
+    AMP_STANDBY_SIGNAL.signal(true);
+    Timer::after_millis(board::POWER_SEQUENCE.shutdown_ms()).await;
+
+    // Activity during the shutdown cancels sleeping.
+    if ACTIVITY_SIGNAL.signaled() {
+        return;
+    }
+
+    info!("Sleeping");
+    if !I2S_IS_ACTIVE.load(Relaxed) {
+        power::stop_i2s_clock();
+        I2S_CLOCK_STOPPED.store(true, Relaxed);
+    }
+    power::reduce_clocks();
+    ASLEEP.store(true, Relaxed);
+}
+
+/// Puts the device to sleep after the configured time without activity.
+#[embassy_executor::task]
+pub async fn sleep_task() {
+    loop {
+        let timeout = settings::get().sleep_timeout;
+
+        // Activity (or a new timeout) restarts the timer.
+        if timeout == 0 || PLAYING.load(Relaxed) || USB_IS_SUSPENDED.load(Relaxed) || is_asleep() {
+            ACTIVITY_SIGNAL.wait().await;
+            continue;
+        }
+
+        let timeout = Duration::from_secs(60 * timeout as u64);
+        if with_timeout(timeout, ACTIVITY_SIGNAL.wait()).await.is_err() {
+            info!("No activity for {} s", timeout.as_secs());
+            sleep().await;
+        }
+    }
+}
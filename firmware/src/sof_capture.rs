@@ -0,0 +1,168 @@
+// Captures the feedback timer's counter at every USB start-of-frame (SOF), for feedback calculation.
+//
+// TIM2 can be triggered by the USB peripherals' SOF internally, by remapping its internal trigger 1 (ITR1). Other
+// timers capture the SOF pulse on an input channel instead, which must be wired to the SOF output pin (OTG_FS_SOF on
+// PA8). These may be 16 bit timers (e.g. TIM9 or TIM11 on pin-constrained boards), whose counters are extended to 32
+// bit by counting update (overflow) interrupts, which share the capture's interrupt handler.
+use blus_core::feedback::CounterExtension;
+use core::cell::RefCell;
+
+use embassy_stm32::interrupt::typelevel::Interrupt;
+use embassy_stm32::pac;
+use embassy_stm32::pac::timer::TimGp16;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::{FilterValue, InputTISelection, Timer, TriggerSource};
+use embassy_stm32::timer::{Channel, CoreInstance, GeneralInstance1Channel, TimerBits};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::board::SofTimer;
+use crate::FEEDBACK_COUNTER_TICK_RATE;
+
+/// The USB peripheral, whose SOF triggers a capture.
+#[derive(Clone, Copy)]
+pub enum UsbSof {
+    OtgFs,
+    OtgHs,
+}
+
+impl UsbSof {
+    // Value of the ITR1_RMP field in TIM2_OR.
+    fn itr1_remap(self) -> u32 {
+        match self {
+            UsbSof::OtgFs => 0b10 << 10,
+            UsbSof::OtgHs => 0b11 << 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SofSource {
+    /// Internal trigger 1 of TIM2, remapped to a USB peripheral's SOF.
+    Itr1(UsbSof),
+    /// A timer input channel, externally wired to the SOF output pin. The pin must be configured for the timer's
+    /// alternate function (e.g. by means of an `input_capture::CapturePin`).
+    Input(Channel),
+}
+
+pub struct SofCapture<T: GeneralInstance1Channel> {
+    timer: Timer<'static, T>,
+    channel: Channel,
+    counter: CounterExtension,
+}
+
+impl<T: GeneralInstance1Channel> SofCapture<T> {
+    pub fn new(tim: T, source: SofSource) -> Self {
+        let timer = Timer::new(tim);
+        timer.set_tick_freq(Hertz(FEEDBACK_COUNTER_TICK_RATE));
+
+        let regs = Self::regs(&timer);
+        let channel = match source {
+            SofSource::Itr1(usb_sof) => {
+                // Only TIM2 can be remapped to the SOF.
+                assert!(regs.as_ptr() == pac::TIM2.as_ptr());
+
+                regs.smcr().modify(|w| w.set_ts(TriggerSource::ITR1));
+                pac::TIM2.or().write(|r| *r = usb_sof.itr1_remap());
+                Self::set_input(regs, Channel::Ch1, InputTISelection::TRC);
+
+                Channel::Ch1
+            }
+            SofSource::Input(channel) => {
+                Self::set_input(regs, channel, InputTISelection::Normal);
+                channel
+            }
+        };
+
+        let counter = CounterExtension::new(match T::BITS {
+            TimerBits::Bits16 => 16,
+            TimerBits::Bits32 => 32,
+        });
+
+        Self {
+            timer,
+            channel,
+            counter,
+        }
+    }
+
+    // General-purpose timers of any counter width and channel count share the 16 bit timers' register layout, for the
+    // registers that are used here. Reading a capture register in full returns all counter bits.
+    fn regs(timer: &Timer<'static, T>) -> TimGp16 {
+        // SAFETY: The pointer is the timer's register block.
+        unsafe { TimGp16::from_ptr(timer.regs_core().as_ptr()) }
+    }
+
+    // Configure a channel as input, without prescaler.
+    fn set_input(regs: TimGp16, channel: Channel, selection: InputTISelection) {
+        let index = channel.index();
+
+        regs.ccmr_input(index / 2).modify(|w| {
+            w.set_ccs(index % 2, selection.into());
+            w.set_icpsc(index % 2, 0);
+            w.set_icf(index % 2, FilterValue::FCK_INT_N2);
+        });
+    }
+
+    fn extends_counter() -> bool {
+        matches!(T::BITS, TimerBits::Bits16)
+    }
+
+    /// Start capturing, with an interrupt per SOF, and per counter overflow for 16 bit timers.
+    pub fn start(&mut self) {
+        let regs = Self::regs(&self.timer);
+        let index = self.channel.index();
+
+        // Reset all interrupt flags.
+        regs.sr().write(|r| r.0 = 0);
+
+        regs.ccer().modify(|w| w.set_cce(index, true));
+        regs.dier().modify(|w| {
+            w.set_ccie(index, true);
+            w.set_uie(Self::extends_counter());
+        });
+        self.timer.start();
+
+        // On timers with separate update and capture vectors, both must be handled.
+        T::CaptureCompareInterrupt::unpend();
+        unsafe { T::CaptureCompareInterrupt::enable() };
+
+        if Self::extends_counter() {
+            <T as CoreInstance>::UpdateInterrupt::unpend();
+            unsafe { <T as CoreInstance>::UpdateInterrupt::enable() };
+        }
+    }
+
+    /// Handle the timer interrupt, and return the captured (extended) counter value, if any.
+    pub fn on_interrupt(&mut self) -> Option<u32> {
+        let regs = Self::regs(&self.timer);
+        let index = self.channel.index();
+        let status = regs.sr().read();
+
+        // Flags are cleared by writing zero, and writing one has no effect. Only the flags of the capture channel and
+        // the update flag are written, the other bits (flags of unused channels, or reserved) are written with their
+        // reset value. The update flag is only cleared, if it was read, so that an overflow after the read is kept for
+        // the next interrupt. The capture flag is cleared by reading the capture register.
+        regs.sr().write(|w| {
+            w.set_ccif(index, true);
+            w.set_uif(!status.uif());
+        });
+
+        let captured = status.ccif(index).then(|| regs.ccr(index).read().0);
+        self.counter.update(status.uif(), captured)
+    }
+}
+
+static SOF_CAPTURE: Mutex<CriticalSectionRawMutex, RefCell<Option<SofCapture<SofTimer>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Hand over the capture to the interrupt handler, and start capturing. The capture is stored first, so that the
+/// interrupt handler finds it as soon as its interrupts are enabled.
+pub fn start(sof_capture: SofCapture<SofTimer>) {
+    SOF_CAPTURE.lock(|cell| cell.borrow_mut().insert(sof_capture).start());
+}
+
+/// Called from the timer's interrupt handler. Returns the captured counter value, if any.
+pub fn on_interrupt() -> Option<u32> {
+    SOF_CAPTURE.lock(|cell| cell.borrow_mut().as_mut().and_then(SofCapture::on_interrupt))
+}
@@ -0,0 +1,317 @@
+// Audio source selection: USB audio, the analog aux input, both mixed, or the external digital (I2S) input.
+//
+// A source is either selected manually, or automatically by signal presence: the first present input in a
+// configurable priority order plays, and the active input is kept while none is present. Selection and priority are
+// part of the persistent settings. The streaming task plays the active source, fades out on a change, and restarts
+// its pipeline with the new one. Sources that the board does not provide cannot be selected.
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::*;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum Source {
+    Usb = 0,
+    Aux = 1,
+    /// USB audio with the aux input mixed in.
+    Mix = 2,
+    /// External S/PDIF or TOSLINK receiver.
+    I2s = 3,
+}
+
+impl Source {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Usb),
+            1 => Some(Self::Aux),
+            2 => Some(Self::Mix),
+            3 => Some(Self::I2s),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Usb => "USB",
+            Source::Aux => "Aux",
+            Source::Mix => "USB + Aux",
+            Source::I2s => "Digital",
+        }
+    }
+
+    fn is_available(self) -> bool {
+        // Mixing requires the aux input.
+        let input = match self {
+            Source::Mix => Source::Aux,
+            _ => self,
+        };
+
+        AVAILABLE.load(Relaxed) & 1 << input as u8 != 0
+    }
+
+    // Index of an input, for its presence detection.
+    fn input_index(self) -> Option<usize> {
+        INPUTS.iter().position(|&input| input == self)
+    }
+}
+
+/// Inputs, whose signal is detected. These are the sources that automatic selection chooses from.
+pub const INPUTS: [Source; 3] = [Source::Usb, Source::Aux, Source::I2s];
+pub const INPUT_COUNT: usize = INPUTS.len();
+
+pub const DEFAULT_PRIORITY: [Source; INPUT_COUNT] = INPUTS;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Selection {
+    Manual(Source),
+    Automatic,
+}
+
+impl Selection {
+    // Value of automatic selection, distinct from all sources.
+    const AUTOMATIC: u8 = 0xff;
+
+    // All selections, in the order that they are cycled through.
+    const ALL: [Self; 5] = [
+        Self::Manual(Source::Usb),
+        Self::Manual(Source::Aux),
+        Self::Manual(Source::Mix),
+        Self::Manual(Source::I2s),
+        Self::Automatic,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            Self::AUTOMATIC => Some(Self::Automatic),
+            _ => Source::from_u8(value).map(Self::Manual),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Selection::Manual(source) => source as u8,
+            Selection::Automatic => Self::AUTOMATIC,
+        }
+    }
+
+    fn is_available(self) -> bool {
+        match self {
+            Selection::Manual(source) => source.is_available(),
+            Selection::Automatic => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct Unavailable(pub u8);
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct InvalidPriority;
+
+// Peaks above this magnitude count as signal (about -48 dBFS, above the noise floor of the aux input).
+const PRESENCE_THRESHOLD: u32 = 1 << 23;
+
+// Signal must be present for this long, before an input counts as present.
+const ATTACK_FRAMES: u32 = SAMPLE_RATE_HZ / 10;
+
+// Silence must last this long, before an input counts as absent.
+const RELEASE_FRAMES: u32 = 5 * SAMPLE_RATE_HZ;
+
+// Presence of signal on an input, with hysteresis.
+#[derive(Clone, Copy)]
+struct Presence {
+    // Frames of signal or silence, depending on the state.
+    frames: u32,
+    present: bool,
+}
+
+impl Presence {
+    const fn new() -> Self {
+        Self {
+            frames: 0,
+            present: false,
+        }
+    }
+
+    // Returns true, if the presence changed.
+    fn update(&mut self, peak: u32, frame_count: u32) -> bool {
+        // Frames count towards a change of state.
+        if (peak >= PRESENCE_THRESHOLD) != self.present {
+            self.frames = self.frames.saturating_add(frame_count);
+        } else {
+            self.frames = 0;
+        }
+
+        let limit = if self.present { RELEASE_FRAMES } else { ATTACK_FRAMES };
+        if self.frames < limit {
+            return false;
+        }
+
+        self.present = !self.present;
+        self.frames = 0;
+        true
+    }
+}
+
+static PRESENCE: Mutex<CriticalSectionRawMutex, RefCell<[Presence; INPUT_COUNT]>> =
+    Mutex::new(RefCell::new([Presence::new(); INPUT_COUNT]));
+
+static ACTIVE: AtomicU8 = AtomicU8::new(Source::Usb as u8);
+static SWITCH_PENDING: AtomicBool = AtomicBool::new(false);
+
+// Available sources, one bit per source. USB audio is always available.
+static AVAILABLE: AtomicU8 = AtomicU8::new(1 << Source::Usb as u8);
+
+/// Mark an input as available, once its driver is running.
+pub fn enable(source: Source) {
+    AVAILABLE.fetch_or(1 << source as u8, Relaxed);
+    update();
+}
+
+/// The selection, falling back to automatic selection for sources that are unavailable.
+pub fn selection() -> Selection {
+    Some(settings::get().source)
+        .filter(|selection| selection.is_available())
+        .unwrap_or(Selection::Automatic)
+}
+
+/// The priority order of automatic selection.
+pub fn priority() -> [Source; INPUT_COUNT] {
+    settings::get().source_priority
+}
+
+/// The source that plays (or is about to play, after a change).
+pub fn active() -> Source {
+    Source::from_u8(ACTIVE.load(Relaxed)).unwrap_or(Source::Usb)
+}
+
+// Access the presence detection of an input.
+fn with_presence<R>(source: Source, f: impl FnOnce(&mut Presence) -> R) -> Option<R> {
+    let index = source.input_index()?;
+    Some(PRESENCE.lock(|presence| f(&mut presence.borrow_mut()[index])))
+}
+
+/// Whether signal is present on an input.
+pub fn is_present(source: Source) -> bool {
+    with_presence(source, |presence| presence.present).unwrap_or(false)
+}
+
+/// Whether the active source changed, and the playing source must fade out.
+pub fn switch_pending() -> bool {
+    SWITCH_PENDING.load(Relaxed)
+}
+
+/// Fade out the playing source, and restart it, e.g. for a measurement.
+pub fn interrupt() {
+    SWITCH_PENDING.store(true, Relaxed);
+    SOURCE_SIGNAL.signal(active());
+}
+
+/// Called by the streaming task, once it plays the active source.
+pub fn switch_done() {
+    SWITCH_PENDING.store(false, Relaxed);
+}
+
+// Re-evaluate the active source, and request a change.
+fn update() {
+    let active = active();
+
+    let target = match selection() {
+        Selection::Manual(source) => source,
+        Selection::Automatic => priority()
+            .into_iter()
+            .find(|&source| source.is_available() && is_present(source))
+            .unwrap_or(match active {
+                // Mixing is not chosen automatically.
+                Source::Mix => Source::Usb,
+                _ => active,
+            }),
+    };
+
+    if ACTIVE.swap(target as u8, Relaxed) != target as u8 {
+        info!("Switching source from {} to {}", active, target);
+        SWITCH_PENDING.store(true, Relaxed);
+        SOURCE_SIGNAL.signal(target);
+    }
+}
+
+/// Report the peak magnitude of a number of frames on an input, for automatic selection.
+pub fn report(source: Source, peak: u32, frame_count: usize) {
+    let changed = with_presence(source, |presence| presence.update(peak, frame_count as u32)).unwrap_or(false);
+
+    if changed {
+        info!("Signal on {}: {}", source, is_present(source));
+        update();
+    }
+}
+
+/// Mark an input as absent, e.g. when the host closes the stream.
+pub fn set_absent(source: Source) {
+    let changed =
+        with_presence(source, |presence| core::mem::replace(presence, Presence::new()).present).unwrap_or(false);
+
+    if changed {
+        info!("Signal on {}: false", source);
+        update();
+    }
+}
+
+/// Change the selection, which is stored in the settings.
+pub fn select(selection: Selection) -> Result<(), Unavailable> {
+    if !selection.is_available() {
+        return Err(Unavailable(selection.to_u8()));
+    }
+
+    info!("Select source: {}", selection);
+    settings::modify(|settings| settings.source = selection);
+    update();
+
+    Ok(())
+}
+
+/// Select the next available source, with automatic selection last.
+pub fn select_next() {
+    let current = selection();
+    let index = Selection::ALL
+        .iter()
+        .position(|&selection| selection == current)
+        .unwrap();
+
+    let next = Selection::ALL
+        .iter()
+        .cycle()
+        .skip(index + 1)
+        .find(|selection| selection.is_available())
+        .unwrap();
+
+    select(*next).unwrap();
+}
+
+/// Check, that a priority order contains every input once.
+pub fn validate_priority(order: &[u8]) -> Result<[Source; INPUT_COUNT], InvalidPriority> {
+    let order: [u8; INPUT_COUNT] = order.try_into().map_err(|_| InvalidPriority)?;
+
+    let is_permutation = INPUTS
+        .iter()
+        .all(|&source| order.iter().filter(|&&value| value == source as u8).count() == 1);
+    if !is_permutation {
+        return Err(InvalidPriority);
+    }
+
+    Ok(order.map(|value| Source::from_u8(value).unwrap()))
+}
+
+/// Set the priority order of automatic selection, which must contain every input once.
+pub fn set_priority(order: &[u8]) -> Result<(), InvalidPriority> {
+    let priority = validate_priority(order)?;
+
+    info!("Source priority: {}", priority);
+    settings::modify(|settings| settings.source_priority = priority);
+    update();
+
+    Ok(())
+}
@@ -0,0 +1,273 @@
+// S/PDIF output via the STM32F446's SAI1 block A in SPDIF mode, mirroring the I2S output (e.g. for an external DAC or
+// AV receiver on coax).
+//
+// The SAI generates preambles, biphase-mark coding and parity. Per subframe, it takes a 24 bit sample along with the
+// validity, user and channel status bits. Channel status is sent as consumer-format PCM (IEC 60958-3), with the sample
+// rate and a 24 bit word length, one bit per frame in blocks of 192 frames. The SAI starts every block with a B
+// preamble, counted from enabling it, so that the frame counter restarts along with the SAI.
+//
+// The SAI clock is 128 fs, derived from the I2S PLL's Q output with the SAI's own divider. For every output sample
+// rate, it has the same offset as the I2S clock (see `i2s_clock`), so that both outputs consume samples at the same
+// rate.
+use blus_core::format::{from_words, narrow, Rounding};
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+use defmt::{info, unwrap, warn};
+use embassy_stm32::dma::{TransferOptions, WritableRingBuffer};
+use embassy_stm32::pac::gpio::vals::{Moder, Ospeedr};
+use embassy_stm32::pac::rcc::vals::Sai1src;
+use embassy_stm32::pac::sai::vals::{Ds, Fth, Mode, Prtcfg};
+use embassy_stm32::peripherals::{DMA2_CH1, SAI1};
+use embassy_stm32::{pac, rcc, sai};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use static_cell::StaticCell;
+
+use crate::i2s_clock::{self, ClockError};
+use crate::sample_block::WORD_ORDER;
+use crate::*;
+
+// Block A of SAI1.
+const REGS: pac::sai::Ch = pac::SAI1.ch(0);
+
+const CHANNEL_COUNT: usize = 2;
+static_assertions::const_assert_eq!(CHANNEL_COUNT, INPUT_CHANNEL_COUNT);
+
+// DMA ring buffer with one 32 bit word per subframe, of the same duration as the I2S ring buffer.
+const RING_BUFFER_SIZE: usize = I2S_BUFFER_SIZE / 2;
+
+// Frames per channel status block.
+const BLOCK_FRAMES: usize = 192;
+
+/// Size of a channel's status block.
+pub const CHANNEL_STATUS_SIZE: usize = BLOCK_FRAMES / 8;
+
+// Samples are sent with 24 bit, rounded to nearest, in the subframe word's lowest bits.
+const SAMPLE_BITS: u32 = 24;
+const SAMPLE_MASK: u32 = (1 << SAMPLE_BITS) - 1;
+
+// Bit position of the channel status in the subframe word, above the 24 bit sample, and the validity and user bits.
+const CHANNEL_STATUS_BIT: u32 = 26;
+
+// Channel status fields (IEC 60958-3, consumer format).
+mod status {
+    // Byte 0: consumer use, linear PCM, copying permitted, no pre-emphasis.
+    pub const COPY_PERMITTED: u8 = 1 << 2;
+
+    // Byte 2: channel number in the upper nibble, starting at 1 for the left channel.
+    pub const CHANNEL_NUMBER_SHIFT: u32 = 4;
+
+    // Byte 4: 24 bit maximum word length, with a word length of 24 bit.
+    pub const WORD_LENGTH_24_BIT: u8 = 0b1011;
+
+    // Byte 3: sample rate code, for rates that do not have one.
+    pub const RATE_NOT_INDICATED: u8 = 0b0001;
+}
+
+// The SAI clock is 128 fs, divided from the I2S PLL's Q output (at half its VCO clock) by the SAI's divider.
+const SAI_CLOCK_FS_RATIO: u32 = 128;
+const PLLI2S_Q_DIVISION: u32 = 2;
+const SAI_DIVIDER_RANGE: RangeInclusive<u32> = 1..=32;
+
+/// GPIO port and pin number of the SAI's serial data line, and its alternate function.
+#[derive(Clone, Copy)]
+pub struct SpdifPin {
+    pub port: pac::gpio::Gpio,
+    pub pin: usize,
+    pub af: u8,
+}
+
+// Sample rate code of the channel status.
+fn rate_code(sample_rate_hz: u32) -> u8 {
+    match sample_rate_hz {
+        32_000 => 0b0011,
+        44_100 => 0b0000,
+        48_000 => 0b0010,
+        88_200 => 0b1000,
+        96_000 => 0b1010,
+        _ => status::RATE_NOT_INDICATED,
+    }
+}
+
+/// The channel status block of a channel (starting at 0), transmitted least significant bit first.
+pub fn channel_status(sample_rate_hz: u32, channel: usize) -> [u8; CHANNEL_STATUS_SIZE] {
+    let mut block = [0; CHANNEL_STATUS_SIZE];
+
+    block[0] = status::COPY_PERMITTED;
+    block[2] = (channel as u8 + 1) << status::CHANNEL_NUMBER_SHIFT;
+    block[3] = rate_code(sample_rate_hz);
+    block[4] = status::WORD_LENGTH_24_BIT;
+
+    block
+}
+
+pub struct SpdifOutput {
+    ring: WritableRingBuffer<'static, u32>,
+    sample_rate_hz: u32,
+    channel_status: [[u8; CHANNEL_STATUS_SIZE]; CHANNEL_COUNT],
+    // Position in the channel status block, in subframes.
+    subframe: usize,
+}
+
+impl SpdifOutput {
+    /// Transmit from SAI1 block A on its serial data pin. The I2S PLL's Q output must be enabled.
+    pub fn new(_sai: SAI1, dma: DMA2_CH1, pin: SpdifPin) -> Self {
+        pin.port.afr(pin.pin / 8).modify(|w| w.set_afr(pin.pin % 8, pin.af));
+        pin.port
+            .ospeedr()
+            .modify(|w| w.set_ospeedr(pin.pin, Ospeedr::HIGHSPEED));
+        pin.port.moder().modify(|w| w.set_moder(pin.pin, Moder::ALTERNATE));
+
+        pac::RCC.dckcfgr().modify(|w| w.set_sai1src(Sai1src::PLLI2S_Q));
+        rcc::enable_and_reset::<SAI1>();
+
+        REGS.cr1().write(|w| {
+            w.set_mode(Mode::MASTERTX);
+            w.set_prtcfg(Prtcfg::SPDIF);
+            w.set_ds(Ds::BIT24);
+            // The SAI clock is the symbol clock.
+            w.set_mckdiv(0);
+            w.set_outdriv(true);
+            w.set_dmaen(true);
+        });
+        REGS.cr2().write(|w| w.set_fth(Fth::HALF));
+
+        static DMA: StaticCell<DMA2_CH1> = StaticCell::new();
+        static BUFFER: StaticCell<[u32; RING_BUFFER_SIZE]> = StaticCell::new();
+
+        let dma = DMA.init(dma);
+        let request = sai::Dma::<SAI1, sai::A>::request(dma);
+
+        // SAFETY: The DMA channel and buffer are owned by the ring buffer for the program's lifetime.
+        let ring = unsafe {
+            WritableRingBuffer::new(
+                dma,
+                request,
+                REGS.dr().as_ptr() as *mut u32,
+                &mut BUFFER.init([0; RING_BUFFER_SIZE])[..],
+                TransferOptions::default(),
+            )
+        };
+
+        let mut output = Self {
+            ring,
+            sample_rate_hz: SAMPLE_RATE_HZ,
+            channel_status: [[0; CHANNEL_STATUS_SIZE]; CHANNEL_COUNT],
+            subframe: 0,
+        };
+        unwrap!(output.set_sample_rate(SAMPLE_RATE_HZ));
+
+        output
+    }
+
+    // Follows the I2S PLL, which must be configured for the sample rate's family.
+    fn set_sample_rate(&mut self, sample_rate_hz: u32) -> Result<(), ClockError> {
+        let (division, _) = i2s_clock::divider(
+            i2s_clock::family().vco_hz() / PLLI2S_Q_DIVISION,
+            sample_rate_hz,
+            SAI_CLOCK_FS_RATIO,
+            SAI_DIVIDER_RANGE,
+        )?;
+
+        // The divider's register value is one less than the division.
+        pac::RCC.dckcfgr().modify(|w| w.set_plli2sdivq(division as u8 - 1));
+
+        self.sample_rate_hz = sample_rate_hz;
+        for (channel, status) in self.channel_status.iter_mut().enumerate() {
+            *status = channel_status(sample_rate_hz, channel);
+        }
+
+        Ok(())
+    }
+
+    fn enable(&mut self) {
+        self.ring.clear();
+        self.subframe = 0;
+        REGS.cr1().modify(|w| w.set_saien(true));
+    }
+
+    fn disable(&mut self) {
+        REGS.cr1().modify(|w| w.set_saien(false));
+        while REGS.cr1().read().saien() {}
+
+        REGS.cr2().modify(|w| w.set_fflush(true));
+        REGS.clrfr().write(|w| w.set_covrudr(true));
+    }
+
+    fn start(&mut self) {
+        self.ring.start();
+        self.enable();
+    }
+
+    fn stop(&mut self) {
+        self.disable();
+        self.ring.request_stop();
+    }
+
+    // Convert Q31 samples to subframes, with their channel status bits.
+    fn write(&mut self, words: &[u16]) {
+        let mut subframes = [0u32; USB_SAMPLE_BLOCK_SAMPLE_COUNT];
+        let subframes = &mut subframes[..(words.len() / 2).min(USB_SAMPLE_BLOCK_SAMPLE_COUNT)];
+
+        for (subframe, word_pair) in subframes.iter_mut().zip(words.chunks_exact(2)) {
+            let sample = from_words([word_pair[0], word_pair[1]], WORD_ORDER);
+            let sample = narrow(sample, SAMPLE_BITS, Rounding::Nearest) as u32;
+
+            let frame = self.subframe / CHANNEL_COUNT;
+            let channel = self.subframe % CHANNEL_COUNT;
+            let status_bit = self.channel_status[channel][frame / 8] >> (frame % 8) & 1;
+
+            // Samples are valid (validity bit clear), and no user data is sent.
+            *subframe = sample & SAMPLE_MASK | (status_bit as u32) << CHANNEL_STATUS_BIT;
+            self.subframe = (self.subframe + 1) % (BLOCK_FRAMES * CHANNEL_COUNT);
+        }
+
+        // The I2S output paces writes, and consumes samples at the same rate, so that there is always room.
+        match self.ring.write_immediate(subframes) {
+            Ok((written, _)) if written == subframes.len() => (),
+            _ => {
+                warn!("S/PDIF buffer overrun");
+
+                // Restarts the channel status block along with the SAI.
+                self.disable();
+                self.enable();
+            }
+        }
+    }
+}
+
+static SPDIF_OUTPUT: Mutex<CriticalSectionRawMutex, RefCell<Option<SpdifOutput>>> = Mutex::new(RefCell::new(None));
+
+fn with_output(f: impl FnOnce(&mut SpdifOutput)) {
+    SPDIF_OUTPUT.lock(|cell| cell.borrow_mut().as_mut().map(f));
+}
+
+/// Hand over the output to the I2S output task, which mirrors its samples.
+pub fn init(output: SpdifOutput) {
+    info!("S/PDIF output at {} Hz", output.sample_rate_hz);
+    SPDIF_OUTPUT.lock(|cell| cell.borrow_mut().replace(output));
+}
+
+/// Follow a change of the I2S output's sample rate, while output is stopped.
+pub fn set_sample_rate(sample_rate_hz: u32) {
+    with_output(|output| {
+        if let Err(e) = output.set_sample_rate(sample_rate_hz) {
+            warn!("Cannot reconfigure S/PDIF clock: {}", e);
+        }
+    });
+}
+
+/// Start output, along with I2S output.
+pub fn start() {
+    with_output(SpdifOutput::start);
+}
+
+/// Stop output, along with I2S output.
+pub fn stop() {
+    with_output(SpdifOutput::stop);
+}
+
+/// Mirror samples that were written to the I2S output, as 16 bit words.
+pub fn write(words: &[u16]) {
+    with_output(|output| output.write(words));
+}
@@ -0,0 +1,175 @@
+// Spectrum snapshots of the output, for checking EQ and crossover settings without measurement equipment.
+//
+// A vendor request arms the capture of `FFT_LENGTH` samples of a channel, at the end of the processing pipeline. The
+// spectrum task then computes their magnitude spectrum with a Hann window (see `blus_core::dsp::fft`), one FFT stage
+// at a time, so that it does not hold up the streaming tasks. The host reads the bins in chunks, as `i16` in 0.1 dBFS.
+use blus_core::dsp::fft;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU8, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use static_cell::StaticCell;
+
+use crate::*;
+
+pub const FFT_LENGTH: usize = 512;
+
+/// Bins from DC to half the sample rate.
+pub const BIN_COUNT: usize = FFT_LENGTH / 2 + 1;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum Status {
+    Idle = 0,
+    Capturing = 1,
+    Computing = 2,
+    Ready = 3,
+}
+
+impl Status {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Capturing,
+            2 => Self::Computing,
+            3 => Self::Ready,
+            _ => Self::Idle,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Format)]
+pub struct UnknownChannel(pub usize);
+
+struct Capture {
+    channel: usize,
+    samples: [i32; FFT_LENGTH],
+    length: usize,
+}
+
+static STATUS: AtomicU8 = AtomicU8::new(Status::Idle as u8);
+
+static CAPTURE: Mutex<CriticalSectionRawMutex, RefCell<Capture>> = Mutex::new(RefCell::new(Capture {
+    channel: 0,
+    samples: [0; FFT_LENGTH],
+    length: 0,
+}));
+
+static SPECTRUM: Mutex<CriticalSectionRawMutex, RefCell<[i16; BIN_COUNT]>> = Mutex::new(RefCell::new([0; BIN_COUNT]));
+
+pub fn status() -> Status {
+    Status::from_u8(STATUS.load(Relaxed))
+}
+
+/// Capture a snapshot of a channel, replacing the last spectrum.
+pub fn start(channel: usize) -> Result<(), UnknownChannel> {
+    if channel >= INPUT_CHANNEL_COUNT {
+        return Err(UnknownChannel(channel));
+    }
+
+    info!("Capture spectrum of channel {}", channel);
+    CAPTURE.lock(|capture| {
+        let mut capture = capture.borrow_mut();
+        capture.channel = channel;
+        capture.length = 0;
+    });
+    STATUS.store(Status::Capturing as u8, Relaxed);
+
+    Ok(())
+}
+
+/// Capture the samples of a processed block, while a snapshot is armed.
+pub fn capture(samples: &mut UsbSampleBlock) {
+    if status() != Status::Capturing {
+        return;
+    }
+
+    let complete = CAPTURE.lock(|capture| {
+        let capture = &mut *capture.borrow_mut();
+        let mut channel = 0;
+
+        samples.process(|sample| {
+            if channel == capture.channel && capture.length < FFT_LENGTH {
+                capture.samples[capture.length] = sample;
+                capture.length += 1;
+            }
+            channel = (channel + 1) % INPUT_CHANNEL_COUNT;
+
+            sample
+        });
+
+        capture.length == FFT_LENGTH
+    });
+
+    if complete {
+        STATUS.store(Status::Computing as u8, Relaxed);
+        SPECTRUM_CAPTURED_SIGNAL.signal(());
+    }
+}
+
+/// Copy the spectrum's bins from `first_bin` into a buffer, and return the number of bytes, once it is ready.
+pub fn read(first_bin: usize, buf: &mut [u8]) -> Option<usize> {
+    if status() != Status::Ready || first_bin >= BIN_COUNT {
+        return None;
+    }
+
+    SPECTRUM.lock(|spectrum| {
+        let spectrum = spectrum.borrow();
+        let bins = &spectrum[first_bin..];
+        let count = bins.len().min(buf.len() / 2);
+
+        for (bytes, bin) in buf.chunks_exact_mut(2).zip(&bins[..count]) {
+            bytes.copy_from_slice(&bin.to_le_bytes());
+        }
+
+        Some(2 * count)
+    })
+}
+
+struct Scratch {
+    samples: [i32; FFT_LENGTH],
+    re: [f32; FFT_LENGTH],
+    im: [f32; FFT_LENGTH],
+    magnitudes_db: [f32; BIN_COUNT],
+}
+
+/// Computes the spectrum of captured snapshots.
+#[embassy_executor::task]
+pub async fn spectrum_task() {
+    static SCRATCH: StaticCell<Scratch> = StaticCell::new();
+    let scratch = SCRATCH.init(Scratch {
+        samples: [0; FFT_LENGTH],
+        re: [0.0; FFT_LENGTH],
+        im: [0.0; FFT_LENGTH],
+        magnitudes_db: [0.0; BIN_COUNT],
+    });
+
+    loop {
+        SPECTRUM_CAPTURED_SIGNAL.wait().await;
+
+        CAPTURE.lock(|capture| scratch.samples = capture.borrow().samples);
+        fft::window(&scratch.samples, &mut scratch.re, &mut scratch.im);
+        fft::bit_reverse(&mut scratch.re, &mut scratch.im);
+
+        let mut size = 2;
+        while size <= FFT_LENGTH {
+            yield_now().await;
+            fft::butterflies(&mut scratch.re, &mut scratch.im, size);
+            size *= 2;
+        }
+
+        yield_now().await;
+        fft::magnitudes_db(&scratch.re, &scratch.im, &mut scratch.magnitudes_db);
+
+        // A new capture may have been started meanwhile.
+        if status() == Status::Computing {
+            SPECTRUM.lock(|spectrum| {
+                for (bin, magnitude_db) in spectrum.borrow_mut().iter_mut().zip(&scratch.magnitudes_db) {
+                    *bin = (magnitude_db * 10.0) as i16;
+                }
+            });
+            STATUS.store(Status::Ready as u8, Relaxed);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Driver for SPI NOR flash memories (e.g. Winbond W25Q series), with 4 kiB sectors and 256 byte pages.
+use defmt::Format;
+use embassy_time::Timer;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+mod cmd {
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const READ_STATUS: u8 = 0x05;
+    pub const READ_DATA: u8 = 0x03;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    pub const JEDEC_ID: u8 = 0x9f;
+}
+
+// Write-in-progress bit of the status register.
+const STATUS_BUSY: u8 = 1 << 0;
+
+pub const SECTOR_SIZE: u32 = 4096;
+pub const PAGE_SIZE: u32 = 256;
+
+// Polling interval while waiting for a page program or erase to complete.
+const BUSY_POLL_INTERVAL_US: u64 = 100;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+impl JedecId {
+    /// The capacity in byte, encoded as a power of two.
+    pub fn capacity_bytes(&self) -> u32 {
+        1u32.checked_shl(self.capacity as u32).unwrap_or(0)
+    }
+}
+
+pub struct SpiFlash<SPI> {
+    spi: SPI,
+}
+
+impl<SPI: SpiDevice> SpiFlash<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self { spi }
+    }
+
+    pub async fn jedec_id(&mut self) -> Result<JedecId, SPI::Error> {
+        let mut id = [0u8; 3];
+        self.spi
+            .transaction(&mut [Operation::Write(&[cmd::JEDEC_ID]), Operation::Read(&mut id)])
+            .await?;
+
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    fn command(command: u8, address: u32) -> [u8; 4] {
+        let address = address.to_be_bytes();
+        [command, address[1], address[2], address[3]]
+    }
+
+    async fn wait_ready(&mut self) -> Result<(), SPI::Error> {
+        loop {
+            let mut status = [0u8];
+            self.spi
+                .transaction(&mut [Operation::Write(&[cmd::READ_STATUS]), Operation::Read(&mut status)])
+                .await?;
+
+            if status[0] & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+
+            Timer::after_micros(BUSY_POLL_INTERVAL_US).await;
+        }
+    }
+
+    async fn write_enable(&mut self) -> Result<(), SPI::Error> {
+        self.spi.write(&[cmd::WRITE_ENABLE]).await
+    }
+
+    pub async fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), SPI::Error> {
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&Self::command(cmd::READ_DATA, address)),
+                Operation::Read(buffer),
+            ])
+            .await
+    }
+
+    /// Erase the 4 kiB sector that contains the address.
+    pub async fn erase_sector(&mut self, address: u32) -> Result<(), SPI::Error> {
+        self.write_enable().await?;
+        self.spi
+            .write(&Self::command(cmd::SECTOR_ERASE, address & !(SECTOR_SIZE - 1)))
+            .await?;
+
+        self.wait_ready().await
+    }
+
+    /// Program erased memory, split at page boundaries.
+    pub async fn write(&mut self, mut address: u32, mut data: &[u8]) -> Result<(), SPI::Error> {
+        while !data.is_empty() {
+            let page_remaining = (PAGE_SIZE - address % PAGE_SIZE) as usize;
+            let (chunk, rest) = data.split_at(page_remaining.min(data.len()));
+
+            self.write_enable().await?;
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&Self::command(cmd::PAGE_PROGRAM, address)),
+                    Operation::Write(chunk),
+                ])
+                .await?;
+            self.wait_ready().await?;
+
+            address += chunk.len() as u32;
+            data = rest;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+// Driver for SSD1306 128x64 monochrome OLED displays, controlled via I2C.
+//
+// The display memory is organized in eight pages of 128 columns, where each byte holds eight vertically stacked
+// pixels (least significant bit on top). Pages are written individually, so that a refresh never holds the shared
+// bus for long.
+use embedded_hal_async::i2c::{I2c, Operation};
+
+pub const WIDTH: usize = 128;
+pub const PAGE_COUNT: usize = 8;
+
+// Control bytes, preceding a command or data stream.
+const CONTROL_COMMAND: u8 = 0x00;
+const CONTROL_DATA: u8 = 0x40;
+
+// Initialization for a 128x64 panel with the internal charge pump, in page addressing mode.
+const INIT_SEQUENCE: &[u8] = &[
+    0xae, // display off
+    0xd5, 0x80, // clock divider
+    0xa8, 0x3f, // multiplex ratio of 64
+    0xd3, 0x00, // no display offset
+    0x40, // start line 0
+    0x8d, 0x14, // charge pump on
+    0x20, 0x02, // page addressing mode
+    0xa1, // mirror segments
+    0xc8, // scan COM outputs in reverse
+    0xda, 0x12, // alternative COM pin configuration
+    0x81, 0xcf, // contrast
+    0xd9, 0xf1, // pre-charge period
+    0xdb, 0x40, // VCOMH deselect level
+    0xa4, // show RAM content
+    0xa6, // non-inverted
+    0xaf, // display on
+];
+
+pub struct Ssd1306<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> Ssd1306<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    async fn command(&mut self, commands: &[u8]) -> Result<(), I2C::Error> {
+        self.i2c
+            .transaction(
+                self.address,
+                &mut [Operation::Write(&[CONTROL_COMMAND]), Operation::Write(commands)],
+            )
+            .await
+    }
+
+    /// Configure the display and switch it on. Its memory content is undefined afterwards.
+    pub async fn init(&mut self) -> Result<(), I2C::Error> {
+        self.command(INIT_SEQUENCE).await
+    }
+
+    /// Write a page of pixels, starting at column zero.
+    pub async fn write_page(&mut self, page: usize, pixels: &[u8; WIDTH]) -> Result<(), I2C::Error> {
+        self.command(&[0xb0 | page as u8, 0x00, 0x10]).await?;
+        self.i2c
+            .transaction(
+                self.address,
+                &mut [Operation::Write(&[CONTROL_DATA]), Operation::Write(pixels)],
+            )
+            .await
+    }
+}
@@ -0,0 +1,295 @@
+// Streaming statistics, updated by the streaming, feedback, and output tasks, and reported periodically.
+use blus_core::feedback::{TickHistogram, HISTOGRAM_BIN_COUNT};
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering::Relaxed};
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Ticker, TICK_HZ};
+use heapless::HistoryBuffer;
+
+use crate::{latency, profile, INPUT_CHANNEL_COUNT};
+
+// Interval between two reports. Buffer fill extremes are tracked per interval.
+const REPORT_PERIOD: Duration = Duration::from_secs(5);
+
+// Number of feedback values that are kept for the report.
+pub const FEEDBACK_HISTORY_LENGTH: usize = 8;
+
+static PACKETS_RECEIVED: AtomicU32 = AtomicU32::new(0);
+static INVALID_PACKETS: AtomicU32 = AtomicU32::new(0);
+static SAMPLES_RECEIVED: AtomicU32 = AtomicU32::new(0);
+static SAMPLES_DROPPED: AtomicU32 = AtomicU32::new(0);
+static UNDERRUNS: AtomicU32 = AtomicU32::new(0);
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+static CONCEALED_FRAMES: AtomicU32 = AtomicU32::new(0);
+static STALLS: AtomicU32 = AtomicU32::new(0);
+
+// Isochronous packets that were missed by the device, and concealed.
+static MISSED_PACKETS: AtomicU32 = AtomicU32::new(0);
+
+// Clipped samples per channel, at the input and the output of the DSP chain.
+static CLIPPED_INPUT: [AtomicU32; INPUT_CHANNEL_COUNT] = [const { AtomicU32::new(0) }; INPUT_CHANNEL_COUNT];
+static CLIPPED_OUTPUT: [AtomicU32; INPUT_CHANNEL_COUNT] = [const { AtomicU32::new(0) }; INPUT_CHANNEL_COUNT];
+
+// Refresh periods that the feedback task did not take before the next one completed.
+static MISSED_FEEDBACK: AtomicU32 = AtomicU32::new(0);
+
+// Measured feedback values that were implausible, and replaced.
+static IMPLAUSIBLE_FEEDBACK: AtomicU32 = AtomicU32::new(0);
+
+// Number of sample blocks, queued between streaming and output task.
+static BUFFER_FILL: AtomicU32 = AtomicU32::new(0);
+static BUFFER_FILL_MIN: AtomicU32 = AtomicU32::new(u32::MAX);
+static BUFFER_FILL_MAX: AtomicU32 = AtomicU32::new(0);
+
+// Highest number of queued sample blocks since boot.
+static BUFFER_FILL_PEAK: AtomicU32 = AtomicU32::new(0);
+
+// Latest measured latency from USB packet arrival to I2S DMA hand-over.
+static LATENCY_TICKS: AtomicU32 = AtomicU32::new(0);
+
+// Offset of the local clock against the host's SOF, in parts per billion (see `clock_accuracy`).
+static CLOCK_OFFSET_PPB: AtomicI32 = AtomicI32::new(CLOCK_OFFSET_UNKNOWN);
+
+/// Value of the clock offset, until the first measurement window completes.
+pub const CLOCK_OFFSET_UNKNOWN: i32 = i32::MIN;
+
+static FEEDBACK_HISTORY: Mutex<CriticalSectionRawMutex, RefCell<HistoryBuffer<u32, FEEDBACK_HISTORY_LENGTH>>> =
+    Mutex::new(RefCell::new(HistoryBuffer::new()));
+
+// Measured refresh periods by their deviation from the average, since boot.
+static TICK_HISTOGRAM: Mutex<CriticalSectionRawMutex, Cell<TickHistogram>> =
+    Mutex::new(Cell::new(TickHistogram::new()));
+
+pub fn record_packet(sample_count: usize) {
+    PACKETS_RECEIVED.fetch_add(1, Relaxed);
+    SAMPLES_RECEIVED.fetch_add(sample_count as u32, Relaxed);
+}
+
+pub fn record_invalid_packet() {
+    INVALID_PACKETS.fetch_add(1, Relaxed);
+}
+
+pub fn record_dropped(sample_count: usize) {
+    SAMPLES_DROPPED.fetch_add(sample_count as u32, Relaxed);
+}
+
+pub fn record_underrun() {
+    UNDERRUNS.fetch_add(1, Relaxed);
+}
+
+pub fn record_overrun() {
+    OVERRUNS.fetch_add(1, Relaxed);
+}
+
+pub fn record_concealed_frame() {
+    CONCEALED_FRAMES.fetch_add(1, Relaxed);
+}
+
+pub fn record_stall() {
+    STALLS.fetch_add(1, Relaxed);
+}
+
+pub fn record_missed_packets(count: usize) {
+    MISSED_PACKETS.fetch_add(count as u32, Relaxed);
+}
+
+pub fn record_clipped(input: [u32; INPUT_CHANNEL_COUNT], output: [u32; INPUT_CHANNEL_COUNT]) {
+    for (counter, count) in CLIPPED_INPUT.iter().zip(input) {
+        counter.fetch_add(count, Relaxed);
+    }
+    for (counter, count) in CLIPPED_OUTPUT.iter().zip(output) {
+        counter.fetch_add(count, Relaxed);
+    }
+}
+
+pub fn record_missed_feedback() {
+    MISSED_FEEDBACK.fetch_add(1, Relaxed);
+}
+
+pub fn record_implausible_feedback() {
+    IMPLAUSIBLE_FEEDBACK.fetch_add(1, Relaxed);
+}
+
+pub fn record_feedback(value: u32) {
+    FEEDBACK_HISTORY.lock(|history| history.borrow_mut().write(value));
+}
+
+/// Add the ticks of a measured refresh period to the histogram.
+pub fn record_feedback_ticks(ticks: u32) {
+    TICK_HISTOGRAM.lock(|histogram| {
+        let mut updated = histogram.get();
+        updated.add(ticks);
+        histogram.set(updated);
+    });
+}
+
+/// Restart the histogram's reference period, when the measurement restarts or its clock changes.
+pub fn restart_tick_histogram() {
+    TICK_HISTOGRAM.lock(|histogram| {
+        let mut updated = histogram.get();
+        updated.restart();
+        histogram.set(updated);
+    });
+}
+
+pub fn tick_histogram() -> [u32; HISTOGRAM_BIN_COUNT] {
+    TICK_HISTOGRAM.lock(|histogram| histogram.get().bins())
+}
+
+pub fn record_latency(ticks: u32) {
+    LATENCY_TICKS.store(ticks, Relaxed);
+}
+
+pub fn record_clock_offset(offset_ppb: i32) {
+    CLOCK_OFFSET_PPB.store(offset_ppb, Relaxed);
+}
+
+fn record_buffer_fill(fill: u32) {
+    BUFFER_FILL_MIN.fetch_min(fill, Relaxed);
+    BUFFER_FILL_MAX.fetch_max(fill, Relaxed);
+    BUFFER_FILL_PEAK.fetch_max(fill, Relaxed);
+}
+
+pub fn buffer_fill() -> u32 {
+    BUFFER_FILL.load(Relaxed)
+}
+
+pub fn underruns() -> u32 {
+    UNDERRUNS.load(Relaxed)
+}
+
+pub fn overruns() -> u32 {
+    OVERRUNS.load(Relaxed)
+}
+
+pub fn buffer_fill_peak() -> u32 {
+    BUFFER_FILL_PEAK.load(Relaxed)
+}
+
+/// Number of counters that are reported to the host tool.
+pub const COUNTER_COUNT: usize = 16;
+
+/// Counters for the host tool: packets, invalid packets, samples, dropped samples, underruns, overruns, concealed
+/// frames, stalls, peak buffer fill (blocks), latency (us), missed feedback deadlines, missed packets, and clipped
+/// samples at the DSP chain's input (left, right) and output (left, right).
+pub fn counters() -> [u32; COUNTER_COUNT] {
+    [
+        PACKETS_RECEIVED.load(Relaxed),
+        INVALID_PACKETS.load(Relaxed),
+        SAMPLES_RECEIVED.load(Relaxed),
+        SAMPLES_DROPPED.load(Relaxed),
+        UNDERRUNS.load(Relaxed),
+        OVERRUNS.load(Relaxed),
+        CONCEALED_FRAMES.load(Relaxed),
+        STALLS.load(Relaxed),
+        BUFFER_FILL_PEAK.load(Relaxed),
+        latency_us(),
+        MISSED_FEEDBACK.load(Relaxed),
+        MISSED_PACKETS.load(Relaxed),
+        CLIPPED_INPUT[0].load(Relaxed),
+        CLIPPED_INPUT[1].load(Relaxed),
+        CLIPPED_OUTPUT[0].load(Relaxed),
+        CLIPPED_OUTPUT[1].load(Relaxed),
+    ]
+}
+
+fn latency_us() -> u32 {
+    (LATENCY_TICKS.load(Relaxed) as u64 * 1_000_000 / TICK_HZ) as u32
+}
+
+/// The offset of the local clock against the host's, in parts per billion, once measured.
+pub fn clock_offset_ppb() -> Option<i32> {
+    Some(CLOCK_OFFSET_PPB.load(Relaxed)).filter(|&offset| offset != CLOCK_OFFSET_UNKNOWN)
+}
+
+// A sample block was handed to the output task.
+pub fn block_queued() {
+    let fill = BUFFER_FILL.fetch_add(1, Relaxed) + 1;
+    record_buffer_fill(fill);
+}
+
+// A sample block was consumed (or discarded) by the output task.
+pub fn block_dequeued() {
+    let fill = BUFFER_FILL.fetch_sub(1, Relaxed).saturating_sub(1);
+    record_buffer_fill(fill);
+}
+
+#[embassy_executor::task]
+pub async fn report_task() {
+    let mut ticker = Ticker::every(REPORT_PERIOD);
+
+    loop {
+        ticker.next().await;
+
+        info!(
+            "Packets: {} ({} invalid), samples: {} ({} dropped), underruns: {}, overruns: {}",
+            PACKETS_RECEIVED.load(Relaxed),
+            INVALID_PACKETS.load(Relaxed),
+            SAMPLES_RECEIVED.load(Relaxed),
+            SAMPLES_DROPPED.load(Relaxed),
+            UNDERRUNS.load(Relaxed),
+            OVERRUNS.load(Relaxed),
+        );
+
+        info!(
+            "Concealed frames: {}, stalls: {}, missed packets: {}, missed feedback deadlines: {}",
+            CONCEALED_FRAMES.load(Relaxed),
+            STALLS.load(Relaxed),
+            MISSED_PACKETS.load(Relaxed),
+            MISSED_FEEDBACK.load(Relaxed)
+        );
+        info!("Implausible feedback values: {}", IMPLAUSIBLE_FEEDBACK.load(Relaxed));
+
+        let clipped =
+            |counters: &[AtomicU32; INPUT_CHANNEL_COUNT]| counters.each_ref().map(|counter| counter.load(Relaxed));
+        info!(
+            "Clipped samples: {} at the input, {} at the output",
+            clipped(&CLIPPED_INPUT),
+            clipped(&CLIPPED_OUTPUT)
+        );
+
+        let fill_min = BUFFER_FILL_MIN.swap(u32::MAX, Relaxed);
+        let fill_max = BUFFER_FILL_MAX.swap(0, Relaxed);
+
+        if fill_min <= fill_max {
+            info!("Buffer fill: {} to {} blocks", fill_min, fill_max);
+        }
+
+        info!(
+            "Latency: {} us to DMA, plus up to {} us DMA buffer",
+            latency_us(),
+            latency::DMA_BUFFER_LATENCY_US
+        );
+
+        for (stage, profile) in profile::STAGES.iter().zip(profile::complete_period()) {
+            if profile.max_cycles > 0 {
+                info!(
+                    "Profile {}: {} / {} / {} ns (min / avg / max)",
+                    stage,
+                    profile::cycles_to_ns(profile.min_cycles),
+                    profile::cycles_to_ns(profile.avg_cycles),
+                    profile::cycles_to_ns(profile.max_cycles)
+                );
+            }
+        }
+
+        if let Some(offset_ppb) = clock_offset_ppb() {
+            info!("Clock offset against USB SOF: {=f32} ppm", offset_ppb as f32 / 1000.0);
+        }
+
+        FEEDBACK_HISTORY.lock(|history| {
+            let history = history.borrow();
+            let mut values = [0u32; FEEDBACK_HISTORY_LENGTH];
+
+            for (value, history_value) in values.iter_mut().zip(history.oldest_ordered()) {
+                *value = *history_value;
+            }
+
+            info!("Feedback history: {}", &values[..history.len()]);
+        });
+
+        info!("Feedback period deviation histogram: {}", tick_histogram());
+    }
+}
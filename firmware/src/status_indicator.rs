@@ -0,0 +1,129 @@
+// Device state indication on an addressable RGB LED, in addition to the plain status LED.
+//
+// The state is derived from the flags that the USB, output, and control tasks maintain, and shown as a color that is
+// either steady or blinking. Before entering the bootloader, the LED is set to the bootloader's color, which it keeps
+// through the reset, while the ROM bootloader runs.
+use core::sync::atomic::Ordering::Relaxed;
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Ticker};
+use embedded_hal_async::spi::SpiBus;
+
+use crate::ws2812::{Rgb, Ws2812};
+use crate::*;
+
+// Period for re-evaluating the device state.
+const UPDATE_PERIOD: Duration = Duration::from_millis(25);
+
+// Blink period, in update periods.
+const BLINK_PERIOD: u32 = 20;
+
+// Limits the LED's current, and avoids glare.
+const BRIGHTNESS: u8 = 64;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum DeviceState {
+    /// Not configured by a host.
+    Disconnected,
+    Suspended,
+    /// Configured, but no audio stream is open.
+    Enumerated,
+    Streaming,
+    /// Streaming with reduced dynamic range (see `night_mode`).
+    NightMode,
+    /// Muted locally or by the host.
+    Muted,
+    /// Outputs are inhibited after a failed self-test or amplifier fault.
+    Fault,
+    /// About to enter the ROM DFU bootloader.
+    Bootloader,
+}
+
+impl DeviceState {
+    /// Evaluate the current device state, with faults and the bootloader taking precedence.
+    pub fn current() -> Self {
+        if bootloader::is_pending() {
+            DeviceState::Bootloader
+        } else if OUTPUT_INHIBITED.load(Relaxed) {
+            DeviceState::Fault
+        } else if !USB_IS_CONFIGURED.load(Relaxed) {
+            DeviceState::Disconnected
+        } else if USB_IS_SUSPENDED.load(Relaxed) {
+            DeviceState::Suspended
+        } else if trim::is_muted() {
+            DeviceState::Muted
+        } else if USB_IS_STREAMING.load(Relaxed) && night_mode::is_enabled() {
+            DeviceState::NightMode
+        } else if USB_IS_STREAMING.load(Relaxed) {
+            DeviceState::Streaming
+        } else {
+            DeviceState::Enumerated
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceState::Disconnected => "Disconnected",
+            DeviceState::Suspended => "Suspended",
+            DeviceState::Enumerated => "Idle",
+            DeviceState::Streaming => "Streaming",
+            DeviceState::NightMode => "Night mode",
+            DeviceState::Muted => "Muted",
+            DeviceState::Fault => "Fault",
+            DeviceState::Bootloader => "Bootloader",
+        }
+    }
+
+    // Color, and whether it blinks.
+    fn pattern(self) -> (Rgb, bool) {
+        match self {
+            DeviceState::Disconnected => (Rgb::OFF, false),
+            DeviceState::Suspended => (Rgb::new(0, 0, 255).scaled(32), false),
+            DeviceState::Enumerated => (Rgb::new(0, 0, 255), false),
+            DeviceState::Streaming => (Rgb::new(0, 255, 0), false),
+            DeviceState::NightMode => (Rgb::new(0, 255, 0).scaled(32), false),
+            DeviceState::Muted => (Rgb::new(255, 128, 0), false),
+            DeviceState::Fault => (Rgb::new(255, 0, 0), true),
+            DeviceState::Bootloader => (Rgb::new(255, 0, 255), false),
+        }
+    }
+
+    fn color(self, phase: u32) -> Rgb {
+        match self.pattern() {
+            (_, true) if phase >= BLINK_PERIOD / 2 => Rgb::OFF,
+            (color, _) => color.scaled(BRIGHTNESS),
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn indicator_task(spi: board::StatusIndicatorSpi) {
+    run(Ws2812::new(spi)).await
+}
+
+async fn run<SPI: SpiBus>(mut led: Ws2812<SPI>) -> ! {
+    let mut ticker = Ticker::every(UPDATE_PERIOD);
+    let mut state = None;
+    let mut color = None;
+    let mut phase = 0;
+
+    loop {
+        let new_state = DeviceState::current();
+        if state != Some(new_state) {
+            info!("Device state: {}", new_state);
+            state = Some(new_state);
+            phase = 0;
+        }
+
+        // Only changes are sent, which leaves the bus idle most of the time.
+        let new_color = new_state.color(phase);
+        if color != Some(new_color) {
+            if led.write(&[new_color]).await.is_err() {
+                warn!("Failed to update the status indicator");
+            }
+            color = Some(new_color);
+        }
+
+        phase = (phase + 1) % BLINK_PERIOD;
+        ticker.next().await;
+    }
+}
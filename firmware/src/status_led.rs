@@ -0,0 +1,98 @@
+use defmt::Format;
+use embassy_futures::select::{select, Either};
+use embassy_time::Timer;
+
+use crate::*;
+
+// Blink period for error reporting.
+const ERROR_BLINK_PERIOD_MS: u64 = 200;
+
+// The LED goes dark this long on clipping, which repeats while clipping continues.
+const CLIP_FLASH_MS: u64 = 50;
+
+// Blink codes of failed self-test checks: a pulse per count, and a pause after each code.
+const CODE_PULSE_MS: u64 = 250;
+const CODE_GAP_MS: u64 = 250;
+const CODE_PAUSE_MS: u64 = 1500;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum LedStatus {
+    Ok,
+    Error,
+    /// Blink codes of the failed self-test checks (see `self_test`).
+    SelfTestFailed,
+}
+
+// Wait for a duration, returning a status change that interrupted it.
+async fn wait(duration_ms: u64) -> Option<LedStatus> {
+    match select(Timer::after_millis(duration_ms), STATUS_LED_SIGNAL.wait()).await {
+        Either::First(()) => None,
+        Either::Second(new_status) => Some(new_status),
+    }
+}
+
+// Shows the device status on a single LED: steady on when ok, briefly off on clipping, fast blinking on errors, and the
+// blink codes of failed self-test checks, in turn. Returns to steady on, once all checks recovered.
+#[embassy_executor::task]
+pub async fn status_task(mut led: board::StatusLed, active_low: bool) {
+    let mut set_led = move |on: bool| led.set_level((on != active_low).into());
+    let mut status = LedStatus::Ok;
+
+    loop {
+        match status {
+            LedStatus::Ok => {
+                set_led(true);
+
+                match select(STATUS_LED_SIGNAL.wait(), CLIP_SIGNAL.wait()).await {
+                    Either::First(new_status) => status = new_status,
+                    Either::Second(()) => {
+                        set_led(false);
+                        Timer::after_millis(CLIP_FLASH_MS).await;
+                        set_led(true);
+                        Timer::after_millis(CLIP_FLASH_MS).await;
+                    }
+                }
+            }
+            LedStatus::Error => {
+                set_led(true);
+                Timer::after_millis(ERROR_BLINK_PERIOD_MS / 2).await;
+                set_led(false);
+
+                if let Some(new_status) = wait(ERROR_BLINK_PERIOD_MS / 2).await {
+                    status = new_status;
+                }
+            }
+            LedStatus::SelfTestFailed => {
+                let mut failures = self_test::failures().peekable();
+                if failures.peek().is_none() {
+                    status = LedStatus::Ok;
+                    continue;
+                }
+
+                'codes: for check in failures {
+                    set_led(false);
+                    if let Some(new_status) = wait(CODE_PAUSE_MS).await {
+                        status = new_status;
+                        break 'codes;
+                    }
+
+                    for pulse in 0..check.blink_count() {
+                        if pulse > 0 {
+                            set_led(false);
+                            if let Some(new_status) = wait(CODE_GAP_MS).await {
+                                status = new_status;
+                                break 'codes;
+                            }
+                        }
+
+                        set_led(true);
+                        if let Some(new_status) = wait(CODE_PULSE_MS).await {
+                            status = new_status;
+                            break 'codes;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// Driver for TI TAS2780/TAS25xx smart amplifiers, controlled via I2C and fed with TDM/I2S audio.
+use blus_core::gain::db_to_linear;
+use defmt::{debug, Format};
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{I2c, Operation};
+
+// Register addresses (book 0).
+mod reg {
+    pub const PAGE: u8 = 0x00;
+    pub const BOOK: u8 = 0x7f;
+    pub const SW_RESET: u8 = 0x01;
+    pub const MODE_CTRL: u8 = 0x02;
+    pub const CHNL_0: u8 = 0x03;
+    pub const TDM_CFG0: u8 = 0x08;
+    pub const TDM_CFG2: u8 = 0x0a;
+    pub const TDM_CFG3: u8 = 0x0c;
+    pub const INT_LTCH0: u8 = 0x49;
+    pub const INT_LTCH1: u8 = 0x4a;
+    pub const INT_CLK_CFG: u8 = 0x5c;
+    pub const TEMP: u8 = 0x58;
+
+    // Digital volume control, located on page 2 (four bytes, big endian).
+    pub const DVC_PAGE: u8 = 0x02;
+    pub const DVC: u8 = 0x0c;
+}
+
+// Operating modes, written to the `MODE_CTRL` register.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Mode {
+    Active = 0b00,
+    Mute = 0b01,
+    SoftwareShutdown = 0b10,
+}
+
+/// Selects the audio slot that an amplifier plays back.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Slot {
+    Left,
+    Right,
+}
+
+// The digital volume control uses a 1.31 format, where 0x4000_0000 equals 0 dB (half scale).
+const DVC_UNITY: f32 = (1 << 30) as f32;
+
+// Offset of the die temperature readout.
+const TEMP_OFFSET_C: i16 = 93;
+
+// Maximum supported digital gain.
+const DVC_MAX_DB: f32 = 6.0;
+
+/// Latched amplifier fault flags.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Faults(u16);
+
+impl Faults {
+    const OVER_TEMPERATURE: u16 = 1 << 0;
+    const OVER_CURRENT: u16 = 1 << 1;
+    const TDM_CLOCK_ERROR: u16 = 1 << 2;
+    const DC_DETECT: u16 = 1 << 3;
+    const BROWN_OUT: u16 = 1 << 10;
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn over_temperature(&self) -> bool {
+        self.0 & Self::OVER_TEMPERATURE != 0
+    }
+
+    pub fn over_current(&self) -> bool {
+        self.0 & Self::OVER_CURRENT != 0
+    }
+
+    pub fn tdm_clock_error(&self) -> bool {
+        self.0 & Self::TDM_CLOCK_ERROR != 0
+    }
+
+    pub fn dc_detect(&self) -> bool {
+        self.0 & Self::DC_DETECT != 0
+    }
+
+    pub fn brown_out(&self) -> bool {
+        self.0 & Self::BROWN_OUT != 0
+    }
+
+    /// Whether the amplifier shut down its output stage, which requires re-initialization.
+    pub fn is_shutdown(&self) -> bool {
+        self.0 & (Self::OVER_TEMPERATURE | Self::OVER_CURRENT | Self::DC_DETECT | Self::BROWN_OUT) != 0
+    }
+}
+
+impl Format for Faults {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{:#06x}:", self.0);
+
+        for (active, name) in [
+            (self.over_temperature(), "over-temperature"),
+            (self.over_current(), "over-current"),
+            (self.tdm_clock_error(), "TDM clock error"),
+            (self.dc_detect(), "DC detected"),
+            (self.brown_out(), "brown-out"),
+        ] {
+            if active {
+                defmt::write!(f, " {}", name);
+            }
+        }
+    }
+}
+
+pub struct Tas2780<I2C> {
+    i2c: I2C,
+    address: u8,
+    book: Option<u8>,
+    page: Option<u8>,
+}
+
+impl<I2C: I2c> Tas2780<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            book: None,
+            page: None,
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    // Books are selected from page 0 of any book.
+    async fn select_book_page(&mut self, book: u8, page: u8) -> Result<(), I2C::Error> {
+        if self.book != Some(book) {
+            self.i2c.write(self.address, &[reg::PAGE, 0]).await?;
+            self.page = Some(0);
+            self.i2c.write(self.address, &[reg::BOOK, book]).await?;
+            self.book = Some(book);
+        }
+
+        if self.page != Some(page) {
+            self.i2c.write(self.address, &[reg::PAGE, page]).await?;
+            self.page = Some(page);
+        }
+
+        Ok(())
+    }
+
+    async fn select_page(&mut self, page: u8) -> Result<(), I2C::Error> {
+        self.select_book_page(0, page).await
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), I2C::Error> {
+        self.select_page(0).await?;
+        self.i2c.write(self.address, &[register, value]).await
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, I2C::Error> {
+        let mut value = [0u8];
+
+        self.select_page(0).await?;
+        self.i2c.write_read(self.address, &[register], &mut value).await?;
+
+        Ok(value[0])
+    }
+
+    /// Reset the amplifier and run the initialization sequence.
+    ///
+    /// The amplifier is left in software shutdown mode.
+    pub async fn init(&mut self, slot: Slot) -> Result<(), I2C::Error> {
+        // Software reset, which returns to page 0.
+        self.i2c.write(self.address, &[reg::PAGE, 0]).await?;
+        self.i2c.write(self.address, &[reg::BOOK, 0]).await?;
+        self.i2c.write(self.address, &[reg::SW_RESET, 0x01]).await?;
+        self.book = Some(0);
+        self.page = Some(0);
+        Timer::after_millis(1).await;
+
+        self.set_mode(Mode::SoftwareShutdown).await?;
+
+        // I2S format, 48 kHz, auto-detected clock ratio.
+        self.write_register(reg::TDM_CFG0, 0x09).await?;
+
+        // 32 bit slot length, 24 bit word length.
+        let slot_config = match slot {
+            Slot::Left => 0b01,
+            Slot::Right => 0b10,
+        };
+        self.write_register(reg::TDM_CFG2, (slot_config << 4) | 0x0a).await?;
+        self.write_register(reg::TDM_CFG3, 0x10).await?;
+
+        // Amplifier level 16 dBV, lowest analog gain for best noise performance.
+        self.write_register(reg::CHNL_0, 0x01 << 1).await?;
+
+        // Report clock errors, but do not clear them automatically.
+        self.write_register(reg::INT_CLK_CFG, 0x19).await?;
+
+        debug!("TAS2780 at {:#x} initialized for slot {}", self.address, slot);
+
+        Ok(())
+    }
+
+    pub async fn set_mode(&mut self, mode: Mode) -> Result<(), I2C::Error> {
+        let value = self.read_register(reg::MODE_CTRL).await?;
+        self.write_register(reg::MODE_CTRL, (value & !0b11) | mode as u8).await
+    }
+
+    pub async fn set_mute(&mut self, mute: bool) -> Result<(), I2C::Error> {
+        let mode = if mute { Mode::Mute } else { Mode::Active };
+        self.set_mode(mode).await
+    }
+
+    pub async fn set_standby(&mut self, standby: bool) -> Result<(), I2C::Error> {
+        let mode = if standby { Mode::SoftwareShutdown } else { Mode::Active };
+        self.set_mode(mode).await
+    }
+
+    /// Set the digital volume in dB, clamped to the supported range.
+    pub async fn set_volume_db(&mut self, db: f32) -> Result<(), I2C::Error> {
+        let linear = db_to_linear(db.min(DVC_MAX_DB));
+        let value = (linear * DVC_UNITY) as u32;
+        let bytes = value.to_be_bytes();
+
+        self.select_page(reg::DVC_PAGE).await?;
+        self.i2c
+            .write(self.address, &[reg::DVC, bytes[0], bytes[1], bytes[2], bytes[3]])
+            .await
+    }
+
+    /// Write consecutive registers in one burst transfer, e.g. a block of DSP coefficients.
+    ///
+    /// The transfer uses DMA, so the executor keeps running other tasks meanwhile.
+    pub async fn write_block(&mut self, book: u8, page: u8, register: u8, data: &[u8]) -> Result<(), I2C::Error> {
+        self.select_book_page(book, page).await?;
+        self.i2c
+            .transaction(
+                self.address,
+                &mut [Operation::Write(&[register]), Operation::Write(data)],
+            )
+            .await
+    }
+
+    /// Read (and thereby clear) the latched fault flags.
+    pub async fn read_faults(&mut self) -> Result<Faults, I2C::Error> {
+        let low = self.read_register(reg::INT_LTCH0).await?;
+        let high = self.read_register(reg::INT_LTCH1).await?;
+
+        Ok(Faults(u16::from_le_bytes([low, high])))
+    }
+
+    /// Read the die temperature in degrees Celsius. Only valid, while the amplifier is active.
+    pub async fn read_temperature(&mut self) -> Result<i16, I2C::Error> {
+        let value = self.read_register(reg::TEMP).await?;
+        Ok(value as i16 - TEMP_OFFSET_C)
+    }
+}
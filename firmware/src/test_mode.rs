@@ -0,0 +1,151 @@
+// USB electrical test modes (USB 2.0, 7.1.20 and 9.4.9), for compliance testing of the high-speed board.
+//
+// The host selects a test mode with SET_FEATURE(TEST_MODE), whose selector is the upper byte of `wIndex`. The USB stack
+// rejects standard device requests that it does not know, before class handlers see them, so that the driver is wrapped
+// instead: its control pipe accepts the request, and the OTG core enters the test mode after the status stage. The
+// device stays in the test mode until it is power-cycled, as required.
+use defmt::{info, Format};
+use embassy_time::Timer;
+use embassy_usb::control::{Recipient, Request, RequestType};
+use embassy_usb::driver::{ControlPipe, Driver, EndpointAllocError, EndpointError, EndpointType};
+
+// The OTG device control register (DCTL), whose test control field (TCTL) selects the test mode.
+const OTG_DCTL: *mut u32 = (0x4004_0000 + 0x804) as *mut u32;
+const TCTL_SHIFT: u32 = 4;
+const TCTL_MASK: u32 = 0b111 << TCTL_SHIFT;
+
+// The TEST_MODE feature selector.
+const FEATURE_TEST_MODE: u16 = 2;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum TestMode {
+    J = 1,
+    K = 2,
+    Se0Nak = 3,
+    Packet = 4,
+}
+
+impl TestMode {
+    fn from_selector(selector: u8) -> Option<Self> {
+        match selector {
+            1 => Some(Self::J),
+            2 => Some(Self::K),
+            3 => Some(Self::Se0Nak),
+            4 => Some(Self::Packet),
+            _ => None,
+        }
+    }
+
+    // The test mode of a SET_FEATURE(TEST_MODE) request.
+    fn from_request(req: &Request) -> Option<Self> {
+        let is_set_test_mode = req.request_type == RequestType::Standard
+            && req.recipient == Recipient::Device
+            && req.request == Request::SET_FEATURE
+            && req.value == FEATURE_TEST_MODE
+            && req.index & 0xff == 0;
+
+        if is_set_test_mode {
+            Self::from_selector((req.index >> 8) as u8)
+        } else {
+            None
+        }
+    }
+
+    fn enter(self) {
+        info!("Enter USB test mode {}", self);
+
+        // SAFETY: Only the test control field is changed, which the driver does not use.
+        unsafe {
+            let dctl = OTG_DCTL.read_volatile();
+            OTG_DCTL.write_volatile(dctl & !TCTL_MASK | (self as u32) << TCTL_SHIFT);
+        }
+    }
+}
+
+/// A USB driver, whose control pipe handles test mode requests.
+pub struct TestModeDriver<D> {
+    inner: D,
+}
+
+impl<D> TestModeDriver<D> {
+    pub fn new(inner: D) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'d, D: Driver<'d>> Driver<'d> for TestModeDriver<D> {
+    type EndpointOut = D::EndpointOut;
+    type EndpointIn = D::EndpointIn;
+    type ControlPipe = TestModeControlPipe<D::ControlPipe>;
+    type Bus = D::Bus;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.inner.alloc_endpoint_out(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.inner.alloc_endpoint_in(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        let (bus, control_pipe) = self.inner.start(control_max_packet_size);
+        (bus, TestModeControlPipe { inner: control_pipe })
+    }
+}
+
+/// A control pipe, which handles test mode requests, and passes all other requests to the USB stack.
+pub struct TestModeControlPipe<C> {
+    inner: C,
+}
+
+impl<C: ControlPipe> ControlPipe for TestModeControlPipe<C> {
+    fn max_packet_size(&self) -> usize {
+        self.inner.max_packet_size()
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        loop {
+            let setup = self.inner.setup().await;
+
+            let Some(test_mode) = TestMode::from_request(&Request::parse(&setup)) else {
+                return setup;
+            };
+
+            // The test mode must be entered within 3 ms after the status stage.
+            self.inner.accept().await;
+            Timer::after_micros(500).await;
+            test_mode.enter();
+        }
+    }
+
+    async fn data_out(&mut self, buf: &mut [u8], first: bool, last: bool) -> Result<usize, EndpointError> {
+        self.inner.data_out(buf, first, last).await
+    }
+
+    async fn data_in(&mut self, data: &[u8], first: bool, last: bool) -> Result<(), EndpointError> {
+        self.inner.data_in(data, first, last).await
+    }
+
+    async fn accept(&mut self) {
+        self.inner.accept().await
+    }
+
+    async fn reject(&mut self) {
+        self.inner.reject().await
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        self.inner.accept_set_address(addr).await
+    }
+}
@@ -0,0 +1,76 @@
+// Thermal foldback policy: progressively reduces gain above a temperature threshold, and mutes at a critical
+// temperature. Both stages release with hysteresis.
+use defmt::Format;
+
+// Temperature, above which gain is reduced.
+const FOLDBACK_START_C: i16 = 100;
+
+// Gain reduction per degree above `FOLDBACK_START_C`, up to `FOLDBACK_MAX_DB`.
+const FOLDBACK_DB_PER_C: f32 = 0.5;
+const FOLDBACK_MAX_DB: f32 = 18.0;
+
+// Temperature, at which output is muted.
+const CRITICAL_C: i16 = 135;
+
+// Temperature drop required for releasing foldback or mute.
+const HYSTERESIS_C: i16 = 10;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Foldback {
+    None,
+    // Gain reduction in dB.
+    Attenuate(f32),
+    Mute,
+}
+
+pub struct ThermalFoldback {
+    attenuation_db: f32,
+    muted: bool,
+}
+
+fn attenuation_db(temperature_c: i16) -> f32 {
+    let excess = temperature_c.saturating_sub(FOLDBACK_START_C).max(0);
+    (excess as f32 * FOLDBACK_DB_PER_C).min(FOLDBACK_MAX_DB)
+}
+
+impl ThermalFoldback {
+    pub const fn new() -> Self {
+        Self {
+            attenuation_db: 0.0,
+            muted: false,
+        }
+    }
+
+    /// Update the policy with a new temperature reading, and return the resulting foldback.
+    pub fn update(&mut self, temperature_c: i16) -> Foldback {
+        if temperature_c >= CRITICAL_C {
+            self.muted = true;
+        } else if temperature_c <= CRITICAL_C - HYSTERESIS_C {
+            self.muted = false;
+        }
+
+        // Attenuation rises immediately, but only falls once the temperature dropped by the hysteresis.
+        let attack = attenuation_db(temperature_c);
+        let release = attenuation_db(temperature_c.saturating_add(HYSTERESIS_C));
+
+        if attack > self.attenuation_db {
+            self.attenuation_db = attack;
+        } else if release < self.attenuation_db {
+            self.attenuation_db = release;
+        }
+
+        if self.muted {
+            Foldback::Mute
+        } else if self.attenuation_db > 0.0 {
+            Foldback::Attenuate(self.attenuation_db)
+        } else {
+            Foldback::None
+        }
+    }
+}
+
+impl Default for ThermalFoldback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,199 @@
+// Per-channel trim and balance, layered on top of the host's master volume.
+//
+// Trim and balance are part of the persistent settings. The resulting per-channel volume is signaled to the amplifiers
+// or codec, whenever either the master volume or the settings change. The host's volume is mapped along the volume
+// curve (see `blus_core::volume`) into the master volume.
+use blus_core::volume::{self, VolumeCurve};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use defmt::{info, Format};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_usb::class::uac1::speaker::Volume;
+
+use crate::*;
+
+pub const STEPS_PER_DB: f32 = 2.0;
+
+// Trim range of +-12 dB.
+pub const TRIM_MAX: i8 = 24;
+
+// Balance range of +-40 dB. At the limits, the attenuated channel is muted.
+pub const BALANCE_MAX: i8 = 80;
+
+// Range of the master volume, as advertised to the host.
+const MASTER_VOLUME_MIN_DB: f32 = -100.0;
+const MASTER_VOLUME_MAX_DB: f32 = 0.0;
+
+#[derive(Clone, Copy, Format)]
+pub struct OutOfRange;
+
+// Local mute, e.g. by a button, on top of the host's mute.
+static LOCAL_MUTE: AtomicBool = AtomicBool::new(false);
+
+// The host's volume, before the volume curve.
+static HOST_VOLUME: Mutex<CriticalSectionRawMutex, Cell<(Volume, Volume)>> =
+    Mutex::new(Cell::new((Volume::Muted, Volume::Muted)));
+
+static MASTER_VOLUME: Mutex<CriticalSectionRawMutex, Cell<(Volume, Volume)>> =
+    Mutex::new(Cell::new((Volume::Muted, Volume::Muted)));
+
+// Layer trim and attenuation (in steps), and the loudness compensation's headroom (in dB) onto a volume.
+fn layer(volume: Volume, trim: i8, attenuation: i8, headroom_db: f32) -> Volume {
+    match volume {
+        Volume::Muted => Volume::Muted,
+        _ if LOCAL_MUTE.load(Relaxed) => Volume::Muted,
+        _ if attenuation >= BALANCE_MAX => Volume::Muted,
+        Volume::DeciBel(db) => Volume::DeciBel(db + headroom_db + (trim as f32 - attenuation as f32) / STEPS_PER_DB),
+    }
+}
+
+/// Signal the master volume with trim, balance, and the loudness compensation's headroom applied, within the power
+/// source's (and battery's) limit.
+pub fn update() {
+    let master = MASTER_VOLUME.lock(|volume| volume.get());
+    let settings = settings::get();
+    let headroom_db = loudness::headroom_db();
+
+    let left = layer(master.0, settings.trim[0], settings.balance.max(0), headroom_db);
+    let right = layer(
+        master.1,
+        settings.trim[1],
+        settings.balance.saturating_neg().max(0),
+        headroom_db,
+    );
+
+    let (left, right) = (power_source::limit(left), power_source::limit(right));
+    #[cfg(feature = "battery-monitor")]
+    let (left, right) = (battery::limit(left), battery::limit(right));
+
+    VOLUME_SIGNAL.signal((left, right));
+}
+
+// Map a host's volume along the volume curve.
+fn apply_curve(volume: Volume, curve: VolumeCurve) -> Volume {
+    match volume {
+        Volume::Muted => Volume::Muted,
+        Volume::DeciBel(db) => {
+            Volume::DeciBel(curve.gain_db(volume::position(db, MASTER_VOLUME_MIN_DB, MASTER_VOLUME_MAX_DB)))
+        }
+    }
+}
+
+/// Set the master volume from the host's volume, along the volume curve.
+pub fn set_host_volume(volume: (Volume, Volume)) {
+    HOST_VOLUME.lock(|host| host.set(volume));
+
+    let curve = settings::get().volume_curve();
+    set_master_volume((apply_curve(volume.0, curve), apply_curve(volume.1, curve)));
+}
+
+/// Set the volume curve, which applies to the host's volume. It is stored in the settings.
+pub fn set_volume_curve(curve: VolumeCurve) -> Result<(), OutOfRange> {
+    if !curve.is_valid() {
+        return Err(OutOfRange);
+    }
+
+    info!(
+        "Volume curve: {} dB to {} dB in {} dB steps",
+        curve.min_db,
+        curve.max_db,
+        curve.step as f32 / volume::STEPS_PER_DB
+    );
+    settings::modify(|settings| _ = settings.set_volume_curve(curve));
+    set_host_volume(HOST_VOLUME.lock(|host| host.get()));
+    Ok(())
+}
+
+/// Set the master volume, e.g. as relayed by a stereo link's primary.
+pub fn set_master_volume(volume: (Volume, Volume)) {
+    MASTER_VOLUME.lock(|master| master.set(volume));
+    update();
+    loudness::volume_changed();
+}
+
+/// Set the master volume of both channels locally (e.g. by an external controller), until the host sets it again.
+pub fn set_local_master_volume(volume: Volume) {
+    let volume = match volume {
+        Volume::DeciBel(db) => Volume::DeciBel(db.clamp(MASTER_VOLUME_MIN_DB, MASTER_VOLUME_MAX_DB)),
+        Volume::Muted => Volume::Muted,
+    };
+    set_master_volume((volume, volume));
+}
+
+// Whether a volume is at the lowest or highest master volume.
+#[cfg(feature = "chimes")]
+fn is_at_limit(volume: Volume) -> bool {
+    match volume {
+        Volume::Muted => false,
+        Volume::DeciBel(db) => db == MASTER_VOLUME_MIN_DB || db == MASTER_VOLUME_MAX_DB,
+    }
+}
+
+/// Adjust the master volume locally (e.g. by a rotary encoder), until the host sets it again. With the `chimes`
+/// feature, a chime plays, when the adjustment reaches the lowest or highest volume.
+pub fn adjust_master_volume(step_db: f32) {
+    let adjust = |volume| match volume {
+        Volume::Muted => Volume::Muted,
+        Volume::DeciBel(db) => Volume::DeciBel((db + step_db).clamp(MASTER_VOLUME_MIN_DB, MASTER_VOLUME_MAX_DB)),
+    };
+
+    MASTER_VOLUME.lock(|master| {
+        let (left, right) = master.get();
+        master.set((adjust(left), adjust(right)));
+    });
+    update();
+    loudness::volume_changed();
+
+    #[cfg(feature = "chimes")]
+    {
+        let (left, right) = master_volume();
+        if is_at_limit(left) || is_at_limit(right) {
+            chime::play(chime::Alert::VolumeLimit);
+        }
+    }
+}
+
+/// Toggle the local mute.
+pub fn toggle_mute() {
+    let muted = !LOCAL_MUTE.fetch_xor(true, Relaxed);
+    info!("Local mute: {}", muted);
+    update();
+}
+
+/// The master volume, as requested by the host.
+pub fn master_volume() -> (Volume, Volume) {
+    MASTER_VOLUME.lock(|volume| volume.get())
+}
+
+/// Whether the output is muted, locally or by the host.
+pub fn is_muted() -> bool {
+    let master = MASTER_VOLUME.lock(|volume| volume.get());
+    LOCAL_MUTE.load(Relaxed) || matches!(master, (Volume::Muted, Volume::Muted))
+}
+
+/// Set the trim of a channel in 0.5 dB steps.
+pub fn set_trim(channel: usize, trim: i8) -> Result<(), OutOfRange> {
+    if channel >= INPUT_CHANNEL_COUNT || !(-TRIM_MAX..=TRIM_MAX).contains(&trim) {
+        return Err(OutOfRange);
+    }
+
+    info!("Trim of channel {}: {} dB", channel, trim as f32 / STEPS_PER_DB);
+    settings::modify(|settings| settings.trim[channel] = trim);
+    update();
+
+    Ok(())
+}
+
+/// Set the balance in 0.5 dB steps. Positive values attenuate the left channel, negative values the right channel.
+pub fn set_balance(balance: i8) -> Result<(), OutOfRange> {
+    if !(-BALANCE_MAX..=BALANCE_MAX).contains(&balance) {
+        return Err(OutOfRange);
+    }
+
+    info!("Balance: {} dB", balance as f32 / STEPS_PER_DB);
+    settings::modify(|settings| settings.balance = balance);
+    update();
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+// Control over USART2 (PA2 TX, PA3 RX) by an external front-end, e.g. a Bluetooth bridge or a home-automation
+// controller.
+//
+// The front-end sends the vendor interface's requests (see `vendor`), framed as in `blus_core::serial`, and receives
+// one response per request (see `blus_core::protocol`). Thereby, it can e.g. set the volume, select presets and inputs,
+// and read the device status, just like the host tool.
+use blus_core::protocol::{self, SERIAL_ACCEPTED, SERIAL_READ, SERIAL_REJECTED};
+use blus_core::serial::{self, Decoder};
+use defmt::{info, warn};
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{self, RingBufferedUartRx, UartTx};
+use embassy_stm32::{bind_interrupts, peripherals};
+
+use crate::vendor::{self, VendorRequest};
+use crate::*;
+
+bind_interrupts!(pub struct ControlIrqs {
+    USART2 => usart::InterruptHandler<peripherals::USART2>;
+});
+
+pub const BAUD_RATE: u32 = 115_200;
+
+// Requests carry their value, followed by at most a control transfer's data. Responses carry a status byte instead.
+const MAX_REQUEST_SIZE: usize = protocol::SERIAL_VALUE_SIZE + USB_CONTROL_BUF_SIZE;
+const MAX_RESPONSE_SIZE: usize = serial::frame_size(1 + USB_CONTROL_BUF_SIZE);
+
+// Request codes must not overlap the read flag (the last request has the highest code).
+static_assertions::const_assert!(VendorRequest::SetVolume as u8 & SERIAL_READ == 0);
+
+pub fn uart_config() -> usart::Config {
+    let mut config = usart::Config::default();
+    config.baudrate = BAUD_RATE;
+
+    config
+}
+
+// Handle a request, and encode its response. Returns the response's length.
+fn respond(kind: u8, sequence: u8, payload: &[u8], response: &mut [u8; MAX_RESPONSE_SIZE]) -> Option<usize> {
+    let mut data = [0u8; 1 + USB_CONTROL_BUF_SIZE];
+
+    let length = match protocol::parse_serial_request(kind, payload) {
+        Some(request) if request.read => {
+            match vendor::read(VendorRequest::from_u8(request.request), request.value, &mut data[1..]) {
+                Some(length) => {
+                    data[0] = SERIAL_ACCEPTED;
+                    1 + length
+                }
+                None => {
+                    data[0] = SERIAL_REJECTED;
+                    1
+                }
+            }
+        }
+        Some(request) => {
+            let accepted = vendor::write(VendorRequest::from_u8(request.request), request.value, request.data);
+            data[0] = if accepted { SERIAL_ACCEPTED } else { SERIAL_REJECTED };
+            1
+        }
+        None => {
+            data[0] = SERIAL_REJECTED;
+            1
+        }
+    };
+
+    serial::encode(kind, sequence, &data[..length], response)
+}
+
+// Receives requests from the front-end, and answers each of them.
+#[embassy_executor::task]
+pub async fn control_task(mut tx: UartTx<'static, Async>, mut rx: RingBufferedUartRx<'static>) {
+    info!("UART control at {} baud", BAUD_RATE);
+
+    let mut decoder = Decoder::<MAX_REQUEST_SIZE>::new();
+    let mut buffer = [0u8; 32];
+    let mut response = [0u8; MAX_RESPONSE_SIZE];
+
+    loop {
+        let length = match rx.read(&mut buffer).await {
+            Ok(length) => length,
+            Err(e) => {
+                warn!("Control receive error: {}", e);
+                decoder.reset();
+                continue;
+            }
+        };
+
+        for &byte in &buffer[..length] {
+            let frame = match decoder.push(byte) {
+                None => continue,
+                Some(Ok(frame)) => frame,
+                Some(Err(_)) => {
+                    log_debug!("Corrupt control frame");
+                    continue;
+                }
+            };
+
+            let Some(length) = respond(frame.kind, frame.sequence, frame.payload, &mut response) else {
+                continue;
+            };
+            if tx.write(&response[..length]).await.is_err() {
+                warn!("Control transmit error");
+            }
+        }
+    }
+}
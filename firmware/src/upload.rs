@@ -0,0 +1,160 @@
+// Uploads of coefficient blobs into the external flash's coefficient partition, via vendor requests (see `vendor`).
+//
+// Control transfers carry at most `USB_CONTROL_BUF_SIZE` byte, so that the blob is framed into chunks, each preceded
+// by its 32 bit offset. Flash operations take long, so requests only queue a command for the upload task, and the host
+// polls the status before sending the next one. The blob header is written last, so that an interrupted upload leaves
+// no valid blob behind. Uploaded coefficients are loaded at the next boot.
+use blus_core::protocol::{self, OFFSET_SIZE};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering::Relaxed};
+use defmt::{info, warn, Format};
+use heapless::Vec;
+
+use crate::partition::{self, PartitionError, BLOB_HEADER_SIZE, COEFFICIENTS, COEFFICIENTS_MAGIC};
+use crate::*;
+
+/// Maximum data length of a chunk.
+pub const MAX_CHUNK_SIZE: usize = USB_CONTROL_BUF_SIZE - OFFSET_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum Status {
+    Idle = 0,
+    /// A command is being executed.
+    Busy = 1,
+    /// The partition is erased, and accepts chunks.
+    Receiving = 2,
+    /// The blob was written and verified.
+    Done = 3,
+    Failed = 4,
+}
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum UploadError {
+    /// The firmware has no external flash support.
+    Unavailable,
+    /// The previous command was not executed yet.
+    Busy,
+    /// The command does not fit the upload's state.
+    InvalidState,
+    /// The blob or chunk does not fit the partition, or the blob's length is not a multiple of four.
+    InvalidLength,
+}
+
+pub enum Command {
+    Begin { length: u32, crc: u32 },
+    Write { offset: u32, data: Vec<u8, MAX_CHUNK_SIZE> },
+    Finish,
+}
+
+static STATUS: AtomicU8 = AtomicU8::new(Status::Idle as u8);
+
+// Length of the blob that is being received.
+static LENGTH: AtomicU32 = AtomicU32::new(0);
+
+pub fn status() -> Status {
+    match STATUS.load(Relaxed) {
+        1 => Status::Busy,
+        2 => Status::Receiving,
+        3 => Status::Done,
+        4 => Status::Failed,
+        _ => Status::Idle,
+    }
+}
+
+// Queue a command for the upload task.
+fn submit(command: Command) -> Result<(), UploadError> {
+    UPLOAD_CHANNEL.try_send(command).map_err(|_| UploadError::Busy)?;
+    STATUS.store(Status::Busy as u8, Relaxed);
+
+    Ok(())
+}
+
+/// Start an upload of a blob with the given length and CRC, which erases the partition.
+pub fn begin(length: u32, crc: u32) -> Result<(), UploadError> {
+    if !cfg!(feature = "spi-flash") {
+        return Err(UploadError::Unavailable);
+    }
+    if status() == Status::Busy {
+        return Err(UploadError::Busy);
+    }
+    if length == 0 || length % 4 != 0 || length > COEFFICIENTS.size - BLOB_HEADER_SIZE {
+        return Err(UploadError::InvalidLength);
+    }
+
+    LENGTH.store(length, Relaxed);
+    submit(Command::Begin { length, crc })
+}
+
+/// Write a chunk, framed as its offset within the blob (`u32`), followed by the data.
+pub fn write(frame: &[u8]) -> Result<(), UploadError> {
+    if status() != Status::Receiving {
+        return Err(UploadError::InvalidState);
+    }
+    let (offset, data) = protocol::parse_chunk(frame).ok_or(UploadError::InvalidLength)?;
+    let data = Vec::from_slice(data).map_err(|_| UploadError::InvalidLength)?;
+
+    match offset.checked_add(data.len() as u32) {
+        Some(end) if end <= LENGTH.load(Relaxed) => submit(Command::Write { offset, data }),
+        _ => Err(UploadError::InvalidLength),
+    }
+}
+
+/// Complete the upload by writing the blob header, and verifying the blob.
+pub fn finish() -> Result<(), UploadError> {
+    if status() != Status::Receiving {
+        return Err(UploadError::InvalidState);
+    }
+
+    submit(Command::Finish)
+}
+
+// Execute a command on the external flash, returning the resulting status.
+async fn execute(command: Command, header: &mut [u8; BLOB_HEADER_SIZE as usize]) -> Result<Status, PartitionError> {
+    let mut external_flash = partition::EXTERNAL_FLASH.lock().await;
+    let flash = external_flash.as_mut().ok_or(PartitionError::Flash)?;
+
+    match command {
+        Command::Begin { length, crc } => {
+            info!("Upload of {} byte to the {} partition", length, COEFFICIENTS.name);
+            COEFFICIENTS.erase(flash, 0, BLOB_HEADER_SIZE + length).await?;
+
+            header[..4].copy_from_slice(&COEFFICIENTS_MAGIC.to_le_bytes());
+            header[4..8].copy_from_slice(&length.to_le_bytes());
+            header[8..].copy_from_slice(&crc.to_le_bytes());
+            Ok(Status::Receiving)
+        }
+        Command::Write { offset, data } => {
+            COEFFICIENTS.write(flash, BLOB_HEADER_SIZE + offset, &data).await?;
+            Ok(Status::Receiving)
+        }
+        Command::Finish => {
+            COEFFICIENTS.write(flash, 0, &header[..]).await?;
+
+            match partition::validate_blob(flash, &COEFFICIENTS, COEFFICIENTS_MAGIC).await? {
+                Some(blob) => {
+                    info!("Uploaded blob is valid: {}", blob);
+                    Ok(Status::Done)
+                }
+                None => Ok(Status::Failed),
+            }
+        }
+    }
+}
+
+/// Executes queued upload commands on the external flash.
+#[embassy_executor::task]
+pub async fn upload_task() {
+    // The header of the blob that is being received, written by the final command.
+    let mut header = [0u8; BLOB_HEADER_SIZE as usize];
+
+    loop {
+        let command = UPLOAD_CHANNEL.receive().await;
+
+        let status = execute(command, &mut header).await.unwrap_or_else(|e| {
+            warn!("Upload failed: {}", e);
+            Status::Failed
+        });
+
+        STATUS.store(status as u8, Relaxed);
+    }
+}
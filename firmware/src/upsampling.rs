@@ -0,0 +1,78 @@
+// Upsampling of telephony rates (8, 16, and 32 kHz) to the output's rate, for voice applications on hosts that do not
+// resample themselves.
+//
+// The I2S output keeps running at `SAMPLE_RATE_HZ`, and received packets are interpolated to it after remixing (see
+// `blus_core::dsp::interpolate`). Feedback is measured against the output's clock, and scaled to the stream's rate.
+use blus_core::dsp::interpolate::{Interpolator, Ratio};
+use defmt::info;
+
+use crate::*;
+
+/// The output's sample rate for a USB stream's rate.
+pub fn output_rate_hz(usb_rate_hz: u32) -> u32 {
+    match Ratio::new(usb_rate_hz, SAMPLE_RATE_HZ) {
+        Some(_) if usb_rate_hz < SAMPLE_RATE_HZ => SAMPLE_RATE_HZ,
+        _ => usb_rate_hz,
+    }
+}
+
+/// Scale a feedback value at the output's rate to a USB stream's rate.
+pub fn scale_feedback(value: u32, usb_rate_hz: u32) -> u32 {
+    let output_rate_hz = output_rate_hz(usb_rate_hz);
+    (value as u64 * usb_rate_hz as u64 / output_rate_hz as u64) as u32
+}
+
+/// Interpolates blocks of stereo frames of a USB stream to the output's rate, if their rates differ.
+pub struct Upsampler {
+    interpolator: Option<Interpolator<i32, INPUT_CHANNEL_COUNT>>,
+    usb_rate_hz: u32,
+}
+
+impl Upsampler {
+    pub const fn new() -> Self {
+        Self {
+            interpolator: None,
+            usb_rate_hz: SAMPLE_RATE_HZ,
+        }
+    }
+
+    /// Follow the USB stream's rate, which restarts the interpolation on a change.
+    pub fn configure(&mut self, usb_rate_hz: u32) {
+        if usb_rate_hz == self.usb_rate_hz {
+            return;
+        }
+
+        self.usb_rate_hz = usb_rate_hz;
+        self.interpolator = match Ratio::new(usb_rate_hz, output_rate_hz(usb_rate_hz)) {
+            Some(ratio) if ratio.up > 1 => {
+                info!("Upsampling {} Hz by {}/{}", usb_rate_hz, ratio.up, ratio.down);
+                Some(Interpolator::new(ratio))
+            }
+            _ => None,
+        };
+    }
+
+    /// Interpolate a block to the output's rate, as many frames as fit.
+    pub fn process(&mut self, samples: &mut UsbSampleBlock) {
+        let Some(interpolator) = self.interpolator.as_mut() else {
+            return;
+        };
+
+        let mut input = [0i32; USB_SAMPLE_BLOCK_SAMPLE_COUNT];
+        let mut count = 0;
+        for (sample, block_sample) in input.iter_mut().zip(samples.samples()) {
+            *sample = block_sample;
+            count += 1;
+        }
+
+        let mut output = [0i32; USB_SAMPLE_BLOCK_SAMPLE_COUNT];
+        let written = interpolator.process(&input[..count], &mut output);
+        samples.set_samples(&output[..written]);
+    }
+}
+
+impl Default for Upsampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,27 +1,81 @@
+use blus_core::meter::Accumulator;
+use blus_core::usb_layout::{audio, class, Endpoint, Function, Interface};
 use core::sync::atomic::Ordering::Relaxed;
-use defmt::{debug, info, panic};
-use embassy_stm32::{peripherals, usb};
+use defmt::{debug_assert, info, panic, warn};
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_stm32::usb;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::zerocopy_channel;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use embassy_usb::class::uac1::speaker;
 use embassy_usb::driver::EndpointError;
+use embassy_usb::{Handler, InterfaceNumber};
 use static_assertions;
 
+use crate::aux_input::{self, AuxInput, AuxStream};
+use crate::bypass::Crossfade;
+use crate::channel_layout::{self, ChannelLayout};
+use crate::concealment::Concealment;
+use crate::i2s_input::{self, I2sInput, I2sStream};
+use crate::preset::{self, DspChain, PRESETS};
+use crate::profile::{self, Stage};
+use crate::silence::{FadeIn, FadeOut, SilenceDetector, FADE_IN_MS, FADE_OUT_MS};
+use crate::source::{self, Selection, Source};
+use crate::upsampling::{self, Upsampler};
+use crate::watchdog::{self, Task};
 use crate::*;
+use crate::{i2s_clock, latency, meter, power, stats, trim, usb_frame};
 
 // Number of ticks of the feedback timer per audio sample period.
 const TICKS_PER_SAMPLE: u32 = FEEDBACK_COUNTER_TICK_RATE / SAMPLE_RATE_HZ;
 static_assertions::const_assert_eq!(TICKS_PER_SAMPLE * SAMPLE_RATE_HZ, FEEDBACK_COUNTER_TICK_RATE);
 
-// Feedback is provided in 10.14 format for full-speed endpoints.
-const FEEDBACK_SHIFT: usize = 14;
+static_assertions::const_assert!(FEEDBACK_FORMAT.size <= USB_FEEDBACK_BUF_SIZE);
 
-const FEEDBACK_FACTOR: u32 = ((1 << FEEDBACK_SHIFT) / TICKS_PER_SAMPLE) / FEEDBACK_REFRESH_PERIOD.frame_count() as u32;
+/// The speaker's interfaces (see `speaker::Speaker`): audio control, and audio streaming with the stream's and the
+/// feedback's isochronous endpoints (see `usb_layout`).
+pub const SPEAKER_FUNCTION: Function = Function {
+    class: class::AUDIO,
+    sub_class: audio::CONTROL,
+    interfaces: &[
+        Interface {
+            class: class::AUDIO,
+            sub_class: audio::CONTROL,
+            endpoints: &[],
+        },
+        Interface {
+            class: class::AUDIO,
+            sub_class: audio::STREAMING,
+            endpoints: &[
+                Endpoint::isochronous_out(USB_MAX_PACKET_SIZE),
+                Endpoint::isochronous_in(USB_FEEDBACK_BUF_SIZE),
+            ],
+        },
+    ],
+};
+
+/// The feedback value in samples per (micro)frame, from the feedback timer ticks over a refresh period.
+pub const fn feedback_value(ticks: u32) -> u32 {
+    feedback::feedback_value(
+        ticks,
+        TICKS_PER_SAMPLE,
+        FEEDBACK_REFRESH_PERIOD.frame_count(),
+        FEEDBACK_FORMAT.shift,
+    )
+}
+
+// The conversion is exact, such that the nominal ticks over a refresh period give the nominal feedback value.
 static_assertions::const_assert_eq!(
-    (FEEDBACK_FACTOR << (FEEDBACK_REFRESH_PERIOD as usize)) * TICKS_PER_SAMPLE,
-    (1 << FEEDBACK_SHIFT)
+    feedback_value(TICKS_PER_SAMPLE << (FEEDBACK_REFRESH_PERIOD as usize)),
+    (1 << FEEDBACK_FORMAT.shift)
 );
 
+// Time for other tasks to react to a suspend, before clocks are reduced.
+const SUSPEND_SETTLE_TIME_MS: u64 = 20;
+
+// Samples are processed in place, which relies on 32 bit samples.
+static_assertions::const_assert_eq!(SAMPLE_SIZE, 4);
+
 struct Disconnected {}
 
 impl From<EndpointError> for Disconnected {
@@ -36,103 +90,807 @@ impl From<EndpointError> for Disconnected {
 async fn feedback_handler<'d, T: usb::Instance + 'd>(
     feedback: &mut speaker::Feedback<'d, usb::Driver<'d, T>>,
 ) -> Result<(), Disconnected> {
-    let mut packet: Vec<u8, 4> = Vec::new();
+    let mut packet: Vec<u8, USB_FEEDBACK_BUF_SIZE> = Vec::new();
+    let mut last_plausible = None;
 
     loop {
-        let counter = FEEDBACK_SIGNAL.wait().await;
-        info!("{}", counter);
+        let counter = watchdog::idle(Task::Feedback, FEEDBACK_TICKS.receive()).await;
+        log_trace!("Feedback counter: {}", counter);
 
         packet.clear();
 
-        let value = counter * FEEDBACK_FACTOR;
+        // Measured at the output's rate, which upsampled streams differ from.
+        let usb_rate_hz = USB_SAMPLE_RATE_HZ.load(Relaxed);
+        let value = upsampling::scale_feedback(feedback_value(counter), usb_rate_hz);
+        stats::record_feedback(value);
+
+        // Implausible values point to a broken measurement, e.g. a missed SOF capture or timer overflow. Debug builds
+        // stop there, release builds replace them by the last plausible value, or the nominal one.
+        let nominal = FEEDBACK_FORMAT.nominal(usb_rate_hz, USB_FRAMES_PER_MS);
+        let plausible = FEEDBACK_FORMAT.is_plausible(value, nominal);
+        if !plausible {
+            warn!("Implausible feedback value {} (nominal {})", value, nominal);
+            stats::record_implausible_feedback();
+        }
+        debug_assert!(plausible, "Implausible feedback value {} (nominal {})", value, nominal);
+
+        let value = if plausible {
+            last_plausible = Some(value);
+            value
+        } else {
+            last_plausible
+                .filter(|&last| FEEDBACK_FORMAT.is_plausible(last, nominal))
+                .unwrap_or(nominal)
+        };
 
-        packet.push(value as u8).unwrap();
-        packet.push((value >> 8) as u8).unwrap();
-        packet.push((value >> 16) as u8).unwrap();
+        packet
+            .extend_from_slice(&value.to_le_bytes()[..FEEDBACK_FORMAT.size])
+            .unwrap();
 
         feedback.write_packet(&packet).await?;
     }
 }
 
+// Samples at or above this magnitude count as clipped. It is the full scale of 16 bit samples, such that clipping is
+// detected for sources of any resolution.
+const CLIP_LEVEL: u32 = 0x7fff_0000;
+
+// Time for a handler to fade out after a source change, before it is stopped.
+const SWITCH_TIMEOUT: Duration = Duration::from_millis(2 * FADE_OUT_MS as u64);
+
+// Processing state of the audio pipeline, shared by all sources.
+struct Pipeline {
+    silence_detector: SilenceDetector,
+    fade_in: FadeIn,
+    fade_out: FadeOut,
+    dsp_chain: DspChain,
+    bypass: Crossfade,
+    #[cfg(feature = "chimes")]
+    chime: chime::Mixer,
+}
+
+impl Pipeline {
+    fn new() -> Self {
+        Self {
+            silence_detector: SilenceDetector::new(),
+            fade_in: FadeIn::new(),
+            fade_out: FadeOut::new(),
+            dsp_chain: DspChain::new(&PRESETS[preset::active()]),
+            bypass: Crossfade::new(),
+            #[cfg(feature = "chimes")]
+            chime: chime::Mixer::new(),
+        }
+    }
+
+    // Re-arm the pipeline with a fade-in, for a new stream or source.
+    fn restart(&mut self) {
+        self.fade_in.restart(STREAM_FADE_IN_MS);
+        self.fade_out.reset();
+        self.dsp_chain.reset();
+        self.bypass.reset();
+        #[cfg(feature = "chimes")]
+        self.chime.reset();
+    }
+
+    // Power the amplifiers down, when a stream or source ends, without waiting for the silence timeout.
+    fn stop(&mut self) {
+        self.silence_detector.reset();
+        AMP_STANDBY_SIGNAL.signal(true);
+        sleep_timer::set_playing(false);
+    }
+
+    // Run a block through the DSP chain (or around it, while bypassed) and fades, returning the input's peak magnitude.
+    // Fades out, when the active source changed. Counts clipped samples before and after the DSP chain, and meters its
+    // output. Chimes are mixed in after metering, so that they neither count as clipped nor show on the meter.
+    fn process_block(&mut self, samples: &mut UsbSampleBlock) -> u32 {
+        let mut peak: u32 = 0;
+        let mut clipped_input = [0u32; INPUT_CHANNEL_COUNT];
+        let mut clipped_output = [0u32; INPUT_CHANNEL_COUNT];
+        let mut levels = [Accumulator::EMPTY; INPUT_CHANNEL_COUNT];
+        profile_scope!(Stage::Dsp);
+
+        if let Some(index) = PRESET_SIGNAL.try_take() {
+            self.dsp_chain.configure(&PRESETS[index]);
+            self.bypass.configure();
+        }
+        self.bypass.update();
+        #[cfg(feature = "chimes")]
+        self.chime.update();
+
+        if source::switch_pending() {
+            self.fade_out.start();
+        }
+
+        // Blocks hold whole frames, so that each one starts with the first channel.
+        let mut channel = 0;
+        samples.process(|sample| {
+            peak = peak.max(sample.unsigned_abs());
+            clipped_input[channel] += (sample.unsigned_abs() >= CLIP_LEVEL) as u32;
+
+            let sample = self.bypass.apply(self.dsp_chain.process(channel, sample), sample);
+            clipped_output[channel] += (sample.unsigned_abs() >= CLIP_LEVEL) as u32;
+            levels[channel].add(sample);
+            #[cfg(feature = "chimes")]
+            let sample = self.chime.apply(sample);
+            channel = (channel + 1) % INPUT_CHANNEL_COUNT;
+            if channel == 0 {
+                self.bypass.step();
+                #[cfg(feature = "chimes")]
+                self.chime.step();
+            }
+
+            self.fade_out.apply(self.fade_in.apply(sample))
+        });
+
+        self.dsp_chain
+            .update_dynamics(peak, samples.sample_count() / INPUT_CHANNEL_COUNT);
+
+        meter::record(&levels);
+
+        #[cfg(feature = "spectrum")]
+        spectrum::capture(samples);
+
+        if clipped_input.iter().chain(&clipped_output).any(|&count| count > 0) {
+            stats::record_clipped(clipped_input, clipped_output);
+            CLIP_SIGNAL.signal(());
+        }
+
+        peak
+    }
+
+    // Power the amplifiers down after a period of silence, and up on signal.
+    fn detect_silence(&mut self, peak: u32) {
+        match self.silence_detector.update(peak) {
+            Some(true) => {
+                AMP_STANDBY_SIGNAL.signal(true);
+                sleep_timer::set_playing(false);
+            }
+            Some(false) => {
+                // Wakes up, before the amplifiers power up.
+                sleep_timer::set_playing(true);
+                self.fade_in.restart(FADE_IN_MS);
+                AMP_STANDBY_SIGNAL.signal(false);
+            }
+            None => (),
+        }
+    }
+
+    // Whether the fade-out after a source change completed.
+    fn is_faded_out(&self) -> bool {
+        self.fade_out.is_done()
+    }
+}
+
+fn peak(samples: &[i32]) -> u32 {
+    samples.iter().map(|sample| sample.unsigned_abs()).max().unwrap_or(0)
+}
+
+// Plays USB audio, optionally mixed with the aux input. Returns after fading out on a source change.
 async fn stream_handler<'d, T: usb::Instance + 'd>(
     stream: &mut speaker::Stream<'d, usb::Driver<'d, T>>,
     sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+    pipeline: &mut Pipeline,
+    concealment: &mut Concealment,
+    mut mix: Option<&mut AuxStream<'_>>,
 ) -> Result<(), Disconnected> {
-    loop {
-        let mut usb_data = [0u8; USB_MAX_PACKET_SIZE];
-        let data_size = stream.read_packet(&mut usb_data).await?;
+    let mut aux_samples = [0i32; USB_SAMPLE_BLOCK_SAMPLE_COUNT];
+    let mut discarded = [0u8; USB_MAX_PACKET_SIZE];
+    let layout = channel_layout::active();
+    let mut upsampler = Upsampler::new();
+
+    'packets: loop {
+        // Receive the packet into a free buffer of the channel directly. While the output is behind, the packet is
+        // received anyway and dropped, so that a full channel does not stall the endpoint.
+        let Some(mut samples) = sender.try_send() else {
+            if let Some(data_size) = receive_packet(stream, &mut discarded, concealment.timeout()).await? {
+                log_debug!("Output buffer full, packet dropped.");
+                stats::record_dropped(data_size / SAMPLE_SIZE);
+            }
+            continue;
+        };
+
+        let Some(data_size) = receive_packet(stream, samples.buffer_mut(), concealment.timeout()).await? else {
+            // The host stopped sending packets mid-stream.
+            if concealment.conceal(samples) {
+                sender.send_done();
+                stats::block_queued();
+                stats::record_concealed_frame();
+            } else {
+                // Stop feeding the output, until packets arrive again.
+                warn!("Stream stalled");
+                stats::record_stall();
+            }
+            continue;
+        };
+        let arrival = Instant::now();
+        let frame = usb_frame::number();
 
         let word_count = data_size / SAMPLE_SIZE;
 
         if word_count * SAMPLE_SIZE == data_size {
-            // Obtain a buffer from the channel
-            let samples = sender.send().await;
-            samples.clear();
+            samples.set_byte_length(data_size);
+
+            // The pipeline processes stereo frames at the output's rate.
+            {
+                profile_scope!(Stage::Conversion);
+                if layout != ChannelLayout::Stereo {
+                    let muted = channel_layout::muted();
+                    samples.remix(layout.channel_count(), |frame| layout.to_stereo(frame, muted));
+                }
+
+                upsampler.configure(USB_SAMPLE_RATE_HZ.load(Relaxed));
+                upsampler.process(samples);
+            }
+            let sample_count = samples.sample_count();
+            let frame_count = sample_count / INPUT_CHANNEL_COUNT;
 
-            for w in 0..word_count {
-                let byte_offset = w * SAMPLE_SIZE;
-                let sample = u32::from_le_bytes(usb_data[byte_offset..byte_offset + SAMPLE_SIZE].try_into().unwrap());
+            // Conceal packets that were missed before this one (e.g. during a long critical section), instead of
+            // leaving a hole.
+            let missed = concealment.missed_packets(frame);
+            if missed > 0 {
+                log_debug!("Missed {} packets", missed);
+                stats::record_missed_packets(missed);
+                concealment.hold(samples);
 
-                // Fill the sample buffer with data.
-                samples.push(sample as u16).unwrap();
-                samples.push((sample >> 16) as u16).unwrap();
+                for _ in 0..missed {
+                    if !concealment.conceal(samples) {
+                        break;
+                    }
+
+                    sender.send_done();
+                    stats::block_queued();
+                    stats::record_concealed_frame();
+
+                    // Waiting for a buffer would stall the endpoint. If the channel ran full, the received packet is
+                    // dropped, as on arrival.
+                    let Some(next) = sender.try_send() else {
+                        log_debug!("Output buffer full, packet dropped.");
+                        stats::record_dropped(word_count);
+                        continue 'packets;
+                    };
+                    samples = next;
+                }
+
+                concealment.release(samples);
+            }
+
+            if concealment.packet_received(samples) {
+                log_debug!("Stream resumed after gap");
+                pipeline.fade_in.restart(STREAM_FADE_IN_MS);
+            }
+
+            // Packets hold whole frames, so the aux input contributes the same number of frames.
+            let mut usb_peak = None;
+            if let Some(aux) = mix.as_mut() {
+                let aux_samples = &mut aux_samples[..sample_count];
+
+                if aux.read(aux_samples).await.is_err() {
+                    warn!("Aux input overrun");
+                    aux_samples.fill(0);
+                }
+                source::report(Source::Aux, peak(aux_samples), frame_count);
+
+                profile_scope!(Stage::Conversion);
+                let mut input_peak: u32 = 0;
+                let mut index = 0;
+                samples.process(|sample| {
+                    input_peak = input_peak.max(sample.unsigned_abs());
+                    let sample = sample.saturating_add(aux_samples[index]);
+                    index += 1;
+                    sample
+                });
+                usb_peak = Some(input_peak);
             }
 
+            let peak = pipeline.process_block(samples);
+
             sender.send_done();
+            stats::block_queued();
+            latency::block_sent(arrival);
+            stats::record_packet(word_count);
+
+            pipeline.detect_silence(peak);
+            source::report(Source::Usb, usb_peak.unwrap_or(peak), frame_count);
+
+            if pipeline.is_faded_out() {
+                return Ok(());
+            }
         } else {
-            debug!("Invalid USB buffer size of {}, skipped.", data_size);
+            // The buffer is not sent, and reused for the next packet.
+            log_debug!("Invalid USB buffer size of {}, skipped.", data_size);
+            stats::record_invalid_packet();
+            stats::record_dropped(word_count);
         }
     }
 }
 
+// Receives a packet while streaming. Returns None, if none arrived within the timeout.
+async fn receive_packet<'d, T: usb::Instance + 'd>(
+    stream: &mut speaker::Stream<'d, usb::Driver<'d, T>>,
+    buffer: &mut [u8],
+    timeout: Option<Duration>,
+) -> Result<Option<usize>, Disconnected> {
+    let read = profile::measure(Stage::Reception, stream.read_packet(buffer));
+
+    match timeout {
+        None => Ok(Some(watchdog::idle(Task::Streaming, read).await?)),
+        Some(timeout) => match watchdog::idle(Task::Streaming, with_timeout(timeout, read)).await {
+            Ok(result) => Ok(Some(result?)),
+            Err(_) => Ok(None),
+        },
+    }
+}
+
+// Plays the aux input alone, paced by its ADC. Returns after fading out on a source change.
+async fn aux_handler(
+    aux: &mut AuxStream<'_>,
+    sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+    pipeline: &mut Pipeline,
+) {
+    let mut aux_samples = [0i32; aux_input::SAMPLES_PER_BLOCK];
+
+    loop {
+        watchdog::check_in(Task::Streaming);
+
+        if aux.read(&mut aux_samples).await.is_err() {
+            warn!("Aux input overrun");
+            continue;
+        }
+
+        let samples = sender.send().await;
+        {
+            profile_scope!(Stage::Conversion);
+            samples.set_samples(&aux_samples);
+        }
+
+        let peak = pipeline.process_block(samples);
+
+        sender.send_done();
+        stats::block_queued();
+
+        pipeline.detect_silence(peak);
+        source::report(Source::Aux, peak, aux_samples.len() / INPUT_CHANNEL_COUNT);
+
+        if pipeline.is_faded_out() {
+            return;
+        }
+    }
+}
+
+// Plays the external digital input, paced by its receiver. The receiver's clock drifts against the output's, so a frame
+// is dropped or repeated, whenever the output buffer runs full or empty. Returns after fading out on a source change.
+async fn i2s_handler(
+    input: &mut I2sStream<'_>,
+    sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+    pipeline: &mut Pipeline,
+    output_rate_hz: &mut Option<u32>,
+) {
+    const BLOCK_SIZE: usize = i2s_input::FRAMES_PER_BLOCK * INPUT_CHANNEL_COUNT;
+
+    // Room for a repeated frame.
+    let mut input_samples = [0i32; BLOCK_SIZE + INPUT_CHANNEL_COUNT];
+
+    loop {
+        watchdog::check_in(Task::Streaming);
+
+        if let Err(e) = input.read(&mut input_samples[..BLOCK_SIZE]).await {
+            log_debug!("I2S input: {}", e);
+            source::set_absent(Source::I2s);
+            continue;
+        }
+
+        // Nothing plays, until the sample rate is known.
+        let Some(rate_hz) = input.sample_rate_hz() else {
+            continue;
+        };
+
+        if !i2s_clock::is_supported(rate_hz) {
+            continue;
+        }
+
+        // Applied by the output task, at the start of the next stream.
+        if rate_hz != output_rate_hz.unwrap_or(upsampling::output_rate_hz(USB_SAMPLE_RATE_HZ.load(Relaxed))) {
+            info!("Switching output to {} Hz", rate_hz);
+            SAMPLE_RATE_SIGNAL.signal(rate_hz);
+            *output_rate_hz = Some(rate_hz);
+        }
+
+        let sample_count = match stats::buffer_fill() as usize {
+            fill if fill + 1 >= USB_SAMPLE_BLOCK_COUNT => BLOCK_SIZE - INPUT_CHANNEL_COUNT,
+            0 if I2S_IS_ACTIVE.load(Relaxed) => {
+                input_samples.copy_within(BLOCK_SIZE - INPUT_CHANNEL_COUNT..BLOCK_SIZE, BLOCK_SIZE);
+                BLOCK_SIZE + INPUT_CHANNEL_COUNT
+            }
+            _ => BLOCK_SIZE,
+        };
+
+        let samples = sender.send().await;
+        {
+            profile_scope!(Stage::Conversion);
+            samples.set_samples(&input_samples[..sample_count]);
+        }
+
+        let peak = pipeline.process_block(samples);
+
+        sender.send_done();
+        stats::block_queued();
+
+        pipeline.detect_silence(peak);
+        source::report(Source::I2s, peak, i2s_input::FRAMES_PER_BLOCK);
+
+        if pipeline.is_faded_out() {
+            return;
+        }
+    }
+}
+
+// Plays the auto-EQ's sweep, and records it from the aux input.
+#[cfg(feature = "auto-eq")]
+async fn measurement_handler(
+    aux: &mut AuxStream<'_>,
+    sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+    pipeline: &mut Pipeline,
+) {
+    // The sweep fades in by itself.
+    sleep_timer::set_playing(true);
+    AMP_STANDBY_SIGNAL.signal(false);
+
+    auto_eq::measure(aux, sender).await;
+    pipeline.stop();
+}
+
+// Samples the aux input while another source plays, for detecting its signal.
+async fn aux_monitor(aux: Option<&mut AuxStream<'_>>) {
+    let Some(aux) = aux else {
+        return core::future::pending().await;
+    };
+
+    let mut aux_samples = [0i32; aux_input::SAMPLES_PER_BLOCK];
+
+    loop {
+        if aux.read(&mut aux_samples).await.is_ok() {
+            source::report(Source::Aux, peak(&aux_samples), aux_samples.len() / INPUT_CHANNEL_COUNT);
+        }
+    }
+}
+
+// Receives the external digital input while another source plays, for detecting its signal.
+async fn i2s_monitor(input: Option<&mut I2sStream<'_>>) {
+    let Some(input) = input else {
+        return core::future::pending().await;
+    };
+
+    let mut input_samples = [0i32; i2s_input::FRAMES_PER_BLOCK * INPUT_CHANNEL_COUNT];
+
+    loop {
+        match input.read(&mut input_samples).await {
+            // Only input at a playable sample rate counts as signal.
+            Ok(()) => {
+                if input.sample_rate_hz().is_some_and(i2s_clock::is_supported) {
+                    source::report(Source::I2s, peak(&input_samples), i2s_input::FRAMES_PER_BLOCK);
+                }
+            }
+            Err(_) => source::set_absent(Source::I2s),
+        }
+    }
+}
+
+// Receives and discards USB audio while another source plays, for detecting its signal.
+async fn usb_monitor<'d, T: usb::Instance + 'd>(stream: &mut speaker::Stream<'d, usb::Driver<'d, T>>) {
+    let mut packet = [0u8; USB_MAX_PACKET_SIZE];
+    let layout = channel_layout::active();
+
+    loop {
+        stream.wait_connection().await;
+
+        while let Ok(data_size) = stream.read_packet(&mut packet).await {
+            let peak = packet[..data_size]
+                .chunks_exact(SAMPLE_SIZE)
+                .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()).unsigned_abs())
+                .max()
+                .unwrap_or(0);
+
+            source::report(Source::Usb, peak, data_size / SAMPLE_SIZE / layout.channel_count());
+        }
+
+        source::set_absent(Source::Usb);
+    }
+}
+
+// Ends a handler that does not fade out after a source change, e.g. while it waits for packets.
+async fn switch_timeout() {
+    SOURCE_SIGNAL.wait().await;
+    Timer::after(SWITCH_TIMEOUT).await;
+}
+
 #[embassy_executor::task]
 pub async fn streaming_task(
-    mut stream: speaker::Stream<'static, usb::Driver<'static, peripherals::USB_OTG_FS>>,
+    mut stream: speaker::Stream<'static, UsbDriver>,
     mut sender: zerocopy_channel::Sender<'static, NoopRawMutex, UsbSampleBlock>,
+    mut aux_input: Option<AuxInput>,
+    mut i2s_input: Option<I2sInput>,
 ) {
+    let mut pipeline = Pipeline::new();
+    let mut concealment = Concealment::new();
+
+    if aux_input.is_some() {
+        source::enable(Source::Aux);
+    }
+    if i2s_input.is_some() {
+        source::enable(Source::I2s);
+    }
+
     loop {
-        stream.wait_connection().await;
-        USB_IS_STREAMING.store(true, Relaxed);
-        _ = stream_handler(&mut stream, &mut sender).await;
-        USB_IS_STREAMING.store(false, Relaxed);
+        // Plays the active source, until it changes.
+        SOURCE_SIGNAL.reset();
+        source::switch_done();
+        let active = source::active();
+
+        // A measurement takes over the output and the aux input.
+        #[cfg(feature = "auto-eq")]
+        if auto_eq::take_request() {
+            if let Some(aux) = aux_input.as_mut() {
+                measurement_handler(&mut aux.start(), &mut sender, &mut pipeline).await;
+            }
+            continue;
+        }
+
+        // Automatic selection receives all inputs, for detecting their signal.
+        let automatic = source::selection() == Selection::Automatic;
+        let mut aux_stream = aux_input
+            .as_mut()
+            .filter(|_| automatic || matches!(active, Source::Aux | Source::Mix))
+            .map(AuxInput::start);
+        let mut i2s_stream = i2s_input
+            .as_mut()
+            .filter(|_| automatic || active == Source::I2s)
+            .map(I2sInput::start);
+
+        match (active, aux_stream.as_mut(), i2s_stream.as_mut()) {
+            (Source::Aux, Some(aux), i2s) => {
+                pipeline.restart();
+
+                select4(
+                    aux_handler(aux, &mut sender, &mut pipeline),
+                    usb_monitor(&mut stream),
+                    i2s_monitor(i2s),
+                    switch_timeout(),
+                )
+                .await;
+            }
+            (Source::I2s, aux, Some(i2s)) => {
+                pipeline.restart();
+
+                let mut output_rate_hz = None;
+                select4(
+                    i2s_handler(i2s, &mut sender, &mut pipeline, &mut output_rate_hz),
+                    usb_monitor(&mut stream),
+                    aux_monitor(aux),
+                    switch_timeout(),
+                )
+                .await;
+
+                // Output returns to the USB sample rate.
+                if output_rate_hz.is_some() {
+                    SAMPLE_RATE_SIGNAL.signal(upsampling::output_rate_hz(USB_SAMPLE_RATE_HZ.load(Relaxed)));
+                }
+            }
+            (_, aux, mut i2s) => {
+                // The aux input is either mixed in, or monitored.
+                let (mix, mut aux) = match active {
+                    Source::Mix => (aux, None),
+                    _ => (None, aux),
+                };
+
+                let Either4::First(_) = select4(
+                    watchdog::idle(Task::Streaming, stream.wait_connection()),
+                    aux_monitor(aux.as_deref_mut()),
+                    i2s_monitor(i2s.as_deref_mut()),
+                    SOURCE_SIGNAL.wait(),
+                )
+                .await
+                else {
+                    continue;
+                };
+                USB_IS_STREAMING.store(true, Relaxed);
+
+                // The host opened the stream (alt setting 1), re-arm the pipeline with a fade-in.
+                pipeline.restart();
+
+                let result = select4(
+                    stream_handler(&mut stream, &mut sender, &mut pipeline, &mut concealment, mix),
+                    aux_monitor(aux),
+                    i2s_monitor(i2s),
+                    switch_timeout(),
+                )
+                .await;
+                USB_IS_STREAMING.store(false, Relaxed);
+                concealment.reset();
+
+                // The host closed the stream (alt setting 0).
+                if let Either4::First(Err(Disconnected {})) = result {
+                    source::set_absent(Source::Usb);
+                }
+            }
+        }
+
+        pipeline.stop();
     }
 }
 
 #[embassy_executor::task]
-pub async fn feedback_task(mut feedback: speaker::Feedback<'static, usb::Driver<'static, peripherals::USB_OTG_FS>>) {
+pub async fn feedback_task(mut feedback: speaker::Feedback<'static, UsbDriver>) {
     loop {
-        feedback.wait_connection().await;
+        watchdog::idle(Task::Feedback, feedback.wait_connection()).await;
         _ = feedback_handler(&mut feedback).await;
     }
 }
 
+// Reset the audio pipeline's state after losing the host connection.
+fn reset_pipeline() {
+    USB_IS_CONFIGURED.store(false, Relaxed);
+    USB_IS_STREAMING.store(false, Relaxed);
+    FEEDBACK_ACCUMULATOR.reset();
+    FEEDBACK_TICKS.reset();
+    clock_accuracy::reset();
+    AMP_STANDBY_SIGNAL.signal(true);
+}
+
+// Handles USB device state changes, such as VBUS loss and bus resets.
+//
+// Streaming and feedback tasks end by themselves when their endpoints are disabled, and the output task discards
+// remaining samples when it stops. Re-enumeration on VBUS return is handled by `UsbDevice::run`.
+pub struct DeviceHandler {
+    configured: bool,
+}
+
+impl DeviceHandler {
+    pub const fn new() -> Self {
+        Self { configured: false }
+    }
+}
+
+impl Default for DeviceHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for DeviceHandler {
+    fn enabled(&mut self, enabled: bool) {
+        self_test::usb_power_changed(enabled);
+
+        if enabled {
+            info!("USB power detected");
+        } else {
+            info!("USB power removed");
+            self.configured = false;
+            reset_pipeline();
+        }
+    }
+
+    fn reset(&mut self) {
+        if self.configured {
+            info!("USB bus reset");
+            self.configured = false;
+            reset_pipeline();
+        }
+    }
+
+    fn configured(&mut self, configured: bool) {
+        if configured {
+            info!("USB configured");
+        } else if self.configured {
+            reset_pipeline();
+        }
+
+        self.configured = configured;
+        USB_IS_CONFIGURED.store(configured, Relaxed);
+    }
+
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        // Only the audio streaming interface has alternate settings: zero closes the stream, one opens it.
+        let open = alternate_setting != 0;
+        info!("Audio stream open: {} (interface {})", open, iface.0);
+        sleep_timer::activity();
+
+        STREAM_OPEN_SIGNAL.signal(open);
+    }
+}
+
+// Prepare for USB suspend: power down the amplifiers, stop the I2S clock, and reduce the core clock.
+async fn suspend() {
+    AMP_STANDBY_SIGNAL.signal(true);
+
+    // Output stops by itself, once no more samples arrive.
+    while I2S_IS_ACTIVE.load(Relaxed) {
+        Timer::after_millis(1).await;
+    }
+
+    // Leave time for powering down the amplifiers via I2C, along the board's power sequence.
+    Timer::after_millis(SUSPEND_SETTLE_TIME_MS + board::POWER_SEQUENCE.shutdown_ms()).await;
+
+    power::stop_i2s_clock();
+    power::reduce_clocks();
+}
+
+fn resume() {
+    power::restore_clocks();
+    power::start_i2s_clock();
+
+    // Amplifiers wake up, as soon as audio is received.
+}
+
 #[embassy_executor::task]
-pub async fn usb_task(mut usb_device: embassy_usb::UsbDevice<'static, usb::Driver<'static, peripherals::USB_OTG_FS>>) {
-    usb_device.run().await;
+pub async fn usb_task(mut usb_device: embassy_usb::UsbDevice<'static, UsbDriver>) {
+    loop {
+        usb_device.run_until_suspend().await;
+        info!("USB suspended");
+        USB_IS_SUSPENDED.store(true, Relaxed);
+        suspend().await;
+
+        // Ignore button presses from before the suspend.
+        REMOTE_WAKEUP_SIGNAL.reset();
+
+        loop {
+            match select(usb_device.wait_resume(), REMOTE_WAKEUP_SIGNAL.wait()).await {
+                Either::First(()) => break,
+                Either::Second(()) => {
+                    // Fails, if the host did not enable remote wakeup.
+                    if usb_device.remote_wakeup().await.is_ok() {
+                        info!("Remote wakeup");
+                        break;
+                    } else {
+                        warn!("Remote wakeup failed");
+                    }
+                }
+            }
+        }
+
+        resume();
+        USB_IS_SUSPENDED.store(false, Relaxed);
+        info!("USB resumed");
+        sleep_timer::activity();
+    }
 }
 
 #[embassy_executor::task]
 pub async fn control_task(control_monitor: speaker::ControlMonitor<'static>) {
+    let mut sample_rate_hz = SAMPLE_RATE_HZ;
+
     loop {
-        control_monitor.changed().await;
+        watchdog::idle(Task::Control, control_monitor.changed()).await;
+        sleep_timer::activity();
 
-        let mut volume_left = Volume::Muted;
-        let mut volume_right = Volume::Muted;
+        // The output task reconfigures clocks at the start of the next stream.
+        if control_monitor.sample_rate_hz() != sample_rate_hz {
+            sample_rate_hz = control_monitor.sample_rate_hz();
+            USB_SAMPLE_RATE_HZ.store(sample_rate_hz, Relaxed);
+            info!("Sample rate changed to {} Hz", sample_rate_hz);
+            SAMPLE_RATE_SIGNAL.signal(upsampling::output_rate_hz(sample_rate_hz));
+        }
 
-        for channel in AUDIO_CHANNELS {
-            let volume = control_monitor.volume(channel).unwrap();
+        // Each side plays at the volume of the first unmuted channel that feeds it (fronts first), and is muted by the
+        // amplifiers, when all of them are muted. Other muted channels are silenced while remixing.
+        let mut volume = [Volume::Muted; INPUT_CHANNEL_COUNT];
+        let mut muted = 0u8;
 
-            match channel {
-                uac1::Channel::LeftFront => {
-                    volume_left = volume;
-                }
-                uac1::Channel::RightFront => {
-                    volume_right = volume;
+        for (index, &channel) in channel_layout::active().channels().iter().enumerate() {
+            match control_monitor.volume(channel).unwrap() {
+                Volume::Muted => muted |= 1 << index,
+                channel_volume => {
+                    for &side in channel_layout::sides(channel) {
+                        if matches!(volume[side], Volume::Muted) {
+                            volume[side] = channel_volume;
+                        }
+                    }
                 }
-                _ => (),
             }
         }
 
-        VOLUME_SIGNAL.signal((volume_left, volume_right));
+        channel_layout::set_muted(muted);
+
+        // The volume curve, trim, and balance are applied on top.
+        trim::set_host_volume((volume[0], volume[1]));
     }
 }
@@ -0,0 +1,28 @@
+// The USB (micro)frame number of the last SOF, read from the OTG core.
+//
+// Used for measuring feedback without SOF capture (see `frame_feedback`), and for detecting missed isochronous packets
+// (see `concealment`).
+
+// The OTG device status register (DSTS), which holds the frame number of the last SOF (FNSOF).
+#[cfg(not(feature = "usb-high-speed"))]
+const OTG_DSTS: *const u32 = (0x5000_0000 + 0x808) as *const u32;
+#[cfg(feature = "usb-high-speed")]
+const OTG_DSTS: *const u32 = (0x4004_0000 + 0x808) as *const u32;
+
+/// The frame number has 11 bits, or 14 bits including the microframe number for high-speed USB.
+#[cfg(not(feature = "usb-high-speed"))]
+pub const FRAME_NUMBER_MASK: u16 = (1 << 11) - 1;
+#[cfg(feature = "usb-high-speed")]
+pub const FRAME_NUMBER_MASK: u16 = (1 << 14) - 1;
+
+/// The number of the current (micro)frame.
+pub fn number() -> u16 {
+    // SAFETY: Reading DSTS has no side effects.
+    let dsts = unsafe { OTG_DSTS.read_volatile() };
+    (dsts >> 8) as u16 & FRAME_NUMBER_MASK
+}
+
+/// The number of (micro)frames from `earlier` to `later`, across the frame number's wrap.
+pub fn elapsed(earlier: u16, later: u16) -> u16 {
+    later.wrapping_sub(earlier) & FRAME_NUMBER_MASK
+}
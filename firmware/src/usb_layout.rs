@@ -0,0 +1,43 @@
+// Layout of the composite USB device, in the order that `App` registers its functions.
+//
+// The layout is validated at build time (see `blus_core::usb_layout`): IADs match their interfaces, audio functions
+// start with their audio control interface, and the endpoints fit the peripheral and bus speed. Registering a function
+// checks the interface number that the USB stack assigned against the layout, so that the order of registration cannot
+// drift from it. New functions are added here, along with their registration.
+use blus_core::usb_layout::{Bus, Function, Layout};
+use embassy_usb::InterfaceNumber;
+
+use crate::*;
+
+// Indices of the functions, whose registration checks its interface number. The speaker and HID classes do not expose
+// theirs, so that the checks of the functions after them cover these.
+pub const VENDOR: usize = 1;
+#[cfg(feature = "usb-midi")]
+pub const MIDI: usize = 3;
+
+const FUNCTIONS: &[Function] = &[
+    usb_audio::SPEAKER_FUNCTION,
+    vendor::FUNCTION,
+    hid::FUNCTION,
+    #[cfg(feature = "usb-midi")]
+    midi::FUNCTION,
+];
+
+pub const LAYOUT: Layout = Layout { functions: FUNCTIONS };
+
+const BUS: Bus = Bus {
+    high_speed: cfg!(feature = "usb-high-speed"),
+    endpoint_count: USB_ENDPOINT_COUNT,
+};
+
+const _: () = LAYOUT.assert_valid(&BUS);
+
+/// Check the number of a function's first interface, as assigned during registration, against the layout.
+pub fn check_interface(function: usize, number: InterfaceNumber) {
+    defmt::assert_eq!(
+        u8::from(number),
+        LAYOUT.first_interface(function),
+        "USB function {} is registered out of order",
+        function
+    );
+}
@@ -0,0 +1,631 @@
+// Vendor-specific USB interface, for configuring the device from a host tool.
+//
+// Requests are vendor control transfers to the interface, with the interface number in `wIndex`. Data is little-endian,
+// and limited to the control buffer's size, so that uploads are framed into chunks (see `upload`). The framing is
+// shared with the host tool (`host-tool`) in `blus-core`. External front-ends send the same requests over a serial link
+// (see `uart_control`).
+use blus_core::protocol::parse_eq_band;
+use blus_core::usb_layout::{class, Endpoint, Function, Interface};
+use blus_core::volume::VolumeCurve;
+#[cfg(feature = "dual-zone")]
+use blus_core::zone::{Route, ZoneConfig, ZONE_COUNT};
+use defmt::Format;
+use embassy_usb::class::uac1::speaker::Volume;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::types::StringIndex;
+use embassy_usb::{Builder, Handler, InterfaceNumber};
+use static_cell::StaticCell;
+
+use crate::channel_layout::{self, ChannelLayout};
+use crate::log_level::{self, Level};
+use crate::meter::{self, MeterEndpoint};
+use crate::preset::{self, PRESETS};
+use crate::source::{self, Selection};
+use crate::*;
+
+/// The vendor interface, with the level meter's interrupt endpoint (see `usb_layout`).
+pub const FUNCTION: Function = Function {
+    class: class::VENDOR,
+    sub_class: 0,
+    interfaces: &[Interface {
+        class: class::VENDOR,
+        sub_class: 0,
+        endpoints: &[Endpoint::interrupt_in(meter::REPORT_SIZE)],
+    }],
+};
+
+// The statistics counters must fit a single control transfer.
+static_assertions::const_assert!(4 * stats::COUNTER_COUNT <= USB_CONTROL_BUF_SIZE);
+static_assertions::const_assert!(4 * 3 * profile::STAGE_COUNT <= USB_CONTROL_BUF_SIZE);
+
+#[derive(Clone, Copy, PartialEq, Format)]
+#[repr(u8)]
+pub enum VendorRequest {
+    /// Read the trim of the channel in `wValue`, in 0.5 dB steps (`i8`).
+    GetTrim = 0x01,
+    /// Set the trim of the channel in `wValue`, in 0.5 dB steps (`i8`).
+    SetTrim = 0x02,
+    /// Read the balance, in 0.5 dB steps (`i8`).
+    GetBalance = 0x03,
+    /// Set the balance, in 0.5 dB steps (`i8`).
+    SetBalance = 0x04,
+    /// Read the index of the active DSP preset (`u8`).
+    GetPreset = 0x05,
+    /// Select the DSP preset with the index in `wValue`.
+    SetPreset = 0x06,
+    /// Read the number of DSP presets (`u8`).
+    GetPresetCount = 0x07,
+    /// Read the name of the DSP preset with the index in `wValue` (UTF-8).
+    GetPresetName = 0x08,
+    /// Read the CPU load of the last second and the peak load since boot, in permille (two `u16`).
+    GetCpuLoad = 0x09,
+    /// Read the runtime log level (`u8`, 0: off, 1: error, 2: warn, 3: info, 4: debug, 5: trace).
+    GetLogLevel = 0x0a,
+    /// Set the runtime log level to the value in `wValue`.
+    SetLogLevel = 0x0b,
+    /// Read the reason of the last reset (`u8`, see `reset_reason::ResetReason`).
+    GetResetReason = 0x0c,
+    /// Reset into the ROM DFU bootloader.
+    EnterBootloader = 0x0d,
+    /// Read the firmware version and build information (UTF-8).
+    GetVersion = 0x0e,
+    /// Learn the IR remote code of the action with the index in `wValue` from the next key press.
+    LearnIrCode = 0x0f,
+    /// Read the learned IR remote code of the action with the index in `wValue` (address `u16`, command `u8`).
+    GetIrCode = 0x10,
+    /// Read the audio source selection (`u8`, 0: USB, 1: aux, 2: USB and aux mixed, 3: I2S input, 255: automatic).
+    GetSource = 0x11,
+    /// Set the audio source selection to the value in `wValue`.
+    SetSource = 0x12,
+    /// Read the priority order of automatic source selection (one `u8` source per input).
+    GetSourcePriority = 0x13,
+    /// Set the priority order of automatic source selection (one `u8` source per input).
+    SetSourcePriority = 0x14,
+    /// Read the active source (`u8`), and the inputs with signal (`u8`, one bit per source).
+    GetSourceStatus = 0x15,
+    /// Read the lock state (`u8`) and inferred sample rate (`u32`, zero if unknown) of the I2S input's receiver.
+    GetI2sInputStatus = 0x16,
+    /// Read the offset of the local clock against the host's SOF, in parts per billion (`i32`, `i32::MIN` until
+    /// measured).
+    GetClockOffset = 0x17,
+    /// Read the streaming statistics (`u32` counters, see `stats::counters`).
+    GetStats = 0x18,
+    /// Set the user equalizer band with the index in `wValue` (type `u8`, frequency, Q, and gain in dB as `f32`), or
+    /// clear it without data.
+    SetEqBand = 0x19,
+    /// Start a coefficient upload of a blob with a length and CRC (two `u32`), which erases the partition.
+    BeginUpload = 0x1a,
+    /// Write a chunk of the uploaded blob (offset `u32`, followed by the data).
+    WriteUpload = 0x1b,
+    /// Complete the upload, which writes the blob header and verifies the blob.
+    FinishUpload = 0x1c,
+    /// Read the upload status (`u8`, see `upload::Status`).
+    GetUploadStatus = 0x1d,
+    /// Read the active channel layout of the USB stream, and the one for the next boot (two `u8`, 0: mono, 1: stereo,
+    /// 2: 2.1, 3: 4.0).
+    GetChannelLayout = 0x1e,
+    /// Select the channel layout in `wValue` for the next boot.
+    SetChannelLayout = 0x1f,
+    /// Read whether night mode is enabled (`u8`).
+    GetNightMode = 0x20,
+    /// Enable (`wValue` 1) or disable (`wValue` 0) night mode.
+    SetNightMode = 0x21,
+    /// Read whether loudness compensation is enabled (`u8`).
+    GetLoudness = 0x22,
+    /// Enable (`wValue` 1) or disable (`wValue` 0) loudness compensation.
+    SetLoudness = 0x23,
+    /// Read the delay of the channel in `wValue` in samples (`u16`).
+    GetDelay = 0x24,
+    /// Set the delay of the channel in `wValue` in samples (`u16`), up to 10 ms.
+    SetDelay = 0x25,
+    /// Read the channels with inverted polarity (`u8`, one bit per channel).
+    GetPolarity = 0x26,
+    /// Invert the polarity of the channels in `wValue` (one bit per channel).
+    SetPolarity = 0x27,
+    /// Capture a spectrum snapshot of the channel in `wValue` (with the `spectrum` feature).
+    StartSpectrum = 0x28,
+    /// Read the spectrum snapshot's status (`u8`, 0: idle, 1: capturing, 2: computing, 3: ready).
+    GetSpectrumStatus = 0x29,
+    /// Read the spectrum snapshot's bins from the one in `wValue`, as many as fit (`i16` in 0.1 dBFS each).
+    GetSpectrum = 0x2a,
+    /// Read whether de-emphasis is enabled (`u8`).
+    GetDeEmphasis = 0x2b,
+    /// Enable (`wValue` 1) or disable (`wValue` 0) de-emphasis.
+    SetDeEmphasis = 0x2c,
+    /// Read the left and right master volume (`i16` in 1/256 dB each, `i16::MIN` while muted).
+    GetVolume = 0x2d,
+    /// Set the master volume of both channels to `wValue` (`i16` in 1/256 dB, `i16::MIN` mutes), until the host sets
+    /// it again.
+    SetVolume = 0x2e,
+    /// Read the sleep timeout in minutes (`u8`, 0: disabled).
+    GetSleepTimeout = 0x2f,
+    /// Set the sleep timeout to `wValue` minutes (up to 240, 0 disables the sleep timer).
+    SetSleepTimeout = 0x30,
+    /// Read the fan's duty in percent (`u8`), speed in revolutions per minute (`u16`), and whether it stalled (`u8`),
+    /// with the `fan-control` feature.
+    GetFan = 0x31,
+    /// Read the averaged cell voltage in mV (`u16`), the state of charge in percent (`u8`), and the battery state
+    /// (`u8`, 0: normal, 1: low, 2: critical), with the `battery-monitor` feature.
+    GetBattery = 0x32,
+    /// Read the execution times of the streaming stages (reception, conversion, DSP, DMA refill) over the last
+    /// statistics period, as minimum, average, and maximum in ns (twelve `u32`).
+    GetProfile = 0x33,
+    /// Read the histogram of measured feedback refresh periods by their deviation from the average, in bins of up to
+    /// 1, 2, 4, ..., 64 ticks and larger deviations (eight `u32`).
+    GetTickHistogram = 0x34,
+    /// Read the volume curve: the gain at the bottom and top of the host's slider in dB (`i8` each), and its resolution
+    /// in 0.5 dB steps (`u8`, 0: continuous).
+    GetVolumeCurve = 0x35,
+    /// Set the volume curve (same data as `GetVolumeCurve`, from -100 dB to 0 dB).
+    SetVolumeCurve = 0x36,
+    /// Read the configuration of output zone `wValue`: the routes of its left and right channel (`u8` each, 0: left,
+    /// 1: right, 2: mix, 3: silent), and its gain in 0.5 dB steps (`i8`), with the `dual-zone` feature.
+    GetZone = 0x37,
+    /// Set the configuration of output zone `wValue` (same data as `GetZone`, gain from -60 dB to 0 dB).
+    SetZone = 0x38,
+    /// Read whether the DSP chain is bypassed (`u8`).
+    GetBypass = 0x39,
+    /// Bypass (`wValue` 1) the DSP chain, or process samples again (`wValue` 0).
+    SetBypass = 0x3a,
+    /// Measure the auto-EQ's correction with the built-in sweep (with the `auto-eq` feature).
+    StartAutoEq = 0x3b,
+    /// Read the auto-EQ's state (`u8`, 0: idle, 1: measuring, 2: done, 3: no signal, 4: overrun), followed by the
+    /// stored correction filters: their band, width in bands, and gain in 0.5 dB steps (`u8`, `u8`, `i8`, 0xff for
+    /// none).
+    GetAutoEq = 0x3c,
+    /// Read the output's pre-fill: whether it adapts to underruns, its current depth, and its largest depth in ms (`u8`
+    /// each).
+    GetPrefill = 0x3d,
+    /// Adapt the pre-fill to underruns (`wValue` 1), or return to the fixed default (`wValue` 0).
+    SetAdaptivePrefill = 0x3e,
+}
+
+impl VendorRequest {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::GetTrim),
+            0x02 => Some(Self::SetTrim),
+            0x03 => Some(Self::GetBalance),
+            0x04 => Some(Self::SetBalance),
+            0x05 => Some(Self::GetPreset),
+            0x06 => Some(Self::SetPreset),
+            0x07 => Some(Self::GetPresetCount),
+            0x08 => Some(Self::GetPresetName),
+            0x09 => Some(Self::GetCpuLoad),
+            0x0a => Some(Self::GetLogLevel),
+            0x0b => Some(Self::SetLogLevel),
+            0x0c => Some(Self::GetResetReason),
+            0x0d => Some(Self::EnterBootloader),
+            0x0e => Some(Self::GetVersion),
+            0x0f => Some(Self::LearnIrCode),
+            0x10 => Some(Self::GetIrCode),
+            0x11 => Some(Self::GetSource),
+            0x12 => Some(Self::SetSource),
+            0x13 => Some(Self::GetSourcePriority),
+            0x14 => Some(Self::SetSourcePriority),
+            0x15 => Some(Self::GetSourceStatus),
+            0x16 => Some(Self::GetI2sInputStatus),
+            0x17 => Some(Self::GetClockOffset),
+            0x18 => Some(Self::GetStats),
+            0x19 => Some(Self::SetEqBand),
+            0x1a => Some(Self::BeginUpload),
+            0x1b => Some(Self::WriteUpload),
+            0x1c => Some(Self::FinishUpload),
+            0x1d => Some(Self::GetUploadStatus),
+            0x1e => Some(Self::GetChannelLayout),
+            0x1f => Some(Self::SetChannelLayout),
+            0x20 => Some(Self::GetNightMode),
+            0x21 => Some(Self::SetNightMode),
+            0x22 => Some(Self::GetLoudness),
+            0x23 => Some(Self::SetLoudness),
+            0x24 => Some(Self::GetDelay),
+            0x25 => Some(Self::SetDelay),
+            0x26 => Some(Self::GetPolarity),
+            0x27 => Some(Self::SetPolarity),
+            0x28 => Some(Self::StartSpectrum),
+            0x29 => Some(Self::GetSpectrumStatus),
+            0x2a => Some(Self::GetSpectrum),
+            0x2b => Some(Self::GetDeEmphasis),
+            0x2c => Some(Self::SetDeEmphasis),
+            0x2d => Some(Self::GetVolume),
+            0x2e => Some(Self::SetVolume),
+            0x2f => Some(Self::GetSleepTimeout),
+            0x30 => Some(Self::SetSleepTimeout),
+            0x31 => Some(Self::GetFan),
+            0x32 => Some(Self::GetBattery),
+            0x33 => Some(Self::GetProfile),
+            0x34 => Some(Self::GetTickHistogram),
+            0x35 => Some(Self::GetVolumeCurve),
+            0x36 => Some(Self::SetVolumeCurve),
+            0x37 => Some(Self::GetZone),
+            0x38 => Some(Self::SetZone),
+            0x39 => Some(Self::GetBypass),
+            0x3a => Some(Self::SetBypass),
+            0x3b => Some(Self::StartAutoEq),
+            0x3c => Some(Self::GetAutoEq),
+            0x3d => Some(Self::GetPrefill),
+            0x3e => Some(Self::SetAdaptivePrefill),
+            _ => None,
+        }
+    }
+}
+
+pub struct VendorHandler {
+    interface: InterfaceNumber,
+    name: StringIndex,
+}
+
+impl VendorHandler {
+    // Whether the request is addressed to the vendor interface.
+    fn is_own(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Interface
+            && req.index == self.interface.0 as u16
+    }
+}
+
+/// Handle a request without data, or with data from the host. Returns whether it was accepted.
+pub fn write(request: Option<VendorRequest>, value: u16, data: &[u8]) -> bool {
+    log_debug!("Vendor request {} (value {})", request, value);
+    sleep_timer::activity();
+
+    match (request, data) {
+        (Some(VendorRequest::SetTrim), &[trim]) => trim::set_trim(value as usize, trim as i8).is_ok(),
+        (Some(VendorRequest::SetBalance), &[balance]) => trim::set_balance(balance as i8).is_ok(),
+        (Some(VendorRequest::SetPreset), &[]) => preset::select(value as usize).is_ok(),
+        (Some(VendorRequest::SetLogLevel), &[]) => match Level::from_u8(value as u8) {
+            Some(level) if value <= u8::MAX as u16 => {
+                log_level::set_level(level);
+                true
+            }
+            _ => false,
+        },
+        (Some(VendorRequest::LearnIrCode), &[]) => ir_remote::learn(value as usize).is_ok(),
+        (Some(VendorRequest::SetSource), &[]) => match Selection::from_u8(value as u8) {
+            Some(selection) if value <= u8::MAX as u16 => source::select(selection).is_ok(),
+            _ => false,
+        },
+        (Some(VendorRequest::SetSourcePriority), order) => source::set_priority(order).is_ok(),
+        (Some(VendorRequest::SetEqBand), &[]) => preset::set_eq_band(value as usize, None).is_ok(),
+        (Some(VendorRequest::SetEqBand), band) => match band
+            .try_into()
+            .ok()
+            .and_then(|band| parse_eq_band(band, SAMPLE_RATE_HZ))
+        {
+            Some(filter) => preset::set_eq_band(value as usize, Some(filter)).is_ok(),
+            None => false,
+        },
+        (Some(VendorRequest::BeginUpload), data) if data.len() == 8 => {
+            let word = |index: usize| u32::from_le_bytes(data[4 * index..4 * index + 4].try_into().unwrap());
+            upload::begin(word(0), word(1)).is_ok()
+        }
+        (Some(VendorRequest::WriteUpload), frame) => upload::write(frame).is_ok(),
+        (Some(VendorRequest::FinishUpload), &[]) => upload::finish().is_ok(),
+        (Some(VendorRequest::SetChannelLayout), &[]) => match ChannelLayout::from_u8(value as u8) {
+            Some(layout) if value <= u8::MAX as u16 => {
+                channel_layout::select(layout);
+                true
+            }
+            _ => false,
+        },
+        (Some(VendorRequest::SetNightMode), &[]) if value <= 1 => {
+            night_mode::set(value == 1);
+            true
+        }
+        (Some(VendorRequest::SetBypass), &[]) if value <= 1 => {
+            bypass::set(value == 1);
+            true
+        }
+        (Some(VendorRequest::SetAdaptivePrefill), &[]) if value <= 1 => {
+            prefill::set_adaptive(value == 1);
+            true
+        }
+        (Some(VendorRequest::SetLoudness), &[]) if value <= 1 => {
+            loudness::set(value == 1);
+            true
+        }
+        (Some(VendorRequest::SetDeEmphasis), &[]) if value <= 1 => {
+            de_emphasis::set(value == 1);
+            true
+        }
+        (Some(VendorRequest::SetVolume), &[]) => {
+            trim::set_local_master_volume(match value as i16 {
+                i16::MIN => Volume::Muted,
+                volume => Volume::DeciBel(volume as f32 / 256.0),
+            });
+            true
+        }
+        (Some(VendorRequest::SetSleepTimeout), &[]) if value <= u8::MAX as u16 => {
+            sleep_timer::set_timeout(value as u8).is_ok()
+        }
+        (Some(VendorRequest::SetVolumeCurve), &[min_db, max_db, step]) => trim::set_volume_curve(VolumeCurve {
+            min_db: min_db as i8,
+            max_db: max_db as i8,
+            step,
+        })
+        .is_ok(),
+        #[cfg(feature = "dual-zone")]
+        (Some(VendorRequest::SetZone), &[left, right, gain]) => match (Route::from_u8(left), Route::from_u8(right)) {
+            (Some(left), Some(right)) => zone::configure(
+                value as usize,
+                ZoneConfig {
+                    routes: [left, right],
+                    gain: gain as i8,
+                },
+            )
+            .is_ok(),
+            _ => false,
+        },
+        (Some(VendorRequest::SetDelay), &[low, high]) => {
+            alignment::set_delay(value as usize, u16::from_le_bytes([low, high])).is_ok()
+        }
+        (Some(VendorRequest::SetPolarity), &[]) if value <= u8::MAX as u16 => {
+            alignment::set_inverted(value as u8).is_ok()
+        }
+        #[cfg(feature = "spectrum")]
+        (Some(VendorRequest::StartSpectrum), &[]) => spectrum::start(value as usize).is_ok(),
+        #[cfg(feature = "auto-eq")]
+        (Some(VendorRequest::StartAutoEq), &[]) => {
+            auto_eq::start();
+            true
+        }
+        (Some(VendorRequest::EnterBootloader), &[]) => {
+            // Reset after the request was acknowledged.
+            BOOTLOADER_SIGNAL.signal(());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Handle a request with data to the host, which is written to a buffer of the control buffer's size. Returns the
+/// data's length, or `None`, if the request was rejected.
+pub fn read(request: Option<VendorRequest>, value: u16, buf: &mut [u8]) -> Option<usize> {
+    log_debug!("Vendor request {} (value {})", request, value);
+
+    let settings = settings::get();
+
+    let value = match request {
+        Some(VendorRequest::GetTrim) => match settings.trim.get(value as usize) {
+            Some(&trim) => trim as u8,
+            None => return None,
+        },
+        Some(VendorRequest::GetBalance) => settings.balance as u8,
+        Some(VendorRequest::GetPreset) => preset::active() as u8,
+        Some(VendorRequest::GetPresetCount) => PRESETS.len() as u8,
+        Some(VendorRequest::GetLogLevel) => log_level::level() as u8,
+        Some(VendorRequest::GetResetReason) => reset_reason::get(),
+        Some(VendorRequest::GetSource) => source::selection().to_u8(),
+        Some(VendorRequest::GetUploadStatus) => upload::status() as u8,
+        Some(VendorRequest::GetNightMode) => night_mode::is_enabled() as u8,
+        Some(VendorRequest::GetBypass) => bypass::is_enabled() as u8,
+        Some(VendorRequest::GetLoudness) => settings.loudness as u8,
+        Some(VendorRequest::GetPolarity) => settings.inverted,
+        Some(VendorRequest::GetDeEmphasis) => settings.de_emphasis as u8,
+        Some(VendorRequest::GetSleepTimeout) => settings.sleep_timeout,
+        #[cfg(feature = "spectrum")]
+        Some(VendorRequest::GetSpectrumStatus) => spectrum::status() as u8,
+        #[cfg(feature = "spectrum")]
+        Some(VendorRequest::GetSpectrum) => {
+            let Some(length) = spectrum::read(value as usize, buf) else {
+                return None;
+            };
+
+            return Some(length);
+        }
+        Some(VendorRequest::GetPresetName) => {
+            let Some(preset) = PRESETS.get(value as usize) else {
+                return None;
+            };
+
+            let length = preset.name.len().min(buf.len());
+            buf[..length].copy_from_slice(&preset.name.as_bytes()[..length]);
+            return Some(length);
+        }
+        Some(VendorRequest::GetVersion) => {
+            let length = version::VERSION_STRING.len().min(buf.len());
+            buf[..length].copy_from_slice(&version::VERSION_STRING.as_bytes()[..length]);
+            return Some(length);
+        }
+        Some(VendorRequest::GetIrCode) => {
+            let Ok(Some(code)) = ir_remote::code(value as usize) else {
+                return None;
+            };
+
+            buf[..2].copy_from_slice(&code.address.to_le_bytes());
+            buf[2] = code.command;
+            return Some(3);
+        }
+        Some(VendorRequest::GetSourcePriority) => {
+            for (byte, &source) in buf.iter_mut().zip(&settings.source_priority) {
+                *byte = source as u8;
+            }
+            return Some(source::INPUT_COUNT);
+        }
+        Some(VendorRequest::GetSourceStatus) => {
+            let present = source::INPUTS
+                .iter()
+                .filter(|&&input| source::is_present(input))
+                .fold(0u8, |flags, &input| flags | 1 << input as u8);
+
+            buf[0] = source::active() as u8;
+            buf[1] = present;
+            return Some(2);
+        }
+        Some(VendorRequest::GetI2sInputStatus) => {
+            let (locked, sample_rate_hz) = i2s_input::status();
+
+            buf[0] = locked as u8;
+            buf[1..5].copy_from_slice(&sample_rate_hz.unwrap_or(0).to_le_bytes());
+            return Some(5);
+        }
+        Some(VendorRequest::GetStats) => {
+            let counters = stats::counters();
+
+            for (bytes, counter) in buf.chunks_exact_mut(4).zip(counters) {
+                bytes.copy_from_slice(&counter.to_le_bytes());
+            }
+            return Some(4 * stats::COUNTER_COUNT);
+        }
+        Some(VendorRequest::GetClockOffset) => {
+            let offset_ppb = stats::clock_offset_ppb().unwrap_or(stats::CLOCK_OFFSET_UNKNOWN);
+
+            buf[..4].copy_from_slice(&offset_ppb.to_le_bytes());
+            return Some(4);
+        }
+        Some(VendorRequest::GetChannelLayout) => {
+            buf[0] = channel_layout::active() as u8;
+            buf[1] = settings.channel_layout as u8;
+            return Some(2);
+        }
+        Some(VendorRequest::GetDelay) => {
+            let Some(samples) = settings.delay.get(value as usize) else {
+                return None;
+            };
+
+            buf[..2].copy_from_slice(&samples.to_le_bytes());
+            return Some(2);
+        }
+        Some(VendorRequest::GetVolume) => {
+            let (left, right) = trim::master_volume();
+            for (bytes, volume) in buf.chunks_exact_mut(2).zip([left, right]) {
+                let volume = match volume {
+                    Volume::DeciBel(db) => (db * 256.0) as i16,
+                    Volume::Muted => i16::MIN,
+                };
+                bytes.copy_from_slice(&volume.to_le_bytes());
+            }
+            return Some(4);
+        }
+        #[cfg(feature = "fan-control")]
+        Some(VendorRequest::GetFan) => {
+            let (duty_percent, rpm, stalled) = fan::status();
+            buf[0] = duty_percent;
+            buf[1..3].copy_from_slice(&rpm.to_le_bytes());
+            buf[3] = stalled as u8;
+            return Some(4);
+        }
+        #[cfg(feature = "dual-zone")]
+        Some(VendorRequest::GetZone) => {
+            let zone = value as usize;
+            if zone >= ZONE_COUNT {
+                return None;
+            }
+
+            let config = settings.zone(zone);
+            buf[..3].copy_from_slice(&[config.routes[0] as u8, config.routes[1] as u8, config.gain as u8]);
+            return Some(3);
+        }
+        #[cfg(feature = "auto-eq")]
+        Some(VendorRequest::GetAutoEq) => {
+            buf[0] = auto_eq::state() as u8;
+            for (bytes, correction) in buf[1..].chunks_exact_mut(3).zip(settings.correction) {
+                bytes.copy_from_slice(&correction);
+            }
+            return Some(1 + 3 * settings.correction.len());
+        }
+        #[cfg(feature = "battery-monitor")]
+        Some(VendorRequest::GetBattery) => {
+            let (cell_mv, level_percent, state) = battery::status();
+            buf[..2].copy_from_slice(&cell_mv.to_le_bytes());
+            buf[2] = level_percent;
+            buf[3] = state as u8;
+            return Some(4);
+        }
+        Some(VendorRequest::GetProfile) => {
+            let times = profile::profiles()
+                .into_iter()
+                .flat_map(|profile| [profile.min_cycles, profile.avg_cycles, profile.max_cycles]);
+
+            for (bytes, cycles) in buf.chunks_exact_mut(4).zip(times) {
+                bytes.copy_from_slice(&profile::cycles_to_ns(cycles).to_le_bytes());
+            }
+            return Some(4 * 3 * profile::STAGE_COUNT);
+        }
+        Some(VendorRequest::GetVolumeCurve) => {
+            buf[..3].copy_from_slice(&[
+                settings.volume_min_db as u8,
+                settings.volume_max_db as u8,
+                settings.volume_step,
+            ]);
+            return Some(3);
+        }
+        Some(VendorRequest::GetTickHistogram) => {
+            let bins = stats::tick_histogram();
+
+            for (bytes, count) in buf.chunks_exact_mut(4).zip(bins) {
+                bytes.copy_from_slice(&count.to_le_bytes());
+            }
+            return Some(4 * bins.len());
+        }
+        Some(VendorRequest::GetPrefill) => {
+            buf[..3].copy_from_slice(&[
+                prefill::is_adaptive() as u8,
+                prefill::depth_ms() as u8,
+                MAX_OUTPUT_PREFILL_MS as u8,
+            ]);
+            return Some(3);
+        }
+        Some(VendorRequest::GetCpuLoad) => {
+            let (load, peak_load) = cpu_load::load_permille();
+            buf[..2].copy_from_slice(&(load as u16).to_le_bytes());
+            buf[2..4].copy_from_slice(&(peak_load as u16).to_le_bytes());
+            return Some(4);
+        }
+        _ => return None,
+    };
+
+    buf[0] = value;
+    Some(1)
+}
+
+impl Handler for VendorHandler {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if !self.is_own(&req) {
+            return None;
+        }
+
+        Some(if write(VendorRequest::from_u8(req.request), req.value, data) {
+            OutResponse::Accepted
+        } else {
+            OutResponse::Rejected
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.is_own(&req) {
+            return None;
+        }
+
+        Some(match read(VendorRequest::from_u8(req.request), req.value, buf) {
+            Some(length) => InResponse::Accepted(&buf[..length]),
+            None => InResponse::Rejected,
+        })
+    }
+
+    fn get_string(&mut self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        (index == self.name).then_some(version::VERSION_STRING)
+    }
+}
+
+/// Add the vendor interface to the USB device, and return its level meter endpoint.
+pub fn register(builder: &mut Builder<'static, UsbDriver>) -> MeterEndpoint {
+    // The interface string identifies the firmware build.
+    let name = builder.string();
+
+    let (interface, meter_endpoint) = {
+        let mut function = builder.function(class::VENDOR, 0, 0);
+        let mut interface = function.interface();
+        let number = interface.interface_number();
+        usb_layout::check_interface(usb_layout::VENDOR, number);
+        let mut alt_setting = interface.alt_setting(class::VENDOR, 0, 0, Some(name));
+        let endpoint = alt_setting.endpoint_interrupt_in(meter::REPORT_SIZE as u16, meter::POLL_INTERVAL_MS);
+
+        (number, endpoint)
+    };
+
+    static VENDOR_HANDLER: StaticCell<VendorHandler> = StaticCell::new();
+    builder.handler(VENDOR_HANDLER.init(VendorHandler { interface, name }));
+
+    meter_endpoint
+}
@@ -0,0 +1,22 @@
+// Firmware version and build information, embedded by the build script.
+use crate::*;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("BUILD_GIT_HASH");
+
+// Build time, in seconds since the Unix epoch.
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// Version and build information, as reported to the host (vendor request and interface string).
+pub const VERSION_STRING: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    " ",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("BUILD_GIT_HASH"),
+    ", built ",
+    env!("BUILD_TIMESTAMP"),
+    ")"
+);
+
+static_assertions::const_assert!(VERSION_STRING.len() <= USB_CONTROL_BUF_SIZE);
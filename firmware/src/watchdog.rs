@@ -0,0 +1,67 @@
+// Independent watchdog supervision of the audio tasks.
+//
+// Each supervised task checks in periodically. The watchdog is only refreshed, if all tasks checked in since the last
+// refresh, so that a hung task causes a clean reset.
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use defmt::{warn, Format};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::{peripherals, wdg::IndependentWatchdog};
+use embassy_time::{Duration, Timer};
+
+// Supervised tasks must check in at least this often.
+pub const CHECK_IN_PERIOD: Duration = Duration::from_millis(100);
+
+// Period for checking the task states, and refreshing the watchdog.
+const SUPERVISION_PERIOD: Duration = Duration::from_millis(500);
+
+// Watchdog timeout, after which the device resets.
+pub const WATCHDOG_TIMEOUT_US: u32 = 2_000_000;
+
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Task {
+    Streaming = 1 << 0,
+    Feedback = 1 << 1,
+    Control = 1 << 2,
+    Output = 1 << 3,
+}
+
+const ALL_TASKS: u32 = Task::Streaming as u32 | Task::Feedback as u32 | Task::Control as u32 | Task::Output as u32;
+
+static CHECK_INS: AtomicU32 = AtomicU32::new(0);
+
+pub fn check_in(task: Task) {
+    CHECK_INS.fetch_or(task as u32, Relaxed);
+}
+
+/// Await a future that may legitimately stay pending for a long time (e.g. waiting for a host connection),
+/// while checking in periodically.
+pub async fn idle<F: Future>(task: Task, future: F) -> F::Output {
+    let mut future = pin!(future);
+
+    loop {
+        check_in(task);
+
+        if let Either::First(output) = select(future.as_mut(), Timer::after(CHECK_IN_PERIOD)).await {
+            return output;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn supervisor_task(mut watchdog: IndependentWatchdog<'static, peripherals::IWDG>) {
+    watchdog.unleash();
+
+    loop {
+        Timer::after(SUPERVISION_PERIOD).await;
+
+        let check_ins = CHECK_INS.swap(0, Relaxed);
+
+        if check_ins == ALL_TASKS {
+            watchdog.pet();
+        } else {
+            warn!("Tasks did not check in: {:#b}", ALL_TASKS & !check_ins);
+        }
+    }
+}
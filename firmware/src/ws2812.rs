@@ -0,0 +1,93 @@
+// Driver for WS2812 (and compatible) addressable RGB LEDs, driven by the MOSI line of a SPI bus.
+//
+// Every data bit is sent as three SPI bits, `100` for a zero and `110` for a one. At an SPI clock of 2.4 to 3 MHz, this
+// yields high times of 333 to 417 ns and 667 to 833 ns, within the LEDs' tolerances. Colors are sent in GRB order, most
+// significant bit first, and latched after the line is held low for the reset time.
+use embedded_hal_async::spi::SpiBus;
+
+/// Maximum number of LEDs in the chain.
+pub const MAX_LED_COUNT: usize = 4;
+
+// Three SPI bits per data bit, 24 data bits per LED.
+const BYTES_PER_LED: usize = 9;
+
+// Low time before the data (for a defined idle level) and after it (for latching). Newer LEDs require at least 280 us,
+// which is 105 byte at 3 MHz.
+const LEAD_BYTES: usize = 1;
+const RESET_BYTES: usize = 112;
+
+const BUFFER_SIZE: usize = LEAD_BYTES + MAX_LED_COUNT * BYTES_PER_LED + RESET_BYTES;
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const OFF: Self = Self::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Scale the color by a brightness from 0 (off) to 255 (full).
+    pub const fn scaled(self, brightness: u8) -> Self {
+        const fn scale(value: u8, brightness: u8) -> u8 {
+            ((value as u16 * (brightness as u16 + 1)) >> 8) as u8
+        }
+
+        Self::new(
+            scale(self.r, brightness),
+            scale(self.g, brightness),
+            scale(self.b, brightness),
+        )
+    }
+}
+
+// Expand a byte into 24 SPI bits.
+fn encode(byte: u8) -> [u8; 3] {
+    let mut bits: u32 = 0;
+
+    for bit in (0..8).rev() {
+        bits = (bits << 3) | if byte & (1 << bit) != 0 { 0b110 } else { 0b100 };
+    }
+
+    let [_, first, second, third] = bits.to_be_bytes();
+    [first, second, third]
+}
+
+pub struct Ws2812<SPI> {
+    spi: SPI,
+    buffer: [u8; BUFFER_SIZE],
+}
+
+impl<SPI: SpiBus> Ws2812<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self {
+            spi,
+            buffer: [0; BUFFER_SIZE],
+        }
+    }
+
+    /// Send colors to the chain, starting with the LED closest to the MCU. Excess colors are ignored.
+    pub async fn write(&mut self, colors: &[Rgb]) -> Result<(), SPI::Error> {
+        let count = colors.len().min(MAX_LED_COUNT);
+        let data = &mut self.buffer[LEAD_BYTES..LEAD_BYTES + count * BYTES_PER_LED];
+
+        for (led, color) in data.chunks_exact_mut(BYTES_PER_LED).zip(colors) {
+            for (bytes, value) in led.chunks_exact_mut(3).zip([color.g, color.r, color.b]) {
+                bytes.copy_from_slice(&encode(value));
+            }
+        }
+
+        // Data of a longer previous write would end up in the reset time.
+        let end = LEAD_BYTES + count * BYTES_PER_LED;
+        self.buffer[end..].fill(0);
+
+        let end = end + RESET_BYTES;
+        self.spi.write(&self.buffer[..end]).await?;
+        self.spi.flush().await
+    }
+}
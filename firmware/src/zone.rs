@@ -0,0 +1,96 @@
+// Dual-zone output: a second stereo DAC on I2S3, which plays the same USB stream as the first with its own routing and
+// gain (see `blus_core::zone`), e.g. for speakers in a second room, or four channels from the chip's two I2S
+// peripherals.
+//
+// Both I2S peripherals are masters, clocked by the I2S PLL with prescalers for the same sample rate (see `i2s_clock`),
+// and are started together by the output task. After the DSP chain, the first zone is routed in place in the sample
+// block, and the second zone into a separate buffer.
+use blus_core::format::to_words;
+use blus_core::zone::{ZoneConfig, ZoneRouting, ZONE_COUNT};
+use core::cell::{Cell, RefCell};
+use defmt::info;
+use embassy_stm32::i2s::I2S;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::sample_block::WORD_ORDER;
+use crate::trim::OutOfRange;
+use crate::*;
+
+/// Words of the second zone's samples per block.
+pub const ZONE_2_BLOCK_SIZE: usize = 2 * USB_SAMPLE_BLOCK_SAMPLE_COUNT;
+
+static_assertions::const_assert_eq!(INPUT_CHANNEL_COUNT, 2);
+
+static ROUTINGS: Mutex<CriticalSectionRawMutex, Cell<[ZoneRouting; ZONE_COUNT]>> =
+    Mutex::new(Cell::new([ZoneRouting::IDENTITY; ZONE_COUNT]));
+
+static ZONE_2_I2S: Mutex<CriticalSectionRawMutex, RefCell<Option<I2S<'static, u16>>>> = Mutex::new(RefCell::new(None));
+
+/// Apply the stored zone configurations, and hand over the second zone's I2S output to the output task.
+pub fn init(i2s: I2S<'static, u16>) {
+    let settings = settings::get();
+    ROUTINGS.lock(|routings| routings.set(core::array::from_fn(|zone| ZoneRouting::new(settings.zone(zone)))));
+    ZONE_2_I2S.lock(|cell| cell.borrow_mut().replace(i2s));
+}
+
+/// Take the second zone's I2S output, once.
+pub fn take_output() -> Option<I2S<'static, u16>> {
+    ZONE_2_I2S.lock(|cell| cell.borrow_mut().take())
+}
+
+/// Configure an output zone, and persist its configuration.
+pub fn configure(zone: usize, config: ZoneConfig) -> Result<(), OutOfRange> {
+    if zone >= ZONE_COUNT || !config.is_valid() {
+        return Err(OutOfRange);
+    }
+
+    info!(
+        "Zone {}: routes {}, {}, gain {} (0.5 dB)",
+        zone + 1,
+        config.routes[0] as u8,
+        config.routes[1] as u8,
+        config.gain
+    );
+    ROUTINGS.lock(|routings| {
+        let mut value = routings.get();
+        value[zone] = ZoneRouting::new(config);
+        routings.set(value);
+    });
+    settings::modify(|settings| _ = settings.set_zone(zone, config));
+
+    Ok(())
+}
+
+/// Route a block to both zones: the first in place, the second into a buffer of 16 bit words. Returns the number of
+/// the second zone's words.
+pub fn route(samples: &mut UsbSampleBlock, zone_2: &mut [u16; ZONE_2_BLOCK_SIZE]) -> usize {
+    let [zone_1_routing, zone_2_routing] = ROUTINGS.lock(|routings| routings.get());
+    let mut zone_2_frames = zone_2.chunks_exact_mut(2 * INPUT_CHANNEL_COUNT);
+    let mut length = 0;
+
+    let mut route_frame = |frame: &[i32]| {
+        let frame = [frame[0], frame[1]];
+
+        if let Some(words) = zone_2_frames.next() {
+            for (word_pair, sample) in words.chunks_exact_mut(2).zip(zone_2_routing.apply(frame)) {
+                word_pair.copy_from_slice(&to_words(sample, WORD_ORDER));
+            }
+            length += 2 * INPUT_CHANNEL_COUNT;
+        }
+
+        zone_1_routing.apply(frame)
+    };
+
+    if zone_1_routing.is_identity() {
+        // The first zone's samples stay unchanged.
+        let mut block_samples = samples.samples();
+        while let (Some(left), Some(right)) = (block_samples.next(), block_samples.next()) {
+            route_frame(&[left, right]);
+        }
+    } else {
+        samples.remix::<INPUT_CHANNEL_COUNT>(INPUT_CHANNEL_COUNT, route_frame);
+    }
+
+    length
+}
@@ -0,0 +1,245 @@
+// Hardware-in-the-loop tests, run on the target with `cargo test --test hil` (via probe-rs, see `.cargo/config.toml`).
+//
+// They cover the timing-critical code that cannot be checked by listening: feedback accumulation, sample conversion,
+// and the DSP kernels, which use DSP instructions on the target. The settings tests write to the key-value store's
+// flash sectors, and restore the stored settings afterwards.
+#![no_std]
+#![no_main]
+
+use blus_fw as _;
+use defmt_rtt as _;
+
+#[defmt_test::tests]
+mod tests {
+    use blus_fw::dsp::kernel::{mac, mul_q31, saturate_24};
+    use blus_fw::dsp::{fir, BiquadCascade, Filter, Fir, Gain, Sample};
+    use blus_fw::feedback::FeedbackAccumulator;
+    use blus_fw::kv_store::{KvError, KvStore, Value, KEY_COUNT};
+    use blus_fw::nec::Code;
+    use blus_fw::sample_block::SampleBlock;
+    use blus_fw::settings::Settings;
+    use blus_fw::source::{Selection, Source};
+    use blus_fw::usb_audio::feedback_value;
+    use blus_fw::*;
+    use defmt::{assert, assert_eq};
+    use embassy_stm32::flash::{Blocking, Flash};
+    use embassy_stm32::peripherals::FLASH;
+
+    const PERIOD_FRAMES: usize = 8;
+
+    // Feedback timer ticks per (micro)frame at the nominal sample rate.
+    const TICKS_PER_FRAME: u32 = FEEDBACK_COUNTER_TICK_RATE / 1000 / USB_FRAMES_PER_MS as u32;
+
+    // Open the key-value store. It takes ownership of the flash, so that every reopening steals the peripheral.
+    fn open_store() -> KvStore {
+        // SAFETY: Only one store is open at a time, and nothing else accesses the flash.
+        let flash: Flash<'static, Blocking> = Flash::new_blocking(unsafe { FLASH::steal() });
+        KvStore::open(flash)
+    }
+
+    #[init]
+    fn init() {
+        // Initializes the clock tree, which flash access and the DSP timing depend on.
+        let _ = embassy_stm32::init(board::config());
+    }
+
+    #[test]
+    fn feedback_accumulator_wraps() {
+        let accumulator = FeedbackAccumulator::new(PERIOD_FRAMES);
+        let mut ticks = u32::MAX - TICKS_PER_FRAME;
+
+        // The first capture starts the measurement.
+        assert_eq!(accumulator.update(ticks), None);
+
+        for frame in 1..=PERIOD_FRAMES {
+            ticks = ticks.wrapping_add(TICKS_PER_FRAME);
+            let expected = (frame == PERIOD_FRAMES).then_some(PERIOD_FRAMES as u32 * TICKS_PER_FRAME);
+            assert_eq!(accumulator.update(ticks), expected);
+        }
+    }
+
+    #[test]
+    fn feedback_accumulator_reset_discards_period() {
+        let accumulator = FeedbackAccumulator::new(PERIOD_FRAMES);
+
+        assert_eq!(accumulator.update(0), None);
+        for frame in 1..PERIOD_FRAMES as u32 {
+            assert_eq!(accumulator.update(frame * TICKS_PER_FRAME), None);
+        }
+
+        accumulator.reset();
+        assert_eq!(accumulator.update(1000), None);
+        for frame in 1..PERIOD_FRAMES as u32 {
+            assert_eq!(accumulator.update(1000 + frame * TICKS_PER_FRAME), None);
+        }
+        assert_eq!(
+            accumulator.update(1000 + PERIOD_FRAMES as u32 * TICKS_PER_FRAME),
+            Some(PERIOD_FRAMES as u32 * TICKS_PER_FRAME)
+        );
+    }
+
+    #[test]
+    fn feedback_value_is_nominal() {
+        let ticks = FEEDBACK_REFRESH_PERIOD.frame_count() as u32 * TICKS_PER_FRAME;
+        let samples_per_frame = (SAMPLE_RATE_HZ << FEEDBACK_FORMAT.shift) / 1000 / USB_FRAMES_PER_MS as u32;
+
+        assert_eq!(feedback_value(ticks), samples_per_frame);
+    }
+
+    #[test]
+    fn sample_block_word_order() {
+        let mut block = SampleBlock::<8>::new();
+        block.set_samples(&[0x1234_5678, -1, i32::MIN]);
+
+        assert_eq!(block.sample_count(), 3);
+        assert_eq!(block.words(), &[0x5678, 0x1234, 0xffff, 0xffff, 0x0000, 0x8000]);
+
+        block.process(|sample| sample.saturating_add(1));
+        assert_eq!(block.words(), &[0x5679, 0x1234, 0x0000, 0x0000, 0x0001, 0x8000]);
+    }
+
+    #[test]
+    fn sample_block_remix() {
+        let mut block = SampleBlock::<16>::new();
+
+        // Mono frames grow in place.
+        block.set_samples(&[1, 2, 3]);
+        block.remix(1, |frame| [frame[0], -frame[0]]);
+        assert_eq!(block.sample_count(), 6);
+        assert_eq!(
+            block.words(),
+            &[1, 0, 0xffff, 0xffff, 2, 0, 0xfffe, 0xffff, 3, 0, 0xfffd, 0xffff]
+        );
+
+        // Quad frames shrink in place.
+        block.set_samples(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        block.remix(4, |frame| [frame[0] + frame[2], frame[1] + frame[3]]);
+        assert_eq!(block.words(), &[4, 0, 6, 0, 12, 0, 14, 0]);
+    }
+
+    #[test]
+    fn float_sample_conversion() {
+        assert_eq!(f32::from_pcm(i32::MIN), -1.0);
+        assert_eq!(f32::from_pcm(1 << 30), 0.5);
+        assert_eq!(0.5f32.to_pcm(), 1 << 30);
+
+        // Saturates at full scale.
+        assert_eq!(1.0f32.to_pcm(), i32::MAX);
+        assert_eq!((-2.0f32).to_pcm(), i32::MIN);
+    }
+
+    #[test]
+    fn kernels() {
+        assert_eq!(mul_q31(1 << 30, 1 << 30), 1 << 29);
+        assert_eq!(mul_q31(-(1 << 30), 1 << 30), -(1 << 29));
+        assert_eq!(mul_q31(i32::MIN, i32::MIN), i32::MAX);
+
+        assert_eq!(mac(1, 1 << 16, 1 << 16), (1 << 32) + 1);
+        assert_eq!(mac(0, i32::MIN, i32::MIN), 1 << 62);
+
+        assert_eq!(saturate_24(1 << 24), (1 << 23) - 1);
+        assert_eq!(saturate_24(-(1 << 24)), -(1 << 23));
+        assert_eq!(saturate_24(1000), 1000);
+    }
+
+    #[test]
+    fn gain() {
+        assert_eq!(Gain::MUTED.apply(i32::MAX), 0);
+        assert!(Gain::UNITY.apply(1 << 30).abs_diff(1 << 30) <= 1);
+
+        // About +6 dB doubles, and saturates at full scale.
+        assert!(Gain::from_linear(2.0).apply(1 << 20).abs_diff(1 << 21) <= 2);
+        assert_eq!(Gain::from_linear(2.0).apply(i32::MAX), i32::MAX);
+    }
+
+    #[test]
+    fn biquad_identity_passes_samples() {
+        let mut cascade = BiquadCascade::<i32, 4>::new();
+        let mut samples = [0, 1 << 30, -(1 << 30), 12345, i32::MAX, i32::MIN];
+        let input = samples;
+
+        cascade.process_block(&mut samples);
+        for (output, input) in samples.iter().zip(input) {
+            assert!(output.abs_diff(input) <= 1);
+        }
+    }
+
+    #[test]
+    fn biquad_low_pass_has_unity_dc_gain() {
+        let mut cascade = BiquadCascade::<i32, 1>::new();
+        let filter = Filter::LowPass {
+            frequency_hz: 1000.0,
+            q: 0.707,
+        };
+        cascade.set_coefficients(0, filter.coefficients(SAMPLE_RATE_HZ));
+
+        // Settles to the DC input.
+        let mut output = 0;
+        for _ in 0..SAMPLE_RATE_HZ / 10 {
+            output = cascade.process(1 << 28);
+        }
+        assert!(output.abs_diff(1 << 28) < 1 << 12);
+    }
+
+    #[test]
+    fn fir_impulse_response() {
+        const TAPS: [f32; 4] = [0.5, 0.25, -0.125, 0.0625];
+        let mut fir = Fir::<i32, 4>::new(fir::coefficients::<i32, 4>(&TAPS));
+
+        let mut samples = [i32::MAX, 0, 0, 0, 0];
+        fir.process_block(&mut samples);
+
+        for (output, tap) in samples.iter().zip(TAPS.iter().chain(&[0.0])) {
+            let expected = (tap * i32::MAX as f32) as i32;
+            assert!(output.abs_diff(expected) <= 2);
+        }
+    }
+
+    #[test]
+    fn kv_store_round_trip() {
+        let mut store = open_store();
+        let previous = store.read(7).map(|value| Value::from_slice(value).unwrap());
+
+        store.write(7, &[1, 2, 3]).unwrap();
+        assert_eq!(store.read(7), Some(&[1u8, 2, 3][..]));
+        drop(store);
+
+        let mut store = open_store();
+        assert!(store.is_healthy());
+        assert_eq!(store.read(7), Some(&[1u8, 2, 3][..]));
+        assert_eq!(store.write(KEY_COUNT as u8, &[0]), Err(KvError::UnknownKey));
+
+        if let Some(previous) = previous {
+            store.write(7, &previous).unwrap();
+        }
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut store = open_store();
+        let previous = Settings::load(&store);
+
+        let mut settings = Settings::DEFAULT;
+        settings.trim = [-3; INPUT_CHANNEL_COUNT];
+        settings.balance = 5;
+        settings.preset = 1;
+        settings.ir_codes[0] = Some(Code {
+            address: 0x1234,
+            command: 0x56,
+        });
+        settings.source = Selection::Manual(Source::Aux);
+        settings.sleep_timeout = 30;
+        settings.volume_min_db = -48;
+        settings.volume_step = 2;
+        settings.zone_routes[1] = [2, 3];
+        settings.zone_gain[1] = -12;
+
+        settings.store(&mut store).unwrap();
+        drop(store);
+
+        let mut store = open_store();
+        assert_eq!(Settings::load(&store), settings);
+
+        previous.store(&mut store).unwrap();
+    }
+}
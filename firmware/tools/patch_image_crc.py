@@ -0,0 +1,102 @@
+#!/usr/bin/env python3
+"""Store the firmware image CRC in the `.image_crc` section of an ELF file (see `src/image_crc.rs`).
+
+The CRC covers the image from `__image_start` to `__image_end` (vector table, code, and read-only data), and matches
+the STM32 CRC peripheral (CRC-32/MPEG-2 over little-endian 32 bit words). The key-value store pages from
+`__kv_store_start` to `__kv_store_end` are excluded.
+
+Usage: patch_image_crc.py <elf file>
+"""
+import struct
+import sys
+
+SHT_SYMTAB = 2
+SHT_NOBITS = 8
+SHF_ALLOC = 0x2
+
+
+def crc32_mpeg2(data):
+    crc = 0xFFFFFFFF
+
+    for (word,) in struct.iter_unpack("<I", data):
+        crc ^= word
+        for _ in range(32):
+            if crc & 0x80000000:
+                crc = ((crc << 1) ^ 0x04C11DB7) & 0xFFFFFFFF
+            else:
+                crc = (crc << 1) & 0xFFFFFFFF
+
+    return crc
+
+
+def read_sections(elf):
+    (shoff,) = struct.unpack_from("<I", elf, 0x20)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x2E)
+
+    sections = []
+    for index in range(shnum):
+        name, kind, flags, addr, offset, size, link, _, _, entsize = struct.unpack_from(
+            "<IIIIIIIIII", elf, shoff + index * shentsize
+        )
+        sections.append(dict(name=name, kind=kind, flags=flags, addr=addr, offset=offset, size=size, link=link))
+
+    names = sections[shstrndx]
+    for section in sections:
+        end = elf.index(b"\0", names["offset"] + section["name"])
+        section["name"] = elf[names["offset"] + section["name"] : end].decode()
+
+    return sections
+
+
+def read_symbols(elf, sections):
+    symbols = {}
+
+    for section in sections:
+        if section["kind"] != SHT_SYMTAB:
+            continue
+
+        strings = sections[section["link"]]
+        for offset in range(section["offset"], section["offset"] + section["size"], 16):
+            name, value = struct.unpack_from("<II", elf, offset)
+            end = elf.index(b"\0", strings["offset"] + name)
+            symbols[elf[strings["offset"] + name : end].decode()] = value
+
+    return symbols
+
+
+def main():
+    path = sys.argv[1]
+    with open(path, "rb") as file:
+        elf = bytearray(file.read())
+
+    sections = read_sections(elf)
+    symbols = read_symbols(elf, sections)
+    start, end = symbols["__image_start"], symbols["__image_end"]
+
+    # Reconstruct the image from all allocated sections in its range, with gaps filled as erased flash.
+    image = bytearray(b"\xff" * (end - start))
+    for section in sections:
+        if not section["flags"] & SHF_ALLOC or section["kind"] == SHT_NOBITS:
+            continue
+        if section["addr"] < start or section["addr"] + section["size"] > end:
+            continue
+
+        position = section["addr"] - start
+        image[position : position + section["size"]] = elf[section["offset"] : section["offset"] + section["size"]]
+
+    kv_start, kv_end = symbols["__kv_store_start"] - start, symbols["__kv_store_end"] - start
+    del image[kv_start:kv_end]
+
+    crc = crc32_mpeg2(image)
+
+    (crc_section,) = [section for section in sections if section["name"] == ".image_crc"]
+    struct.pack_into("<I", elf, crc_section["offset"], crc)
+
+    with open(path, "wb") as file:
+        file.write(elf)
+
+    print(f"Image CRC {crc:#010x} over {len(image)} byte")
+
+
+if __name__ == "__main__":
+    main()
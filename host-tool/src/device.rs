@@ -0,0 +1,191 @@
+// Access to the device's vendor interface.
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nusb::transfer::{Control, ControlType, Direction, EndpointType, Recipient, RequestBuffer, TransferError};
+
+use crate::protocol::{self, UploadStatus};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+// Interval of upload status polls, and the longest flash operation (erasing the partition).
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    Io(std::io::Error),
+    /// The device stalled the request, e.g. for an invalid value.
+    Rejected(u8),
+    Transfer(TransferError),
+    InvalidResponse,
+    UploadFailed,
+    UploadTimeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no device with a vendor interface found"),
+            Error::Io(e) => write!(f, "cannot open device: {e}"),
+            Error::Rejected(request) => write!(f, "device rejected request {request:#04x}"),
+            Error::Transfer(e) => write!(f, "transfer failed: {e}"),
+            Error::InvalidResponse => write!(f, "invalid response"),
+            Error::UploadFailed => write!(f, "upload failed"),
+            Error::UploadTimeout => write!(f, "upload timed out"),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub struct Device {
+    interface: nusb::Interface,
+    number: u8,
+    // Interrupt endpoint of the level meter reports, if the firmware has one.
+    meter_endpoint: Option<u8>,
+}
+
+impl Device {
+    /// Open the first attached device, and claim its vendor interface.
+    pub fn open() -> Result<Self, Error> {
+        let info = nusb::list_devices()?
+            .find(|info| info.vendor_id() == protocol::USB_VID && info.product_id() == protocol::USB_PID)
+            .ok_or(Error::NotFound)?;
+
+        let number = info
+            .interfaces()
+            .find(|interface| interface.class() == protocol::VENDOR_CLASS)
+            .map(|interface| interface.interface_number())
+            .ok_or(Error::NotFound)?;
+
+        let device = info.open()?;
+        let meter_endpoint = device.active_configuration().ok().and_then(|configuration| {
+            configuration
+                .interface_alt_settings()
+                .filter(|alt_setting| alt_setting.interface_number() == number)
+                .flat_map(|alt_setting| alt_setting.endpoints().collect::<Vec<_>>())
+                .find(|endpoint| {
+                    endpoint.transfer_type() == EndpointType::Interrupt && endpoint.direction() == Direction::In
+                })
+                .map(|endpoint| endpoint.address())
+        });
+
+        let interface = device.claim_interface(number)?;
+        Ok(Self {
+            interface,
+            number,
+            meter_endpoint,
+        })
+    }
+
+    fn control(&self, request: u8, value: u16) -> Control {
+        Control {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request,
+            value,
+            index: self.number as u16,
+        }
+    }
+
+    fn map_error(request: u8, e: TransferError) -> Error {
+        match e {
+            TransferError::Stall => Error::Rejected(request),
+            e => Error::Transfer(e),
+        }
+    }
+
+    /// Read a request's data, of at most the control buffer's size.
+    pub fn read(&self, request: u8, value: u16) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; protocol::CONTROL_BUF_SIZE];
+
+        let length = self
+            .interface
+            .control_in_blocking(self.control(request, value), &mut data, TIMEOUT)
+            .map_err(|e| Self::map_error(request, e))?;
+
+        data.truncate(length);
+        Ok(data)
+    }
+
+    /// Read a request's data of a fixed length.
+    pub fn read_exact<const N: usize>(&self, request: u8, value: u16) -> Result<[u8; N], Error> {
+        let data = self.read(request, value)?;
+        data.get(..N)
+            .and_then(|data| data.try_into().ok())
+            .ok_or(Error::InvalidResponse)
+    }
+
+    pub fn write(&self, request: u8, value: u16, data: &[u8]) -> Result<(), Error> {
+        self.interface
+            .control_out_blocking(self.control(request, value), data, TIMEOUT)
+            .map_err(|e| Self::map_error(request, e))?;
+
+        Ok(())
+    }
+
+    /// Wait for the next level meter report.
+    pub fn read_meter(&self) -> Result<Vec<u8>, Error> {
+        let endpoint = self.meter_endpoint.ok_or(Error::NotFound)?;
+        let completion = futures_lite::future::block_on(
+            self.interface
+                .interrupt_in(endpoint, RequestBuffer::new(protocol::METER_REPORT_SIZE)),
+        );
+
+        completion.status.map_err(Error::Transfer)?;
+        Ok(completion.data)
+    }
+
+    pub fn upload_status(&self) -> Result<UploadStatus, Error> {
+        let [status] = self.read_exact(protocol::GET_UPLOAD_STATUS, 0)?;
+        UploadStatus::from_u8(status).ok_or(Error::InvalidResponse)
+    }
+
+    // Wait for the device to execute the last upload command.
+    fn wait_for_upload(&self) -> Result<UploadStatus, Error> {
+        let start = Instant::now();
+
+        loop {
+            match self.upload_status()? {
+                UploadStatus::Busy if start.elapsed() > UPLOAD_TIMEOUT => return Err(Error::UploadTimeout),
+                UploadStatus::Busy => thread::sleep(POLL_INTERVAL),
+                UploadStatus::Failed => return Err(Error::UploadFailed),
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Upload a coefficient blob's body (a multiple of four byte), reporting the progress in byte.
+    pub fn upload(&self, blob: &[u8], mut progress: impl FnMut(usize)) -> Result<(), Error> {
+        let mut begin = [0; 8];
+        begin[..4].copy_from_slice(&(blob.len() as u32).to_le_bytes());
+        begin[4..].copy_from_slice(&protocol::crc32(blob).to_le_bytes());
+
+        self.write(protocol::BEGIN_UPLOAD, 0, &begin)?;
+        self.wait_for_upload()?;
+
+        for (index, data) in blob.chunks(protocol::MAX_CHUNK_SIZE).enumerate() {
+            let offset = index * protocol::MAX_CHUNK_SIZE;
+
+            let mut frame = [0; protocol::CONTROL_BUF_SIZE];
+            let length = protocol::frame_chunk(offset as u32, data, &mut frame).unwrap();
+
+            self.write(protocol::WRITE_UPLOAD, 0, &frame[..length])?;
+            self.wait_for_upload()?;
+            progress(offset + data.len());
+        }
+
+        self.write(protocol::FINISH_UPLOAD, 0, &[])?;
+        match self.wait_for_upload()? {
+            UploadStatus::Done => Ok(()),
+            _ => Err(Error::UploadFailed),
+        }
+    }
+}
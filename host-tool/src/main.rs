@@ -0,0 +1,478 @@
+// Host tool for the device's vendor interface (see `firmware/src/vendor.rs`).
+mod device;
+mod protocol;
+
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use device::Device;
+
+const USAGE: &str = "usage: host-tool <command>
+
+commands:
+    version                                   firmware version
+    stats                                     streaming statistics, CPU load, profile, feedback jitter, clock offset
+    presets                                   list DSP presets
+    preset <index>                            switch the DSP preset
+    trim <channel> <dB>                       set a channel's trim (0.5 dB steps)
+    balance <dB>                              set the balance (positive attenuates the left channel)
+    eq <band> <type> <Hz> <Q> [dB]            set a user equalizer band
+                                              (peaking, lowshelf, highshelf, highpass, lowpass)
+    eq <band> clear                           clear a user equalizer band
+    layout [mono|stereo|2.1|4.0]              show the channel layout, or select it for the next boot
+    night [on|off]                            show night mode, or switch it (reduced dynamic range and bass)
+    bypass [on|off]                           show the DSP bypass, or switch it (for A/B comparison)
+    auto-eq [start]                           show the room correction, or measure it (with the auto-eq feature)
+    prefill [adaptive|fixed]                  show the output's pre-fill, or let it grow after repeated underruns
+    loudness [on|off]                         show loudness compensation, or switch it
+    deemphasis [on|off]                       show de-emphasis (for pre-emphasized CDs), or switch it
+    volume [<dB>|mute]                        show the master volume, or set it until the host does
+    sleep [<minutes>|off]                     show the sleep timeout, or set it (up to 240 minutes)
+    curve [<min dB> <max dB> <step dB>]       show or set the volume curve (the gain range of the host's slider)
+    zone <1|2> [<left> <right> <dB>]          show or set an output zone's routes (left, right, mix, silent) and gain
+                                              (with the dual-zone feature)
+    fan                                       show the fan's duty and speed (with the fan-control feature)
+    battery                                   show the battery's level (with the battery-monitor feature)
+    delay <channel> [<samples>|<mm>mm]        show or set a channel's delay, in samples or as a distance
+    invert [none|<channel>,...]               show or set the channels with inverted polarity
+    meter                                     show the output's peak and RMS levels continuously
+    spectrum <channel>                        capture and show the output's spectrum (with the spectrum feature)
+    upload-coefficients <file>                upload a coefficient blob to the external flash
+    dfu                                       enter the DFU bootloader";
+
+fn parse<T: FromStr>(argument: &str) -> Result<T, String> {
+    argument.parse().map_err(|_| format!("invalid argument '{argument}'"))
+}
+
+fn parse_gain(argument: &str) -> Result<u8, String> {
+    protocol::half_db_steps(parse(argument)?)
+        .map(|steps| steps as u8)
+        .ok_or(format!("gain '{argument}' out of range"))
+}
+
+// The index of an output zone, numbered from 1.
+fn parse_zone(argument: &str) -> Result<u16, String> {
+    match parse::<usize>(argument)? {
+        zone @ 1..=protocol::zone::ZONE_COUNT => Ok(zone as u16 - 1),
+        _ => Err(format!("unknown zone '{argument}'")),
+    }
+}
+
+fn print_stats(device: &Device) -> Result<(), device::Error> {
+    let stats = device.read(protocol::GET_STATS, 0)?;
+    for (name, counter) in protocol::COUNTER_NAMES.iter().zip(stats.chunks_exact(4)) {
+        println!("{name:>20}: {}", u32::from_le_bytes(counter.try_into().unwrap()));
+    }
+
+    let load: [u8; 4] = device.read_exact(protocol::GET_CPU_LOAD, 0)?;
+    let permille = |bytes: &[u8]| u16::from_le_bytes(bytes.try_into().unwrap()) as f32 / 10.0;
+    println!(
+        "{:>20}: {:.1} % (peak {:.1} %)",
+        "CPU load",
+        permille(&load[..2]),
+        permille(&load[2..])
+    );
+
+    let profile = device.read(protocol::GET_PROFILE, 0)?;
+    for (name, times) in protocol::STAGE_NAMES.iter().zip(profile.chunks_exact(12)) {
+        let us = |index: usize| u32::from_le_bytes(times[4 * index..4 * index + 4].try_into().unwrap()) as f32 / 1000.0;
+        println!(
+            "{name:>20}: {:.1} / {:.1} / {:.1} us (min / avg / max)",
+            us(0),
+            us(1),
+            us(2)
+        );
+    }
+
+    let histogram = device.read(protocol::GET_TICK_HISTOGRAM, 0)?;
+    println!("feedback period deviation (ticks):");
+    for (label, count) in protocol::TICK_HISTOGRAM_BINS.iter().zip(histogram.chunks_exact(4)) {
+        println!("{label:>20}: {}", u32::from_le_bytes(count.try_into().unwrap()));
+    }
+
+    match i32::from_le_bytes(device.read_exact(protocol::GET_CLOCK_OFFSET, 0)?) {
+        protocol::CLOCK_OFFSET_UNKNOWN => println!("{:>20}: not measured yet", "clock offset"),
+        offset_ppb => println!("{:>20}: {:.3} ppm", "clock offset", offset_ppb as f32 / 1000.0),
+    }
+
+    Ok(())
+}
+
+fn print_presets(device: &Device) -> Result<(), device::Error> {
+    let [count] = device.read_exact(protocol::GET_PRESET_COUNT, 0)?;
+    let [active] = device.read_exact(protocol::GET_PRESET, 0)?;
+
+    for index in 0..count {
+        let name = device.read(protocol::GET_PRESET_NAME, index as u16)?;
+        let marker = if index == active { '*' } else { ' ' };
+        println!("{marker} {index}: {}", String::from_utf8_lossy(&name));
+    }
+
+    Ok(())
+}
+
+fn print_channel_layout(device: &Device) -> Result<(), device::Error> {
+    let name = |layout: u8| {
+        protocol::CHANNEL_LAYOUTS
+            .get(layout as usize)
+            .copied()
+            .unwrap_or("unknown")
+    };
+
+    let [active, next] = device.read_exact(protocol::GET_CHANNEL_LAYOUT, 0)?;
+    println!("{} (next boot: {})", name(active), name(next));
+    Ok(())
+}
+
+fn print_polarity(device: &Device) -> Result<(), device::Error> {
+    let [mask] = device.read_exact(protocol::GET_POLARITY, 0)?;
+    let channels: Vec<String> = (0..8)
+        .filter(|channel| mask & 1 << channel != 0)
+        .map(|channel| channel.to_string())
+        .collect();
+
+    if channels.is_empty() {
+        println!("none");
+    } else {
+        println!("{}", channels.join(","));
+    }
+    Ok(())
+}
+
+// Print level meter reports as bars, until interrupted.
+fn run_meter(device: &Device) -> Result<(), device::Error> {
+    const WIDTH_DB: f32 = 60.0;
+
+    let bar = |level_db: f32| {
+        let length = ((level_db + WIDTH_DB).clamp(0.0, WIDTH_DB) / 2.0) as usize;
+        format!("{:<30}", "#".repeat(length))
+    };
+
+    loop {
+        let levels = protocol::meter_levels(&device.read_meter()?);
+        let line: Vec<String> = levels
+            .iter()
+            .map(|&(peak_db, rms_db)| format!("{} {peak_db:6.1} {rms_db:6.1}", bar(rms_db)))
+            .collect();
+
+        eprint!("\r{}", line.join(" | "));
+    }
+}
+
+// Optionally measure the auto-EQ's correction, and print its state and filters.
+fn print_auto_eq(device: &Device, start: bool) -> Result<(), device::Error> {
+    if start {
+        device.write(protocol::START_AUTO_EQ, 0, &[])?;
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut response = device.read(protocol::GET_AUTO_EQ, 0)?;
+    while start && response.first() == Some(&protocol::AUTO_EQ_MEASURING) {
+        thread::sleep(Duration::from_millis(250));
+        response = device.read(protocol::GET_AUTO_EQ, 0)?;
+    }
+
+    let Some((&state, corrections)) = response.split_first() else {
+        return Err(device::Error::InvalidResponse);
+    };
+    println!("{}", protocol::AUTO_EQ_STATES.get(state as usize).unwrap_or(&"unknown"));
+
+    for correction in corrections.chunks_exact(protocol::Correction::SIZE) {
+        if let Some(correction) = protocol::Correction::from_bytes(correction.try_into().unwrap()) {
+            println!(
+                "{:>8.1} Hz: {:+5.1} dB, Q {:.2}",
+                protocol::band_hz(correction.band as usize),
+                correction.gain_db(),
+                correction.q()
+            );
+        }
+    }
+    Ok(())
+}
+
+// Capture a spectrum snapshot, and print its bins with their frequency.
+fn print_spectrum(device: &Device, channel: u16) -> Result<(), device::Error> {
+    device.write(protocol::START_SPECTRUM, channel, &[])?;
+
+    while device.read_exact::<1>(protocol::GET_SPECTRUM_STATUS, 0)? != [protocol::SPECTRUM_READY] {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut bins = Vec::with_capacity(protocol::SPECTRUM_BIN_COUNT);
+    while bins.len() < protocol::SPECTRUM_BIN_COUNT {
+        let chunk = protocol::spectrum_bins(&device.read(protocol::GET_SPECTRUM, bins.len() as u16)?);
+        if chunk.is_empty() {
+            return Err(device::Error::InvalidResponse);
+        }
+        bins.extend(chunk);
+    }
+
+    let bin_width_hz = protocol::DSP_SAMPLE_RATE_HZ as f32 / protocol::FFT_LENGTH as f32;
+    for (index, magnitude_db) in bins.iter().enumerate() {
+        println!("{:>8.1} Hz: {magnitude_db:6.1} dBFS", index as f32 * bin_width_hz);
+    }
+    Ok(())
+}
+
+fn upload_coefficients(device: &Device, path: &str) -> Result<(), String> {
+    let blob = std::fs::read(path).map_err(|e| format!("cannot read '{path}': {e}"))?;
+    let blob = protocol::pad_blob(blob);
+
+    device
+        .upload(&blob, |written| eprint!("\ruploaded {written} of {} byte", blob.len()))
+        .map_err(|e| e.to_string())?;
+
+    eprintln!("\nupload verified, coefficients are loaded at the next boot");
+    Ok(())
+}
+
+fn run(args: &[&str]) -> Result<(), String> {
+    let open = || Device::open().map_err(|e| e.to_string());
+
+    let result = match args {
+        ["version"] => {
+            let device = open()?;
+            let version = device.read(protocol::GET_VERSION, 0).map_err(|e| e.to_string())?;
+            println!("{}", String::from_utf8_lossy(&version));
+            Ok(())
+        }
+        ["stats"] => print_stats(&open()?),
+        ["presets"] => print_presets(&open()?),
+        ["preset", index] => open()?.write(protocol::SET_PRESET, parse(index)?, &[]),
+        ["trim", channel, gain_db] => {
+            let steps = parse_gain(gain_db)?;
+            open()?.write(protocol::SET_TRIM, parse(channel)?, &[steps])
+        }
+        ["balance", gain_db] => {
+            let steps = parse_gain(gain_db)?;
+            open()?.write(protocol::SET_BALANCE, 0, &[steps])
+        }
+        ["eq", band, "clear"] => open()?.write(protocol::SET_EQ_BAND, parse(band)?, &[]),
+        ["eq", band, filter_type, frequency_hz, q, gain_db @ ..] if gain_db.len() <= 1 => {
+            let gain_db = gain_db.first().map_or(Ok(0.0), |gain_db| parse(gain_db))?;
+            let filter = protocol::filter(filter_type, parse(frequency_hz)?, parse(q)?, gain_db)
+                .ok_or(format!("unknown filter type '{filter_type}'"))?;
+
+            open()?.write(protocol::SET_EQ_BAND, parse(band)?, &protocol::encode_eq_band(&filter))
+        }
+        ["layout"] => print_channel_layout(&open()?),
+        ["layout", name] => {
+            let layout = protocol::CHANNEL_LAYOUTS
+                .iter()
+                .position(|layout| layout == name)
+                .ok_or(format!("unknown channel layout '{name}'"))?;
+
+            open()?.write(protocol::SET_CHANNEL_LAYOUT, layout as u16, &[])
+        }
+        ["night"] => {
+            let [enabled] = open()?
+                .read_exact(protocol::GET_NIGHT_MODE, 0)
+                .map_err(|e| e.to_string())?;
+            println!("{}", if enabled != 0 { "on" } else { "off" });
+            Ok(())
+        }
+        ["night", "on"] => open()?.write(protocol::SET_NIGHT_MODE, 1, &[]),
+        ["night", "off"] => open()?.write(protocol::SET_NIGHT_MODE, 0, &[]),
+        ["bypass"] => {
+            let [enabled] = open()?.read_exact(protocol::GET_BYPASS, 0).map_err(|e| e.to_string())?;
+            println!("{}", if enabled != 0 { "on" } else { "off" });
+            Ok(())
+        }
+        ["bypass", "on"] => open()?.write(protocol::SET_BYPASS, 1, &[]),
+        ["bypass", "off"] => open()?.write(protocol::SET_BYPASS, 0, &[]),
+        ["auto-eq"] => print_auto_eq(&open()?, false),
+        ["auto-eq", "start"] => print_auto_eq(&open()?, true),
+        ["prefill"] => {
+            let [adaptive, depth_ms, max_ms] = open()?
+                .read_exact(protocol::GET_PREFILL, 0)
+                .map_err(|e| e.to_string())?;
+            let mode = if adaptive != 0 { "adaptive" } else { "fixed" };
+            println!("{depth_ms} ms ({mode}, at most {max_ms} ms)");
+            Ok(())
+        }
+        ["prefill", "adaptive"] => open()?.write(protocol::SET_ADAPTIVE_PREFILL, 1, &[]),
+        ["prefill", "fixed"] => open()?.write(protocol::SET_ADAPTIVE_PREFILL, 0, &[]),
+        ["loudness"] => {
+            let [enabled] = open()?
+                .read_exact(protocol::GET_LOUDNESS, 0)
+                .map_err(|e| e.to_string())?;
+            println!("{}", if enabled != 0 { "on" } else { "off" });
+            Ok(())
+        }
+        ["loudness", "on"] => open()?.write(protocol::SET_LOUDNESS, 1, &[]),
+        ["loudness", "off"] => open()?.write(protocol::SET_LOUDNESS, 0, &[]),
+        ["deemphasis"] => {
+            let [enabled] = open()?
+                .read_exact(protocol::GET_DE_EMPHASIS, 0)
+                .map_err(|e| e.to_string())?;
+            println!("{}", if enabled != 0 { "on" } else { "off" });
+            Ok(())
+        }
+        ["deemphasis", "on"] => open()?.write(protocol::SET_DE_EMPHASIS, 1, &[]),
+        ["deemphasis", "off"] => open()?.write(protocol::SET_DE_EMPHASIS, 0, &[]),
+        ["volume"] => {
+            let volume: [u8; 4] = open()?.read_exact(protocol::GET_VOLUME, 0).map_err(|e| e.to_string())?;
+            for (name, volume) in ["left", "right"].iter().zip(volume.chunks_exact(2)) {
+                match i16::from_le_bytes(volume.try_into().unwrap()) {
+                    protocol::VOLUME_MUTED => println!("{name:>5}: muted"),
+                    volume => println!("{name:>5}: {:.1} dB", volume as f32 / 256.0),
+                }
+            }
+            Ok(())
+        }
+        ["volume", "mute"] => open()?.write(protocol::SET_VOLUME, protocol::VOLUME_MUTED as u16, &[]),
+        ["volume", volume_db] => {
+            let volume_db: f32 = parse(volume_db)?;
+            if !(-100.0..=0.0).contains(&volume_db) {
+                return Err(format!("volume '{volume_db}' out of range"));
+            }
+            open()?.write(protocol::SET_VOLUME, (volume_db * 256.0) as i16 as u16, &[])
+        }
+        ["sleep"] => {
+            let [minutes] = open()?
+                .read_exact(protocol::GET_SLEEP_TIMEOUT, 0)
+                .map_err(|e| e.to_string())?;
+            match minutes {
+                0 => println!("off"),
+                minutes => println!("{minutes} min"),
+            }
+            Ok(())
+        }
+        ["sleep", "off"] => open()?.write(protocol::SET_SLEEP_TIMEOUT, 0, &[]),
+        ["sleep", minutes] => {
+            let minutes: u8 = parse(minutes)?;
+            if minutes > protocol::MAX_SLEEP_TIMEOUT_MIN {
+                return Err(format!("sleep timeout '{minutes}' out of range"));
+            }
+            open()?.write(protocol::SET_SLEEP_TIMEOUT, minutes as u16, &[])
+        }
+        ["curve"] => {
+            let [min_db, max_db, step] = open()?
+                .read_exact(protocol::GET_VOLUME_CURVE, 0)
+                .map_err(|e| e.to_string())?;
+            let step_db = step as f32 / protocol::volume::STEPS_PER_DB;
+            println!("{} dB to {} dB, {step_db} dB steps", min_db as i8, max_db as i8);
+            Ok(())
+        }
+        ["curve", min_db, max_db, step_db] => {
+            let step: f32 = parse(step_db)?;
+            let curve = protocol::VolumeCurve {
+                min_db: parse(min_db)?,
+                max_db: parse(max_db)?,
+                step: (step * protocol::volume::STEPS_PER_DB).round() as u8,
+            };
+            if !curve.is_valid() {
+                return Err(format!(
+                    "volume curve out of range ({} dB to {} dB)",
+                    protocol::volume::MIN_DB,
+                    protocol::volume::MAX_DB
+                ));
+            }
+            open()?.write(
+                protocol::SET_VOLUME_CURVE,
+                0,
+                &[curve.min_db as u8, curve.max_db as u8, curve.step],
+            )
+        }
+        ["zone", zone] => {
+            let [left, right, gain] = open()?
+                .read_exact(protocol::GET_ZONE, parse_zone(zone)?)
+                .map_err(|e| e.to_string())?;
+            let name = |route: u8| protocol::ROUTE_NAMES.get(route as usize).unwrap_or(&"unknown");
+            let gain_db = gain as i8 as f32 / protocol::zone::STEPS_PER_DB;
+            println!("{} {}, {gain_db} dB", name(left), name(right));
+            Ok(())
+        }
+        ["zone", zone, left, right, gain_db] => {
+            let route = |name: &str| {
+                protocol::ROUTE_NAMES
+                    .iter()
+                    .position(|&route| route == name)
+                    .and_then(|route| protocol::Route::from_u8(route as u8))
+                    .ok_or(format!("unknown route '{name}'"))
+            };
+            let config = protocol::ZoneConfig {
+                routes: [route(left)?, route(right)?],
+                gain: protocol::half_db_steps(parse(gain_db)?).ok_or(format!("gain '{gain_db}' out of range"))?,
+            };
+            if !config.is_valid() {
+                return Err(format!("gain '{gain_db}' out of range"));
+            }
+            open()?.write(
+                protocol::SET_ZONE,
+                parse_zone(zone)?,
+                &[config.routes[0] as u8, config.routes[1] as u8, config.gain as u8],
+            )
+        }
+        ["fan"] => {
+            let [duty_percent, rpm_low, rpm_high, stalled] =
+                open()?.read_exact(protocol::GET_FAN, 0).map_err(|e| e.to_string())?;
+            let rpm = u16::from_le_bytes([rpm_low, rpm_high]);
+            let state = if stalled != 0 { " (stalled)" } else { "" };
+            println!("{duty_percent} % duty, {rpm} rpm{state}");
+            Ok(())
+        }
+        ["battery"] => {
+            let [mv_low, mv_high, level_percent, state] = open()?
+                .read_exact(protocol::GET_BATTERY, 0)
+                .map_err(|e| e.to_string())?;
+            let cell_mv = u16::from_le_bytes([mv_low, mv_high]);
+            let state = protocol::BATTERY_STATES.get(state as usize).unwrap_or(&"unknown");
+            println!("{level_percent} % ({cell_mv} mV per cell), {state}");
+            Ok(())
+        }
+        ["delay", channel] => {
+            let samples: [u8; 2] = open()?
+                .read_exact(protocol::GET_DELAY, parse(channel)?)
+                .map_err(|e| e.to_string())?;
+            let samples = u16::from_le_bytes(samples);
+            let ms = samples as f32 * 1000.0 / protocol::DSP_SAMPLE_RATE_HZ as f32;
+            println!("{samples} samples ({ms:.2} ms)");
+            Ok(())
+        }
+        ["delay", channel, delay] => {
+            let samples = match delay.strip_suffix("mm") {
+                Some(distance_mm) => {
+                    protocol::samples_from_distance_mm(parse(distance_mm)?, protocol::DSP_SAMPLE_RATE_HZ)
+                }
+                None => parse(delay)?,
+            };
+            let samples = u16::try_from(samples).map_err(|_| format!("delay '{delay}' out of range"))?;
+
+            open()?.write(protocol::SET_DELAY, parse(channel)?, &samples.to_le_bytes())
+        }
+        ["invert"] => print_polarity(&open()?),
+        ["invert", channels] => {
+            let mut mask = 0u16;
+            if *channels != "none" {
+                for channel in channels.split(',') {
+                    mask |= 1 << parse::<u8>(channel)?.min(15);
+                }
+            }
+
+            open()?.write(protocol::SET_POLARITY, mask, &[])
+        }
+        ["meter"] => run_meter(&open()?),
+        ["spectrum", channel] => print_spectrum(&open()?, parse(channel)?),
+        ["upload-coefficients", path] => return upload_coefficients(&open()?, path),
+        ["dfu"] => open()?.write(protocol::ENTER_BOOTLOADER, 0, &[]),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    result.map_err(|e| e.to_string())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// The device's vendor protocol, mirroring `firmware/src/vendor.rs`.
+//
+// Requests are vendor control transfers to the vendor interface. Data is little-endian, and limited to the device's
+// control buffer, so that coefficient blobs are uploaded in chunks. The framing is shared with the firmware (see
+// `blus-core`).
+pub use blus_core::dsp::auto_eq::{band_hz, Correction};
+pub use blus_core::dsp::delay::samples_from_distance_mm;
+pub use blus_core::dsp::Filter;
+pub use blus_core::protocol::{encode_eq_band, frame_chunk, OFFSET_SIZE};
+pub use blus_core::volume::{self, VolumeCurve};
+pub use blus_core::zone::{self, Route, ZoneConfig};
+
+pub const USB_VID: u16 = 0x1209;
+pub const USB_PID: u16 = 0xaf02;
+
+/// Class of the vendor interface.
+pub const VENDOR_CLASS: u8 = 0xff;
+
+pub const SET_TRIM: u8 = 0x02;
+pub const SET_BALANCE: u8 = 0x04;
+pub const GET_PRESET: u8 = 0x05;
+pub const SET_PRESET: u8 = 0x06;
+pub const GET_PRESET_COUNT: u8 = 0x07;
+pub const GET_PRESET_NAME: u8 = 0x08;
+pub const GET_CPU_LOAD: u8 = 0x09;
+pub const ENTER_BOOTLOADER: u8 = 0x0d;
+pub const GET_VERSION: u8 = 0x0e;
+pub const GET_CLOCK_OFFSET: u8 = 0x17;
+pub const GET_STATS: u8 = 0x18;
+pub const SET_EQ_BAND: u8 = 0x19;
+pub const BEGIN_UPLOAD: u8 = 0x1a;
+pub const WRITE_UPLOAD: u8 = 0x1b;
+pub const FINISH_UPLOAD: u8 = 0x1c;
+pub const GET_UPLOAD_STATUS: u8 = 0x1d;
+pub const GET_CHANNEL_LAYOUT: u8 = 0x1e;
+pub const SET_CHANNEL_LAYOUT: u8 = 0x1f;
+pub const GET_NIGHT_MODE: u8 = 0x20;
+pub const SET_NIGHT_MODE: u8 = 0x21;
+pub const GET_LOUDNESS: u8 = 0x22;
+pub const SET_LOUDNESS: u8 = 0x23;
+pub const GET_DELAY: u8 = 0x24;
+pub const SET_DELAY: u8 = 0x25;
+pub const GET_POLARITY: u8 = 0x26;
+pub const SET_POLARITY: u8 = 0x27;
+pub const START_SPECTRUM: u8 = 0x28;
+pub const GET_SPECTRUM_STATUS: u8 = 0x29;
+pub const GET_SPECTRUM: u8 = 0x2a;
+pub const GET_DE_EMPHASIS: u8 = 0x2b;
+pub const SET_DE_EMPHASIS: u8 = 0x2c;
+pub const GET_VOLUME: u8 = 0x2d;
+pub const SET_VOLUME: u8 = 0x2e;
+pub const GET_SLEEP_TIMEOUT: u8 = 0x2f;
+pub const SET_SLEEP_TIMEOUT: u8 = 0x30;
+pub const GET_FAN: u8 = 0x31;
+pub const GET_BATTERY: u8 = 0x32;
+pub const GET_PROFILE: u8 = 0x33;
+pub const GET_TICK_HISTOGRAM: u8 = 0x34;
+pub const GET_VOLUME_CURVE: u8 = 0x35;
+pub const SET_VOLUME_CURVE: u8 = 0x36;
+pub const GET_ZONE: u8 = 0x37;
+pub const SET_ZONE: u8 = 0x38;
+pub const GET_BYPASS: u8 = 0x39;
+pub const SET_BYPASS: u8 = 0x3a;
+pub const START_AUTO_EQ: u8 = 0x3b;
+pub const GET_AUTO_EQ: u8 = 0x3c;
+pub const GET_PREFILL: u8 = 0x3d;
+pub const SET_ADAPTIVE_PREFILL: u8 = 0x3e;
+
+/// Names of the routes of an output zone's channels, by their value.
+pub const ROUTE_NAMES: [&str; 4] = ["left", "right", "mix", "silent"];
+
+/// Labels of the tick histogram's bins, by deviation from the average refresh period.
+pub const TICK_HISTOGRAM_BINS: [&str; 8] = ["<= 1", "<= 2", "<= 4", "<= 8", "<= 16", "<= 32", "<= 64", "> 64"];
+
+/// Names of the profiled streaming stages, in the order of the profile.
+pub const STAGE_NAMES: [&str; 4] = ["reception", "conversion", "DSP", "DMA refill"];
+
+/// Names of the battery states, by their value.
+pub const BATTERY_STATES: [&str; 3] = ["normal", "low, volume limited", "critical, output shut down"];
+
+/// Longest sleep timeout, in minutes.
+pub const MAX_SLEEP_TIMEOUT_MIN: u8 = 240;
+
+/// Master volume value while muted, otherwise in 1/256 dB.
+pub const VOLUME_MUTED: i16 = i16::MIN;
+
+/// Sample rate of the device's DSP chain, in which delays are set.
+pub const DSP_SAMPLE_RATE_HZ: u32 = 48_000;
+
+/// Names of the channel layouts, by their value.
+pub const CHANNEL_LAYOUTS: [&str; 4] = ["mono", "stereo", "2.1", "4.0"];
+
+/// Size of the device's control buffer.
+pub const CONTROL_BUF_SIZE: usize = 64;
+
+/// Maximum data length of an upload chunk, which is preceded by its offset.
+pub const MAX_CHUNK_SIZE: usize = CONTROL_BUF_SIZE - OFFSET_SIZE;
+
+/// Names of the streaming statistics counters, in their order.
+pub const COUNTER_NAMES: [&str; 16] = [
+    "packets",
+    "invalid packets",
+    "samples",
+    "dropped samples",
+    "underruns",
+    "overruns",
+    "concealed frames",
+    "stalls",
+    "buffer fill peak",
+    "latency (us)",
+    "missed feedback",
+    "missed packets",
+    "clipped input (L)",
+    "clipped input (R)",
+    "clipped output (L)",
+    "clipped output (R)",
+];
+
+/// Size of a level meter report: peak and RMS level (`i16` in 0.1 dBFS) per channel.
+pub const METER_REPORT_SIZE: usize = 8;
+
+/// Peak and RMS levels in dBFS per channel, from a level meter report.
+pub fn meter_levels(report: &[u8]) -> Vec<(f32, f32)> {
+    let level = |bytes: &[u8]| i16::from_le_bytes(bytes.try_into().unwrap()) as f32 / 10.0;
+
+    report
+        .chunks_exact(4)
+        .map(|channel| (level(&channel[..2]), level(&channel[2..])))
+        .collect()
+}
+
+/// Length of the device's FFT, and the number of bins of its spectrum snapshots (from DC to half the sample rate).
+pub const FFT_LENGTH: usize = 512;
+pub const SPECTRUM_BIN_COUNT: usize = FFT_LENGTH / 2 + 1;
+
+/// Status of a spectrum snapshot, when it can be read.
+pub const SPECTRUM_READY: u8 = 3;
+
+/// Names of the auto-EQ's states, by their value.
+pub const AUTO_EQ_STATES: [&str; 5] = ["idle", "measuring", "done", "no signal", "overrun"];
+
+/// State of the auto-EQ, while it measures.
+pub const AUTO_EQ_MEASURING: u8 = 1;
+
+/// Magnitudes in dBFS, from a chunk of spectrum bins.
+pub fn spectrum_bins(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(2)
+        .map(|bin| i16::from_le_bytes(bin.try_into().unwrap()) as f32 / 10.0)
+        .collect()
+}
+
+/// Reported clock offset before the first measurement.
+pub const CLOCK_OFFSET_UNKNOWN: i32 = i32::MIN;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UploadStatus {
+    Idle,
+    Busy,
+    Receiving,
+    Done,
+    Failed,
+}
+
+impl UploadStatus {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Idle),
+            1 => Some(Self::Busy),
+            2 => Some(Self::Receiving),
+            3 => Some(Self::Done),
+            4 => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// An equalizer band from its type's name, frequency, Q, and gain (ignored by high and low passes).
+pub fn filter(name: &str, frequency_hz: f32, q: f32, gain_db: f32) -> Option<Filter> {
+    match name {
+        "peaking" => Some(Filter::Peaking {
+            frequency_hz,
+            q,
+            gain_db,
+        }),
+        "lowshelf" => Some(Filter::LowShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        }),
+        "highshelf" => Some(Filter::HighShelf {
+            frequency_hz,
+            q,
+            gain_db,
+        }),
+        "highpass" => Some(Filter::HighPass { frequency_hz, q }),
+        "lowpass" => Some(Filter::LowPass { frequency_hz, q }),
+        _ => None,
+    }
+}
+
+/// A gain in dB, in the device's 0.5 dB steps.
+pub fn half_db_steps(gain_db: f32) -> Option<i8> {
+    let steps = (gain_db * 2.0).round();
+    (steps >= i8::MIN as f32 && steps <= i8::MAX as f32).then_some(steps as i8)
+}
+
+/// Pad a coefficient blob's body to a multiple of four byte. Zeros end the blob as an empty block.
+pub fn pad_blob(mut blob: Vec<u8>) -> Vec<u8> {
+    blob.resize(blob.len().next_multiple_of(4), 0);
+    blob
+}
+
+/// CRC-32/MPEG-2 over little-endian words, as computed by the STM32 CRC unit. The data length must be a multiple of
+/// four.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for word in data.chunks_exact(4) {
+        crc ^= u32::from_le_bytes(word.try_into().unwrap());
+
+        for _ in 0..32 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}